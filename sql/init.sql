@@ -1,4 +1,12 @@
 PRAGMA journal_mode=WAL;
+-- Timestamps are stored in UTC (see Database::migrate_timestamps_to_utc);
+-- folders and notes have a flags column (see Database::migrate_file_flags);
+-- inode numbers are kept in inodes (see Database::migrate_inodes);
+-- notes have an encrypted column (see Database::migrate_encrypted_notes);
+-- notes have a lock_hash column (see Database::migrate_note_locks).
+-- Mounting requires this version (database::SCHEMA_VERSION); raise both
+-- along with each new migration.
+PRAGMA user_version = 5;
 
 ------------------------------------------------------------
 -- Folders--------------------------------------------------
@@ -11,6 +19,16 @@ CREATE TABLE folders (
   user_id TEXT NOT NULL,
   created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
   updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  -- Access time set through utimens (e.g. touch -a); NULL reads as updated_at
+  accessed_at DATETIME,
+  -- Set by chmod/chown; NULL reports the mount's defaults
+  mode INTEGER,
+  uid INTEGER,
+  gid INTEGER,
+  -- BSD file flags set by chflags (e.g. UF_HIDDEN); NULL means none
+  flags INTEGER,
+  -- Set by rmdir; the folder is hidden until purge-deleted removes it
+  deleted_at DATETIME,
   FOREIGN KEY (parent_id) REFERENCES folders(id) ON DELETE CASCADE
 );
 CREATE INDEX idx_folders_user_id ON folders(user_id);
@@ -19,6 +37,10 @@ CREATE INDEX idx_folders_parent_id ON folders(parent_id);
 CREATE INDEX idx_folders_parent_user_title ON folders(parent_id, user_id, title);
 -- Partial index for root folder listings (optimized for parent_id IS NULL queries)
 CREATE INDEX idx_folders_root_user_title ON folders(user_id, title) WHERE parent_id IS NULL;
+-- Folder names must be unique per directory (NULL parents need their own partial index);
+-- deleted folders don't hold on to their names
+CREATE UNIQUE INDEX idx_folders_unique_title ON folders(parent_id, user_id, title) WHERE parent_id IS NOT NULL AND deleted_at IS NULL;
+CREATE UNIQUE INDEX idx_folders_unique_root_title ON folders(user_id, title) WHERE parent_id IS NULL AND deleted_at IS NULL;
 
 ------------------------------------------------------------
 -- Notes----------------------------------------------------
@@ -34,9 +56,34 @@ CREATE TABLE notes (
     user_id TEXT NOT NULL,
     created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
     updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-    FOREIGN KEY (parent_id) REFERENCES folders(id) ON DELETE CASCADE,
-    -- Include syntax to for pandoc sake
-    UNIQUE(parent_id, title, syntax)
+    -- Set for files created without an extension (e.g. LICENSE); shown as bare title
+    extensionless INTEGER NOT NULL DEFAULT 0,
+    -- Access time set through utimens (e.g. touch -a); NULL reads as updated_at
+    accessed_at DATETIME,
+    -- Set by chmod/chown; NULL reports the mount's defaults
+    mode INTEGER,
+    uid INTEGER,
+    gid INTEGER,
+    -- BSD file flags set by chflags (e.g. UF_HIDDEN); NULL means none
+    flags INTEGER,
+    -- Byte length of content, kept current by the notes_content_size triggers
+    content_size INTEGER NOT NULL DEFAULT 0,
+    -- Bumped by notes_revision_update whenever content changes, whoever changes it
+    revision INTEGER NOT NULL DEFAULT 0,
+    -- Set when the content is shared through note_contents (content is then '');
+    -- writing the content clears it again
+    content_hash TEXT,
+    -- Hash of the content (shown as user.etag), set by the mount's writes;
+    -- notes_etag_update clears it when another writer changes the content
+    etag TEXT,
+    -- Set by rm; the note is hidden until purge-deleted removes it
+    deleted_at DATETIME,
+    -- Set when content is age ciphertext, decrypted by a mount given the identity
+    encrypted INTEGER NOT NULL DEFAULT 0,
+    -- Set while the note is locked: the salted hash of the passphrase
+    -- unlocking it; the mount refuses access until it is unlocked
+    lock_hash TEXT,
+    FOREIGN KEY (parent_id) REFERENCES folders(id) ON DELETE CASCADE
   );
 CREATE INDEX idx_notes_user_id ON notes(user_id);
 CREATE INDEX idx_notes_parent_id ON notes(parent_id);
@@ -45,10 +92,199 @@ CREATE INDEX idx_notes_updated_at ON notes(updated_at);
 CREATE INDEX idx_notes_parent_title ON notes(parent_id, title);
 CREATE INDEX idx_notes_parent_updated ON notes(parent_id, updated_at);
 CREATE INDEX idx_notes_content ON notes(content IS NULL);
+CREATE INDEX idx_notes_content_hash ON notes(content_hash) WHERE content_hash IS NOT NULL;
 -- Composite index for child count queries (used in rmdir validation)
 CREATE INDEX idx_notes_parent_user ON notes(parent_id, user_id);
 -- Partial index for root note listings (optimized for parent_id IS NULL queries)
 CREATE INDEX idx_notes_root_user_title ON notes(user_id, title) WHERE parent_id IS NULL;
+-- Include syntax to for pandoc sake; deleted notes don't hold on to their names
+CREATE UNIQUE INDEX idx_notes_unique_title ON notes(parent_id, title, syntax, extensionless) WHERE deleted_at IS NULL;
+-- The index above treats NULLs as distinct, so root notes need their own
+CREATE UNIQUE INDEX idx_notes_unique_root_title ON notes(user_id, title, syntax, extensionless) WHERE parent_id IS NULL AND deleted_at IS NULL;
+
+------------------------------------------------------------
+-- Tags-----------------------------------------------------
+------------------------------------------------------------
+
+CREATE TABLE tags (
+  id TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+  title TEXT NOT NULL,
+  user_id TEXT NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(user_id, title)
+);
+
+CREATE TABLE note_tags (
+  note_id TEXT NOT NULL,
+  tag_id TEXT NOT NULL,
+  PRIMARY KEY (note_id, tag_id),
+  FOREIGN KEY (note_id) REFERENCES notes(id) ON DELETE CASCADE,
+  FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE
+);
+CREATE INDEX idx_note_tags_tag_id ON note_tags(tag_id);
+
+-- content_size lets attribute lookups skip reading the content itself
+-- (shared content keeps the size it had when it was deduplicated)
+CREATE TRIGGER notes_content_size_insert AFTER INSERT ON notes
+WHEN new.content_hash IS NULL AND length(CAST(new.content AS BLOB)) IS NOT new.content_size
+BEGIN
+    UPDATE notes SET content_size = length(CAST(new.content AS BLOB)) WHERE id = new.id;
+END;
+
+CREATE TRIGGER notes_content_size_update AFTER UPDATE OF content ON notes
+WHEN new.content_hash IS NULL AND length(CAST(new.content AS BLOB)) IS NOT new.content_size
+BEGIN
+    UPDATE notes SET content_size = length(CAST(new.content AS BLOB)) WHERE id = new.id;
+END;
+
+-- revision lets a writer notice the content changed since it last read it;
+-- moving the content into note_contents doesn't change it, writing it back does
+CREATE TRIGGER notes_revision_update AFTER UPDATE OF content ON notes
+WHEN (new.content IS NOT old.content OR old.content_hash IS NOT NULL)
+AND new.content_hash IS NULL AND new.revision IS old.revision
+BEGIN
+    UPDATE notes SET revision = old.revision + 1 WHERE id = new.id;
+END;
+
+-- Writers that don't know about etag leave it as it was; it is computed
+-- again on the next read
+CREATE TRIGGER notes_etag_update AFTER UPDATE OF content ON notes
+WHEN new.etag IS NOT NULL AND new.etag IS old.etag
+AND new.content IS NOT old.content AND new.content_hash IS NULL
+BEGIN
+    UPDATE notes SET etag = NULL WHERE id = new.id;
+END;
+
+-- Foreign keys are not enforced on every connection, so clean up explicitly
+CREATE TRIGGER notes_tags_delete AFTER DELETE ON notes BEGIN
+    DELETE FROM note_tags WHERE note_id = old.id;
+END;
+
+------------------------------------------------------------
+-- Note Aliases---------------------------------------------
+------------------------------------------------------------
+
+-- Extra names (hard links) for a note; the note's own parent_id and title
+-- are its first name, and unlinking that promotes the oldest alias
+CREATE TABLE note_aliases (
+  id TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+  note_id TEXT NOT NULL,
+  parent_id TEXT,
+  title TEXT NOT NULL,
+  syntax TEXT NOT NULL,
+  extensionless INTEGER NOT NULL DEFAULT 0,
+  user_id TEXT NOT NULL,
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY (note_id) REFERENCES notes(id) ON DELETE CASCADE,
+  FOREIGN KEY (parent_id) REFERENCES folders(id) ON DELETE CASCADE
+);
+CREATE INDEX idx_note_aliases_note_id ON note_aliases(note_id);
+CREATE INDEX idx_note_aliases_parent_user ON note_aliases(parent_id, user_id);
+
+CREATE TRIGGER notes_aliases_delete AFTER DELETE ON notes BEGIN
+    DELETE FROM note_aliases WHERE note_id = old.id;
+END;
+
+------------------------------------------------------------
+-- Attachments ---------------------------------------------
+------------------------------------------------------------
+
+-- Binary files (images, PDFs, ...) kept with a note, which can't be stored
+-- as note content since that is UTF-8 text
+CREATE TABLE attachments (
+  id TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+  note_id TEXT NOT NULL,
+  filename TEXT NOT NULL,
+  blob BLOB NOT NULL,
+  mime TEXT NOT NULL DEFAULT 'application/octet-stream',
+  created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(note_id, filename),
+  FOREIGN KEY (note_id) REFERENCES notes(id) ON DELETE CASCADE
+);
+
+CREATE TRIGGER notes_attachments_delete AFTER DELETE ON notes BEGIN
+    DELETE FROM attachments WHERE note_id = old.id;
+END;
+
+------------------------------------------------------------
+-- Links ---------------------------------------------------
+------------------------------------------------------------
+
+-- [[wikilinks]] found in each note's content when it is written, by the
+-- title they name; the note with that title may not exist (yet)
+CREATE TABLE links (
+  source_id TEXT NOT NULL,
+  target_title TEXT NOT NULL,
+  PRIMARY KEY (source_id, target_title),
+  FOREIGN KEY (source_id) REFERENCES notes(id) ON DELETE CASCADE
+);
+CREATE INDEX idx_links_target_title ON links(target_title);
+
+CREATE TRIGGER notes_links_delete AFTER DELETE ON notes BEGIN
+    DELETE FROM links WHERE source_id = old.id;
+END;
+
+------------------------------------------------------------
+-- Note Contents -------------------------------------------
+------------------------------------------------------------
+
+-- Bodies shared by notes with identical content (e.g. after a bulk import),
+-- keyed by a hash of the body. A note refers to one through content_hash
+-- until its content is written again, which stores it inline (copy-on-write).
+CREATE TABLE note_contents (
+  hash TEXT PRIMARY KEY,
+  body TEXT NOT NULL
+);
+
+CREATE TRIGGER note_contents_release_delete AFTER DELETE ON notes
+WHEN old.content_hash IS NOT NULL
+BEGIN
+    DELETE FROM note_contents WHERE hash = old.content_hash
+    AND NOT EXISTS (SELECT 1 FROM notes WHERE content_hash = old.content_hash);
+END;
+
+CREATE TRIGGER note_contents_release_update AFTER UPDATE OF content_hash ON notes
+WHEN old.content_hash IS NOT NULL AND new.content_hash IS NOT old.content_hash
+BEGIN
+    DELETE FROM note_contents WHERE hash = old.content_hash
+    AND NOT EXISTS (SELECT 1 FROM notes WHERE content_hash = old.content_hash);
+END;
+
+------------------------------------------------------------
+-- Syntax Extensions----------------------------------------
+------------------------------------------------------------
+
+-- Maps file name extensions to the syntax stored on notes. Extensions not
+-- listed are stored verbatim; the preferred extension is the one shown in
+-- file names for its syntax (syntaxes without one are shown verbatim).
+CREATE TABLE syntax_extensions (
+    extension TEXT PRIMARY KEY COLLATE NOCASE,
+    syntax TEXT NOT NULL,
+    preferred INTEGER NOT NULL DEFAULT 0
+);
+CREATE UNIQUE INDEX idx_syntax_extensions_preferred ON syntax_extensions(syntax) WHERE preferred;
+
+INSERT INTO syntax_extensions (extension, syntax, preferred) VALUES
+    ('md', 'md', 1),
+    ('markdown', 'md', 0),
+    ('mkd', 'md', 0),
+    ('mkdn', 'md', 0),
+    ('mdown', 'md', 0),
+    ('mdwn', 'md', 0),
+    ('txt', 'txt', 1),
+    ('text', 'txt', 0),
+    ('rst', 'rst', 1),
+    ('rest', 'rst', 0),
+    ('html', 'html', 1),
+    ('htm', 'html', 0),
+    ('yaml', 'yaml', 1),
+    ('yml', 'yaml', 0),
+    ('tex', 'tex', 1),
+    ('latex', 'tex', 0),
+    ('adoc', 'adoc', 1),
+    ('asciidoc', 'adoc', 0);
 
 ------------------------------------------------------------
 -- FTS -----------------------------------------------------
@@ -71,12 +307,71 @@ CREATE TRIGGER notes_fts_delete AFTER DELETE ON notes BEGIN
     DELETE FROM notes_fts WHERE id = old.id;
 END;
 
-CREATE TRIGGER notes_fts_update AFTER UPDATE ON notes BEGIN
+CREATE TRIGGER notes_fts_update AFTER UPDATE OF title, abstract, content, user_id ON notes BEGIN
     DELETE FROM notes_fts WHERE id = old.id;
     INSERT INTO notes_fts(id, title, abstract, content, user_id)
-    VALUES (new.id, new.title, new.abstract, new.content, new.user_id);
+    VALUES (new.id, new.title, new.abstract,
+            COALESCE((SELECT body FROM note_contents WHERE hash = new.content_hash), new.content),
+            new.user_id);
 END;
 
+------------------------------------------------------------
+-- Saved Searches ------------------------------------------
+------------------------------------------------------------
+
+-- FTS5 queries listed as directories under /.searches/<name>/
+CREATE TABLE saved_searches (
+    id TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    name TEXT NOT NULL,
+    query TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(user_id, name)
+);
+
+-- Other users allowed into a folder (and everything below it); the owner
+-- always has full access
+CREATE TABLE folder_acl (
+    folder_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    access TEXT NOT NULL CHECK (access IN ('read', 'write')),
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (folder_id, user_id),
+    FOREIGN KEY (folder_id) REFERENCES folders(id) ON DELETE CASCADE
+);
+CREATE INDEX idx_folder_acl_user_id ON folder_acl(user_id);
+
+------------------------------------------------------------
+-- Users ---------------------------------------------------
+------------------------------------------------------------
+
+-- Per-user preferences; a user's times are shown in their timezone, or the
+-- mount's --timezone when it is NULL (they are stored in UTC either way)
+CREATE TABLE users (
+    id TEXT PRIMARY KEY,
+    display_name TEXT,
+    timezone TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+------------------------------------------------------------
+-- Inodes --------------------------------------------------
+------------------------------------------------------------
+
+-- Inode number of each path the kernel has been told about, kept across
+-- mounts so file handles given out over NFS stay valid. Numbers are never
+-- reused (AUTOINCREMENT); the generation tells them apart from those of a
+-- database this one replaced.
+CREATE TABLE inodes (
+    ino INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id TEXT NOT NULL,
+    path TEXT NOT NULL,
+    generation INTEGER NOT NULL DEFAULT (CAST(strftime('%s', 'now') AS INTEGER)),
+    UNIQUE (user_id, path)
+);
+-- The root of every mount, so other paths start at 2
+INSERT INTO inodes (ino, user_id, path) VALUES (1, '', '/');
+
 
 ------------------------------------------------------------
 -- TODO FTS Trigram on Path --------------------------------
@@ -113,10 +408,19 @@ END;
  -- Update
 CREATE TRIGGER notes_before_update
 BEFORE UPDATE ON notes
+-- Skip the follow-up updates that only refresh content_size, revision or
+-- etag, moving the content into note_contents, and soft deletes (the note
+-- is logged once it is purged)
+WHEN new.content_size IS old.content_size AND new.revision IS old.revision
+AND NOT (old.content_hash IS NULL AND new.content_hash IS NOT NULL)
+AND NOT (new.etag IS NOT old.etag AND new.content IS old.content)
+AND new.deleted_at IS old.deleted_at
 BEGIN
     -- Copy the old version of the note to notes_history
     INSERT INTO notes_history (id, title, abstract, content, syntax, log_action, parent_id, user_id, created_at, updated_at, deleted_at)
-    VALUES (old.id, old.title, old.abstract, old.content, old.syntax, 'UPDATE', old.parent_id, old.user_id, old.created_at, old.updated_at, CURRENT_TIMESTAMP);
+    VALUES (old.id, old.title, old.abstract,
+            COALESCE((SELECT body FROM note_contents WHERE hash = old.content_hash), old.content),
+            old.syntax, 'UPDATE', old.parent_id, old.user_id, old.created_at, old.updated_at, CURRENT_TIMESTAMP);
 
     -- Delete older history entries beyond the last 30 for this note where log_action is 'UPDATE'
     DELETE FROM notes_history
@@ -137,7 +441,9 @@ BEFORE DELETE ON notes
 BEGIN
     -- Copy the note being deleted to notes_history
     INSERT INTO notes_history (id, title, abstract, content, syntax, log_action, parent_id, user_id, created_at, updated_at, deleted_at)
-    VALUES (old.id, old.title, old.abstract, old.content, old.syntax, 'DELETE', old.parent_id, old.user_id, old.created_at, old.updated_at, CURRENT_TIMESTAMP);
+    VALUES (old.id, old.title, old.abstract,
+            COALESCE((SELECT body FROM note_contents WHERE hash = old.content_hash), old.content),
+            old.syntax, 'DELETE', old.parent_id, old.user_id, old.created_at, old.updated_at, CURRENT_TIMESTAMP);
 
     -- Delete older history entries beyond the last 15 for this note where log_action is 'DELETE'
     DELETE FROM notes_history
@@ -156,6 +462,9 @@ END;
 -- Views ---------------------------------------------------
 ------------------------------------------------------------
 
+-- Titles may contain '/', which is shown as U+2044 (⁄) so every title stays a
+-- single path component. Keep in sync with SLASH_SUBSTITUTE in src/fuse_fs.rs.
+
 CREATE VIEW v_folder_id_path_mapping AS
 WITH RECURSIVE folder_path AS (
     -- Base case: root folders (folders with no parent)
@@ -164,9 +473,9 @@ WITH RECURSIVE folder_path AS (
         title,
         parent_id,
         user_id,
-        title AS path
+        replace(title, '/', '⁄') AS path
     FROM folders
-    WHERE parent_id IS NULL
+    WHERE parent_id IS NULL AND deleted_at IS NULL
 
     UNION ALL
 
@@ -176,9 +485,10 @@ WITH RECURSIVE folder_path AS (
         f.title,
         f.parent_id,
         f.user_id,
-        fp.path || '/' || f.title AS path
+        fp.path || '/' || replace(f.title, '/', '⁄') AS path
     FROM folders f
     INNER JOIN folder_path fp ON f.parent_id = fp.id
+    WHERE f.deleted_at IS NULL
 )
 SELECT
     id,
@@ -196,9 +506,9 @@ WITH RECURSIVE folder_path AS (
         title,
         parent_id,
         user_id,
-        title AS path
+        replace(title, '/', '⁄') AS path
     FROM folders
-    WHERE parent_id IS NULL
+    WHERE parent_id IS NULL AND deleted_at IS NULL
 
     UNION ALL
 
@@ -208,9 +518,10 @@ WITH RECURSIVE folder_path AS (
         f.title,
         f.parent_id,
         f.user_id,
-        fp.path || '/' || f.title AS path
+        fp.path || '/' || replace(f.title, '/', '⁄') AS path
     FROM folders f
     INNER JOIN folder_path fp ON f.parent_id = fp.id
+    WHERE f.deleted_at IS NULL
 )
 SELECT
     n.id,
@@ -218,9 +529,33 @@ SELECT
     n.syntax,
     n.user_id,
     CASE
-        WHEN n.parent_id IS NULL THEN n.title || '.' || n.syntax
-        ELSE fp.path || '/' || n.title || '.' || n.syntax
+        WHEN n.parent_id IS NULL THEN ''
+        ELSE fp.path || '/'
+    END || replace(n.title, '/', '⁄') || CASE
+        WHEN n.extensionless THEN ''
+        ELSE '.' || COALESCE(
+            (SELECT se.extension FROM syntax_extensions se
+             WHERE se.syntax = n.syntax AND se.preferred),
+            n.syntax)
     END AS full_path
 FROM notes n
-LEFT JOIN folder_path fp ON n.parent_id = fp.id;
+LEFT JOIN folder_path fp ON n.parent_id = fp.id
+WHERE n.deleted_at IS NULL;
 
+CREATE VIEW v_note_alias_path_mapping AS
+SELECT
+    a.id,
+    a.note_id,
+    a.user_id,
+    CASE
+        WHEN a.parent_id IS NULL THEN ''
+        ELSE fp.full_path || '/'
+    END || replace(a.title, '/', '⁄') || CASE
+        WHEN a.extensionless THEN ''
+        ELSE '.' || COALESCE(
+            (SELECT se.extension FROM syntax_extensions se
+             WHERE se.syntax = a.syntax AND se.preferred),
+            a.syntax)
+    END AS full_path
+FROM note_aliases a
+LEFT JOIN v_folder_id_path_mapping fp ON a.parent_id = fp.id;
@@ -0,0 +1,209 @@
+//! Timestamped backups of the mounted database (`--backup-dir`), taken with
+//! SQLite's backup API on a thread of their own, keeping the newest few
+//!
+//! Each backup is a complete database, e.g. "notes-20250102T030405Z.sqlite"
+//! for "notes.sqlite", that can be mounted or copied back as it is. It is
+//! written under a ".partial" name first, so an interrupted backup is never
+//! taken for one, and only files named like backups are ever removed.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite::Connection;
+
+use crate::database::Database;
+
+/// Format of the time in a backup's name, in UTC
+const STAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Extension of backups
+const EXTENSION: &str = "sqlite";
+
+/// How long a backup waits for the mount's own writes to finish
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where, how often and how many backups are kept
+#[derive(Debug, Clone)]
+pub struct BackupPolicy {
+    pub dir: PathBuf,
+    /// Name of the database file without its extension, which backups
+    /// start with
+    pub stem: String,
+    pub interval: Duration,
+    /// Backups kept; older ones are removed after each backup
+    pub keep: usize,
+    /// SQLCipher passphrase backups are encrypted with, as the database is
+    pub key: Option<String>,
+}
+
+impl BackupPolicy {
+    /// Name of the backup taken at `time`
+    fn backup_name(&self, time: &DateTime<Utc>) -> String {
+        format!("{}-{}.{EXTENSION}", self.stem, time.format(STAMP_FORMAT))
+    }
+
+    /// When the backup called `name` was taken, if it is one of these backups
+    fn taken_at(&self, name: &str) -> Option<DateTime<Utc>> {
+        let stamp = name
+            .strip_prefix(self.stem.as_str())?
+            .strip_prefix('-')?
+            .strip_suffix(EXTENSION)?
+            .strip_suffix('.')?;
+        NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT)
+            .ok()
+            .map(|time| time.and_utc())
+    }
+}
+
+/// Back the database up now and then every `policy.interval`, on a thread
+/// of its own. Each backup has a connection of its own from `open`, so it
+/// reaches whatever file is at the database path then (see
+/// `ExampleFuseFs::reopen_when_replaced`), and none is held in between.
+pub fn spawn(
+    open: impl Fn() -> rusqlite::Result<Connection> + Send + 'static,
+    policy: BackupPolicy,
+) {
+    let spawned = thread::Builder::new()
+        .name("backups".to_string())
+        .spawn(move || {
+            loop {
+                match back_up(&open, &policy) {
+                    Ok(path) => debug!("backups: Backed up to {}", path.display()),
+                    Err(e) => error!(
+                        "backups: Unable to back up to {}: {e}",
+                        policy.dir.display()
+                    ),
+                }
+                match prune(&policy) {
+                    Ok(removed) => {
+                        for path in removed {
+                            debug!("backups: Removed {}", path.display());
+                        }
+                    }
+                    Err(e) => error!(
+                        "backups: Unable to remove old backups from {}: {e}",
+                        policy.dir.display()
+                    ),
+                }
+                thread::sleep(policy.interval);
+            }
+        });
+    if let Err(e) = spawned {
+        error!("backups: Unable to start thread, the database won't be backed up: {e}");
+    }
+}
+
+/// Take one backup, on a new connection; the path of the backup
+fn back_up(
+    open: &impl Fn() -> rusqlite::Result<Connection>,
+    policy: &BackupPolicy,
+) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(&policy.dir)?;
+    let path = policy.dir.join(policy.backup_name(&Utc::now()));
+    let partial = path.with_extension(format!("{EXTENSION}.partial"));
+    let copied = (|| {
+        let connection = open()?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        Database::new(connection, None).snapshot(&partial, policy.key.as_deref(), None)
+    })();
+    if let Err(e) = copied {
+        let _ = std::fs::remove_file(&partial);
+        return Err(io::Error::other(e));
+    }
+    std::fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+/// Remove all but the newest `policy.keep` backups; the paths removed
+fn prune(policy: &BackupPolicy) -> io::Result<Vec<PathBuf>> {
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(&policy.dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if let Some(taken_at) = name.to_str().and_then(|name| policy.taken_at(name)) {
+            backups.push((taken_at, entry.path()));
+        }
+    }
+    // Newest first
+    backups.sort_unstable_by_key(|(taken_at, _)| std::cmp::Reverse(*taken_at));
+    let mut removed = Vec::new();
+    for (_, path) in backups.into_iter().skip(policy.keep) {
+        std::fs::remove_file(&path)?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// Name backups of the database at `path` start with
+pub fn backup_stem(path: &Path) -> String {
+    path.file_stem().map_or_else(
+        || "notes".to_string(),
+        |stem| stem.to_string_lossy().into_owned(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(dir: PathBuf, keep: usize) -> BackupPolicy {
+        BackupPolicy {
+            dir,
+            stem: "notes".to_string(),
+            interval: Duration::from_secs(60),
+            keep,
+            key: None,
+        }
+    }
+
+    #[test]
+    fn test_backup_name() {
+        let policy = policy(PathBuf::from("backups"), 3);
+        let time = DateTime::from_timestamp(1_735_787_045, 0).unwrap();
+        let name = policy.backup_name(&time);
+        assert_eq!(name, "notes-20250102T030405Z.sqlite");
+        assert_eq!(policy.taken_at(&name), Some(time));
+        assert_eq!(
+            policy.taken_at("notes-20250102T030405Z.sqlite.partial"),
+            None
+        );
+        assert_eq!(policy.taken_at("other-20250102T030405Z.sqlite"), None);
+        assert_eq!(policy.taken_at("notes-latest.sqlite"), None);
+        assert_eq!(backup_stem(Path::new("/data/notes.sqlite")), "notes");
+    }
+
+    #[test]
+    fn test_back_up_and_prune() {
+        let dir = std::env::temp_dir().join(format!("backups-{}", uuid::Uuid::new_v4()));
+        let policy = policy(dir.clone(), 2);
+        let source = dir.join("source.sqlite");
+        std::fs::create_dir_all(&dir).unwrap();
+        Connection::open(&source)
+            .unwrap()
+            .execute_batch("CREATE TABLE notes (title TEXT); INSERT INTO notes VALUES ('kept');")
+            .unwrap();
+
+        let path = back_up(&|| Connection::open(&source), &policy).unwrap();
+        let title: String = Connection::open(&path)
+            .unwrap()
+            .query_row("SELECT title FROM notes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(title, "kept");
+
+        // Older backups, and files that aren't backups
+        std::fs::write(dir.join("notes-20200101T000000Z.sqlite"), "").unwrap();
+        std::fs::write(dir.join("notes-20210101T000000Z.sqlite"), "").unwrap();
+        std::fs::write(dir.join("notes-20190101T000000Z.sqlite.partial"), "").unwrap();
+        let removed = prune(&policy).unwrap();
+        assert_eq!(removed, [dir.join("notes-20200101T000000Z.sqlite")]);
+        assert!(path.exists());
+        assert!(dir.join("notes-20210101T000000Z.sqlite").exists());
+        assert!(dir.join("notes-20190101T000000Z.sqlite.partial").exists());
+        assert!(source.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,185 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
 use chrono::{DateTime, NaiveDateTime, Utc};
 use chrono_tz::Tz;
-use rusqlite::{Connection, Result, params};
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Result, params};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::wikilinks;
+
+/// `PRAGMA user_version` from which timestamps are stored in UTC
+const UTC_TIMESTAMPS_VERSION: i64 = 1;
+
+/// `PRAGMA user_version` from which folders and notes have a `flags` column
+const FILE_FLAGS_VERSION: i64 = 2;
+
+/// `PRAGMA user_version` from which inode numbers are kept in `inodes`
+const INODES_VERSION: i64 = 3;
+
+/// `PRAGMA user_version` from which notes have an `encrypted` column
+const ENCRYPTED_NOTES_VERSION: i64 = 4;
+
+/// `PRAGMA user_version` from which notes have a `lock_hash` column
+const NOTE_LOCKS_VERSION: i64 = 5;
+
+/// `PRAGMA user_version` of the schema this build reads and writes: what
+/// `sql/init.sql` creates, and what `Database::migrate` brings older
+/// databases up to
+pub const SCHEMA_VERSION: i64 = NOTE_LOCKS_VERSION;
+
+/// One step of `Database::migrate`: returns false if it was done already
+type Migration = fn(&Database) -> Result<bool>;
+
+/// The schema new databases are created with
+const INIT_SQL: &str = include_str!("../sql/init.sql");
+
+/// The statement of `INIT_SQL` starting at byte `start`, up to and
+/// including its ';' (a trigger's "END;"), skipping comments and strings
+fn init_sql_statement_at(start: usize) -> &'static str {
+    let rest = &INIT_SQL[start..];
+    if rest.split_whitespace().nth(1) == Some("TRIGGER") {
+        let end = rest
+            .find("END;")
+            .map_or(rest.len(), |end| end + "END;".len());
+        return &rest[..end];
+    }
+    let bytes = rest.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i += rest[i..].find('\n').unwrap_or(rest.len() - i);
+            }
+            b'\'' => {
+                i += rest[i + 1..]
+                    .find('\'')
+                    .map_or(rest.len() - i, |end| end + 1)
+            }
+            b';' => return &rest[..=i],
+            _ => {}
+        }
+        i += 1;
+    }
+    rest
+}
+
+/// The statement of `sql/init.sql` creating `name` (a table, index, trigger
+/// or view), so migrations create it the way new databases have it
+fn init_sql_creating(name: &str) -> &'static str {
+    let start = INIT_SQL
+        .match_indices("CREATE ")
+        .map(|(start, _)| start)
+        .find(|&start| {
+            INIT_SQL[start..].split_whitespace().find(|word| {
+                !matches!(
+                    *word,
+                    "CREATE" | "UNIQUE" | "VIRTUAL" | "TABLE" | "INDEX" | "TRIGGER" | "VIEW"
+                )
+            }) == Some(name)
+        })
+        .unwrap_or_else(|| panic!("sql/init.sql doesn't create {name}"));
+    init_sql_statement_at(start)
+}
+
+/// How a database's schema compares to `SCHEMA_VERSION`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaState {
+    /// No tables at all: a new file, for the init subcommand
+    Empty,
+    Current,
+    /// Created by an older version, until `migrate` is run on it
+    Older(i64),
+    /// Written by a newer version of fuse_example, which this one can't read
+    Newer(i64),
+}
+
+impl SchemaState {
+    /// Why a database in this state can't be mounted, and what to do about
+    /// it; None if it can
+    pub fn problem(self) -> Option<String> {
+        match self {
+            SchemaState::Current => None,
+            SchemaState::Empty => Some(
+                "the database has no schema yet; set it up with the init subcommand".to_string(),
+            ),
+            SchemaState::Older(version) => Some(format!(
+                "the database has schema version {version}, older than version {SCHEMA_VERSION} \
+                 of this build; back it up and run the migrate subcommand on it"
+            )),
+            SchemaState::Newer(version) => Some(format!(
+                "the database has schema version {version}, newer than version {SCHEMA_VERSION} \
+                 of this build; mount it with a newer fuse_example"
+            )),
+        }
+    }
+}
+
+/// Title of the notes ("index.md", "index.rst", ...) holding a folder's own content
+pub const INDEX_TITLE: &str = "index";
+
+/// Columns of the search index a query can be limited to
+pub const SEARCH_COLUMNS: [&str; 3] = ["title", "abstract", "content"];
+
+/// bm25 weights of the search index columns (id, title, abstract, content,
+/// user_id): words in the title count most, then the abstract
+const SEARCH_WEIGHTS: &str = "0.0, 10.0, 5.0, 1.0, 0.0";
+
+/// How `fts_query` combines the words of a search
+#[derive(Debug, Clone, Default)]
+pub struct QuerySyntax {
+    /// The words in this order, next to each other
+    pub phrase: bool,
+    /// The words within this many words of each other
+    pub near: Option<u32>,
+    /// Only in this column (one of `SEARCH_COLUMNS`)
+    pub column: Option<String>,
+}
+
+/// An FTS5 query for `words`. With `phrase` or `near` they are matched as
+/// plain words (quoted, so '-', ':' or '*' mean nothing); otherwise they are
+/// FTS5 syntax already, e.g. `rust AND "error handling"`.
+pub fn fts_query(words: &[String], syntax: &QuerySyntax) -> String {
+    let quote = |word: &str| format!("\"{}\"", word.replace('"', "\"\""));
+    let query = if syntax.phrase {
+        quote(&words.join(" "))
+    } else if let Some(distance) = syntax.near {
+        let words: Vec<String> = words.iter().map(|word| quote(word)).collect();
+        format!("NEAR({}, {distance})", words.join(" "))
+    } else {
+        words.join(" ")
+    };
+    match &syntax.column {
+        Some(column) => format!("{column} : ({query})"),
+        None => query,
+    }
+}
+
+/// Key of a body in `note_contents`: its length and 64-bit FNV-1a hash
+///
+/// Stable across builds, unlike std's hasher. Bodies are compared before they
+/// are shared, so a collision only means a note stays inline.
+fn content_hash(content: &str) -> String {
+    let hash = content
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    format!("{:x}-{hash:016x}", content.len())
+}
 
 pub struct Database {
     pub connection: Connection,
+    /// Timezone times are shown in for users without one of their own
     pub timezone: Tz,
+    /// Timezones of the users whose times were shown, read once from `users`
+    user_timezones: RefCell<HashMap<String, Tz>>,
+    /// Match paths and titles with `COLLATE NOCASE` (stored case is preserved)
+    pub case_insensitive: bool,
+    /// Match paths and titles by their NFC forms (see `normalize_names`)
+    normalize_unicode: bool,
 }
 
 impl Database {
@@ -12,1804 +187,7709 @@ impl Database {
         Self {
             connection,
             timezone: timezone.unwrap_or(chrono_tz::UTC),
+            user_timezones: RefCell::new(HashMap::new()),
+            case_insensitive: false,
+            normalize_unicode: false,
         }
     }
 
-    pub fn create_folder(
-        &self,
-        title: &str,
-        parent_id: Option<&str>,
-        user_id: &str,
-    ) -> Result<String> {
-        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
-        let now = Utc::now()
-            .with_timezone(&self.timezone)
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
+    /// Match paths and titles by their NFC forms from now on, so names the
+    /// mount lists normalized still find titles stored decomposed (NFD)
+    pub fn normalize_names(&mut self) -> Result<()> {
+        self.connection
+            .create_collation("NFC", |a, b| a.nfc().cmp(b.nfc()))?;
+        // NOCASE folds ASCII letters only, and so does this
+        self.connection.create_collation("NFC_NOCASE", |a, b| {
+            a.nfc()
+                .map(|c| c.to_ascii_lowercase())
+                .cmp(b.nfc().map(|c| c.to_ascii_lowercase()))
+        })?;
+        self.normalize_unicode = true;
+        Ok(())
+    }
 
-        self.connection.execute(
-            "INSERT INTO folders (id, title, parent_id, user_id, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
-            params![id, title, parent_id, user_id, now],
+    /// Registers `user_id` with `profile`; false (changing nothing) if they
+    /// were already registered
+    pub fn add_user(&self, user_id: &str, profile: UserProfile) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "INSERT INTO users (id, display_name, timezone) VALUES (?1, ?2, ?3)
+             ON CONFLICT (id) DO NOTHING",
+            params![
+                user_id,
+                profile.display_name,
+                profile.timezone.map(|tz| tz.name())
+            ],
         )?;
+        Ok(rows_affected > 0)
+    }
 
-        Ok(id)
+    /// Registered users and everyone owning folders or notes, which covers
+    /// databases from before the users table, sorted
+    pub fn list_users(&self) -> Result<Vec<String>> {
+        let registered = if self.has_table("main", "users")? {
+            "SELECT id FROM users UNION"
+        } else {
+            ""
+        };
+        let mut stmt = self.connection.prepare(&format!(
+            "{registered} SELECT user_id FROM folders UNION SELECT user_id FROM notes ORDER BY 1"
+        ))?;
+        let users = stmt.query_map([], |row| row.get(0))?;
+
+        users.collect()
     }
 
-    pub fn get_folder_by_id(&self, id: &str, user_id: &str) -> Result<Option<Folder>> {
-        let mut stmt = self.connection.prepare(
-            "SELECT id, title, parent_id, user_id, created_at, updated_at FROM folders WHERE id = ?1 AND user_id = ?2",
+    /// Stores the timezone `user_id`'s times are shown in, or with `None`
+    /// goes back to the mount-wide one; adds the user if they aren't known yet
+    pub fn set_user_timezone(&self, user_id: &str, timezone: Option<Tz>) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO users (id, timezone) VALUES (?1, ?2)
+             ON CONFLICT (id) DO UPDATE SET timezone = excluded.timezone",
+            params![user_id, timezone.map(|tz| tz.name())],
         )?;
+        self.user_timezones.borrow_mut().remove(user_id);
+        Ok(())
+    }
 
-        let mut folder_iter = stmt.query_map([id, user_id], Self::map_folder_row)?;
+    /// `user_id`'s preferred timezone, if they stored one (and it is still a
+    /// known zone name)
+    pub fn get_user_timezone(&self, user_id: &str) -> Result<Option<Tz>> {
+        let name: Option<Option<String>> = self
+            .connection
+            .query_row(
+                "SELECT timezone FROM users WHERE id = ?1",
+                [user_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(name.flatten().and_then(|name| name.parse().ok()))
+    }
 
-        match folder_iter.next() {
-            Some(folder) => Ok(Some(folder?)),
-            None => Ok(None),
+    /// Timezone `user_id`'s times are shown in
+    pub fn timezone_for(&self, user_id: &str) -> Tz {
+        if let Some(&tz) = self.user_timezones.borrow().get(user_id) {
+            return tz;
         }
+        // Databases created before the users table existed use the mount-wide zone
+        let tz = self
+            .get_user_timezone(user_id)
+            .ok()
+            .flatten()
+            .unwrap_or(self.timezone);
+        self.user_timezones
+            .borrow_mut()
+            .insert(user_id.to_string(), tz);
+        tz
     }
 
-    pub fn update_folder(&self, id: &str, title: &str, user_id: &str) -> Result<bool> {
-        let now = Utc::now()
-            .with_timezone(&self.timezone)
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
-        let rows_affected = self.connection.execute(
-            "UPDATE folders SET title = ?1, updated_at = ?2 WHERE id = ?3 AND user_id = ?4",
-            params![title, now, id, user_id],
-        )?;
+    fn format_timestamp(dt: DateTime<Utc>) -> String {
+        dt.format("%Y-%m-%d %H:%M:%S").to_string()
+    }
 
-        Ok(rows_affected > 0)
+    /// The current time as stored: UTC, like `CURRENT_TIMESTAMP`
+    fn now() -> String {
+        Self::format_timestamp(Utc::now())
     }
 
-    pub fn delete_folder(&self, id: &str, user_id: &str) -> Result<bool> {
-        let rows_affected = self.connection.execute(
-            "DELETE FROM folders WHERE id = ?1 AND user_id = ?2",
-            params![id, user_id],
-        )?;
+    /// Whether `schema` ("main", or an attached database) has the table `name`
+    fn has_table(&self, schema: &str, name: &str) -> Result<bool> {
+        self.connection.query_row(
+            &format!(
+                "SELECT EXISTS (SELECT 1 FROM {schema}.sqlite_master WHERE type = 'table' AND name = ?1)"
+            ),
+            [name],
+            |row| row.get(0),
+        )
+    }
 
-        Ok(rows_affected > 0)
+    /// Whether the database has the index `name`
+    fn has_index(&self, name: &str) -> Result<bool> {
+        self.connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM main.sqlite_master WHERE type = 'index' AND name = ?1)",
+            [name],
+            |row| row.get(0),
+        )
     }
 
-    pub fn update_folder_parent(
-        &self,
-        id: &str,
-        parent_id: Option<&str>,
-        user_id: &str,
-    ) -> Result<bool> {
-        let now = Utc::now()
-            .with_timezone(&self.timezone)
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
+    /// Creates `names` (tables, indexes, triggers or views) as `sql/init.sql`
+    /// does, replacing indexes, triggers and views of an older definition;
+    /// tables that exist are left as they are
+    fn create_from_init_sql(conn: &Connection, names: &[&str]) -> Result<()> {
+        for name in names {
+            let statement = init_sql_creating(name);
+            let kind = statement
+                .split_whitespace()
+                .find(|word| matches!(*word, "TABLE" | "INDEX" | "TRIGGER" | "VIEW"))
+                .unwrap_or("TABLE");
+            if kind == "TABLE" {
+                let exists: bool = conn.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM main.sqlite_master WHERE type = 'table' AND name = ?1)",
+                    [name],
+                    |row| row.get(0),
+                )?;
+                if exists {
+                    continue;
+                }
+            } else {
+                conn.execute_batch(&format!("DROP {kind} IF EXISTS {name}"))?;
+            }
+            conn.execute_batch(statement)?;
+        }
+        Ok(())
+    }
 
-        let rows_affected = match parent_id {
-            Some(pid) => self.connection.execute(
-                "UPDATE folders SET parent_id = ?1, updated_at = ?2 WHERE id = ?3 AND user_id = ?4",
-                params![pid, now, id, user_id],
-            )?,
-            None => self.connection.execute(
-                "UPDATE folders SET parent_id = NULL, updated_at = ?1 WHERE id = ?2 AND user_id = ?3",
-                params![now, id, user_id],
-            )?,
-        };
+    /// How the schema compares to the one this build expects
+    pub fn schema_state(&self) -> Result<SchemaState> {
+        let version: i64 = self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let empty: bool = self.connection.query_row(
+            "SELECT NOT EXISTS (SELECT 1 FROM main.sqlite_master WHERE type = 'table')",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(match version {
+            0 if empty => SchemaState::Empty,
+            SCHEMA_VERSION => SchemaState::Current,
+            version if version < SCHEMA_VERSION => SchemaState::Older(version),
+            version => SchemaState::Newer(version),
+        })
+    }
 
-        Ok(rows_affected > 0)
+    /// Creates the schema (`sql/init.sql`) in a database without one, next
+    /// to any tables of its own. Returns false, changing nothing, if it has
+    /// the notes tables already, so running it on a database in use is
+    /// harmless.
+    pub fn init_schema(&self) -> Result<bool> {
+        if self.has_table("main", "notes")? {
+            return Ok(false);
+        }
+        // The journal mode can't change inside a transaction; the rest is
+        // all or nothing, so a failure leaves no half a schema behind
+        let journal_mode = init_sql_statement_at(0);
+        debug_assert!(journal_mode.starts_with("PRAGMA journal_mode"));
+        self.connection.execute_batch(journal_mode)?;
+        let tx = self.connection.unchecked_transaction()?;
+        // The error alone: SQL input errors come with the whole of init.sql
+        tx.execute_batch(&INIT_SQL[journal_mode.len()..])
+            .map_err(|e| match e {
+                rusqlite::Error::SqlInputError { error, msg, .. } => {
+                    rusqlite::Error::SqliteFailure(error, Some(msg))
+                }
+                e => e,
+            })?;
+        tx.commit()?;
+        Ok(true)
     }
 
-    pub fn list_folders_by_parent(
-        &self,
-        parent_id: Option<&str>,
-        user_id: &str,
-    ) -> Result<Vec<Folder>> {
-        let query = match parent_id {
-            Some(_) => {
-                "SELECT id, title, parent_id, user_id, created_at, updated_at FROM folders WHERE parent_id = ?1 AND user_id = ?2 ORDER BY title"
+    /// Brings the schema of a database created by an older version up to
+    /// `SCHEMA_VERSION`, one step at a time. Returns what was done, nothing
+    /// if it was current already.
+    ///
+    /// The first steps add what the schema gained before it had a version
+    /// (`PRAGMA user_version` 0); they look at the schema itself to tell
+    /// whether they are needed.
+    pub fn migrate(&self) -> Result<Vec<&'static str>> {
+        let steps: &[(Migration, &str)] = &[
+            (Self::migrate_unique_names, "Added the unique name indexes"),
+            (Self::migrate_path_views, "Escaped '/' in paths"),
+            (
+                Self::migrate_extensionless,
+                "Added the extensionless column",
+            ),
+            (
+                Self::migrate_syntax_extensions,
+                "Added the syntax_extensions table",
+            ),
+            (Self::migrate_tags, "Added the tags tables"),
+            (
+                Self::migrate_saved_searches,
+                "Added the saved_searches table",
+            ),
+            (Self::migrate_note_aliases, "Added the note_aliases table"),
+            (Self::migrate_access_times, "Added the accessed_at columns"),
+            (
+                Self::migrate_ownership,
+                "Added the mode, uid and gid columns",
+            ),
+            (Self::migrate_content_size, "Added the content_size column"),
+            (Self::migrate_revisions, "Added the revision column"),
+            (Self::migrate_note_contents, "Added the note_contents table"),
+            (Self::migrate_attachments, "Added the attachments table"),
+            (Self::migrate_links, "Added the links table"),
+            (Self::migrate_etags, "Added the etag column"),
+            (Self::migrate_soft_deletes, "Added the deleted_at columns"),
+            (Self::migrate_folder_acl, "Added the folder_acl table"),
+            (Self::migrate_users, "Added the users table"),
+            (Self::migrate_display_names, "Added the display_name column"),
+            (
+                Self::migrate_timestamps_to_utc,
+                "Converted stored timestamps to UTC",
+            ),
+            (Self::migrate_file_flags, "Added file flags columns"),
+            (Self::migrate_inodes, "Added the inodes table"),
+            (Self::migrate_encrypted_notes, "Added the encrypted column"),
+            (Self::migrate_note_locks, "Added the lock_hash column"),
+        ];
+        let mut done = Vec::new();
+        for &(step, description) in steps {
+            if step(self)? {
+                done.push(description);
             }
-            None => {
-                "SELECT id, title, parent_id, user_id, created_at, updated_at FROM folders WHERE parent_id IS NULL AND user_id = ?1 ORDER BY title"
+        }
+        Ok(done)
+    }
+
+    /// Gives folders, and notes at the root, that share a name in their
+    /// directory names of their own (" (1)", " (2)", ...), then adds the
+    /// indexes keeping names unique, to a database created before them.
+    /// Returns false if already done.
+    pub fn migrate_unique_names(&self) -> Result<bool> {
+        if self.has_index("idx_folders_unique_title")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        // Notes inside a folder were already unique (a table constraint)
+        let renames: [(&str, &str, &str); 2] = [
+            (
+                "folders",
+                "parent_id, user_id, title",
+                "parent_id IS ?1 AND user_id = ?2",
+            ),
+            (
+                "notes",
+                "user_id, title, syntax",
+                "parent_id IS ?1 AND user_id = ?2 AND syntax = ?4",
+            ),
+        ];
+        for (table, name, directory) in renames {
+            let duplicates = {
+                let mut stmt = tx.prepare(&format!(
+                    "SELECT id, parent_id, user_id, title, {syntax} FROM {table}
+                     WHERE rowid NOT IN (SELECT MIN(rowid) FROM {table} GROUP BY parent_id, {name})
+                     {root} ORDER BY rowid",
+                    syntax = if table == "notes" { "syntax" } else { "''" },
+                    root = if table == "notes" {
+                        "AND parent_id IS NULL"
+                    } else {
+                        ""
+                    },
+                ))?;
+                stmt.query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>>>()?
+            };
+            let mut taken = tx.prepare(&format!(
+                "SELECT EXISTS (SELECT 1 FROM {table} WHERE {directory} AND title = ?3)"
+            ))?;
+            for (id, parent_id, user_id, title, syntax) in duplicates {
+                let mut candidate = title.clone();
+                let mut n = 1;
+                loop {
+                    let mut params: Vec<&dyn rusqlite::ToSql> =
+                        vec![&parent_id, &user_id, &candidate];
+                    if table == "notes" {
+                        params.push(&syntax);
+                    }
+                    if !taken.query_row(params.as_slice(), |row| row.get::<_, bool>(0))? {
+                        break;
+                    }
+                    candidate = format!("{title} ({n})");
+                    n += 1;
+                }
+                tx.execute(
+                    &format!("UPDATE {table} SET title = ?2 WHERE id = ?1"),
+                    params![id, candidate],
+                )?;
             }
-        };
+        }
+        // As first added; migrate_soft_deletes leaves deleted rows out of them
+        tx.execute_batch(
+            "CREATE UNIQUE INDEX idx_folders_unique_title ON folders(parent_id, user_id, title) WHERE parent_id IS NOT NULL;
+             CREATE UNIQUE INDEX idx_folders_unique_root_title ON folders(user_id, title) WHERE parent_id IS NULL;
+             CREATE UNIQUE INDEX idx_notes_unique_root_title ON notes(user_id, title, syntax) WHERE parent_id IS NULL;",
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        let mut stmt = self.connection.prepare(query)?;
-        let folder_iter = match parent_id {
-            Some(pid) => stmt.query_map(params![pid, user_id], Self::map_folder_row)?,
-            None => stmt.query_map([user_id], Self::map_folder_row)?,
-        };
+    /// Replaces the path views of a database from before a '/' in a title
+    /// was shown as '⁄'. Returns false if already done.
+    pub fn migrate_path_views(&self) -> Result<bool> {
+        let view: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT sql FROM main.sqlite_master WHERE type = 'view' AND name = 'v_note_id_path_mapping'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if view.is_some_and(|sql| sql.contains('⁄')) {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        Self::create_from_init_sql(&tx, &["v_folder_id_path_mapping", "v_note_id_path_mapping"])?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        folder_iter.collect()
+    /// Adds the `extensionless` column (files without an extension, e.g.
+    /// LICENSE) to notes of a database created before it existed. Returns
+    /// false if already done.
+    pub fn migrate_extensionless(&self) -> Result<bool> {
+        if self.has_column("main", "notes", "extensionless")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        // The table's UNIQUE constraint only gains the column when
+        // migrate_soft_deletes rebuilds the table
+        tx.execute_batch(
+            "ALTER TABLE notes ADD COLUMN extensionless INTEGER NOT NULL DEFAULT 0;
+             DROP INDEX IF EXISTS idx_notes_unique_root_title;
+             CREATE UNIQUE INDEX idx_notes_unique_root_title ON notes(user_id, title, syntax, extensionless) WHERE parent_id IS NULL;",
+        )?;
+        Self::create_from_init_sql(&tx, &["v_note_id_path_mapping"])?;
+        tx.commit()?;
+        Ok(true)
     }
 
-    pub fn create_note(
-        &self,
-        id: &str,
-        note_content: NoteContent,
-        parent_id: Option<&str>,
-        user_id: &str,
-    ) -> Result<String> {
-        let now = Utc::now()
-            .with_timezone(&self.timezone)
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
+    /// Adds the `syntax_extensions` table, with the extensions new databases
+    /// start with, to a database created before it existed. Returns false if
+    /// already done.
+    pub fn migrate_syntax_extensions(&self) -> Result<bool> {
+        if self.has_table("main", "syntax_extensions")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        Self::create_from_init_sql(
+            &tx,
+            &["syntax_extensions", "idx_syntax_extensions_preferred"],
+        )?;
+        let rows = INIT_SQL
+            .find("INSERT INTO syntax_extensions")
+            .expect("sql/init.sql fills syntax_extensions");
+        tx.execute_batch(init_sql_statement_at(rows))?;
+        Self::create_from_init_sql(&tx, &["v_note_id_path_mapping"])?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        self.connection.execute(
-            "INSERT INTO notes (id, title, abstract, content, syntax, parent_id, user_id, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
-            params![
-                id,
-                note_content.title,
-                note_content.abstract_text,
-                note_content.content,
-                note_content.syntax,
-                parent_id,
-                user_id,
-                now
+    /// Adds the `tags` and `note_tags` tables to a database created before
+    /// they existed. Returns false if already done.
+    pub fn migrate_tags(&self) -> Result<bool> {
+        if self.has_table("main", "tags")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        Self::create_from_init_sql(
+            &tx,
+            &[
+                "tags",
+                "note_tags",
+                "idx_note_tags_tag_id",
+                "notes_tags_delete",
             ],
         )?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        Ok(id.to_string())
+    /// Adds the `saved_searches` table to a database created before it
+    /// existed. Returns false if already done.
+    pub fn migrate_saved_searches(&self) -> Result<bool> {
+        if self.has_table("main", "saved_searches")? {
+            return Ok(false);
+        }
+        Self::create_from_init_sql(&self.connection, &["saved_searches"])?;
+        Ok(true)
     }
 
-    pub fn get_note_by_id(&self, id: &str) -> Result<Option<Note>> {
-        let mut stmt = self.connection.prepare(
-            "SELECT id, title, abstract, content, syntax, parent_id, user_id, created_at, updated_at
-             FROM notes WHERE id = ?1",
+    /// Adds the `note_aliases` table (hard links) to a database created
+    /// before it existed. Returns false if already done.
+    pub fn migrate_note_aliases(&self) -> Result<bool> {
+        if self.has_table("main", "note_aliases")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        Self::create_from_init_sql(
+            &tx,
+            &[
+                "note_aliases",
+                "idx_note_aliases_note_id",
+                "idx_note_aliases_parent_user",
+                "notes_aliases_delete",
+                "v_note_alias_path_mapping",
+            ],
         )?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        let mut note_iter = stmt.query_map([id], Self::map_note_row)?;
+    /// Adds the `accessed_at` columns (set through utimens) to a database
+    /// created before they existed. Returns false if already done.
+    pub fn migrate_access_times(&self) -> Result<bool> {
+        if self.has_column("main", "notes", "accessed_at")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        for table in ["folders", "notes"] {
+            if !self.has_column("main", table, "accessed_at")? {
+                tx.execute(
+                    &format!("ALTER TABLE {table} ADD COLUMN accessed_at DATETIME"),
+                    [],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(true)
+    }
 
-        match note_iter.next() {
-            Some(note) => Ok(Some(note?)),
-            None => Ok(None),
+    /// Adds the `mode`, `uid` and `gid` columns (set by chmod and chown) to
+    /// a database created before they existed. Returns false if already done.
+    pub fn migrate_ownership(&self) -> Result<bool> {
+        if self.has_column("main", "notes", "mode")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        for table in ["folders", "notes"] {
+            for column in ["mode", "uid", "gid"] {
+                if !self.has_column("main", table, column)? {
+                    tx.execute(
+                        &format!("ALTER TABLE {table} ADD COLUMN {column} INTEGER"),
+                        [],
+                    )?;
+                }
+            }
         }
+        tx.commit()?;
+        Ok(true)
     }
 
-    pub fn update_note(
-        &self,
-        id: &str,
-        title: &str,
-        abstract_text: Option<&str>,
-        content: &str,
-        syntax: &str,
-    ) -> Result<bool> {
-        let now = Utc::now()
-            .with_timezone(&self.timezone)
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
+    /// Adds the `content_size` column, filled in from the content, and the
+    /// triggers keeping it current to a database created before it existed.
+    /// Returns false if already done.
+    pub fn migrate_content_size(&self) -> Result<bool> {
+        if self.has_column("main", "notes", "content_size")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        // Filling in the sizes is neither an edit to keep a version of nor
+        // one to index again
+        tx.execute_batch(
+            "DROP TRIGGER IF EXISTS notes_before_update;
+             DROP TRIGGER IF EXISTS notes_fts_update;
+             ALTER TABLE notes ADD COLUMN content_size INTEGER NOT NULL DEFAULT 0;
+             UPDATE notes SET content_size = length(CAST(content AS BLOB));",
+        )?;
+        Self::create_from_init_sql(
+            &tx,
+            &[
+                "notes_content_size_insert",
+                "notes_content_size_update",
+                "notes_fts_update",
+                "notes_before_update",
+            ],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        let rows_affected = self.connection.execute(
-            "UPDATE notes SET title = ?1, abstract = ?2, content = ?3, syntax = ?4, updated_at = ?5
-             WHERE id = ?6",
-            params![title, abstract_text, content, syntax, now, id],
+    /// Adds the `revision` column, and the trigger bumping it, to a database
+    /// created before it existed. Returns false if already done.
+    pub fn migrate_revisions(&self) -> Result<bool> {
+        if self.has_column("main", "notes", "revision")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute(
+            "ALTER TABLE notes ADD COLUMN revision INTEGER NOT NULL DEFAULT 0",
+            [],
         )?;
+        Self::create_from_init_sql(&tx, &["notes_revision_update", "notes_before_update"])?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        Ok(rows_affected > 0)
+    /// Adds the `content_hash` column and the `note_contents` table it refers
+    /// to (contents shared by dedup) to a database created before they
+    /// existed, and the triggers reading through them. Returns false if
+    /// already done.
+    pub fn migrate_note_contents(&self) -> Result<bool> {
+        if self.has_column("main", "notes", "content_hash")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute("ALTER TABLE notes ADD COLUMN content_hash TEXT", [])?;
+        Self::create_from_init_sql(
+            &tx,
+            &[
+                "idx_notes_content_hash",
+                "note_contents",
+                "note_contents_release_delete",
+                "note_contents_release_update",
+                "notes_content_size_insert",
+                "notes_content_size_update",
+                "notes_revision_update",
+                "notes_fts_update",
+                "notes_before_update",
+                "notes_before_delete",
+            ],
+        )?;
+        tx.commit()?;
+        Ok(true)
     }
 
-    pub fn update_note_parent(&self, id: &str, parent_id: Option<&str>) -> Result<bool> {
-        let now = Utc::now()
-            .with_timezone(&self.timezone)
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
+    /// Adds the `attachments` table to a database created before it existed.
+    /// Returns false if already done.
+    pub fn migrate_attachments(&self) -> Result<bool> {
+        if self.has_table("main", "attachments")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        Self::create_from_init_sql(&tx, &["attachments", "notes_attachments_delete"])?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        let rows_affected = self.connection.execute(
-            "UPDATE notes SET parent_id = ?1, updated_at = ?2 WHERE id = ?3",
-            params![parent_id, now, id],
+    /// Adds the `links` table, filled in from the wikilinks of every note, to
+    /// a database created before it existed. Returns false if already done.
+    pub fn migrate_links(&self) -> Result<bool> {
+        if self.has_table("main", "links")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        Self::create_from_init_sql(
+            &tx,
+            &["links", "idx_links_target_title", "notes_links_delete"],
         )?;
+        let notes = {
+            let mut stmt = tx.prepare(
+                "SELECT id, COALESCE((SELECT body FROM note_contents WHERE hash = content_hash), content)
+                 FROM notes",
+            )?;
+            stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>>>()?
+        };
+        for (id, content) in notes {
+            Self::index_links(&tx, &id, &content)?;
+        }
+        tx.commit()?;
+        Ok(true)
+    }
 
-        Ok(rows_affected > 0)
+    /// Adds the `etag` column, and the trigger clearing it when another
+    /// writer changes the content, to a database created before it existed.
+    /// Returns false if already done.
+    pub fn migrate_etags(&self) -> Result<bool> {
+        if self.has_column("main", "notes", "etag")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute("ALTER TABLE notes ADD COLUMN etag TEXT", [])?;
+        Self::create_from_init_sql(&tx, &["notes_etag_update", "notes_before_update"])?;
+        tx.commit()?;
+        Ok(true)
     }
 
-    pub fn delete_note(&self, id: &str) -> Result<bool> {
-        let rows_affected = self
+    /// Adds the `deleted_at` columns (rm and rmdir only hide rows until
+    /// purge-deleted) to a database created before they existed. The notes
+    /// table is rebuilt, since deleted notes mustn't hold on to their names
+    /// and its UNIQUE constraint can't be changed in place. Returns false if
+    /// already done.
+    pub fn migrate_soft_deletes(&self) -> Result<bool> {
+        if self.has_column("main", "notes", "deleted_at")? {
+            return Ok(false);
+        }
+        let foreign_keys: bool = self
             .connection
-            .execute("DELETE FROM notes WHERE id = ?1", params![id])?;
-
-        Ok(rows_affected > 0)
+            .query_row("PRAGMA foreign_keys", [], |row| row.get(0))?;
+        // Dropping the old table would delete the rows referring to its notes
+        // (the setting can't change inside a transaction)
+        self.connection.execute_batch("PRAGMA foreign_keys = OFF")?;
+        let rebuilt = self.rebuild_notes_for_soft_deletes();
+        if foreign_keys {
+            self.connection.execute_batch("PRAGMA foreign_keys = ON")?;
+        }
+        rebuilt?;
+        Ok(true)
     }
 
-    pub fn list_notes_by_parent(
-        &self,
-        parent_id: Option<&str>,
-        user_id: &str,
-    ) -> Result<Vec<Note>> {
-        let query = match parent_id {
-            Some(_) => "SELECT id, title, abstract, content, syntax, parent_id, user_id, created_at, updated_at
-                       FROM notes WHERE parent_id = ?1 AND user_id = ?2 ORDER BY title",
-            None => "SELECT id, title, abstract, content, syntax, parent_id, user_id, created_at, updated_at
-                    FROM notes WHERE parent_id IS NULL AND user_id = ?1 ORDER BY title"
+    /// The body of `migrate_soft_deletes`, run with foreign keys off
+    fn rebuild_notes_for_soft_deletes(&self) -> Result<()> {
+        let tx = self.connection.unchecked_transaction()?;
+        if !self.has_column("main", "folders", "deleted_at")? {
+            tx.execute("ALTER TABLE folders ADD COLUMN deleted_at DATETIME", [])?;
+        }
+        // Renaming the rebuilt table checks the views, which read notes
+        tx.execute_batch(
+            "DROP VIEW IF EXISTS v_folder_id_path_mapping;
+             DROP VIEW IF EXISTS v_note_id_path_mapping;
+             DROP VIEW IF EXISTS v_note_alias_path_mapping;",
+        )?;
+        tx.execute_batch(&init_sql_creating("notes").replacen(
+            "CREATE TABLE notes (",
+            "CREATE TABLE notes_rebuilt (",
+            1,
+        ))?;
+        let columns = {
+            let mut stmt = tx.prepare(
+                "SELECT name FROM pragma_table_info('notes')
+                 WHERE name IN (SELECT name FROM pragma_table_info('notes_rebuilt'))",
+            )?;
+            stmt.query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>>>()?
+                .join(", ")
         };
+        // Dropping notes drops its indexes and triggers along with it
+        tx.execute_batch(&format!(
+            "INSERT INTO notes_rebuilt ({columns}) SELECT {columns} FROM notes;
+             DROP TABLE notes;
+             ALTER TABLE notes_rebuilt RENAME TO notes;"
+        ))?;
+        Self::create_from_init_sql(
+            &tx,
+            &[
+                "idx_notes_user_id",
+                "idx_notes_parent_id",
+                "idx_notes_syntax",
+                "idx_notes_updated_at",
+                "idx_notes_parent_title",
+                "idx_notes_parent_updated",
+                "idx_notes_content",
+                "idx_notes_content_hash",
+                "idx_notes_parent_user",
+                "idx_notes_root_user_title",
+                "idx_notes_unique_title",
+                "idx_notes_unique_root_title",
+                "idx_folders_unique_title",
+                "idx_folders_unique_root_title",
+                "notes_content_size_insert",
+                "notes_content_size_update",
+                "notes_revision_update",
+                "notes_etag_update",
+                "notes_tags_delete",
+                "notes_aliases_delete",
+                "notes_attachments_delete",
+                "notes_links_delete",
+                "note_contents_release_delete",
+                "note_contents_release_update",
+                "notes_fts_insert",
+                "notes_fts_delete",
+                "notes_fts_update",
+                "notes_before_update",
+                "notes_before_delete",
+                "v_folder_id_path_mapping",
+                "v_note_id_path_mapping",
+                "v_note_alias_path_mapping",
+            ],
+        )?;
+        tx.commit()
+    }
 
-        let mut stmt = self.connection.prepare(query)?;
-        let note_iter = match parent_id {
-            Some(pid) => stmt.query_map(params![pid, user_id], Self::map_note_row)?,
-            None => stmt.query_map([user_id], Self::map_note_row)?,
-        };
+    /// Adds the `folder_acl` table (folders shared with other users) to a
+    /// database created before it existed. Returns false if already done.
+    pub fn migrate_folder_acl(&self) -> Result<bool> {
+        if self.has_table("main", "folder_acl")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        Self::create_from_init_sql(&tx, &["folder_acl", "idx_folder_acl_user_id"])?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        note_iter.collect()
+    /// Adds the `users` table (per-user timezones) to a database created
+    /// before it existed, registering everyone owning folders or notes so
+    /// they can still mount it. Returns false if already done.
+    pub fn migrate_users(&self) -> Result<bool> {
+        if self.has_table("main", "users")? {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        Self::create_from_init_sql(&tx, &["users"])?;
+        tx.execute(
+            "INSERT OR IGNORE INTO users (id)
+             SELECT user_id FROM folders UNION SELECT user_id FROM notes",
+            [],
+        )?;
+        tx.commit()?;
+        Ok(true)
     }
 
-    #[cfg(test)]
-    pub fn get_folder_path_by_id(&self, id: &str, user_id: &str) -> Result<Option<String>> {
-        let mut stmt = self.connection.prepare(
-            "SELECT full_path FROM v_folder_id_path_mapping WHERE id = ?1 AND user_id = ?2",
-        )?;
+    /// Adds the `display_name` column to the users of a database created
+    /// before it existed. Returns false if already done.
+    pub fn migrate_display_names(&self) -> Result<bool> {
+        if self.has_column("main", "users", "display_name")? {
+            return Ok(false);
+        }
+        self.connection
+            .execute("ALTER TABLE users ADD COLUMN display_name TEXT", [])?;
+        Ok(true)
+    }
 
-        let mut path_iter = stmt.query_map([id, user_id], |row| row.get::<_, String>(0))?;
+    /// Rewrites the timestamps of a database from before they were stored
+    /// in UTC, when they were local times in the writer's timezone: each
+    /// user's own, else this database's. Returns false if already done.
+    pub fn migrate_timestamps_to_utc(&self) -> Result<bool> {
+        let version: i64 = self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version >= UTC_TIMESTAMPS_VERSION {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+
+        // Moving a timestamp isn't an edit worth keeping a version for
+        let history_trigger: Option<String> = tx
+            .query_row(
+                "SELECT sql FROM sqlite_master WHERE type = 'trigger' AND name = 'notes_before_update'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if history_trigger.is_some() {
+            tx.execute("DROP TRIGGER notes_before_update", [])?;
+        }
 
-        match path_iter.next() {
-            Some(path) => Ok(Some(path?)),
-            None => Ok(None),
+        // Columns the application wrote; the others default to CURRENT_TIMESTAMP
+        let times = ["created_at", "updated_at", "accessed_at", "deleted_at"];
+        let tables: [(&str, &str, &[&str]); 4] = [
+            ("folders", "user_id", &times),
+            ("notes", "user_id", &times),
+            ("notes_history", "user_id", &times[..2]),
+            (
+                "attachments",
+                "(SELECT user_id FROM notes WHERE notes.id = attachments.note_id)",
+                &times[..2],
+            ),
+        ];
+        for (table, owner, columns) in tables {
+            if !self.has_table("main", table)? {
+                continue;
+            }
+            // Left out where the database predates them
+            let mut present = Vec::new();
+            for &column in columns {
+                if self.has_column("main", table, column)? {
+                    present.push(column);
+                }
+            }
+            let columns = present;
+            let rows = {
+                let mut stmt = tx.prepare(&format!(
+                    "SELECT rowid, {owner}, {} FROM {table}",
+                    columns.join(", ")
+                ))?;
+                stmt.query_map([], |row| {
+                    let values = (0..columns.len())
+                        .map(|i| row.get::<_, Option<String>>(i + 2))
+                        .collect::<Result<Vec<_>>>()?;
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        values,
+                    ))
+                })?
+                .collect::<Result<Vec<_>>>()?
+            };
+            let assignments: Vec<String> = columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| format!("{column} = ?{}", i + 2))
+                .collect();
+            let mut update = tx.prepare(&format!(
+                "UPDATE {table} SET {} WHERE rowid = ?1",
+                assignments.join(", ")
+            ))?;
+            for (rowid, owner, values) in rows {
+                let timezone = owner.map_or(self.timezone, |owner| self.timezone_for(&owner));
+                let values: Vec<Option<String>> = values
+                    .into_iter()
+                    .map(|value| {
+                        value.map(|value| Self::local_to_utc(&value, timezone).unwrap_or(value))
+                    })
+                    .collect();
+                let mut params: Vec<&dyn rusqlite::ToSql> = vec![&rowid];
+                params.extend(values.iter().map(|value| value as &dyn rusqlite::ToSql));
+                update.execute(params.as_slice())?;
+            }
+        }
+
+        if let Some(sql) = history_trigger {
+            tx.execute_batch(&sql)?;
         }
+        tx.execute_batch(&format!("PRAGMA user_version = {UTC_TIMESTAMPS_VERSION}"))?;
+        tx.commit()?;
+        Ok(true)
     }
 
-    pub fn get_folder_id_by_path(&self, path: &str, user_id: &str) -> Result<Option<String>> {
-        let mut stmt = self.connection.prepare(
-            "SELECT id FROM v_folder_id_path_mapping WHERE full_path = ?1 AND user_id = ?2",
-        )?;
+    /// Adds the `flags` column (BSD file flags, set by chflags) to folders and
+    /// notes of a database created before it existed. Returns false if
+    /// already done.
+    pub fn migrate_file_flags(&self) -> Result<bool> {
+        let version: i64 = self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version >= FILE_FLAGS_VERSION {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        for table in ["folders", "notes"] {
+            if !self.has_column("main", table, "flags")? {
+                tx.execute(&format!("ALTER TABLE {table} ADD COLUMN flags INTEGER"), [])?;
+            }
+        }
+        tx.execute_batch(&format!("PRAGMA user_version = {FILE_FLAGS_VERSION}"))?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        let mut id_iter = stmt.query_map([path, user_id], |row| row.get::<_, String>(0))?;
+    /// Adds the `inodes` table to a database created before it existed.
+    /// Returns false if already done.
+    pub fn migrate_inodes(&self) -> Result<bool> {
+        let version: i64 = self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version >= INODES_VERSION {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        if !self.has_table("main", "inodes")? {
+            Self::create_from_init_sql(&tx, &["inodes"])?;
+            let root = INIT_SQL
+                .find("INSERT INTO inodes")
+                .expect("sql/init.sql adds the root to inodes");
+            tx.execute_batch(init_sql_statement_at(root))?;
+        }
+        tx.execute_batch(&format!("PRAGMA user_version = {INODES_VERSION}"))?;
+        tx.commit()?;
+        Ok(true)
+    }
 
-        match id_iter.next() {
-            Some(id) => Ok(Some(id?)),
-            None => Ok(None),
+    /// Adds the `encrypted` column (set for notes stored as age ciphertext)
+    /// to notes of a database created before it existed. Returns false if
+    /// already done.
+    pub fn migrate_encrypted_notes(&self) -> Result<bool> {
+        let version: i64 = self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version >= ENCRYPTED_NOTES_VERSION {
+            return Ok(false);
         }
+        let tx = self.connection.unchecked_transaction()?;
+        if !self.has_column("main", "notes", "encrypted")? {
+            tx.execute(
+                "ALTER TABLE notes ADD COLUMN encrypted INTEGER NOT NULL DEFAULT 0",
+                [],
+            )?;
+        }
+        tx.execute_batch(&format!("PRAGMA user_version = {ENCRYPTED_NOTES_VERSION}"))?;
+        tx.commit()?;
+        Ok(true)
     }
 
-    #[cfg(test)]
-    pub fn get_note_path_by_id(&self, id: &str) -> Result<Option<String>> {
+    /// Adds the `lock_hash` column (set for locked notes) to notes of a
+    /// database created before it existed. Returns false if already done.
+    pub fn migrate_note_locks(&self) -> Result<bool> {
+        let version: i64 = self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version >= NOTE_LOCKS_VERSION {
+            return Ok(false);
+        }
+        let tx = self.connection.unchecked_transaction()?;
+        if !self.has_column("main", "notes", "lock_hash")? {
+            tx.execute("ALTER TABLE notes ADD COLUMN lock_hash TEXT", [])?;
+        }
+        tx.execute_batch(&format!("PRAGMA user_version = {NOTE_LOCKS_VERSION}"))?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Finds folders whose chain of parent_ids loops back on itself, which
+    /// leaves them out of every path, and breaks each loop by moving its
+    /// folder with the smallest id to the root (under a free title). Returns
+    /// the ids in each loop, the moved one first.
+    pub fn break_folder_cycles(&self) -> Result<Vec<Vec<String>>> {
         let mut stmt = self
             .connection
-            .prepare("SELECT full_path FROM v_note_id_path_mapping WHERE id = ?1")?;
+            .prepare("SELECT id, parent_id FROM folders ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })?;
+        let parents = rows.collect::<Result<HashMap<String, Option<String>>>>()?;
+        let mut ids: Vec<&String> = parents.keys().collect();
+        ids.sort();
+
+        let mut cycles = Vec::new();
+        let mut checked: HashSet<&str> = HashSet::new();
+        for start in ids {
+            let mut chain: Vec<&str> = Vec::new();
+            let mut current = Some(start.as_str());
+            while let Some(id) = current {
+                if checked.contains(id) {
+                    break;
+                }
+                if let Some(pos) = chain.iter().position(|&seen| seen == id) {
+                    let mut cycle: Vec<String> =
+                        chain[pos..].iter().map(|id| id.to_string()).collect();
+                    let first = (0..cycle.len()).min_by_key(|&i| &cycle[i]).unwrap_or(0);
+                    cycle.rotate_left(first);
+                    cycles.push(cycle);
+                    break;
+                }
+                chain.push(id);
+                current = parents.get(id).and_then(|parent| parent.as_deref());
+            }
+            checked.extend(chain);
+        }
 
-        let mut path_iter = stmt.query_map([id], |row| row.get::<_, String>(0))?;
+        for cycle in &cycles {
+            let id = &cycle[0];
+            let (title, user_id): (String, String) = self.connection.query_row(
+                "SELECT title, user_id FROM folders WHERE id = ?1",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            let title = self.unique_folder_title(None, &title, &user_id)?;
+            self.connection.execute(
+                "UPDATE folders SET parent_id = NULL, title = ?2 WHERE id = ?1",
+                params![id, title],
+            )?;
+        }
+        Ok(cycles)
+    }
 
-        match path_iter.next() {
-            Some(path) => Ok(Some(path?)),
-            None => Ok(None),
+    /// Whether `table` in `schema` has the column `name`
+    fn has_column(&self, schema: &str, table: &str, name: &str) -> Result<bool> {
+        self.connection.query_row(
+            &format!(
+                "SELECT EXISTS (SELECT 1 FROM {schema}.pragma_table_info(?1) WHERE name = ?2)"
+            ),
+            [table, name],
+            |row| row.get(0),
+        )
+    }
+
+    /// `value`, a stored local time in `timezone`, as a UTC timestamp; `None`
+    /// if it doesn't parse or never happened there (skipped by a DST change)
+    fn local_to_utc(value: &str, timezone: Tz) -> Option<String> {
+        let local = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").ok()?;
+        let utc = local.and_local_timezone(timezone).earliest()?;
+        Some(Self::format_timestamp(utc.with_timezone(&Utc)))
+    }
+
+    /// Collation applied to name comparisons in path and title lookups
+    fn name_collation(&self) -> &'static str {
+        match (self.normalize_unicode, self.case_insensitive) {
+            (true, true) => "COLLATE NFC_NOCASE",
+            (true, false) => "COLLATE NFC",
+            (false, true) => "COLLATE NOCASE",
+            (false, false) => "COLLATE BINARY",
         }
     }
 
-    pub fn get_note_id_by_path(&self, path: &str) -> Result<Option<String>> {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT id FROM v_note_id_path_mapping WHERE full_path = ?1")?;
+    /// Bumps the mtime of the folder `folder_id` (nothing for the root); run in
+    /// the same transaction that adds, renames or removes one of its entries
+    fn touch_folder(conn: &Connection, folder_id: Option<&str>, now: &str) -> Result<()> {
+        conn.execute(
+            "UPDATE folders SET updated_at = ?1 WHERE id = ?2",
+            params![now, folder_id],
+        )?;
+        Ok(())
+    }
 
-        let mut id_iter = stmt.query_map([path], |row| row.get::<_, String>(0))?;
+    /// Replaces the `links` rows of `note_id` with the wikilinks in `content`;
+    /// run in the same transaction that stores the content
+    fn index_links(conn: &Connection, note_id: &str, content: &str) -> Result<()> {
+        conn.execute("DELETE FROM links WHERE source_id = ?1", [note_id])?;
+        let mut stmt =
+            conn.prepare_cached("INSERT INTO links (source_id, target_title) VALUES (?1, ?2)")?;
+        for title in wikilinks::parse(content) {
+            stmt.execute(params![note_id, title])?;
+        }
+        Ok(())
+    }
 
-        match id_iter.next() {
-            Some(id) => Ok(Some(id?)),
+    /// Bumps the mtime of the folder currently holding row `id` of `table`
+    /// ("folders", "notes" or "note_aliases")
+    fn touch_parent_of(conn: &Connection, table: &str, id: &str, now: &str) -> Result<()> {
+        conn.execute(
+            &format!(
+                "UPDATE folders SET updated_at = ?1
+                 WHERE id = (SELECT parent_id FROM {table} WHERE id = ?2)"
+            ),
+            params![now, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn create_folder(
+        &self,
+        title: &str,
+        parent_id: Option<&str>,
+        user_id: &str,
+    ) -> Result<String> {
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        let now = Self::now();
+
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO folders (id, title, parent_id, user_id, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+            params![id, title, parent_id, user_id, now],
+        )?;
+        Self::touch_folder(&tx, parent_id, &now)?;
+        tx.commit()?;
+
+        Ok(id)
+    }
+
+    pub fn get_folder_by_id(&self, id: &str, user_id: &str) -> Result<Option<Folder>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, title, parent_id, user_id, created_at, updated_at, accessed_at, mode, uid, gid, flags FROM folders WHERE id = ?1 AND user_id = ?2 AND deleted_at IS NULL",
+        )?;
+
+        let mut folder_iter = stmt.query_map([id, user_id], Self::map_folder_row)?;
+
+        match folder_iter.next() {
+            Some(folder) => Ok(Some(folder?)),
             None => Ok(None),
         }
     }
 
-    #[cfg(test)]
-    pub fn get_folder_contents_recursive(
+    pub fn update_folder(&self, id: &str, title: &str, user_id: &str) -> Result<bool> {
+        let now = Self::now();
+        let tx = self.connection.unchecked_transaction()?;
+        let rows_affected = tx.execute(
+            "UPDATE folders SET title = ?1, updated_at = ?2 WHERE id = ?3 AND user_id = ?4",
+            params![title, now, id, user_id],
+        )?;
+        if rows_affected > 0 {
+            Self::touch_parent_of(&tx, "folders", id, &now)?;
+        }
+        tx.commit()?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Sets a folder's access, modification and/or creation time (as given to
+    /// utimens, or setattrlist on macOS)
+    pub fn set_folder_times(
         &self,
-        folder_id: &str,
+        id: &str,
         user_id: &str,
-    ) -> Result<Vec<FileType>> {
-        let query = "
-            WITH RECURSIVE folder_tree AS (
-                -- Base case: the specified folder
-                SELECT
-                    id,
-                    title,
-                    parent_id,
-                    0 as depth,
-                    title as path
-                FROM folders
-                WHERE id = ?1 AND user_id = ?2
+        accessed_at: Option<DateTime<Utc>>,
+        updated_at: Option<DateTime<Utc>>,
+        created_at: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let format = Self::format_timestamp;
+        let rows_affected = self.connection.execute(
+            "UPDATE folders SET accessed_at = COALESCE(?1, accessed_at),
+                                updated_at = COALESCE(?2, updated_at),
+                                created_at = COALESCE(?3, created_at)
+             WHERE id = ?4 AND user_id = ?5",
+            params![
+                accessed_at.map(format),
+                updated_at.map(format),
+                created_at.map(format),
+                id,
+                user_id
+            ],
+        )?;
 
-                UNION ALL
+        Ok(rows_affected > 0)
+    }
 
-                -- Recursive case: child folders
-                SELECT
-                    f.id,
-                    f.title,
-                    f.parent_id,
-                    ft.depth + 1 as depth,
-                    CASE
-                        WHEN ft.depth = 0 THEN f.title
-                        ELSE ft.path || '/' || f.title
-                    END as path
-                FROM folders f
-                INNER JOIN folder_tree ft ON f.parent_id = ft.id
-                WHERE f.user_id = ?2
-            ),
-            folder_paths AS (
-                SELECT 
-                    id,
-                    path,
-                    'directory' as type
-                FROM folder_tree
-                WHERE depth > 0  -- Exclude the root folder itself
-            ),
-            note_paths AS (
-                SELECT 
-                    n.id,
-                    CASE 
-                        WHEN ft.depth = 0 THEN n.title || '.' || n.syntax
-                        ELSE ft.path || '/' || n.title || '.' || n.syntax
-                    END as path,
-                    'file' as type
-                FROM notes n
-                INNER JOIN folder_tree ft ON (n.parent_id = ft.id OR (n.parent_id IS NULL AND ft.id = ?1))
-                WHERE n.user_id = ?2
-            )
-            SELECT path, type FROM folder_paths
-            UNION ALL
-            SELECT path, type FROM note_paths
-            ORDER BY path";
+    /// Stores the permission bits and/or owner of a folder (as given to chmod/chown)
+    pub fn set_folder_permissions(
+        &self,
+        id: &str,
+        user_id: &str,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "UPDATE folders SET mode = COALESCE(?1, mode),
+                                uid = COALESCE(?2, uid),
+                                gid = COALESCE(?3, gid)
+             WHERE id = ?4 AND user_id = ?5",
+            params![mode, uid, gid, id, user_id],
+        )?;
 
-        let mut stmt = self.connection.prepare(query)?;
-        let file_iter = stmt.query_map(params![folder_id, user_id], |row| {
-            let path: String = row.get(0)?;
-            let file_type: String = row.get(1)?;
+        Ok(rows_affected > 0)
+    }
 
-            match file_type.as_str() {
-                "directory" => Ok(FileType::Directory { path }),
-                "file" => Ok(FileType::File { path }),
-                _ => Err(rusqlite::Error::InvalidColumnType(
-                    1,
-                    "type".to_string(),
-                    rusqlite::types::Type::Text,
-                )),
-            }
-        })?;
+    /// Stores a folder's BSD file flags (as given to chflags)
+    pub fn set_folder_flags(&self, id: &str, user_id: &str, flags: u32) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "UPDATE folders SET flags = ?1 WHERE id = ?2 AND user_id = ?3",
+            params![flags, id, user_id],
+        )?;
 
-        file_iter.collect()
+        Ok(rows_affected > 0)
+    }
+
+    /// Marks the folder deleted; it is hidden until `purge_deleted` removes it
+    pub fn delete_folder(&self, id: &str, user_id: &str) -> Result<bool> {
+        let now = Self::now();
+
+        let tx = self.connection.unchecked_transaction()?;
+        Self::touch_parent_of(&tx, "folders", id, &now)?;
+        let rows_affected = tx.execute(
+            "UPDATE folders SET deleted_at = ?1 WHERE id = ?2 AND user_id = ?3 AND deleted_at IS NULL",
+            params![now, id, user_id],
+        )?;
+        if rows_affected > 0 {
+            tx.commit()?;
+        }
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Marks the folder and everything below it deleted in one transaction,
+    /// returning how many folders and notes that was
+    pub fn delete_folder_recursive(&self, id: &str, user_id: &str) -> Result<(usize, usize)> {
+        let now = Self::now();
+        let subtree = "WITH RECURSIVE subtree(id) AS (
+                           SELECT id FROM folders WHERE id = ?2 AND user_id = ?3 AND deleted_at IS NULL
+                           UNION ALL
+                           SELECT f.id FROM folders f JOIN subtree s ON f.parent_id = s.id
+                           WHERE f.deleted_at IS NULL
+                       )";
+
+        let tx = self.connection.unchecked_transaction()?;
+        Self::touch_parent_of(&tx, "folders", id, &now)?;
+        let notes = tx.execute(
+            &format!(
+                "{subtree} UPDATE notes SET deleted_at = ?1
+                 WHERE parent_id IN (SELECT id FROM subtree) AND deleted_at IS NULL"
+            ),
+            params![now, id, user_id],
+        )?;
+        // Like delete_note, the names of deleted notes go right away
+        tx.execute(
+            "DELETE FROM note_aliases
+             WHERE note_id IN (SELECT id FROM notes WHERE deleted_at = ?1)",
+            [&now],
+        )?;
+        let folders = tx.execute(
+            &format!(
+                "{subtree} UPDATE folders SET deleted_at = ?1
+                 WHERE id IN (SELECT id FROM subtree)"
+            ),
+            params![now, id, user_id],
+        )?;
+        tx.commit()?;
+
+        Ok((folders, notes))
     }
 
-    pub fn get_child_count(
+    pub fn update_folder_parent(
         &self,
+        id: &str,
         parent_id: Option<&str>,
         user_id: &str,
-    ) -> Result<(usize, usize)> {
-        let (folder_count, note_count) = match parent_id {
-            Some(pid) => {
-                // Count folders with this parent (filtered by user)
-                let folder_count: i64 = self.connection.query_row(
-                    "SELECT COUNT(*) FROM folders WHERE parent_id = ?1 AND user_id = ?2",
-                    params![pid, user_id],
-                    |row| row.get(0),
-                )?;
+    ) -> Result<bool> {
+        let now = Self::now();
 
-                // Count notes with this parent (filtered by user)
-                let note_count: i64 = self.connection.query_row(
-                    "SELECT COUNT(*) FROM notes WHERE parent_id = ?1 AND user_id = ?2",
-                    params![pid, user_id],
-                    |row| row.get(0),
-                )?;
+        let tx = self.connection.unchecked_transaction()?;
+        Self::touch_parent_of(&tx, "folders", id, &now)?;
+        let rows_affected = match parent_id {
+            Some(pid) => tx.execute(
+                "UPDATE folders SET parent_id = ?1, updated_at = ?2 WHERE id = ?3 AND user_id = ?4",
+                params![pid, now, id, user_id],
+            )?,
+            None => tx.execute(
+                "UPDATE folders SET parent_id = NULL, updated_at = ?1 WHERE id = ?2 AND user_id = ?3",
+                params![now, id, user_id],
+            )?,
+        };
+        if rows_affected > 0 {
+            Self::touch_folder(&tx, parent_id, &now)?;
+            tx.commit()?;
+        }
 
-                (folder_count, note_count)
+        Ok(rows_affected > 0)
+    }
+
+    #[allow(dead_code)]
+    pub fn list_folders_by_parent(
+        &self,
+        parent_id: Option<&str>,
+        user_id: &str,
+        page: Page,
+    ) -> Result<Vec<Folder>> {
+        let query = match parent_id {
+            Some(_) => {
+                "SELECT id, title, parent_id, user_id, created_at, updated_at, accessed_at, mode, uid, gid, flags FROM folders WHERE parent_id = ?1 AND user_id = ?2 AND deleted_at IS NULL ORDER BY title, id LIMIT ?3 OFFSET ?4"
             }
             None => {
-                // Count root folders (no parent, filtered by user)
-                let folder_count: i64 = self.connection.query_row(
-                    "SELECT COUNT(*) FROM folders WHERE parent_id IS NULL AND user_id = ?1",
-                    [user_id],
-                    |row| row.get(0),
-                )?;
-
-                // Count root notes (filtered by user)
-                let note_count: i64 = self.connection.query_row(
-                    "SELECT COUNT(*) FROM notes WHERE parent_id IS NULL AND user_id = ?1",
-                    [user_id],
-                    |row| row.get(0),
-                )?;
+                "SELECT id, title, parent_id, user_id, created_at, updated_at, accessed_at, mode, uid, gid, flags FROM folders WHERE parent_id IS NULL AND user_id = ?1 AND deleted_at IS NULL ORDER BY title, id LIMIT ?2 OFFSET ?3"
+            }
+        };
 
-                (folder_count, note_count)
+        let (limit, offset) = page.bounds();
+        let mut stmt = self.connection.prepare(query)?;
+        let folder_iter = match parent_id {
+            Some(pid) => {
+                stmt.query_map(params![pid, user_id, limit, offset], Self::map_folder_row)?
             }
+            None => stmt.query_map(params![user_id, limit, offset], Self::map_folder_row)?,
         };
 
-        Ok((folder_count as usize, note_count as usize))
+        folder_iter.collect()
     }
 
-    /// Maps a database row to a Folder struct, handling datetime parsing.
-    /// Extracted as a helper to avoid code duplication across query methods.
-    /// Expects columns: id, title, parent_id, user_id, created_at, updated_at
-    fn map_folder_row(row: &rusqlite::Row) -> rusqlite::Result<Folder> {
-        Ok(Folder {
-            id: row.get(0)?,
-            title: row.get(1)?,
-            parent_id: row.get(2)?,
-            user_id: row.get(3)?,
-            created_at: NaiveDateTime::parse_from_str(
-                &row.get::<_, String>(4)?,
-                "%Y-%m-%d %H:%M:%S",
-            )
-            .map_err(|_| {
-                rusqlite::Error::InvalidColumnType(
-                    4,
-                    "created_at".to_string(),
-                    rusqlite::types::Type::Text,
-                )
-            })?
-            .and_utc(),
-            updated_at: NaiveDateTime::parse_from_str(
-                &row.get::<_, String>(5)?,
-                "%Y-%m-%d %H:%M:%S",
-            )
-            .map_err(|_| {
-                rusqlite::Error::InvalidColumnType(
-                    5,
-                    "updated_at".to_string(),
-                    rusqlite::types::Type::Text,
-                )
-            })?
-            .and_utc(),
-        })
-    }
+    pub fn create_note(
+        &self,
+        id: &str,
+        note_content: NoteContent,
+        parent_id: Option<&str>,
+        user_id: &str,
+    ) -> Result<String> {
+        let now = Self::now();
 
-    /// Maps a database row to a Note struct, handling datetime parsing.
-    /// Extracted as a helper to avoid code duplication across query methods.
-    fn map_note_row(row: &rusqlite::Row) -> rusqlite::Result<Note> {
-        Ok(Note {
-            id: row.get(0)?,
-            title: row.get(1)?,
-            abstract_text: row.get(2)?,
-            content: row.get(3)?,
-            syntax: row.get(4)?,
-            parent_id: row.get(5)?,
-            user_id: row.get(6)?,
-            created_at: NaiveDateTime::parse_from_str(
-                &row.get::<_, String>(7)?,
-                "%Y-%m-%d %H:%M:%S",
-            )
-            .map_err(|_| {
-                rusqlite::Error::InvalidColumnType(
-                    7,
-                    "created_at".to_string(),
-                    rusqlite::types::Type::Text,
-                )
-            })?
-            .and_utc(),
-            updated_at: NaiveDateTime::parse_from_str(
-                &row.get::<_, String>(8)?,
-                "%Y-%m-%d %H:%M:%S",
-            )
-            .map_err(|_| {
-                rusqlite::Error::InvalidColumnType(
-                    8,
-                    "updated_at".to_string(),
-                    rusqlite::types::Type::Text,
-                )
-            })?
-            .and_utc(),
-        })
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO notes (id, title, abstract, content, syntax, extensionless, parent_id, user_id, created_at, updated_at, etag)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?9, ?10)",
+            params![
+                id,
+                note_content.title,
+                note_content.abstract_text,
+                note_content.content,
+                note_content.syntax,
+                note_content.extensionless,
+                parent_id,
+                user_id,
+                now,
+                content_hash(note_content.content)
+            ],
+        )?;
+        Self::index_links(&tx, id, note_content.content)?;
+        Self::touch_folder(&tx, parent_id, &now)?;
+        tx.commit()?;
+
+        Ok(id.to_string())
     }
-}
 
-/// Content fields for a note that are frequently updated together
-#[derive(Debug, Clone)]
-pub struct NoteContent<'a> {
-    pub title: &'a str,
-    pub abstract_text: Option<&'a str>,
-    pub content: &'a str,
-    pub syntax: &'a str,
-}
+    pub fn get_note_by_id(&self, id: &str) -> Result<Option<Note>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, title, abstract, COALESCE((SELECT body FROM note_contents WHERE hash = notes.content_hash), content), syntax, parent_id, user_id, created_at, updated_at, extensionless, accessed_at, mode, uid, gid, flags, content_size, revision, encrypted, lock_hash IS NOT NULL
+             FROM notes WHERE id = ?1 AND deleted_at IS NULL",
+        )?;
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-pub struct Folder {
-    pub id: String,
-    pub title: String,
-    pub parent_id: Option<String>,
-    pub user_id: String,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-}
+        let mut note_iter = stmt.query_map([id], Self::map_note_row)?;
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-pub struct Note {
-    pub id: String,
-    pub title: String,
-    pub abstract_text: Option<String>,
-    pub content: String,
-    pub syntax: String,
-    pub parent_id: Option<String>,
-    pub user_id: String,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-}
+        match note_iter.next() {
+            Some(note) => Ok(Some(note?)),
+            None => Ok(None),
+        }
+    }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-pub enum FileType {
-    Directory { path: String },
-    File { path: String },
-}
+    /// Like `get_note_by_id`, without reading the content
+    pub fn get_note_meta_by_id(&self, id: &str) -> Result<Option<NoteMeta>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, title, abstract, syntax, parent_id, user_id, created_at, updated_at, extensionless, accessed_at, mode, uid, gid, flags, content_size, encrypted, lock_hash IS NOT NULL
+             FROM notes WHERE id = ?1 AND deleted_at IS NULL",
+        )?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let mut note_iter = stmt.query_map([id], Self::map_note_meta_row)?;
 
-    use rusqlite::Connection;
+        match note_iter.next() {
+            Some(note) => Ok(Some(note?)),
+            None => Ok(None),
+        }
+    }
 
-    fn setup_test_database() -> Database {
-        let conn = Connection::open_in_memory().expect("Failed to create in-memory database");
+    pub fn update_note(
+        &self,
+        id: &str,
+        title: &str,
+        abstract_text: Option<&str>,
+        content: &str,
+        syntax: &str,
+    ) -> Result<bool> {
+        let now = Self::now();
 
-        // Read and execute the init.sql file
-        let init_sql = include_str!("../sql/init.sql");
-        conn.execute_batch(init_sql)
-            .expect("Failed to initialize database");
+        let tx = self.connection.unchecked_transaction()?;
+        let rows_affected = tx.execute(
+            "UPDATE notes SET title = ?1, abstract = ?2, content = ?3, content_hash = NULL, syntax = ?4, updated_at = ?5, etag = ?7
+             WHERE id = ?6",
+            params![title, abstract_text, content, syntax, now, id, content_hash(content)],
+        )?;
+        if rows_affected > 0 {
+            Self::index_links(&tx, id, content)?;
+        }
+        tx.commit()?;
 
-        Database::new(conn, Some(chrono_tz::Australia::Sydney))
+        Ok(rows_affected > 0)
     }
 
-    #[test]
-    fn test_folder_crud_operations() {
-        let db = setup_test_database();
-        let user_id = "test_user";
+    /// Like `update_note`, but only if the note's content is still at `revision`
+    ///
+    /// Returns false (changing nothing) when another writer changed the content
+    /// since it was read, so their edit isn't overwritten.
+    pub fn update_note_at_revision(
+        &self,
+        id: &str,
+        revision: i64,
+        title: &str,
+        abstract_text: Option<&str>,
+        content: &str,
+        syntax: &str,
+    ) -> Result<bool> {
+        let now = Self::now();
 
-        // Create root folder
-        let root_id = db
-            .create_folder("Documents", None, user_id)
-            .expect("Failed to create root folder");
-        assert!(!root_id.is_empty());
+        let tx = self.connection.unchecked_transaction()?;
+        let rows_affected = tx.execute(
+            "UPDATE notes SET title = ?1, abstract = ?2, content = ?3, content_hash = NULL, syntax = ?4, updated_at = ?5, etag = ?8
+             WHERE id = ?6 AND revision = ?7",
+            params![
+                title,
+                abstract_text,
+                content,
+                syntax,
+                now,
+                id,
+                revision,
+                content_hash(content)
+            ],
+        )?;
+        if rows_affected > 0 {
+            Self::index_links(&tx, id, content)?;
+        }
+        tx.commit()?;
 
-        // Create child folder
-        let child_id = db
-            .create_folder("Projects", Some(&root_id), user_id)
-            .expect("Failed to create child folder");
-        assert!(!child_id.is_empty());
-        assert_ne!(root_id, child_id);
+        Ok(rows_affected > 0)
+    }
 
-        // Read back the root folder
-        let folder = db
-            .get_folder_by_id(&root_id, user_id)
-            .expect("Failed to query folder")
-            .expect("Root folder not found");
+    /// Store `content` as the note's content, encrypted (age ciphertext) or
+    /// not, if its content is still at `revision`
+    ///
+    /// The note's earlier versions are dropped from `notes_history`, where
+    /// they would stay in the other form: in plain text once the note is
+    /// encrypted.
+    pub fn set_note_encrypted(
+        &self,
+        id: &str,
+        revision: i64,
+        content: &str,
+        encrypted: bool,
+    ) -> Result<bool> {
+        let now = Self::now();
 
-        assert_eq!(folder.id, root_id);
-        assert_eq!(folder.title, "Documents");
-        assert_eq!(folder.parent_id, None);
-        assert!(!folder.created_at.to_string().is_empty());
-        assert!(!folder.updated_at.to_string().is_empty());
+        let tx = self.connection.unchecked_transaction()?;
+        let rows_affected = tx.execute(
+            "UPDATE notes SET content = ?1, content_hash = NULL, encrypted = ?2, updated_at = ?3, etag = ?4
+             WHERE id = ?5 AND revision = ?6",
+            params![content, encrypted, now, content_hash(content), id, revision],
+        )?;
+        if rows_affected > 0 {
+            Self::index_links(&tx, id, content)?;
+            tx.execute(
+                "DELETE FROM notes_history WHERE id = ?1 AND log_action = 'UPDATE'",
+                [id],
+            )?;
+        }
+        tx.commit()?;
 
-        // Read back the child folder
-        let child_folder = db
-            .get_folder_by_id(&child_id, user_id)
-            .expect("Failed to query child folder")
-            .expect("Child folder not found");
+        Ok(rows_affected > 0)
+    }
 
-        assert_eq!(child_folder.id, child_id);
-        assert_eq!(child_folder.title, "Projects");
-        assert_eq!(child_folder.parent_id, Some(root_id.clone()));
+    /// Hash of the note's stored content, computed (and kept) if no write
+    /// through the mount has set it since the content last changed
+    pub fn get_note_etag(&self, id: &str) -> Result<Option<String>> {
+        let row = self
+            .connection
+            .query_row(
+                "SELECT etag, COALESCE((SELECT body FROM note_contents WHERE hash = notes.content_hash), content)
+                 FROM notes WHERE id = ?1 AND deleted_at IS NULL",
+                [id],
+                |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?;
+        let Some((etag, content)) = row else {
+            return Ok(None);
+        };
+        if let Some(etag) = etag {
+            return Ok(Some(etag));
+        }
+        let etag = content_hash(&content);
+        self.connection.execute(
+            "UPDATE notes SET etag = ?1 WHERE id = ?2 AND etag IS NULL",
+            params![etag, id],
+        )?;
+        Ok(Some(etag))
+    }
 
-        // Test update
-        let updated = db
-            .update_folder(&root_id, "My Documents", user_id)
-            .expect("Failed to update folder");
-        assert!(updated);
+    /// Replaces a note's abstract (`None` clears it)
+    pub fn set_note_abstract(&self, id: &str, abstract_text: Option<&str>) -> Result<bool> {
+        let now = Self::now();
 
-        let updated_folder = db
-            .get_folder_by_id(&root_id, user_id)
-            .expect("Failed to query updated folder")
-            .expect("Updated folder not found");
-        assert_eq!(updated_folder.title, "My Documents");
+        let rows_affected = self.connection.execute(
+            "UPDATE notes SET abstract = ?1, updated_at = ?2 WHERE id = ?3",
+            params![abstract_text, now, id],
+        )?;
 
-        // Test list folders by parent
-        let children = db
-            .list_folders_by_parent(Some(&root_id), user_id)
-            .expect("Failed to list child folders");
-        assert_eq!(children.len(), 1);
-        assert_eq!(children[0].title, "Projects");
+        Ok(rows_affected > 0)
+    }
 
-        // Test delete
-        let deleted = db
-            .delete_folder(&child_id, user_id)
-            .expect("Failed to delete folder");
-        assert!(deleted);
+    /// Sets a note's access, modification and/or creation time (as given to
+    /// utimens, or setattrlist on macOS)
+    pub fn set_note_times(
+        &self,
+        id: &str,
+        accessed_at: Option<DateTime<Utc>>,
+        updated_at: Option<DateTime<Utc>>,
+        created_at: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let format = Self::format_timestamp;
+        let rows_affected = self.connection.execute(
+            "UPDATE notes SET accessed_at = COALESCE(?1, accessed_at),
+                              updated_at = COALESCE(?2, updated_at),
+                              created_at = COALESCE(?3, created_at)
+             WHERE id = ?4",
+            params![
+                accessed_at.map(format),
+                updated_at.map(format),
+                created_at.map(format),
+                id
+            ],
+        )?;
 
-        let deleted_folder = db
-            .get_folder_by_id(&child_id, user_id)
-            .expect("Failed to query deleted folder");
-        assert!(deleted_folder.is_none());
+        Ok(rows_affected > 0)
     }
 
-    #[test]
-    fn test_timezone_functionality() {
-        let db = setup_test_database();
-        let user_id = "test_user";
+    /// Stores the permission bits and/or owner of a note (as given to chmod/chown)
+    pub fn set_note_permissions(
+        &self,
+        id: &str,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "UPDATE notes SET mode = COALESCE(?1, mode),
+                              uid = COALESCE(?2, uid),
+                              gid = COALESCE(?3, gid)
+             WHERE id = ?4",
+            params![mode, uid, gid, id],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Stores a note's BSD file flags (as given to chflags)
+    pub fn set_note_flags(&self, id: &str, flags: u32) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "UPDATE notes SET flags = ?1 WHERE id = ?2",
+            params![flags, id],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// The salted hash of the passphrase unlocking a note, or `None` if the
+    /// note isn't locked
+    pub fn get_note_lock(&self, id: &str) -> Result<Option<String>> {
+        let lock: Option<Option<String>> = self
+            .connection
+            .query_row("SELECT lock_hash FROM notes WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(lock.flatten())
+    }
+
+    /// Locks a note behind `lock_hash` (see `get_note_lock`), or with `None`
+    /// unlocks it for good
+    pub fn set_note_lock(&self, id: &str, lock_hash: Option<&str>) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "UPDATE notes SET lock_hash = ?1 WHERE id = ?2",
+            params![lock_hash, id],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Empties a note's content, keeping the note itself
+    pub fn clear_note_content(&self, id: &str) -> Result<bool> {
+        let now = Self::now();
+
+        let tx = self.connection.unchecked_transaction()?;
+        let rows_affected = tx.execute(
+            "UPDATE notes SET content = '', content_hash = NULL, updated_at = ?1, etag = ?3 WHERE id = ?2",
+            params![now, id, content_hash("")],
+        )?;
+        tx.execute("DELETE FROM links WHERE source_id = ?1", [id])?;
+        tx.commit()?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Changes the file name of a note (title, syntax and whether it has an extension)
+    pub fn update_note_name(
+        &self,
+        id: &str,
+        title: &str,
+        syntax: &str,
+        extensionless: bool,
+    ) -> Result<bool> {
+        let now = Self::now();
+
+        let tx = self.connection.unchecked_transaction()?;
+        let rows_affected = tx.execute(
+            "UPDATE notes SET title = ?1, syntax = ?2, extensionless = ?3, updated_at = ?4
+             WHERE id = ?5",
+            params![title, syntax, extensionless, now, id],
+        )?;
+        Self::touch_parent_of(&tx, "notes", id, &now)?;
+        tx.commit()?;
+
+        Ok(rows_affected > 0)
+    }
+
+    pub fn update_note_parent(&self, id: &str, parent_id: Option<&str>) -> Result<bool> {
+        let now = Self::now();
+
+        let tx = self.connection.unchecked_transaction()?;
+        Self::touch_parent_of(&tx, "notes", id, &now)?;
+        let rows_affected = tx.execute(
+            "UPDATE notes SET parent_id = ?1, updated_at = ?2 WHERE id = ?3",
+            params![parent_id, now, id],
+        )?;
+        Self::touch_folder(&tx, parent_id, &now)?;
+        tx.commit()?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Marks the note deleted; it is hidden until `purge_deleted` removes it
+    ///
+    /// Its other names (hard links) go right away.
+    pub fn delete_note(&self, id: &str) -> Result<bool> {
+        let now = Self::now();
+
+        let tx = self.connection.unchecked_transaction()?;
+        Self::touch_parent_of(&tx, "notes", id, &now)?;
+        let rows_affected = tx.execute(
+            "UPDATE notes SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            params![now, id],
+        )?;
+        tx.execute("DELETE FROM note_aliases WHERE note_id = ?1", [id])?;
+        tx.commit()?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Moves the content of `source_id` into `target_id` and deletes `source_id`
+    ///
+    /// This is a rename of one note over another: the target keeps its id,
+    /// title, history and links, and only its content changes.
+    pub fn replace_note_content(&self, target_id: &str, source_id: &str) -> Result<bool> {
+        let now = Self::now();
+
+        let tx = self.connection.unchecked_transaction()?;
+        let rows_affected = tx.execute(
+            "UPDATE notes SET content = (SELECT COALESCE((SELECT body FROM note_contents WHERE hash = source.content_hash), source.content)
+                                         FROM notes source WHERE source.id = ?2),
+                              encrypted = (SELECT encrypted FROM notes source WHERE source.id = ?2),
+                              content_hash = NULL, updated_at = ?3, etag = NULL
+             WHERE id = ?1 AND EXISTS (SELECT 1 FROM notes WHERE id = ?2)",
+            params![target_id, source_id, now],
+        )?;
+        if rows_affected == 0 {
+            return Ok(false);
+        }
+        Self::touch_parent_of(&tx, "notes", source_id, &now)?;
+        // Wikilinks follow the content
+        tx.execute("DELETE FROM links WHERE source_id = ?1", [target_id])?;
+        tx.execute(
+            "UPDATE links SET source_id = ?1 WHERE source_id = ?2",
+            [target_id, source_id],
+        )?;
+        tx.execute("DELETE FROM notes WHERE id = ?1", params![source_id])?;
+        tx.commit()?;
+
+        Ok(true)
+    }
+
+    /// Stores `data` as the attachment `filename` of `note_id`, returning its id
+    pub fn create_attachment(
+        &self,
+        note_id: &str,
+        filename: &str,
+        mime: &str,
+        data: &[u8],
+    ) -> Result<String> {
+        let now = Self::now();
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        self.connection.execute(
+            "INSERT INTO attachments (id, note_id, filename, blob, mime, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+            params![id, note_id, filename, data, mime, now],
+        )?;
+
+        Ok(id)
+    }
+
+    /// The attachment `filename` of `note_id`, without its data
+    pub fn get_attachment_by_name(
+        &self,
+        note_id: &str,
+        filename: &str,
+    ) -> Result<Option<Attachment>> {
+        self.connection
+            .query_row(
+                "SELECT id, note_id, filename, mime, length(blob), created_at, updated_at
+                 FROM attachments WHERE note_id = ?1 AND filename = ?2",
+                [note_id, filename],
+                Self::map_attachment_row,
+            )
+            .optional()
+    }
+
+    /// Attachments of `note_id` by file name, without their data
+    pub fn list_attachments(&self, note_id: &str) -> Result<Vec<Attachment>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, note_id, filename, mime, length(blob), created_at, updated_at
+             FROM attachments WHERE note_id = ?1 ORDER BY filename",
+        )?;
+        let attachment_iter = stmt.query_map([note_id], Self::map_attachment_row)?;
+
+        attachment_iter.collect()
+    }
+
+    /// The stored bytes of the attachment `id`
+    pub fn get_attachment_data(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        self.connection
+            .query_row("SELECT blob FROM attachments WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()
+    }
+
+    /// At most `len` stored bytes of the attachment `id`, starting at `offset`
+    pub fn get_attachment_range(&self, id: &str, offset: u64, len: u64) -> Result<Option<Vec<u8>>> {
+        // substr() counts from 1 and copies only the requested part of the blob
+        self.connection
+            .query_row(
+                "SELECT substr(blob, ?2, ?3) FROM attachments WHERE id = ?1",
+                params![id, offset as i64 + 1, len as i64],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Replaces the stored bytes of the attachment `id`
+    pub fn update_attachment_data(&self, id: &str, data: &[u8]) -> Result<bool> {
+        let now = Self::now();
+        let rows_affected = self.connection.execute(
+            "UPDATE attachments SET blob = ?1, updated_at = ?2 WHERE id = ?3",
+            params![data, now, id],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Gives the attachment `id` a new file name (and the MIME type that goes with it)
+    pub fn rename_attachment(&self, id: &str, filename: &str, mime: &str) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "UPDATE attachments SET filename = ?1, mime = ?2 WHERE id = ?3",
+            params![filename, mime, id],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    pub fn delete_attachment(&self, id: &str) -> Result<bool> {
+        let rows_affected = self
+            .connection
+            .execute("DELETE FROM attachments WHERE id = ?1", [id])?;
+
+        Ok(rows_affected > 0)
+    }
+
+    fn map_attachment_row(row: &rusqlite::Row) -> rusqlite::Result<Attachment> {
+        Ok(Attachment {
+            id: row.get(0)?,
+            note_id: row.get(1)?,
+            filename: row.get(2)?,
+            mime: row.get(3)?,
+            size: row.get(4)?,
+            created_at: Self::parse_timestamp(row, 5, "created_at")?,
+            updated_at: Self::parse_timestamp(row, 6, "updated_at")?,
+        })
+    }
+
+    /// Problems found in the database, none if it is sound: what SQLite's
+    /// `integrity_check` (or with `quick`, the faster `quick_check`) reports,
+    /// search index rows that don't match their note, and notes the path
+    /// views give no path or the same path as another
+    pub fn integrity_check(&self, quick: bool) -> Result<Vec<String>> {
+        let pragma = if quick {
+            "PRAGMA quick_check"
+        } else {
+            "PRAGMA integrity_check"
+        };
+        let mut problems: Vec<String> = {
+            let mut stmt = self.connection.prepare(pragma)?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            rows.collect::<Result<Vec<_>>>()?
+        };
+        if problems == ["ok"] {
+            problems.clear();
+        } else {
+            // The rest can't be trusted on a damaged file
+            return Ok(problems);
+        }
+
+        // FTS5's own check, of its index against the text it stores
+        if let Err(e) = self.connection.execute(
+            "INSERT INTO notes_fts(notes_fts) VALUES ('integrity-check')",
+            [],
+        ) {
+            problems.push(format!("Search index is corrupt: {e}"));
+        }
+        let mut stmt = self.connection.prepare(
+            "SELECT n.id, 'is missing from the search index' FROM notes n
+             WHERE NOT EXISTS (SELECT 1 FROM notes_fts f WHERE f.id = n.id)
+             UNION ALL
+             SELECT f.id, 'is in the search index ' || COUNT(*) || ' times' FROM notes_fts f
+             GROUP BY f.id HAVING COUNT(*) > 1
+             UNION ALL
+             SELECT f.id, 'is in the search index but not in notes' FROM notes_fts f
+             WHERE NOT EXISTS (SELECT 1 FROM notes n WHERE n.id = f.id)
+             UNION ALL
+             SELECT n.id, 'is indexed with outdated text' FROM notes n JOIN notes_fts f ON f.id = n.id
+             WHERE f.title IS NOT n.title OR f.abstract IS NOT n.abstract
+                 OR f.user_id IS NOT n.user_id
+                 OR f.content IS NOT COALESCE(
+                     (SELECT body FROM note_contents WHERE hash = n.content_hash), n.content)
+             UNION ALL
+             SELECT id, 'has no path (its folder is gone)' FROM v_note_id_path_mapping
+             WHERE full_path IS NULL
+             UNION ALL
+             SELECT MIN(id), 'shares its path ' || full_path || ' with ' || (COUNT(*) - 1)
+                 || ' other notes of ' || user_id
+             FROM v_note_id_path_mapping WHERE full_path IS NOT NULL
+             GROUP BY user_id, full_path HAVING COUNT(*) > 1",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(format!(
+                "Note {} {}",
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?
+            ))
+        })?;
+        for problem in rows {
+            problems.push(problem?);
+        }
+        Ok(problems)
+    }
+
+    /// Share the storage of notes whose content is identical
+    ///
+    /// Each such body is stored once in `note_contents` and the notes refer to
+    /// it by hash; their content, size, revision and history are unchanged.
+    /// Writing one of them stores its content inline again (copy-on-write).
+    pub fn deduplicate_notes(&self) -> Result<DedupSummary> {
+        let tx = self.connection.unchecked_transaction()?;
+        let mut ids_by_hash: HashMap<String, Vec<String>> = HashMap::new();
+        {
+            let mut stmt = tx.prepare(
+                "SELECT id, content FROM notes WHERE content_hash IS NULL AND content_size > 0",
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let content: String = row.get(1)?;
+                ids_by_hash
+                    .entry(content_hash(&content))
+                    .or_default()
+                    .push(row.get(0)?);
+            }
+        }
+
+        let mut summary = DedupSummary::default();
+        for (hash, ids) in ids_by_hash {
+            let stored: Option<String> = tx
+                .query_row(
+                    "SELECT body FROM note_contents WHERE hash = ?1",
+                    [&hash],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if stored.is_none() && ids.len() < 2 {
+                continue;
+            }
+            let body = match stored {
+                Some(body) => body,
+                None => {
+                    let body: String = tx.query_row(
+                        "SELECT content FROM notes WHERE id = ?1",
+                        [&ids[0]],
+                        |row| row.get(0),
+                    )?;
+                    tx.execute(
+                        "INSERT INTO note_contents (hash, body) VALUES (?1, ?2)",
+                        params![hash, body],
+                    )?;
+                    summary.bodies += 1;
+                    summary.bytes_saved -= body.len() as i64;
+                    body
+                }
+            };
+            for id in &ids {
+                // Only notes with this very body, should two bodies share a hash
+                let shared = tx.execute(
+                    "UPDATE notes SET content = '', content_hash = ?1
+                     WHERE id = ?2 AND content_hash IS NULL AND content = ?3",
+                    params![hash, id, body],
+                )?;
+                if shared > 0 {
+                    summary.notes += 1;
+                    summary.bytes_saved += body.len() as i64;
+                }
+            }
+            tx.execute(
+                "DELETE FROM note_contents WHERE hash = ?1
+                 AND NOT EXISTS (SELECT 1 FROM notes WHERE content_hash = ?1)",
+                [&hash],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(summary)
+    }
+
+    /// Remove the notes and folders deleted more than `older_than_days` days
+    /// ago (all of them for `None`) for good, returning how many of each
+    ///
+    /// Notes go through `notes_before_delete`, so their last version stays in
+    /// the history.
+    pub fn purge_deleted(&self, older_than_days: Option<u32>) -> Result<(usize, usize)> {
+        let cutoff = Self::format_timestamp(
+            Utc::now() - chrono::Duration::days(older_than_days.unwrap_or(0).into()),
+        );
+
+        let tx = self.connection.unchecked_transaction()?;
+        let notes = tx.execute(
+            "DELETE FROM notes WHERE deleted_at IS NOT NULL AND deleted_at <= ?1",
+            [&cutoff],
+        )?;
+        // Counted first, as deleting a folder cascades to the folders inside it
+        let folders: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM folders WHERE deleted_at IS NOT NULL AND deleted_at <= ?1",
+            [&cutoff],
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            "DELETE FROM folders WHERE deleted_at IS NOT NULL AND deleted_at <= ?1",
+            [&cutoff],
+        )?;
+        tx.commit()?;
+
+        Ok((folders as usize, notes))
+    }
+
+    /// Delete the versions in `notes_history` outside `retention`, returning
+    /// how many were deleted
+    pub fn purge_history(&self, retention: &HistoryRetention) -> Result<usize> {
+        let tx = self.connection.unchecked_transaction()?;
+        let mut purged = 0;
+        if let Some(keep) = retention.keep_versions {
+            purged += tx.execute(
+                "DELETE FROM notes_history WHERE history_id IN (
+                     SELECT history_id FROM (
+                         SELECT history_id, ROW_NUMBER() OVER (
+                             PARTITION BY id ORDER BY deleted_at DESC, rowid DESC
+                         ) AS newer
+                         FROM notes_history
+                     ) WHERE newer > ?1
+                 )",
+                [keep as i64],
+            )?;
+        }
+        if let Some(days) = retention.keep_days {
+            // deleted_at is stamped with CURRENT_TIMESTAMP, which is UTC
+            purged += tx.execute(
+                "DELETE FROM notes_history WHERE deleted_at < datetime('now', ?1)",
+                [format!("-{days} days")],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(purged)
+    }
+
+    /// Copy the whole database to a new file at `dest` as it is at this
+    /// moment, however busy the mount is, encrypted with `key` if given. A
+    /// `tag` is recorded in the copy's `snapshot_info`, with the time.
+    pub fn snapshot(&self, dest: &Path, key: Option<&str>, tag: Option<&str>) -> Result<()> {
+        let mut copy = Connection::open(dest)?;
+        if let Some(key) = key {
+            copy.pragma_update(None, "key", key)?;
+        }
+        {
+            // Every page is copied in one step, from the view of this read
+            // transaction, so writes meanwhile can't tear the copy
+            let _read = self.connection.unchecked_transaction()?;
+            self.connection
+                .query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+                    row.get::<_, i64>(0)
+                })?;
+            let backup = Backup::new(&self.connection, &mut copy)?;
+            match backup.step(-1)? {
+                StepResult::Done => {}
+                _ => {
+                    return Err(rusqlite::Error::SqliteFailure(
+                        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+                        Some("database changed while it was copied".to_string()),
+                    ));
+                }
+            }
+        }
+        if let Some(tag) = tag {
+            copy.execute_batch(
+                "CREATE TABLE IF NOT EXISTS snapshot_info (
+                     tag TEXT NOT NULL,
+                     taken_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+                 )",
+            )?;
+            copy.execute("INSERT INTO snapshot_info (tag) VALUES (?1)", [tag])?;
+        }
+        Ok(())
+    }
+
+    /// Replace this database with a copy of the one at `source` (opened with
+    /// `key` if given), e.g. to load an in-memory database saved earlier
+    pub fn restore_from(&mut self, source: &Path, key: Option<&str>) -> Result<()> {
+        let original = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        if let Some(key) = key {
+            original.pragma_update(None, "key", key)?;
+        }
+        let backup = Backup::new(&original, &mut self.connection)?;
+        match backup.step(-1)? {
+            StepResult::Done => Ok(()),
+            _ => Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+                Some(format!("{} changed while it was copied", source.display())),
+            )),
+        }
+    }
+
+    /// Leave the database tidy for whatever opens it next: update the query
+    /// planner's statistics (`PRAGMA optimize`), rebuild the file without its
+    /// free pages if `vacuum`, and checkpoint the whole WAL into it. Returns
+    /// false if another connection kept the checkpoint from finishing.
+    pub fn tidy(&self, vacuum: bool) -> Result<bool> {
+        self.connection.execute_batch("PRAGMA optimize")?;
+        if vacuum {
+            self.connection.execute_batch("VACUUM")?;
+        }
+        // (busy, WAL frames, frames checkpointed); -1s outside WAL mode
+        let busy: i64 =
+            self.connection
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+        Ok(busy == 0)
+    }
+
+    /// Fill this (freshly initialized) database with the notes of the database
+    /// at `source` as they were at `as_of`, in this database's timezone
+    ///
+    /// A source from before timestamps were stored in UTC is read as it is
+    /// and the copy migrated afterwards.
+    ///
+    /// Each note takes the first version in `notes_history` replaced after
+    /// `as_of`, or its current row if none was. Folders aren't versioned: the
+    /// ones that existed then are copied with their current names, and notes
+    /// whose folder has since been deleted are placed at the root. Returns
+    /// the number of notes restored.
+    pub fn load_as_of(
+        &self,
+        source: &str,
+        key: Option<&str>,
+        as_of: NaiveDateTime,
+    ) -> Result<usize> {
+        let local = as_of.format("%Y-%m-%d %H:%M:%S").to_string();
+        let utc = as_of
+            .and_local_timezone(self.timezone)
+            .earliest()
+            .map_or(as_of, |dt| dt.naive_utc())
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+
+        self.connection.execute(
+            "ATTACH DATABASE ?1 AS source KEY ?2",
+            params![source, key.unwrap_or("")],
+        )?;
+        let result = (|| {
+            let version: i64 =
+                self.connection
+                    .query_row("PRAGMA source.user_version", [], |row| row.get(0))?;
+            let migrate = version < UTC_TIMESTAMPS_VERSION;
+            // History rows are stamped with CURRENT_TIMESTAMP, which is UTC
+            let stored = if migrate { &local } else { &utc };
+
+            let tx = self.connection.unchecked_transaction()?;
+            for table in ["tags", "syntax_extensions", "saved_searches"] {
+                tx.execute(
+                    &format!("INSERT OR IGNORE INTO main.{table} SELECT * FROM source.{table}"),
+                    [],
+                )?;
+            }
+            if self.has_table("source", "users")? {
+                tx.execute(
+                    "INSERT OR IGNORE INTO main.users (id, timezone)
+                     SELECT id, timezone FROM source.users",
+                    [],
+                )?;
+            }
+            // Older sources lack some columns, which keep their defaults
+            let folder_columns = {
+                let mut stmt =
+                    tx.prepare("SELECT name FROM source.pragma_table_info('folders')")?;
+                stmt.query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>>>()?
+                    .join(", ")
+            };
+            let note_flags = if self.has_column("source", "notes", "flags")? {
+                "current.flags"
+            } else {
+                "NULL"
+            };
+            let note_encrypted = if self.has_column("source", "notes", "encrypted")? {
+                "COALESCE(current.encrypted, 0)"
+            } else {
+                "0"
+            };
+            let note_lock = if self.has_column("source", "notes", "lock_hash")? {
+                "current.lock_hash"
+            } else {
+                "NULL"
+            };
+            // Parents are created before their children
+            tx.execute(
+                &format!(
+                    "INSERT INTO main.folders ({folder_columns}) SELECT {folder_columns} FROM source.folders
+                 WHERE COALESCE(created_at, '') <= ?1 AND (deleted_at IS NULL OR deleted_at > ?1)
+                 ORDER BY created_at"
+                ),
+                [stored],
+            )?;
+            tx.execute("UPDATE main.folders SET deleted_at = NULL", [])?;
+            let notes = tx.execute(
+                &format!(
+                    "WITH versions AS (
+                     SELECT id, title, abstract, content, syntax, parent_id, user_id, created_at,
+                            updated_at, deleted_at AS replaced_at, rowid AS seq
+                     FROM source.notes_history WHERE deleted_at > ?2
+                     UNION ALL
+                     SELECT id, title, abstract,
+                            COALESCE((SELECT body FROM source.note_contents WHERE hash = notes.content_hash), content),
+                            syntax, parent_id, user_id, created_at, updated_at, NULL, NULL
+                     FROM source.notes WHERE deleted_at IS NULL OR deleted_at > ?1
+                 ),
+                 ranked AS (
+                     SELECT *, ROW_NUMBER() OVER (
+                         PARTITION BY id ORDER BY replaced_at IS NULL, replaced_at, seq
+                     ) AS version
+                     FROM versions
+                 )
+                 INSERT OR IGNORE INTO main.notes (id, title, abstract, content, syntax, parent_id,
+                     user_id, created_at, updated_at, extensionless, mode, uid, gid, flags, encrypted, lock_hash)
+                 SELECT ranked.id, ranked.title, ranked.abstract, ranked.content, ranked.syntax,
+                        (SELECT id FROM main.folders WHERE id = ranked.parent_id),
+                        ranked.user_id, ranked.created_at, ranked.updated_at,
+                        COALESCE(current.extensionless, 0), current.mode, current.uid, current.gid,
+                        {note_flags}, {note_encrypted}, {note_lock}
+                 FROM ranked LEFT JOIN source.notes current ON current.id = ranked.id
+                 WHERE ranked.version = 1 AND COALESCE(ranked.created_at, '') <= ?1"
+                ),
+                params![stored, utc],
+            )?;
+            tx.execute(
+                "INSERT INTO main.note_tags SELECT * FROM source.note_tags
+                 WHERE note_id IN (SELECT id FROM main.notes)",
+                [],
+            )?;
+            tx.execute(
+                "INSERT INTO main.note_aliases SELECT * FROM source.note_aliases
+                 WHERE note_id IN (SELECT id FROM main.notes)
+                 AND (parent_id IS NULL OR parent_id IN (SELECT id FROM main.folders))
+                 AND COALESCE(created_at, '') <= ?1",
+                [&utc],
+            )?;
+            tx.execute(
+                "INSERT INTO main.attachments SELECT * FROM source.attachments
+                 WHERE note_id IN (SELECT id FROM main.notes) AND COALESCE(created_at, '') <= ?1",
+                [stored],
+            )?;
+            let contents = {
+                let mut stmt = tx.prepare("SELECT id, content FROM main.notes")?;
+                stmt.query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<Result<Vec<_>>>()?
+            };
+            for (id, content) in contents {
+                Self::index_links(&tx, &id, &content)?;
+            }
+            tx.commit()?;
+            Ok::<_, rusqlite::Error>((notes, migrate))
+        })();
+        self.connection.execute("DETACH DATABASE source", [])?;
+        let (notes, migrate) = result?;
+        if migrate {
+            self.connection.execute_batch("PRAGMA user_version = 0")?;
+            self.migrate()?;
+        }
+        Ok(notes)
+    }
+
+    /// The inode number of `path` as `user_id` sees it, allocating one the
+    /// first time the path is seen
+    pub fn inode_for_path(&self, user_id: &str, path: &str) -> Result<u64> {
+        self.connection.query_row(
+            "INSERT INTO inodes (user_id, path) VALUES (?1, ?2)
+             ON CONFLICT (user_id, path) DO UPDATE SET path = excluded.path
+             RETURNING ino",
+            params![user_id, path],
+            |row| row.get(0),
+        )
+    }
+
+    /// The inode `user_id` was given for `path`, if any, without storing one
+    pub fn stored_inode_for_path(&self, user_id: &str, path: &str) -> Result<Option<u64>> {
+        self.connection
+            .query_row(
+                "SELECT ino FROM inodes WHERE user_id = ?1 AND path = ?2",
+                params![user_id, path],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// The path `user_id` was given inode `ino` for, if it still exists
+    pub fn path_for_inode(&self, user_id: &str, ino: u64) -> Result<Option<String>> {
+        self.connection
+            .query_row(
+                "SELECT path FROM inodes WHERE ino = ?1 AND user_id = ?2",
+                params![ino, user_id],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Moves the inode numbers of `old_path` and everything below it to
+    /// `new_path`, dropping those of whatever was there
+    pub fn rename_inode_paths(&self, user_id: &str, old_path: &str, new_path: &str) -> Result<()> {
+        let tx = self.connection.unchecked_transaction()?;
+        Self::delete_inode_paths(&tx, user_id, new_path)?;
+        tx.execute(
+            "UPDATE inodes SET path = ?3 || substr(path, length(?2) + 1)
+             WHERE user_id = ?1 AND (path = ?2 OR substr(path, 1, length(?2) + 1) = ?2 || '/')",
+            params![user_id, old_path, new_path],
+        )?;
+        tx.commit()
+    }
+
+    /// Drops the inode numbers of `path` and everything below it, once it is
+    /// gone; a new file there gets a new number
+    pub fn remove_inode_paths(&self, user_id: &str, path: &str) -> Result<()> {
+        Self::delete_inode_paths(&self.connection, user_id, path)
+    }
+
+    fn delete_inode_paths(connection: &Connection, user_id: &str, path: &str) -> Result<()> {
+        connection.execute(
+            "DELETE FROM inodes
+             WHERE user_id = ?1 AND (path = ?2 OR substr(path, 1, length(?2) + 1) = ?2 || '/')",
+            params![user_id, path],
+        )?;
+        Ok(())
+    }
+
+    /// Generation of this database's inode numbers, set when it was created
+    pub fn inode_generation(&self) -> Result<u64> {
+        self.connection
+            .query_row("SELECT generation FROM inodes WHERE ino = 1", [], |row| {
+                row.get(0)
+            })
+    }
+
+    /// Adds another name for `note_id` (a hard link) in the folder `parent_id`
+    pub fn create_note_alias(
+        &self,
+        note_id: &str,
+        parent_id: Option<&str>,
+        title: &str,
+        syntax: &str,
+        extensionless: bool,
+        user_id: &str,
+    ) -> Result<String> {
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        let now = Self::now();
+
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO note_aliases (id, note_id, parent_id, title, syntax, extensionless, user_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![id, note_id, parent_id, title, syntax, extensionless, user_id],
+        )?;
+        Self::touch_folder(&tx, parent_id, &now)?;
+        tx.commit()?;
+
+        Ok(id)
+    }
+
+    pub fn get_alias_by_path(&self, path: &str, user_id: &str) -> Result<Option<NoteAlias>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT a.id, a.note_id, a.parent_id, a.title, a.syntax, a.extensionless, a.user_id
+             FROM v_note_alias_path_mapping m JOIN note_aliases a ON a.id = m.id
+             WHERE m.full_path = ?1 {} AND m.user_id = ?2",
+            self.name_collation()
+        ))?;
+
+        let mut alias_iter = stmt.query_map([path, user_id], Self::map_alias_row)?;
+
+        match alias_iter.next() {
+            Some(alias) => Ok(Some(alias?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list_aliases_by_parent(
+        &self,
+        parent_id: Option<&str>,
+        user_id: &str,
+    ) -> Result<Vec<NoteAlias>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, note_id, parent_id, title, syntax, extensionless, user_id
+             FROM note_aliases WHERE parent_id IS ?1 AND user_id = ?2 ORDER BY title",
+        )?;
+        let alias_iter = stmt.query_map(params![parent_id, user_id], Self::map_alias_row)?;
+
+        alias_iter.collect()
+    }
+
+    /// Number of names a note has: its own plus one per alias
+    pub fn get_note_link_count(&self, note_id: &str) -> Result<u32> {
+        let aliases: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM note_aliases WHERE note_id = ?1",
+            [note_id],
+            |row| row.get(0),
+        )?;
+
+        Ok(aliases as u32 + 1)
+    }
+
+    /// Moves and renames an alias
+    pub fn update_note_alias(
+        &self,
+        id: &str,
+        parent_id: Option<&str>,
+        title: &str,
+        syntax: &str,
+        extensionless: bool,
+    ) -> Result<bool> {
+        let now = Self::now();
+
+        let tx = self.connection.unchecked_transaction()?;
+        Self::touch_parent_of(&tx, "note_aliases", id, &now)?;
+        let rows_affected = tx.execute(
+            "UPDATE note_aliases SET parent_id = ?1, title = ?2, syntax = ?3, extensionless = ?4
+             WHERE id = ?5",
+            params![parent_id, title, syntax, extensionless, id],
+        )?;
+        Self::touch_folder(&tx, parent_id, &now)?;
+        tx.commit()?;
+
+        Ok(rows_affected > 0)
+    }
+
+    pub fn delete_note_alias(&self, id: &str) -> Result<bool> {
+        let now = Self::now();
+
+        let tx = self.connection.unchecked_transaction()?;
+        Self::touch_parent_of(&tx, "note_aliases", id, &now)?;
+        let rows_affected = tx.execute("DELETE FROM note_aliases WHERE id = ?1", params![id])?;
+        tx.commit()?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Gives a note the name of its oldest alias, removing that alias
+    ///
+    /// Used when the note's own name is unlinked while other names remain.
+    /// Returns the promoted alias, or `None` if the note has no aliases.
+    pub fn promote_note_alias(&self, note_id: &str) -> Result<Option<NoteAlias>> {
+        let tx = self.connection.unchecked_transaction()?;
+        let alias = {
+            let mut stmt = tx.prepare(
+                "SELECT id, note_id, parent_id, title, syntax, extensionless, user_id
+                 FROM note_aliases WHERE note_id = ?1 ORDER BY created_at, rowid LIMIT 1",
+            )?;
+            let mut alias_iter = stmt.query_map([note_id], Self::map_alias_row)?;
+            match alias_iter.next() {
+                Some(alias) => alias?,
+                None => return Ok(None),
+            }
+        };
+        let now = Self::now();
+        // The note's old name disappears from its folder, the alias's name stays
+        Self::touch_parent_of(&tx, "notes", note_id, &now)?;
+        tx.execute(
+            "UPDATE notes SET parent_id = ?1, title = ?2, syntax = ?3, extensionless = ?4
+             WHERE id = ?5",
+            params![
+                alias.parent_id,
+                alias.title,
+                alias.syntax,
+                alias.extensionless,
+                note_id
+            ],
+        )?;
+        tx.execute("DELETE FROM note_aliases WHERE id = ?1", [&alias.id])?;
+        tx.commit()?;
+
+        Ok(Some(alias))
+    }
+
+    fn map_alias_row(row: &rusqlite::Row) -> rusqlite::Result<NoteAlias> {
+        Ok(NoteAlias {
+            id: row.get(0)?,
+            note_id: row.get(1)?,
+            parent_id: row.get(2)?,
+            title: row.get(3)?,
+            syntax: row.get(4)?,
+            extensionless: row.get(5)?,
+            user_id: row.get(6)?,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn list_notes_by_parent(
+        &self,
+        parent_id: Option<&str>,
+        user_id: &str,
+        page: Page,
+    ) -> Result<Vec<Note>> {
+        let query = match parent_id {
+            Some(_) => "SELECT id, title, abstract, COALESCE((SELECT body FROM note_contents WHERE hash = notes.content_hash), content), syntax, parent_id, user_id, created_at, updated_at, extensionless, accessed_at, mode, uid, gid, flags, content_size, revision, encrypted, lock_hash IS NOT NULL
+                       FROM notes WHERE parent_id = ?1 AND user_id = ?2 AND deleted_at IS NULL ORDER BY title, id LIMIT ?3 OFFSET ?4",
+            None => "SELECT id, title, abstract, COALESCE((SELECT body FROM note_contents WHERE hash = notes.content_hash), content), syntax, parent_id, user_id, created_at, updated_at, extensionless, accessed_at, mode, uid, gid, flags, content_size, revision, encrypted, lock_hash IS NOT NULL
+                    FROM notes WHERE parent_id IS NULL AND user_id = ?1 AND deleted_at IS NULL ORDER BY title, id LIMIT ?2 OFFSET ?3"
+        };
+
+        let (limit, offset) = page.bounds();
+        let mut stmt = self.connection.prepare(query)?;
+        let note_iter = match parent_id {
+            Some(pid) => {
+                stmt.query_map(params![pid, user_id, limit, offset], Self::map_note_row)?
+            }
+            None => stmt.query_map(params![user_id, limit, offset], Self::map_note_row)?,
+        };
+
+        note_iter.collect()
+    }
+
+    /// Like `list_notes_by_parent`, without reading any content
+    pub fn list_note_meta_by_parent(
+        &self,
+        parent_id: Option<&str>,
+        user_id: &str,
+    ) -> Result<Vec<NoteMeta>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, title, abstract, syntax, parent_id, user_id, created_at, updated_at, extensionless, accessed_at, mode, uid, gid, flags, content_size, encrypted, lock_hash IS NOT NULL
+             FROM notes WHERE parent_id IS ?1 AND user_id = ?2 AND deleted_at IS NULL ORDER BY title",
+        )?;
+        let note_iter = stmt.query_map(params![parent_id, user_id], Self::map_note_meta_row)?;
+
+        note_iter.collect()
+    }
+
+    /// Folders and notes directly inside `parent_id` (the root for `None`) in
+    /// one query, folders first, each kind by title
+    ///
+    /// Folders are always `user_id`'s; with `all_users` so are the notes of
+    /// every other user.
+    pub fn list_children(
+        &self,
+        parent_id: Option<&str>,
+        user_id: &str,
+        all_users: bool,
+        page: Page,
+    ) -> Result<Vec<Child>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, title, NULL, NULL, parent_id, user_id, created_at, updated_at, 0, accessed_at, mode, uid, gid, flags, 0, 0, 0, 'folder' AS kind
+             FROM folders WHERE parent_id IS ?1 AND user_id = ?2 AND deleted_at IS NULL
+             UNION ALL
+             SELECT id, title, abstract, syntax, parent_id, user_id, created_at, updated_at, extensionless, accessed_at, mode, uid, gid, flags, content_size, encrypted, lock_hash IS NOT NULL, 'note'
+             FROM notes WHERE parent_id IS ?1 AND (user_id = ?2 OR ?5) AND deleted_at IS NULL
+             ORDER BY kind, title, id LIMIT ?3 OFFSET ?4",
+        )?;
+        let (limit, offset) = page.bounds();
+        let child_iter = stmt.query_map(
+            params![parent_id, user_id, limit, offset, all_users],
+            |row| {
+                if row.get::<_, String>(17)? == "note" {
+                    return Self::map_note_meta_row(row).map(Child::Note);
+                }
+                Ok(Child::Folder(Folder {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    parent_id: row.get(4)?,
+                    user_id: row.get(5)?,
+                    created_at: Self::parse_timestamp(row, 6, "created_at")?,
+                    updated_at: Self::parse_timestamp(row, 7, "updated_at")?,
+                    accessed_at: Self::parse_optional_timestamp(row, 9, "accessed_at")?,
+                    mode: row.get(10)?,
+                    uid: row.get(11)?,
+                    gid: row.get(12)?,
+                    flags: row.get(13)?,
+                }))
+            },
+        )?;
+
+        child_iter.collect()
+    }
+
+    /// The user's `limit` most recently updated notes, newest first
+    pub fn list_recent_notes(&self, user_id: &str, limit: usize) -> Result<Vec<Note>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, title, abstract, COALESCE((SELECT body FROM note_contents WHERE hash = notes.content_hash), content), syntax, parent_id, user_id, created_at, updated_at, extensionless, accessed_at, mode, uid, gid, flags, content_size, revision, encrypted, lock_hash IS NOT NULL
+             FROM notes WHERE user_id = ?1 AND deleted_at IS NULL ORDER BY updated_at DESC, title LIMIT ?2",
+        )?;
+        let note_iter = stmt.query_map(params![user_id, limit as i64], Self::map_note_row)?;
+
+        note_iter.collect()
+    }
+
+    /// All of the user's notes, by title
+    pub fn list_notes(&self, user_id: &str) -> Result<Vec<Note>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, title, abstract, COALESCE((SELECT body FROM note_contents WHERE hash = notes.content_hash), content), syntax, parent_id, user_id, created_at, updated_at, extensionless, accessed_at, mode, uid, gid, flags, content_size, revision, encrypted, lock_hash IS NOT NULL
+             FROM notes WHERE user_id = ?1 AND deleted_at IS NULL ORDER BY title, id",
+        )?;
+        let note_iter = stmt.query_map(params![user_id], Self::map_note_row)?;
+
+        note_iter.collect()
+    }
+
+    /// The user's notes no path leads to, as their folder or one above it
+    /// is gone (deleted by a client that left its notes behind), by title
+    pub fn list_orphaned_notes(&self, user_id: &str) -> Result<Vec<Note>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, title, abstract, COALESCE((SELECT body FROM note_contents WHERE hash = notes.content_hash), content), syntax, parent_id, user_id, created_at, updated_at, extensionless, accessed_at, mode, uid, gid, flags, content_size, revision, encrypted, lock_hash IS NOT NULL
+             FROM notes WHERE user_id = ?1 AND deleted_at IS NULL AND parent_id IS NOT NULL
+                 AND parent_id NOT IN (SELECT id FROM v_folder_id_path_mapping)
+             ORDER BY title, id",
+        )?;
+        let note_iter = stmt.query_map(params![user_id], Self::map_note_row)?;
+
+        note_iter.collect()
+    }
+
+    /// Whether `list_orphaned_notes` would list any
+    pub fn has_orphaned_notes(&self, user_id: &str) -> Result<bool> {
+        self.connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM notes WHERE user_id = ?1 AND deleted_at IS NULL
+                 AND parent_id IS NOT NULL
+                 AND parent_id NOT IN (SELECT id FROM v_folder_id_path_mapping))",
+            params![user_id],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn get_folder_path_by_id(&self, id: &str, user_id: &str) -> Result<Option<String>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT full_path FROM v_folder_id_path_mapping WHERE id = ?1 AND user_id = ?2",
+        )?;
+
+        let mut path_iter = stmt.query_map([id, user_id], |row| row.get::<_, String>(0))?;
+
+        match path_iter.next() {
+            Some(path) => Ok(Some(path?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_folder_id_by_path(&self, path: &str, user_id: &str) -> Result<Option<String>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT id FROM v_folder_id_path_mapping WHERE full_path = ?1 {} AND user_id = ?2",
+            self.name_collation()
+        ))?;
+
+        let mut id_iter = stmt.query_map([path, user_id], |row| row.get::<_, String>(0))?;
+
+        match id_iter.next() {
+            Some(id) => Ok(Some(id?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_note_path_by_id(&self, id: &str) -> Result<Option<String>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT full_path FROM v_note_id_path_mapping WHERE id = ?1")?;
+
+        let mut path_iter = stmt.query_map([id], |row| row.get::<_, String>(0))?;
+
+        match path_iter.next() {
+            Some(path) => Ok(Some(path?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_note_id_by_path(&self, path: &str) -> Result<Option<String>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT id FROM v_note_id_path_mapping WHERE full_path = ?1 {}",
+            self.name_collation()
+        ))?;
+
+        let mut id_iter = stmt.query_map([path], |row| row.get::<_, String>(0))?;
+
+        match id_iter.next() {
+            Some(id) => Ok(Some(id?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `get_note_id_by_path`, limited to `user_id`'s notes unless
+    /// `all_users`, and preferring theirs where users share a path
+    pub fn get_visible_note_id_by_path(
+        &self,
+        path: &str,
+        user_id: &str,
+        all_users: bool,
+    ) -> Result<Option<String>> {
+        self.connection
+            .query_row(
+                &format!(
+                    "SELECT id FROM v_note_id_path_mapping
+                     WHERE full_path = ?1 {} AND (user_id = ?2 OR ?3)
+                     ORDER BY user_id = ?2 DESC LIMIT 1",
+                    self.name_collation()
+                ),
+                params![path, user_id, all_users],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    #[cfg(test)]
+    pub fn get_folder_contents_recursive(
+        &self,
+        folder_id: &str,
+        user_id: &str,
+    ) -> Result<Vec<FileType>> {
+        let query = "
+            WITH RECURSIVE folder_tree AS (
+                -- Base case: the specified folder
+                SELECT
+                    id,
+                    title,
+                    parent_id,
+                    0 as depth,
+                    title as path
+                FROM folders
+                WHERE id = ?1 AND user_id = ?2 AND deleted_at IS NULL
+
+                UNION ALL
+
+                -- Recursive case: child folders
+                SELECT
+                    f.id,
+                    f.title,
+                    f.parent_id,
+                    ft.depth + 1 as depth,
+                    CASE
+                        WHEN ft.depth = 0 THEN replace(f.title, '/', '⁄')
+                        ELSE ft.path || '/' || replace(f.title, '/', '⁄')
+                    END as path
+                FROM folders f
+                INNER JOIN folder_tree ft ON f.parent_id = ft.id
+                WHERE f.user_id = ?2 AND f.deleted_at IS NULL
+            ),
+            folder_paths AS (
+                SELECT 
+                    id,
+                    path,
+                    'directory' as type
+                FROM folder_tree
+                WHERE depth > 0  -- Exclude the root folder itself
+            ),
+            note_paths AS (
+                SELECT 
+                    n.id,
+                    CASE 
+                        WHEN ft.depth = 0 THEN ''
+                        ELSE ft.path || '/'
+                    END || replace(n.title, '/', '⁄')
+                        || CASE WHEN n.extensionless THEN '' ELSE '.' || COALESCE(
+                            (SELECT se.extension FROM syntax_extensions se
+                             WHERE se.syntax = n.syntax AND se.preferred),
+                            n.syntax) END as path,
+                    'file' as type
+                FROM notes n
+                INNER JOIN folder_tree ft ON (n.parent_id = ft.id OR (n.parent_id IS NULL AND ft.id = ?1))
+                WHERE n.user_id = ?2 AND n.deleted_at IS NULL
+            )
+            SELECT path, type FROM folder_paths
+            UNION ALL
+            SELECT path, type FROM note_paths
+            ORDER BY path";
+
+        let mut stmt = self.connection.prepare(query)?;
+        let file_iter = stmt.query_map(params![folder_id, user_id], |row| {
+            let path: String = row.get(0)?;
+            let file_type: String = row.get(1)?;
+
+            match file_type.as_str() {
+                "directory" => Ok(FileType::Directory { path }),
+                "file" => Ok(FileType::File { path }),
+                _ => Err(rusqlite::Error::InvalidColumnType(
+                    1,
+                    "type".to_string(),
+                    rusqlite::types::Type::Text,
+                )),
+            }
+        })?;
+
+        file_iter.collect()
+    }
+
+    /// Whether anything besides its index notes (the folder's own content)
+    /// is directly inside `parent_id`: a folder, a note or a hard link
+    pub fn has_children(&self, parent_id: Option<&str>, user_id: &str) -> Result<bool> {
+        self.connection.query_row(
+            &format!(
+                "SELECT EXISTS (SELECT 1 FROM folders
+                                WHERE parent_id IS ?1 AND user_id = ?2 AND deleted_at IS NULL)
+                     OR EXISTS (SELECT 1 FROM notes
+                                WHERE parent_id IS ?1 AND user_id = ?2 AND deleted_at IS NULL
+                                  AND NOT (title = ?3 {}))
+                     OR EXISTS (SELECT 1 FROM note_aliases WHERE parent_id IS ?1 AND user_id = ?2)",
+                self.name_collation()
+            ),
+            params![parent_id, user_id, INDEX_TITLE],
+            |row| row.get(0),
+        )
+    }
+
+    /// Number of folders directly inside `parent_id` (the root for `None`)
+    pub fn get_subfolder_count(&self, parent_id: Option<&str>, user_id: &str) -> Result<usize> {
+        let count: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM folders WHERE parent_id IS ?1 AND user_id = ?2 AND deleted_at IS NULL",
+            params![parent_id, user_id],
+            |row| row.get(0),
+        )?;
+
+        Ok(count as usize)
+    }
+
+    /// Ids of the index notes (one per syntax) holding the content of `folder_id`
+    pub fn get_index_note_ids(&self, folder_id: &str, user_id: &str) -> Result<Vec<String>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT id FROM notes WHERE parent_id = ?1 AND title = ?2 {} AND user_id = ?3 AND deleted_at IS NULL",
+            self.name_collation()
+        ))?;
+        let id_iter = stmt.query_map(params![folder_id, INDEX_TITLE, user_id], |row| {
+            row.get::<_, String>(0)
+        })?;
+
+        id_iter.collect()
+    }
+
+    /// Returns the id of the folder called `title` directly under `parent_id`, if any
+    pub fn find_folder_by_title(
+        &self,
+        parent_id: Option<&str>,
+        title: &str,
+        user_id: &str,
+    ) -> Result<Option<String>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT id FROM folders WHERE parent_id IS ?1 AND title = ?2 {} AND user_id = ?3 AND deleted_at IS NULL",
+            self.name_collation()
+        ))?;
+
+        let mut id_iter = stmt.query_map(params![parent_id, title, user_id], |row| {
+            row.get::<_, String>(0)
+        })?;
+
+        match id_iter.next() {
+            Some(id) => Ok(Some(id?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the id of the note `title.syntax` (or bare `title` when
+    /// `extensionless`) directly under `parent_id`, if any
+    ///
+    /// Mirrors the schema's uniqueness rules: notes inside a folder are unique
+    /// regardless of owner, root notes are unique per user.
+    pub fn find_note_by_title(
+        &self,
+        parent_id: Option<&str>,
+        title: &str,
+        syntax: &str,
+        extensionless: bool,
+        user_id: &str,
+    ) -> Result<Option<String>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT id FROM notes
+             WHERE parent_id IS ?1 AND title = ?2 {collation} AND syntax = ?3 {collation}
+               AND extensionless = ?4 AND (parent_id IS NOT NULL OR user_id = ?5)
+               AND deleted_at IS NULL",
+            collation = self.name_collation()
+        ))?;
+
+        let mut id_iter = stmt.query_map(
+            params![parent_id, title, syntax, extensionless, user_id],
+            |row| row.get::<_, String>(0),
+        )?;
+
+        match id_iter.next() {
+            Some(id) => Ok(Some(id?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Picks a folder title that is free under `parent_id`, appending " (n)" on collision
+    pub fn unique_folder_title(
+        &self,
+        parent_id: Option<&str>,
+        title: &str,
+        user_id: &str,
+    ) -> Result<String> {
+        let mut candidate = title.to_string();
+        let mut n = 1;
+        while self
+            .find_folder_by_title(parent_id, &candidate, user_id)?
+            .is_some()
+        {
+            candidate = format!("{title} ({n})");
+            n += 1;
+        }
+        Ok(candidate)
+    }
+
+    /// Picks a note title that is free under `parent_id` for the given syntax,
+    /// appending " (n)" on collision
+    pub fn unique_note_title(
+        &self,
+        parent_id: Option<&str>,
+        title: &str,
+        syntax: &str,
+        extensionless: bool,
+        user_id: &str,
+    ) -> Result<String> {
+        let mut candidate = title.to_string();
+        let mut n = 1;
+        while self
+            .find_note_by_title(parent_id, &candidate, syntax, extensionless, user_id)?
+            .is_some()
+        {
+            candidate = format!("{title} ({n})");
+            n += 1;
+        }
+        Ok(candidate)
+    }
+
+    /// Tag titles attached to a note, sorted by title
+    pub fn get_note_tags(&self, note_id: &str) -> Result<Vec<String>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT t.title FROM tags t
+             INNER JOIN note_tags nt ON nt.tag_id = t.id
+             WHERE nt.note_id = ?1 ORDER BY t.title",
+        )?;
+        let tags = stmt.query_map([note_id], |row| row.get(0))?;
+
+        tags.collect()
+    }
+
+    /// Replaces the tags on a note, creating any tags the user doesn't have yet
+    pub fn set_note_tags(&self, note_id: &str, tags: &[String], user_id: &str) -> Result<()> {
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute("DELETE FROM note_tags WHERE note_id = ?1", [note_id])?;
+        for tag in tags {
+            tx.execute(
+                "INSERT OR IGNORE INTO tags (title, user_id) VALUES (?1, ?2)",
+                params![tag, user_id],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO note_tags (note_id, tag_id)
+                 SELECT ?1, id FROM tags WHERE title = ?2 AND user_id = ?3",
+                params![note_id, tag, user_id],
+            )?;
+        }
+        tx.commit()
+    }
+
+    /// Adds `tag` to a note, creating the tag if the user doesn't have it yet
+    pub fn add_note_tag(&self, note_id: &str, tag: &str, user_id: &str) -> Result<()> {
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO tags (title, user_id) VALUES (?1, ?2)",
+            params![tag, user_id],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO note_tags (note_id, tag_id)
+             SELECT ?1, id FROM tags WHERE title = ?2 AND user_id = ?3",
+            params![note_id, tag, user_id],
+        )?;
+        tx.commit()
+    }
+
+    /// Takes `user_id`'s tag `tag` off a note; false if it didn't carry it
+    pub fn remove_note_tag(&self, note_id: &str, tag: &str, user_id: &str) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "DELETE FROM note_tags WHERE note_id = ?1
+                 AND tag_id IN (SELECT id FROM tags WHERE title = ?2 AND user_id = ?3)",
+            params![note_id, tag, user_id],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Whether the note `note_id` carries `user_id`'s tag `tag`
+    pub fn note_has_tag(&self, note_id: &str, tag: &str, user_id: &str) -> Result<bool> {
+        self.connection.query_row(
+            "SELECT EXISTS (
+                 SELECT 1 FROM note_tags nt
+                 INNER JOIN tags t ON t.id = nt.tag_id
+                 WHERE nt.note_id = ?1 AND t.title = ?2 AND t.user_id = ?3)",
+            params![note_id, tag, user_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Whether any note in the folder `folder_id` or below it carries
+    /// `user_id`'s tag `tag`
+    pub fn folder_has_tag(&self, folder_id: &str, tag: &str, user_id: &str) -> Result<bool> {
+        self.connection.query_row(
+            "WITH RECURSIVE subtree(id) AS (
+                 SELECT ?1
+                 UNION ALL
+                 SELECT f.id FROM folders f INNER JOIN subtree s ON f.parent_id = s.id
+                 WHERE f.deleted_at IS NULL
+             )
+             SELECT EXISTS (
+                 SELECT 1 FROM notes n
+                 INNER JOIN note_tags nt ON nt.note_id = n.id
+                 INNER JOIN tags t ON t.id = nt.tag_id
+                 WHERE n.parent_id IN (SELECT id FROM subtree) AND n.deleted_at IS NULL
+                   AND t.title = ?2 AND t.user_id = ?3)",
+            params![folder_id, tag, user_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Rows of the extension↔syntax mapping as (extension, syntax, preferred)
+    pub fn list_syntax_extensions(&self) -> Result<Vec<(String, String, bool)>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT extension, syntax, preferred FROM syntax_extensions")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+
+        rows.collect()
+    }
+
+    /// Maps `extension` to `syntax`, replacing any existing mapping for it
+    ///
+    /// The extension becomes the one shown for `syntax` unless that syntax
+    /// already has a preferred extension.
+    pub fn set_syntax_extension(&self, extension: &str, syntax: &str) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO syntax_extensions (extension, syntax, preferred)
+             VALUES (lower(?1), ?2, NOT EXISTS (
+                 SELECT 1 FROM syntax_extensions
+                 WHERE syntax = ?2 AND preferred AND extension <> ?1))
+             ON CONFLICT(extension) DO UPDATE
+             SET syntax = excluded.syntax, preferred = excluded.preferred",
+            params![extension, syntax],
+        )?;
+
+        Ok(())
+    }
+
+    /// Content of the template for `syntax` in the folder `folder_id`: the note
+    /// titled "default" if there is one, otherwise the first by title
+    pub fn find_template_content(
+        &self,
+        folder_id: &str,
+        syntax: &str,
+        user_id: &str,
+    ) -> Result<Option<String>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT COALESCE((SELECT body FROM note_contents WHERE hash = notes.content_hash), content) FROM notes
+             WHERE parent_id = ?1 AND syntax = ?2 AND user_id = ?3 AND deleted_at IS NULL
+             ORDER BY title <> 'default', title LIMIT 1",
+        )?;
+
+        let mut content_iter =
+            stmt.query_map([folder_id, syntax, user_id], |row| row.get::<_, String>(0))?;
+
+        match content_iter.next() {
+            Some(content) => Ok(Some(content?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Notes of `user_id` matching the FTS5 `query`, best match first
+    pub fn search_notes(&self, query: &str, user_id: &str) -> Result<Vec<Note>> {
+        let ranked = self.search_notes_ranked(query, user_id, None)?;
+        Ok(ranked.into_iter().map(|(note, _)| note).collect())
+    }
+
+    /// Notes of `user_id` matching the FTS5 `query` with their bm25 score
+    /// (lower is better), best match first; at most `limit` of them
+    pub fn search_notes_ranked(
+        &self,
+        query: &str,
+        user_id: &str,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Note, f64)>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT n.id, n.title, n.abstract, COALESCE((SELECT body FROM note_contents WHERE hash = n.content_hash), n.content), n.syntax, n.parent_id, n.user_id, n.created_at, n.updated_at, n.extensionless, n.accessed_at, n.mode, n.uid, n.gid, n.flags, n.content_size, n.revision, n.encrypted, n.lock_hash IS NOT NULL,
+                    bm25(notes_fts, {SEARCH_WEIGHTS}) AS score
+             FROM notes_fts f JOIN notes n ON n.id = f.id
+             WHERE notes_fts MATCH ?1 AND n.user_id = ?2 AND n.deleted_at IS NULL
+             ORDER BY score, n.title
+             LIMIT ?3"
+        ))?;
+        let limit = limit.map_or(-1, |limit| limit as i64);
+        let note_iter = stmt.query_map(params![query, user_id, limit], |row| {
+            Ok((Self::map_note_row(row)?, row.get(19)?))
+        })?;
+
+        note_iter.collect()
+    }
+
+    /// Titles of the user's notes that another of their notes links to
+    pub fn list_linked_titles(&self, user_id: &str) -> Result<Vec<String>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT DISTINCT n.title FROM notes n
+             JOIN links l ON l.target_title = n.title {}
+             JOIN notes source ON source.id = l.source_id AND source.user_id = ?1
+                  AND source.deleted_at IS NULL
+             WHERE n.user_id = ?1 AND n.deleted_at IS NULL
+             ORDER BY n.title",
+            self.name_collation()
+        ))?;
+        let title_iter = stmt.query_map([user_id], |row| row.get::<_, String>(0))?;
+
+        title_iter.collect()
+    }
+
+    /// The user's folders and notes, and the wikilinks between the notes
+    pub fn link_graph(&self, user_id: &str) -> Result<LinkGraph> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, parent_id, full_path FROM v_folder_id_path_mapping
+             WHERE user_id = ?1 ORDER BY full_path",
+        )?;
+        let folders = stmt
+            .query_map([user_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_>>()?;
+        // Orphaned notes have no path, so no place in the hierarchy
+        let mut stmt = self.connection.prepare(
+            "SELECT m.id, n.parent_id, m.full_path FROM v_note_id_path_mapping m
+             JOIN notes n ON n.id = m.id
+             WHERE m.user_id = ?1 AND m.full_path IS NOT NULL ORDER BY m.full_path",
+        )?;
+        let notes = stmt
+            .query_map([user_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_>>()?;
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT l.source_id, n.id FROM links l
+             JOIN notes source ON source.id = l.source_id AND source.user_id = ?1
+                  AND source.deleted_at IS NULL
+             JOIN notes n ON n.title = l.target_title {}
+             WHERE n.user_id = ?1 AND n.deleted_at IS NULL
+             ORDER BY l.source_id, n.id",
+            self.name_collation()
+        ))?;
+        let links = stmt
+            .query_map([user_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_>>()?;
+
+        Ok(LinkGraph {
+            folders,
+            notes,
+            links,
+        })
+    }
+
+    /// The user's notes with a wikilink to `title`
+    pub fn list_backlinks(&self, title: &str, user_id: &str) -> Result<Vec<Note>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT n.id, n.title, n.abstract, COALESCE((SELECT body FROM note_contents WHERE hash = n.content_hash), n.content), n.syntax, n.parent_id, n.user_id, n.created_at, n.updated_at, n.extensionless, n.accessed_at, n.mode, n.uid, n.gid, n.flags, n.content_size, n.revision, n.encrypted, n.lock_hash IS NOT NULL
+             FROM links l JOIN notes n ON n.id = l.source_id
+             WHERE l.target_title = ?1 {} AND n.user_id = ?2 AND n.deleted_at IS NULL
+             ORDER BY n.title",
+            self.name_collation()
+        ))?;
+        let note_iter = stmt.query_map([title, user_id], Self::map_note_row)?;
+
+        note_iter.collect()
+    }
+
+    /// The user's notes named by a wikilink in `note_id`
+    pub fn list_linked_notes(&self, note_id: &str, user_id: &str) -> Result<Vec<Note>> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT n.id, n.title, n.abstract, COALESCE((SELECT body FROM note_contents WHERE hash = n.content_hash), n.content), n.syntax, n.parent_id, n.user_id, n.created_at, n.updated_at, n.extensionless, n.accessed_at, n.mode, n.uid, n.gid, n.flags, n.content_size, n.revision, n.encrypted, n.lock_hash IS NOT NULL
+             FROM links l JOIN notes n ON n.title = l.target_title {}
+             WHERE l.source_id = ?1 AND n.user_id = ?2 AND n.deleted_at IS NULL
+             ORDER BY n.title",
+            self.name_collation()
+        ))?;
+        let note_iter = stmt.query_map([note_id, user_id], Self::map_note_row)?;
+
+        note_iter.collect()
+    }
+
+    /// Whether any of the user's notes is called `title` (in any syntax)
+    pub fn note_title_exists(&self, title: &str, user_id: &str) -> Result<bool> {
+        self.connection.query_row(
+            &format!(
+                "SELECT EXISTS (SELECT 1 FROM notes WHERE title = ?1 {} AND user_id = ?2 AND deleted_at IS NULL)",
+                self.name_collation()
+            ),
+            [title, user_id],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn create_saved_search(&self, name: &str, query: &str, user_id: &str) -> Result<String> {
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        self.connection.execute(
+            "INSERT INTO saved_searches (id, name, query, user_id) VALUES (?1, ?2, ?3, ?4)",
+            params![id, name, query, user_id],
+        )?;
+
+        Ok(id)
+    }
+
+    pub fn get_saved_search(&self, name: &str, user_id: &str) -> Result<Option<SavedSearch>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, name, query, user_id FROM saved_searches WHERE name = ?1 AND user_id = ?2",
+        )?;
+
+        let mut search_iter = stmt.query_map([name, user_id], Self::map_saved_search_row)?;
+
+        match search_iter.next() {
+            Some(search) => Ok(Some(search?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list_saved_searches(&self, user_id: &str) -> Result<Vec<SavedSearch>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, name, query, user_id FROM saved_searches WHERE user_id = ?1 ORDER BY name",
+        )?;
+        let search_iter = stmt.query_map([user_id], Self::map_saved_search_row)?;
+
+        search_iter.collect()
+    }
+
+    pub fn delete_saved_search(&self, name: &str, user_id: &str) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "DELETE FROM saved_searches WHERE name = ?1 AND user_id = ?2",
+            params![name, user_id],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    fn map_saved_search_row(row: &rusqlite::Row) -> rusqlite::Result<SavedSearch> {
+        Ok(SavedSearch {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            query: row.get(2)?,
+            user_id: row.get(3)?,
+        })
+    }
+
+    /// Let `grantee` into folder `id` of `owner_id` and everything below it,
+    /// replacing any earlier grant; false if `owner_id` has no such folder
+    pub fn grant_folder_access(
+        &self,
+        id: &str,
+        owner_id: &str,
+        grantee: &str,
+        access: Access,
+    ) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "INSERT INTO folder_acl (folder_id, user_id, access)
+             SELECT id, ?3, ?4 FROM folders WHERE id = ?1 AND user_id = ?2 AND deleted_at IS NULL
+             ON CONFLICT (folder_id, user_id) DO UPDATE SET access = excluded.access",
+            params![id, owner_id, grantee, access.as_str()],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Withdraw the grant of folder `id` of `owner_id` to `grantee`
+    pub fn revoke_folder_access(&self, id: &str, owner_id: &str, grantee: &str) -> Result<bool> {
+        let rows_affected = self.connection.execute(
+            "DELETE FROM folder_acl WHERE folder_id = ?1 AND user_id = ?3
+               AND folder_id IN (SELECT id FROM folders WHERE user_id = ?2)",
+            params![id, owner_id, grantee],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Other users' folders shared with `user_id`, with the access granted,
+    /// by owner and title
+    pub fn list_shared_folders(&self, user_id: &str) -> Result<Vec<(Folder, Access)>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT f.id, f.title, f.parent_id, f.user_id, f.created_at, f.updated_at, f.accessed_at, f.mode, f.uid, f.gid, f.flags, acl.access
+             FROM folder_acl acl JOIN folders f ON f.id = acl.folder_id
+             WHERE acl.user_id = ?1 AND f.user_id != ?1 AND f.deleted_at IS NULL
+             ORDER BY f.user_id, f.title",
+        )?;
+        let folder_iter = stmt.query_map([user_id], |row| {
+            let access = row.get::<_, String>(11)?;
+            Ok((Self::map_folder_row(row)?, Access::from_str(&access)))
+        })?;
+
+        let mut folders = Vec::new();
+        for folder in folder_iter {
+            if let (folder, Some(access)) = folder? {
+                folders.push((folder, access));
+            }
+        }
+        Ok(folders)
+    }
+
+    /// What `user_id` may do in folder `id`: everything in their own folders,
+    /// otherwise what the nearest grant on it or an ancestor allows
+    pub fn get_folder_access(&self, id: &str, user_id: &str) -> Result<Option<Access>> {
+        let access = self
+            .connection
+            .query_row(
+                "WITH RECURSIVE ancestors(id, parent_id, user_id, depth) AS (
+                     SELECT id, parent_id, user_id, 0 FROM folders
+                     WHERE id = ?1 AND deleted_at IS NULL
+                     UNION ALL
+                     SELECT f.id, f.parent_id, f.user_id, a.depth + 1
+                     FROM folders f JOIN ancestors a ON f.id = a.parent_id
+                     WHERE f.deleted_at IS NULL
+                 )
+                 SELECT CASE WHEN a.user_id = ?2 THEN 'write' ELSE acl.access END
+                 FROM ancestors a
+                 LEFT JOIN folder_acl acl ON acl.folder_id = a.id AND acl.user_id = ?2
+                 WHERE a.user_id = ?2 OR acl.access IS NOT NULL
+                 ORDER BY a.depth LIMIT 1",
+                params![id, user_id],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+
+        Ok(access.as_deref().and_then(Access::from_str))
+    }
+
+    /// True when `err` was raised by a UNIQUE/PRIMARY KEY constraint, i.e. the
+    /// write would have produced two entries with the same name in a directory
+    pub fn is_unique_violation(err: &rusqlite::Error) -> bool {
+        match err {
+            rusqlite::Error::SqliteFailure(e, _) => {
+                e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+                    || e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY
+            }
+            _ => false,
+        }
+    }
+
+    /// True when `err` is a query cancelled through `interrupt_handle`
+    pub fn is_interrupted(err: &rusqlite::Error) -> bool {
+        matches!(
+            err,
+            rusqlite::Error::SqliteFailure(e, _) if e.code == rusqlite::ErrorCode::OperationInterrupted
+        )
+    }
+
+    /// Handle that cancels the query running on this connection from another thread
+    pub fn interrupt_handle(&self) -> rusqlite::InterruptHandle {
+        self.connection.get_interrupt_handle()
+    }
+
+    /// Fails if the database can't be read (gone, locked, or the wrong key)
+    pub fn ping(&self) -> Result<()> {
+        self.connection
+            .query_row("SELECT count(*) FROM sqlite_master", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map(|_| ())
+    }
+
+    /// A number that changes whenever another connection (another process
+    /// included) commits to the database, and not when this one does
+    pub fn data_version(&self) -> Result<i64> {
+        self.connection
+            .query_row("PRAGMA data_version", [], |row| row.get(0))
+    }
+
+    /// The notes and folders of `user_id` changed or deleted at `since` or
+    /// later: what another connection changed since a look taken then
+    ///
+    /// Moving or renaming an item also touches the folder it left, so that
+    /// folder is among them too (unless it is the top of the notes).
+    pub fn changed_since(&self, user_id: &str, since: DateTime<Utc>) -> Result<Vec<ChangedItem>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, 1, parent_id, deleted_at IS NOT NULL FROM folders
+             WHERE user_id = ?1 AND (updated_at >= ?2 OR deleted_at >= ?2)
+             UNION ALL
+             SELECT id, 0, parent_id, deleted_at IS NOT NULL FROM notes
+             WHERE user_id = ?1 AND (updated_at >= ?2 OR deleted_at >= ?2)",
+        )?;
+        stmt.query_map(params![user_id, Self::format_timestamp(since)], |row| {
+            Ok(ChangedItem {
+                id: row.get(0)?,
+                is_folder: row.get(1)?,
+                parent_id: row.get(2)?,
+                deleted: row.get(3)?,
+            })
+        })?
+        .collect()
+    }
+
+    /// Call `hook` with the run time of every statement this connection finishes
+    pub fn profile_queries(&self, hook: fn(rusqlite::trace::TraceEvent<'_>)) {
+        self.connection.trace_v2(
+            rusqlite::trace::TraceEventCodes::SQLITE_TRACE_PROFILE,
+            Some(hook),
+        );
+    }
+
+    /// Maps a database row to a Folder struct, handling datetime parsing.
+    /// Extracted as a helper to avoid code duplication across query methods.
+    /// Expects columns: id, title, parent_id, user_id, created_at, updated_at, accessed_at,
+    /// mode, uid, gid, flags
+    fn map_folder_row(row: &rusqlite::Row) -> rusqlite::Result<Folder> {
+        Ok(Folder {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            parent_id: row.get(2)?,
+            user_id: row.get(3)?,
+            created_at: NaiveDateTime::parse_from_str(
+                &row.get::<_, String>(4)?,
+                "%Y-%m-%d %H:%M:%S",
+            )
+            .map_err(|_| {
+                rusqlite::Error::InvalidColumnType(
+                    4,
+                    "created_at".to_string(),
+                    rusqlite::types::Type::Text,
+                )
+            })?
+            .and_utc(),
+            updated_at: NaiveDateTime::parse_from_str(
+                &row.get::<_, String>(5)?,
+                "%Y-%m-%d %H:%M:%S",
+            )
+            .map_err(|_| {
+                rusqlite::Error::InvalidColumnType(
+                    5,
+                    "updated_at".to_string(),
+                    rusqlite::types::Type::Text,
+                )
+            })?
+            .and_utc(),
+            accessed_at: Self::parse_optional_timestamp(row, 6, "accessed_at")?,
+            mode: row.get(7)?,
+            uid: row.get(8)?,
+            gid: row.get(9)?,
+            flags: row.get(10)?,
+        })
+    }
+
+    /// Maps a database row to a Note struct, handling datetime parsing.
+    /// Extracted as a helper to avoid code duplication across query methods.
+    fn map_note_row(row: &rusqlite::Row) -> rusqlite::Result<Note> {
+        Ok(Note {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            abstract_text: row.get(2)?,
+            content: row.get(3)?,
+            syntax: row.get(4)?,
+            parent_id: row.get(5)?,
+            user_id: row.get(6)?,
+            created_at: NaiveDateTime::parse_from_str(
+                &row.get::<_, String>(7)?,
+                "%Y-%m-%d %H:%M:%S",
+            )
+            .map_err(|_| {
+                rusqlite::Error::InvalidColumnType(
+                    7,
+                    "created_at".to_string(),
+                    rusqlite::types::Type::Text,
+                )
+            })?
+            .and_utc(),
+            updated_at: NaiveDateTime::parse_from_str(
+                &row.get::<_, String>(8)?,
+                "%Y-%m-%d %H:%M:%S",
+            )
+            .map_err(|_| {
+                rusqlite::Error::InvalidColumnType(
+                    8,
+                    "updated_at".to_string(),
+                    rusqlite::types::Type::Text,
+                )
+            })?
+            .and_utc(),
+            extensionless: row.get(9)?,
+            accessed_at: Self::parse_optional_timestamp(row, 10, "accessed_at")?,
+            mode: row.get(11)?,
+            uid: row.get(12)?,
+            gid: row.get(13)?,
+            flags: row.get(14)?,
+            content_size: row.get(15)?,
+            revision: row.get(16)?,
+            encrypted: row.get(17)?,
+            locked: row.get(18)?,
+        })
+    }
+
+    /// Maps a row of the note columns other than content to a NoteMeta.
+    /// Expects columns: id, title, abstract, syntax, parent_id, user_id, created_at,
+    /// updated_at, extensionless, accessed_at, mode, uid, gid, flags, content_size,
+    /// encrypted, locked
+    fn map_note_meta_row(row: &rusqlite::Row) -> rusqlite::Result<NoteMeta> {
+        Ok(NoteMeta {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            abstract_text: row.get(2)?,
+            syntax: row.get(3)?,
+            parent_id: row.get(4)?,
+            user_id: row.get(5)?,
+            created_at: Self::parse_timestamp(row, 6, "created_at")?,
+            updated_at: Self::parse_timestamp(row, 7, "updated_at")?,
+            extensionless: row.get(8)?,
+            accessed_at: Self::parse_optional_timestamp(row, 9, "accessed_at")?,
+            mode: row.get(10)?,
+            uid: row.get(11)?,
+            gid: row.get(12)?,
+            flags: row.get(13)?,
+            content_size: row.get(14)?,
+            encrypted: row.get(15)?,
+            locked: row.get(16)?,
+        })
+    }
+
+    /// Parses a timestamp column, e.g. `created_at`
+    fn parse_timestamp(
+        row: &rusqlite::Row,
+        idx: usize,
+        name: &str,
+    ) -> rusqlite::Result<DateTime<Utc>> {
+        Self::parse_optional_timestamp(row, idx, name)?.ok_or_else(|| {
+            rusqlite::Error::InvalidColumnType(idx, name.to_string(), rusqlite::types::Type::Null)
+        })
+    }
+
+    /// Parses a nullable timestamp column, e.g. `accessed_at`
+    fn parse_optional_timestamp(
+        row: &rusqlite::Row,
+        idx: usize,
+        name: &str,
+    ) -> rusqlite::Result<Option<DateTime<Utc>>> {
+        row.get::<_, Option<String>>(idx)?
+            .map(|s| {
+                NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S")
+                    .map(|dt| dt.and_utc())
+                    .map_err(|_| {
+                        rusqlite::Error::InvalidColumnType(
+                            idx,
+                            name.to_string(),
+                            rusqlite::types::Type::Text,
+                        )
+                    })
+            })
+            .transpose()
+    }
+}
+
+/// Optional details of a user being registered
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UserProfile<'a> {
+    /// Human-readable name shown next to the user id
+    pub display_name: Option<&'a str>,
+    /// Timezone their times are shown in; the mount's when `None`
+    pub timezone: Option<Tz>,
+}
+
+/// Content fields for a note that are frequently updated together
+#[derive(Debug, Clone)]
+pub struct NoteContent<'a> {
+    pub title: &'a str,
+    pub abstract_text: Option<&'a str>,
+    pub content: &'a str,
+    pub syntax: &'a str,
+    /// The file name is the bare title; `syntax` holds the configured default
+    pub extensionless: bool,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Folder {
+    pub id: String,
+    pub title: String,
+    pub parent_id: Option<String>,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Set by utimens; `None` means the same as `updated_at`
+    pub accessed_at: Option<DateTime<Utc>>,
+    /// Permission bits set by chmod; `None` uses the mount's default
+    pub mode: Option<u32>,
+    /// Owner set by chown; `None` uses the mount's default
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// BSD file flags set by chflags (e.g. UF_HIDDEN); `None` means none
+    pub flags: Option<u32>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub id: String,
+    pub title: String,
+    pub abstract_text: Option<String>,
+    pub content: String,
+    pub syntax: String,
+    pub parent_id: Option<String>,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub extensionless: bool,
+    /// Set by utimens; `None` means the same as `updated_at`
+    pub accessed_at: Option<DateTime<Utc>>,
+    /// Permission bits set by chmod; `None` uses the mount's default
+    pub mode: Option<u32>,
+    /// Owner set by chown; `None` uses the mount's default
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// BSD file flags set by chflags (e.g. UF_HIDDEN); `None` means none
+    pub flags: Option<u32>,
+    /// Byte length of `content`, maintained by the database
+    pub content_size: u64,
+    /// Incremented by the database each time `content` changes
+    pub revision: i64,
+    /// `content` is age ciphertext, which the mount decrypts
+    pub encrypted: bool,
+    /// Locked (see `Database::get_note_lock`): kept from the mount until unlocked
+    pub locked: bool,
+}
+
+/// A binary file kept with a note; its bytes are read separately
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub id: String,
+    pub note_id: String,
+    pub filename: String,
+    pub mime: String,
+    /// Byte length of the stored data
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// An entry of a folder, as listed by `list_children`
+#[derive(Debug, Clone)]
+pub enum Child {
+    Folder(Folder),
+    Note(NoteMeta),
+}
+
+/// A window of a listing: at most `limit` rows (all for `None`) after the
+/// first `offset`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl Page {
+    /// The whole listing
+    pub const ALL: Page = Page {
+        offset: 0,
+        limit: None,
+    };
+
+    /// `LIMIT` and `OFFSET` bound for the query; SQLite reads a negative limit as none
+    fn bounds(&self) -> (i64, i64) {
+        (
+            self.limit.map_or(-1, |limit| limit as i64),
+            self.offset as i64,
+        )
+    }
+}
+
+/// A user's notes network, as `link_graph` reads it
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+    /// (id, parent folder id, path) of each folder
+    pub folders: Vec<(String, Option<String>, String)>,
+    /// (id, parent folder id, path) of each note
+    pub notes: Vec<(String, Option<String>, String)>,
+    /// (source note id, target note id) of each wikilink that leads to a
+    /// note; a link to a title several notes have leads to each of them
+    pub links: Vec<(String, String)>,
+}
+
+/// What `deduplicate_notes` shared
+#[derive(Debug, Default, PartialEq)]
+pub struct DedupSummary {
+    /// Notes that now refer to a shared body
+    pub notes: usize,
+    /// Bodies newly stored in `note_contents`
+    pub bodies: usize,
+    /// Bytes of content no longer stored more than once
+    pub bytes_saved: i64,
+}
+
+/// How much of `notes_history` `purge_history` keeps; a version is purged
+/// once it falls outside either limit
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HistoryRetention {
+    /// Newest versions kept per note
+    pub keep_versions: Option<usize>,
+    /// Days a version is kept after it was replaced or deleted
+    pub keep_days: Option<u32>,
+}
+
+/// Everything about a note except its content, for paths that only need attributes
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct NoteMeta {
+    pub id: String,
+    pub title: String,
+    pub abstract_text: Option<String>,
+    pub syntax: String,
+    pub parent_id: Option<String>,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub extensionless: bool,
+    /// Set by utimens; `None` means the same as `updated_at`
+    pub accessed_at: Option<DateTime<Utc>>,
+    /// Permission bits set by chmod; `None` uses the mount's default
+    pub mode: Option<u32>,
+    /// Owner set by chown; `None` uses the mount's default
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// BSD file flags set by chflags (e.g. UF_HIDDEN); `None` means none
+    pub flags: Option<u32>,
+    /// Byte length of the content
+    pub content_size: u64,
+    /// The content is age ciphertext, which the mount decrypts
+    pub encrypted: bool,
+    /// Locked (see `Database::get_note_lock`): kept from the mount until unlocked
+    pub locked: bool,
+}
+
+/// A note or folder as `Database::changed_since` found it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangedItem {
+    pub id: String,
+    pub is_folder: bool,
+    pub parent_id: Option<String>,
+    /// Deleted since (not yet purged)
+    pub deleted: bool,
+}
+
+/// Another name (hard link) for a note, in the same shape as a note's own name
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct NoteAlias {
+    pub id: String,
+    pub note_id: String,
+    pub parent_id: Option<String>,
+    pub title: String,
+    pub syntax: String,
+    pub extensionless: bool,
+    pub user_id: String,
+}
+
+/// What another user may do in a folder shared with them (`folder_acl`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl Access {
+    /// The value stored in `folder_acl.access`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Write => "write",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<Access> {
+        match value {
+            "read" => Some(Access::Read),
+            "write" => Some(Access::Write),
+            _ => None,
+        }
+    }
+}
+
+/// A named FTS5 query, listed as a directory of its matches
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct SavedSearch {
+    pub id: String,
+    pub name: String,
+    pub query: String,
+    pub user_id: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum FileType {
+    Directory { path: String },
+    File { path: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use rusqlite::Connection;
+
+    fn setup_test_database() -> Database {
+        let conn = Connection::open_in_memory().expect("Failed to create in-memory database");
+
+        // Read and execute the init.sql file
+        let init_sql = include_str!("../sql/init.sql");
+        conn.execute_batch(init_sql)
+            .expect("Failed to initialize database");
+
+        Database::new(conn, Some(chrono_tz::Australia::Sydney))
+    }
+
+    /// Creates a Markdown note with a random id in `parent_id`, returning the id
+    fn create_test_note(
+        db: &Database,
+        title: &str,
+        content: &str,
+        parent_id: Option<&str>,
+        user_id: &str,
+    ) -> String {
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        db.create_note(
+            &id,
+            NoteContent {
+                title,
+                abstract_text: None,
+                content,
+                syntax: "md",
+                extensionless: false,
+            },
+            parent_id,
+            user_id,
+        )
+        .expect("Failed to create note");
+        id
+    }
+
+    #[test]
+    fn test_folder_crud_operations() {
+        let db = setup_test_database();
+        let user_id = "test_user";
+
+        // Create root folder
+        let root_id = db
+            .create_folder("Documents", None, user_id)
+            .expect("Failed to create root folder");
+        assert!(!root_id.is_empty());
+
+        // Create child folder
+        let child_id = db
+            .create_folder("Projects", Some(&root_id), user_id)
+            .expect("Failed to create child folder");
+        assert!(!child_id.is_empty());
+        assert_ne!(root_id, child_id);
+
+        // Read back the root folder
+        let folder = db
+            .get_folder_by_id(&root_id, user_id)
+            .expect("Failed to query folder")
+            .expect("Root folder not found");
+
+        assert_eq!(folder.id, root_id);
+        assert_eq!(folder.title, "Documents");
+        assert_eq!(folder.parent_id, None);
+        assert!(!folder.created_at.to_string().is_empty());
+        assert!(!folder.updated_at.to_string().is_empty());
+
+        // Read back the child folder
+        let child_folder = db
+            .get_folder_by_id(&child_id, user_id)
+            .expect("Failed to query child folder")
+            .expect("Child folder not found");
+
+        assert_eq!(child_folder.id, child_id);
+        assert_eq!(child_folder.title, "Projects");
+        assert_eq!(child_folder.parent_id, Some(root_id.clone()));
+
+        // Test update
+        let updated = db
+            .update_folder(&root_id, "My Documents", user_id)
+            .expect("Failed to update folder");
+        assert!(updated);
+
+        let updated_folder = db
+            .get_folder_by_id(&root_id, user_id)
+            .expect("Failed to query updated folder")
+            .expect("Updated folder not found");
+        assert_eq!(updated_folder.title, "My Documents");
+
+        // Test list folders by parent
+        let children = db
+            .list_folders_by_parent(Some(&root_id), user_id, Page::ALL)
+            .expect("Failed to list child folders");
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].title, "Projects");
+
+        // Test delete
+        let deleted = db
+            .delete_folder(&child_id, user_id)
+            .expect("Failed to delete folder");
+        assert!(deleted);
+
+        let deleted_folder = db
+            .get_folder_by_id(&child_id, user_id)
+            .expect("Failed to query deleted folder");
+        assert!(deleted_folder.is_none());
+    }
+
+    #[test]
+    fn test_timezone_functionality() {
+        let db = setup_test_database();
+        let user_id = "test_user";
+
+        let folder_id = db
+            .create_folder("Test Timezone", None, user_id)
+            .expect("Failed to create folder for timezone test");
+
+        let folder = db
+            .get_folder_by_id(&folder_id, user_id)
+            .expect("Failed to query folder")
+            .expect("Folder not found");
+
+        // The timestamps should be stored and retrieved properly
+        // Note: The actual timezone conversion happens during storage
+        assert!(!folder.created_at.to_string().is_empty());
+        assert!(!folder.updated_at.to_string().is_empty());
+        assert_eq!(folder.created_at, folder.updated_at);
+    }
+
+    #[test]
+    fn test_get_note_by_id() {
+        let db = setup_test_database();
+        let user_id = "test_user_123";
+        let note_id = "test_note_456";
+
+        // Test getting non-existent note
+        let non_existent = db
+            .get_note_by_id("non_existent_id")
+            .expect("Failed to query non-existent note");
+        assert!(non_existent.is_none());
+
+        // Create a note directly in database for testing
+        db.connection.execute(
+            "INSERT INTO notes (id, title, abstract, content, syntax, parent_id, user_id, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
+            params![note_id, "Test Note", "Test abstract", "# Test Content", "md", None::<String>, user_id, "2023-01-01 12:00:00"]
+        ).expect("Failed to insert test note");
+
+        // Test getting existing note
+        let note = db
+            .get_note_by_id(note_id)
+            .expect("Failed to query note")
+            .expect("Note not found");
+
+        assert_eq!(note.id, note_id);
+        assert_eq!(note.title, "Test Note");
+        assert_eq!(note.abstract_text, Some("Test abstract".to_string()));
+        assert_eq!(note.content, "# Test Content");
+        assert_eq!(note.syntax, "md");
+        assert_eq!(note.parent_id, None);
+        assert_eq!(note.user_id, user_id);
+    }
+
+    #[test]
+    fn test_create_note() {
+        let db = setup_test_database();
+        let user_id = "test_user_456";
+
+        // Create note without parent
+        let note_id = db
+            .create_note(
+                "note_1",
+                NoteContent {
+                    title: "My First Note",
+                    abstract_text: Some("An abstract"),
+                    content: "# Hello World",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                None,
+                user_id,
+            )
+            .expect("Failed to create note");
+        assert_eq!(note_id, "note_1");
+
+        // Verify note was created
+        let note = db
+            .get_note_by_id("note_1")
+            .expect("Failed to query created note")
+            .expect("Created note not found");
+
+        assert_eq!(note.title, "My First Note");
+        assert_eq!(note.abstract_text, Some("An abstract".to_string()));
+        assert_eq!(note.content, "# Hello World");
+        assert_eq!(note.syntax, "md");
+        assert_eq!(note.user_id, user_id);
+
+        // Create note with parent folder
+        let folder_id = db
+            .create_folder("Notes Folder", None, user_id)
+            .expect("Failed to create folder");
+
+        let note_id2 = db
+            .create_note(
+                "note_2",
+                NoteContent {
+                    title: "Second Note",
+                    abstract_text: None,
+                    content: "Some content",
+                    syntax: "txt",
+                    extensionless: false,
+                },
+                Some(&folder_id),
+                user_id,
+            )
+            .expect("Failed to create note with parent");
+
+        let note2 = db
+            .get_note_by_id(&note_id2)
+            .expect("Failed to query second note")
+            .expect("Second note not found");
+
+        assert_eq!(note2.parent_id, Some(folder_id));
+        assert_eq!(note2.abstract_text, None);
+        assert_eq!(note2.syntax, "txt");
+    }
+
+    #[test]
+    fn test_update_note_content() {
+        let db = setup_test_database();
+        let user_id = "test_user_789";
+
+        let note_id = db
+            .create_note(
+                "update_test",
+                NoteContent {
+                    title: "Original Title",
+                    abstract_text: Some("Original abstract"),
+                    content: "Original content",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                None,
+                user_id,
+            )
+            .expect("Failed to create note for update test");
+
+        // Test updating all content fields
+        let updated = db
+            .update_note(
+                &note_id,
+                "Updated Title",
+                Some("Updated abstract"),
+                "Updated content",
+                "rst",
+            )
+            .expect("Failed to update note");
+        assert!(updated);
+
+        let note = db
+            .get_note_by_id(&note_id)
+            .expect("Failed to query updated note")
+            .expect("Updated note not found");
+
+        assert_eq!(note.title, "Updated Title");
+        assert_eq!(note.abstract_text, Some("Updated abstract".to_string()));
+        assert_eq!(note.content, "Updated content");
+        assert_eq!(note.syntax, "rst");
+
+        // Test updating with None abstract
+        let updated2 = db
+            .update_note(&note_id, "Final Title", None, "Final content", "md")
+            .expect("Failed to update note with None abstract");
+        assert!(updated2);
+
+        let note2 = db
+            .get_note_by_id(&note_id)
+            .expect("Failed to query updated note")
+            .expect("Updated note not found");
+
+        assert_eq!(note2.abstract_text, None);
+
+        // Test updating non-existent note
+        let not_updated = db
+            .update_note("non_existent", "Title", None, "Content", "md")
+            .expect("Failed to update non-existent note");
+        assert!(!not_updated);
+    }
+
+    #[test]
+    fn test_update_note_parent() {
+        let db = setup_test_database();
+        let user_id = "test_user_parent";
+
+        let note_id = db
+            .create_note(
+                "parent_test",
+                NoteContent {
+                    title: "Test Note",
+                    abstract_text: None,
+                    content: "Content",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                None,
+                user_id,
+            )
+            .expect("Failed to create note for parent test");
+
+        // Create folders
+        let folder1_id = db
+            .create_folder("Folder 1", None, user_id)
+            .expect("Failed to create folder 1");
+        let folder2_id = db
+            .create_folder("Folder 2", None, user_id)
+            .expect("Failed to create folder 2");
+
+        // Move note to folder 1
+        let updated = db
+            .update_note_parent(&note_id, Some(&folder1_id))
+            .expect("Failed to update note parent");
+        assert!(updated);
+
+        let note = db
+            .get_note_by_id(&note_id)
+            .expect("Failed to query note")
+            .expect("Note not found");
+        assert_eq!(note.parent_id, Some(folder1_id.clone()));
+
+        // Move note to folder 2
+        let updated2 = db
+            .update_note_parent(&note_id, Some(&folder2_id))
+            .expect("Failed to update note parent to folder 2");
+        assert!(updated2);
+
+        let note2 = db
+            .get_note_by_id(&note_id)
+            .expect("Failed to query note")
+            .expect("Note not found");
+        assert_eq!(note2.parent_id, Some(folder2_id));
+
+        // Move note to root (no parent)
+        let updated3 = db
+            .update_note_parent(&note_id, None)
+            .expect("Failed to update note parent to None");
+        assert!(updated3);
+
+        let note3 = db
+            .get_note_by_id(&note_id)
+            .expect("Failed to query note")
+            .expect("Note not found");
+        assert_eq!(note3.parent_id, None);
+
+        // Test updating non-existent note
+        let not_updated = db
+            .update_note_parent("non_existent", Some(&folder1_id))
+            .expect("Failed to update non-existent note parent");
+        assert!(!not_updated);
+    }
+
+    #[test]
+    fn test_delete_note() {
+        let db = setup_test_database();
+        let user_id = "test_user_delete";
+
+        let note_id = db
+            .create_note(
+                "delete_test",
+                NoteContent {
+                    title: "Delete Me",
+                    abstract_text: Some("Abstract"),
+                    content: "Content to delete",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                None,
+                user_id,
+            )
+            .expect("Failed to create note for delete test");
+
+        // Verify note exists
+        let note = db
+            .get_note_by_id(&note_id)
+            .expect("Failed to query note before delete")
+            .expect("Note should exist before delete");
+        assert_eq!(note.title, "Delete Me");
+
+        // Delete note
+        let deleted = db.delete_note(&note_id).expect("Failed to delete note");
+        assert!(deleted);
+
+        // Verify note is gone
+        let deleted_note = db
+            .get_note_by_id(&note_id)
+            .expect("Failed to query deleted note");
+        assert!(deleted_note.is_none());
+
+        // It stays in the table until purged, which logs it to history
+        let (_, purged) = db
+            .purge_deleted(None)
+            .expect("Failed to purge deleted notes");
+        assert_eq!(purged, 1);
+
+        // Verify history was created (check if history table has the note)
+        let mut stmt = db
+            .connection
+            .prepare("SELECT COUNT(*) FROM notes_history WHERE id = ?1 AND log_action = 'DELETE'")
+            .expect("Failed to prepare history query");
+        let count: i64 = stmt
+            .query_row([&note_id], |row| row.get(0))
+            .expect("Failed to query history count");
+        assert_eq!(count, 1, "Note should be in history after deletion");
+
+        // Test deleting non-existent note
+        let not_deleted = db
+            .delete_note("non_existent")
+            .expect("Failed to delete non-existent note");
+        assert!(!not_deleted);
+    }
+
+    #[test]
+    fn test_list_notes_by_parent() {
+        let db = setup_test_database();
+        let user_id = "test_user_list";
+
+        // Create folder
+        let folder_id = db
+            .create_folder("Test Folder", None, user_id)
+            .expect("Failed to create folder");
+
+        // Create notes in folder
+        db.create_note(
+            "note1",
+            NoteContent {
+                title: "Note 1",
+                abstract_text: None,
+                content: "Content 1",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&folder_id),
+            user_id,
+        )
+        .expect("Failed to create note 1");
+        db.create_note(
+            "note2",
+            NoteContent {
+                title: "Note 2",
+                abstract_text: None,
+                content: "Content 2",
+                syntax: "txt",
+                extensionless: false,
+            },
+            Some(&folder_id),
+            user_id,
+        )
+        .expect("Failed to create note 2");
+
+        // Create note at root level
+        db.create_note(
+            "note3",
+            NoteContent {
+                title: "Root Note",
+                abstract_text: None,
+                content: "Root content",
+                syntax: "md",
+                extensionless: false,
+            },
+            None,
+            user_id,
+        )
+        .expect("Failed to create root note");
+
+        // Create note for different user in same folder
+        db.create_note(
+            "note4",
+            NoteContent {
+                title: "Other User",
+                abstract_text: None,
+                content: "Content",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&folder_id),
+            "other_user",
+        )
+        .expect("Failed to create note for other user");
+
+        // Test listing notes in folder for specific user
+        let folder_notes = db
+            .list_notes_by_parent(Some(&folder_id), user_id, Page::ALL)
+            .expect("Failed to list notes in folder");
+        assert_eq!(folder_notes.len(), 2);
+
+        let titles: Vec<&str> = folder_notes.iter().map(|n| n.title.as_str()).collect();
+        assert!(titles.contains(&"Note 1"));
+        assert!(titles.contains(&"Note 2"));
+        assert!(!titles.contains(&"Other User"));
+
+        // Test listing root notes for user
+        let root_notes = db
+            .list_notes_by_parent(None, user_id, Page::ALL)
+            .expect("Failed to list root notes");
+        assert_eq!(root_notes.len(), 1);
+        assert_eq!(root_notes[0].title, "Root Note");
+
+        // Test listing notes for user with no notes in folder
+        let empty_folder_id = db
+            .create_folder("Empty Folder", None, user_id)
+            .expect("Failed to create empty folder");
+        let empty_notes = db
+            .list_notes_by_parent(Some(&empty_folder_id), user_id, Page::ALL)
+            .expect("Failed to list notes in empty folder");
+        assert_eq!(empty_notes.len(), 0);
+    }
+
+    #[test]
+    fn test_fts_triggers() {
+        let db = setup_test_database();
+        let user_id = "fts_test_user";
+
+        // Create a note
+        let note_id = db
+            .create_note(
+                "fts_test",
+                NoteContent {
+                    title: "Searchable Note",
+                    abstract_text: Some("This is searchable"),
+                    content: "Content with keywords",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                None,
+                user_id,
+            )
+            .expect("Failed to create note for FTS test");
+
+        // Verify note was added to FTS table
+        let mut stmt = db
+            .connection
+            .prepare("SELECT COUNT(*) FROM notes_fts WHERE id = ?1")
+            .expect("Failed to prepare FTS query");
+        let count: i64 = stmt
+            .query_row([&note_id], |row| row.get(0))
+            .expect("Failed to query FTS count");
+        assert_eq!(count, 1, "Note should be in FTS table after creation");
+
+        // Update the note and verify FTS is updated
+        db.update_note(
+            &note_id,
+            "Updated Searchable",
+            Some("Updated abstract"),
+            "Updated content",
+            "md",
+        )
+        .expect("Failed to update note");
+
+        let mut stmt = db
+            .connection
+            .prepare("SELECT title FROM notes_fts WHERE id = ?1")
+            .expect("Failed to prepare FTS query");
+        let title: String = stmt
+            .query_row([&note_id], |row| row.get(0))
+            .expect("Failed to query FTS title");
+        assert_eq!(
+            title, "Updated Searchable",
+            "FTS should reflect updated title"
+        );
+
+        // Delete the note and verify it's removed from FTS
+        db.delete_note(&note_id).expect("Failed to delete note");
+        db.purge_deleted(None)
+            .expect("Failed to purge deleted notes");
+
+        let mut stmt = db
+            .connection
+            .prepare("SELECT COUNT(*) FROM notes_fts WHERE id = ?1")
+            .expect("Failed to prepare FTS query");
+        let count: i64 = stmt
+            .query_row([&note_id], |row| row.get(0))
+            .expect("Failed to query FTS count");
+        assert_eq!(
+            count, 0,
+            "Note should be removed from FTS table after deletion"
+        );
+    }
+
+    #[test]
+    fn test_folder_path_resolution() {
+        let db = setup_test_database();
+        let user_id = "test_user";
+
+        // Create nested folder structure: Documents/Projects/MyProject
+        let docs_id = db
+            .create_folder("Documents", None, user_id)
+            .expect("Failed to create Documents folder");
+        let projects_id = db
+            .create_folder("Projects", Some(&docs_id), user_id)
+            .expect("Failed to create Projects folder");
+        let myproject_id = db
+            .create_folder("MyProject", Some(&projects_id), user_id)
+            .expect("Failed to create MyProject folder");
+
+        // Test getting paths by ID
+        let docs_path = db
+            .get_folder_path_by_id(&docs_id, user_id)
+            .expect("Failed to get Documents path")
+            .expect("Documents path should exist");
+        assert_eq!(docs_path, "Documents");
+
+        let projects_path = db
+            .get_folder_path_by_id(&projects_id, user_id)
+            .expect("Failed to get Projects path")
+            .expect("Projects path should exist");
+        assert_eq!(projects_path, "Documents/Projects");
+
+        let myproject_path = db
+            .get_folder_path_by_id(&myproject_id, user_id)
+            .expect("Failed to get MyProject path")
+            .expect("MyProject path should exist");
+        assert_eq!(myproject_path, "Documents/Projects/MyProject");
+
+        // Test getting IDs by path
+        let docs_id_resolved = db
+            .get_folder_id_by_path("Documents", user_id)
+            .expect("Failed to resolve Documents ID")
+            .expect("Documents ID should exist");
+        assert_eq!(docs_id_resolved, docs_id);
+
+        let projects_id_resolved = db
+            .get_folder_id_by_path("Documents/Projects", user_id)
+            .expect("Failed to resolve Projects ID")
+            .expect("Projects ID should exist");
+        assert_eq!(projects_id_resolved, projects_id);
+
+        let myproject_id_resolved = db
+            .get_folder_id_by_path("Documents/Projects/MyProject", user_id)
+            .expect("Failed to resolve MyProject ID")
+            .expect("MyProject ID should exist");
+        assert_eq!(myproject_id_resolved, myproject_id);
+
+        // Test non-existent paths/IDs
+        let non_existent_path = db
+            .get_folder_path_by_id("non_existent_id", user_id)
+            .expect("Failed to query non-existent folder path");
+        assert!(non_existent_path.is_none());
+
+        let non_existent_id = db
+            .get_folder_id_by_path("Non/Existent/Path", user_id)
+            .expect("Failed to query non-existent folder ID");
+        assert!(non_existent_id.is_none());
+    }
+
+    #[test]
+    fn test_note_path_resolution() {
+        let db = setup_test_database();
+        let user_id = "path_test_user";
+
+        // Create folder structure and notes
+        let work_id = db
+            .create_folder("Work", None, user_id)
+            .expect("Failed to create Work folder");
+        let projects_id = db
+            .create_folder("Projects", Some(&work_id), user_id)
+            .expect("Failed to create Projects folder");
+
+        // Create notes at different levels
+        let root_note_id = db
+            .create_note(
+                "root_note",
+                NoteContent {
+                    title: "README",
+                    abstract_text: None,
+                    content: "Root readme content",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                None,
+                user_id,
+            )
+            .expect("Failed to create root note");
+
+        let work_note_id = db
+            .create_note(
+                "work_note",
+                NoteContent {
+                    title: "agenda",
+                    abstract_text: None,
+                    content: "Work agenda",
+                    syntax: "org",
+                    extensionless: false,
+                },
+                Some(&work_id),
+                user_id,
+            )
+            .expect("Failed to create work note");
+
+        let project_note_id = db
+            .create_note(
+                "project_note",
+                NoteContent {
+                    title: "specification",
+                    abstract_text: None,
+                    content: "Project spec",
+                    syntax: "txt",
+                    extensionless: false,
+                },
+                Some(&projects_id),
+                user_id,
+            )
+            .expect("Failed to create project note");
+
+        // Test getting paths by ID
+        let root_path = db
+            .get_note_path_by_id(&root_note_id)
+            .expect("Failed to get root note path")
+            .expect("Root note path should exist");
+        assert_eq!(root_path, "README.md");
+
+        let work_path = db
+            .get_note_path_by_id(&work_note_id)
+            .expect("Failed to get work note path")
+            .expect("Work note path should exist");
+        assert_eq!(work_path, "Work/agenda.org");
+
+        let project_path = db
+            .get_note_path_by_id(&project_note_id)
+            .expect("Failed to get project note path")
+            .expect("Project note path should exist");
+        assert_eq!(project_path, "Work/Projects/specification.txt");
+
+        // Test getting IDs by path
+        let root_id_resolved = db
+            .get_note_id_by_path("README.md")
+            .expect("Failed to resolve root note ID")
+            .expect("Root note ID should exist");
+        assert_eq!(root_id_resolved, root_note_id);
+
+        let work_id_resolved = db
+            .get_note_id_by_path("Work/agenda.org")
+            .expect("Failed to resolve work note ID")
+            .expect("Work note ID should exist");
+        assert_eq!(work_id_resolved, work_note_id);
+
+        let project_id_resolved = db
+            .get_note_id_by_path("Work/Projects/specification.txt")
+            .expect("Failed to resolve project note ID")
+            .expect("Project note ID should exist");
+        assert_eq!(project_id_resolved, project_note_id);
+
+        // Test non-existent paths/IDs
+        let non_existent_path = db
+            .get_note_path_by_id("non_existent_note")
+            .expect("Failed to query non-existent note path");
+        assert!(non_existent_path.is_none());
+
+        let non_existent_id = db
+            .get_note_id_by_path("Non/Existent/note.md")
+            .expect("Failed to query non-existent note ID");
+        assert!(non_existent_id.is_none());
+    }
+
+    #[test]
+    fn test_path_resolution_edge_cases() {
+        let db = setup_test_database();
+        let user_id = "edge_case_user";
+
+        // Test folder with special characters in name
+        let special_id = db
+            .create_folder("Folder-With_Special.Chars", None, user_id)
+            .expect("Failed to create folder with special chars");
+
+        let special_path = db
+            .get_folder_path_by_id(&special_id, user_id)
+            .expect("Failed to get special folder path")
+            .expect("Special folder path should exist");
+        assert_eq!(special_path, "Folder-With_Special.Chars");
+
+        let special_id_resolved = db
+            .get_folder_id_by_path("Folder-With_Special.Chars", user_id)
+            .expect("Failed to resolve special folder ID")
+            .expect("Special folder ID should exist");
+        assert_eq!(special_id_resolved, special_id);
+
+        // Test note with different syntax extensions
+        let py_note_id = db
+            .create_note(
+                "python_script",
+                NoteContent {
+                    title: "script",
+                    abstract_text: None,
+                    content: "print('hello')",
+                    syntax: "py",
+                    extensionless: false,
+                },
+                None,
+                user_id,
+            )
+            .expect("Failed to create Python note");
+
+        let py_path = db
+            .get_note_path_by_id(&py_note_id)
+            .expect("Failed to get Python note path")
+            .expect("Python note path should exist");
+        assert_eq!(py_path, "script.py");
+
+        let py_id_resolved = db
+            .get_note_id_by_path("script.py")
+            .expect("Failed to resolve Python note ID")
+            .expect("Python note ID should exist");
+        assert_eq!(py_id_resolved, py_note_id);
+
+        // Test deeply nested structure (5 levels)
+        let mut current_parent = None;
+        let mut expected_path = String::new();
+
+        for i in 1..=5 {
+            let folder_name = format!("Level{i}");
+            let folder_id = db
+                .create_folder(&folder_name, current_parent.as_deref(), user_id)
+                .expect("Failed to create nested folder");
+
+            if expected_path.is_empty() {
+                expected_path = folder_name.clone();
+            } else {
+                expected_path = format!("{expected_path}/{folder_name}");
+            }
+
+            let resolved_path = db
+                .get_folder_path_by_id(&folder_id, user_id)
+                .expect("Failed to get nested folder path")
+                .expect("Nested folder path should exist");
+            assert_eq!(resolved_path, expected_path);
+
+            current_parent = Some(folder_id);
+        }
+
+        // Create note in deeply nested folder
+        let deep_note_id = db
+            .create_note(
+                "deep_note",
+                NoteContent {
+                    title: "deep",
+                    abstract_text: None,
+                    content: "Deep content",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                current_parent.as_deref(),
+                user_id,
+            )
+            .expect("Failed to create deep note");
+
+        let deep_path = db
+            .get_note_path_by_id(&deep_note_id)
+            .expect("Failed to get deep note path")
+            .expect("Deep note path should exist");
+        assert_eq!(deep_path, "Level1/Level2/Level3/Level4/Level5/deep.md");
+    }
+
+    #[test]
+    fn test_get_folder_contents_recursive() {
+        let db = setup_test_database();
+        let user_id = "recursive_test_user";
+
+        // Create nested folder structure:
+        // Root/
+        //   ├── Documents/
+        //   │   ├── Projects/
+        //   │   │   ├── project1.md
+        //   │   │   └── SubProjects/
+        //   │   │       └── subproject.txt
+        //   │   └── notes.md
+        //   ├── Work/
+        //   │   └── agenda.org
+        //   └── readme.md
+
+        let root_id = db
+            .create_folder("Root", None, user_id)
+            .expect("Failed to create Root folder");
+        let docs_id = db
+            .create_folder("Documents", Some(&root_id), user_id)
+            .expect("Failed to create Documents folder");
+        let projects_id = db
+            .create_folder("Projects", Some(&docs_id), user_id)
+            .expect("Failed to create Projects folder");
+        let subprojects_id = db
+            .create_folder("SubProjects", Some(&projects_id), user_id)
+            .expect("Failed to create SubProjects folder");
+        let work_id = db
+            .create_folder("Work", Some(&root_id), user_id)
+            .expect("Failed to create Work folder");
+
+        // Create notes
+        db.create_note(
+            "readme",
+            NoteContent {
+                title: "readme",
+                abstract_text: None,
+                content: "Root readme",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&root_id),
+            user_id,
+        )
+        .expect("Failed to create root readme");
+        db.create_note(
+            "notes",
+            NoteContent {
+                title: "notes",
+                abstract_text: None,
+                content: "Doc notes",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&docs_id),
+            user_id,
+        )
+        .expect("Failed to create docs notes");
+        db.create_note(
+            "project1",
+            NoteContent {
+                title: "project1",
+                abstract_text: None,
+                content: "Project content",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&projects_id),
+            user_id,
+        )
+        .expect("Failed to create project1");
+        db.create_note(
+            "subproject",
+            NoteContent {
+                title: "subproject",
+                abstract_text: None,
+                content: "Sub content",
+                syntax: "txt",
+                extensionless: false,
+            },
+            Some(&subprojects_id),
+            user_id,
+        )
+        .expect("Failed to create subproject");
+        db.create_note(
+            "agenda",
+            NoteContent {
+                title: "agenda",
+                abstract_text: None,
+                content: "Work agenda",
+                syntax: "org",
+                extensionless: false,
+            },
+            Some(&work_id),
+            user_id,
+        )
+        .expect("Failed to create work agenda");
+
+        // Test getting all contents under root
+        let contents = db
+            .get_folder_contents_recursive(&root_id, user_id)
+            .expect("Failed to get recursive contents");
+
+        // Extract paths for easier testing
+        let paths: Vec<String> = contents
+            .iter()
+            .map(|item| match item {
+                FileType::Directory { path } => path.clone(),
+                FileType::File { path } => path.clone(),
+            })
+            .collect();
 
-        let folder_id = db
-            .create_folder("Test Timezone", None, user_id)
-            .expect("Failed to create folder for timezone test");
+        // Check that we have all expected items
+        assert!(paths.contains(&"Documents".to_string()));
+        assert!(paths.contains(&"Documents/Projects".to_string()));
+        assert!(paths.contains(&"Documents/Projects/SubProjects".to_string()));
+        assert!(paths.contains(&"Work".to_string()));
+        assert!(paths.contains(&"readme.md".to_string()));
+        assert!(paths.contains(&"Documents/notes.md".to_string()));
+        assert!(paths.contains(&"Documents/Projects/project1.md".to_string()));
+        assert!(paths.contains(&"Documents/Projects/SubProjects/subproject.txt".to_string()));
+        assert!(paths.contains(&"Work/agenda.org".to_string()));
 
-        let folder = db
-            .get_folder_by_id(&folder_id, user_id)
-            .expect("Failed to query folder")
-            .expect("Folder not found");
+        // Check types are correct
+        let directories: Vec<&String> = contents
+            .iter()
+            .filter_map(|item| match item {
+                FileType::Directory { path } => Some(path),
+                FileType::File { .. } => None,
+            })
+            .collect();
 
-        // The timestamps should be stored and retrieved properly
-        // Note: The actual timezone conversion happens during storage
-        assert!(!folder.created_at.to_string().is_empty());
-        assert!(!folder.updated_at.to_string().is_empty());
-        assert_eq!(folder.created_at, folder.updated_at);
-    }
+        let files: Vec<&String> = contents
+            .iter()
+            .filter_map(|item| match item {
+                FileType::File { path } => Some(path),
+                FileType::Directory { .. } => None,
+            })
+            .collect();
 
-    #[test]
-    fn test_get_note_by_id() {
-        let db = setup_test_database();
-        let user_id = "test_user_123";
-        let note_id = "test_note_456";
+        assert_eq!(directories.len(), 4); // Documents, Projects, SubProjects, Work
+        assert_eq!(files.len(), 5); // readme, notes, project1, subproject, agenda
 
-        // Test getting non-existent note
-        let non_existent = db
-            .get_note_by_id("non_existent_id")
-            .expect("Failed to query non-existent note");
-        assert!(non_existent.is_none());
+        // Test getting contents under Documents folder only
+        let docs_contents = db
+            .get_folder_contents_recursive(&docs_id, user_id)
+            .expect("Failed to get docs contents");
 
-        // Create a note directly in database for testing
-        db.connection.execute(
-            "INSERT INTO notes (id, title, abstract, content, syntax, parent_id, user_id, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
-            params![note_id, "Test Note", "Test abstract", "# Test Content", "md", None::<String>, user_id, "2023-01-01 12:00:00"]
-        ).expect("Failed to insert test note");
+        let docs_paths: Vec<String> = docs_contents
+            .iter()
+            .map(|item| match item {
+                FileType::Directory { path } => path.clone(),
+                FileType::File { path } => path.clone(),
+            })
+            .collect();
 
-        // Test getting existing note
-        let note = db
-            .get_note_by_id(note_id)
-            .expect("Failed to query note")
-            .expect("Note not found");
+        assert!(docs_paths.contains(&"Projects".to_string()));
+        assert!(docs_paths.contains(&"Projects/SubProjects".to_string()));
+        assert!(docs_paths.contains(&"notes.md".to_string()));
+        assert!(docs_paths.contains(&"Projects/project1.md".to_string()));
+        assert!(docs_paths.contains(&"Projects/SubProjects/subproject.txt".to_string()));
 
-        assert_eq!(note.id, note_id);
-        assert_eq!(note.title, "Test Note");
-        assert_eq!(note.abstract_text, Some("Test abstract".to_string()));
-        assert_eq!(note.content, "# Test Content");
-        assert_eq!(note.syntax, "md");
-        assert_eq!(note.parent_id, None);
-        assert_eq!(note.user_id, user_id);
+        // Should not contain Work or root items
+        assert!(!docs_paths.contains(&"Work".to_string()));
+        assert!(!docs_paths.contains(&"readme.md".to_string()));
+        assert!(!docs_paths.contains(&"Work/agenda.org".to_string()));
     }
 
     #[test]
-    fn test_create_note() {
+    fn test_get_folder_contents_recursive_empty_folder() {
         let db = setup_test_database();
-        let user_id = "test_user_456";
-
-        // Create note without parent
-        let note_id = db
-            .create_note(
-                "note_1",
-                NoteContent {
-                    title: "My First Note",
-                    abstract_text: Some("An abstract"),
-                    content: "# Hello World",
-                    syntax: "md",
-                },
-                None,
-                user_id,
-            )
-            .expect("Failed to create note");
-        assert_eq!(note_id, "note_1");
-
-        // Verify note was created
-        let note = db
-            .get_note_by_id("note_1")
-            .expect("Failed to query created note")
-            .expect("Created note not found");
-
-        assert_eq!(note.title, "My First Note");
-        assert_eq!(note.abstract_text, Some("An abstract".to_string()));
-        assert_eq!(note.content, "# Hello World");
-        assert_eq!(note.syntax, "md");
-        assert_eq!(note.user_id, user_id);
-
-        // Create note with parent folder
-        let folder_id = db
-            .create_folder("Notes Folder", None, user_id)
-            .expect("Failed to create folder");
+        let user_id = "empty_test_user";
 
-        let note_id2 = db
-            .create_note(
-                "note_2",
-                NoteContent {
-                    title: "Second Note",
-                    abstract_text: None,
-                    content: "Some content",
-                    syntax: "txt",
-                },
-                Some(&folder_id),
-                user_id,
-            )
-            .expect("Failed to create note with parent");
+        // Create empty folder
+        let empty_id = db
+            .create_folder("Empty", None, user_id)
+            .expect("Failed to create empty folder");
 
-        let note2 = db
-            .get_note_by_id(&note_id2)
-            .expect("Failed to query second note")
-            .expect("Second note not found");
+        let contents = db
+            .get_folder_contents_recursive(&empty_id, user_id)
+            .expect("Failed to get empty folder contents");
 
-        assert_eq!(note2.parent_id, Some(folder_id));
-        assert_eq!(note2.abstract_text, None);
-        assert_eq!(note2.syntax, "txt");
+        assert_eq!(contents.len(), 0);
     }
 
     #[test]
-    fn test_update_note_content() {
+    fn test_get_folder_contents_recursive_user_filtering() {
         let db = setup_test_database();
-        let user_id = "test_user_789";
+        let user1 = "user1";
+        let user2 = "user2";
 
-        let note_id = db
-            .create_note(
-                "update_test",
-                NoteContent {
-                    title: "Original Title",
-                    abstract_text: Some("Original abstract"),
-                    content: "Original content",
-                    syntax: "md",
-                },
-                None,
-                user_id,
-            )
-            .expect("Failed to create note for update test");
+        // Create separate folder structures for each user (folders are now user-specific)
+        let user1_folder_id = db
+            .create_folder("User1Folder", None, user1)
+            .expect("Failed to create user1 folder");
+        let user2_folder_id = db
+            .create_folder("User2Folder", None, user2)
+            .expect("Failed to create user2 folder");
 
-        // Test updating all content fields
-        let updated = db
-            .update_note(
-                &note_id,
-                "Updated Title",
-                Some("Updated abstract"),
-                "Updated content",
-                "rst",
-            )
-            .expect("Failed to update note");
-        assert!(updated);
+        // Create notes for each user in their own folders
+        db.create_note(
+            "note1",
+            NoteContent {
+                title: "note1",
+                abstract_text: None,
+                content: "User 1 content",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&user1_folder_id),
+            user1,
+        )
+        .expect("Failed to create user1 note");
+        db.create_note(
+            "note2",
+            NoteContent {
+                title: "note2",
+                abstract_text: None,
+                content: "User 2 content",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&user2_folder_id),
+            user2,
+        )
+        .expect("Failed to create user2 note");
 
-        let note = db
-            .get_note_by_id(&note_id)
-            .expect("Failed to query updated note")
-            .expect("Updated note not found");
+        // Test that each user only sees contents of their own folders
+        let user1_contents = db
+            .get_folder_contents_recursive(&user1_folder_id, user1)
+            .expect("Failed to get user1 contents");
 
-        assert_eq!(note.title, "Updated Title");
-        assert_eq!(note.abstract_text, Some("Updated abstract".to_string()));
-        assert_eq!(note.content, "Updated content");
-        assert_eq!(note.syntax, "rst");
+        // User2 cannot access user1's folder (folders are user-specific)
+        let user2_accessing_user1_folder = db
+            .get_folder_contents_recursive(&user1_folder_id, user2)
+            .expect("Failed to get user2 attempting user1 folder");
 
-        // Test updating with None abstract
-        let updated2 = db
-            .update_note(&note_id, "Final Title", None, "Final content", "md")
-            .expect("Failed to update note with None abstract");
-        assert!(updated2);
+        let user2_contents = db
+            .get_folder_contents_recursive(&user2_folder_id, user2)
+            .expect("Failed to get user2 contents");
 
-        let note2 = db
-            .get_note_by_id(&note_id)
-            .expect("Failed to query updated note")
-            .expect("Updated note not found");
+        // User1 sees their own note
+        assert_eq!(user1_contents.len(), 1);
+        match &user1_contents[0] {
+            FileType::File { path } => assert_eq!(path, "note1.md"),
+            _ => panic!("Expected file, got directory"),
+        }
 
-        assert_eq!(note2.abstract_text, None);
+        // User2 cannot see user1's folder contents (folder ownership check prevents it)
+        assert_eq!(user2_accessing_user1_folder.len(), 0);
 
-        // Test updating non-existent note
-        let not_updated = db
-            .update_note("non_existent", "Title", None, "Content", "md")
-            .expect("Failed to update non-existent note");
-        assert!(!not_updated);
+        // User2 sees their own note in their own folder
+        assert_eq!(user2_contents.len(), 1);
+        match &user2_contents[0] {
+            FileType::File { path } => assert_eq!(path, "note2.md"),
+            _ => panic!("Expected file, got directory"),
+        }
     }
 
     #[test]
-    fn test_update_note_parent() {
+    fn test_get_folder_contents_recursive_single_level() {
         let db = setup_test_database();
-        let user_id = "test_user_parent";
-
-        let note_id = db
-            .create_note(
-                "parent_test",
-                NoteContent {
-                    title: "Test Note",
-                    abstract_text: None,
-                    content: "Content",
-                    syntax: "md",
-                },
-                None,
-                user_id,
-            )
-            .expect("Failed to create note for parent test");
-
-        // Create folders
-        let folder1_id = db
-            .create_folder("Folder 1", None, user_id)
-            .expect("Failed to create folder 1");
-        let folder2_id = db
-            .create_folder("Folder 2", None, user_id)
-            .expect("Failed to create folder 2");
-
-        // Move note to folder 1
-        let updated = db
-            .update_note_parent(&note_id, Some(&folder1_id))
-            .expect("Failed to update note parent");
-        assert!(updated);
+        let user_id = "single_level_user";
 
-        let note = db
-            .get_note_by_id(&note_id)
-            .expect("Failed to query note")
-            .expect("Note not found");
-        assert_eq!(note.parent_id, Some(folder1_id.clone()));
+        // Create folder with only direct children
+        let folder_id = db
+            .create_folder("SingleLevel", None, user_id)
+            .expect("Failed to create folder");
+        let _child_folder_id = db
+            .create_folder("ChildFolder", Some(&folder_id), user_id)
+            .expect("Failed to create child folder");
 
-        // Move note to folder 2
-        let updated2 = db
-            .update_note_parent(&note_id, Some(&folder2_id))
-            .expect("Failed to update note parent to folder 2");
-        assert!(updated2);
+        db.create_note(
+            "file1",
+            NoteContent {
+                title: "file1",
+                abstract_text: None,
+                content: "Content 1",
+                syntax: "txt",
+                extensionless: false,
+            },
+            Some(&folder_id),
+            user_id,
+        )
+        .expect("Failed to create file1");
+        db.create_note(
+            "file2",
+            NoteContent {
+                title: "file2",
+                abstract_text: None,
+                content: "Content 2",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&folder_id),
+            user_id,
+        )
+        .expect("Failed to create file2");
 
-        let note2 = db
-            .get_note_by_id(&note_id)
-            .expect("Failed to query note")
-            .expect("Note not found");
-        assert_eq!(note2.parent_id, Some(folder2_id));
+        let contents = db
+            .get_folder_contents_recursive(&folder_id, user_id)
+            .expect("Failed to get single level contents");
 
-        // Move note to root (no parent)
-        let updated3 = db
-            .update_note_parent(&note_id, None)
-            .expect("Failed to update note parent to None");
-        assert!(updated3);
+        assert_eq!(contents.len(), 3); // 1 folder + 2 files
 
-        let note3 = db
-            .get_note_by_id(&note_id)
-            .expect("Failed to query note")
-            .expect("Note not found");
-        assert_eq!(note3.parent_id, None);
+        let paths: Vec<String> = contents
+            .iter()
+            .map(|item| match item {
+                FileType::Directory { path } => path.clone(),
+                FileType::File { path } => path.clone(),
+            })
+            .collect();
 
-        // Test updating non-existent note
-        let not_updated = db
-            .update_note_parent("non_existent", Some(&folder1_id))
-            .expect("Failed to update non-existent note parent");
-        assert!(!not_updated);
+        assert!(paths.contains(&"ChildFolder".to_string()));
+        assert!(paths.contains(&"file1.txt".to_string()));
+        assert!(paths.contains(&"file2.md".to_string()));
     }
 
     #[test]
-    fn test_delete_note() {
+    fn test_has_children_per_user() {
         let db = setup_test_database();
-        let user_id = "test_user_delete";
+        let user_id = "count_test_user";
+        let other_user = "other_count_user";
 
-        let note_id = db
-            .create_note(
-                "delete_test",
-                NoteContent {
-                    title: "Delete Me",
-                    abstract_text: Some("Abstract"),
-                    content: "Content to delete",
-                    syntax: "md",
-                },
-                None,
-                user_id,
-            )
-            .expect("Failed to create note for delete test");
+        // Create folder structure
+        let parent_folder_id = db
+            .create_folder("Parent", None, user_id)
+            .expect("Failed to create parent folder");
+        let _child_folder1_id = db
+            .create_folder("Child1", Some(&parent_folder_id), user_id)
+            .expect("Failed to create child folder 1");
+        let _child_folder2_id = db
+            .create_folder("Child2", Some(&parent_folder_id), user_id)
+            .expect("Failed to create child folder 2");
 
-        // Verify note exists
-        let note = db
-            .get_note_by_id(&note_id)
-            .expect("Failed to query note before delete")
-            .expect("Note should exist before delete");
-        assert_eq!(note.title, "Delete Me");
+        // Create notes in parent folder for different users
+        db.create_note(
+            "note1",
+            NoteContent {
+                title: "Note 1",
+                abstract_text: None,
+                content: "Content 1",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&parent_folder_id),
+            user_id,
+        )
+        .expect("Failed to create note 1");
+        db.create_note(
+            "note2",
+            NoteContent {
+                title: "Note 2",
+                abstract_text: None,
+                content: "Content 2",
+                syntax: "txt",
+                extensionless: false,
+            },
+            Some(&parent_folder_id),
+            user_id,
+        )
+        .expect("Failed to create note 2");
+        db.create_note(
+            "note3",
+            NoteContent {
+                title: "Note 3",
+                abstract_text: None,
+                content: "Content 3",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&parent_folder_id),
+            other_user,
+        )
+        .expect("Failed to create note 3 for other user");
 
-        // Delete note
-        let deleted = db.delete_note(&note_id).expect("Failed to delete note");
-        assert!(deleted);
+        // Create notes at root level
+        db.create_note(
+            "root1",
+            NoteContent {
+                title: "Root 1",
+                abstract_text: None,
+                content: "Root content",
+                syntax: "md",
+                extensionless: false,
+            },
+            None,
+            user_id,
+        )
+        .expect("Failed to create root note 1");
+        db.create_note(
+            "root2",
+            NoteContent {
+                title: "Root 2",
+                abstract_text: None,
+                content: "Root content",
+                syntax: "org",
+                extensionless: false,
+            },
+            None,
+            other_user,
+        )
+        .expect("Failed to create root note 2 for other user");
 
-        // Verify note is gone
-        let deleted_note = db
-            .get_note_by_id(&note_id)
-            .expect("Failed to query deleted note");
-        assert!(deleted_note.is_none());
+        // Child1, Child2, note1 and note2
+        assert!(
+            db.has_children(Some(&parent_folder_id), user_id)
+                .expect("Failed to check children for specific user")
+        );
 
-        // Verify history was created (check if history table has the note)
-        let mut stmt = db
-            .connection
-            .prepare("SELECT COUNT(*) FROM notes_history WHERE id = ?1 AND log_action = 'DELETE'")
-            .expect("Failed to prepare history query");
-        let count: i64 = stmt
-            .query_row([&note_id], |row| row.get(0))
-            .expect("Failed to query history count");
-        assert_eq!(count, 1, "Note should be in history after deletion");
+        // Only note3 is other_user's
+        assert!(
+            db.has_children(Some(&parent_folder_id), other_user)
+                .expect("Failed to check children for other user")
+        );
 
-        // Test deleting non-existent note
-        let not_deleted = db
-            .delete_note("non_existent")
-            .expect("Failed to delete non-existent note");
-        assert!(!not_deleted);
+        // Test an empty folder
+        let empty_folder_id = db
+            .create_folder("Empty", None, user_id)
+            .expect("Failed to create empty folder");
+        assert!(
+            !db.has_children(Some(&empty_folder_id), user_id)
+                .expect("Failed to check empty folder children")
+        );
+
+        // Test the root level with specific user
+        assert!(
+            db.has_children(None, user_id)
+                .expect("Failed to check root children for specific user")
+        );
+        assert!(
+            !db.has_children(None, "nobody")
+                .expect("Failed to check root children for unknown user")
+        );
     }
 
     #[test]
-    fn test_list_notes_by_parent() {
+    fn test_has_children_edge_cases() {
         let db = setup_test_database();
-        let user_id = "test_user_list";
+        let user_id = "edge_test_user";
+
+        // Test with non-existent folder ID
+        assert!(
+            !db.has_children(Some("non_existent_id"), user_id)
+                .expect("Failed to check non-existent folder")
+        );
 
-        // Create folder
+        // Test with non-existent user ID
         let folder_id = db
-            .create_folder("Test Folder", None, user_id)
-            .expect("Failed to create folder");
-
-        // Create notes in folder
+            .create_folder("Test", None, user_id)
+            .expect("Failed to create test folder");
         db.create_note(
-            "note1",
+            "test_note",
             NoteContent {
-                title: "Note 1",
+                title: "Test",
                 abstract_text: None,
-                content: "Content 1",
+                content: "Content",
                 syntax: "md",
+                extensionless: false,
             },
             Some(&folder_id),
             user_id,
         )
-        .expect("Failed to create note 1");
+        .expect("Failed to create test note");
+
+        // No notes for this user
+        assert!(
+            !db.has_children(Some(&folder_id), "non_existent_user")
+                .expect("Failed to check non-existent user")
+        );
+
+        // Test deeply nested structure
+        let mut current_parent = Some(folder_id.clone());
+        for i in 1..=3 {
+            let child_id = db
+                .create_folder(&format!("Level{i}"), current_parent.as_deref(), user_id)
+                .expect("Failed to create nested folder");
+            current_parent = Some(child_id);
+        }
+
+        // Level1 and test_note are inside the original folder; Level3 is empty
+        assert!(
+            db.has_children(Some(&folder_id), user_id)
+                .expect("Failed to check nested structure")
+        );
+        assert!(
+            !db.has_children(current_parent.as_deref(), user_id)
+                .expect("Failed to check innermost folder")
+        );
+    }
+
+    #[test]
+    fn test_has_children() {
+        let db = setup_test_database();
+        let user_id = "has_children_test_user";
+        let folder_id = db.create_folder("folder", None, user_id).unwrap();
+        assert!(!db.has_children(Some(&folder_id), user_id).unwrap());
+
+        // Index notes are the folder's own content
+        create_test_note(&db, INDEX_TITLE, "", Some(&folder_id), user_id);
+        assert!(!db.has_children(Some(&folder_id), user_id).unwrap());
+
+        let note_id = create_test_note(&db, "child", "", Some(&folder_id), user_id);
+        assert!(db.has_children(Some(&folder_id), user_id).unwrap());
+        assert!(!db.has_children(Some(&folder_id), "other_user").unwrap());
+        db.delete_note(&note_id).unwrap();
+        assert!(!db.has_children(Some(&folder_id), user_id).unwrap());
+
+        db.create_folder("sub", Some(&folder_id), user_id).unwrap();
+        assert!(db.has_children(Some(&folder_id), user_id).unwrap());
+        assert!(db.has_children(None, user_id).unwrap());
+    }
+
+    #[test]
+    fn test_unique_titles_per_directory() {
+        let db = setup_test_database();
+        let user_id = "unique_test_user";
+
+        let folder_id = db
+            .create_folder("Projects", None, user_id)
+            .expect("Failed to create folder");
+
+        // Duplicate folder names are rejected at the root and inside folders
+        let err = db
+            .create_folder("Projects", None, user_id)
+            .expect_err("Duplicate root folder should be rejected");
+        assert!(Database::is_unique_violation(&err));
+
+        db.create_folder("Child", Some(&folder_id), user_id)
+            .expect("Failed to create child folder");
+        let err = db
+            .create_folder("Child", Some(&folder_id), user_id)
+            .expect_err("Duplicate child folder should be rejected");
+        assert!(Database::is_unique_violation(&err));
+
+        // Other users may reuse the same root folder name
+        db.create_folder("Projects", None, "other_user")
+            .expect("Other user should be able to reuse the name");
+
+        // Root notes are unique per (title, syntax)
+        let note = NoteContent {
+            title: "readme",
+            abstract_text: None,
+            content: "",
+            syntax: "md",
+            extensionless: false,
+        };
+        db.create_note("n1", note.clone(), None, user_id)
+            .expect("Failed to create root note");
+        let err = db
+            .create_note("n2", note.clone(), None, user_id)
+            .expect_err("Duplicate root note should be rejected");
+        assert!(Database::is_unique_violation(&err));
+
+        // Same title with a different syntax is a different file name
         db.create_note(
-            "note2",
+            "n3",
             NoteContent {
-                title: "Note 2",
-                abstract_text: None,
-                content: "Content 2",
                 syntax: "txt",
+                extensionless: false,
+                ..note.clone()
             },
-            Some(&folder_id),
+            None,
             user_id,
         )
-        .expect("Failed to create note 2");
+        .expect("Different syntax should not collide");
 
-        // Create note at root level
+        // Lookups by title
+        assert_eq!(
+            db.find_folder_by_title(None, "Projects", user_id).unwrap(),
+            Some(folder_id.clone())
+        );
+        assert_eq!(
+            db.find_note_by_title(None, "readme", "md", false, user_id)
+                .unwrap(),
+            Some("n1".to_string())
+        );
+        assert_eq!(
+            db.find_note_by_title(None, "readme", "org", false, user_id)
+                .unwrap(),
+            None
+        );
+
+        // Auto-suffixing picks the first free title
+        assert_eq!(
+            db.unique_folder_title(None, "Projects", user_id).unwrap(),
+            "Projects (1)"
+        );
+        assert_eq!(
+            db.unique_folder_title(None, "Fresh", user_id).unwrap(),
+            "Fresh"
+        );
         db.create_note(
-            "note3",
+            "n4",
             NoteContent {
-                title: "Root Note",
-                abstract_text: None,
-                content: "Root content",
-                syntax: "md",
+                title: "readme (1)",
+                ..note.clone()
             },
             None,
             user_id,
         )
-        .expect("Failed to create root note");
-
-        // Create note for different user in same folder
-        db.create_note(
-            "note4",
-            NoteContent {
-                title: "Other User",
-                abstract_text: None,
-                content: "Content",
-                syntax: "md",
-            },
-            Some(&folder_id),
-            "other_user",
-        )
-        .expect("Failed to create note for other user");
-
-        // Test listing notes in folder for specific user
-        let folder_notes = db
-            .list_notes_by_parent(Some(&folder_id), user_id)
-            .expect("Failed to list notes in folder");
-        assert_eq!(folder_notes.len(), 2);
-
-        let titles: Vec<&str> = folder_notes.iter().map(|n| n.title.as_str()).collect();
-        assert!(titles.contains(&"Note 1"));
-        assert!(titles.contains(&"Note 2"));
-        assert!(!titles.contains(&"Other User"));
-
-        // Test listing root notes for user
-        let root_notes = db
-            .list_notes_by_parent(None, user_id)
-            .expect("Failed to list root notes");
-        assert_eq!(root_notes.len(), 1);
-        assert_eq!(root_notes[0].title, "Root Note");
-
-        // Test listing notes for user with no notes in folder
-        let empty_folder_id = db
-            .create_folder("Empty Folder", None, user_id)
-            .expect("Failed to create empty folder");
-        let empty_notes = db
-            .list_notes_by_parent(Some(&empty_folder_id), user_id)
-            .expect("Failed to list notes in empty folder");
-        assert_eq!(empty_notes.len(), 0);
+        .expect("Failed to create suffixed note");
+        assert_eq!(
+            db.unique_note_title(None, "readme", "md", false, user_id)
+                .unwrap(),
+            "readme (2)"
+        );
     }
-
     #[test]
-    fn test_fts_triggers() {
+    fn test_path_resolution_with_slash_in_title() {
         let db = setup_test_database();
-        let user_id = "fts_test_user";
+        let user_id = "slash_test_user";
 
-        // Create a note
+        // Titles written by other clients may contain '/'; paths show it as U+2044
+        let folder_id = db
+            .create_folder("2024/25", None, user_id)
+            .expect("Failed to create folder with slash");
         let note_id = db
             .create_note(
-                "fts_test",
+                "slash_note",
                 NoteContent {
-                    title: "Searchable Note",
-                    abstract_text: Some("This is searchable"),
-                    content: "Content with keywords",
+                    title: "either/or",
+                    abstract_text: None,
+                    content: "",
                     syntax: "md",
+                    extensionless: false,
                 },
-                None,
+                Some(&folder_id),
                 user_id,
             )
-            .expect("Failed to create note for FTS test");
+            .expect("Failed to create note with slash");
 
-        // Verify note was added to FTS table
-        let mut stmt = db
-            .connection
-            .prepare("SELECT COUNT(*) FROM notes_fts WHERE id = ?1")
-            .expect("Failed to prepare FTS query");
-        let count: i64 = stmt
-            .query_row([&note_id], |row| row.get(0))
-            .expect("Failed to query FTS count");
-        assert_eq!(count, 1, "Note should be in FTS table after creation");
+        assert_eq!(
+            db.get_folder_path_by_id(&folder_id, user_id).unwrap(),
+            Some("2024\u{2044}25".to_string())
+        );
+        assert_eq!(
+            db.get_note_path_by_id(&note_id).unwrap(),
+            Some("2024\u{2044}25/either\u{2044}or.md".to_string())
+        );
+        assert_eq!(
+            db.get_note_id_by_path("2024\u{2044}25/either\u{2044}or.md")
+                .unwrap(),
+            Some(note_id)
+        );
 
-        // Update the note and verify FTS is updated
-        db.update_note(
-            &note_id,
-            "Updated Searchable",
-            Some("Updated abstract"),
-            "Updated content",
-            "md",
+        // The raw slashed path must not resolve to anything
+        assert_eq!(db.get_folder_id_by_path("2024/25", user_id).unwrap(), None);
+    }
+    #[test]
+    fn test_case_insensitive_lookups() {
+        let mut db = setup_test_database();
+        let user_id = "case_test_user";
+
+        let folder_id = db
+            .create_folder("Projects", None, user_id)
+            .expect("Failed to create folder");
+        db.create_note(
+            "readme",
+            NoteContent {
+                title: "README",
+                abstract_text: None,
+                content: "",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&folder_id),
+            user_id,
         )
-        .expect("Failed to update note");
+        .expect("Failed to create note");
 
-        let mut stmt = db
-            .connection
-            .prepare("SELECT title FROM notes_fts WHERE id = ?1")
-            .expect("Failed to prepare FTS query");
-        let title: String = stmt
-            .query_row([&note_id], |row| row.get(0))
-            .expect("Failed to query FTS title");
+        // Exact matching by default
+        assert_eq!(db.get_folder_id_by_path("projects", user_id).unwrap(), None);
+        assert_eq!(db.get_note_id_by_path("projects/readme.md").unwrap(), None);
+
+        db.case_insensitive = true;
         assert_eq!(
-            title, "Updated Searchable",
-            "FTS should reflect updated title"
+            db.get_folder_id_by_path("projects", user_id).unwrap(),
+            Some(folder_id.clone())
+        );
+        assert_eq!(
+            db.get_note_id_by_path("PROJECTS/readme.MD").unwrap(),
+            Some("readme".to_string())
+        );
+        assert_eq!(
+            db.find_note_by_title(Some(&folder_id), "Readme", "md", false, user_id)
+                .unwrap(),
+            Some("readme".to_string())
         );
 
-        // Delete the note and verify it's removed from FTS
-        db.delete_note(&note_id).expect("Failed to delete note");
-
-        let mut stmt = db
-            .connection
-            .prepare("SELECT COUNT(*) FROM notes_fts WHERE id = ?1")
-            .expect("Failed to prepare FTS query");
-        let count: i64 = stmt
-            .query_row([&note_id], |row| row.get(0))
-            .expect("Failed to query FTS count");
+        // Stored case is preserved
         assert_eq!(
-            count, 0,
-            "Note should be removed from FTS table after deletion"
+            db.get_note_path_by_id("readme").unwrap(),
+            Some("Projects/README.md".to_string())
         );
     }
-
     #[test]
-    fn test_folder_path_resolution() {
-        let db = setup_test_database();
-        let user_id = "test_user";
+    fn test_normalized_lookups() {
+        let mut db = setup_test_database();
+        let user_id = "nfc_test_user";
 
-        // Create nested folder structure: Documents/Projects/MyProject
-        let docs_id = db
-            .create_folder("Documents", None, user_id)
-            .expect("Failed to create Documents folder");
-        let projects_id = db
-            .create_folder("Projects", Some(&docs_id), user_id)
-            .expect("Failed to create Projects folder");
-        let myproject_id = db
-            .create_folder("MyProject", Some(&projects_id), user_id)
-            .expect("Failed to create MyProject folder");
+        // Stored decomposed, as a macOS client of the companion app may
+        let folder_id = db
+            .create_folder("Cafe\u{301}", None, user_id)
+            .expect("Failed to create folder");
+        db.create_note(
+            "resume",
+            NoteContent {
+                title: "Re\u{301}sume\u{301}",
+                abstract_text: None,
+                content: "",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&folder_id),
+            user_id,
+        )
+        .expect("Failed to create note");
 
-        // Test getting paths by ID
-        let docs_path = db
-            .get_folder_path_by_id(&docs_id, user_id)
-            .expect("Failed to get Documents path")
-            .expect("Documents path should exist");
-        assert_eq!(docs_path, "Documents");
+        // Exact matching by default
+        assert_eq!(
+            db.get_folder_id_by_path("Caf\u{e9}", user_id).unwrap(),
+            None
+        );
 
-        let projects_path = db
-            .get_folder_path_by_id(&projects_id, user_id)
-            .expect("Failed to get Projects path")
-            .expect("Projects path should exist");
-        assert_eq!(projects_path, "Documents/Projects");
+        db.normalize_names().unwrap();
+        assert_eq!(
+            db.get_folder_id_by_path("Caf\u{e9}", user_id).unwrap(),
+            Some(folder_id.clone())
+        );
+        assert_eq!(
+            db.get_note_id_by_path("Caf\u{e9}/R\u{e9}sum\u{e9}.md")
+                .unwrap(),
+            Some("resume".to_string())
+        );
+        assert_eq!(
+            db.find_note_by_title(Some(&folder_id), "R\u{e9}sum\u{e9}", "md", false, user_id)
+                .unwrap(),
+            Some("resume".to_string())
+        );
 
-        let myproject_path = db
-            .get_folder_path_by_id(&myproject_id, user_id)
-            .expect("Failed to get MyProject path")
-            .expect("MyProject path should exist");
-        assert_eq!(myproject_path, "Documents/Projects/MyProject");
+        // Along with case-insensitive matching
+        db.case_insensitive = true;
+        assert_eq!(
+            db.get_note_id_by_path("CAF\u{e9}/r\u{e9}sum\u{e9}.MD")
+                .unwrap(),
+            Some("resume".to_string())
+        );
 
-        // Test getting IDs by path
-        let docs_id_resolved = db
-            .get_folder_id_by_path("Documents", user_id)
-            .expect("Failed to resolve Documents ID")
-            .expect("Documents ID should exist");
-        assert_eq!(docs_id_resolved, docs_id);
+        // Stored form is preserved
+        assert_eq!(
+            db.get_note_path_by_id("resume").unwrap(),
+            Some("Cafe\u{301}/Re\u{301}sume\u{301}.md".to_string())
+        );
+    }
+    #[test]
+    fn test_extensionless_notes() {
+        let db = setup_test_database();
+        let user_id = "extensionless_test_user";
 
-        let projects_id_resolved = db
-            .get_folder_id_by_path("Documents/Projects", user_id)
-            .expect("Failed to resolve Projects ID")
-            .expect("Projects ID should exist");
-        assert_eq!(projects_id_resolved, projects_id);
+        let folder_id = db
+            .create_folder("project", None, user_id)
+            .expect("Failed to create folder");
+        let license = NoteContent {
+            title: "LICENSE",
+            abstract_text: None,
+            content: "MIT",
+            syntax: "txt",
+            extensionless: true,
+        };
+        db.create_note("license", license.clone(), Some(&folder_id), user_id)
+            .expect("Failed to create extensionless note");
 
-        let myproject_id_resolved = db
-            .get_folder_id_by_path("Documents/Projects/MyProject", user_id)
-            .expect("Failed to resolve MyProject ID")
-            .expect("MyProject ID should exist");
-        assert_eq!(myproject_id_resolved, myproject_id);
+        // The path is the bare title, with no ".txt" appended
+        assert_eq!(
+            db.get_note_path_by_id("license").unwrap(),
+            Some("project/LICENSE".to_string())
+        );
+        assert_eq!(
+            db.get_note_id_by_path("project/LICENSE").unwrap(),
+            Some("license".to_string())
+        );
+        assert_eq!(db.get_note_id_by_path("project/LICENSE.txt").unwrap(), None);
+        assert!(db.get_note_by_id("license").unwrap().unwrap().extensionless);
 
-        // Test non-existent paths/IDs
-        let non_existent_path = db
-            .get_folder_path_by_id("non_existent_id", user_id)
-            .expect("Failed to query non-existent folder path");
-        assert!(non_existent_path.is_none());
+        // LICENSE and LICENSE.txt are different file names and may coexist
+        db.create_note(
+            "license_txt",
+            NoteContent {
+                extensionless: false,
+                ..license.clone()
+            },
+            Some(&folder_id),
+            user_id,
+        )
+        .expect("LICENSE.txt should not collide with LICENSE");
+        assert_eq!(
+            db.find_note_by_title(Some(&folder_id), "LICENSE", "txt", true, user_id)
+                .unwrap(),
+            Some("license".to_string())
+        );
 
-        let non_existent_id = db
-            .get_folder_id_by_path("Non/Existent/Path", user_id)
-            .expect("Failed to query non-existent folder ID");
-        assert!(non_existent_id.is_none());
+        // Renaming to a name with an extension clears the flag
+        db.update_note_name("license", "COPYING", "md", false)
+            .expect("Failed to rename note");
+        assert_eq!(
+            db.get_note_path_by_id("license").unwrap(),
+            Some("project/COPYING.md".to_string())
+        );
     }
-
     #[test]
-    fn test_note_path_resolution() {
+    fn test_syntax_extensions() {
         let db = setup_test_database();
-        let user_id = "path_test_user";
+        let user_id = "syntax_test_user";
 
-        // Create folder structure and notes
-        let work_id = db
-            .create_folder("Work", None, user_id)
-            .expect("Failed to create Work folder");
-        let projects_id = db
-            .create_folder("Projects", Some(&work_id), user_id)
-            .expect("Failed to create Projects folder");
+        let rows = db.list_syntax_extensions().unwrap();
+        assert!(rows.contains(&("markdown".to_string(), "md".to_string(), false)));
+        assert!(rows.contains(&("md".to_string(), "md".to_string(), true)));
 
-        // Create notes at different levels
-        let root_note_id = db
-            .create_note(
-                "root_note",
-                NoteContent {
-                    title: "README",
-                    abstract_text: None,
-                    content: "Root readme content",
-                    syntax: "md",
-                },
-                None,
-                user_id,
-            )
-            .expect("Failed to create root note");
+        // A syntax with a preferred extension is shown with it
+        db.set_syntax_extension("qmd", "quarto").unwrap();
+        db.create_note(
+            "quarto_note",
+            NoteContent {
+                title: "report",
+                abstract_text: None,
+                content: "",
+                syntax: "quarto",
+                extensionless: false,
+            },
+            None,
+            user_id,
+        )
+        .unwrap();
+        assert_eq!(
+            db.get_note_path_by_id("quarto_note").unwrap(),
+            Some("report.qmd".to_string())
+        );
+        assert_eq!(
+            db.get_note_id_by_path("report.qmd").unwrap(),
+            Some("quarto_note".to_string())
+        );
 
-        let work_note_id = db
-            .create_note(
-                "work_note",
-                NoteContent {
-                    title: "agenda",
-                    abstract_text: None,
-                    content: "Work agenda",
-                    syntax: "org",
-                },
-                Some(&work_id),
-                user_id,
+        // Adding another alias keeps the existing preferred extension
+        db.set_syntax_extension("QUARTO", "quarto").unwrap();
+        assert_eq!(
+            db.get_note_path_by_id("quarto_note").unwrap(),
+            Some("report.qmd".to_string())
+        );
+        assert!(db.list_syntax_extensions().unwrap().contains(&(
+            "quarto".to_string(),
+            "quarto".to_string(),
+            false
+        )));
+    }
+    #[test]
+    fn test_note_tags() {
+        let db = setup_test_database();
+        let user_id = "tag_test_user";
+
+        db.create_note(
+            "tagged",
+            NoteContent {
+                title: "tagged",
+                abstract_text: None,
+                content: "",
+                syntax: "md",
+                extensionless: false,
+            },
+            None,
+            user_id,
+        )
+        .expect("Failed to create note");
+        assert!(db.get_note_tags("tagged").unwrap().is_empty());
+
+        let tags = vec!["rust".to_string(), "fuse".to_string()];
+        db.set_note_tags("tagged", &tags, user_id)
+            .expect("Failed to set tags");
+        assert_eq!(db.get_note_tags("tagged").unwrap(), vec!["fuse", "rust"]);
+
+        // Replacing the set drops tags that are no longer listed
+        db.set_note_tags("tagged", &["rust".to_string()], user_id)
+            .expect("Failed to replace tags");
+        assert_eq!(db.get_note_tags("tagged").unwrap(), vec!["rust"]);
+
+        // Deleting the note removes its tag links
+        db.delete_note("tagged").expect("Failed to delete note");
+        db.purge_deleted(None)
+            .expect("Failed to purge deleted notes");
+        let links: i64 = db
+            .connection
+            .query_row("SELECT COUNT(*) FROM note_tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(links, 0);
+    }
+    #[test]
+    fn test_list_recent_notes() {
+        let db = setup_test_database();
+        let user_id = "recent_test_user";
+
+        for (id, updated_at) in [
+            ("old", "2024-01-01 09:00:00"),
+            ("newest", "2024-03-01 09:00:00"),
+            ("middle", "2024-02-01 09:00:00"),
+        ] {
+            db.connection
+                .execute(
+                    "INSERT INTO notes (id, title, content, user_id, created_at, updated_at)
+                     VALUES (?1, ?1, '', ?2, ?3, ?3)",
+                    params![id, user_id, updated_at],
+                )
+                .expect("Failed to insert note");
+        }
+        db.connection
+            .execute(
+                "INSERT INTO notes (id, title, content, user_id, created_at, updated_at)
+                 VALUES ('other', 'other', '', 'someone_else', '2024-04-01 09:00:00', '2024-04-01 09:00:00')",
+                [],
             )
-            .expect("Failed to create work note");
+            .expect("Failed to insert note");
 
-        let project_note_id = db
-            .create_note(
-                "project_note",
+        let ids: Vec<String> = db
+            .list_recent_notes(user_id, 2)
+            .expect("Failed to list recent notes")
+            .into_iter()
+            .map(|note| note.id)
+            .collect();
+        assert_eq!(ids, vec!["newest", "middle"]);
+    }
+
+    #[test]
+    fn test_find_template_content() {
+        let db = setup_test_database();
+        let user_id = "template_test_user";
+        let folder_id = db
+            .create_folder(".templates", None, user_id)
+            .expect("Failed to create folder");
+
+        for (title, syntax, content) in [
+            ("meeting", "md", "# Meeting\n"),
+            ("default", "md", "# Untitled\n"),
+            ("article", "rst", "Title\n=====\n"),
+        ] {
+            let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+            db.create_note(
+                &id,
                 NoteContent {
-                    title: "specification",
+                    title,
                     abstract_text: None,
-                    content: "Project spec",
-                    syntax: "txt",
+                    content,
+                    syntax,
+                    extensionless: false,
                 },
-                Some(&projects_id),
+                Some(&folder_id),
                 user_id,
             )
-            .expect("Failed to create project note");
-
-        // Test getting paths by ID
-        let root_path = db
-            .get_note_path_by_id(&root_note_id)
-            .expect("Failed to get root note path")
-            .expect("Root note path should exist");
-        assert_eq!(root_path, "README.md");
-
-        let work_path = db
-            .get_note_path_by_id(&work_note_id)
-            .expect("Failed to get work note path")
-            .expect("Work note path should exist");
-        assert_eq!(work_path, "Work/agenda.org");
-
-        let project_path = db
-            .get_note_path_by_id(&project_note_id)
-            .expect("Failed to get project note path")
-            .expect("Project note path should exist");
-        assert_eq!(project_path, "Work/Projects/specification.txt");
-
-        // Test getting IDs by path
-        let root_id_resolved = db
-            .get_note_id_by_path("README.md")
-            .expect("Failed to resolve root note ID")
-            .expect("Root note ID should exist");
-        assert_eq!(root_id_resolved, root_note_id);
-
-        let work_id_resolved = db
-            .get_note_id_by_path("Work/agenda.org")
-            .expect("Failed to resolve work note ID")
-            .expect("Work note ID should exist");
-        assert_eq!(work_id_resolved, work_note_id);
-
-        let project_id_resolved = db
-            .get_note_id_by_path("Work/Projects/specification.txt")
-            .expect("Failed to resolve project note ID")
-            .expect("Project note ID should exist");
-        assert_eq!(project_id_resolved, project_note_id);
-
-        // Test non-existent paths/IDs
-        let non_existent_path = db
-            .get_note_path_by_id("non_existent_note")
-            .expect("Failed to query non-existent note path");
-        assert!(non_existent_path.is_none());
+            .expect("Failed to create template");
+        }
 
-        let non_existent_id = db
-            .get_note_id_by_path("Non/Existent/note.md")
-            .expect("Failed to query non-existent note ID");
-        assert!(non_existent_id.is_none());
+        let template = |syntax: &str| {
+            db.find_template_content(&folder_id, syntax, user_id)
+                .expect("Failed to find template")
+        };
+        assert_eq!(template("md").as_deref(), Some("# Untitled\n"));
+        assert_eq!(template("rst").as_deref(), Some("Title\n=====\n"));
+        assert_eq!(template("txt"), None);
     }
 
     #[test]
-    fn test_path_resolution_edge_cases() {
+    fn test_index_notes() {
         let db = setup_test_database();
-        let user_id = "edge_case_user";
-
-        // Test folder with special characters in name
-        let special_id = db
-            .create_folder("Folder-With_Special.Chars", None, user_id)
-            .expect("Failed to create folder with special chars");
-
-        let special_path = db
-            .get_folder_path_by_id(&special_id, user_id)
-            .expect("Failed to get special folder path")
-            .expect("Special folder path should exist");
-        assert_eq!(special_path, "Folder-With_Special.Chars");
-
-        let special_id_resolved = db
-            .get_folder_id_by_path("Folder-With_Special.Chars", user_id)
-            .expect("Failed to resolve special folder ID")
-            .expect("Special folder ID should exist");
-        assert_eq!(special_id_resolved, special_id);
+        let user_id = "index_test_user";
+        let folder_id = db
+            .create_folder("Wiki", None, user_id)
+            .expect("Failed to create folder");
 
-        // Test note with different syntax extensions
-        let py_note_id = db
-            .create_note(
-                "python_script",
+        let mut ids = Vec::new();
+        for (title, syntax) in [("index", "md"), ("index", "rst"), ("page", "md")] {
+            let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+            db.create_note(
+                &id,
                 NoteContent {
-                    title: "script",
+                    title,
                     abstract_text: None,
-                    content: "print('hello')",
-                    syntax: "py",
+                    content: "text",
+                    syntax,
+                    extensionless: false,
                 },
-                None,
+                Some(&folder_id),
                 user_id,
             )
-            .expect("Failed to create Python note");
+            .expect("Failed to create note");
+            ids.push(id);
+        }
 
-        let py_path = db
-            .get_note_path_by_id(&py_note_id)
-            .expect("Failed to get Python note path")
-            .expect("Python note path should exist");
-        assert_eq!(py_path, "script.py");
+        let mut index_ids = db
+            .get_index_note_ids(&folder_id, user_id)
+            .expect("Failed to get index notes");
+        index_ids.sort();
+        let mut expected = ids[..2].to_vec();
+        expected.sort();
+        assert_eq!(index_ids, expected);
+
+        assert!(db.clear_note_content(&ids[0]).unwrap());
+        let note = db.get_note_by_id(&ids[0]).unwrap().unwrap();
+        assert_eq!(note.content, "");
+        assert_eq!(note.title, "index");
+    }
 
-        let py_id_resolved = db
-            .get_note_id_by_path("script.py")
-            .expect("Failed to resolve Python note ID")
-            .expect("Python note ID should exist");
-        assert_eq!(py_id_resolved, py_note_id);
+    #[test]
+    fn test_note_aliases() {
+        let db = setup_test_database();
+        let user_id = "alias_test_user";
+        let a = db.create_folder("A", None, user_id).unwrap();
+        let b = db.create_folder("B", None, user_id).unwrap();
+        db.create_note(
+            "note1",
+            NoteContent {
+                title: "n",
+                abstract_text: None,
+                content: "shared",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&a),
+            user_id,
+        )
+        .expect("Failed to create note");
+
+        let alias_id = db
+            .create_note_alias("note1", Some(&b), "l", "md", false, user_id)
+            .expect("Failed to create alias");
+        let alias = db
+            .get_alias_by_path("B/l.md", user_id)
+            .expect("Failed to look up alias")
+            .expect("Alias should resolve by path");
+        assert_eq!(alias.note_id, "note1");
+        assert!(
+            db.get_alias_by_path("B/l.md", "other_user")
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(db.get_note_link_count("note1").unwrap(), 2);
+        assert!(db.has_children(Some(&b), user_id).unwrap());
+        assert_eq!(
+            db.list_aliases_by_parent(Some(&b), user_id).unwrap().len(),
+            1
+        );
 
-        // Test deeply nested structure (5 levels)
-        let mut current_parent = None;
-        let mut expected_path = String::new();
+        // Unlinking the note's own name hands it over to the alias
+        let promoted = db
+            .promote_note_alias("note1")
+            .expect("Failed to promote alias")
+            .expect("Note has an alias");
+        assert_eq!(promoted.id, alias_id);
+        assert_eq!(
+            db.get_note_path_by_id("note1").unwrap().as_deref(),
+            Some("B/l.md")
+        );
+        assert_eq!(db.get_note_link_count("note1").unwrap(), 1);
+        assert!(db.promote_note_alias("note1").unwrap().is_none());
+
+        // Aliases go with their note
+        db.create_note_alias("note1", None, "root", "md", false, user_id)
+            .unwrap();
+        db.delete_note("note1").unwrap();
+        assert!(db.list_aliases_by_parent(None, user_id).unwrap().is_empty());
+    }
 
-        for i in 1..=5 {
-            let folder_name = format!("Level{i}");
-            let folder_id = db
-                .create_folder(&folder_name, current_parent.as_deref(), user_id)
-                .expect("Failed to create nested folder");
+    #[test]
+    fn test_saved_searches() {
+        let db = setup_test_database();
+        let user_id = "search_test_user";
+
+        for (title, content, owner) in [
+            ("Rust notes", "ownership and borrowing", user_id),
+            ("Groceries", "milk, eggs", user_id),
+            ("Other rust", "not mine", "someone_else"),
+        ] {
+            create_test_note(&db, title, content, None, owner);
+        }
 
-            if expected_path.is_empty() {
-                expected_path = folder_name.clone();
-            } else {
-                expected_path = format!("{expected_path}/{folder_name}");
-            }
+        let titles = |query: &str| -> Vec<String> {
+            db.search_notes(query, user_id)
+                .expect("Search failed")
+                .into_iter()
+                .map(|note| note.title)
+                .collect()
+        };
+        assert_eq!(titles("rust"), vec!["Rust notes"]);
+        assert_eq!(titles("milk OR borrowing").len(), 2);
+        assert!(db.search_notes("\"unterminated", user_id).is_err());
+
+        db.create_saved_search("rust", "rust", user_id)
+            .expect("Failed to save search");
+        let err = db
+            .create_saved_search("rust", "other", user_id)
+            .expect_err("Duplicate name should fail");
+        assert!(Database::is_unique_violation(&err));
+        db.create_saved_search("rust", "rust", "someone_else")
+            .expect("Names are per user");
+
+        let search = db
+            .get_saved_search("rust", user_id)
+            .expect("Failed to get saved search")
+            .expect("Saved search should exist");
+        assert_eq!(search.query, "rust");
+        assert_eq!(db.list_saved_searches(user_id).unwrap().len(), 1);
+
+        assert!(db.delete_saved_search("rust", user_id).unwrap());
+        assert!(!db.delete_saved_search("rust", user_id).unwrap());
+        assert!(db.get_saved_search("rust", user_id).unwrap().is_none());
+    }
 
-            let resolved_path = db
-                .get_folder_path_by_id(&folder_id, user_id)
-                .expect("Failed to get nested folder path")
-                .expect("Nested folder path should exist");
-            assert_eq!(resolved_path, expected_path);
+    #[test]
+    fn test_set_note_times() {
+        let db = setup_test_database();
+        let user_id = "times_test_user";
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        db.create_note(
+            &id,
+            NoteContent {
+                title: "touched",
+                abstract_text: None,
+                content: "text",
+                syntax: "md",
+                extensionless: false,
+            },
+            None,
+            user_id,
+        )
+        .expect("Failed to create note");
+        let note = db.get_note_by_id(&id).unwrap().unwrap();
+        assert!(note.accessed_at.is_none());
+
+        let mtime = Utc.with_ymd_and_hms(2020, 1, 2, 3, 4, 5).unwrap();
+        let atime = mtime + chrono::Duration::hours(1);
+        assert!(
+            db.set_note_times(&id, Some(atime), Some(mtime), None)
+                .unwrap()
+        );
+        let note = db.get_note_by_id(&id).unwrap().unwrap();
+        let accessed_at = note.accessed_at.expect("accessed_at should be set");
+        assert_eq!(accessed_at - note.updated_at, chrono::Duration::hours(1));
+        assert_eq!(note.updated_at.format("%Y").to_string(), "2020");
+
+        // Times that aren't given are kept
+        assert!(db.set_note_times(&id, None, None, None).unwrap());
+        let unchanged = db.get_note_by_id(&id).unwrap().unwrap();
+        assert_eq!(unchanged.accessed_at, Some(accessed_at));
+        assert_eq!(unchanged.updated_at, note.updated_at);
+
+        let folder_id = db.create_folder("Dir", None, user_id).unwrap();
+        assert!(
+            db.set_folder_times(&folder_id, user_id, None, Some(mtime), None)
+                .unwrap()
+        );
+        assert!(
+            !db.set_folder_times(&folder_id, "someone_else", None, Some(mtime), None)
+                .unwrap()
+        );
+        let folder = db.get_folder_by_id(&folder_id, user_id).unwrap().unwrap();
+        assert!(folder.accessed_at.is_none());
+        assert_eq!(folder.updated_at.format("%Y").to_string(), "2020");
+    }
 
-            current_parent = Some(folder_id);
-        }
+    #[test]
+    fn test_set_permissions() {
+        let db = setup_test_database();
+        let user_id = "perm_test_user";
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        db.create_note(
+            &id,
+            NoteContent {
+                title: "script",
+                abstract_text: None,
+                content: "echo hi",
+                syntax: "sh",
+                extensionless: false,
+            },
+            None,
+            user_id,
+        )
+        .expect("Failed to create note");
+        let note = db.get_note_by_id(&id).unwrap().unwrap();
+        assert_eq!((note.mode, note.uid, note.gid), (None, None, None));
 
-        // Create note in deeply nested folder
-        let deep_note_id = db
-            .create_note(
-                "deep_note",
-                NoteContent {
-                    title: "deep",
-                    abstract_text: None,
-                    content: "Deep content",
-                    syntax: "md",
-                },
-                current_parent.as_deref(),
-                user_id,
-            )
-            .expect("Failed to create deep note");
+        assert!(
+            db.set_note_permissions(&id, Some(0o755), None, None)
+                .unwrap()
+        );
+        assert!(
+            db.set_note_permissions(&id, None, Some(1000), Some(100))
+                .unwrap()
+        );
+        let note = db.get_note_by_id(&id).unwrap().unwrap();
+        assert_eq!(
+            (note.mode, note.uid, note.gid),
+            (Some(0o755), Some(1000), Some(100))
+        );
 
-        let deep_path = db
-            .get_note_path_by_id(&deep_note_id)
-            .expect("Failed to get deep note path")
-            .expect("Deep note path should exist");
-        assert_eq!(deep_path, "Level1/Level2/Level3/Level4/Level5/deep.md");
+        let folder_id = db.create_folder("Private", None, user_id).unwrap();
+        assert!(
+            !db.set_folder_permissions(&folder_id, "someone_else", Some(0o700), None, None)
+                .unwrap()
+        );
+        assert!(
+            db.set_folder_permissions(&folder_id, user_id, Some(0o700), None, None)
+                .unwrap()
+        );
+        let folder = db.get_folder_by_id(&folder_id, user_id).unwrap().unwrap();
+        assert_eq!(
+            (folder.mode, folder.uid, folder.gid),
+            (Some(0o700), None, None)
+        );
     }
 
     #[test]
-    fn test_get_folder_contents_recursive() {
+    fn test_child_changes_touch_parent_folder() {
         let db = setup_test_database();
-        let user_id = "recursive_test_user";
+        let user_id = "touch_test_user";
+        let old = Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap();
+        let folder_id = db.create_folder("Parent", None, user_id).unwrap();
+        let other_id = db.create_folder("Other", None, user_id).unwrap();
+        let year = |id: &str| {
+            db.get_folder_by_id(id, user_id)
+                .unwrap()
+                .unwrap()
+                .updated_at
+                .format("%Y")
+                .to_string()
+        };
+        let reset = || {
+            for id in [&folder_id, &other_id] {
+                db.set_folder_times(id, user_id, None, Some(old), None)
+                    .unwrap();
+            }
+        };
 
-        // Create nested folder structure:
-        // Root/
-        //   ├── Documents/
-        //   │   ├── Projects/
-        //   │   │   ├── project1.md
-        //   │   │   └── SubProjects/
-        //   │   │       └── subproject.txt
-        //   │   └── notes.md
-        //   ├── Work/
-        //   │   └── agenda.org
-        //   └── readme.md
+        reset();
+        let note_id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        db.create_note(
+            &note_id,
+            NoteContent {
+                title: "child",
+                abstract_text: None,
+                content: "",
+                syntax: "md",
+                extensionless: false,
+            },
+            Some(&folder_id),
+            user_id,
+        )
+        .unwrap();
+        assert_ne!(year(&folder_id), "2000");
+
+        reset();
+        db.update_note_name(&note_id, "renamed", "md", false)
+            .unwrap();
+        assert_ne!(year(&folder_id), "2000");
+
+        // A move touches both the old and the new folder
+        reset();
+        db.update_note_parent(&note_id, Some(&other_id)).unwrap();
+        assert_ne!(year(&folder_id), "2000");
+        assert_ne!(year(&other_id), "2000");
+
+        reset();
+        db.delete_note(&note_id).unwrap();
+        assert_eq!(year(&folder_id), "2000");
+        assert_ne!(year(&other_id), "2000");
+
+        reset();
+        let child_id = db.create_folder("Sub", Some(&folder_id), user_id).unwrap();
+        assert_ne!(year(&folder_id), "2000");
+
+        reset();
+        db.delete_folder(&child_id, user_id).unwrap();
+        assert_ne!(year(&folder_id), "2000");
+        assert_eq!(year(&other_id), "2000");
+    }
 
-        let root_id = db
-            .create_folder("Root", None, user_id)
-            .expect("Failed to create Root folder");
-        let docs_id = db
-            .create_folder("Documents", Some(&root_id), user_id)
-            .expect("Failed to create Documents folder");
-        let projects_id = db
-            .create_folder("Projects", Some(&docs_id), user_id)
-            .expect("Failed to create Projects folder");
-        let subprojects_id = db
-            .create_folder("SubProjects", Some(&projects_id), user_id)
-            .expect("Failed to create SubProjects folder");
-        let work_id = db
-            .create_folder("Work", Some(&root_id), user_id)
-            .expect("Failed to create Work folder");
+    #[test]
+    fn test_get_subfolder_count() {
+        let db = setup_test_database();
+        let user_id = "subfolder_test_user";
+        let parent_id = db.create_folder("Parent", None, user_id).unwrap();
+        db.create_folder("A", Some(&parent_id), user_id).unwrap();
+        db.create_folder("B", Some(&parent_id), user_id).unwrap();
+        db.create_folder("Elsewhere", None, "someone_else").unwrap();
 
-        // Create notes
+        assert_eq!(
+            db.get_subfolder_count(Some(&parent_id), user_id).unwrap(),
+            2
+        );
+        assert_eq!(db.get_subfolder_count(None, user_id).unwrap(), 1);
+        assert_eq!(db.get_subfolder_count(None, "someone_else").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_content_size_tracks_content() {
+        let db = setup_test_database();
+        let user_id = "size_test_user";
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
         db.create_note(
-            "readme",
+            &id,
             NoteContent {
-                title: "readme",
+                title: "sized",
                 abstract_text: None,
-                content: "Root readme",
+                content: "héllo",
                 syntax: "md",
+                extensionless: false,
             },
-            Some(&root_id),
+            None,
             user_id,
         )
-        .expect("Failed to create root readme");
+        .unwrap();
+        // Bytes, not characters
+        assert_eq!(db.get_note_by_id(&id).unwrap().unwrap().content_size, 6);
+
+        db.update_note(&id, "sized", None, "", "md").unwrap();
+        assert_eq!(db.get_note_by_id(&id).unwrap().unwrap().content_size, 0);
+
+        let history: i64 = db
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM notes_history WHERE id = ?1",
+                [&id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(history, 1, "Refreshing the size is not a separate revision");
+    }
+
+    #[test]
+    fn test_note_meta_queries() {
+        let db = setup_test_database();
+        let user_id = "meta_test_user";
+        let folder_id = db.create_folder("Folder", None, user_id).unwrap();
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
         db.create_note(
-            "notes",
+            &id,
             NoteContent {
-                title: "notes",
-                abstract_text: None,
-                content: "Doc notes",
+                title: "big",
+                abstract_text: Some("summary"),
+                content: &"x".repeat(1000),
                 syntax: "md",
+                extensionless: false,
             },
-            Some(&docs_id),
+            Some(&folder_id),
             user_id,
         )
-        .expect("Failed to create docs notes");
+        .unwrap();
+
+        let meta = db
+            .get_note_meta_by_id(&id)
+            .unwrap()
+            .expect("Note should exist");
+        assert_eq!(meta.title, "big");
+        assert_eq!(meta.abstract_text.as_deref(), Some("summary"));
+        assert_eq!(meta.content_size, 1000);
+        assert!(db.get_note_meta_by_id("missing").unwrap().is_none());
+
+        let listed = db
+            .list_note_meta_by_parent(Some(&folder_id), user_id)
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, id);
+        assert!(
+            db.list_note_meta_by_parent(None, user_id)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            db.list_note_meta_by_parent(Some(&folder_id), "someone_else")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_update_note_at_revision() {
+        let db = setup_test_database();
+        let user_id = "revision_test_user";
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
         db.create_note(
-            "project1",
+            &id,
             NoteContent {
-                title: "project1",
+                title: "rev",
                 abstract_text: None,
-                content: "Project content",
+                content: "one",
                 syntax: "md",
+                extensionless: false,
             },
-            Some(&projects_id),
+            None,
             user_id,
         )
-        .expect("Failed to create project1");
+        .unwrap();
+        let read = db.get_note_by_id(&id).unwrap().unwrap();
+        assert_eq!(read.revision, 0);
+
+        // Renames leave the revision alone, content changes bump it
+        db.update_note_name(&id, "renamed", "md", false).unwrap();
+        assert_eq!(db.get_note_by_id(&id).unwrap().unwrap().revision, 0);
+        db.update_note(&id, "renamed", None, "two", "md").unwrap();
+        assert_eq!(db.get_note_by_id(&id).unwrap().unwrap().revision, 1);
+
+        // A writer that read revision 0 must not clobber "two"
+        assert!(
+            !db.update_note_at_revision(&id, read.revision, "rev", None, "three", "md")
+                .unwrap()
+        );
+        assert_eq!(db.get_note_by_id(&id).unwrap().unwrap().content, "two");
+
+        assert!(
+            db.update_note_at_revision(&id, 1, "renamed", None, "three", "md")
+                .unwrap()
+        );
+        let note = db.get_note_by_id(&id).unwrap().unwrap();
+        assert_eq!((note.content.as_str(), note.revision), ("three", 2));
+
+        // The revision bump itself isn't a history entry
+        let history: i64 = db
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM notes_history WHERE id = ?1",
+                [&id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(history, 3);
+    }
+
+    #[test]
+    fn test_replace_note_content() {
+        let db = setup_test_database();
+        let user_id = "replace_test_user";
+        let target = create_test_note(&db, "report", "old draft", None, user_id);
+        db.connection
+            .execute(
+                "UPDATE notes SET abstract = 'kept' WHERE id = ?1",
+                [&target],
+            )
+            .unwrap();
+        let source = create_test_note(&db, "report.md.tmp", "new draft", None, user_id);
+
+        assert!(db.replace_note_content(&target, &source).unwrap());
+        let note = db.get_note_by_id(&target).unwrap().unwrap();
+        assert_eq!(note.title, "report");
+        assert_eq!(note.content, "new draft");
+        assert_eq!(note.abstract_text.as_deref(), Some("kept"));
+        assert!(db.get_note_by_id(&source).unwrap().is_none());
+
+        // The old draft is in the target's history
+        let old: String = db
+            .connection
+            .query_row(
+                "SELECT content FROM notes_history WHERE id = ?1 AND log_action = 'UPDATE'",
+                [&target],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(old, "old draft");
+
+        // A missing source changes nothing
+        assert!(!db.replace_note_content(&target, &source).unwrap());
+        assert_eq!(
+            db.get_note_by_id(&target).unwrap().unwrap().content,
+            "new draft"
+        );
+    }
+
+    #[test]
+    fn test_interrupted_query() {
+        let db = setup_test_database();
+        let handle = db.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            handle.interrupt();
+        });
+        let err = db
+            .connection
+            .query_row(
+                "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 10000000000)
+                 SELECT COUNT(*) FROM n",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .unwrap_err();
+        interrupter.join().unwrap();
+        assert!(Database::is_interrupted(&err));
+        assert!(!Database::is_unique_violation(&err));
+    }
+
+    #[test]
+    fn test_deduplicate_notes() {
+        let db = setup_test_database();
+        let user_id = "dedup_test_user";
+        let a = create_test_note(&db, "a", "imported body", None, user_id);
+        let b = create_test_note(&db, "b", "imported body", None, user_id);
+        let c = create_test_note(&db, "c", "other body", None, user_id);
+        let before = db.get_note_by_id(&a).unwrap().unwrap();
+        let stored_bodies = || -> i64 {
+            db.connection
+                .query_row("SELECT COUNT(*) FROM note_contents", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        let summary = db.deduplicate_notes().unwrap();
+        assert_eq!(
+            summary,
+            DedupSummary {
+                notes: 2,
+                bodies: 1,
+                bytes_saved: "imported body".len() as i64,
+            }
+        );
+        assert_eq!(stored_bodies(), 1);
+        // Reads, sizes, revisions, history and search are unaffected
+        let after = db.get_note_by_id(&a).unwrap().unwrap();
+        assert_eq!(after.content, "imported body");
+        assert_eq!(after.content_size, before.content_size);
+        assert_eq!(after.revision, before.revision);
+        let history: i64 = db
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM notes_history WHERE id = ?1",
+                [&a],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(history, 0);
+        assert_eq!(db.search_notes("imported", user_id).unwrap().len(), 2);
+        assert_eq!(db.deduplicate_notes().unwrap(), DedupSummary::default());
+
+        // Writing one note stores its content inline again
+        assert!(db.update_note(&a, "a", None, "edited body", "md").unwrap());
+        let edited = db.get_note_by_id(&a).unwrap().unwrap();
+        assert_eq!(edited.content, "edited body");
+        assert_eq!(edited.revision, before.revision + 1);
+        assert_eq!(
+            db.get_note_by_id(&b).unwrap().unwrap().content,
+            "imported body"
+        );
+        let history: String = db
+            .connection
+            .query_row(
+                "SELECT content FROM notes_history WHERE id = ?1",
+                [&a],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(history, "imported body");
+
+        // The shared body goes once no note refers to it
+        assert_eq!(stored_bodies(), 1);
+        db.delete_note(&b).unwrap();
+        db.purge_deleted(None).unwrap();
+        assert_eq!(stored_bodies(), 0);
+        assert_eq!(
+            db.get_note_by_id(&c).unwrap().unwrap().content,
+            "other body"
+        );
+    }
+
+    #[test]
+    fn test_attachments() {
+        let db = setup_test_database();
+        let note_id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
         db.create_note(
-            "subproject",
+            &note_id,
             NoteContent {
-                title: "subproject",
+                title: "trip",
                 abstract_text: None,
-                content: "Sub content",
-                syntax: "txt",
+                content: "![map](trip_assets/map.png)",
+                syntax: "md",
+                extensionless: false,
             },
-            Some(&subprojects_id),
-            user_id,
+            None,
+            "attachment_test_user",
         )
-        .expect("Failed to create subproject");
+        .unwrap();
+
+        // Arbitrary bytes, not valid UTF-8
+        let png = [0x89, b'P', b'N', b'G', 0xff, 0x00];
+        let id = db
+            .create_attachment(&note_id, "map.png", "image/png", &png)
+            .unwrap();
+        let attachment = db
+            .get_attachment_by_name(&note_id, "map.png")
+            .unwrap()
+            .unwrap();
+        assert_eq!(attachment.id, id);
+        assert_eq!(attachment.mime, "image/png");
+        assert_eq!(attachment.size, png.len() as u64);
+        assert_eq!(db.get_attachment_data(&id).unwrap().unwrap(), png);
+        let err = db
+            .create_attachment(&note_id, "map.png", "image/png", b"")
+            .unwrap_err();
+        assert!(Database::is_unique_violation(&err));
+
+        assert!(db.update_attachment_data(&id, b"%PDF").unwrap());
+        assert!(
+            db.rename_attachment(&id, "map.pdf", "application/pdf")
+                .unwrap()
+        );
+        let listed = db.list_attachments(&note_id).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].filename, "map.pdf");
+        assert_eq!(listed[0].size, 4);
+
+        assert!(db.delete_attachment(&id).unwrap());
+        assert!(!db.delete_attachment(&id).unwrap());
+        db.create_attachment(&note_id, "map.png", "image/png", &png)
+            .unwrap();
+        // Attachments go with their note
+        db.delete_note(&note_id).unwrap();
+        db.purge_deleted(None).unwrap();
+        assert!(db.list_attachments(&note_id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_backlinks() {
+        let db = setup_test_database();
+        let user_id = "backlinks_test_user";
+        create_test_note(&db, "target", "no links here", None, user_id);
+        let a = create_test_note(&db, "a", "see [[target]] and [[missing]]", None, user_id);
+        let b = create_test_note(&db, "b", "[[target|the target]]", None, user_id);
+        let titles = |notes: Vec<Note>| notes.into_iter().map(|n| n.title).collect::<Vec<_>>();
+
+        assert_eq!(
+            titles(db.list_backlinks("target", user_id).unwrap()),
+            ["a", "b"]
+        );
+        // Links to notes that don't exist are kept, but not listed
+        assert_eq!(
+            titles(db.list_backlinks("missing", user_id).unwrap()),
+            ["a"]
+        );
+        assert_eq!(db.list_linked_titles(user_id).unwrap(), ["target"]);
+        assert!(db.note_title_exists("target", user_id).unwrap());
+        assert!(!db.note_title_exists("missing", user_id).unwrap());
+        // Outgoing links only name notes that exist
+        assert_eq!(
+            titles(db.list_linked_notes(&a, user_id).unwrap()),
+            ["target"]
+        );
+
+        // Rewriting or clearing the content updates the links
+        db.update_note(&a, "a", None, "only [[missing]] now", "md")
+            .unwrap();
+        assert_eq!(titles(db.list_backlinks("target", user_id).unwrap()), ["b"]);
+        db.clear_note_content(&b).unwrap();
+        assert!(db.list_backlinks("target", user_id).unwrap().is_empty());
+        assert!(db.list_linked_titles(user_id).unwrap().is_empty());
+
+        // Deleting the note drops its links
+        db.delete_note(&a).unwrap();
+        assert!(db.list_backlinks("missing", user_id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_note_etag() {
+        let db = setup_test_database();
+        let user_id = "etag_test_user";
+        let a = create_test_note(&db, "a", "same body", None, user_id);
+        let b = create_test_note(&db, "b", "same body", None, user_id);
+        let etag = db.get_note_etag(&a).unwrap().unwrap();
+        assert_eq!(db.get_note_etag(&b).unwrap().unwrap(), etag);
+        assert_eq!(db.get_note_etag("missing").unwrap(), None);
+
+        // Sharing the body doesn't change it, editing does
+        db.deduplicate_notes().unwrap();
+        assert_eq!(db.get_note_etag(&a).unwrap().unwrap(), etag);
+        db.update_note(&a, "a", None, "new body", "md").unwrap();
+        let edited = db.get_note_etag(&a).unwrap().unwrap();
+        assert_ne!(edited, etag);
+
+        // Writes from outside the mount are hashed again on the next read,
+        // without recording a revision
+        db.connection
+            .execute("UPDATE notes SET content = 'same body' WHERE id = ?1", [&a])
+            .unwrap();
+        let history = || -> i64 {
+            db.connection
+                .query_row(
+                    "SELECT COUNT(*) FROM notes_history WHERE id = ?1",
+                    [&a],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        let revisions = history();
+        assert_eq!(db.get_note_etag(&a).unwrap().unwrap(), etag);
+        assert_eq!(history(), revisions);
+    }
+
+    #[test]
+    fn test_load_as_of() {
+        let path = std::env::temp_dir().join(format!("sqlite_fuse-as-of-{}", uuid::Uuid::new_v4()));
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(include_str!("../sql/init.sql")).unwrap();
+        let db = Database::new(conn, Some(chrono_tz::Australia::Sydney));
+        let user_id = "as_of_test_user";
+        let folder_id = db.create_folder("old", None, user_id).unwrap();
+        let edited = create_test_note(&db, "edited", "first [[kept]]", None, user_id);
+        let removed = create_test_note(&db, "removed", "gone now", Some(&folder_id), user_id);
+        let kept = create_test_note(&db, "kept", "unchanged", None, user_id);
+        db.update_note(&edited, "renamed", None, "second", "md")
+            .unwrap();
+        db.delete_note(&removed).unwrap();
+        db.delete_folder(&folder_id, user_id).unwrap();
+        db.purge_deleted(None).unwrap();
+        create_test_note(&db, "later", "created afterwards", None, user_id);
+        // Date everything written so far to 2024, local time in Sydney (UTC+10/11)
+        db.connection
+            .execute_batch(
+                "UPDATE folders SET created_at = '2024-01-01 00:00:00';
+                 UPDATE notes SET created_at = '2024-01-01 00:00:00' WHERE title != 'later';
+                 UPDATE notes_history SET created_at = '2024-01-01 00:00:00';
+                 UPDATE notes_history SET deleted_at = '2024-06-01 00:00:00' WHERE log_action = 'UPDATE';
+                 UPDATE notes_history SET deleted_at = '2024-09-01 00:00:00' WHERE log_action = 'DELETE';",
+            )
+            .unwrap();
+        let source = path.to_str().unwrap();
+        let at = |time: &str| {
+            let snapshot = setup_test_database();
+            let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap();
+            let count = snapshot.load_as_of(source, None, time).unwrap();
+            (snapshot, count)
+        };
+
+        let (snapshot, count) = at("2024-03-01 00:00:00");
+        assert_eq!(count, 3);
+        let note = snapshot.get_note_by_id(&edited).unwrap().unwrap();
+        assert_eq!(
+            (note.title.as_str(), note.content.as_str()),
+            ("edited", "first [[kept]]")
+        );
+        // The folder has been deleted since, so its note is at the root
+        assert_eq!(
+            snapshot.get_note_id_by_path("removed.md").unwrap(),
+            Some(removed.clone())
+        );
+        assert_eq!(snapshot.list_backlinks("kept", user_id).unwrap().len(), 1);
+        assert!(snapshot.get_note_id_by_path("later.md").unwrap().is_none());
+
+        let (snapshot, count) = at("2024-10-01 00:00:00");
+        assert_eq!(count, 2);
+        assert_eq!(
+            snapshot.get_note_by_id(&edited).unwrap().unwrap().content,
+            "second"
+        );
+        assert!(snapshot.get_note_by_id(&kept).unwrap().is_some());
+        assert!(snapshot.get_note_by_id(&removed).unwrap().is_none());
+
+        // Before anything was created
+        assert_eq!(at("2023-01-01 00:00:00").1, 0);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_purge_history() {
+        let db = setup_test_database();
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
         db.create_note(
-            "agenda",
+            &id,
             NoteContent {
-                title: "agenda",
+                title: "log",
                 abstract_text: None,
-                content: "Work agenda",
-                syntax: "org",
+                content: "v0",
+                syntax: "md",
+                extensionless: false,
             },
-            Some(&work_id),
-            user_id,
+            None,
+            "history_test_user",
         )
-        .expect("Failed to create work agenda");
+        .unwrap();
+        for version in 1..=5 {
+            db.update_note(&id, "log", None, &format!("v{version}"), "md")
+                .unwrap();
+        }
+        let versions = || -> Vec<String> {
+            let mut stmt = db
+                .connection
+                .prepare("SELECT content FROM notes_history ORDER BY rowid")
+                .unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_>>()
+                .unwrap()
+        };
+        assert_eq!(versions().len(), 5);
+
+        let keep = |keep_versions, keep_days| HistoryRetention {
+            keep_versions,
+            keep_days,
+        };
+        assert_eq!(db.purge_history(&keep(Some(3), None)).unwrap(), 2);
+        assert_eq!(versions(), ["v2", "v3", "v4"]);
+        assert_eq!(db.purge_history(&keep(None, Some(1))).unwrap(), 0);
+        db.connection
+            .execute(
+                "UPDATE notes_history SET deleted_at = datetime('now', '-2 days')
+                 WHERE content = 'v2'",
+                [],
+            )
+            .unwrap();
+        assert_eq!(db.purge_history(&keep(None, Some(1))).unwrap(), 1);
+        assert_eq!(versions(), ["v3", "v4"]);
+        // The note itself is untouched
+        assert_eq!(db.get_note_by_id(&id).unwrap().unwrap().content, "v5");
+        assert_eq!(db.purge_history(&HistoryRetention::default()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_soft_delete() {
+        let db = setup_test_database();
+        let user_id = "soft_delete_test_user";
+        let folder_id = db.create_folder("trash me", None, user_id).unwrap();
+        let note_id = create_test_note(
+            &db,
+            "note",
+            "findable [[elsewhere]]",
+            Some(&folder_id),
+            user_id,
+        );
+        assert!(db.delete_note(&note_id).unwrap());
+        assert!(!db.delete_note(&note_id).unwrap());
+
+        // Hidden everywhere, but still stored
+        assert!(db.get_note_by_id(&note_id).unwrap().is_none());
+        assert!(
+            db.get_note_id_by_path("trash me/note.md")
+                .unwrap()
+                .is_none()
+        );
+        assert!(db.search_notes("findable", user_id).unwrap().is_empty());
+        assert!(db.list_backlinks("elsewhere", user_id).unwrap().is_empty());
+        assert!(!db.has_children(Some(&folder_id), user_id).unwrap());
+        let stored: i64 = db
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM notes WHERE id = ?1",
+                [&note_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(stored, 1);
+
+        // The name is free again
+        let replacement = create_test_note(&db, "note", "new", Some(&folder_id), user_id);
+        assert!(db.delete_note(&replacement).unwrap());
+        assert!(db.delete_folder(&folder_id, user_id).unwrap());
+        assert!(db.get_folder_by_id(&folder_id, user_id).unwrap().is_none());
+        assert!(
+            db.list_folders_by_parent(None, user_id, Page::ALL)
+                .unwrap()
+                .is_empty()
+        );
+        db.create_folder("trash me", None, user_id).unwrap();
+
+        // Purging only takes what was deleted long enough ago
+        assert_eq!(db.purge_deleted(Some(1)).unwrap(), (0, 0));
+        assert_eq!(db.purge_deleted(None).unwrap(), (1, 2));
+        assert_eq!(db.purge_deleted(None).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_delete_folder_recursive() {
+        let db = setup_test_database();
+        let user_id = "recursive_delete_test_user";
+        let top = db.create_folder("top", None, user_id).unwrap();
+        let nested = db.create_folder("nested", Some(&top), user_id).unwrap();
+        let kept = db.create_folder("kept", None, user_id).unwrap();
+        create_test_note(&db, "a", "", Some(&top), user_id);
+        create_test_note(&db, "b", "", Some(&nested), user_id);
+        let outside = create_test_note(&db, "c", "", Some(&kept), user_id);
+        db.create_note_alias(&outside, Some(&nested), "alias", "md", false, user_id)
+            .unwrap();
+
+        assert_eq!(db.delete_folder_recursive(&top, user_id).unwrap(), (2, 2));
+        assert!(db.get_folder_id_by_path("top", user_id).unwrap().is_none());
+        assert!(db.get_note_id_by_path("top/nested/b.md").unwrap().is_none());
+        assert!(db.get_note_id_by_path("kept/c.md").unwrap().is_some());
+        // Already deleted, or another user's
+        assert_eq!(db.delete_folder_recursive(&top, user_id).unwrap(), (0, 0));
+        assert_eq!(
+            db.delete_folder_recursive(&kept, "someone else").unwrap(),
+            (0, 0)
+        );
 
-        // Test getting all contents under root
-        let contents = db
-            .get_folder_contents_recursive(&root_id, user_id)
-            .expect("Failed to get recursive contents");
+        // Purging takes the whole subtree, but not notes linked into it
+        assert_eq!(db.purge_deleted(None).unwrap(), (2, 2));
+        assert!(db.get_note_by_id(&outside).unwrap().is_some());
+    }
 
-        // Extract paths for easier testing
-        let paths: Vec<String> = contents
-            .iter()
-            .map(|item| match item {
-                FileType::Directory { path } => path.clone(),
-                FileType::File { path } => path.clone(),
+    #[test]
+    fn test_list_children() {
+        let db = setup_test_database();
+        let user_id = "children_test_user";
+        let parent = db.create_folder("parent", None, user_id).unwrap();
+        db.create_folder("zeta", Some(&parent), user_id).unwrap();
+        db.create_folder("alpha", Some(&parent), user_id).unwrap();
+        for (title, content) in [("beta", "four"), ("aardvark", "")] {
+            create_test_note(&db, title, content, Some(&parent), user_id);
+        }
+
+        let listed: Vec<(String, Option<u64>)> = db
+            .list_children(Some(&parent), user_id, false, Page::ALL)
+            .unwrap()
+            .into_iter()
+            .map(|child| match child {
+                Child::Folder(folder) => (folder.title, None),
+                Child::Note(note) => (note.title, Some(note.content_size)),
             })
             .collect();
+        assert_eq!(
+            listed,
+            [
+                ("alpha".to_string(), None),
+                ("zeta".to_string(), None),
+                ("aardvark".to_string(), Some(0)),
+                ("beta".to_string(), Some(4)),
+            ]
+        );
+        assert_eq!(
+            db.list_children(None, user_id, false, Page::ALL)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(
+            db.list_children(Some(&parent), "other_user", false, Page::ALL)
+                .unwrap()
+                .is_empty()
+        );
+    }
 
-        // Check that we have all expected items
-        assert!(paths.contains(&"Documents".to_string()));
-        assert!(paths.contains(&"Documents/Projects".to_string()));
-        assert!(paths.contains(&"Documents/Projects/SubProjects".to_string()));
-        assert!(paths.contains(&"Work".to_string()));
-        assert!(paths.contains(&"readme.md".to_string()));
-        assert!(paths.contains(&"Documents/notes.md".to_string()));
-        assert!(paths.contains(&"Documents/Projects/project1.md".to_string()));
-        assert!(paths.contains(&"Documents/Projects/SubProjects/subproject.txt".to_string()));
-        assert!(paths.contains(&"Work/agenda.org".to_string()));
+    #[test]
+    fn test_list_pages() {
+        let db = setup_test_database();
+        let user_id = "pages_test_user";
+        let parent = db.create_folder("parent", None, user_id).unwrap();
+        for title in ["f2", "f1"] {
+            db.create_folder(title, Some(&parent), user_id).unwrap();
+        }
+        for title in ["n3", "n1", "n2"] {
+            create_test_note(&db, title, "", Some(&parent), user_id);
+        }
+        let page = |offset, limit| Page {
+            offset,
+            limit: Some(limit),
+        };
+        let titles = |children: Vec<Child>| -> Vec<String> {
+            children
+                .into_iter()
+                .map(|child| match child {
+                    Child::Folder(folder) => folder.title,
+                    Child::Note(note) => note.title,
+                })
+                .collect()
+        };
 
-        // Check types are correct
-        let directories: Vec<&String> = contents
-            .iter()
-            .filter_map(|item| match item {
-                FileType::Directory { path } => Some(path),
-                FileType::File { .. } => None,
-            })
-            .collect();
+        // Pages of children run on from folders into notes
+        assert_eq!(
+            titles(
+                db.list_children(Some(&parent), user_id, false, page(0, 3))
+                    .unwrap()
+            ),
+            ["f1", "f2", "n1"]
+        );
+        assert_eq!(
+            titles(
+                db.list_children(Some(&parent), user_id, false, page(3, 3))
+                    .unwrap()
+            ),
+            ["n2", "n3"]
+        );
+        assert!(
+            db.list_children(Some(&parent), user_id, false, page(5, 3))
+                .unwrap()
+                .is_empty()
+        );
 
-        let files: Vec<&String> = contents
-            .iter()
-            .filter_map(|item| match item {
-                FileType::File { path } => Some(path),
-                FileType::Directory { .. } => None,
-            })
-            .collect();
+        let notes = db
+            .list_notes_by_parent(Some(&parent), user_id, page(1, 1))
+            .unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "n2");
+        let folders = db
+            .list_folders_by_parent(
+                Some(&parent),
+                user_id,
+                Page {
+                    offset: 1,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(folders.len(), 1);
+        assert_eq!(folders[0].title, "f2");
+    }
 
-        assert_eq!(directories.len(), 4); // Documents, Projects, SubProjects, Work
-        assert_eq!(files.len(), 5); // readme, notes, project1, subproject, agenda
+    #[test]
+    fn test_other_users_notes() {
+        let db = setup_test_database();
+        let folder = db.create_folder("shared", None, "owner").unwrap();
+        let own = create_test_note(&db, "mine", "", Some(&folder), "owner");
+        let other = create_test_note(&db, "theirs", "", Some(&folder), "guest");
+
+        let notes = |all_users| -> Vec<String> {
+            db.list_children(Some(&folder), "owner", all_users, Page::ALL)
+                .unwrap()
+                .into_iter()
+                .filter_map(|child| match child {
+                    Child::Note(note) => Some(note.id),
+                    Child::Folder(_) => None,
+                })
+                .collect()
+        };
+        assert_eq!(notes(false), std::slice::from_ref(&own));
+        assert_eq!(notes(true), [own.clone(), other.clone()]);
 
-        // Test getting contents under Documents folder only
-        let docs_contents = db
-            .get_folder_contents_recursive(&docs_id, user_id)
-            .expect("Failed to get docs contents");
+        let visible = |path: &str, all_users| {
+            db.get_visible_note_id_by_path(path, "owner", all_users)
+                .unwrap()
+        };
+        assert_eq!(visible("shared/mine.md", false), Some(own));
+        assert_eq!(visible("shared/theirs.md", false), None);
+        assert_eq!(visible("shared/theirs.md", true), Some(other));
+    }
 
-        let docs_paths: Vec<String> = docs_contents
-            .iter()
-            .map(|item| match item {
-                FileType::Directory { path } => path.clone(),
-                FileType::File { path } => path.clone(),
-            })
+    #[test]
+    fn test_folder_access() {
+        let db = setup_test_database();
+        let projects = db.create_folder("Projects", None, "owner").unwrap();
+        let inner = db.create_folder("inner", Some(&projects), "owner").unwrap();
+        let private = db.create_folder("Private", None, "owner").unwrap();
+
+        assert_eq!(
+            db.get_folder_access(&private, "owner").unwrap(),
+            Some(Access::Write)
+        );
+        assert_eq!(db.get_folder_access(&inner, "guest").unwrap(), None);
+
+        // A grant covers the folder's subtree, and only the owner can make it
+        assert!(
+            !db.grant_folder_access(&projects, "guest", "guest", Access::Write)
+                .unwrap()
+        );
+        assert!(
+            db.grant_folder_access(&projects, "owner", "guest", Access::Read)
+                .unwrap()
+        );
+        assert_eq!(
+            db.get_folder_access(&inner, "guest").unwrap(),
+            Some(Access::Read)
+        );
+        assert_eq!(db.get_folder_access(&private, "guest").unwrap(), None);
+
+        // Granting again replaces the access; the nearest grant wins
+        assert!(
+            db.grant_folder_access(&projects, "owner", "guest", Access::Write)
+                .unwrap()
+        );
+        db.grant_folder_access(&inner, "owner", "guest", Access::Read)
+            .unwrap();
+        assert_eq!(
+            db.get_folder_access(&projects, "guest").unwrap(),
+            Some(Access::Write)
+        );
+        assert_eq!(
+            db.get_folder_access(&inner, "guest").unwrap(),
+            Some(Access::Read)
+        );
+
+        assert!(!db.revoke_folder_access(&inner, "guest", "guest").unwrap());
+        assert!(db.revoke_folder_access(&inner, "owner", "guest").unwrap());
+        assert_eq!(
+            db.get_folder_access(&inner, "guest").unwrap(),
+            Some(Access::Write)
+        );
+    }
+
+    #[test]
+    fn test_list_shared_folders() {
+        let db = setup_test_database();
+        let projects = db.create_folder("Projects", None, "owner").unwrap();
+        let inner = db.create_folder("inner", Some(&projects), "owner").unwrap();
+        let own = db.create_folder("Own", None, "guest").unwrap();
+        db.grant_folder_access(&projects, "owner", "guest", Access::Read)
+            .unwrap();
+        db.grant_folder_access(&inner, "owner", "guest", Access::Write)
+            .unwrap();
+        db.grant_folder_access(&own, "guest", "guest", Access::Write)
+            .unwrap();
+
+        // Each grant is listed, but not the user's own folders
+        let shared: Vec<(String, Access)> = db
+            .list_shared_folders("guest")
+            .unwrap()
+            .into_iter()
+            .map(|(folder, access)| (folder.title, access))
             .collect();
+        assert_eq!(
+            shared,
+            vec![
+                ("Projects".to_string(), Access::Read),
+                ("inner".to_string(), Access::Write),
+            ]
+        );
+        assert!(db.list_shared_folders("owner").unwrap().is_empty());
+    }
 
-        assert!(docs_paths.contains(&"Projects".to_string()));
-        assert!(docs_paths.contains(&"Projects/SubProjects".to_string()));
-        assert!(docs_paths.contains(&"notes.md".to_string()));
-        assert!(docs_paths.contains(&"Projects/project1.md".to_string()));
-        assert!(docs_paths.contains(&"Projects/SubProjects/subproject.txt".to_string()));
+    #[test]
+    fn test_user_timezone() {
+        let db = setup_test_database();
+        let sydney = db.create_folder("Sydney", None, "local").unwrap();
+        let utc = db.create_folder("Utc", None, "remote").unwrap();
+        db.set_user_timezone("remote", Some(chrono_tz::UTC))
+            .unwrap();
+        assert_eq!(
+            db.get_user_timezone("remote").unwrap(),
+            Some(chrono_tz::UTC)
+        );
+        assert_eq!(db.get_user_timezone("local").unwrap(), None);
+
+        // Times are shown in each user's zone, else the database's, but
+        // stored in UTC for everyone
+        assert_eq!(db.timezone_for("remote"), chrono_tz::UTC);
+        assert_eq!(db.timezone_for("local"), chrono_tz::Australia::Sydney);
+        let when = Utc.with_ymd_and_hms(2024, 1, 15, 0, 0, 0).unwrap();
+        db.set_folder_times(&sydney, "local", None, Some(when), None)
+            .unwrap();
+        db.set_folder_times(&utc, "remote", None, Some(when), None)
+            .unwrap();
+        for (id, user) in [(&sydney, "local"), (&utc, "remote")] {
+            let folder = db.get_folder_by_id(id, user).unwrap().unwrap();
+            assert_eq!(folder.updated_at, when);
+        }
 
-        // Should not contain Work or root items
-        assert!(!docs_paths.contains(&"Work".to_string()));
-        assert!(!docs_paths.contains(&"readme.md".to_string()));
-        assert!(!docs_paths.contains(&"Work/agenda.org".to_string()));
+        // Clearing the zone goes back to the database's
+        db.set_user_timezone("remote", None).unwrap();
+        assert_eq!(db.timezone_for("remote"), chrono_tz::Australia::Sydney);
     }
 
     #[test]
-    fn test_get_folder_contents_recursive_empty_folder() {
+    fn test_migrate_timestamps_to_utc() {
         let db = setup_test_database();
-        let user_id = "empty_test_user";
+        assert!(!db.migrate_timestamps_to_utc().unwrap());
 
-        // Create empty folder
-        let empty_id = db
-            .create_folder("Empty", None, user_id)
-            .expect("Failed to create empty folder");
+        // As written before: local times in the user's zone, else the database's
+        db.set_user_timezone("berlin", Some(chrono_tz::Europe::Berlin))
+            .unwrap();
+        let folder = db.create_folder("Inbox", None, "sydney").unwrap();
+        let note = db
+            .create_note(
+                "n1",
+                NoteContent {
+                    title: "Note",
+                    abstract_text: None,
+                    content: "text",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                None,
+                "berlin",
+            )
+            .unwrap();
+        db.connection
+            .execute_batch(
+                "UPDATE folders SET created_at = '2024-01-15 11:00:00', updated_at = '2024-07-01 10:00:00';
+                 UPDATE notes SET created_at = '2024-01-15 01:00:00', updated_at = '2024-01-15 01:00:00',
+                                  accessed_at = 'not a time';
+                 PRAGMA user_version = 0;",
+            )
+            .unwrap();
+        let versions = |db: &Database| -> i64 {
+            db.connection
+                .query_row("SELECT COUNT(*) FROM notes_history", [], |row| row.get(0))
+                .unwrap()
+        };
+        let history = versions(&db);
 
-        let contents = db
-            .get_folder_contents_recursive(&empty_id, user_id)
-            .expect("Failed to get empty folder contents");
+        assert!(db.migrate_timestamps_to_utc().unwrap());
+        assert!(!db.migrate_timestamps_to_utc().unwrap());
 
-        assert_eq!(contents.len(), 0);
+        let folder = db.get_folder_by_id(&folder, "sydney").unwrap().unwrap();
+        assert_eq!(
+            folder.created_at,
+            Utc.with_ymd_and_hms(2024, 1, 15, 0, 0, 0).unwrap()
+        );
+        // Sydney winters are on standard time, UTC+10
+        assert_eq!(
+            folder.updated_at,
+            Utc.with_ymd_and_hms(2024, 7, 1, 0, 0, 0).unwrap()
+        );
+        let (created_at, accessed_at): (String, String) = db
+            .connection
+            .query_row(
+                "SELECT created_at, accessed_at FROM notes WHERE id = ?1",
+                [&note],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(created_at, "2024-01-15 00:00:00");
+        // What doesn't parse is left alone, and nothing lands in the history
+        assert_eq!(accessed_at, "not a time");
+        assert_eq!(versions(&db), history);
+        db.update_note(&note, "Note", None, "edited", "md").unwrap();
+        assert_eq!(versions(&db), history + 1);
+
+        // Databases from before accessed_at and deleted_at only have the others
+        let db = Database::new(
+            Connection::open_in_memory().unwrap(),
+            Some(chrono_tz::Australia::Sydney),
+        );
+        db.connection
+            .execute_batch(
+                "CREATE TABLE folders (id TEXT, user_id TEXT, created_at DATETIME, updated_at DATETIME);
+                 CREATE TABLE notes (id TEXT, user_id TEXT, created_at DATETIME, updated_at DATETIME);
+                 INSERT INTO notes VALUES ('n1', 'user', '2024-01-15 11:00:00', '2024-01-15 11:00:00');",
+            )
+            .unwrap();
+        assert!(db.migrate_timestamps_to_utc().unwrap());
+        let created_at: String = db
+            .connection
+            .query_row("SELECT created_at FROM notes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(created_at, "2024-01-15 00:00:00");
     }
 
     #[test]
-    fn test_get_folder_contents_recursive_user_filtering() {
+    fn test_list_users() {
         let db = setup_test_database();
-        let user1 = "user1";
-        let user2 = "user2";
+        assert!(db.list_users().unwrap().is_empty());
 
-        // Create separate folder structures for each user (folders are now user-specific)
-        let user1_folder_id = db
-            .create_folder("User1Folder", None, user1)
-            .expect("Failed to create user1 folder");
-        let user2_folder_id = db
-            .create_folder("User2Folder", None, user2)
-            .expect("Failed to create user2 folder");
+        // Owners of existing rows count as known without being registered
+        db.create_folder("Inbox", None, "legacy").unwrap();
+        let profile = UserProfile {
+            display_name: Some("Alice"),
+            timezone: Some(chrono_tz::Europe::Berlin),
+        };
+        assert!(db.add_user("alice", profile).unwrap());
+        assert!(!db.add_user("alice", UserProfile::default()).unwrap());
+        assert_eq!(
+            db.get_user_timezone("alice").unwrap(),
+            Some(chrono_tz::Europe::Berlin)
+        );
+        assert_eq!(db.list_users().unwrap(), vec!["alice", "legacy"]);
 
-        // Create notes for each user in their own folders
-        db.create_note(
-            "note1",
-            NoteContent {
-                title: "note1",
-                abstract_text: None,
-                content: "User 1 content",
-                syntax: "md",
-            },
-            Some(&user1_folder_id),
-            user1,
-        )
-        .expect("Failed to create user1 note");
-        db.create_note(
-            "note2",
-            NoteContent {
-                title: "note2",
-                abstract_text: None,
-                content: "User 2 content",
-                syntax: "md",
-            },
-            Some(&user2_folder_id),
-            user2,
-        )
-        .expect("Failed to create user2 note");
+        db.connection.execute_batch("DROP TABLE users").unwrap();
+        assert_eq!(db.list_users().unwrap(), vec!["legacy"]);
+    }
 
-        // Test that each user only sees contents of their own folders
-        let user1_contents = db
-            .get_folder_contents_recursive(&user1_folder_id, user1)
-            .expect("Failed to get user1 contents");
+    #[test]
+    fn test_crtime_and_flags() {
+        let db = setup_test_database();
+        let user_id = "test_user";
+        let id = db
+            .create_note(
+                "n1",
+                NoteContent {
+                    title: "Note",
+                    abstract_text: None,
+                    content: "text",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                None,
+                user_id,
+            )
+            .unwrap();
+        let folder_id = db.create_folder("Dir", None, user_id).unwrap();
+        assert_eq!(db.get_note_by_id(&id).unwrap().unwrap().flags, None);
+
+        let crtime = Utc.with_ymd_and_hms(2019, 6, 7, 8, 9, 10).unwrap();
+        assert!(db.set_note_times(&id, None, None, Some(crtime)).unwrap());
+        assert!(db.set_note_flags(&id, 0x8000).unwrap());
+        let note = db.get_note_by_id(&id).unwrap().unwrap();
+        assert_eq!(note.created_at, crtime);
+        assert_eq!(note.flags, Some(0x8000));
+        assert_ne!(note.updated_at, crtime);
+
+        assert!(
+            db.set_folder_times(&folder_id, user_id, None, None, Some(crtime))
+                .unwrap()
+        );
+        assert!(!db.set_folder_flags(&folder_id, "someone_else", 1).unwrap());
+        assert!(db.set_folder_flags(&folder_id, user_id, 0x8000).unwrap());
+        let folder = db.get_folder_by_id(&folder_id, user_id).unwrap().unwrap();
+        assert_eq!(folder.created_at, crtime);
+        assert_eq!(folder.flags, Some(0x8000));
+
+        // Databases from before the column existed gain it
+        assert!(!db.migrate_file_flags().unwrap());
+        db.connection
+            .execute_batch(
+                "ALTER TABLE folders DROP COLUMN flags;
+                 ALTER TABLE notes DROP COLUMN flags;
+                 PRAGMA user_version = 1;",
+            )
+            .unwrap();
+        assert!(db.migrate_file_flags().unwrap());
+        assert!(!db.migrate_file_flags().unwrap());
+        assert_eq!(db.get_note_by_id(&id).unwrap().unwrap().flags, None);
+    }
 
-        // User2 cannot access user1's folder (folders are user-specific)
-        let user2_accessing_user1_folder = db
-            .get_folder_contents_recursive(&user1_folder_id, user2)
-            .expect("Failed to get user2 attempting user1 folder");
+    #[test]
+    fn test_inodes() {
+        let db = setup_test_database();
+        assert!(!db.migrate_inodes().unwrap());
+        assert!(db.inode_generation().unwrap() > 0);
+
+        let dir = db.inode_for_path("alice", "/Dir").unwrap();
+        let note = db.inode_for_path("alice", "/Dir/note.md").unwrap();
+        assert!(dir >= 2);
+        assert_ne!(dir, note);
+        // Stable, and per user
+        assert_eq!(db.inode_for_path("alice", "/Dir").unwrap(), dir);
+        assert_ne!(db.inode_for_path("bob", "/Dir").unwrap(), dir);
+        assert_eq!(
+            db.path_for_inode("alice", note).unwrap().as_deref(),
+            Some("/Dir/note.md")
+        );
+        assert_eq!(db.path_for_inode("bob", note).unwrap(), None);
+        assert_eq!(
+            db.stored_inode_for_path("alice", "/Dir/note.md").unwrap(),
+            Some(note)
+        );
+        assert_eq!(db.stored_inode_for_path("alice", "/New").unwrap(), None);
 
-        let user2_contents = db
-            .get_folder_contents_recursive(&user2_folder_id, user2)
-            .expect("Failed to get user2 contents");
+        // Renames carry the numbers below the folder along
+        let other = db.inode_for_path("alice", "/Other").unwrap();
+        db.rename_inode_paths("alice", "/Dir", "/Other").unwrap();
+        assert_eq!(db.inode_for_path("alice", "/Other").unwrap(), dir);
+        assert_eq!(
+            db.path_for_inode("alice", note).unwrap().as_deref(),
+            Some("/Other/note.md")
+        );
+        assert_eq!(db.path_for_inode("alice", other).unwrap(), None);
 
-        // User1 sees their own note
-        assert_eq!(user1_contents.len(), 1);
-        match &user1_contents[0] {
-            FileType::File { path } => assert_eq!(path, "note1.md"),
-            _ => panic!("Expected file, got directory"),
-        }
+        // Numbers of removed paths aren't handed out again
+        db.remove_inode_paths("alice", "/Other").unwrap();
+        assert_eq!(db.path_for_inode("alice", note).unwrap(), None);
+        let again = db.inode_for_path("alice", "/Other/note.md").unwrap();
+        assert!(again > note && again > other);
+    }
 
-        // User2 cannot see user1's folder contents (folder ownership check prevents it)
-        assert_eq!(user2_accessing_user1_folder.len(), 0);
+    #[test]
+    fn test_tag_filter() {
+        let db = setup_test_database();
+        let user_id = "filter_user";
 
-        // User2 sees their own note in their own folder
-        assert_eq!(user2_contents.len(), 1);
-        match &user2_contents[0] {
-            FileType::File { path } => assert_eq!(path, "note2.md"),
-            _ => panic!("Expected file, got directory"),
+        let project = db.create_folder("Project", None, user_id).unwrap();
+        let nested = db.create_folder("Nested", Some(&project), user_id).unwrap();
+        let other = db.create_folder("Other", None, user_id).unwrap();
+        for (id, parent) in [("deep", &nested), ("loose", &other)] {
+            db.create_note(
+                id,
+                NoteContent {
+                    title: id,
+                    abstract_text: None,
+                    content: "",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                Some(parent),
+                user_id,
+            )
+            .unwrap();
+        }
+        assert!(!db.folder_has_tag(&project, "work", user_id).unwrap());
+
+        db.add_note_tag("deep", "work", user_id).unwrap();
+        db.add_note_tag("deep", "work", user_id).unwrap();
+        assert_eq!(db.get_note_tags("deep").unwrap(), vec!["work"]);
+        assert!(db.note_has_tag("deep", "work", user_id).unwrap());
+        assert!(!db.note_has_tag("loose", "work", user_id).unwrap());
+        assert!(!db.note_has_tag("deep", "work", "someone_else").unwrap());
+
+        // Folders count notes anywhere below them
+        assert!(db.folder_has_tag(&project, "work", user_id).unwrap());
+        assert!(db.folder_has_tag(&nested, "work", user_id).unwrap());
+        assert!(!db.folder_has_tag(&other, "work", user_id).unwrap());
+
+        assert!(!db.remove_note_tag("deep", "work", "someone_else").unwrap());
+        assert!(db.remove_note_tag("deep", "work", user_id).unwrap());
+        assert!(!db.remove_note_tag("deep", "work", user_id).unwrap());
+        assert!(db.get_note_tags("deep").unwrap().is_empty());
+        db.add_note_tag("deep", "work", user_id).unwrap();
+
+        db.delete_note("deep").unwrap();
+        assert!(!db.folder_has_tag(&project, "work", user_id).unwrap());
+    }
+
+    #[test]
+    fn test_orphaned_notes() {
+        let db = setup_test_database();
+        let user_id = "orphan_user";
+
+        let kept = db.create_folder("Kept", None, user_id).unwrap();
+        let gone = db.create_folder("Gone", None, user_id).unwrap();
+        let below = db.create_folder("Below", Some(&gone), user_id).unwrap();
+        for (id, parent) in [
+            ("kept", Some(&kept)),
+            ("root", None),
+            ("lost", Some(&gone)),
+            ("deep", Some(&below)),
+        ] {
+            db.create_note(
+                id,
+                NoteContent {
+                    title: id,
+                    abstract_text: None,
+                    content: "",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                parent.map(String::as_str),
+                user_id,
+            )
+            .unwrap();
         }
+        assert!(!db.has_orphaned_notes(user_id).unwrap());
+
+        // A client deleting the folder without what it holds
+        db.connection
+            .execute(
+                "UPDATE folders SET deleted_at = CURRENT_TIMESTAMP WHERE id = ?1",
+                [&gone],
+            )
+            .unwrap();
+        assert!(db.has_orphaned_notes(user_id).unwrap());
+        assert!(!db.has_orphaned_notes("someone_else").unwrap());
+        let orphans: Vec<String> = db
+            .list_orphaned_notes(user_id)
+            .unwrap()
+            .into_iter()
+            .map(|note| note.id)
+            .collect();
+        assert_eq!(orphans, vec!["deep", "lost"]);
+
+        db.update_note_parent("lost", Some(&kept)).unwrap();
+        db.delete_note("deep").unwrap();
+        assert!(!db.has_orphaned_notes(user_id).unwrap());
     }
 
     #[test]
-    fn test_get_folder_contents_recursive_single_level() {
+    fn test_break_folder_cycles() {
         let db = setup_test_database();
-        let user_id = "single_level_user";
+        let user_id = "cycle_user";
+
+        let a = db.create_folder("A", None, user_id).unwrap();
+        let b = db.create_folder("B", Some(&a), user_id).unwrap();
+        let c = db.create_folder("C", Some(&b), user_id).unwrap();
+        let fine = db.create_folder("Fine", Some(&a), user_id).unwrap();
+        db.create_folder("C", None, user_id).unwrap();
+        assert!(db.break_folder_cycles().unwrap().is_empty());
+
+        // A -> C -> B -> A, and a folder that is its own parent
+        let looped = db.create_folder("Looped", None, user_id).unwrap();
+        db.connection
+            .execute_batch(&format!(
+                "UPDATE folders SET parent_id = '{c}' WHERE id = '{a}';
+                 UPDATE folders SET parent_id = id WHERE id = '{looped}';"
+            ))
+            .unwrap();
+        assert_eq!(db.get_folder_path_by_id(&fine, user_id).unwrap(), None);
+
+        let mut cycles = db.break_folder_cycles().unwrap();
+        cycles.sort_by_key(|cycle| cycle.len());
+        assert_eq!(cycles[0], vec![looped.clone()]);
+        let mut looping = vec![a.clone(), b.clone(), c.clone()];
+        looping.sort();
+        assert_eq!(cycles[1][0], looping[0]);
+        cycles[1].sort();
+        assert_eq!(cycles[1], looping);
+        assert!(db.break_folder_cycles().unwrap().is_empty());
+
+        // Every folder has a path again, a title taken in the root suffixed
+        let mut paths: Vec<String> = [&a, &b, &c, &fine, &looped]
+            .into_iter()
+            .map(|id| db.get_folder_path_by_id(id, user_id).unwrap().unwrap())
+            .collect();
+        assert!(paths.contains(&"Looped".to_string()));
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), 5);
+    }
 
-        // Create folder with only direct children
-        let folder_id = db
-            .create_folder("SingleLevel", None, user_id)
-            .expect("Failed to create folder");
-        let _child_folder_id = db
-            .create_folder("ChildFolder", Some(&folder_id), user_id)
-            .expect("Failed to create child folder");
+    #[test]
+    fn test_integrity_check() {
+        let db = setup_test_database();
+        let user_id = "check_user";
+
+        let folder = db.create_folder("Folder", None, user_id).unwrap();
+        for (id, parent) in [
+            ("kept", Some(&folder)),
+            ("stale", None),
+            ("lost", Some(&folder)),
+        ] {
+            db.create_note(
+                id,
+                NoteContent {
+                    title: id,
+                    abstract_text: None,
+                    content: "body",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                parent.map(String::as_str),
+                user_id,
+            )
+            .unwrap();
+        }
+        db.deduplicate_notes().unwrap();
+        assert_eq!(db.integrity_check(false).unwrap(), Vec::<String>::new());
+        assert_eq!(db.integrity_check(true).unwrap(), Vec::<String>::new());
+
+        // Changes made behind the triggers' back
+        db.connection
+            .execute_batch(&format!(
+                "UPDATE notes_fts SET content = 'old' WHERE id = 'stale';
+                 INSERT INTO notes_fts (id, title) VALUES ('ghost', 'ghost');
+                 UPDATE folders SET deleted_at = CURRENT_TIMESTAMP WHERE id = '{folder}';"
+            ))
+            .unwrap();
+        let problems = db.integrity_check(true).unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                "Note ghost is in the search index but not in notes",
+                "Note stale is indexed with outdated text",
+                "Note kept has no path (its folder is gone)",
+                "Note lost has no path (its folder is gone)",
+            ]
+        );
+    }
 
+    #[test]
+    fn test_snapshot() {
+        let db = setup_test_database();
+        let user_id = "snapshot_user";
+        let folder = db.create_folder("Folder", None, user_id).unwrap();
         db.create_note(
-            "file1",
-            NoteContent {
-                title: "file1",
-                abstract_text: None,
-                content: "Content 1",
-                syntax: "txt",
-            },
-            Some(&folder_id),
-            user_id,
-        )
-        .expect("Failed to create file1");
-        db.create_note(
-            "file2",
+            "note",
             NoteContent {
-                title: "file2",
+                title: "note",
                 abstract_text: None,
-                content: "Content 2",
+                content: "before",
                 syntax: "md",
+                extensionless: false,
             },
-            Some(&folder_id),
+            Some(&folder),
             user_id,
         )
-        .expect("Failed to create file2");
+        .unwrap();
 
-        let contents = db
-            .get_folder_contents_recursive(&folder_id, user_id)
-            .expect("Failed to get single level contents");
+        let dir = std::env::temp_dir().join(format!("snapshot-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dest = dir.join("copy.sqlite");
+        db.snapshot(&dest, None, Some("before import")).unwrap();
+        db.update_note("note", "note", None, "after", "md").unwrap();
 
-        assert_eq!(contents.len(), 3); // 1 folder + 2 files
+        let copy = Database::new(Connection::open(&dest).unwrap(), None);
+        let note = copy.get_note_by_id("note").unwrap().unwrap();
+        assert_eq!(note.content, "before");
+        assert_eq!(
+            copy.get_folder_path_by_id(&folder, user_id)
+                .unwrap()
+                .as_deref(),
+            Some("Folder")
+        );
+        let tag: String = copy
+            .connection
+            .query_row("SELECT tag FROM snapshot_info", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tag, "before import");
+
+        // Loaded back into memory, as it was when copied
+        let mut restored = Database::new(Connection::open_in_memory().unwrap(), None);
+        restored.restore_from(&dest, None).unwrap();
+        let note = restored.get_note_by_id("note").unwrap().unwrap();
+        assert_eq!(note.content, "before");
+        assert!(
+            restored
+                .restore_from(&dir.join("missing.sqlite"), None)
+                .is_err()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-        let paths: Vec<String> = contents
-            .iter()
-            .map(|item| match item {
-                FileType::Directory { path } => path.clone(),
-                FileType::File { path } => path.clone(),
-            })
+    #[test]
+    fn test_data_version() {
+        let dir = std::env::temp_dir().join(format!("data_version-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.sqlite");
+        let db = Database::new(Connection::open(&path).unwrap(), None);
+        let other = Database::new(Connection::open(&path).unwrap(), None);
+        db.connection
+            .execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1);")
+            .unwrap();
+        let before = db.data_version().unwrap();
+
+        // Its own commits don't count
+        db.connection
+            .execute("INSERT INTO t VALUES (2)", [])
+            .unwrap();
+        assert_eq!(db.data_version().unwrap(), before);
+
+        other
+            .connection
+            .execute("INSERT INTO t VALUES (3)", [])
+            .unwrap();
+        assert_ne!(db.data_version().unwrap(), before);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_changed_since() {
+        let db = setup_test_database();
+        let user_id = "changes_user";
+        let work = db.create_folder("Work", None, user_id).unwrap();
+        let other = db.create_folder("Other", None, user_id).unwrap();
+        for (id, parent_id) in [("moved", Some(work.as_str())), ("removed", None)] {
+            db.create_note(
+                id,
+                NoteContent {
+                    title: id,
+                    abstract_text: None,
+                    content: "one",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                parent_id,
+                user_id,
+            )
+            .unwrap();
+        }
+        db.connection
+            .execute_batch(
+                "UPDATE folders SET updated_at = '2000-01-01 00:00:00';
+                 UPDATE notes SET updated_at = '2000-01-01 00:00:00';",
+            )
+            .unwrap();
+        let since = Utc::now();
+        let changed = |db: &Database| {
+            let mut items = db.changed_since(user_id, since).unwrap();
+            items.sort_by(|a, b| a.id.cmp(&b.id));
+            items
+        };
+        assert!(changed(&db).is_empty());
+
+        // Purging history leaves them as they were
+        db.purge_history(&HistoryRetention {
+            keep_versions: Some(0),
+            keep_days: None,
+        })
+        .unwrap();
+        assert!(changed(&db).is_empty());
+
+        // A move shows in the note and both folders
+        db.update_note_parent("moved", Some(&other)).unwrap();
+        let mut expected = vec![
+            ("moved".to_string(), false),
+            (work.clone(), true),
+            (other.clone(), true),
+        ];
+        expected.sort();
+        let ids: Vec<_> = changed(&db)
+            .into_iter()
+            .map(|item| (item.id, item.is_folder))
             .collect();
+        assert_eq!(ids, expected);
+
+        // A deleted note is still found, as deleted
+        assert!(db.delete_note("removed").unwrap());
+        let removed = changed(&db)
+            .into_iter()
+            .find(|item| item.id == "removed")
+            .unwrap();
+        assert!(removed.deleted);
+        assert_eq!(removed.parent_id, None);
+
+        // Other users' notes and folders aren't
+        assert!(db.changed_since("nobody", since).unwrap().is_empty());
+    }
 
-        assert!(paths.contains(&"ChildFolder".to_string()));
-        assert!(paths.contains(&"file1.txt".to_string()));
-        assert!(paths.contains(&"file2.md".to_string()));
+    #[test]
+    fn test_tidy() {
+        let dir = std::env::temp_dir().join(format!("tidy-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.sqlite");
+        let db = Database::new(Connection::open(&path).unwrap(), None);
+        db.connection
+            .execute_batch(
+                "PRAGMA journal_mode = WAL;
+                 CREATE TABLE t (x);
+                 WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 64)
+                 INSERT INTO t SELECT zeroblob(4096) FROM n;
+                 DELETE FROM t;",
+            )
+            .unwrap();
+        let wal = dir.join("notes.sqlite-wal");
+        assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+        let free_pages = |db: &Database| -> i64 {
+            db.connection
+                .query_row("PRAGMA freelist_count", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert!(free_pages(&db) > 0);
+
+        assert!(db.tidy(false).unwrap());
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+        assert!(free_pages(&db) > 0);
+        // Everything written is in the database file itself
+        let copy = Database::new(Connection::open(&path).unwrap(), None);
+        assert!(copy.tidy(true).unwrap());
+        assert_eq!(free_pages(&copy), 0);
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+
+        // Another connection reading keeps the checkpoint from finishing
+        db.connection
+            .execute("INSERT INTO t VALUES (1)", [])
+            .unwrap();
+        let reading = copy.connection.unchecked_transaction().unwrap();
+        copy.connection
+            .query_row("SELECT COUNT(*) FROM t", [], |row| row.get::<_, i64>(0))
+            .unwrap();
+        db.connection
+            .execute("INSERT INTO t VALUES (2)", [])
+            .unwrap();
+        db.connection
+            .busy_timeout(std::time::Duration::ZERO)
+            .unwrap();
+        assert!(!db.tidy(false).unwrap());
+        drop(reading);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_get_child_count() {
+    fn test_search_ranking_and_syntax() {
         let db = setup_test_database();
-        let user_id = "count_test_user";
-        let other_user = "other_count_user";
+        let user_id = "ranking_user";
+        for (id, title, content) in [
+            (
+                "body",
+                "Notes",
+                "some text about rust and error handling in detail",
+            ),
+            ("title", "Rust", "a language"),
+            (
+                "apart",
+                "Far",
+                "error is one thing; much later comes handling",
+            ),
+        ] {
+            db.create_note(
+                id,
+                NoteContent {
+                    title,
+                    abstract_text: None,
+                    content,
+                    syntax: "md",
+                    extensionless: false,
+                },
+                None,
+                user_id,
+            )
+            .unwrap();
+        }
+        let ids = |query: &str| -> Vec<String> {
+            db.search_notes_ranked(query, user_id, None)
+                .unwrap()
+                .into_iter()
+                .map(|(note, _)| note.id)
+                .collect()
+        };
+        let words =
+            |words: &[&str]| -> Vec<String> { words.iter().map(|w| w.to_string()).collect() };
+
+        // A match in the title outranks one in the content
+        assert_eq!(ids("rust"), vec!["title", "body"]);
+        let ranked = db.search_notes_ranked("rust", user_id, Some(1)).unwrap();
+        assert_eq!(ranked.len(), 1);
+        assert!(ranked[0].1 < 0.0);
+
+        let column = QuerySyntax {
+            column: Some("content".to_string()),
+            ..QuerySyntax::default()
+        };
+        assert_eq!(fts_query(&words(&["rust"]), &column), "content : (rust)");
+        assert_eq!(ids(&fts_query(&words(&["rust"]), &column)), vec!["body"]);
 
-        // Create folder structure
-        let parent_folder_id = db
-            .create_folder("Parent", None, user_id)
-            .expect("Failed to create parent folder");
-        let _child_folder1_id = db
-            .create_folder("Child1", Some(&parent_folder_id), user_id)
-            .expect("Failed to create child folder 1");
-        let _child_folder2_id = db
-            .create_folder("Child2", Some(&parent_folder_id), user_id)
-            .expect("Failed to create child folder 2");
+        let phrase = QuerySyntax {
+            phrase: true,
+            ..QuerySyntax::default()
+        };
+        let query = fts_query(&words(&["error", "handling"]), &phrase);
+        assert_eq!(query, "\"error handling\"");
+        assert_eq!(ids(&query), vec!["body"]);
 
-        // Create notes in parent folder for different users
-        db.create_note(
-            "note1",
-            NoteContent {
-                title: "Note 1",
-                abstract_text: None,
-                content: "Content 1",
-                syntax: "md",
-            },
-            Some(&parent_folder_id),
-            user_id,
-        )
-        .expect("Failed to create note 1");
-        db.create_note(
-            "note2",
-            NoteContent {
-                title: "Note 2",
-                abstract_text: None,
-                content: "Content 2",
-                syntax: "txt",
+        let near = QuerySyntax {
+            near: Some(6),
+            ..QuerySyntax::default()
+        };
+        let query = fts_query(&words(&["error", "handling"]), &near);
+        assert_eq!(query, "NEAR(\"error\" \"handling\", 6)");
+        let mut matched = ids(&query);
+        matched.sort();
+        assert_eq!(matched, vec!["apart", "body"]);
+        let query = fts_query(
+            &words(&["error", "handling"]),
+            &QuerySyntax {
+                near: Some(2),
+                ..near
             },
-            Some(&parent_folder_id),
-            user_id,
-        )
-        .expect("Failed to create note 2");
+        );
+        assert_eq!(ids(&query), vec!["body"]);
+
+        // Quoted words lose their FTS5 meaning
+        assert_eq!(
+            fts_query(&words(&["say \"hi\"", "-x"]), &phrase),
+            "\"say \"\"hi\"\" -x\""
+        );
+        assert!(ids(&fts_query(&words(&["rust-lang:"]), &phrase)).is_empty());
+    }
+
+    #[test]
+    fn test_list_notes() {
+        let db = setup_test_database();
+        for (id, title, user_id) in [
+            ("b", "Beta", "lister"),
+            ("a", "Alpha", "lister"),
+            ("o", "Other", "someone"),
+        ] {
+            db.create_note(
+                id,
+                NoteContent {
+                    title,
+                    abstract_text: None,
+                    content: "text",
+                    syntax: "md",
+                    extensionless: false,
+                },
+                None,
+                user_id,
+            )
+            .unwrap();
+        }
+        db.delete_note("b").unwrap();
         db.create_note(
-            "note3",
+            "c",
             NoteContent {
-                title: "Note 3",
+                title: "Gamma",
                 abstract_text: None,
-                content: "Content 3",
+                content: "text",
                 syntax: "md",
+                extensionless: false,
             },
-            Some(&parent_folder_id),
-            other_user,
+            None,
+            "lister",
         )
-        .expect("Failed to create note 3 for other user");
+        .unwrap();
 
-        // Create notes at root level
+        let titles: Vec<String> = db
+            .list_notes("lister")
+            .unwrap()
+            .into_iter()
+            .map(|note| note.title)
+            .collect();
+        assert_eq!(titles, ["Alpha", "Gamma"]);
+    }
+
+    #[test]
+    fn test_link_graph() {
+        let db = setup_test_database();
+        let user_id = "graph_user";
+        let folder = db.create_folder("Projects", None, user_id).unwrap();
+        for (id, parent, content) in [
+            ("a", Some(&folder), "see [[b]] and [[missing]]"),
+            ("b", None, "back to [[a]]"),
+        ] {
+            db.create_note(
+                id,
+                NoteContent {
+                    title: id,
+                    abstract_text: None,
+                    content,
+                    syntax: "md",
+                    extensionless: false,
+                },
+                parent.map(String::as_str),
+                user_id,
+            )
+            .unwrap();
+        }
+
+        let graph = db.link_graph(user_id).unwrap();
+        assert_eq!(
+            graph.folders,
+            [(folder.clone(), None, "Projects".to_string())]
+        );
+        assert_eq!(
+            graph.notes,
+            [
+                ("a".to_string(), Some(folder), "Projects/a.md".to_string()),
+                ("b".to_string(), None, "b.md".to_string()),
+            ]
+        );
+        // Links to titles no note has lead nowhere
+        assert_eq!(
+            graph.links,
+            [
+                ("a".to_string(), "b".to_string()),
+                ("b".to_string(), "a".to_string())
+            ]
+        );
+        assert!(db.link_graph("someone_else").unwrap().notes.is_empty());
+    }
+
+    #[test]
+    fn test_set_note_encrypted() {
+        let db = setup_test_database();
+        let user_id = "encrypt_test_user";
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
         db.create_note(
-            "root1",
+            &id,
             NoteContent {
-                title: "Root 1",
+                title: "secret",
                 abstract_text: None,
-                content: "Root content",
+                content: "first draft",
                 syntax: "md",
+                extensionless: false,
             },
             None,
             user_id,
         )
-        .expect("Failed to create root note 1");
+        .unwrap();
+        db.update_note(&id, "secret", None, "plain text [[Other]]", "md")
+            .unwrap();
+        let note = db.get_note_by_id(&id).unwrap().unwrap();
+        assert!(!note.encrypted);
+
+        // Only at the revision read
+        assert!(
+            !db.set_note_encrypted(&id, note.revision + 1, "ciphertext", true)
+                .unwrap()
+        );
+        assert!(
+            db.set_note_encrypted(&id, note.revision, "ciphertext", true)
+                .unwrap()
+        );
+        let note = db.get_note_by_id(&id).unwrap().unwrap();
+        assert!(note.encrypted);
+        assert_eq!(note.content, "ciphertext");
+        assert!(db.get_note_meta_by_id(&id).unwrap().unwrap().encrypted);
+        // The plain text versions and links are gone
+        let history: i64 = db
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM notes_history WHERE id = ?1",
+                [&id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(history, 0);
+        assert!(db.list_backlinks("Other", user_id).unwrap().is_empty());
+
+        // A note renamed over an encrypted one takes its place as it is
+        let source = format!("{:x}", uuid::Uuid::new_v4().as_simple());
         db.create_note(
-            "root2",
+            &source,
             NoteContent {
-                title: "Root 2",
+                title: "secret.md.tmp",
                 abstract_text: None,
-                content: "Root content",
-                syntax: "org",
+                content: "plain",
+                syntax: "md",
+                extensionless: false,
             },
             None,
-            other_user,
+            user_id,
         )
-        .expect("Failed to create root note 2 for other user");
-
-        // Test counting children of parent folder with specific user
-        let (folder_count, note_count) = db
-            .get_child_count(Some(&parent_folder_id), user_id)
-            .expect("Failed to get child count for specific user");
-        assert_eq!(folder_count, 2); // Child1, Child2
-        assert_eq!(note_count, 2); // note1, note2 (only for user_id)
-
-        // Test counting children of parent folder with other user
-        let (folder_count, note_count) = db
-            .get_child_count(Some(&parent_folder_id), other_user)
-            .expect("Failed to get child count for other user");
-        assert_eq!(folder_count, 0); // Child1, Child2 are owned by user_id, not visible to other_user
-        assert_eq!(note_count, 1); // note3 (only for other_user)
-
-        // Test counting children of empty folder
-        let empty_folder_id = db
-            .create_folder("Empty", None, user_id)
-            .expect("Failed to create empty folder");
-        let (folder_count, note_count) = db
-            .get_child_count(Some(&empty_folder_id), user_id)
-            .expect("Failed to get empty folder child count");
-        assert_eq!(folder_count, 0);
-        assert_eq!(note_count, 0);
-
-        // Test counting root level items with specific user
-        let (folder_count, note_count) = db
-            .get_child_count(None, user_id)
-            .expect("Failed to get root count for specific user");
-        assert_eq!(folder_count, 2); // Parent, Empty (both owned by user_id)
-        assert_eq!(note_count, 1); // root1 (only for user_id)
+        .unwrap();
+        assert!(db.replace_note_content(&id, &source).unwrap());
+        assert!(!db.get_note_by_id(&id).unwrap().unwrap().encrypted);
+
+        // Databases from before the column existed gain it
+        assert!(!db.migrate_encrypted_notes().unwrap());
+        db.connection
+            .execute_batch(
+                "ALTER TABLE notes DROP COLUMN encrypted;
+                 PRAGMA user_version = 3;",
+            )
+            .unwrap();
+        assert!(db.migrate_encrypted_notes().unwrap());
+        assert!(!db.migrate_encrypted_notes().unwrap());
+        assert!(!db.get_note_by_id(&id).unwrap().unwrap().encrypted);
     }
 
     #[test]
-    fn test_get_child_count_edge_cases() {
+    fn test_set_note_lock() {
         let db = setup_test_database();
-        let user_id = "edge_test_user";
-
-        // Test with non-existent folder ID
-        let (folder_count, note_count) = db
-            .get_child_count(Some("non_existent_id"), user_id)
-            .expect("Failed to get count for non-existent folder");
-        assert_eq!(folder_count, 0);
-        assert_eq!(note_count, 0);
-
-        // Test with non-existent user ID
-        let folder_id = db
-            .create_folder("Test", None, user_id)
-            .expect("Failed to create test folder");
+        let user_id = "lock_test_user";
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
         db.create_note(
-            "test_note",
+            &id,
             NoteContent {
-                title: "Test",
+                title: "diary",
                 abstract_text: None,
-                content: "Content",
+                content: "secret",
                 syntax: "md",
+                extensionless: false,
             },
-            Some(&folder_id),
+            None,
             user_id,
         )
-        .expect("Failed to create test note");
+        .unwrap();
+        assert!(!db.get_note_by_id(&id).unwrap().unwrap().locked);
+        assert_eq!(db.get_note_lock(&id).unwrap(), None);
+
+        assert!(db.set_note_lock(&id, Some("salt:hash")).unwrap());
+        assert!(db.get_note_by_id(&id).unwrap().unwrap().locked);
+        assert!(db.get_note_meta_by_id(&id).unwrap().unwrap().locked);
+        assert_eq!(db.get_note_lock(&id).unwrap().as_deref(), Some("salt:hash"));
+        let listed = db.list_notes_by_parent(None, user_id, Page::ALL).unwrap();
+        assert!(listed[0].locked);
+
+        assert!(db.set_note_lock(&id, None).unwrap());
+        assert!(!db.get_note_by_id(&id).unwrap().unwrap().locked);
+        assert!(!db.set_note_lock("missing", None).unwrap());
+        assert_eq!(db.get_note_lock("missing").unwrap(), None);
+
+        // Databases from before the column existed gain it
+        assert!(!db.migrate_note_locks().unwrap());
+        db.connection
+            .execute_batch(
+                "ALTER TABLE notes DROP COLUMN lock_hash;
+                 PRAGMA user_version = 4;",
+            )
+            .unwrap();
+        assert!(db.migrate_note_locks().unwrap());
+        assert!(!db.migrate_note_locks().unwrap());
+        assert!(!db.get_note_by_id(&id).unwrap().unwrap().locked);
+    }
 
-        let (folder_count, note_count) = db
-            .get_child_count(Some(&folder_id), "non_existent_user")
-            .expect("Failed to get count for non-existent user");
-        assert_eq!(folder_count, 0); // No child folders created yet
-        assert_eq!(note_count, 0); // No notes for this user
+    #[test]
+    fn test_init_schema_failure() {
+        let db = Database::new(Connection::open_in_memory().unwrap(), None);
+        db.connection
+            .execute_batch("CREATE TABLE tags (name TEXT)")
+            .unwrap();
+        let e = db.init_schema().unwrap_err().to_string();
+        assert!(e.contains("tags"), "{e}");
+        assert!(!e.contains("CREATE TABLE folders"), "{e}");
+
+        // Nothing of it is left behind, so it isn't taken for a schema
+        assert!(!db.has_table("main", "folders").unwrap());
+        assert!(!db.has_table("main", "notes").unwrap());
+        assert_eq!(db.schema_state().unwrap(), SchemaState::Older(0));
+    }
 
-        // Test deeply nested structure
-        let mut current_parent = Some(folder_id.clone());
-        for i in 1..=3 {
-            let child_id = db
-                .create_folder(&format!("Level{i}"), current_parent.as_deref(), user_id)
-                .expect("Failed to create nested folder");
-            current_parent = Some(child_id);
-        }
+    #[test]
+    fn test_schema_state() {
+        let db = Database::new(Connection::open_in_memory().unwrap(), None);
+        assert_eq!(db.schema_state().unwrap(), SchemaState::Empty);
+        // Tables of the database's own stay
+        db.connection
+            .execute_batch("CREATE TABLE bookmarks (url TEXT)")
+            .unwrap();
+        assert_eq!(db.schema_state().unwrap(), SchemaState::Older(0));
+        assert!(db.init_schema().unwrap());
+        assert!(db.has_table("main", "bookmarks").unwrap());
+        assert_eq!(db.schema_state().unwrap(), SchemaState::Current);
+        assert_eq!(SchemaState::Current.problem(), None);
+
+        // Initializing again leaves the database as it is
+        let folder = db.create_folder("Inbox", None, "user").unwrap();
+        assert!(!db.init_schema().unwrap());
+        assert!(db.get_folder_by_id(&folder, "user").unwrap().is_some());
+
+        // Older databases are brought up to date by migrate
+        db.connection
+            .execute_batch(
+                "ALTER TABLE notes DROP COLUMN lock_hash;
+                 PRAGMA user_version = 4;",
+            )
+            .unwrap();
+        assert_eq!(db.schema_state().unwrap(), SchemaState::Older(4));
+        assert!(SchemaState::Older(4).problem().unwrap().contains("migrate"));
+        assert!(!db.init_schema().unwrap());
+        assert_eq!(db.migrate().unwrap(), ["Added the lock_hash column"]);
+        assert_eq!(db.schema_state().unwrap(), SchemaState::Current);
+        assert!(db.migrate().unwrap().is_empty());
+
+        db.connection
+            .execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1))
+            .unwrap();
+        assert_eq!(
+            db.schema_state().unwrap(),
+            SchemaState::Newer(SCHEMA_VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn test_migrate_baseline_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../sql/baseline.sql"))
+            .unwrap();
+        conn.execute_batch(
+            "INSERT INTO folders (id, title, user_id) VALUES ('f1', 'Inbox', 'user'), ('f2', 'Inbox', 'user');
+             INSERT INTO notes (id, title, content, user_id, parent_id) VALUES
+                 ('n1', 'Todo', 'See [[Ideas]]', 'user', 'f1'),
+                 ('n2', 'Ideas', 'rust', 'user', NULL),
+                 ('n3', 'Ideas', 'more', 'user', NULL);",
+        )
+        .unwrap();
+        let db = Database::new(conn, Some(chrono_tz::Australia::Sydney));
+        assert_eq!(db.schema_state().unwrap(), SchemaState::Older(0));
+
+        let done = db.migrate().unwrap();
+        assert!(done.contains(&"Added the deleted_at columns"));
+        assert_eq!(db.schema_state().unwrap(), SchemaState::Current);
+        assert!(db.migrate().unwrap().is_empty());
+
+        // Everything new databases have works on the migrated one
+        assert!(db.integrity_check(false).unwrap().is_empty());
+        assert_eq!(db.search_notes("rust", "user").unwrap()[0].id, "n2");
+        assert_eq!(db.list_backlinks("Ideas", "user").unwrap()[0].id, "n1");
+        let names: Vec<String> = db
+            .list_children(None, "user", false, Page::ALL)
+            .unwrap()
+            .into_iter()
+            .map(|child| match child {
+                Child::Folder(folder) => folder.title,
+                Child::Note(note) => note.title,
+            })
+            .collect();
+        assert_eq!(names, ["Inbox", "Inbox (1)", "Ideas", "Ideas (1)"]);
+        assert_eq!(
+            db.get_note_id_by_path("Inbox/Todo.md").unwrap().as_deref(),
+            Some("n1")
+        );
+        assert_eq!(db.list_users().unwrap(), ["user"]);
 
-        // The original folder should have 1 child (Level1)
-        let (folder_count, note_count) = db
-            .get_child_count(Some(&folder_id), user_id)
-            .expect("Failed to get count for nested structure");
-        assert_eq!(folder_count, 1); // Level1
-        assert_eq!(note_count, 1); // test_note
+        // and it mounts
+        crate::fuse_fs::ExampleFuseFs::new(
+            db.connection,
+            None,
+            "user".to_string(),
+            crate::fuse_fs::FsOptions::default(),
+        )
+        .unwrap();
     }
 }
@@ -0,0 +1,226 @@
+use chrono_tz::Tz;
+
+use crate::database::Note;
+
+/// Metadata read back from a front matter block written through the mount
+///
+/// Fields are `None` when the key is missing, leaving the column untouched.
+/// `id` and the timestamps are shown for reference only and are not parsed.
+#[derive(Debug, Default, PartialEq)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub abstract_text: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+/// Render the YAML block shown above a note's content with `--front-matter`,
+/// with its times in `timezone`
+pub fn render(note: &Note, tags: &[String], timezone: Tz) -> String {
+    let tags = tags.iter().map(|t| quote(t)).collect::<Vec<_>>().join(", ");
+    format!(
+        "---\nid: {}\ntitle: {}\nabstract: {}\ntags: [{}]\ncreated: {}\nupdated: {}\n---\n",
+        note.id,
+        quote(&note.title),
+        quote(note.abstract_text.as_deref().unwrap_or("")),
+        tags,
+        note.created_at
+            .with_timezone(&timezone)
+            .format("%Y-%m-%d %H:%M:%S"),
+        note.updated_at
+            .with_timezone(&timezone)
+            .format("%Y-%m-%d %H:%M:%S"),
+    )
+}
+
+/// Split `text` into its front matter and the content after it
+///
+/// Returns `None` when the text does not open with a complete `---` block,
+/// in which case the whole text is content.
+pub fn split(text: &str) -> Option<(FrontMatter, &str)> {
+    let rest = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))?;
+
+    let mut offset = 0;
+    let mut lines = Vec::new();
+    for line in rest.split_inclusive('\n') {
+        offset += line.len();
+        let trimmed = line.trim_end();
+        if trimmed == "---" || trimmed == "..." {
+            return Some((parse(&lines), &rest[offset..]));
+        }
+        lines.push(trimmed);
+    }
+    None
+}
+
+fn parse(lines: &[&str]) -> FrontMatter {
+    let mut front_matter = FrontMatter::default();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let value = value.trim();
+        match key.trim() {
+            "title" => front_matter.title = Some(unquote(value)),
+            "abstract" => front_matter.abstract_text = Some(unquote(value)),
+            "tags" if value.is_empty() => {
+                // Block list: one "- tag" per following indented line
+                let mut tags = Vec::new();
+                while let Some(item) = lines.get(i).and_then(|l| l.trim().strip_prefix('-')) {
+                    tags.push(unquote(item.trim()));
+                    i += 1;
+                }
+                front_matter.tags = Some(tags);
+            }
+            "tags" => {
+                let inner = value
+                    .strip_prefix('[')
+                    .and_then(|v| v.strip_suffix(']'))
+                    .unwrap_or(value);
+                front_matter.tags = Some(
+                    split_flow(inner)
+                        .iter()
+                        .map(|t| unquote(t))
+                        .filter(|t| !t.is_empty())
+                        .collect(),
+                );
+            }
+            _ => {}
+        }
+    }
+    front_matter
+}
+
+/// Split a flow sequence body on commas that are not inside quotes
+fn split_flow(s: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, ',') => {
+                items.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    items.push(s[start..].trim());
+    items
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn unquote(s: &str) -> String {
+    if let Some(inner) = s.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        let mut out = String::with_capacity(inner.len());
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            }
+        }
+        out
+    } else if let Some(inner) = s.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        inner.replace("''", "'")
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn note(title: &str, abstract_text: &str) -> Note {
+        Note {
+            id: "abc".to_string(),
+            title: title.to_string(),
+            abstract_text: Some(abstract_text.to_string()),
+            content: String::new(),
+            syntax: "md".to_string(),
+            parent_id: None,
+            user_id: "u".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            extensionless: false,
+            accessed_at: None,
+            mode: None,
+            uid: None,
+            gid: None,
+            flags: None,
+            content_size: 0,
+            revision: 0,
+            encrypted: false,
+            locked: false,
+        }
+    }
+
+    #[test]
+    fn test_render_round_trip() {
+        let tags = vec!["rust".to_string(), "a, \"b\"".to_string()];
+        let text = render(&note("Say \"hi\"", "line1\nline2"), &tags, chrono_tz::UTC) + "# Body\n";
+
+        let (front_matter, body) = split(&text).expect("front matter should parse");
+        assert_eq!(body, "# Body\n");
+        assert_eq!(front_matter.title.as_deref(), Some("Say \"hi\""));
+        assert_eq!(front_matter.abstract_text.as_deref(), Some("line1\nline2"));
+        assert_eq!(front_matter.tags, Some(tags));
+    }
+
+    #[test]
+    fn test_split_hand_written() {
+        let text = "---\ntitle: Plain title\ntags:\n  - one\n  - 'two'\n---\nbody";
+        let (front_matter, body) = split(text).unwrap();
+        assert_eq!(body, "body");
+        assert_eq!(front_matter.title.as_deref(), Some("Plain title"));
+        assert_eq!(front_matter.abstract_text, None);
+        assert_eq!(
+            front_matter.tags,
+            Some(vec!["one".to_string(), "two".to_string()])
+        );
+
+        // No block, or an unterminated one, leaves everything as content
+        assert!(split("# Heading\n").is_none());
+        assert!(split("---\ntitle: x\n").is_none());
+    }
+}
@@ -1,7 +1,8 @@
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
     error::Error,
-    path::Path,
+    path::{Path, PathBuf},
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
@@ -13,19 +14,431 @@ use fuser::Filesystem;
 use libc::ENOENT;
 use rusqlite::Connection;
 use std::ffi::OsStr;
+use unicode_normalization::{UnicodeNormalization, is_nfc};
 
 use fuser::{FileAttr, FileType, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request};
 
-use crate::database::{Database, NoteContent};
+use crate::database::{
+    Access, Child, Database, Folder, INDEX_TITLE, Note, NoteContent, NoteMeta, Page,
+};
+use crate::front_matter;
+use crate::ignore::{self, IgnoreList};
+use crate::json;
+use crate::syntax::SyntaxMap;
+
+mod access;
+mod assets;
+mod encryption;
+mod export;
+mod handles;
+mod html;
+mod interrupt;
+mod ioctl;
+mod locks;
+mod note_locks;
+mod notify;
+mod persist;
+mod poll;
+mod reload;
+mod scratch;
+mod shared;
+mod stats;
+mod virtual_dirs;
+mod virtual_files;
+mod xattr;
+use assets::AssetPath;
+use encryption::Decrypted;
+use export::{ExportPath, Exports};
+use handles::OpenHandle;
+use interrupt::InterruptWatch;
+use locks::LockTable;
+use notify::{KernelNotify, NotifierSlot};
+use persist::PersistTo;
+use reload::{DatabaseFile, LastSeen};
+use scratch::ScratchFile;
+use shared::SharedPath;
+use stats::{ErrorReply, Stats};
+use virtual_dirs::{LOST_FOUND_DIR, SEARCHES_DIR, VirtualPath, lost_found_name};
+
+/// Stand-in for '/' when a title is shown as a file name (U+2044 FRACTION SLASH).
+/// Must match the `replace()` calls in the path views of sql/init.sql.
+const SLASH_SUBSTITUTE: char = '\u{2044}';
+
+/// Suffix of the virtual metadata file shown next to each note with `--meta-sidecars`
+const META_SUFFIX: &str = ".meta.json";
+
+/// Children fetched from the database at a time while listing a directory
+const READDIR_PAGE: usize = 1000;
+
+/// Directory listings whose names are kept for the next readdir, at most
+const READDIR_RESUMES: usize = 16;
+
+/// First inode handed out when the database can't store one, far above
+/// those it allocates
+const TRANSIENT_INODES: u64 = 1 << 62;
+
+/// Default limit on the byte length of a single name (matches Linux NAME_MAX)
+pub const DEFAULT_MAX_NAME_LENGTH: usize = 255;
+
+/// Default time the kernel may cache attributes and entries (`--attr-ttl`, `--entry-ttl`)
+pub const DEFAULT_TTL: Duration = Duration::from_secs(1);
+
+/// Names editors give swap, backup and temporary files, which are kept out
+/// of the database; the first takes in every dotfile
+pub const DEFAULT_EDITOR_TEMP_PATTERNS: &[&str] = &[
+    DOTFILE_PATTERN,
+    // Vim/Neovim swap and backup files
+    ".*.swp",
+    ".*.swo",
+    ".*.tmp",
+    "*~",
+    // Emacs backup and auto-save files
+    "#*#",
+    ".#*",
+    // VSCode temporary files
+    ".vscode*",
+    // General temporary file patterns
+    "*.tmp",
+    "*.tmp.*",
+    "*.temp",
+    "*.temp.*",
+];
+
+/// The pattern of `DEFAULT_EDITOR_TEMP_PATTERNS` dropped by `--dotfile-notes`
+pub const DOTFILE_PATTERN: &str = ".*";
+
+/// Names programs probe for on every filesystem, answered ENOENT without
+/// a database query and never created
+pub const DEFAULT_SYSTEM_FILE_PATTERNS: &[&str] = &[
+    // Shared libraries
+    "*.so",
+    "*.so.[0-9]*",
+    "lib*.so*",
+    "glibc-hwcaps",
+    // Filesystem metadata files
+    ".Trash",
+    ".Trash-*",
+    "BDMV",
+    ".xdg-volume-info",
+    "autorun.inf",
+    ".hidden",
+    "System Volume Information",
+    "$RECYCLE.BIN",
+];
+
+/// Default number of path to inode mappings kept in memory
+pub const DEFAULT_MAX_INODES: usize = 100_000;
+
+/// Default number of notes listed in /.recent
+pub const DEFAULT_RECENT_LIMIT: usize = 50;
+
+/// Syntax recorded for files created without an extension (LICENSE, Makefile)
+pub const DEFAULT_SYNTAX: &str = "txt";
+
+/// Folder holding the daily notes listed in /journal
+pub const DEFAULT_JOURNAL_FOLDER: &str = ".journal";
+
+/// Folder holding the templates new notes start from
+pub const DEFAULT_TEMPLATES_FOLDER: &str = ".templates";
+
+/// Permission bits reported for notes that were never chmod'ed
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+
+/// Permission bits reported for folders that were never chmod'ed
+pub const DEFAULT_DIR_MODE: u16 = 0o755;
+
+/// Owner reported for entries that were never chown'ed
+pub const DEFAULT_UID: u32 = 501;
+pub const DEFAULT_GID: u32 = 20;
+
+/// Parse an octal permission mode from the command line (e.g. "644" or "0o644")
+pub fn parse_mode(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches("0o");
+    match u16::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("expected an octal mode such as 644, got '{s}'")),
+    }
+}
+
+/// Parse a cache timeout in seconds, such as "1", "0.5" or "0"
+pub fn parse_ttl(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("expected a number of seconds such as 1 or 0.5, got '{s}'"))
+}
+
+/// What the mount shows of other users' notes in the directories it lists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OtherUsers {
+    /// Leave them out of listings and answer lookups with ENOENT
+    #[default]
+    Hide,
+    /// List them without permission bits and answer open with EACCES
+    Deny,
+}
+
+/// Parse `--other-users` ("hide" or "deny")
+pub fn parse_other_users(s: &str) -> Result<OtherUsers, String> {
+    match s {
+        "hide" => Ok(OtherUsers::Hide),
+        "deny" => Ok(OtherUsers::Deny),
+        _ => Err(format!("expected hide or deny, got '{s}'")),
+    }
+}
+
+/// Behavioural switches for the mounted filesystem, set from the command line
+#[derive(Debug, Clone)]
+pub struct FsOptions {
+    /// On a name collision in create/mkdir/rename, store the entry as
+    /// "title (n)" instead of failing with EEXIST
+    pub auto_rename: bool,
+    /// NFC-normalize names coming from the kernel and names listed by readdir
+    pub normalize_unicode: bool,
+    /// Match names case-insensitively in lookup/create/rename, keeping stored case
+    pub case_insensitive: bool,
+    /// Longest name (in bytes) accepted by create/mkdir/rename
+    pub max_name_length: usize,
+    /// Syntax stored for names without an extension; the name itself is kept
+    pub default_syntax: String,
+    /// Extra EXT=SYNTAX rows written to the syntax_extensions table at mount
+    pub syntax_overrides: Vec<(String, String)>,
+    /// Only notes of these syntaxes (or extensions) are listed and found by
+    /// lookups; all of them when empty
+    pub only_syntax: Vec<String>,
+    /// Only notes carrying this tag are shown, and folders holding one of
+    /// them somewhere below; notes created through the mount get the tag
+    pub filter_tag: Option<String>,
+    /// gitignore-style patterns of entries kept out of the mount: hidden from
+    /// listings and lookups, and refused by create, mkdir and rename
+    pub ignore: IgnoreList,
+    /// Globs of names treated as editor temporary files: created and removed
+    /// as asked, but never stored
+    pub editor_temp_patterns: Vec<String>,
+    /// Globs of names looked up with ENOENT at once and refused by create
+    pub system_file_patterns: Vec<String>,
+    /// Show title, abstract, tags, id and timestamps as a YAML block above the
+    /// content, and store edits to the block back into those columns
+    pub front_matter: bool,
+    /// Answer lookups of "<file>.meta.json" with an editable JSON view of the
+    /// note's id, abstract, syntax, parent_id and timestamps (not listed by readdir)
+    pub meta_sidecars: bool,
+    /// Number of notes listed in /.recent
+    pub recent_limit: usize,
+    /// Group /.recent into one subdirectory per day
+    pub recent_by_day: bool,
+    /// Number the matches in /.searches/<name>/ by rank ("01 title.md")
+    pub ranked_searches: bool,
+    /// List daily notes as /journal/YYYY/MM/DD.md, creating today's on lookup
+    pub journal: bool,
+    /// Folder path (e.g. ".journal" or "Notes/Daily") the daily notes are stored in
+    pub journal_folder: String,
+    /// Folder path whose notes are copied into new notes of the same syntax
+    pub templates_folder: String,
+    /// Keep a Markdown note's title (and so its file name) in sync with a
+    /// "# Heading" on its first line
+    pub title_from_heading: bool,
+    /// Permission bits of notes without a stored mode
+    pub file_mode: u16,
+    /// Permission bits of folders (and the root) without a stored mode
+    pub dir_mode: u16,
+    /// Owner of entries without a stored uid/gid
+    pub uid: u32,
+    pub gid: u32,
+    /// Open notes with FOPEN_DIRECT_IO, so every read comes from the database
+    pub direct_io: bool,
+    /// Keep the kernel's cached pages of a note across opens while the note
+    /// is unchanged (otherwise they are dropped on every open)
+    pub auto_cache: bool,
+    /// Print a latency histogram of each operation on unmount
+    pub latency_summary: bool,
+    /// On unmount, update the query planner's statistics and checkpoint the
+    /// WAL into the database file
+    pub optimize_on_unmount: bool,
+    /// On unmount, also VACUUM the database (implies `optimize_on_unmount`)
+    pub vacuum_on_unmount: bool,
+    /// List a "<title>_assets" directory next to each note, holding its
+    /// attachments as files (it shadows a real folder of the same name)
+    pub assets: bool,
+    /// List a read-only "<title>.links" directory next to each note, with a
+    /// symlink to each note its [[wikilinks]] name
+    pub link_dirs: bool,
+    /// List a read-only "<title>.html" rendering next to each Markdown note
+    pub html_previews: bool,
+    /// List `/.export/<format>/<path>`: the notes converted by pandoc to PDF,
+    /// DOCX and HTML when read
+    pub pandoc_export: bool,
+    /// The pandoc run for `pandoc_export`
+    pub pandoc: PathBuf,
+    /// age identity file decrypting encrypted notes, and whose recipients
+    /// notes are encrypted to; without one they can only be read as ciphertext
+    pub identity: Option<PathBuf>,
+    /// The age run to encrypt and decrypt notes
+    pub age: PathBuf,
+    /// Let rmdir of a non-empty folder delete everything inside it (so
+    /// `rm -r` gets past entries it can't unlink itself)
+    pub recursive_rmdir: bool,
+    /// Whether other users' notes are hidden or listed but inaccessible
+    pub other_users: OtherUsers,
+    /// List the folders other users shared with this one as
+    /// /shared/<owner>/<folder>, writable where they granted write access
+    pub shared: bool,
+    /// Path to inode mappings kept in memory before the least recently used
+    /// are evicted (their numbers are kept in the database)
+    pub max_inodes: usize,
+    /// How long the kernel may cache attributes before asking again
+    pub attr_ttl: Duration,
+    /// How long the kernel may cache a name lookup (and the attributes
+    /// returned with it) before asking again
+    pub entry_ttl: Duration,
+    /// Folder (path from the top of the user's notes) mounted as the root;
+    /// nothing outside it is reachable
+    pub subdir: Option<String>,
+    /// The database was opened read-only (`--read-only`), next to a writable
+    /// mount elsewhere: inode numbers it hasn't stored yet aren't stored
+    pub read_only: bool,
+}
+
+impl Default for FsOptions {
+    fn default() -> Self {
+        Self {
+            auto_rename: false,
+            normalize_unicode: false,
+            case_insensitive: false,
+            max_name_length: DEFAULT_MAX_NAME_LENGTH,
+            default_syntax: DEFAULT_SYNTAX.to_string(),
+            syntax_overrides: Vec::new(),
+            only_syntax: Vec::new(),
+            filter_tag: None,
+            ignore: IgnoreList::default(),
+            editor_temp_patterns: DEFAULT_EDITOR_TEMP_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            system_file_patterns: DEFAULT_SYSTEM_FILE_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            front_matter: false,
+            meta_sidecars: false,
+            recent_limit: DEFAULT_RECENT_LIMIT,
+            recent_by_day: false,
+            ranked_searches: false,
+            journal: false,
+            journal_folder: DEFAULT_JOURNAL_FOLDER.to_string(),
+            templates_folder: DEFAULT_TEMPLATES_FOLDER.to_string(),
+            title_from_heading: false,
+            file_mode: DEFAULT_FILE_MODE,
+            dir_mode: DEFAULT_DIR_MODE,
+            uid: DEFAULT_UID,
+            gid: DEFAULT_GID,
+            direct_io: false,
+            auto_cache: false,
+            latency_summary: false,
+            optimize_on_unmount: false,
+            vacuum_on_unmount: false,
+            assets: false,
+            link_dirs: false,
+            html_previews: false,
+            pandoc_export: false,
+            pandoc: PathBuf::from("pandoc"),
+            identity: None,
+            age: PathBuf::from("age"),
+            recursive_rmdir: false,
+            other_users: OtherUsers::Hide,
+            shared: false,
+            max_inodes: DEFAULT_MAX_INODES,
+            attr_ttl: DEFAULT_TTL,
+            entry_ttl: DEFAULT_TTL,
+            subdir: None,
+            read_only: false,
+        }
+    }
+}
 
-const TTL: Duration = Duration::from_secs(1); // 1 second
+/// The folder mounted as the root with `--subdir`
+struct Subdir {
+    id: String,
+    /// Its database path, as stored ("Work/Projects")
+    path: String,
+}
 
 pub struct ExampleFuseFs {
     inode_map: HashMap<String, u64>,
     reverse_inode_map: HashMap<u64, String>,
+    /// Next inode for paths that couldn't be given a lasting one (the
+    /// database is read-only, say)
     next_inode: u64,
+    /// Generation of every inode number; they are never reused, so it only
+    /// changes with the database (see `inodes` in init.sql)
+    generation: u64,
+    /// When each mapped inode was last looked up, on `inode_clock`, for eviction
+    inode_last_used: HashMap<u64, u64>,
+    inode_clock: u64,
+    /// Mappings past which `evict_inodes` looks for some to drop:
+    /// `--max-inodes`, or more while the kernel holds that many
+    inode_evict_at: usize,
+    /// References the kernel holds to each inode: entries replied minus
+    /// those it has forgotten. Inodes without any are absent.
+    lookup_counts: HashMap<u64, u64>,
     db: Database,
     user_id: String,
+    options: FsOptions,
+    /// Resolved `--subdir`, if any
+    subdir: Option<Subdir>,
+    syntax_map: SyntaxMap,
+    /// File contents (front matter included) of notes being written with
+    /// `--front-matter`, by inode. Writes land at offsets into what the writer
+    /// sees, so they are applied here and the result parsed back on every write.
+    front_matter_buffers: HashMap<u64, Vec<u8>>,
+    /// Pending contents of .meta.json sidecars being written, by inode;
+    /// parsed and stored on flush/release once the JSON is complete
+    sidecar_buffers: HashMap<u64, Vec<u8>>,
+    /// Pending contents of attachments being written, by inode; stored on flush/release
+    asset_buffers: HashMap<u64, Vec<u8>>,
+    /// Companions of notes removed through the mount, by path, which can
+    /// still be looked up (empty) and removed until their folder is listed
+    /// again: `rm -r` lists a folder before it removes anything, so it gets
+    /// to them after their note
+    removed_companions: HashMap<String, FileType>,
+    /// Names listed so far by readdir calls that filled their buffer, by
+    /// (directory inode, offset the next call starts at), so resuming there
+    /// doesn't read every child before the offset again
+    readdir_names: HashMap<(u64, usize), HashSet<String>>,
+    /// Folders made through the mount with `--filter-tag`, shown while they
+    /// hold no tagged note yet
+    made_folders: HashSet<String>,
+    /// macOS metadata files (._*, .DS_Store), by inode; kept only in memory
+    scratch_files: HashMap<u64, ScratchFile>,
+    /// Advisory locks taken through fcntl/flock on the mount
+    locks: LockTable,
+    /// Handles open for writing on notes, by file handle
+    handles: HashMap<u64, OpenHandle>,
+    next_fh: u64,
+    /// The version of a virtual file each handle last read it at, by file
+    /// handle, which polls wait to change
+    virtual_seen: HashMap<u64, u64>,
+    /// Notes converted for `/.export`
+    exports: Exports,
+    /// Encrypted notes decrypted, for `--identity`
+    decrypted: Decrypted,
+    /// Ids of the locked notes unlocked until the mount ends
+    unlocked: HashSet<String>,
+    /// (revision, updated_at) of each note when it was last opened, for `--auto-cache`
+    cached_versions: HashMap<u64, (i64, DateTime<Utc>)>,
+    /// Cancels database work for callers that were interrupted
+    interrupts: InterruptWatch,
+    /// Drops entries removed underneath the mount from the kernel's cache
+    notify: KernelNotify,
+    /// The database file, if it is reopened when replaced
+    database_file: Option<DatabaseFile>,
+    /// Where an in-memory database is saved on unmount
+    persist_to: Option<PersistTo>,
+    /// The database at the last look, to notice other processes' commits
+    /// and what they changed
+    last_seen: Option<LastSeen>,
+    /// Counters since mount, shown in `/.stats` and dumped on SIGUSR1
+    stats: Stats,
 }
 
 impl ExampleFuseFs {
@@ -33,10 +446,10 @@ impl ExampleFuseFs {
         if path == "/" {
             return true; // Root is always a directory
         }
-        let db_path = Self::normalize_path_for_db(path);
+        let db_path = self.db_path(path);
         match self
             .db
-            .get_folder_id_by_path(db_path, self.user_id.as_str())
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
         {
             Ok(Some(_id)) => true, // Folder exists
             Ok(None) => false,     // Not a folder (file or doesn't exist)
@@ -49,112 +462,1203 @@ impl ExampleFuseFs {
         UNIX_EPOCH + Duration::from_secs(dt.timestamp() as u64)
     }
 
+    /// Permission bits of a note, as last set by chmod; none for a note of
+    /// another user or one that is locked
+    fn note_perm(&self, id: &str, owner: &str, mode: Option<u32>, locked: bool) -> u16 {
+        if owner != self.user_id || self.is_locked(id, locked) {
+            return 0;
+        }
+        mode.map_or(self.options.file_mode, |mode| (mode & 0o7777) as u16)
+    }
+
+    /// The id of the note at `db_path` as the mount sees it: the user's own,
+    /// or with `--other-users deny` another user's; in `/lost+found`, the
+    /// orphaned note listed under that name
+    fn note_id_by_path(&self, db_path: &str) -> rusqlite::Result<Option<String>> {
+        if let Some(name) = self.fuse_path(db_path).as_deref().and_then(lost_found_name) {
+            return Ok(self.orphan_id(name));
+        }
+        let id = self.db.get_visible_note_id_by_path(
+            db_path,
+            self.user_id.as_str(),
+            self.options.other_users == OtherUsers::Deny,
+        )?;
+        if self.options.only_syntax.is_empty() && self.options.filter_tag.is_none() {
+            return Ok(id);
+        }
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        let shown = self
+            .db
+            .get_note_meta_by_id(&id)?
+            .is_some_and(|note| self.note_shown(&note.id, &note.syntax));
+        Ok(shown.then_some(id))
+    }
+
+    /// Whether the note `id` of `syntax` is shown, as limited by
+    /// `--only-syntax` and `--filter-tag`
+    fn note_shown(&self, id: &str, syntax: &str) -> bool {
+        if !self.syntax_shown(syntax) {
+            return false;
+        }
+        let Some(tag) = &self.options.filter_tag else {
+            return true;
+        };
+        match self.db.note_has_tag(id, tag, self.user_id.as_str()) {
+            Ok(tagged) => tagged,
+            Err(e) => {
+                error!("Unable to check the tags of note {id}: {e}");
+                false
+            }
+        }
+    }
+
+    /// Whether the folder `id` is shown: with `--filter-tag`, only if a note
+    /// below it carries the tag (or it was made through this mount)
+    fn folder_shown(&self, id: &str) -> bool {
+        let Some(tag) = &self.options.filter_tag else {
+            return true;
+        };
+        if self.made_folders.contains(id) {
+            return true;
+        }
+        match self.db.folder_has_tag(id, tag, self.user_id.as_str()) {
+            Ok(tagged) => tagged,
+            Err(e) => {
+                error!("Unable to check the tags below folder {id}: {e}");
+                false
+            }
+        }
+    }
+
+    /// With `--filter-tag`, tags a note created through the mount so it stays shown
+    fn tag_new_note(&self, id: &str) {
+        let Some(tag) = &self.options.filter_tag else {
+            return;
+        };
+        if let Err(e) = self.db.add_note_tag(id, tag, self.user_id.as_str()) {
+            error!("Unable to tag new note {id} with {tag}: {e}");
+        }
+    }
+
+    /// Whether notes of `syntax` are shown, as limited by `--only-syntax`
+    fn syntax_shown(&self, syntax: &str) -> bool {
+        self.options.only_syntax.is_empty()
+            || self.options.only_syntax.iter().any(|shown| shown == syntax)
+    }
+
+    /// Whether `child` is shown in listings
+    fn child_shown(&self, child: &Child) -> bool {
+        match child {
+            Child::Folder(folder) => self.folder_shown(&folder.id),
+            Child::Note(note) => self.note_shown(&note.id, &note.syntax),
+        }
+    }
+
+    /// EACCES unless note `id` belongs to the mount's user
+    fn check_note_owner(&self, id: &str) -> Result<(), i32> {
+        match self.db.get_note_meta_by_id(id) {
+            Ok(Some(note)) if note.user_id == self.user_id => Ok(()),
+            Ok(Some(_)) => Err(libc::EACCES),
+            Ok(None) => Err(ENOENT),
+            Err(e) => {
+                error!("Unable to check the owner of note {id}: {e}");
+                Err(Self::errno_for(&e, libc::EIO))
+            }
+        }
+    }
+
+    /// Permission bits of a folder, as last set by chmod
+    fn folder_perm(&self, folder: &Folder) -> u16 {
+        folder
+            .mode
+            .map_or(self.options.dir_mode, |mode| (mode & 0o7777) as u16)
+    }
+
+    /// Answer getxattr/listxattr: the length when `size` is 0, else the data
+    fn reply_xattr(&self, data: &[u8], size: u32, reply: fuser::ReplyXattr) {
+        if size == 0 {
+            reply.size(data.len() as u32);
+        } else if data.len() > size as usize {
+            self.reply_error(reply, libc::ERANGE);
+        } else {
+            reply.data(data);
+        }
+    }
+
+    /// A time passed to utimens, as stored in the database
+    fn time_or_now_to_datetime(time: fuser::TimeOrNow) -> DateTime<Utc> {
+        match time {
+            fuser::TimeOrNow::SpecificTime(time) => DateTime::<Utc>::from(time),
+            fuser::TimeOrNow::Now => Utc::now(),
+        }
+    }
+
     /// Normalize a FUSE path for database queries
     ///
     /// The FUSE layer uses paths with leading slashes (e.g., "/1", "/1/2"),
     /// but the database stores paths without leading slashes (e.g., "1", "1/2").
-    /// This function strips the leading slash for database queries.
+    /// This function strips the leading slash for database queries, and with
+    /// `--subdir` puts the mounted folder's path in front.
     ///
     /// Special case: "/" (root) remains as "/" since it has special handling
-    fn normalize_path_for_db(fuse_path: &str) -> &str {
-        if fuse_path == "/" {
-            "/"
-        } else if let Some(stripped) = fuse_path.strip_prefix('/') {
-            stripped
+    fn db_path<'a>(&self, fuse_path: &'a str) -> Cow<'a, str> {
+        let relative = fuse_path.strip_prefix('/').unwrap_or(fuse_path);
+        match &self.subdir {
+            Some(subdir) if relative.is_empty() => Cow::Owned(subdir.path.clone()),
+            Some(subdir) => Cow::Owned(format!("{}/{relative}", subdir.path)),
+            None if relative.is_empty() => Cow::Borrowed("/"),
+            None => Cow::Borrowed(relative),
+        }
+    }
+
+    /// The FUSE path of a note or folder at `db_path`; none outside `--subdir`
+    fn fuse_path(&self, db_path: &str) -> Option<String> {
+        let relative = match &self.subdir {
+            Some(subdir) => db_path
+                .strip_prefix(subdir.path.as_str())?
+                .strip_prefix('/')?,
+            None => db_path,
+        };
+        Some(format!("/{relative}"))
+    }
+
+    /// Id of the folder listed at the root: none for the top of the user's
+    /// notes, or the `--subdir` folder
+    fn root_folder_id(&self) -> Option<String> {
+        self.subdir.as_ref().map(|subdir| subdir.id.clone())
+    }
+
+    /// Key of `path` in the inodes table, which holds paths from the top of
+    /// the user's notes so numbers agree between mounts of different subdirs
+    fn inode_key<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        match &self.subdir {
+            Some(subdir) if path == "/" => Cow::Owned(format!("/{}", subdir.path)),
+            Some(subdir) => Cow::Owned(format!("/{}{path}", subdir.path)),
+            None => Cow::Borrowed(path),
+        }
+    }
+
+    /// The mount path of an inodes table key; none outside `--subdir`
+    fn path_from_inode_key(&self, key: String) -> Option<String> {
+        match &self.subdir {
+            Some(subdir) => {
+                let rest = key.strip_prefix('/')?.strip_prefix(subdir.path.as_str())?;
+                rest.starts_with('/').then(|| rest.to_string())
+            }
+            None => Some(key),
+        }
+    }
+
+    /// Render a database title as a single path component
+    ///
+    /// Titles written by the companion app may contain '/', which would split
+    /// the name into several path components, so it is swapped for U+2044.
+    fn title_to_name(&self, title: &str) -> String {
+        let name = title.replace('/', &SLASH_SUBSTITUTE.to_string());
+        if self.options.normalize_unicode {
+            name.nfc().collect()
         } else {
-            fuse_path
+            name
         }
     }
 
-    fn get_path_from_inode(&self, inode: u64) -> Option<&String> {
-        self.reverse_inode_map.get(&inode)
+    /// Apply the configured normalization to a name received from the kernel
+    fn incoming_name<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.options.normalize_unicode && !is_nfc(name) {
+            Cow::Owned(name.nfc().collect())
+        } else {
+            Cow::Borrowed(name)
+        }
     }
 
-    fn get_or_create_inode(&mut self, path: &str) -> u64 {
-        if let Some(&inode) = self.inode_map.get(path) {
-            return inode;
+    /// Inverse of `title_to_name`, applied to names coming from the kernel
+    fn name_to_title(name: &str) -> String {
+        name.replace(SLASH_SUBSTITUTE, "/")
+    }
+
+    /// Split a file name into the (title, syntax, extensionless) stored for a note
+    ///
+    /// Names without an extension keep the whole name as the title and get the
+    /// configured default syntax, flagged so `note_file_name` gives it back unchanged.
+    fn parse_note_name(&self, name: &str) -> (String, String, bool) {
+        let path = Path::new(name);
+        match (path.file_stem(), path.extension()) {
+            (Some(stem), Some(ext)) => (
+                Self::name_to_title(&stem.to_string_lossy()),
+                self.syntax_map.syntax_for_extension(&ext.to_string_lossy()),
+                false,
+            ),
+            _ => (
+                Self::name_to_title(name),
+                self.options.default_syntax.clone(),
+                true,
+            ),
         }
+    }
 
-        let inode = self.next_inode;
-        self.next_inode += 1;
-        self.inode_map.insert(path.to_string(), inode);
-        self.reverse_inode_map.insert(inode, path.to_string());
-        inode
+    /// File name shown for a note, the inverse of `parse_note_name`
+    fn note_file_name(&self, title: &str, syntax: &str, extensionless: bool) -> String {
+        if extensionless {
+            self.title_to_name(title)
+        } else {
+            format!(
+                "{}.{}",
+                self.title_to_name(title),
+                self.syntax_map.extension_for_syntax(syntax)
+            )
+        }
+    }
+
+    /// The name a file requested as `name` is listed under, e.g. "a.markdown"
+    /// becomes "a.md", so every extension alias resolves to the same note
+    fn canonical_note_name(&self, name: &str) -> String {
+        let (title, syntax, extensionless) = self.parse_note_name(name);
+        self.note_file_name(&title, &syntax, extensionless)
     }
 
-    fn is_system_file(path: &str) -> bool {
-        // Extract filename from path
-        let filename = path.split('/').next_back().unwrap_or(path);
+    /// Initial content for a new note of `syntax` created in `parent_id`
+    ///
+    /// Copied from the template note of that syntax in `--templates-folder`
+    /// ("default.<ext>" if there are several). Notes created in the templates
+    /// folder itself start empty.
+    fn template_content(&self, parent_id: Option<&str>, syntax: &str) -> String {
+        let db_path = self.db_path(&self.options.templates_folder);
+        let folder_id = match self
+            .db
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
+        {
+            Ok(Some(id)) if parent_id != Some(id.as_str()) => id,
+            Ok(_) => return String::new(),
+            Err(e) => {
+                error!("Unable to look up templates folder: {e}");
+                return String::new();
+            }
+        };
+        match self
+            .db
+            .find_template_content(&folder_id, syntax, self.user_id.as_str())
+        {
+            Ok(content) => content.unwrap_or_default(),
+            Err(e) => {
+                error!("Unable to read template for syntax {syntax}: {e}");
+                String::new()
+            }
+        }
+    }
 
-        // Common system files that programs try to access
+    /// Link count of a note: its own name plus its hard links
+    fn note_nlink(&self, note_id: &str) -> u32 {
+        match self.db.get_note_link_count(note_id) {
+            Ok(count) => count,
+            Err(e) => {
+                error!("Unable to count links to note {note_id}: {e}");
+                1
+            }
+        }
+    }
 
-        // Shared libraries
-        filename.ends_with(".so") ||
-        filename.ends_with(".so.1") ||
-        filename.ends_with(".so.6") ||
-        filename == "glibc-hwcaps" ||
-        filename.starts_with("lib") && filename.contains(".so") ||
+    /// Link count of a folder (the root for `root_folder_id`): its own entry, its "."
+    /// and the ".." of each subdirectory
+    fn folder_nlink(&self, folder_id: Option<&str>) -> u32 {
+        let virtual_dirs = if folder_id == self.root_folder_id().as_deref() {
+            self.root_virtual_dir_count()
+        } else {
+            0
+        };
+        match self
+            .db
+            .get_subfolder_count(folder_id, self.user_id.as_str())
+        {
+            Ok(count) => 2 + (count + virtual_dirs) as u32,
+            Err(e) => {
+                error!("Unable to count subfolders of {folder_id:?}: {e}");
+                2
+            }
+        }
+    }
 
-        // Filesystem metadata files
-        filename == ".Trash" ||
-        filename.starts_with(".Trash-") ||
-        filename == "BDMV" ||
-        filename == ".xdg-volume-info" ||
-        filename == "autorun.inf" ||
-        filename == ".hidden" ||
-        filename == "System Volume Information" ||
-        filename == "$RECYCLE.BIN"
+    /// Path of the note that the hard link at `path` names, if it is one
+    fn hard_link_target(&self, path: &str) -> Option<String> {
+        let db_path = self.db_path(path);
+        let alias = self
+            .db
+            .get_alias_by_path(&db_path, self.user_id.as_str())
+            .ok()??;
+        let note_path = self.db.get_note_path_by_id(&alias.note_id).ok()??;
+        self.fuse_path(&note_path)
     }
 
-    fn is_editor_temp_file(filename: &str) -> bool {
-        // ignore all dotfiles
-        if filename.starts_with('.') {
-            return true;
+    /// Hard links in the folder `parent_id` (at `dir_path`), listed with the
+    /// inode of the note they name
+    fn hard_link_entries(
+        &mut self,
+        parent_id: Option<&str>,
+        dir_path: &str,
+    ) -> Vec<(u64, FileType, String)> {
+        let aliases = match self
+            .db
+            .list_aliases_by_parent(parent_id, self.user_id.as_str())
+        {
+            Ok(aliases) => aliases,
+            Err(e) => {
+                error!("readdir: Unable to get hard links in {dir_path}: {e}");
+                return Vec::new();
+            }
+        };
+        let mut entries = Vec::new();
+        for alias in aliases {
+            if !self.note_shown(&alias.note_id, &alias.syntax) {
+                continue;
+            }
+            let Ok(Some(note_path)) = self.db.get_note_path_by_id(&alias.note_id) else {
+                continue;
+            };
+            let Some(note_path) = self.fuse_path(&note_path) else {
+                continue;
+            };
+            let ino = self.get_or_create_inode(&note_path);
+            let name = self.note_file_name(&alias.title, &alias.syntax, alias.extensionless);
+            entries.push((ino, FileType::RegularFile, name));
         }
-        // Vim/Neovim swap files
-        if filename.starts_with('.') && filename.ends_with(".swp") {
-            return true;
+        entries
+    }
+
+    /// True for "index.<ext>" names, whose note holds the folder's own content
+    fn is_index_name(&self, name: &str) -> bool {
+        let (title, _, _) = self.parse_note_name(name);
+        if self.options.case_insensitive {
+            title.eq_ignore_ascii_case(INDEX_TITLE)
+        } else {
+            title == INDEX_TITLE
         }
-        if filename.starts_with('.') && filename.ends_with(".swo") {
-            return true;
+    }
+
+    /// Find the note called `name` in the directory at `parent_path`
+    ///
+    /// Tries the name as given, then its canonical spelling, so "a.markdown"
+    /// reaches a note listed as "a.md". Returns the note id and the path it
+    /// was found under.
+    fn resolve_note(
+        &self,
+        parent_path: &str,
+        name: &str,
+    ) -> rusqlite::Result<Option<(String, String)>> {
+        let canonical = self.canonical_note_name(name);
+        let mut candidates = vec![name];
+        if canonical != name {
+            candidates.push(&canonical);
         }
-        if filename.starts_with('.') && filename.ends_with(".tmp") {
-            return true;
+        for candidate in candidates {
+            let path = if parent_path == "/" {
+                format!("/{candidate}")
+            } else {
+                format!("{parent_path}/{candidate}")
+            };
+            if let Some(id) = self.note_id_by_path(&self.db_path(&path))? {
+                return Ok(Some((id, path)));
+            }
         }
+        Ok(None)
+    }
 
-        // Vim backup files
-        if filename.ends_with('~') {
-            return true;
+    /// Bytes presented as the file contents of a note
+    ///
+    /// Writes collected on an open handle that aren't stored yet, or else the
+    /// stored content, or with `--front-matter` the generated block followed
+    /// by the content (or the pending write buffer, if any).
+    fn note_bytes(&self, ino: u64, note: &Note) -> Vec<u8> {
+        if let Some(pending) = self.pending_bytes(ino) {
+            return pending.clone();
+        }
+        if !self.options.front_matter {
+            return self.note_content(note).into_owned().into_bytes();
         }
+        if let Some(buffer) = self.front_matter_buffers.get(&ino) {
+            return buffer.clone();
+        }
+        let tags = match self.db.get_note_tags(&note.id) {
+            Ok(tags) => tags,
+            Err(e) => {
+                error!("note_bytes: Unable to get tags for {}: {e}", note.id);
+                Vec::new()
+            }
+        };
+        let timezone = self.db.timezone_for(self.user_id.as_str());
+        let mut text = front_matter::render(note, &tags, timezone);
+        text.push_str(&self.note_content(note));
+        text.into_bytes()
+    }
 
-        // Emacs backup and auto-save files
-        if filename.starts_with('#') && filename.ends_with('#') {
-            return true;
+    /// Length of `note_bytes`, read from the stored `content_size` unless a
+    /// front matter block has to be generated or the note decrypted
+    fn note_size(&self, ino: u64, note: &Note) -> usize {
+        if let Some(pending) = self.pending_bytes(ino) {
+            return pending.len();
+        }
+        if self.options.front_matter || self.decrypts(note.encrypted) {
+            return self.note_bytes(ino, note).len();
+        }
+        note.content_size as usize
+    }
+
+    /// `note_size` for a note whose content wasn't loaded, which is only read
+    /// when a front matter block has to be generated or the note decrypted
+    fn note_meta_size(&self, ino: u64, meta: &NoteMeta) -> usize {
+        if let Some(pending) = self.pending_bytes(ino) {
+            return pending.len();
+        }
+        if !self.options.front_matter && !self.decrypts(meta.encrypted) {
+            return meta.content_size as usize;
+        }
+        if let Some(buffer) = self.front_matter_buffers.get(&ino) {
+            return buffer.len();
+        }
+        match self.db.get_note_by_id(&meta.id) {
+            Ok(Some(note)) => self.note_bytes(ino, &note).len(),
+            _ => meta.content_size as usize,
+        }
+    }
+
+    /// Store new file contents for the note at `path` (inode `ino`)
+    ///
+    /// With `--front-matter` a leading block updates title, abstract and tags,
+    /// and with `--title-from-heading` a leading "# Heading" updates the title
+    /// of a Markdown note. A title that collides with a sibling is left unchanged.
+    ///
+    /// Returns false, storing nothing, if the content changed in the database
+    /// since `note` was read.
+    fn save_note_bytes(
+        &mut self,
+        ino: u64,
+        path: &str,
+        note: &Note,
+        bytes: Vec<u8>,
+    ) -> rusqlite::Result<bool> {
+        let text = String::from_utf8_lossy(&bytes).to_string();
+        if self.options.front_matter {
+            self.front_matter_buffers.insert(ino, bytes);
+        }
+        let (meta, body) = match front_matter::split(&text) {
+            Some((meta, body)) if self.options.front_matter => (meta, body),
+            _ => (front_matter::FrontMatter::default(), text.as_str()),
+        };
+
+        // An edited front matter title wins over the heading, which wins over the old title
+        let title = meta
+            .title
+            .filter(|t| !t.trim().is_empty() && *t != note.title)
+            .or_else(|| self.heading_title(note, body))
+            .unwrap_or_else(|| note.title.clone());
+        let abstract_text = meta.abstract_text.or_else(|| note.abstract_text.clone());
+        let body = self.stored_form(note, body)?;
+        let (saved, renamed) = match self.db.update_note_at_revision(
+            &note.id,
+            note.revision,
+            &title,
+            abstract_text.as_deref(),
+            &body,
+            &note.syntax,
+        ) {
+            Ok(saved) => (saved, saved && title != note.title),
+            Err(e) if Database::is_unique_violation(&e) => {
+                error!("write: Title {title:?} is taken, keeping {:?}", note.title);
+                let saved = self.db.update_note_at_revision(
+                    &note.id,
+                    note.revision,
+                    &note.title,
+                    abstract_text.as_deref(),
+                    &body,
+                    &note.syntax,
+                )?;
+                (saved, false)
+            }
+            Err(e) => return Err(e),
+        };
+        if !saved {
+            error!(
+                "write: Note {} changed in the database since it was read (revision {}); not overwriting it",
+                note.id, note.revision
+            );
+            self.front_matter_buffers.remove(&ino);
+            return Ok(false);
+        }
+        if let Some(tags) = meta.tags {
+            self.db
+                .set_note_tags(&note.id, &tags, self.user_id.as_str())?;
+        }
+
+        if renamed {
+            let name = self.note_file_name(&title, &note.syntax, note.extensionless);
+            let new_path = match path.rsplit_once('/') {
+                Some(("", _)) | None => format!("/{name}"),
+                Some((parent, _)) => format!("{parent}/{name}"),
+            };
+            debug!("write: Title changed, {path} is now {new_path}");
+            self.update_inode_mappings(path, &new_path);
+        }
+        self.refresh_handles(&note.id);
+        Ok(true)
+    }
+
+    /// rename of the note `source_id` (at `source_path`) over the note
+    /// `target_id`: the target takes the source's content and the source is
+    /// removed, so the target keeps its id, history and links
+    fn replace_note(
+        &mut self,
+        source_id: &str,
+        source_path: &str,
+        target_id: &str,
+    ) -> Result<(), i32> {
+        // A locked note isn't written over either
+        self.check_note_unlocked(target_id)?;
+        let target_path = match self.db.get_note_path_by_id(target_id) {
+            Ok(Some(path)) => self.fuse_path(&path).ok_or(ENOENT)?,
+            Ok(None) => return Err(ENOENT),
+            Err(e) => {
+                error!("rename: Unable to find path of note {target_id}: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        if let Some(&source_ino) = self.inode_map.get(source_path) {
+            self.commit_pending(source_ino)?;
+        }
+        // An editor saving an encrypted note through a new file renamed over
+        // it: the new content is encrypted too (and takes over the flag)
+        let encrypted = |id: &str| {
+            self.db
+                .get_note_meta_by_id(id)
+                .ok()
+                .flatten()
+                .is_some_and(|note| note.encrypted)
+        };
+        if encrypted(target_id) && !encrypted(source_id) {
+            let source_ino = self.get_or_create_inode(source_path);
+            self.set_note_encrypted(source_ino, source_path, source_id, true)
+                .map_err(|errno| {
+                    if errno == libc::ENOKEY {
+                        libc::EACCES
+                    } else {
+                        errno
+                    }
+                })?;
+        }
+        match self.db.replace_note_content(target_id, source_id) {
+            Ok(true) => {}
+            Ok(false) => return Err(ENOENT),
+            Err(e) => {
+                error!("rename: Unable to replace {target_path} with {source_path}: {e}");
+                return Err(libc::EIO);
+            }
+        }
+        debug!("rename: {source_path} replaced the content of {target_path}");
+
+        // As with any rename the kernel now knows the target by the source's
+        // inode, and the target's old inode is gone
+        if let Some(old_ino) = self.remove_inode_mapping(&target_path) {
+            self.release_inode(old_ino);
+        }
+        self.update_inode_mappings(source_path, &target_path);
+        let ino = self.get_or_create_inode(&target_path);
+        self.front_matter_buffers.remove(&ino);
+        self.retarget_handles(source_id, target_id, ino);
+        Ok(())
+    }
+
+    /// With `--title-from-heading`, the title given by a Markdown note's first
+    /// line when it is an ATX heading ("# Title" or "# Title #")
+    fn heading_title(&self, note: &Note, body: &str) -> Option<String> {
+        if !self.options.title_from_heading
+            || note.syntax != self.syntax_map.syntax_for_extension("md")
+        {
+            return None;
+        }
+        let heading = body.lines().next()?.strip_prefix("# ")?;
+        let title = heading.trim().trim_end_matches('#').trim_end();
+        (!title.is_empty()).then(|| title.to_string())
+    }
+
+    /// The note whose metadata sidecar lives at `path`, if `path` is one
+    fn sidecar_note(&self, path: &str) -> Option<Note> {
+        if !self.options.meta_sidecars {
+            return None;
+        }
+        let note_path = path.strip_suffix(META_SUFFIX)?;
+        let note_id = self
+            .db
+            .get_visible_note_id_by_path(&self.db_path(note_path), self.user_id.as_str(), false)
+            .ok()??;
+        self.db.get_note_by_id(&note_id).ok()?
+    }
+
+    /// The note listed next to `path` under its title plus `suffix` (e.g.
+    /// "<title>_assets"), if `path` is named that way
+    ///
+    /// Notes of the same title but different syntax share the name; the first
+    /// listed owns it.
+    fn companion_note(&self, path: &str, suffix: &str) -> Option<NoteMeta> {
+        let (parent, name) = path.rsplit_once('/')?;
+        let title_name = name.strip_suffix(suffix)?;
+        if title_name.is_empty() {
+            return None;
+        }
+        let parent_id = if parent.is_empty() {
+            self.root_folder_id()
+        } else {
+            Some(
+                self.db
+                    .get_folder_id_by_path(&self.db_path(parent), self.user_id.as_str())
+                    .ok()??,
+            )
+        };
+        self.db
+            .list_note_meta_by_parent(parent_id.as_deref(), self.user_id.as_str())
+            .ok()?
+            .into_iter()
+            .find(|note| {
+                self.title_to_name(&note.title) == title_name
+                    && self.note_shown(&note.id, &note.syntax)
+            })
+    }
+
+    /// Name and kind `child` is listed under
+    fn child_entry_name(&self, child: &Child) -> (String, FileType) {
+        match child {
+            Child::Folder(folder) => (self.title_to_name(&folder.title), FileType::Directory),
+            Child::Note(note) => (
+                self.note_file_name(&note.title, &note.syntax, note.extensionless),
+                FileType::RegularFile,
+            ),
+        }
+    }
+
+    /// Whether any of `--assets`, `--link-dirs` and `--html-previews` is on
+    fn companions_enabled(&self) -> bool {
+        self.options.assets || self.options.link_dirs || self.options.html_previews
+    }
+
+    /// Entries listed next to `note` in `dir_path` by `--assets`, `--link-dirs`
+    /// and `--html-previews`
+    fn companion_entries(
+        &mut self,
+        dir_path: &str,
+        note: &NoteMeta,
+    ) -> Vec<(u64, FileType, String)> {
+        [
+            self.asset_dir_entry(dir_path, note),
+            self.note_links_entry(dir_path, note),
+            self.html_entry(dir_path, note),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Keep the companions of the note at `note_path` around, empty, once the
+    /// note is removed
+    fn keep_removed_companions(&mut self, note_path: &str, note: &NoteMeta) {
+        if !self.companions_enabled() {
+            return;
+        }
+        let dir_path = match note_path.rsplit_once('/') {
+            Some(("", _)) | None => "/",
+            Some((dir_path, _)) => dir_path,
+        };
+        for (_, kind, name) in self.companion_entries(dir_path, note) {
+            let path = if dir_path == "/" {
+                format!("/{name}")
+            } else {
+                format!("{dir_path}/{name}")
+            };
+            self.removed_companions.insert(path, kind);
+        }
+    }
+
+    /// Remove the companion left behind at `path` (a directory if `dir`), if
+    /// there is one
+    fn remove_companion_left_behind(&mut self, path: &str, dir: bool) -> bool {
+        match self.removed_companions.get(path) {
+            Some(&kind) if (kind == FileType::Directory) == dir => {
+                self.removed_companions.remove(path);
+                if let Some(inode) = self.remove_inode_mapping(path) {
+                    self.release_inode(inode);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Attributes of a companion left behind by `keep_removed_companions`
+    fn removed_companion_attr(&self, ino: u64, kind: FileType) -> FileAttr {
+        match kind {
+            FileType::Directory => self.virtual_dir_attr(ino),
+            _ => FileAttr {
+                kind,
+                perm: self.options.file_mode & !0o222,
+                nlink: 1,
+                ..self.virtual_dir_attr(ino)
+            },
+        }
+    }
+
+    /// Contents of a sidecar: the pending write buffer, or freshly rendered JSON
+    fn sidecar_bytes(&self, ino: u64, note: &Note) -> Vec<u8> {
+        if let Some(buffer) = self.sidecar_buffers.get(&ino) {
+            return buffer.clone();
+        }
+        let timezone = self.db.timezone_for(self.user_id.as_str());
+        let timestamp = |dt: &DateTime<Utc>| {
+            dt.with_timezone(&timezone)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        };
+        json::Value::Object(vec![
+            ("id".to_string(), note.id.as_str().into()),
+            ("abstract".to_string(), note.abstract_text.as_deref().into()),
+            ("syntax".to_string(), note.syntax.as_str().into()),
+            ("parent_id".to_string(), note.parent_id.as_deref().into()),
+            ("created_at".to_string(), timestamp(&note.created_at).into()),
+            ("updated_at".to_string(), timestamp(&note.updated_at).into()),
+        ])
+        .to_string_pretty()
+        .into_bytes()
+    }
+
+    fn sidecar_attr(&self, ino: u64, note: &Note) -> FileAttr {
+        let size = self.sidecar_bytes(ino, note).len();
+        FileAttr {
+            ino,
+            size: size as u64,
+            blocks: size.div_ceil(512) as u64,
+            atime: Self::datetime_to_systemtime(&note.accessed_at.unwrap_or(note.updated_at)),
+            mtime: Self::datetime_to_systemtime(&note.updated_at),
+            ctime: Self::datetime_to_systemtime(&note.updated_at),
+            crtime: Self::datetime_to_systemtime(&note.created_at),
+            kind: FileType::RegularFile,
+            perm: self.note_perm(&note.id, &note.user_id, note.mode, note.locked),
+            nlink: 1,
+            uid: note.uid.unwrap_or(self.options.uid),
+            gid: note.gid.unwrap_or(self.options.gid),
+            rdev: 0,
+            flags: note.flags.unwrap_or(0),
+            blksize: 512,
+        }
+    }
+
+    /// Store the JSON written to the sidecar at `ino`, if any is pending
+    ///
+    /// `abstract`, `syntax` and `parent_id` are applied when present; the other
+    /// keys are informational. Returns EINVAL for malformed JSON or values.
+    fn apply_sidecar(&mut self, ino: u64) -> Result<(), i32> {
+        let Some(bytes) = self.sidecar_buffers.remove(&ino) else {
+            return Ok(());
+        };
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            // Truncated but not yet rewritten (e.g. a shell redirect closing a dup'd fd)
+            self.sidecar_buffers.insert(ino, bytes);
+            return Ok(());
+        }
+        let path = self.get_path_from_inode(ino).ok_or(ENOENT)?;
+        let note = self.sidecar_note(&path).ok_or(ENOENT)?;
+        let text = String::from_utf8(bytes).map_err(|_| libc::EINVAL)?;
+        let value = json::parse(&text).map_err(|e| {
+            error!("sidecar: Invalid JSON written to {path}: {e}");
+            libc::EINVAL
+        })?;
+
+        let abstract_text = match value.get("abstract") {
+            None => note.abstract_text.clone(),
+            Some(json::Value::Null) => None,
+            Some(json::Value::String(s)) => Some(s.clone()),
+            Some(_) => return Err(libc::EINVAL),
+        };
+        let syntax = match value.get("syntax") {
+            None => note.syntax.clone(),
+            Some(json::Value::String(s)) if !s.is_empty() => s.clone(),
+            Some(_) => return Err(libc::EINVAL),
+        };
+        let parent_id = match value.get("parent_id") {
+            None => note.parent_id.clone(),
+            Some(json::Value::Null) => None,
+            Some(json::Value::String(id)) => {
+                match self.db.get_folder_by_id(id, self.user_id.as_str()) {
+                    Ok(Some(_)) => Some(id.clone()),
+                    Ok(None) => {
+                        error!("sidecar: No folder with id {id}");
+                        return Err(libc::EINVAL);
+                    }
+                    Err(_) => return Err(libc::EIO),
+                }
+            }
+            Some(_) => return Err(libc::EINVAL),
+        };
+
+        let to_errno = |e: rusqlite::Error| {
+            if Database::is_unique_violation(&e) {
+                libc::EEXIST
+            } else {
+                error!("sidecar: Failed to update note {}: {e}", note.id);
+                libc::EIO
+            }
+        };
+        self.db
+            .update_note(
+                &note.id,
+                &note.title,
+                abstract_text.as_deref(),
+                &note.content,
+                &syntax,
+            )
+            .map_err(to_errno)?;
+        if parent_id != note.parent_id {
+            self.db
+                .update_note_parent(&note.id, parent_id.as_deref())
+                .map_err(to_errno)?;
+        }
+
+        // A new syntax or parent moves the note (and its sidecar)
+        let note_path = path.strip_suffix(META_SUFFIX).unwrap_or(&path).to_string();
+        if let Ok(Some(new_path)) = self.db.get_note_path_by_id(&note.id)
+            && let Some(new_path) = self.fuse_path(&new_path)
+            && new_path != note_path
+        {
+            self.update_inode_mappings(&note_path, &new_path);
+            self.update_inode_mappings(&path, &format!("{new_path}{META_SUFFIX}"));
+        }
+        Ok(())
+    }
+
+    /// The at most `size` bytes of `bytes` a read at `offset` returns (none
+    /// past the end); replying with more than was asked for fails the read
+    fn read_range(bytes: &[u8], offset: i64, size: u32) -> &[u8] {
+        let start = (offset.max(0) as usize).min(bytes.len());
+        let end = start.saturating_add(size as usize).min(bytes.len());
+        &bytes[start..end]
+    }
+
+    /// Apply a write of `data` at `offset` to `current`
+    ///
+    /// Offset 0 replaces everything; later offsets overwrite or extend in place.
+    fn apply_write(mut current: Vec<u8>, offset: i64, data: &[u8]) -> Vec<u8> {
+        if offset == 0 {
+            // Overwrite from the beginning
+            return data.to_vec();
+        }
+
+        // Insert/append at offset
+        let start_pos = offset as usize;
+        if start_pos > current.len() {
+            // If offset is beyond current content, pad with zeros
+            current.resize(start_pos, 0);
+        }
+
+        // Replace or extend content
+        if start_pos + data.len() <= current.len() {
+            // Replace existing content at offset
+            current[start_pos..start_pos + data.len()].copy_from_slice(data);
+        } else {
+            // Extend content - truncate at offset and append new data
+            current.truncate(start_pos);
+            current.extend_from_slice(data);
+        }
+        current
+    }
+
+    /// Path under which a folder found at `requested` is tracked in the inode map
+    ///
+    /// With case-insensitive matching the kernel may ask for "notes" while the
+    /// folder is stored as "Notes"; keying inodes by the stored spelling keeps
+    /// one inode per entry so renames and deletes find it.
+    fn stored_folder_path(&self, folder_id: &str, requested: &str) -> String {
+        if !self.options.case_insensitive {
+            return requested.to_string();
+        }
+        match self
+            .db
+            .get_folder_path_by_id(folder_id, self.user_id.as_str())
+        {
+            Ok(Some(path)) => self
+                .fuse_path(&path)
+                .unwrap_or_else(|| requested.to_string()),
+            _ => requested.to_string(),
+        }
+    }
+
+    /// Note counterpart of `stored_folder_path`
+    fn stored_note_path(&self, note_id: &str, requested: &str) -> String {
+        if !self.options.case_insensitive {
+            return requested.to_string();
+        }
+        match self.db.get_note_path_by_id(note_id) {
+            Ok(Some(path)) => self
+                .fuse_path(&path)
+                .unwrap_or_else(|| requested.to_string()),
+            _ => requested.to_string(),
+        }
+    }
+
+    /// EPERM for a new entry at `path` that `--ignore` or the system file
+    /// denylist keeps out of the mount
+    fn check_not_denied(&self, op: &str, path: &str, is_dir: bool) -> Result<(), i32> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        if self.is_system_file(name) {
+            debug!("{op}: Refusing system file {path}");
+            return Err(libc::EPERM);
+        }
+        if self.options.ignore.is_ignored(path, is_dir) {
+            debug!("{op}: Refusing ignored path {path}");
+            return Err(libc::EPERM);
+        }
+        Ok(())
+    }
+
+    /// Check a name before it is stored as a title by create/mkdir/rename
+    ///
+    /// Returns the errno to reply with: ENAMETOOLONG past the configured limit,
+    /// EINVAL for control characters or names that amount to "." or "..".
+    fn validate_name(&self, name: &str) -> Result<(), i32> {
+        if name.len() > self.options.max_name_length {
+            return Err(libc::ENAMETOOLONG);
+        }
+        if name.is_empty() || name.chars().any(char::is_control) {
+            return Err(libc::EINVAL);
+        }
+        let trimmed = name.trim();
+        if trimmed == "." || trimmed == ".." {
+            return Err(libc::EINVAL);
+        }
+        Ok(())
+    }
+
+    /// The path of `inode`; one evicted from the map (see `evict_inodes`) is
+    /// read back from the database
+    fn get_path_from_inode(&self, inode: u64) -> Option<String> {
+        if let Some(path) = self.reverse_inode_map.get(&inode) {
+            return Some(path.clone());
+        }
+        if inode >= TRANSIENT_INODES {
+            return None;
+        }
+        match self.db.path_for_inode(self.user_id.as_str(), inode) {
+            Ok(key) => key.and_then(|key| self.path_from_inode_key(key)),
+            Err(e) => {
+                error!("get_path_from_inode: Unable to look up inode {inode}: {e}");
+                None
+            }
+        }
+    }
+
+    fn get_or_create_inode(&mut self, path: &str) -> u64 {
+        let found = self.inode_map.get(path).copied();
+        self.stats.inode_lookup(found.is_some());
+        if let Some(inode) = found {
+            self.touch_inode(inode);
+            return inode;
+        }
+
+        // Kept in the database, so the kernel (and NFS clients holding file
+        // handles) see the same number after a remount
+        let key = self.inode_key(path);
+        let stored = if self.options.read_only {
+            // Numbers the writer gave out are kept; the rest last this mount
+            self.db
+                .stored_inode_for_path(self.user_id.as_str(), &key)
+                .map(|inode| {
+                    inode.unwrap_or_else(|| {
+                        self.next_inode += 1;
+                        self.next_inode - 1
+                    })
+                })
+        } else {
+            self.db.inode_for_path(self.user_id.as_str(), &key)
+        };
+        let inode = match stored {
+            // A database that replaced the mounted one may number paths differently
+            Ok(inode) if self.reverse_inode_map.contains_key(&inode) => {
+                error!(
+                    "get_or_create_inode: Inode {inode} of {path} is in use for {}",
+                    self.reverse_inode_map[&inode]
+                );
+                self.next_inode += 1;
+                self.next_inode - 1
+            }
+            Ok(inode) => inode,
+            Err(e) => {
+                error!("get_or_create_inode: Unable to store inode of {path}: {e}");
+                self.next_inode += 1;
+                self.next_inode - 1
+            }
+        };
+        self.inode_map.insert(path.to_string(), inode);
+        self.reverse_inode_map.insert(inode, path.to_string());
+        self.touch_inode(inode);
+        inode
+    }
+
+    /// Attributes of the root directory
+    fn root_attr(&self) -> FileAttr {
+        FileAttr {
+            ino: 1,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::Directory,
+            perm: self.options.dir_mode,
+            nlink: self.folder_nlink(self.root_folder_id().as_deref()),
+            uid: self.options.uid,
+            gid: self.options.gid,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+
+    /// The path of `inode`, including one the kernel knows only from a file
+    /// handle (NFS export) given out before this mount; mapped again if it
+    /// wasn't
+    fn inode_path(&mut self, inode: u64) -> Option<String> {
+        let path = self.get_path_from_inode(inode)?;
+        if !self.reverse_inode_map.contains_key(&inode) {
+            self.inode_map.insert(path.clone(), inode);
+            self.reverse_inode_map.insert(inode, path.clone());
+            self.touch_inode(inode);
+        }
+        Some(path)
+    }
+
+    /// Marks `inode` as just used, so eviction leaves it for last
+    fn touch_inode(&mut self, inode: u64) {
+        self.inode_clock += 1;
+        self.inode_last_used.insert(inode, self.inode_clock);
+    }
+
+    /// Drops the least recently used mappings the kernel no longer refers to
+    /// once there are more than `--max-inodes`, down to nine tenths of that
+    /// where it can. Inodes with state held here (open handles, unsaved
+    /// buffers, scratch files) are kept; the numbers of the others stay in
+    /// the database.
+    ///
+    /// Where it can't, the next look waits until a tenth more are mapped,
+    /// rather than sorting them all again for every entry replied.
+    fn evict_inodes(&mut self) {
+        let limit = self.options.max_inodes;
+        if self.reverse_inode_map.len() <= self.inode_evict_at {
+            return;
         }
-        if filename.starts_with(".#") {
-            return true;
+        let mut pinned: HashSet<u64> = self.open_inodes().collect();
+        pinned.extend(self.front_matter_buffers.keys());
+        pinned.extend(self.sidecar_buffers.keys());
+        pinned.extend(self.asset_buffers.keys());
+        pinned.extend(self.scratch_files.keys());
+        let mut candidates: Vec<(u64, u64)> = self
+            .reverse_inode_map
+            .keys()
+            .filter(|&&inode| {
+                inode != 1
+                    && inode < TRANSIENT_INODES
+                    && !pinned.contains(&inode)
+                    && !self.lookup_counts.contains_key(&inode)
+            })
+            .map(|&inode| {
+                (
+                    self.inode_last_used.get(&inode).copied().unwrap_or(0),
+                    inode,
+                )
+            })
+            .collect();
+        candidates.sort_unstable();
+
+        let excess = self.reverse_inode_map.len() - limit * 9 / 10;
+        for &(_, inode) in candidates.iter().take(excess) {
+            if let Some(path) = self.reverse_inode_map.remove(&inode) {
+                self.inode_map.remove(&path);
+            }
+            self.inode_last_used.remove(&inode);
+            self.cached_versions.remove(&inode);
         }
+        // Other names of the evicted inodes (see update_inode_mappings)
+        let reverse = &self.reverse_inode_map;
+        self.inode_map
+            .retain(|_, inode| reverse.contains_key(inode));
+        self.stats.inodes_evicted(excess.min(candidates.len()));
+        let kept = self.reverse_inode_map.len();
+        self.inode_evict_at = if kept <= limit * 9 / 10 {
+            limit
+        } else {
+            kept + (kept.max(limit) / 10).max(1)
+        };
+    }
 
-        // VSCode temporary files
-        if filename.starts_with(".vscode") {
-            return true;
+    /// Forgets the inode of `path`, once it is gone; the database also drops
+    /// those of anything below it, so new files there get new numbers
+    fn remove_inode_mapping(&mut self, path: &str) -> Option<u64> {
+        if let Err(e) = self
+            .db
+            .remove_inode_paths(self.user_id.as_str(), &self.inode_key(path))
+        {
+            error!("remove_inode_mapping: Unable to drop inode of {path}: {e}");
         }
+        let inode = self.inode_map.remove(path)?;
+        self.reverse_inode_map.remove(&inode);
+        Some(inode)
+    }
 
-        // General temporary file patterns
-        if filename.contains(".tmp.") || filename.ends_with(".tmp") {
-            return true;
+    /// Errno for `ino`, which the kernel holds for `path` but which is gone
+    /// from the database (the web app deleted the note, say): ESTALE, once
+    /// the mapping is dropped and the kernel told to forget the entry.
+    /// Editor temporary files never were stored, so they stay ENOENT.
+    fn stale(&mut self, op: &str, ino: u64, path: &str) -> i32 {
+        let (parent_path, name) = match path.rsplit_once('/') {
+            Some(("", name)) => ("/", name),
+            Some((parent, name)) => (parent, name),
+            None => return ENOENT,
+        };
+        if self.is_editor_temp_file(name) {
+            return ENOENT;
         }
-        if filename.contains(".temp.") || filename.ends_with(".temp") {
-            return true;
+        debug!("{op}: {path} was removed underneath the mount");
+        if let Some(&parent) = self.inode_map.get(parent_path) {
+            self.notify.delete(parent, ino, name);
         }
+        self.remove_inode_mapping(path);
+        self.inode_last_used.remove(&ino);
+        self.cached_versions.remove(&ino);
+        libc::ESTALE
+    }
+
+    /// Where to put the session's notifier after mounting, so entries
+    /// removed underneath the mount are dropped from the kernel's cache
+    pub fn notifier_slot(&self) -> NotifierSlot {
+        self.notify.slot()
+    }
 
-        false
+    /// Whether `name` matches the `--system-file` denylist: names that
+    /// loaders, desktops and file managers probe for on any mount
+    fn is_system_file(&self, name: &str) -> bool {
+        self.options
+            .system_file_patterns
+            .iter()
+            .any(|pattern| ignore::glob_match(pattern, name))
+    }
+
+    /// Whether `filename` matches one of the `--editor-temp` patterns
+    fn is_editor_temp_file(&self, filename: &str) -> bool {
+        self.options
+            .editor_temp_patterns
+            .iter()
+            .any(|pattern| ignore::glob_match(pattern, filename))
     }
 
     pub fn new(
         connection: Connection,
         timezone: Option<Tz>,
         user_id: String,
+        mut options: FsOptions,
     ) -> Result<Self, Box<dyn Error>> {
         /*
         // Create performance indexes for unified notes table
@@ -175,14 +1679,59 @@ impl ExampleFuseFs {
             [],
         )?;
         */
-        let db = Database::new(connection, timezone);
+        let mut db = Database::new(connection, timezone);
+        db.case_insensitive = options.case_insensitive;
+        if options.normalize_unicode {
+            db.normalize_names()?;
+        }
+        // Before the interrupt watcher starts, so it doesn't take SIGUSR1 either
+        let stats = Stats::default();
+        stats.dump_on_sigusr1();
+        let interrupts = InterruptWatch::spawn(db.interrupt_handle());
+        let notify = KernelNotify::spawn();
+        db.profile_queries(stats::record_query);
+        let (generation, subdir, syntax_map) = Self::prepare_database(&db, &user_id, &options)?;
+        options.only_syntax = options
+            .only_syntax
+            .iter()
+            .map(|name| syntax_map.syntax_for_extension(name.trim_start_matches('.')))
+            .collect();
 
         let mut fs = ExampleFuseFs {
             db,
             inode_map: HashMap::new(),
             reverse_inode_map: HashMap::new(),
-            next_inode: 2,
+            next_inode: TRANSIENT_INODES,
+            generation,
+            inode_last_used: HashMap::new(),
+            inode_clock: 0,
+            inode_evict_at: options.max_inodes,
+            lookup_counts: HashMap::new(),
             user_id,
+            options,
+            subdir,
+            syntax_map,
+            front_matter_buffers: HashMap::new(),
+            sidecar_buffers: HashMap::new(),
+            removed_companions: HashMap::new(),
+            readdir_names: HashMap::new(),
+            asset_buffers: HashMap::new(),
+            made_folders: HashSet::new(),
+            scratch_files: HashMap::new(),
+            locks: LockTable::default(),
+            handles: HashMap::new(),
+            next_fh: 1,
+            virtual_seen: HashMap::new(),
+            exports: Exports::default(),
+            decrypted: Decrypted::default(),
+            unlocked: HashSet::new(),
+            cached_versions: HashMap::new(),
+            interrupts,
+            notify,
+            database_file: None,
+            persist_to: None,
+            last_seen: None,
+            stats,
         };
 
         // Root directory gets inode 1
@@ -191,7 +1740,138 @@ impl ExampleFuseFs {
 
         Ok(fs)
     }
+
+    /// Brings a freshly opened database up to date and reads what the mount
+    /// needs from it: the inode generation, the `--subdir` folder and the
+    /// syntax extensions
+    fn prepare_database(
+        db: &Database,
+        user_id: &str,
+        options: &FsOptions,
+    ) -> Result<(u64, Option<Subdir>, SyntaxMap), Box<dyn Error>> {
+        // Older schemas are brought up to date by the migrate subcommand, not
+        // behind the user's back when mounting
+        if let Some(problem) = db.schema_state()?.problem() {
+            return Err(problem.into());
+        }
+        // Folders in a parent_id loop (a corrupted or hand-edited database)
+        // have no path, and recursive queries over them never end
+        match db.break_folder_cycles() {
+            Ok(cycles) => {
+                for cycle in cycles {
+                    error!(
+                        "new: Folders {} form a parent_id cycle, moved {} to the root",
+                        cycle.join(" -> "),
+                        cycle[0]
+                    );
+                }
+            }
+            Err(e) => error!("new: Unable to check folders for cycles: {e}"),
+        }
+        let generation = db.inode_generation()?;
+
+        let subdir = match &options.subdir {
+            Some(requested) => {
+                let requested = requested.trim_matches('/');
+                let id = db
+                    .get_folder_id_by_path(requested, user_id)?
+                    .ok_or_else(|| format!("No folder {requested} to mount as the root"))?;
+                let path = db
+                    .get_folder_path_by_id(&id, user_id)?
+                    .unwrap_or_else(|| requested.to_string());
+                debug!("new: Mounting folder {path} as the root");
+                Some(Subdir { id, path })
+            }
+            None => None,
+        };
+
+        for (extension, syntax) in &options.syntax_overrides {
+            db.set_syntax_extension(extension, syntax)?;
+        }
+        // Databases created before the mapping table existed keep verbatim extensions
+        let syntax_map = match db.list_syntax_extensions() {
+            Ok(rows) => SyntaxMap::from_rows(rows),
+            Err(e) => {
+                error!("new: Unable to load syntax extensions, using verbatim: {e}");
+                SyntaxMap::default()
+            }
+        };
+        Ok((generation, subdir, syntax_map))
+    }
+
+    /// Reply `errno`, counting the error against the operation being served
+    fn reply_error(&self, reply: impl ErrorReply, errno: i32) {
+        self.stats.error(errno);
+        reply.error(errno);
+    }
+
+    /// Reply with the entry for `attr`; the kernel now holds one more
+    /// reference to the inode, until it forgets it. Mappings are evicted
+    /// here, once the inode being replied is counted as referenced.
+    fn reply_entry(&mut self, reply: ReplyEntry, attr: FileAttr) {
+        *self.lookup_counts.entry(attr.ino).or_default() += 1;
+        reply.entry(&self.options.entry_ttl, &attr, self.generation);
+        self.evict_inodes();
+    }
+
+    /// Reply to create with the new file's `attr`, counted as a lookup
+    fn reply_created(&mut self, reply: fuser::ReplyCreate, attr: FileAttr, fh: u64, flags: u32) {
+        *self.lookup_counts.entry(attr.ino).or_default() += 1;
+        reply.created(&self.options.entry_ttl, &attr, self.generation, fh, flags);
+        self.evict_inodes();
+    }
+
+    /// The kernel dropped `nlookup` references to `inode`. Once it holds none,
+    /// a mapping that is still current may be evicted, and one that was
+    /// unlinked or replaced by a rename is released.
+    fn forget_inode(&mut self, inode: u64, nlookup: u64) {
+        let Some(count) = self.lookup_counts.get_mut(&inode) else {
+            return;
+        };
+        *count = count.saturating_sub(nlookup);
+        if *count > 0 {
+            return;
+        }
+        self.lookup_counts.remove(&inode);
+        if !self.reverse_inode_map.contains_key(&inode) {
+            self.release_inode(inode);
+        }
+    }
+
+    /// Drops what is kept for `inode` after it was unlinked or replaced by a
+    /// rename, unless the kernel still refers to it (then it happens when
+    /// the kernel forgets it)
+    fn release_inode(&mut self, inode: u64) {
+        if self.lookup_counts.contains_key(&inode) {
+            return;
+        }
+        debug!("release_inode: Dropping state of removed inode {inode}");
+        self.front_matter_buffers.remove(&inode);
+        self.sidecar_buffers.remove(&inode);
+        self.asset_buffers.remove(&inode);
+        self.scratch_files.remove(&inode);
+        self.cached_versions.remove(&inode);
+        self.inode_last_used.remove(&inode);
+    }
+
+    /// `fallback` for a database error, or EINTR if the caller was interrupted
+    fn errno_for(err: &rusqlite::Error, fallback: i32) -> i32 {
+        if Database::is_interrupted(err) {
+            libc::EINTR
+        } else {
+            fallback
+        }
+    }
+
     fn update_inode_mappings(&mut self, old_path: &str, new_path: &str) {
+        if let Err(e) = self.db.rename_inode_paths(
+            self.user_id.as_str(),
+            &self.inode_key(old_path),
+            &self.inode_key(new_path),
+        ) {
+            error!("rename: Unable to move inodes of {old_path} to {new_path}: {e}");
+        }
+
         // Collect paths to update (including descendants)
         let mut paths_to_update = Vec::new();
         for (path, inode) in &self.inode_map {
@@ -209,90 +1889,274 @@ impl ExampleFuseFs {
         for (old_path, new_path, inode) in paths_to_update {
             self.inode_map.remove(&old_path);
             self.inode_map.insert(new_path.clone(), inode);
-            self.reverse_inode_map.insert(inode, new_path);
+            // Only move the inode's own path, not other names that share it
+            if self.reverse_inode_map.get(&inode) == Some(&old_path) {
+                self.reverse_inode_map.insert(inode, new_path);
+            }
         }
     }
 }
 
 impl Filesystem for ExampleFuseFs {
+    /// Ask the kernel to send POSIX and BSD locks here instead of keeping them
+    /// local to this machine, and "." and ".." lookups for NFS export
+    fn init(
+        &mut self,
+        _req: &Request,
+        config: &mut fuser::KernelConfig,
+    ) -> Result<(), libc::c_int> {
+        for capability in [
+            fuser::consts::FUSE_POSIX_LOCKS,
+            fuser::consts::FUSE_FLOCK_LOCKS,
+        ] {
+            if let Err(unsupported) = config.add_capabilities(capability) {
+                debug!("init: Kernel lacks lock capability {unsupported:#x}");
+            }
+        }
+        // Lookups of "." and ".." let the mount be exported over NFS
+        if let Err(unsupported) = config.add_capabilities(fuser::consts::FUSE_EXPORT_SUPPORT) {
+            debug!("init: Kernel lacks export capability {unsupported:#x}");
+        }
+        Ok(())
+    }
+
+    /// Called on unmount
+    fn destroy(&mut self) {
+        if self.options.latency_summary {
+            debug!(
+                "destroy: Operation latencies\n{}",
+                self.stats.latency_summary()
+            );
+        }
+        let vacuum = self.options.vacuum_on_unmount;
+        if (self.options.optimize_on_unmount || vacuum) && self.options.read_only {
+            debug!("destroy: Read-only mount, leaving the database to the writer");
+        } else if self.options.optimize_on_unmount || vacuum {
+            match self.db.tidy(vacuum) {
+                Ok(true) => debug!(
+                    "destroy: Optimized{} and checkpointed the database",
+                    if vacuum { ", vacuumed" } else { "" }
+                ),
+                Ok(false) => {
+                    error!("destroy: Another connection kept the WAL from being checkpointed")
+                }
+                Err(e) => error!("destroy: Unable to optimize the database: {e}"),
+            }
+        }
+        self.save_persisted();
+    }
+
+    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
+        let _op = self.begin_op("forget");
+        self.forget_inode(ino, nlookup);
+    }
+
+    fn batch_forget(&mut self, _req: &Request, nodes: &[fuser::fuse_forget_one]) {
+        let _op = self.begin_op("batch_forget");
+        for node in nodes {
+            self.forget_inode(node.nodeid, node.nlookup);
+        }
+    }
+
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let _op = self.begin_op("lookup");
         let name_str = match name.to_str() {
-            Some(s) => s,
+            Some(s) => self.incoming_name(s),
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
-        eprintln!("[DEBUG] lookup: parent={parent}, name={name_str}");
+        // Probes for shared libraries, trash folders and the like are
+        // answered before anything is logged or queried
+        if self.is_system_file(&name_str) {
+            self.reply_error(reply, ENOENT);
+            return;
+        }
+
+        debug!("lookup: parent={parent}, name={name_str}");
+
+        // Get parent path (NFS clients may hold its inode from an earlier mount)
+        let Some(parent_path) = self.inode_path(parent) else {
+            // Gone since the file handle was given out
+            self.reply_error(reply, libc::ESTALE);
+            return;
+        };
+
+        // Construct full path; an NFS server looks up "." and ".." of inodes
+        // it only has a file handle for
+        let full_path = match name_str.as_ref() {
+            "." => parent_path.clone(),
+            ".." => match parent_path.rsplit_once('/') {
+                Some(("", _)) | None => "/".to_string(),
+                Some((grandparent, _)) => grandparent.to_string(),
+            },
+            _ if parent_path == "/" => format!("/{name_str}"),
+            _ => format!("{parent_path}/{name_str}"),
+        };
+        if full_path == "/" {
+            self.reply_entry(reply, self.root_attr());
+            return;
+        }
 
-        // Get parent path
-        let parent_path = match self.get_path_from_inode(parent) {
-            Some(path) => path.clone(),
-            None => {
-                reply.error(ENOENT);
+        if let Some(file) = self.virtual_file(&full_path) {
+            let inode = self.get_or_create_inode(&full_path);
+            self.reply_entry(reply, self.virtual_file_attr(inode, file));
+            return;
+        }
+        if let Some(ExportPath::File(note, format)) = self.export_path(&full_path) {
+            let inode = self.get_or_create_inode(&full_path);
+            self.reply_entry(reply, self.export_attr(inode, &note, format));
+            return;
+        }
+        if let Some((inode, file)) = self.scratch_file(&full_path) {
+            self.reply_entry(reply, self.scratch_attr(inode, file));
+            return;
+        }
+        match self.shared_path(&full_path) {
+            Some(SharedPath::Note { note: None, .. }) => {
+                self.reply_error(reply, ENOENT);
                 return;
             }
-        };
+            Some(shared) => {
+                let inode = self.get_or_create_inode(&full_path);
+                match self.shared_attr(inode, &shared) {
+                    Ok(attr) => self.reply_entry(reply, attr),
+                    Err(errno) => self.reply_error(reply, errno),
+                }
+                return;
+            }
+            None => {}
+        }
 
-        // Construct full path
-        let full_path = if parent_path == "/" {
-            format!("/{name_str}")
-        } else {
-            format!("{parent_path}/{name_str}")
+        // Virtual directories answer for themselves; aliases and hard links are
+        // looked up at their real path, so they share its inode
+        let real_path = match self.resolve_virtual(&full_path) {
+            Some(VirtualPath::Dir) => {
+                let inode = self.get_or_create_inode(&full_path);
+                self.reply_entry(reply, self.virtual_dir_attr(inode));
+                return;
+            }
+            Some(VirtualPath::Symlink(target)) => {
+                let inode = self.get_or_create_inode(&full_path);
+                self.reply_entry(reply, self.symlink_attr(inode, &target));
+                return;
+            }
+            Some(VirtualPath::Alias(real_path)) => Some(real_path),
+            None => self.hard_link_target(&full_path),
+        };
+        let (parent_path, name_str, full_path) = match real_path
+            .as_deref()
+            .and_then(|p| Some((p, p.rsplit_once('/')?)))
+        {
+            Some((real_path, (parent, name))) => (
+                if parent.is_empty() { "/" } else { parent }.to_string(),
+                Cow::Owned(name.to_string()),
+                real_path.to_string(),
+            ),
+            None => (parent_path, name_str, full_path),
         };
 
+        // Metadata sidecars ("<file>.meta.json") are answered without a row of their own
+        if let Some(note) = self.sidecar_note(&full_path) {
+            let inode = self.get_or_create_inode(&full_path);
+            self.reply_entry(reply, self.sidecar_attr(inode, &note));
+            return;
+        }
+        if let Some(note) = self.html_note(&full_path) {
+            let inode = self.get_or_create_inode(&full_path);
+            self.reply_entry(reply, self.html_attr(inode, &note));
+            return;
+        }
+        match self.asset_path(&full_path) {
+            Some(AssetPath::Dir(note)) => {
+                let inode = self.get_or_create_inode(&full_path);
+                self.reply_entry(reply, self.asset_dir_attr(inode, &note));
+                return;
+            }
+            Some(AssetPath::File {
+                note,
+                attachment: Some(attachment),
+                ..
+            }) => {
+                let inode = self.get_or_create_inode(&full_path);
+                self.reply_entry(reply, self.attachment_attr(inode, &note, &attachment));
+                return;
+            }
+            Some(AssetPath::File { .. }) => {
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+            None => {}
+        }
+        if let Some(&kind) = self.removed_companions.get(&full_path) {
+            let inode = self.get_or_create_inode(&full_path);
+            self.reply_entry(reply, self.removed_companion_attr(inode, kind));
+            return;
+        }
+
         // Normalize path for database queries
-        let db_path = Self::normalize_path_for_db(&full_path);
+        let db_path = self.db_path(&full_path);
 
-        // Check if it's a system file that shouldn't be exposed
-        if Self::is_system_file(&full_path) {
-            eprintln!("[DEBUG] lookup: Filtering out system file {full_path}");
-            reply.error(ENOENT);
+        if self.options.ignore.is_ignored(&full_path, false) {
+            debug!("lookup: Ignoring {full_path}");
+            self.reply_error(reply, ENOENT);
             return;
         }
 
         // First, check if it's a folder/directory
         match self
             .db
-            .get_folder_id_by_path(db_path, self.user_id.as_str())
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
         {
+            Ok(Some(folder_id))
+                if !self.folder_shown(&folder_id)
+                    || self.options.ignore.is_ignored(&full_path, true) =>
+            {
+                debug!("lookup: Folder {full_path} is filtered out of the mount");
+                self.reply_error(reply, ENOENT);
+                return;
+            }
             Ok(Some(folder_id)) => {
                 // It's a directory - retrieve full folder object for timestamps
                 match self.db.get_folder_by_id(&folder_id, self.user_id.as_str()) {
                     Ok(Some(folder)) => {
-                        let inode = self.get_or_create_inode(&full_path);
+                        let entry_path = self.stored_folder_path(&folder_id, &full_path);
+                        let inode = self.get_or_create_inode(&entry_path);
                         let attr = FileAttr {
                             ino: inode,
                             size: 0,
                             blocks: 0,
-                            atime: Self::datetime_to_systemtime(&folder.updated_at),
+                            atime: Self::datetime_to_systemtime(
+                                &folder.accessed_at.unwrap_or(folder.updated_at),
+                            ),
                             mtime: Self::datetime_to_systemtime(&folder.updated_at),
                             ctime: Self::datetime_to_systemtime(&folder.updated_at),
                             crtime: Self::datetime_to_systemtime(&folder.created_at),
                             kind: FileType::Directory,
-                            perm: 0o755,
-                            nlink: 2,
-                            uid: 501,
-                            gid: 20,
+                            perm: self.folder_perm(&folder),
+                            nlink: self.folder_nlink(Some(&folder.id)),
+                            uid: folder.uid.unwrap_or(self.options.uid),
+                            gid: folder.gid.unwrap_or(self.options.gid),
                             rdev: 0,
-                            flags: 0,
+                            flags: folder.flags.unwrap_or(0),
                             blksize: 512,
                         };
-                        reply.entry(&TTL, &attr, 0);
+                        self.reply_entry(reply, attr);
                         return;
                     }
                     Ok(None) => {
                         // Folder ID found but folder doesn't exist - database inconsistency
-                        eprintln!(
-                            "[ERROR] lookup: Folder ID found but folder object not retrieved: {folder_id}"
+                        error!(
+                            "lookup: Folder ID found but folder object not retrieved: {folder_id}"
                         );
-                        reply.error(ENOENT);
+                        self.reply_error(reply, ENOENT);
                         return;
                     }
                     Err(e) => {
-                        eprintln!("[ERROR] lookup: Failed to get folder by ID {folder_id}: {e}");
-                        reply.error(ENOENT);
+                        error!("lookup: Failed to get folder by ID {folder_id}: {e}");
+                        self.reply_error(reply, ENOENT);
                         return;
                     }
                 }
@@ -301,104 +2165,162 @@ impl Filesystem for ExampleFuseFs {
                 // Not a directory, continue to check if it's a note
             }
             Err(e) => {
-                eprintln!("[ERROR] lookup: Database error checking for folder {full_path}: {e}");
-                reply.error(ENOENT);
+                error!("lookup: Database error checking for folder {full_path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         }
 
         // Second, check if it's a note/file
-        match self.db.get_note_id_by_path(db_path) {
-            Ok(Some(note_id)) => {
-                // It's a note/file
-                match self.db.get_note_by_id(&note_id) {
+        match self.resolve_note(&parent_path, &name_str) {
+            Ok(Some((note_id, note_path))) => {
+                // It's a note/file; its content isn't needed for attributes
+                match self.db.get_note_meta_by_id(&note_id) {
                     Ok(Some(note)) => {
-                        let inode = self.get_or_create_inode(&full_path);
-                        let content_size = note.content.len();
+                        let entry_path = self.stored_note_path(&note_id, &note_path);
+                        let inode = self.get_or_create_inode(&entry_path);
+                        let content_size = self.note_meta_size(inode, &note);
 
                         let attr = FileAttr {
                             ino: inode,
                             size: content_size as u64,
                             blocks: content_size.div_ceil(512) as u64,
-                            atime: Self::datetime_to_systemtime(&note.updated_at),
+                            atime: Self::datetime_to_systemtime(
+                                &note.accessed_at.unwrap_or(note.updated_at),
+                            ),
                             mtime: Self::datetime_to_systemtime(&note.updated_at),
                             ctime: Self::datetime_to_systemtime(&note.updated_at),
                             crtime: Self::datetime_to_systemtime(&note.created_at),
                             kind: FileType::RegularFile,
-                            perm: 0o644,
-                            nlink: 1,
-                            uid: 501,
-                            gid: 20,
+                            perm: self.note_perm(&note.id, &note.user_id, note.mode, note.locked),
+                            nlink: self.note_nlink(&note.id),
+                            uid: note.uid.unwrap_or(self.options.uid),
+                            gid: note.gid.unwrap_or(self.options.gid),
                             rdev: 0,
-                            flags: 0,
+                            flags: note.flags.unwrap_or(0),
                             blksize: 512,
                         };
-                        reply.entry(&TTL, &attr, 0);
+                        self.reply_entry(reply, attr);
                     }
                     Ok(None) => {
-                        eprintln!("[ERROR] lookup: Note with id {note_id} not found in database");
-                        reply.error(ENOENT);
+                        error!("lookup: Note with id {note_id} not found in database");
+                        self.reply_error(reply, ENOENT);
                     }
                     Err(e) => {
-                        eprintln!("[ERROR] lookup: Database error retrieving note {note_id}: {e}");
-                        reply.error(ENOENT);
+                        error!("lookup: Database error retrieving note {note_id}: {e}");
+                        self.reply_error(reply, ENOENT);
                     }
                 }
             }
             Ok(None) => {
                 // Neither a directory nor a note - doesn't exist
-                eprintln!("[DEBUG] lookup: Path {full_path} not found in database");
-                reply.error(ENOENT);
+                debug!("lookup: Path {full_path} not found in database");
+                self.reply_error(reply, ENOENT);
             }
             Err(e) => {
-                eprintln!("[ERROR] lookup: Database error checking for note {full_path}: {e}");
-                reply.error(ENOENT);
+                error!("lookup: Database error checking for note {full_path}: {e}");
+                self.reply_error(reply, ENOENT);
             }
         }
     }
 
     fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
-        eprintln!("[DEBUG] getattr: ino={ino}");
+        let _op = self.begin_op("getattr");
+        debug!("getattr: ino={ino}");
 
         // Handle root directory specially
         if ino == 1 {
-            let attr = FileAttr {
-                ino: 1,
-                size: 0,
-                blocks: 0,
-                atime: UNIX_EPOCH,
-                mtime: UNIX_EPOCH,
-                ctime: UNIX_EPOCH,
-                crtime: UNIX_EPOCH,
-                kind: FileType::Directory,
-                perm: 0o755,
-                nlink: 2,
-                uid: 501,
-                gid: 20,
-                rdev: 0,
-                flags: 0,
-                blksize: 512,
-            };
-            reply.attr(&TTL, &attr);
+            reply.attr(&self.options.attr_ttl, &self.root_attr());
             return;
         }
 
         // Get path from inode
         let path = match self.get_path_from_inode(ino) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
+        match self.resolve_virtual(&path) {
+            Some(VirtualPath::Dir) => {
+                reply.attr(&self.options.attr_ttl, &self.virtual_dir_attr(ino));
+                return;
+            }
+            Some(VirtualPath::Symlink(target)) => {
+                reply.attr(&self.options.attr_ttl, &self.symlink_attr(ino, &target));
+                return;
+            }
+            _ => {}
+        }
+        if let Some(file) = self.virtual_file(&path) {
+            reply.attr(&self.options.attr_ttl, &self.virtual_file_attr(ino, file));
+            return;
+        }
+        if let Some(ExportPath::File(note, format)) = self.export_path(&path) {
+            reply.attr(
+                &self.options.attr_ttl,
+                &self.export_attr(ino, &note, format),
+            );
+            return;
+        }
+        if let Some(file) = self.scratch_files.get(&ino) {
+            reply.attr(&self.options.attr_ttl, &self.scratch_attr(ino, file));
+            return;
+        }
+        if let Some(shared) = self.shared_path(&path) {
+            match self.shared_attr(ino, &shared) {
+                Ok(attr) => reply.attr(&self.options.attr_ttl, &attr),
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
+        if let Some(note) = self.sidecar_note(&path) {
+            reply.attr(&self.options.attr_ttl, &self.sidecar_attr(ino, &note));
+            return;
+        }
+        if let Some(note) = self.html_note(&path) {
+            reply.attr(&self.options.attr_ttl, &self.html_attr(ino, &note));
+            return;
+        }
+        match self.asset_path(&path) {
+            Some(AssetPath::Dir(note)) => {
+                reply.attr(&self.options.attr_ttl, &self.asset_dir_attr(ino, &note));
+                return;
+            }
+            Some(AssetPath::File {
+                note,
+                attachment: Some(attachment),
+                ..
+            }) => {
+                reply.attr(
+                    &self.options.attr_ttl,
+                    &self.attachment_attr(ino, &note, &attachment),
+                );
+                return;
+            }
+            Some(AssetPath::File { .. }) => {
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+            None => {}
+        }
+        if let Some(&kind) = self.removed_companions.get(&path) {
+            reply.attr(
+                &self.options.attr_ttl,
+                &self.removed_companion_attr(ino, kind),
+            );
+            return;
+        }
+
         // Normalize path for database queries
-        let db_path = Self::normalize_path_for_db(&path);
+        let db_path = self.db_path(&path);
 
         // First, check if it's a folder/directory
         match self
             .db
-            .get_folder_id_by_path(db_path, self.user_id.as_str())
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
         {
             Ok(Some(folder_id)) => {
                 // It's a directory - retrieve full folder object for timestamps
@@ -408,32 +2330,32 @@ impl Filesystem for ExampleFuseFs {
                             ino,
                             size: 0,
                             blocks: 0,
-                            atime: Self::datetime_to_systemtime(&folder.updated_at),
+                            atime: Self::datetime_to_systemtime(
+                                &folder.accessed_at.unwrap_or(folder.updated_at),
+                            ),
                             mtime: Self::datetime_to_systemtime(&folder.updated_at),
                             ctime: Self::datetime_to_systemtime(&folder.updated_at),
                             crtime: Self::datetime_to_systemtime(&folder.created_at),
                             kind: FileType::Directory,
-                            perm: 0o755,
-                            nlink: 2,
-                            uid: 501,
-                            gid: 20,
+                            perm: self.folder_perm(&folder),
+                            nlink: self.folder_nlink(Some(&folder.id)),
+                            uid: folder.uid.unwrap_or(self.options.uid),
+                            gid: folder.gid.unwrap_or(self.options.gid),
                             rdev: 0,
-                            flags: 0,
+                            flags: folder.flags.unwrap_or(0),
                             blksize: 512,
                         };
-                        reply.attr(&TTL, &attr);
+                        reply.attr(&self.options.attr_ttl, &attr);
                         return;
                     }
                     Ok(None) => {
-                        eprintln!(
-                            "[ERROR] getattr: Folder ID found but folder object not retrieved: {folder_id}"
-                        );
-                        reply.error(ENOENT);
+                        let errno = self.stale("getattr", ino, &path);
+                        self.reply_error(reply, errno);
                         return;
                     }
                     Err(e) => {
-                        eprintln!("[ERROR] getattr: Failed to get folder by ID {folder_id}: {e}");
-                        reply.error(ENOENT);
+                        error!("getattr: Failed to get folder by ID {folder_id}: {e}");
+                        self.reply_error(reply, ENOENT);
                         return;
                     }
                 }
@@ -442,185 +2364,347 @@ impl Filesystem for ExampleFuseFs {
                 // Not a directory, continue to check if it's a note
             }
             Err(e) => {
-                eprintln!("[ERROR] getattr: Database error checking for folder {path}: {e}");
-                reply.error(ENOENT);
+                error!("getattr: Database error checking for folder {path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         }
 
         // Second, check if it's a note/file
-        match self.db.get_note_id_by_path(db_path) {
+        match self.note_id_by_path(&db_path) {
             Ok(Some(note_id)) => {
-                // It's a note/file, get the note content
-                match self.db.get_note_by_id(&note_id) {
+                // It's a note/file; its content isn't needed for attributes
+                match self.db.get_note_meta_by_id(&note_id) {
                     Ok(Some(note)) => {
-                        let size = note.content.len() as u64;
-                        let blocks = note.content.len().div_ceil(512) as u64;
+                        let content_size = self.note_meta_size(ino, &note);
+                        let size = content_size as u64;
+                        let blocks = content_size.div_ceil(512) as u64;
 
                         let attr = FileAttr {
                             ino,
                             size,
                             blocks,
-                            atime: Self::datetime_to_systemtime(&note.updated_at),
+                            atime: Self::datetime_to_systemtime(
+                                &note.accessed_at.unwrap_or(note.updated_at),
+                            ),
                             mtime: Self::datetime_to_systemtime(&note.updated_at),
                             ctime: Self::datetime_to_systemtime(&note.updated_at),
                             crtime: Self::datetime_to_systemtime(&note.created_at),
                             kind: FileType::RegularFile,
-                            perm: 0o644,
-                            nlink: 1,
-                            uid: 501,
-                            gid: 20,
+                            perm: self.note_perm(&note.id, &note.user_id, note.mode, note.locked),
+                            nlink: self.note_nlink(&note.id),
+                            uid: note.uid.unwrap_or(self.options.uid),
+                            gid: note.gid.unwrap_or(self.options.gid),
                             rdev: 0,
-                            flags: 0,
+                            flags: note.flags.unwrap_or(0),
                             blksize: 512,
                         };
-                        reply.attr(&TTL, &attr);
+                        reply.attr(&self.options.attr_ttl, &attr);
                     }
                     Ok(None) => {
-                        eprintln!("[ERROR] getattr: Note with id {note_id} not found in database");
-                        reply.error(ENOENT);
+                        let errno = self.stale("getattr", ino, &path);
+                        self.reply_error(reply, errno);
                     }
                     Err(e) => {
-                        eprintln!("[ERROR] getattr: Database error retrieving note {note_id}: {e}");
-                        reply.error(ENOENT);
+                        error!("getattr: Database error retrieving note {note_id}: {e}");
+                        self.reply_error(reply, ENOENT);
                     }
                 }
             }
             Ok(None) => {
                 // Neither a directory nor a note - doesn't exist
-                eprintln!("[DEBUG] getattr: Path {path} not found in database");
-                reply.error(ENOENT);
+                let errno = self.stale("getattr", ino, &path);
+                self.reply_error(reply, errno);
             }
             Err(e) => {
-                eprintln!("[ERROR] getattr: Database error checking for note {path}: {e}");
-                reply.error(ENOENT);
+                error!("getattr: Database error checking for note {path}: {e}");
+                self.reply_error(reply, ENOENT);
             }
         }
     }
 
+    /// Target of a symlink; only the ones in "<title>.links" directories exist
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        let _op = self.begin_op("readlink");
+        debug!("readlink: ino={ino}");
+        let Some(path) = self.get_path_from_inode(ino) else {
+            self.reply_error(reply, ENOENT);
+            return;
+        };
+        match self.resolve_virtual(&path) {
+            Some(VirtualPath::Symlink(target)) => reply.data(target.as_bytes()),
+            // Not a symlink
+            _ => self.reply_error(reply, libc::EINVAL),
+        }
+    }
+
     fn read(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
-        _size: u32,
+        size: u32,
         _flags: i32,
         _lock: Option<u64>,
         reply: ReplyData,
     ) {
-        eprintln!("[DEBUG] read: ino={ino}, offset={offset}");
+        let _op = self.begin_op("read");
+        debug!("read: ino={ino}, offset={offset}");
+        let _in_flight = self.interrupts.begin(req.pid());
 
         // Get path from inode
         let path = match self.get_path_from_inode(ino) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
+        if let Some(file) = self.virtual_file(&path) {
+            let bytes = self.virtual_file_bytes(file);
+            reply.data(Self::read_range(&bytes, offset, size));
+            self.virtual_file_read(fh, file);
+            return;
+        }
+        if let Some(ExportPath::File(note, format)) = self.export_path(&path) {
+            match self.export_bytes(&note, format) {
+                Ok(bytes) => reply.data(Self::read_range(bytes, offset, size)),
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
+        if let Some(bytes) = self.scratch_bytes(ino) {
+            reply.data(Self::read_range(bytes, offset, size));
+            return;
+        }
+        match self.shared_path(&path) {
+            Some(SharedPath::Note {
+                note: Some(note), ..
+            }) => {
+                let bytes = self.note_bytes(ino, &note);
+                reply.data(Self::read_range(&bytes, offset, size));
+                return;
+            }
+            Some(SharedPath::Folder(..)) => {
+                self.reply_error(reply, libc::EISDIR);
+                return;
+            }
+            Some(SharedPath::Note { .. }) => {
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+            None => {}
+        }
+        if let Some(note) = self.sidecar_note(&path) {
+            let bytes = self.sidecar_bytes(ino, &note);
+            reply.data(Self::read_range(&bytes, offset, size));
+            return;
+        }
+        if let Some(note) = self.html_note(&path) {
+            let bytes = self.html_bytes(&note);
+            reply.data(Self::read_range(&bytes, offset, size));
+            return;
+        }
+        match self.asset_path(&path) {
+            Some(AssetPath::Dir(_)) => {
+                self.reply_error(reply, libc::EISDIR);
+                return;
+            }
+            Some(AssetPath::File {
+                attachment: Some(attachment),
+                ..
+            }) => {
+                match self.read_attachment(ino, &attachment, offset, size) {
+                    Ok(bytes) => reply.data(&bytes),
+                    Err(errno) => self.reply_error(reply, errno),
+                }
+                return;
+            }
+            Some(AssetPath::File { .. }) => {
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+            None => {}
+        }
+
         // Normalize path for database queries
-        let db_path = Self::normalize_path_for_db(&path);
+        let db_path = self.db_path(&path);
 
         // Check if it's a directory - directories cannot be read as files
         match self
             .db
-            .get_folder_id_by_path(db_path, self.user_id.as_str())
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
         {
             Ok(Some(_folder_id)) => {
                 // It's a directory - cannot read as file
-                eprintln!("[ERROR] read: Attempted to read directory {path} as file");
-                reply.error(libc::EISDIR);
+                error!("read: Attempted to read directory {path} as file");
+                self.reply_error(reply, libc::EISDIR);
                 return;
             }
             Ok(None) => {
                 // Not a directory, continue to check if it's a note
             }
             Err(e) => {
-                eprintln!("[ERROR] read: Database error checking for folder {path}: {e}");
-                reply.error(ENOENT);
+                error!("read: Database error checking for folder {path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         }
 
         // Check if it's a note/file
-        match self.db.get_note_id_by_path(db_path) {
+        match self.note_id_by_path(&db_path) {
             Ok(Some(note_id)) => {
                 // It's a note/file, get the content
                 match self.db.get_note_by_id(&note_id) {
                     Ok(Some(note)) => {
-                        let content_bytes = note.content.as_bytes();
-                        let start = offset as usize;
-
-                        if start < content_bytes.len() {
-                            reply.data(&content_bytes[start..]);
-                        } else {
-                            // Offset beyond file content, return empty data
-                            reply.data(&[]);
-                        }
+                        let content_bytes = self.note_bytes(ino, &note);
+                        reply.data(Self::read_range(&content_bytes, offset, size));
                     }
                     Ok(None) => {
-                        eprintln!("[ERROR] read: Note with id {note_id} not found in database");
-                        reply.error(ENOENT);
+                        let errno = self.stale("read", ino, &path);
+                        self.reply_error(reply, errno);
                     }
                     Err(e) => {
-                        eprintln!("[ERROR] read: Database error retrieving note {note_id}: {e}");
-                        reply.error(ENOENT);
+                        error!("read: Database error retrieving note {note_id}: {e}");
+                        self.reply_error(reply, Self::errno_for(&e, ENOENT));
                     }
                 }
             }
             Ok(None) => {
                 // Neither a directory nor a note - doesn't exist
-                eprintln!("[DEBUG] read: Path {path} not found in database");
-                reply.error(ENOENT);
+                let errno = self.stale("read", ino, &path);
+                self.reply_error(reply, errno);
             }
             Err(e) => {
-                eprintln!("[ERROR] read: Database error checking for note {path}: {e}");
-                reply.error(ENOENT);
+                error!("read: Database error checking for note {path}: {e}");
+                self.reply_error(reply, ENOENT);
+            }
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let _op = self.begin_op("readdir");
+        debug!("readdir: ino={ino}, offset={offset}");
+        let _in_flight = self.interrupts.begin(req.pid());
+
+        // Get path from inode
+        let path = match self.get_path_from_inode(ino) {
+            Some(path) => path,
+            None => {
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+        };
+
+        if self.is_virtual_dir(&path) {
+            let mut entries = vec![
+                (ino, FileType::Directory, ".".to_string()),
+                (1, FileType::Directory, "..".to_string()),
+            ];
+            entries.extend(self.virtual_dir_entries(&path));
+            for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+                if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+                    break;
+                }
+            }
+            reply.ok();
+            return;
+        }
+        if let Some(SharedPath::Folder(folder, _)) = self.shared_path(&path) {
+            let parent_ino = path
+                .rsplit_once('/')
+                .and_then(|(parent, _)| self.inode_map.get(parent).copied())
+                .unwrap_or(1);
+            let mut entries = vec![
+                (ino, FileType::Directory, ".".to_string()),
+                (parent_ino, FileType::Directory, "..".to_string()),
+            ];
+            match self.shared_dir_entries(&path, &folder) {
+                Ok(children) => entries.extend(children),
+                Err(errno) => {
+                    self.reply_error(reply, errno);
+                    return;
+                }
+            }
+            for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+                if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+                    break;
+                }
+            }
+            reply.ok();
+            return;
+        }
+        if let Some(AssetPath::Dir(note)) = self.asset_path(&path) {
+            let parent_ino = path
+                .rsplit_once('/')
+                .and_then(|(parent, _)| self.inode_map.get(parent).copied())
+                .unwrap_or(1);
+            let mut entries = vec![
+                (ino, FileType::Directory, ".".to_string()),
+                (parent_ino, FileType::Directory, "..".to_string()),
+            ];
+            match self.asset_dir_entries(&path, &note) {
+                Ok(files) => entries.extend(files),
+                Err(errno) => {
+                    self.reply_error(reply, errno);
+                    return;
+                }
+            }
+            for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+                if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+                    break;
+                }
+            }
+            reply.ok();
+            return;
+        }
+        if self.removed_companions.get(&path) == Some(&FileType::Directory) {
+            let parent_ino = path
+                .rsplit_once('/')
+                .and_then(|(parent, _)| self.inode_map.get(parent).copied())
+                .unwrap_or(1);
+            let entries = [
+                (ino, FileType::Directory, ".".to_string()),
+                (parent_ino, FileType::Directory, "..".to_string()),
+            ];
+            for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+                if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+                    break;
+                }
             }
+            reply.ok();
+            return;
         }
-    }
-
-    fn readdir(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        mut reply: ReplyDirectory,
-    ) {
-        eprintln!("[DEBUG] readdir: ino={ino}, offset={offset}");
-
-        // Get path from inode
-        let path = match self.get_path_from_inode(ino) {
-            Some(path) => path.clone(),
-            None => {
-                reply.error(ENOENT);
-                return;
-            }
-        };
 
         // Check if it's actually a directory before reading it
         let folder_id = if path == "/" {
             // Root directory - special case
-            None
+            self.root_folder_id()
         } else {
-            let db_path = Self::normalize_path_for_db(&path);
+            let db_path = self.db_path(&path);
             match self
                 .db
-                .get_folder_id_by_path(db_path, self.user_id.as_str())
+                .get_folder_id_by_path(&db_path, self.user_id.as_str())
             {
                 Ok(Some(id)) => Some(id),
                 Ok(None) => {
                     // Not a directory - cannot readdir on a file
-                    eprintln!("[ERROR] readdir: Attempted to readdir on non-directory {path}");
-                    reply.error(libc::ENOTDIR);
+                    error!("readdir: Attempted to readdir on non-directory {path}");
+                    self.reply_error(reply, libc::ENOTDIR);
                     return;
                 }
                 Err(e) => {
-                    eprintln!("[ERROR] readdir: Database error checking for folder {path}: {e}");
-                    reply.error(ENOENT);
+                    error!("readdir: Database error checking for folder {path}: {e}");
+                    self.reply_error(reply, ENOENT);
                     return;
                 }
             }
@@ -636,121 +2720,156 @@ impl Filesystem for ExampleFuseFs {
             1
         };
 
-        // Start with standard directory entries
-        let mut entries = vec![
+        // Entry i (from 0) is returned with offset i + 1, in three runs:
+        // ".", ".." and the root's virtual directories; the folder's children,
+        // fetched a page at a time from the offset asked for; then hard links
+        // and companions, which need every child's name to skip taken ones
+        let mut fixed = vec![
             (ino, FileType::Directory, ".".to_string()),
             (parent_ino, FileType::Directory, "..".to_string()),
         ];
-
-        // Get directory contents from database
-        let mut seen_names: HashSet<String> = HashSet::new();
-
-        // Handle root vs non-root directories
         if path == "/" {
-            // Root directory - get top-level folders and notes
-
-            // Get root folders
-            match self.db.list_folders_by_parent(None, self.user_id.as_str()) {
-                Ok(folders) => {
-                    for folder in folders {
-                        let name = folder.title.clone();
-                        if !seen_names.contains(&name) {
-                            seen_names.insert(name.clone());
-                            let folder_path = format!("/{name}");
-                            let child_ino = self.get_or_create_inode(&folder_path);
-                            entries.push((child_ino, FileType::Directory, name));
-                        }
-                    }
-                }
+            fixed.extend(self.root_virtual_entries());
+        }
+        let start = offset.max(0) as usize;
+        if start == 0 && !self.removed_companions.is_empty() {
+            let dir = if path == "/" { "" } else { path.as_str() };
+            self.removed_companions.retain(|companion, _| {
+                companion.rsplit_once('/').map(|(parent, _)| parent) != Some(dir)
+            });
+        }
+        for (i, entry) in fixed.iter().enumerate().skip(start) {
+            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2.clone()) {
+                reply.ok();
+                return;
+            }
+        }
+
+        // Virtual directories shadow real entries of the same name
+        let base = fixed.len();
+        let mut seen_names: HashSet<String> = fixed.into_iter().map(|entry| entry.2).collect();
+        let mut index = start.saturating_sub(base);
+        let all_users = self.options.other_users == OtherUsers::Deny;
+        if let Some(names) = self.readdir_names.remove(&(ino, start)) {
+            seen_names = names;
+        } else if index > 0 {
+            // Resuming where the last call didn't stop (a seek, or the
+            // kernel took fewer entries): the children already listed took
+            // their names, so a later child of the same name is still skipped
+            let listed = match self.db.list_children(
+                folder_id.as_deref(),
+                self.user_id.as_str(),
+                all_users,
+                Page {
+                    offset: 0,
+                    limit: Some(index),
+                },
+            ) {
+                Ok(children) => children,
                 Err(e) => {
-                    eprintln!("[ERROR] readdir: Unable to get root folders: {e}");
-                    reply.error(ENOENT);
+                    error!("readdir: Unable to get children of {path}: {e}");
+                    self.reply_error(reply, Self::errno_for(&e, ENOENT));
                     return;
                 }
+            };
+            for child in listed.iter().filter(|child| self.child_shown(child)) {
+                seen_names.insert(self.child_entry_name(child).0);
             }
-
-            // Get root notes
-            match self.db.list_notes_by_parent(None, self.user_id.as_str()) {
-                Ok(notes) => {
-                    for note in notes {
-                        let filename = format!("{}.{}", note.title, note.syntax);
-                        if !seen_names.contains(&filename) {
-                            seen_names.insert(filename.clone());
-                            let file_path = format!("/{filename}");
-                            let child_ino = self.get_or_create_inode(&file_path);
-                            entries.push((child_ino, FileType::RegularFile, filename));
-                        }
-                    }
-                }
+        }
+        loop {
+            let children = match self.db.list_children(
+                folder_id.as_deref(),
+                self.user_id.as_str(),
+                all_users,
+                Page {
+                    offset: index,
+                    limit: Some(READDIR_PAGE),
+                },
+            ) {
+                Ok(children) => children,
                 Err(e) => {
-                    eprintln!("[ERROR] readdir: Unable to get root notes: {e}");
-                    reply.error(ENOENT);
+                    error!("readdir: Unable to get children of {path}: {e}");
+                    self.reply_error(reply, Self::errno_for(&e, ENOENT));
                     return;
                 }
-            }
-        } else {
-            // Non-root directory - get direct children only
-            let current_folder_id = folder_id.unwrap();
-
-            // Get direct child folders
-            match self
-                .db
-                .list_folders_by_parent(Some(&current_folder_id), self.user_id.as_str())
-            {
-                Ok(folders) => {
-                    for folder in folders {
-                        let name = folder.title.clone();
-                        if !seen_names.contains(&name) {
-                            seen_names.insert(name.clone());
-                            let folder_path = if path == "/" {
-                                format!("/{name}")
-                            } else {
-                                format!("{path}/{name}")
-                            };
-                            let child_ino = self.get_or_create_inode(&folder_path);
-                            entries.push((child_ino, FileType::Directory, name));
-                        }
-                    }
+            };
+            let fetched = children.len();
+            for child in children {
+                index += 1;
+                if !self.child_shown(&child) {
+                    continue;
                 }
-                Err(e) => {
-                    eprintln!("[ERROR] readdir: Unable to get child folders for {path}: {e}");
-                    reply.error(ENOENT);
+                let (name, kind) = self.child_entry_name(&child);
+                if !seen_names.insert(name.clone()) {
+                    continue;
+                }
+                let child_path = if path == "/" {
+                    format!("/{name}")
+                } else {
+                    format!("{path}/{name}")
+                };
+                if self
+                    .options
+                    .ignore
+                    .is_ignored(&child_path, kind == FileType::Directory)
+                {
+                    continue;
+                }
+                let child_ino = self.get_or_create_inode(&child_path);
+                if reply.add(child_ino, (base + index) as i64, kind, &name) {
+                    // The next call starts with this child again
+                    seen_names.remove(&name);
+                    if self.readdir_names.len() >= READDIR_RESUMES {
+                        self.readdir_names.clear();
+                    }
+                    self.readdir_names
+                        .insert((ino, base + index - 1), seen_names);
+                    reply.ok();
                     return;
                 }
             }
+            if fetched < READDIR_PAGE {
+                break;
+            }
+        }
 
-            // Get direct child notes
-            match self
-                .db
-                .list_notes_by_parent(Some(&current_folder_id), self.user_id.as_str())
-            {
-                Ok(notes) => {
-                    for note in notes {
-                        let filename = format!("{}.{}", note.title, note.syntax);
-                        if !seen_names.contains(&filename) {
-                            seen_names.insert(filename.clone());
-                            let file_path = if path == "/" {
-                                format!("/{filename}")
-                            } else {
-                                format!("{path}/{filename}")
-                            };
-                            let child_ino = self.get_or_create_inode(&file_path);
-                            entries.push((child_ino, FileType::RegularFile, filename));
-                        }
-                    }
-                }
+        // Hard links to notes elsewhere, then the notes' companions
+        let mut tail = self.hard_link_entries(folder_id.as_deref(), &path);
+        if self.companions_enabled() || !tail.is_empty() {
+            let children = match self.db.list_children(
+                folder_id.as_deref(),
+                self.user_id.as_str(),
+                all_users,
+                Page::ALL,
+            ) {
+                Ok(children) => children,
                 Err(e) => {
-                    eprintln!("[ERROR] readdir: Unable to get child notes for {path}: {e}");
-                    reply.error(ENOENT);
+                    error!("readdir: Unable to get children of {path}: {e}");
+                    self.reply_error(reply, Self::errno_for(&e, ENOENT));
                     return;
                 }
+            };
+            index = children.len();
+            let mut companions = Vec::new();
+            for child in &children {
+                seen_names.insert(self.child_entry_name(child).0);
+                if let Child::Note(note) = child
+                    && note.user_id == self.user_id
+                    && self.note_shown(&note.id, &note.syntax)
+                {
+                    companions.extend(self.companion_entries(&path, note));
+                }
             }
+            tail.extend(companions);
+            tail.retain(|entry| seen_names.insert(entry.2.clone()));
         }
-
-        // Return entries starting from the requested offset
-        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
-            // i + 1 is the offset for the next entry
-            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+        let tail_base = base + index;
+        for (i, entry) in tail
+            .into_iter()
+            .enumerate()
+            .skip(start.saturating_sub(tail_base))
+        {
+            if reply.add(entry.0, (tail_base + i + 1) as i64, entry.1, entry.2) {
                 break; // Buffer full
             }
         }
@@ -773,114 +2892,201 @@ impl Filesystem for ExampleFuseFs {
         _umask: u32,
         reply: ReplyEntry,
     ) {
+        let _op = self.begin_op("mkdir");
         let folder_name = match name.to_str() {
-            Some(s) => s,
+            Some(s) => self.incoming_name(s),
             None => {
-                reply.error(libc::EINVAL);
+                self.reply_error(reply, libc::EINVAL);
                 return;
             }
         };
 
-        eprintln!("[DEBUG] mkdir: parent={parent}, name={folder_name}");
+        debug!("mkdir: parent={parent}, name={folder_name}");
+
+        if let Err(errno) = self.validate_name(&folder_name) {
+            error!("mkdir: Rejecting invalid name {folder_name:?}");
+            self.reply_error(reply, errno);
+            return;
+        }
 
         // Get parent path
         let parent_path = match self.get_path_from_inode(parent) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
+        // A new directory in /.searches saves its (percent-decoded) name as a query
+        if parent_path == SEARCHES_DIR {
+            match self.create_saved_search_dir(&folder_name) {
+                Ok(attr) => self.reply_entry(reply, attr),
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
+
+        // Virtual directories are read-only
+        if self.is_virtual_dir(&parent_path) {
+            self.reply_error(reply, libc::EROFS);
+            return;
+        }
+
         // Create the full path for the new directory
         let full_path = if parent_path == "/" {
             format!("/{folder_name}")
         } else {
             format!("{parent_path}/{folder_name}")
         };
+        if let Err(errno) = self.check_not_denied("mkdir", &full_path, true) {
+            self.reply_error(reply, errno);
+            return;
+        }
+
+        // Assets directories hold only files, and exist as long as their note
+        match self.asset_path(&full_path) {
+            Some(AssetPath::Dir(_)) => {
+                self.reply_error(reply, libc::EEXIST);
+                return;
+            }
+            Some(AssetPath::File { .. }) => {
+                self.reply_error(reply, libc::EPERM);
+                return;
+            }
+            None => {}
+        }
+
+        // A directory made in a shared folder belongs to the folder's owner
+        if let Some(shared) = self.shared_path(&full_path) {
+            let created = self.create_shared_folder(&shared).and_then(|folder| {
+                let access = shared.access();
+                let inode = self.get_or_create_inode(&full_path);
+                self.shared_attr(inode, &SharedPath::Folder(folder, access))
+            });
+            match created {
+                Ok(attr) => self.reply_entry(reply, attr),
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
 
         // Normalize paths for database queries
-        let db_path = Self::normalize_path_for_db(&full_path);
+        let db_path = self.db_path(&full_path);
 
-        // Check if directory already exists
+        // Check if directory already exists (auto-rename picks a free title below)
         match self
             .db
-            .get_folder_id_by_path(db_path, self.user_id.as_str())
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
         {
-            Ok(Some(_existing_id)) => {
-                eprintln!("[ERROR] mkdir: Directory {full_path} already exists");
-                reply.error(libc::EEXIST);
+            Ok(Some(_existing_id)) if !self.options.auto_rename => {
+                error!("mkdir: Directory {full_path} already exists");
+                self.reply_error(reply, libc::EEXIST);
                 return;
             }
+            Ok(Some(_existing_id)) => {
+                // Collision resolved by suffixing the title once the parent is known
+            }
             Ok(None) => {
                 // Good, directory doesn't exist
             }
             Err(e) => {
-                eprintln!(
-                    "[ERROR] mkdir: Database error checking for existing directory {full_path}: {e}"
-                );
-                reply.error(libc::EIO);
+                error!("mkdir: Database error checking for existing directory {full_path}: {e}");
+                self.reply_error(reply, libc::EIO);
                 return;
             }
         }
 
         // Check if a file/note with the same name exists
-        match self.db.get_note_id_by_path(db_path) {
+        match self.db.get_note_id_by_path(&db_path) {
             Ok(Some(_existing_id)) => {
-                eprintln!("[ERROR] mkdir: File {full_path} already exists");
-                reply.error(libc::EEXIST);
+                error!("mkdir: File {full_path} already exists");
+                self.reply_error(reply, libc::EEXIST);
                 return;
             }
             Ok(None) => {
                 // Good, no file with this name
             }
             Err(e) => {
-                eprintln!(
-                    "[ERROR] mkdir: Database error checking for existing file {full_path}: {e}"
-                );
-                reply.error(libc::EIO);
+                error!("mkdir: Database error checking for existing file {full_path}: {e}");
+                self.reply_error(reply, libc::EIO);
                 return;
             }
         }
 
         // Get parent folder ID - None for root, Some(id) for other paths
         let parent_id = if parent_path == "/" {
-            None
+            self.root_folder_id()
         } else {
-            let db_parent_path = Self::normalize_path_for_db(&parent_path);
+            let db_parent_path = self.db_path(&parent_path);
             match self
                 .db
-                .get_folder_id_by_path(db_parent_path, self.user_id.as_str())
+                .get_folder_id_by_path(&db_parent_path, self.user_id.as_str())
             {
                 Ok(Some(id)) => Some(id),
                 Ok(None) => {
-                    eprintln!("[ERROR] mkdir: Parent directory {parent_path} not found");
-                    reply.error(ENOENT);
+                    error!("mkdir: Parent directory {parent_path} not found");
+                    self.reply_error(reply, ENOENT);
                     return;
                 }
                 Err(e) => {
-                    eprintln!(
-                        "[ERROR] mkdir: Database error checking parent directory {parent_path}: {e}"
-                    );
-                    reply.error(ENOENT);
+                    error!("mkdir: Database error checking parent directory {parent_path}: {e}");
+                    self.reply_error(reply, ENOENT);
+                    return;
+                }
+            }
+        };
+
+        // Resolve name collisions by suffixing the title when auto-rename is enabled
+        let folder_title = Self::name_to_title(&folder_name);
+        let (title, full_path) = if self.options.auto_rename {
+            match self.db.unique_folder_title(
+                parent_id.as_deref(),
+                &folder_title,
+                self.user_id.as_str(),
+            ) {
+                Ok(title) if title != folder_title => {
+                    let renamed_name = self.title_to_name(&title);
+                    let renamed_path = if parent_path == "/" {
+                        format!("/{renamed_name}")
+                    } else {
+                        format!("{parent_path}/{renamed_name}")
+                    };
+                    debug!("mkdir: {full_path} exists, creating {renamed_path}");
+                    (title, renamed_path)
+                }
+                Ok(title) => (title, full_path),
+                Err(e) => {
+                    error!("mkdir: Unable to pick a free name for {full_path}: {e}");
+                    self.reply_error(reply, libc::EIO);
                     return;
                 }
             }
+        } else {
+            (folder_title, full_path)
         };
 
         // Create the folder in the database
-        let _folder_id =
+        let folder_id =
             match self
                 .db
-                .create_folder(folder_name, parent_id.as_deref(), self.user_id.as_str())
+                .create_folder(&title, parent_id.as_deref(), self.user_id.as_str())
             {
                 Ok(id) => id,
+                Err(e) if Database::is_unique_violation(&e) => {
+                    error!("mkdir: Directory {full_path} already exists");
+                    self.reply_error(reply, libc::EEXIST);
+                    return;
+                }
                 Err(e) => {
-                    eprintln!("[ERROR] mkdir: Unable to create folder {full_path}: {e}");
-                    reply.error(libc::EIO);
+                    error!("mkdir: Unable to create folder {full_path}: {e}");
+                    self.reply_error(reply, libc::EIO);
                     return;
                 }
             };
+        if self.options.filter_tag.is_some() {
+            self.made_folders.insert(folder_id);
+        }
 
         // Create inode for the new directory
         let inode = self.get_or_create_inode(&full_path);
@@ -900,16 +3106,16 @@ impl Filesystem for ExampleFuseFs {
             ctime: UNIX_EPOCH + Duration::from_secs(now),
             crtime: UNIX_EPOCH + Duration::from_secs(now),
             kind: FileType::Directory,
-            perm: 0o755,
+            perm: self.options.dir_mode,
             nlink: 2,
-            uid: 501,
-            gid: 20,
+            uid: self.options.uid,
+            gid: self.options.gid,
             rdev: 0,
             flags: 0,
             blksize: 512,
         };
 
-        reply.entry(&TTL, &attr, 0);
+        self.reply_entry(reply, attr);
     }
 
     /// Handle file creation operations
@@ -925,59 +3131,125 @@ impl Filesystem for ExampleFuseFs {
         flags: i32,
         reply: fuser::ReplyCreate,
     ) {
+        let _op = self.begin_op("create");
         let file_name = match name.to_str() {
-            Some(s) => s,
+            Some(s) => self.incoming_name(s),
             None => {
-                reply.error(libc::EINVAL);
+                self.reply_error(reply, libc::EINVAL);
                 return;
             }
         };
 
-        eprintln!(
-            "[DEBUG] create: parent={parent}, name={file_name}, mode={mode:#o}, flags={flags:#x}"
-        );
+        debug!("create: parent={parent}, name={file_name}, mode={mode:#o}, flags={flags:#x}");
+
+        if let Err(errno) = self.validate_name(&file_name) {
+            error!("create: Rejecting invalid name {file_name:?}");
+            self.reply_error(reply, errno);
+            return;
+        }
 
         // Get parent path
         let parent_path = match self.get_path_from_inode(parent) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
-        // Construct the full path
+        // Virtual directories are read-only
+        if self.is_virtual_dir(&parent_path) {
+            self.reply_error(reply, libc::EROFS);
+            return;
+        }
+
+        // A file created in an assets directory is a new attachment of its note
+        if let Some(AssetPath::Dir(note)) = self.asset_path(&parent_path) {
+            let full_path = format!("{parent_path}/{file_name}");
+            let inode = self.get_or_create_inode(&full_path);
+            match self.create_attachment_file(inode, &note, &file_name) {
+                Ok(attr) => {
+                    let fh = self.open_handle(inode, None);
+                    self.reply_created(reply, attr, fh, 0);
+                }
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
+
+        // Construct the full path, with any extension alias replaced by the listed one
+        let note_name = self.canonical_note_name(&file_name);
         let full_path = if parent_path == "/" {
-            format!("/{file_name}")
+            format!("/{note_name}")
         } else {
-            format!("{parent_path}/{file_name}")
+            format!("{parent_path}/{note_name}")
         };
+        if let Err(errno) = self.check_not_denied("create", &full_path, false) {
+            self.reply_error(reply, errno);
+            return;
+        }
+
+        // A file created in a shared folder is a note of the folder's owner
+        if let Some(shared) = self.shared_path(&full_path) {
+            let access = shared.access();
+            let created = self.create_shared_note(&shared).map(|note| {
+                let inode = self.get_or_create_inode(&full_path);
+                let attr = self.shared_note_attr(inode, &note, access);
+                (inode, note, attr)
+            });
+            match created {
+                Ok((inode, note, attr)) => {
+                    let fh = self.open_handle(inode, Some(&note));
+                    self.reply_created(reply, attr, fh, 0);
+                }
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
 
         // Normalize path for database queries
-        let db_path = Self::normalize_path_for_db(&full_path);
+        let db_path = self.db_path(&full_path);
 
-        // Check if file already exists
-        match self.db.get_note_id_by_path(db_path) {
-            Ok(Some(_existing_id)) => {
-                eprintln!("[ERROR] create: File {full_path} already exists");
-                reply.error(libc::EEXIST);
+        // A hard link holding the name counts as an existing file
+        if let Ok(Some(_)) = self.db.get_alias_by_path(&db_path, self.user_id.as_str()) {
+            error!("create: File {full_path} already exists as a hard link");
+            self.reply_error(reply, libc::EEXIST);
+            return;
+        }
+
+        // Check if file already exists (auto-rename picks a free title below)
+        match self.db.get_note_id_by_path(&db_path) {
+            Ok(Some(_existing_id)) if !self.options.auto_rename => {
+                error!("create: File {full_path} already exists");
+                self.reply_error(reply, libc::EEXIST);
                 return;
             }
+            Ok(Some(_existing_id)) => {
+                // Collision resolved by suffixing the title once the parent is known
+            }
             Ok(None) => {
                 // File doesn't exist, good to proceed
             }
             Err(e) => {
-                eprintln!(
-                    "[ERROR] create: Database error checking for existing file {full_path}: {e}"
-                );
-                reply.error(libc::EIO);
+                error!("create: Database error checking for existing file {full_path}: {e}");
+                self.reply_error(reply, libc::EIO);
                 return;
             }
         }
 
+        // macOS metadata (Finder's .DS_Store, AppleDouble "._*" files) is kept
+        // in memory, so it reads back for as long as the mount lasts
+        if scratch::is_scratch_name(&file_name) {
+            let inode = self.create_scratch(&full_path);
+            let attr = self.scratch_attr(inode, &self.scratch_files[&inode]);
+            let fh = self.open_handle(inode, None);
+            self.reply_created(reply, attr, fh, 0);
+            return;
+        }
+
         // Handle editor temporary files by creating them as regular empty files
         // but don't store them in the database
-        if Self::is_editor_temp_file(file_name) {
+        if self.is_editor_temp_file(&file_name) {
             let inode = self.get_or_create_inode(&full_path);
             let now = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
@@ -993,111 +3265,140 @@ impl Filesystem for ExampleFuseFs {
                 ctime: UNIX_EPOCH + Duration::from_secs(now),
                 crtime: UNIX_EPOCH + Duration::from_secs(now),
                 kind: FileType::RegularFile,
-                perm: 0o644,
+                perm: self.options.file_mode,
                 nlink: 1,
-                uid: 501,
-                gid: 20,
+                uid: self.options.uid,
+                gid: self.options.gid,
                 rdev: 0,
                 flags: 0,
                 blksize: 512,
             };
 
-            reply.created(&TTL, &attr, 0, inode, 0);
+            let fh = self.open_handle(inode, None);
+            self.reply_created(reply, attr, fh, 0);
             return;
         }
 
         // Parse file name to extract title and syntax
-        let file_name_path = Path::new(file_name);
-        let title = match file_name_path.file_stem() {
-            Some(stem) => stem.to_string_lossy().into_owned(),
-            None => {
-                eprintln!("[ERROR] create: Unable to extract title from filename {file_name}");
-                reply.error(libc::EINVAL);
-                return;
-            }
-        };
-
-        let syntax = match file_name_path.extension() {
-            Some(ext) => ext.to_string_lossy().into_owned(),
-            None => {
-                eprintln!(
-                    "[ERROR] create: All files must have an extension (e.g., {file_name}.txt, {file_name}.md)"
-                );
-                reply.error(libc::EINVAL);
-                return;
-            }
-        };
+        let (title, syntax, extensionless) = self.parse_note_name(&file_name);
 
         // Get parent folder ID - None for root, Some(id) for other paths
         let parent_folder_id = if parent_path == "/" {
-            None
+            self.root_folder_id()
         } else {
-            let db_parent_path = Self::normalize_path_for_db(&parent_path);
+            let db_parent_path = self.db_path(&parent_path);
             match self
                 .db
-                .get_folder_id_by_path(db_parent_path, self.user_id.as_str())
+                .get_folder_id_by_path(&db_parent_path, self.user_id.as_str())
             {
                 Ok(Some(id)) => Some(id),
                 Ok(None) => {
-                    eprintln!("[ERROR] create: Parent directory {parent_path} not found");
-                    reply.error(ENOENT);
+                    error!("create: Parent directory {parent_path} not found");
+                    self.reply_error(reply, ENOENT);
                     return;
                 }
                 Err(e) => {
-                    eprintln!(
-                        "[ERROR] create: Database error checking parent directory {parent_path}: {e}"
-                    );
-                    reply.error(ENOENT);
+                    error!("create: Database error checking parent directory {parent_path}: {e}");
+                    self.reply_error(reply, ENOENT);
+                    return;
+                }
+            }
+        };
+
+        // Resolve name collisions by suffixing the title when auto-rename is enabled
+        let (title, full_path) = if self.options.auto_rename {
+            match self.db.unique_note_title(
+                parent_folder_id.as_deref(),
+                &title,
+                &syntax,
+                extensionless,
+                self.user_id.as_str(),
+            ) {
+                Ok(unique) if unique != title => {
+                    let renamed_name = self.note_file_name(&unique, &syntax, extensionless);
+                    let renamed_path = if parent_path == "/" {
+                        format!("/{renamed_name}")
+                    } else {
+                        format!("{parent_path}/{renamed_name}")
+                    };
+                    debug!("create: {full_path} exists, creating {renamed_path}");
+                    (unique, renamed_path)
+                }
+                Ok(unique) => (unique, full_path),
+                Err(e) => {
+                    error!("create: Unable to pick a free name for {full_path}: {e}");
+                    self.reply_error(reply, libc::EIO);
                     return;
                 }
             }
+        } else {
+            (title, full_path)
         };
 
-        // Create new note in database
+        // Create new note in database, starting from the template for its syntax
         let note_id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        let content = self.template_content(parent_folder_id.as_deref(), &syntax);
 
         match self.db.create_note(
             &note_id,
             NoteContent {
                 title: &title,
                 abstract_text: Some(""),
-                content: "",
+                content: &content,
                 syntax: &syntax,
+                extensionless,
             },
             parent_folder_id.as_deref(),
             self.user_id.as_str(),
         ) {
             Ok(_created_id) => {
                 // Note created successfully
+                self.tag_new_note(&note_id);
                 let inode = self.get_or_create_inode(&full_path);
+                if self.options.front_matter {
+                    // The creator starts from the template, not a generated block
+                    self.front_matter_buffers
+                        .insert(inode, content.clone().into_bytes());
+                }
                 let now = SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .unwrap()
                     .as_secs();
 
+                // What reads will return, which may not be the template as stored
+                let note = self.db.get_note_by_id(&note_id).ok().flatten();
+                let size = note
+                    .as_ref()
+                    .map_or(content.len(), |note| self.note_size(inode, note));
                 let attr = FileAttr {
                     ino: inode,
-                    size: 0, // Empty file initially
+                    size: size as u64,
                     blocks: 0,
                     atime: UNIX_EPOCH + Duration::from_secs(now),
                     mtime: UNIX_EPOCH + Duration::from_secs(now),
                     ctime: UNIX_EPOCH + Duration::from_secs(now),
                     crtime: UNIX_EPOCH + Duration::from_secs(now),
                     kind: FileType::RegularFile,
-                    perm: 0o644,
+                    perm: self.options.file_mode,
                     nlink: 1,
-                    uid: 501,
-                    gid: 20,
+                    uid: self.options.uid,
+                    gid: self.options.gid,
                     rdev: 0,
                     flags: 0,
                     blksize: 512,
                 };
 
-                reply.created(&TTL, &attr, 0, inode, 0);
+                let fh = self.open_handle(inode, note.as_ref());
+                let flags = note.map_or(0, |note| self.open_flags(inode, &note));
+                self.reply_created(reply, attr, fh, flags);
+            }
+            Err(e) if Database::is_unique_violation(&e) => {
+                error!("create: File {full_path} already exists");
+                self.reply_error(reply, libc::EEXIST);
             }
             Err(e) => {
-                eprintln!("[ERROR] create: Failed to create note in database for {full_path}: {e}");
-                reply.error(libc::EIO);
+                error!("create: Failed to create note in database for {full_path}: {e}");
+                self.reply_error(reply, libc::EIO);
             }
         }
     }
@@ -1115,7 +3416,7 @@ impl Filesystem for ExampleFuseFs {
         &mut self,
         _req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         data: &[u8],
         _write_flags: u32,
@@ -1123,131 +3424,156 @@ impl Filesystem for ExampleFuseFs {
         _lock_owner: Option<u64>,
         reply: fuser::ReplyWrite,
     ) {
-        eprintln!(
-            "[DEBUG] write: ino={}, offset={}, data_len={}",
+        let _op = self.begin_op("write");
+        debug!(
+            "write: ino={}, offset={}, data_len={}",
             ino,
             offset,
             data.len()
         );
 
+        // After a conflict or a rename over another note, writes through this
+        // handle go to the note that took its place
+        let ino = self.handle_target(fh).unwrap_or(ino);
+
+        // Writes through a handle are collected and stored together
+        if let Some(result) = self.buffer_write(fh, offset, data) {
+            match result {
+                Ok(()) => reply.written(data.len() as u32),
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
+        if self.write_scratch(ino, offset, data) {
+            reply.written(data.len() as u32);
+            return;
+        }
+
         // Get path from inode
         let path = match self.get_path_from_inode(ino) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
+        // Sidecar writes are buffered until flush/release, when the JSON is complete
+        if let Some(note) = self.sidecar_note(&path) {
+            let bytes = Self::apply_write(self.sidecar_bytes(ino, &note), offset, data);
+            self.sidecar_buffers.insert(ino, bytes);
+            reply.written(data.len() as u32);
+            return;
+        }
+        // Attachment writes are likewise collected until flush/release
+        if let Some(result) = self.write_attachment(ino, &path, offset, data) {
+            match result {
+                Ok(()) => reply.written(data.len() as u32),
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
+        // Shared notes were opened for writing only where write access was granted
+        match self.shared_path(&path) {
+            Some(SharedPath::Note {
+                note: Some(note),
+                access: Access::Write,
+                ..
+            }) if self.handle_target(fh).is_some() => {
+                match self.commit_pending(ino) {
+                    Ok(()) => {
+                        self.begin_pending(fh, &note, offset, data);
+                        reply.written(data.len() as u32);
+                    }
+                    Err(errno) => self.reply_error(reply, errno),
+                }
+                return;
+            }
+            Some(SharedPath::Folder(..)) => {
+                self.reply_error(reply, libc::EISDIR);
+                return;
+            }
+            Some(_) => {
+                self.reply_error(reply, libc::EACCES);
+                return;
+            }
+            None => {}
+        }
+
         // Normalize path for database queries
-        let db_path = Self::normalize_path_for_db(&path);
+        let db_path = self.db_path(&path);
 
         // Check if it's a directory - can't write to directories
         match self
             .db
-            .get_folder_id_by_path(db_path, self.user_id.as_str())
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
         {
             Ok(Some(_folder_id)) => {
-                reply.error(libc::EISDIR);
+                self.reply_error(reply, libc::EISDIR);
                 return;
             }
             Ok(None) => {
                 // Not a directory, continue to check if it's a note
             }
             Err(e) => {
-                eprintln!("[ERROR] write: Database error checking for folder {path}: {e}");
-                reply.error(ENOENT);
+                error!("write: Database error checking for folder {path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         }
 
-        // Get the note ID and current content
-        let (note_id, current_content) = match self.db.get_note_id_by_path(db_path) {
-            Ok(Some(note_id)) => {
-                // Get the note content
-                match self.db.get_note_by_id(&note_id) {
-                    Ok(Some(note)) => (note_id, note.content),
-                    Ok(None) => {
-                        eprintln!("[ERROR] write: Note with id {note_id} not found in database");
-                        reply.error(ENOENT);
-                        return;
-                    }
-                    Err(e) => {
-                        eprintln!("[ERROR] write: Database error retrieving note {note_id}: {e}");
-                        reply.error(ENOENT);
-                        return;
-                    }
+        // Get the note, preserving title, syntax, etc. on save
+        let note = match self.note_id_by_path(&db_path) {
+            Ok(Some(note_id)) => match self.db.get_note_by_id(&note_id) {
+                Ok(Some(note)) => note,
+                Ok(None) => {
+                    let errno = self.stale("write", ino, &path);
+                    self.reply_error(reply, errno);
+                    return;
                 }
-            }
+                Err(e) => {
+                    error!("write: Database error retrieving note {note_id}: {e}");
+                    self.reply_error(reply, ENOENT);
+                    return;
+                }
+            },
             Ok(None) => {
-                eprintln!("[DEBUG] write: File {path} not found in database");
-                reply.error(ENOENT);
+                let errno = self.stale("write", ino, &path);
+                self.reply_error(reply, errno);
                 return;
             }
             Err(e) => {
-                eprintln!("[ERROR] write: Database error checking for note {path}: {e}");
-                reply.error(ENOENT);
+                error!("write: Database error checking for note {path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
-        // Calculate new content based on offset and data
-        let new_content = if offset == 0 {
-            // Overwrite from the beginning
-            String::from_utf8_lossy(data).to_string()
-        } else {
-            // Insert/append at offset
-            let mut content_bytes = current_content.into_bytes();
-            let start_pos = offset as usize;
-
-            if start_pos > content_bytes.len() {
-                // If offset is beyond current content, pad with zeros
-                content_bytes.resize(start_pos, 0);
-            }
-
-            // Replace or extend content
-            if start_pos + data.len() <= content_bytes.len() {
-                // Replace existing content at offset
-                content_bytes[start_pos..start_pos + data.len()].copy_from_slice(data);
-            } else {
-                // Extend content - truncate at offset and append new data
-                content_bytes.truncate(start_pos);
-                content_bytes.extend_from_slice(data);
-            }
-
-            String::from_utf8_lossy(&content_bytes).to_string()
-        };
-
-        // Update the note content in the database
-        // First get the note again to preserve title, syntax, etc.
-        let note = match self.db.get_note_by_id(&note_id) {
-            Ok(Some(note)) => note,
-            Ok(None) => {
-                eprintln!("[ERROR] write: Note disappeared during write operation");
-                reply.error(ENOENT);
-                return;
-            }
-            Err(e) => {
-                eprintln!("[ERROR] write: Database error re-retrieving note: {e}");
-                reply.error(libc::EIO);
+        // Start collecting writes through this handle; other handles' writes
+        // to the file are stored first so neither overwrites the other
+        if self.handle_target(fh).is_some() {
+            if let Err(errno) = self.commit_pending(ino) {
+                self.reply_error(reply, errno);
                 return;
             }
-        };
+            self.begin_pending(fh, &note, offset, data);
+            reply.written(data.len() as u32);
+            return;
+        }
+
+        // Calculate new content based on offset and data
+        let current_content = self.note_bytes(ino, &note);
+        let new_content = Self::apply_write(current_content, offset, data);
 
         // Update the note with new content
-        match self.db.update_note(
-            &note_id,
-            &note.title,
-            note.abstract_text.as_deref(),
-            &new_content,
-            &note.syntax,
-        ) {
-            Ok(_success) => {
+        match self.save_note_bytes(ino, &path, &note, new_content) {
+            Ok(true) => {
                 reply.written(data.len() as u32);
             }
+            Ok(false) => self.reply_error(reply, libc::ESTALE),
             Err(e) => {
-                eprintln!("[ERROR] write: Failed to update note content: {e}");
-                reply.error(libc::EIO);
+                error!("write: Failed to update note content: {e}");
+                self.reply_error(reply, libc::EIO);
             }
         }
     }
@@ -1256,54 +3582,168 @@ impl Filesystem for ExampleFuseFs {
     ///
     /// This method verifies that a file exists before allowing it to be opened.
     fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
-        eprintln!("[DEBUG] open: ino={ino}, flags={flags:#x}");
+        let _op = self.begin_op("open");
+        debug!("open: ino={ino}, flags={flags:#x}");
 
         // Get path from inode
         let path = match self.get_path_from_inode(ino) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
+        // Virtual files are generated on every read, so never served from the page cache
+        let html = self.html_note(&path);
+        if self.virtual_file(&path).is_some() || html.is_some() {
+            // A locked note can't be read through its preview either
+            if flags & libc::O_ACCMODE != libc::O_RDONLY
+                || html.is_some_and(|note| self.is_locked(&note.id, note.locked))
+            {
+                self.reply_error(reply, libc::EACCES);
+                return;
+            }
+            let fh = self.open_handle(ino, None);
+            reply.opened(fh, fuser::consts::FOPEN_DIRECT_IO);
+            return;
+        }
+        // Converted now, so a failing pandoc fails the open; the size isn't
+        // known before, hence direct I/O
+        if let Some(ExportPath::File(note, format)) = self.export_path(&path) {
+            if flags & libc::O_ACCMODE != libc::O_RDONLY || self.is_locked(&note.id, note.locked) {
+                self.reply_error(reply, libc::EACCES);
+                return;
+            }
+            if let Err(errno) = self.export_bytes(&note, format) {
+                self.reply_error(reply, errno);
+                return;
+            }
+            let fh = self.open_handle(ino, None);
+            reply.opened(fh, fuser::consts::FOPEN_DIRECT_IO);
+            return;
+        }
+        if self.sidecar_note(&path).is_some() || self.scratch_files.contains_key(&ino) {
+            let fh = self.open_handle(ino, None);
+            reply.opened(fh, 0);
+            return;
+        }
+        match self.asset_path(&path) {
+            Some(AssetPath::Dir(_)) => {
+                self.reply_error(reply, libc::EISDIR);
+                return;
+            }
+            Some(AssetPath::File {
+                attachment: Some(_),
+                ..
+            }) => {
+                let fh = self.open_handle(ino, None);
+                reply.opened(fh, 0);
+                return;
+            }
+            Some(AssetPath::File { .. }) => {
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+            None => {}
+        }
+        match self.shared_path(&path) {
+            Some(SharedPath::Folder(..)) => {
+                self.reply_error(reply, libc::EISDIR);
+                return;
+            }
+            Some(SharedPath::Note {
+                note: Some(note),
+                access,
+                ..
+            }) => {
+                let writable = flags & libc::O_ACCMODE != libc::O_RDONLY;
+                // Encrypted to the owner's recipients, which may not be this identity's
+                if writable && (matches!(access, Access::Read) || note.encrypted)
+                    || self.is_locked(&note.id, note.locked)
+                {
+                    self.reply_error(reply, libc::EACCES);
+                    return;
+                }
+                let fh = self.open_handle(ino, writable.then_some(&note));
+                reply.opened(fh, self.open_flags(ino, &note));
+                return;
+            }
+            Some(SharedPath::Note { .. }) => {
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+            None => {}
+        }
+
         // Normalize path for database queries
-        let db_path = Self::normalize_path_for_db(&path);
+        let db_path = self.db_path(&path);
 
         // First, check if it's a folder/directory - can't open directories as files
         match self
             .db
-            .get_folder_id_by_path(db_path, self.user_id.as_str())
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
         {
             Ok(Some(_folder_id)) => {
                 // It's a directory - return error since we're trying to open it as a file
-                reply.error(libc::EISDIR);
+                self.reply_error(reply, libc::EISDIR);
                 return;
             }
             Ok(None) => {
                 // Not a directory, continue to check if it's a note/file
             }
             Err(e) => {
-                eprintln!("[ERROR] open: Database error checking for folder {path}: {e}");
-                reply.error(ENOENT);
+                error!("open: Database error checking for folder {path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         }
 
         // Second, check if it's a note/file
-        match self.db.get_note_id_by_path(db_path) {
-            Ok(Some(_note_id)) => {
-                // It's a valid file - allow opening
-                reply.opened(ino, 0);
-            }
+        match self.note_id_by_path(&db_path) {
+            Ok(Some(note_id)) => match self.db.get_note_by_id(&note_id) {
+                Ok(Some(note)) if note.user_id != self.user_id => {
+                    debug!("open: {path} belongs to another user");
+                    self.reply_error(reply, libc::EACCES);
+                }
+                Ok(Some(note)) if self.is_locked(&note.id, note.locked) => {
+                    debug!("open: {path} is locked");
+                    self.reply_error(reply, libc::EACCES);
+                }
+                Ok(Some(note)) => {
+                    // It's a valid file - allow opening
+                    let writable = flags & libc::O_ACCMODE != libc::O_RDONLY;
+                    if writable && self.encryption_read_only(&note) {
+                        debug!("open: {path} is encrypted and there is no identity");
+                        self.reply_error(reply, libc::EACCES);
+                        return;
+                    }
+                    // Rather than reading the ciphertext (and writing over it)
+                    if let Err(e) = self.plaintext(&note) {
+                        error!("open: Unable to decrypt {path}: {e}");
+                        self.reply_error(reply, libc::EIO);
+                        return;
+                    }
+                    let fh = self.open_handle(ino, writable.then_some(&note));
+                    reply.opened(fh, self.open_flags(ino, &note));
+                }
+                Ok(None) => {
+                    let errno = self.stale("open", ino, &path);
+                    self.reply_error(reply, errno);
+                }
+                Err(e) => {
+                    error!("open: Database error retrieving note {note_id}: {e}");
+                    self.reply_error(reply, libc::EIO);
+                }
+            },
             Ok(None) => {
                 // Neither a directory nor a note - doesn't exist
-                eprintln!("[DEBUG] open: File {path} not found in database");
-                reply.error(ENOENT);
+                let errno = self.stale("open", ino, &path);
+                self.reply_error(reply, errno);
             }
             Err(e) => {
-                eprintln!("[ERROR] open: Database error checking for note {path}: {e}");
-                reply.error(ENOENT);
+                error!("open: Database error checking for note {path}: {e}");
+                self.reply_error(reply, ENOENT);
             }
         }
     }
@@ -1313,6 +3753,7 @@ impl Filesystem for ExampleFuseFs {
     /// Key behaviors:
     /// - Handles size changes (truncation/extension of file content)
     /// - Updates timestamps in the database when modified
+    /// - Stores chmod/chown changes so they survive remounts
     /// - Validates that the file exists before making changes
     fn setattr(
         &mut self,
@@ -1322,36 +3763,165 @@ impl Filesystem for ExampleFuseFs {
         uid: Option<u32>,
         gid: Option<u32>,
         size: Option<u64>,
-        _atime: Option<fuser::TimeOrNow>,
-        _mtime: Option<fuser::TimeOrNow>,
+        atime: Option<fuser::TimeOrNow>,
+        mtime: Option<fuser::TimeOrNow>,
         _ctime: Option<SystemTime>,
         _fh: Option<u64>,
-        _crtime: Option<SystemTime>,
+        crtime: Option<SystemTime>,
         _chgtime: Option<SystemTime>,
         _bkuptime: Option<SystemTime>,
-        _flags: Option<u32>,
+        flags: Option<u32>,
         reply: ReplyAttr,
     ) {
-        eprintln!("[DEBUG] setattr: ino={ino}, size={size:?}");
+        let _op = self.begin_op("setattr");
+        debug!(
+            "setattr: ino={ino}, size={size:?}, atime={atime:?}, mtime={mtime:?}, crtime={crtime:?}, flags={flags:?}"
+        );
+
+        // utimens (touch -d, rsync -t): stored so they survive remounts
+        let accessed_at = atime.map(Self::time_or_now_to_datetime);
+        let modified_at = mtime.map(Self::time_or_now_to_datetime);
+        // Creation time and chflags, as set by Finder or SetFile on macOS
+        let created_at = crtime.map(DateTime::<Utc>::from);
 
         // Get path from inode
         let path = match self.get_path_from_inode(ino) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
+        if self.virtual_file(&path).is_some()
+            || self.html_note(&path).is_some()
+            || self.export_path(&path).is_some()
+        {
+            self.reply_error(reply, libc::EPERM);
+            return;
+        }
+        if self.setattr_scratch(
+            ino,
+            size,
+            accessed_at.map(SystemTime::from),
+            modified_at.map(SystemTime::from),
+            crtime,
+        ) {
+            reply.attr(
+                &self.options.attr_ttl,
+                &self.scratch_attr(ino, &self.scratch_files[&ino]),
+            );
+            return;
+        }
+        if let Some(note) = self.sidecar_note(&path) {
+            if let Some(new_size) = size {
+                let mut bytes = self.sidecar_bytes(ino, &note);
+                bytes.resize(new_size as usize, 0);
+                self.sidecar_buffers.insert(ino, bytes);
+            }
+            reply.attr(&self.options.attr_ttl, &self.sidecar_attr(ino, &note));
+            return;
+        }
+        // Attachments can be truncated; their other attributes follow the note's
+        match self.asset_path(&path) {
+            Some(AssetPath::Dir(note)) => {
+                reply.attr(&self.options.attr_ttl, &self.asset_dir_attr(ino, &note));
+                return;
+            }
+            Some(AssetPath::File {
+                note,
+                attachment: Some(attachment),
+                ..
+            }) => {
+                if let Some(new_size) = size
+                    && let Err(errno) = self.truncate_attachment(ino, &attachment, new_size)
+                {
+                    self.reply_error(reply, errno);
+                    return;
+                }
+                let attachment = match self
+                    .db
+                    .get_attachment_by_name(&note.id, &attachment.filename)
+                {
+                    Ok(Some(attachment)) => attachment,
+                    Ok(None) => {
+                        self.reply_error(reply, ENOENT);
+                        return;
+                    }
+                    Err(e) => {
+                        error!("setattr: Unable to read attachment {path}: {e}");
+                        self.reply_error(reply, libc::EIO);
+                        return;
+                    }
+                };
+                reply.attr(
+                    &self.options.attr_ttl,
+                    &self.attachment_attr(ino, &note, &attachment),
+                );
+                return;
+            }
+            Some(AssetPath::File { .. }) => {
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+            None => {}
+        }
+
+        if let Some(shared) = self.shared_path(&path) {
+            match self.setattr_shared(ino, &path, shared, size, (mode, uid, gid)) {
+                Ok(attr) => reply.attr(&self.options.attr_ttl, &attr),
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
+
         // Normalize path for database queries
-        let db_path = Self::normalize_path_for_db(&path);
+        let db_path = self.db_path(&path);
 
         // First, check if it's a folder/directory
         match self
             .db
-            .get_folder_id_by_path(db_path, self.user_id.as_str())
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
         {
             Ok(Some(folder_id)) => {
+                if (accessed_at.is_some() || modified_at.is_some() || created_at.is_some())
+                    && let Err(e) = self.db.set_folder_times(
+                        &folder_id,
+                        self.user_id.as_str(),
+                        accessed_at,
+                        modified_at,
+                        created_at,
+                    )
+                {
+                    error!("setattr: Failed to set times of folder {path}: {e}");
+                    self.reply_error(reply, libc::EIO);
+                    return;
+                }
+
+                // chmod/chown
+                if (mode.is_some() || uid.is_some() || gid.is_some())
+                    && let Err(e) = self.db.set_folder_permissions(
+                        &folder_id,
+                        self.user_id.as_str(),
+                        mode.map(|mode| mode & 0o7777),
+                        uid,
+                        gid,
+                    )
+                {
+                    error!("setattr: Failed to set permissions of folder {path}: {e}");
+                    self.reply_error(reply, libc::EIO);
+                    return;
+                }
+                if let Some(flags) = flags
+                    && let Err(e) =
+                        self.db
+                            .set_folder_flags(&folder_id, self.user_id.as_str(), flags)
+                {
+                    error!("setattr: Failed to set flags of folder {path}: {e}");
+                    self.reply_error(reply, libc::EIO);
+                    return;
+                }
+
                 // It's a directory - retrieve full folder object for timestamps
                 match self.db.get_folder_by_id(&folder_id, self.user_id.as_str()) {
                     Ok(Some(folder)) => {
@@ -1359,32 +3929,34 @@ impl Filesystem for ExampleFuseFs {
                             ino,
                             size: 0,
                             blocks: 0,
-                            atime: Self::datetime_to_systemtime(&folder.updated_at),
+                            atime: Self::datetime_to_systemtime(
+                                &folder.accessed_at.unwrap_or(folder.updated_at),
+                            ),
                             mtime: Self::datetime_to_systemtime(&folder.updated_at),
                             ctime: Self::datetime_to_systemtime(&folder.updated_at),
                             crtime: Self::datetime_to_systemtime(&folder.created_at),
                             kind: FileType::Directory,
-                            perm: mode.unwrap_or(0o755) as u16,
-                            nlink: 2,
-                            uid: uid.unwrap_or(501),
-                            gid: gid.unwrap_or(20),
+                            perm: self.folder_perm(&folder),
+                            nlink: self.folder_nlink(Some(&folder.id)),
+                            uid: folder.uid.unwrap_or(self.options.uid),
+                            gid: folder.gid.unwrap_or(self.options.gid),
                             rdev: 0,
-                            flags: 0,
+                            flags: folder.flags.unwrap_or(0),
                             blksize: 512,
                         };
-                        reply.attr(&TTL, &attr);
+                        reply.attr(&self.options.attr_ttl, &attr);
                         return;
                     }
                     Ok(None) => {
-                        eprintln!(
-                            "[ERROR] setattr: Folder ID found but folder object not retrieved: {folder_id}"
+                        error!(
+                            "setattr: Folder ID found but folder object not retrieved: {folder_id}"
                         );
-                        reply.error(ENOENT);
+                        self.reply_error(reply, ENOENT);
                         return;
                     }
                     Err(e) => {
-                        eprintln!("[ERROR] setattr: Failed to get folder by ID {folder_id}: {e}");
-                        reply.error(ENOENT);
+                        error!("setattr: Failed to get folder by ID {folder_id}: {e}");
+                        self.reply_error(reply, ENOENT);
                         return;
                     }
                 }
@@ -1393,46 +3965,63 @@ impl Filesystem for ExampleFuseFs {
                 // Not a directory, continue to check if it's a note/file
             }
             Err(e) => {
-                eprintln!("[ERROR] setattr: Database error checking for folder {path}: {e}");
-                reply.error(ENOENT);
+                error!("setattr: Database error checking for folder {path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         }
 
+        // Store collected writes first, so changes apply on top of them
+        if let Err(errno) = self.commit_pending(ino) {
+            self.reply_error(reply, errno);
+            return;
+        }
+
         // Second, check if it's a note/file and get current content
-        let (note_id, mut note) = match self.db.get_note_id_by_path(db_path) {
+        let (note_id, mut note) = match self.note_id_by_path(&db_path) {
             Ok(Some(note_id)) => {
                 // Get the note content
                 match self.db.get_note_by_id(&note_id) {
+                    Ok(Some(note))
+                        if note.user_id != self.user_id
+                            || self.is_locked(&note.id, note.locked) =>
+                    {
+                        self.reply_error(reply, libc::EACCES);
+                        return;
+                    }
                     Ok(Some(note)) => (note_id, note),
                     Ok(None) => {
-                        eprintln!("[ERROR] setattr: Note with id {note_id} not found in database");
-                        reply.error(ENOENT);
+                        let errno = self.stale("setattr", ino, &path);
+                        self.reply_error(reply, errno);
                         return;
                     }
                     Err(e) => {
-                        eprintln!("[ERROR] setattr: Database error retrieving note {note_id}: {e}");
-                        reply.error(ENOENT);
+                        error!("setattr: Database error retrieving note {note_id}: {e}");
+                        self.reply_error(reply, ENOENT);
                         return;
                     }
                 }
             }
             Ok(None) => {
                 // Neither a directory nor a note - doesn't exist
-                eprintln!("[DEBUG] setattr: File {path} not found in database");
-                reply.error(ENOENT);
+                let errno = self.stale("setattr", ino, &path);
+                self.reply_error(reply, errno);
                 return;
             }
             Err(e) => {
-                eprintln!("[ERROR] setattr: Database error checking for note {path}: {e}");
-                reply.error(ENOENT);
+                error!("setattr: Database error checking for note {path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
         // Handle size changes (file truncation/extension)
         if let Some(new_size) = size {
-            let mut content_bytes = note.content.clone().into_bytes();
+            if self.encryption_read_only(&note) {
+                self.reply_error(reply, libc::EACCES);
+                return;
+            }
+            let mut content_bytes = self.note_bytes(ino, &note);
             let target_size = new_size as usize;
 
             // Adjust content size based on target
@@ -1443,15 +4032,12 @@ impl Filesystem for ExampleFuseFs {
             }
 
             // Update content in database
-            let new_content = String::from_utf8_lossy(&content_bytes).to_string();
-            match self.db.update_note(
-                &note_id,
-                &note.title,
-                note.abstract_text.as_deref(),
-                &new_content,
-                &note.syntax,
-            ) {
-                Ok(_success) => {
+            match self.save_note_bytes(ino, &path, &note, content_bytes.clone()) {
+                Ok(false) => {
+                    self.reply_error(reply, libc::ESTALE);
+                    return;
+                }
+                Ok(true) => {
                     // Re-fetch the note to get updated timestamps from database
                     match self.db.get_note_by_id(&note_id) {
                         Ok(Some(updated_note)) => {
@@ -1459,96 +4045,208 @@ impl Filesystem for ExampleFuseFs {
                         }
                         Ok(None) | Err(_) => {
                             // If we can't re-fetch, just update the content locally
-                            note.content = new_content;
+                            note.content = String::from_utf8_lossy(&content_bytes).to_string();
+                            note.content_size = note.content.len() as u64;
                         }
                     }
                 }
                 Err(e) => {
-                    eprintln!("[ERROR] setattr: Failed to update note content: {e}");
-                    reply.error(libc::EIO);
+                    error!("setattr: Failed to update note content: {e}");
+                    self.reply_error(reply, libc::EIO);
                     return;
                 }
             };
         }
 
+        // chmod/chown
+        if mode.is_some() || uid.is_some() || gid.is_some() {
+            match self
+                .db
+                .set_note_permissions(&note_id, mode.map(|mode| mode & 0o7777), uid, gid)
+            {
+                Ok(_) => {
+                    if let Ok(Some(updated_note)) = self.db.get_note_by_id(&note_id) {
+                        note = updated_note;
+                    }
+                }
+                Err(e) => {
+                    error!("setattr: Failed to set permissions of {path}: {e}");
+                    self.reply_error(reply, libc::EIO);
+                    return;
+                }
+            }
+        }
+
+        // chflags
+        if let Some(flags) = flags {
+            match self.db.set_note_flags(&note_id, flags) {
+                Ok(_) => note.flags = Some(flags),
+                Err(e) => {
+                    error!("setattr: Failed to set flags of {path}: {e}");
+                    self.reply_error(reply, libc::EIO);
+                    return;
+                }
+            }
+        }
+
+        // Apply times after any truncation, which would otherwise bump updated_at
+        if accessed_at.is_some() || modified_at.is_some() || created_at.is_some() {
+            match self
+                .db
+                .set_note_times(&note_id, accessed_at, modified_at, created_at)
+            {
+                Ok(_) => {
+                    if let Ok(Some(updated_note)) = self.db.get_note_by_id(&note_id) {
+                        note = updated_note;
+                    }
+                }
+                Err(e) => {
+                    error!("setattr: Failed to set times of {path}: {e}");
+                    self.reply_error(reply, libc::EIO);
+                    return;
+                }
+            }
+        }
+
         // Calculate file size and blocks
-        let file_size = note.content.len() as u64;
-        let file_blocks = note.content.len().div_ceil(512) as u64;
+        let content_size = self.note_size(ino, &note);
+        let file_size = content_size as u64;
+        let file_blocks = content_size.div_ceil(512) as u64;
 
         // Return updated file attributes
         let attr = FileAttr {
             ino,
             size: file_size,
             blocks: file_blocks,
-            atime: Self::datetime_to_systemtime(&note.updated_at),
+            atime: Self::datetime_to_systemtime(&note.accessed_at.unwrap_or(note.updated_at)),
             mtime: Self::datetime_to_systemtime(&note.updated_at),
             ctime: Self::datetime_to_systemtime(&note.updated_at),
             crtime: Self::datetime_to_systemtime(&note.created_at),
             kind: FileType::RegularFile,
-            perm: mode.unwrap_or(0o644) as u16,
-            nlink: 1,
-            uid: uid.unwrap_or(501),
-            gid: gid.unwrap_or(20),
+            perm: self.note_perm(&note.id, &note.user_id, note.mode, note.locked),
+            nlink: self.note_nlink(&note.id),
+            uid: note.uid.unwrap_or(self.options.uid),
+            gid: note.gid.unwrap_or(self.options.gid),
             rdev: 0,
-            flags: 0,
+            flags: note.flags.unwrap_or(0),
             blksize: 512,
         };
 
-        reply.attr(&TTL, &attr);
+        reply.attr(&self.options.attr_ttl, &attr);
     }
 
     /// Handle file flush operations
     /// This method is called when editors or applications want to ensure that
-    /// all pending writes have been completed. Since we write directly to the
-    /// database in our write() method, this is essentially a no-op, but we
-    /// need to implement it for editor compatibility.
+    /// all pending writes have been completed. Writes collected on the handle
+    /// are stored now.
     ///
     /// Key behaviors:
-    /// - Always returns success since writes are already persistent
+    /// - Returns the error if collected writes couldn't be stored, so close() reports it
     /// - Required for proper editor functionality (many editors call flush before close)
     /// - Validates that the file handle corresponds to a valid file
     fn flush(
         &mut self,
         _req: &Request,
         ino: u64,
-        _fh: u64,
-        _lock_owner: u64,
+        fh: u64,
+        lock_owner: u64,
         reply: fuser::ReplyEmpty,
     ) {
+        let _op = self.begin_op("flush");
+        // Closing any descriptor drops the POSIX locks of its owner
+        self.release_locks(lock_owner);
+
+        if let Err(errno) = self.commit_handle(fh) {
+            error!("flush: Writes through fh={fh} not stored (errno {errno})");
+            self.reply_error(reply, errno);
+            return;
+        }
+
         // Verify that the inode exists (basic validation)
         if self.get_path_from_inode(ino).is_some() {
-            // Since we write directly to the database, flush is always successful
-            reply.ok();
+            // Sidecar JSON and attachment writes are stored now
+            match self
+                .apply_sidecar(ino)
+                .and_then(|()| self.store_attachment(ino))
+            {
+                Ok(()) => reply.ok(),
+                Err(errno) => self.reply_error(reply, errno),
+            }
         } else {
-            reply.error(ENOENT);
+            // Removed while open; closing it isn't an error
+            reply.ok();
         }
     }
 
     /// Handle file release (close) operations
-    /// This method is called when a file handle is closed. Since we don't
-    /// maintain any file-specific state or resources, this is essentially
-    /// a no-op, but it's required for proper FUSE operation.
+    /// This method is called when a file handle is closed. Writes collected
+    /// on the handle are stored (sidecars were stored on flush), then the
+    /// write buffers and the handle's revision are dropped.
     ///
     /// Key behaviors:
-    /// - Always returns success since no cleanup is needed
+    /// - Returns the error if collected writes or sidecar JSON couldn't be stored
     /// - Called when editors close files or when file handles are released
     /// - Validates that the file handle corresponds to a valid file
     fn release(
         &mut self,
         _req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         _flags: i32,
-        _lock_owner: Option<u64>,
+        lock_owner: Option<u64>,
         _flush: bool,
         reply: fuser::ReplyEmpty,
     ) {
+        let _op = self.begin_op("release");
+        self.virtual_seen.remove(&fh);
+        let stored = self.close_handle(fh);
+        if let Err(errno) = stored {
+            error!("release: Writes through fh={fh} not stored (errno {errno})");
+        }
+
+        // flock locks last until the last descriptor is released
+        if let Some(owner) = lock_owner {
+            self.release_locks(owner);
+        }
+
         // Verify that the inode exists (basic validation)
         if self.get_path_from_inode(ino).is_some() {
-            // No cleanup needed since we don't maintain file-specific resources
-            reply.ok();
+            // Front matter edits are already stored; show the regenerated block from now on
+            self.front_matter_buffers.remove(&ino);
+            let sidecar = self.apply_sidecar(ino);
+            if let Err(errno) = sidecar {
+                error!("release: Sidecar for ino={ino} not stored (errno {errno})");
+            }
+            self.sidecar_buffers.remove(&ino);
+            let attachment = self.store_attachment(ino);
+            if let Err(errno) = attachment {
+                error!("release: Attachment at ino={ino} not stored (errno {errno})");
+            }
+            match stored.and(sidecar).and(attachment) {
+                Ok(()) => reply.ok(),
+                Err(errno) => self.reply_error(reply, errno),
+            }
         } else {
-            reply.error(ENOENT);
+            match stored {
+                Ok(()) => reply.ok(),
+                Err(errno) => self.reply_error(reply, errno),
+            }
+        }
+    }
+
+    /// Store the writes collected on the handle (fsync, fdatasync)
+    fn fsync(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        _datasync: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let _op = self.begin_op("fsync");
+        match self.commit_handle(fh) {
+            Ok(()) => reply.ok(),
+            Err(errno) => self.reply_error(reply, errno),
         }
     }
 
@@ -1568,41 +4266,56 @@ impl Filesystem for ExampleFuseFs {
         _flags: u32,
         reply: fuser::ReplyEmpty,
     ) {
+        let _op = self.begin_op("rename");
         let old_name = match name.to_str() {
-            Some(n) => n,
+            Some(n) => self.incoming_name(n),
             None => {
-                reply.error(libc::EINVAL);
+                self.reply_error(reply, libc::EINVAL);
                 return;
             }
         };
 
         let new_name = match newname.to_str() {
-            Some(n) => n,
+            Some(n) => self.incoming_name(n),
             None => {
-                reply.error(libc::EINVAL);
+                self.reply_error(reply, libc::EINVAL);
                 return;
             }
         };
 
-        eprintln!("[DEBUG] rename: {old_name} -> {new_name}");
+        debug!("rename: {old_name} -> {new_name}");
+
+        if let Err(errno) = self.validate_name(&new_name) {
+            error!("rename: Rejecting invalid name {new_name:?}");
+            self.reply_error(reply, errno);
+            return;
+        }
 
         // Get parent paths
         let parent_path = match self.get_path_from_inode(parent) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
         let new_parent_path = match self.get_path_from_inode(newparent) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
+        // Virtual directories are read-only, but orphaned notes can be moved
+        // out of /lost+found
+        let from_virtual = self.is_virtual_dir(&parent_path) && parent_path != LOST_FOUND_DIR;
+        if from_virtual || self.is_virtual_dir(&new_parent_path) {
+            self.reply_error(reply, libc::EROFS);
+            return;
+        }
+
         // Construct old and new paths
         let old_path = if parent_path == "/" {
             format!("/{old_name}")
@@ -1615,40 +4328,192 @@ impl Filesystem for ExampleFuseFs {
         } else {
             format!("{new_parent_path}/{new_name}")
         };
+        if let Err(errno) = self.check_not_denied("rename", &new_path, self.is_dir(&old_path)) {
+            self.reply_error(reply, errno);
+            return;
+        }
+
+        // Scratch files only take other scratch names; mv copies them out instead
+        if self.scratch_file(&old_path).is_some() {
+            if !scratch::is_scratch_name(&new_name) {
+                self.reply_error(reply, libc::EXDEV);
+                return;
+            }
+            self.remove_scratch(&new_path);
+            self.update_inode_mappings(&old_path, &new_path);
+            reply.ok();
+            return;
+        }
+
+        // Shared folders belong to another user; mv copies across instead
+        if self.is_in_shared(&old_path) || self.is_in_shared(&new_path) {
+            self.reply_error(reply, libc::EXDEV);
+            return;
+        }
+
+        // Attachments only move within their note's assets directory; other
+        // moves in or out get EXDEV, so mv copies the file across instead
+        let old_asset = self.asset_path(&old_path);
+        let new_asset = self.asset_path(&new_path);
+        if old_asset.is_some() || new_asset.is_some() {
+            match (old_asset, new_asset) {
+                (
+                    Some(AssetPath::File {
+                        note,
+                        attachment: Some(attachment),
+                        ..
+                    }),
+                    Some(AssetPath::File {
+                        note: new_note,
+                        name,
+                        attachment: replaced,
+                    }),
+                ) if note.id == new_note.id => {
+                    match self.rename_attachment_file(&attachment, replaced.as_ref(), &name) {
+                        Ok(()) => {
+                            self.update_inode_mappings(&old_path, &new_path);
+                            reply.ok();
+                        }
+                        Err(errno) => self.reply_error(reply, errno),
+                    }
+                }
+                (
+                    Some(AssetPath::File {
+                        attachment: None, ..
+                    }),
+                    _,
+                ) => self.reply_error(reply, ENOENT),
+                (Some(AssetPath::Dir(_)), _) | (_, Some(AssetPath::Dir(_))) => {
+                    self.reply_error(reply, libc::EPERM);
+                }
+                _ => self.reply_error(reply, libc::EXDEV),
+            }
+            return;
+        }
+        if self.html_note(&old_path).is_some() {
+            self.reply_error(reply, libc::EPERM);
+            return;
+        }
 
         // Normalize paths for database queries
-        let db_old_path = Self::normalize_path_for_db(&old_path);
+        let db_old_path = self.db_path(&old_path);
 
         // Get the new parent ID for database operations
         let new_parent_id = if new_parent_path == "/" {
-            None
+            self.root_folder_id()
         } else {
-            let db_new_parent_path = Self::normalize_path_for_db(&new_parent_path);
+            let db_new_parent_path = self.db_path(&new_parent_path);
             match self
                 .db
-                .get_folder_id_by_path(db_new_parent_path, self.user_id.as_str())
+                .get_folder_id_by_path(&db_new_parent_path, self.user_id.as_str())
             {
                 Ok(maybe_id) => maybe_id,
                 Err(e) => {
-                    eprintln!(
-                        "[ERROR] rename: Database error checking for new parent folder {new_parent_path}: {e}"
+                    error!(
+                        "rename: Database error checking for new parent folder {new_parent_path}: {e}"
                     );
-                    reply.error(ENOENT);
+                    self.reply_error(reply, ENOENT);
                     return;
                 }
             }
         };
 
+        // Renaming a hard link moves only that name
+        match self
+            .db
+            .get_alias_by_path(&db_old_path, self.user_id.as_str())
+        {
+            Ok(Some(alias)) => {
+                if new_parent_path != "/" && new_parent_id.is_none() {
+                    self.reply_error(reply, ENOENT);
+                    return;
+                }
+                let db_new_path = self.db_path(&new_path);
+                let taken = matches!(self.db.get_note_id_by_path(&db_new_path), Ok(Some(_)))
+                    || matches!(
+                        self.db.get_alias_by_path(&db_new_path, self.user_id.as_str()),
+                        Ok(Some(other)) if other.id != alias.id
+                    );
+                if taken {
+                    error!("rename: {new_path} already exists");
+                    self.reply_error(reply, libc::EEXIST);
+                    return;
+                }
+                let (title, syntax, extensionless) = self.parse_note_name(&new_name);
+                match self.db.update_note_alias(
+                    &alias.id,
+                    new_parent_id.as_deref(),
+                    &title,
+                    &syntax,
+                    extensionless,
+                ) {
+                    Ok(_) => reply.ok(),
+                    Err(e) => {
+                        error!("rename: Unable to move hard link {old_path}: {e}");
+                        self.reply_error(reply, libc::EIO);
+                    }
+                }
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("rename: Database error checking for hard link {old_path}: {e}");
+                self.reply_error(reply, libc::EIO);
+                return;
+            }
+        }
+
         // First, check if it's a directory being renamed
         match self
             .db
-            .get_folder_id_by_path(db_old_path, self.user_id.as_str())
+            .get_folder_id_by_path(&db_old_path, self.user_id.as_str())
         {
             Ok(Some(folder_id)) => {
+                let old_path = self.stored_folder_path(&folder_id, &old_path);
+
+                // Refuse (or suffix) a name already taken by another folder in the target
+                let new_title = Self::name_to_title(&new_name);
+                let title = match self.db.find_folder_by_title(
+                    new_parent_id.as_deref(),
+                    &new_title,
+                    self.user_id.as_str(),
+                ) {
+                    Ok(Some(existing_id)) if existing_id != folder_id => {
+                        if !self.options.auto_rename {
+                            error!("rename: Directory {new_path} already exists");
+                            self.reply_error(reply, libc::EEXIST);
+                            return;
+                        }
+                        match self.db.unique_folder_title(
+                            new_parent_id.as_deref(),
+                            &new_title,
+                            self.user_id.as_str(),
+                        ) {
+                            Ok(title) => title,
+                            Err(e) => {
+                                error!("rename: Unable to pick a free name for {new_path}: {e}");
+                                self.reply_error(reply, libc::EIO);
+                                return;
+                            }
+                        }
+                    }
+                    Ok(_) => new_title,
+                    Err(e) => {
+                        error!("rename: Database error checking target {new_path}: {e}");
+                        self.reply_error(reply, libc::EIO);
+                        return;
+                    }
+                };
+                let new_path = if new_parent_path == "/" {
+                    format!("/{}", self.title_to_name(&title))
+                } else {
+                    format!("{new_parent_path}/{}", self.title_to_name(&title))
+                };
+
                 // It's a directory - update both name and parent
                 match self
                     .db
-                    .update_folder(&folder_id, new_name, self.user_id.as_str())
+                    .update_folder(&folder_id, &title, self.user_id.as_str())
                 {
                     Ok(_success) => {
                         // Also update the parent relationship
@@ -1662,16 +4527,26 @@ impl Filesystem for ExampleFuseFs {
                                 reply.ok();
                                 return;
                             }
+                            Err(e) if Database::is_unique_violation(&e) => {
+                                error!("rename: Directory {new_path} already exists");
+                                self.reply_error(reply, libc::EEXIST);
+                                return;
+                            }
                             Err(e) => {
-                                eprintln!("[ERROR] rename: Failed to update folder parent: {e}");
-                                reply.error(libc::EIO);
+                                error!("rename: Failed to update folder parent: {e}");
+                                self.reply_error(reply, libc::EIO);
                                 return;
                             }
                         }
                     }
+                    Err(e) if Database::is_unique_violation(&e) => {
+                        error!("rename: Directory {new_path} already exists");
+                        self.reply_error(reply, libc::EEXIST);
+                        return;
+                    }
                     Err(e) => {
-                        eprintln!("[ERROR] rename: Failed to update folder: {e}");
-                        reply.error(libc::EIO);
+                        error!("rename: Failed to update folder: {e}");
+                        self.reply_error(reply, libc::EIO);
                         return;
                     }
                 }
@@ -1680,52 +4555,65 @@ impl Filesystem for ExampleFuseFs {
                 // Not a directory, continue to check if it's a note
             }
             Err(e) => {
-                eprintln!("[ERROR] rename: Database error checking for folder {old_path}: {e}");
-                reply.error(ENOENT);
+                error!("rename: Database error checking for folder {old_path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         }
 
         // Second, check if it's a note/file being renamed
-        match self.db.get_note_id_by_path(db_old_path) {
-            Ok(Some(note_id)) => {
+        match self.resolve_note(&parent_path, &old_name) {
+            Ok(Some((note_id, old_path))) => {
+                if let Err(errno) = self
+                    .check_note_owner(&note_id)
+                    .and_then(|()| self.check_note_unlocked(&note_id))
+                {
+                    self.reply_error(reply, errno);
+                    return;
+                }
+                let old_path = self.stored_note_path(&note_id, &old_path);
+
                 // It's a note/file - get the note and update it
                 match self.db.get_note_by_id(&note_id) {
-                    Ok(Some(note)) => {
+                    Ok(Some(_note)) => {
                         // Extract title and extension from new filename
-                        let file_name_path = Path::new(new_name);
-                        let title = match file_name_path.file_stem() {
-                            Some(stem) => stem.to_string_lossy().into_owned(),
-                            None => {
-                                eprintln!(
-                                    "[ERROR] rename: Unable to extract title from {new_name}"
-                                );
-                                reply.error(libc::EINVAL);
+                        let (title, syntax, extensionless) = self.parse_note_name(&new_name);
+
+                        // Renaming over another note (an editor's atomic save)
+                        // replaces its content but keeps its id
+                        match self.db.find_note_by_title(
+                            new_parent_id.as_deref(),
+                            &title,
+                            &syntax,
+                            extensionless,
+                            self.user_id.as_str(),
+                        ) {
+                            Ok(Some(existing_id)) if existing_id != note_id => {
+                                match self.replace_note(&note_id, &old_path, &existing_id) {
+                                    Ok(()) => reply.ok(),
+                                    Err(errno) => self.reply_error(reply, errno),
+                                }
                                 return;
                             }
-                        };
-
-                        // Extension is required because our database stores the syntax field
-                        // separately for use by the front-end application to determine file type
-                        let syntax = match file_name_path.extension() {
-                            Some(ext) => ext.to_string_lossy().into_owned(),
-                            None => {
-                                eprintln!(
-                                    "[ERROR] rename: Cannot rename file without extension (e.g., {new_name}.txt, {new_name}.md)"
-                                );
-                                reply.error(libc::EINVAL);
+                            Ok(_) => {}
+                            Err(e) => {
+                                error!("rename: Database error checking target {new_path}: {e}");
+                                self.reply_error(reply, libc::EIO);
                                 return;
                             }
+                        }
+                        let new_file_name = self.note_file_name(&title, &syntax, extensionless);
+                        let new_path = if new_parent_path == "/" {
+                            format!("/{new_file_name}")
+                        } else {
+                            format!("{new_parent_path}/{new_file_name}")
                         };
 
                         // Update note with new title and syntax
-                        match self.db.update_note(
-                            &note_id,
-                            &title,
-                            note.abstract_text.as_deref(),
-                            &note.content,
-                            &syntax,
-                        ) {
+                        match self
+                            .db
+                            .update_note_name(&note_id, &title, &syntax, extensionless)
+                        {
                             Ok(_success) => {
                                 // Update note parent if moving to different directory
                                 match self
@@ -1736,38 +4624,44 @@ impl Filesystem for ExampleFuseFs {
                                         self.update_inode_mappings(&old_path, &new_path);
                                         reply.ok();
                                     }
+                                    Err(e) if Database::is_unique_violation(&e) => {
+                                        error!("rename: File {new_path} already exists");
+                                        self.reply_error(reply, libc::EEXIST);
+                                    }
                                     Err(e) => {
-                                        eprintln!(
-                                            "[ERROR] rename: Failed to update note parent: {e}"
-                                        );
-                                        reply.error(libc::EIO);
+                                        error!("rename: Failed to update note parent: {e}");
+                                        self.reply_error(reply, libc::EIO);
                                     }
                                 }
                             }
+                            Err(e) if Database::is_unique_violation(&e) => {
+                                error!("rename: File {new_path} already exists");
+                                self.reply_error(reply, libc::EEXIST);
+                            }
                             Err(e) => {
-                                eprintln!("[ERROR] rename: Failed to update note: {e}");
-                                reply.error(libc::EIO);
+                                error!("rename: Failed to update note: {e}");
+                                self.reply_error(reply, libc::EIO);
                             }
                         }
                     }
                     Ok(None) => {
-                        eprintln!("[ERROR] rename: Note with id {note_id} not found in database");
-                        reply.error(ENOENT);
+                        error!("rename: Note with id {note_id} not found in database");
+                        self.reply_error(reply, ENOENT);
                     }
                     Err(e) => {
-                        eprintln!("[ERROR] rename: Database error retrieving note {note_id}: {e}");
-                        reply.error(ENOENT);
+                        error!("rename: Database error retrieving note {note_id}: {e}");
+                        self.reply_error(reply, ENOENT);
                     }
                 }
             }
             Ok(None) => {
                 // Neither a directory nor a note - doesn't exist
-                eprintln!("[DEBUG] rename: Path {old_path} not found in database");
-                reply.error(ENOENT);
+                debug!("rename: Path {old_path} not found in database");
+                self.reply_error(reply, ENOENT);
             }
             Err(e) => {
-                eprintln!("[ERROR] rename: Database error checking for note {old_path}: {e}");
-                reply.error(ENOENT);
+                error!("rename: Database error checking for note {old_path}: {e}");
+                self.reply_error(reply, ENOENT);
             }
         }
     }
@@ -1776,98 +4670,386 @@ impl Filesystem for ExampleFuseFs {
     ///
     /// In the unified schema, file deletion has special considerations:
     /// - Regular files: Delete the note if it has no children
-    /// - Index files: Clear the content of the parent note (but don't delete the note itself)
+    /// - Index files: Clear the content the folder carries (but don't delete the note itself)
     /// - Cannot delete notes that have children (they appear as directories)
     ///
     /// Key behaviors:
     /// - Deletes the most recent row (based on updated_at) if duplicates exist
     /// - Extracts title from filename using syntax-based extensions
-    /// - Handles index.{ext} files specially by clearing parent content
+    /// - Handles index.{ext} files specially by clearing their content; rmdir
+    ///   deletes them along with the folder
     /// - Updates inode mappings to reflect the deletion
     /// - Required for proper file manager and shell integration
     fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+        let _op = self.begin_op("unlink");
         // Check the filename
         let filename = match name.to_str() {
-            Some(n) => n,
+            Some(n) => self.incoming_name(n),
             None => {
-                reply.error(libc::EINVAL);
+                self.reply_error(reply, libc::EINVAL);
                 return;
             }
         };
 
+        if scratch::is_scratch_name(&filename)
+            && let Some(parent_path) = self.get_path_from_inode(parent)
+        {
+            let path = if parent_path == "/" {
+                format!("/{filename}")
+            } else {
+                format!("{parent_path}/{filename}")
+            };
+            self.remove_scratch(&path);
+        }
+
         // Handle special editor files (backup, swap, temporary files)
-        if Self::is_editor_temp_file(filename) {
+        if self.is_editor_temp_file(&filename) {
             // For editor temporary files, just reply OK without doing anything
             // This allows editors like Neovim to create and delete backup files
             reply.ok();
             return;
         }
 
-        // Get parent path
-        let parent_path = match self.get_path_from_inode(parent) {
-            Some(path) => path.clone(),
-            None => {
-                reply.error(ENOENT);
+        // Get parent path
+        let parent_path = match self.get_path_from_inode(parent) {
+            Some(path) => path,
+            None => {
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+        };
+
+        // Virtual directories are read-only; orphaned notes can be deleted
+        if self.is_virtual_dir(&parent_path) && parent_path != LOST_FOUND_DIR {
+            self.reply_error(reply, libc::EROFS);
+            return;
+        }
+
+        // Construct the full path
+        let path = if parent_path == "/" {
+            format!("/{filename}")
+        } else {
+            format!("{parent_path}/{filename}")
+        };
+
+        match self.asset_path(&path) {
+            Some(AssetPath::File {
+                attachment: Some(attachment),
+                ..
+            }) => {
+                if let Some(ino) = self.inode_map.get(&path) {
+                    self.asset_buffers.remove(ino);
+                }
+                match self.db.delete_attachment(&attachment.id) {
+                    Ok(_) => reply.ok(),
+                    Err(e) => {
+                        error!("unlink: Unable to remove attachment {path}: {e}");
+                        self.reply_error(reply, libc::EIO);
+                    }
+                }
+                return;
+            }
+            Some(AssetPath::File { .. }) => {
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+            Some(AssetPath::Dir(_)) => {
+                self.reply_error(reply, libc::EISDIR);
+                return;
+            }
+            None => {}
+        }
+        if let Some(shared) = self.shared_path(&path) {
+            match self.remove_shared_note(&shared) {
+                Ok(()) => {
+                    if let Some(inode) = self.remove_inode_mapping(&path) {
+                        self.release_inode(inode);
+                    }
+                    reply.ok();
+                }
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
+        // Rendered companions go away with their note
+        if self.html_note(&path).is_some() {
+            self.reply_error(reply, libc::EPERM);
+            return;
+        }
+        if self.remove_companion_left_behind(&path, false) {
+            reply.ok();
+            return;
+        }
+
+        // Removing a hard link leaves the note and its other names alone
+        let db_path = self.db_path(&path);
+        match self.db.get_alias_by_path(&db_path, self.user_id.as_str()) {
+            Ok(Some(alias)) => {
+                match self.db.delete_note_alias(&alias.id) {
+                    Ok(_) => reply.ok(),
+                    Err(e) => {
+                        error!("unlink: Unable to remove hard link {path}: {e}");
+                        self.reply_error(reply, libc::EIO);
+                    }
+                }
                 return;
             }
-        };
-
-        // Construct the full path
-        let path = if parent_path == "/" {
-            format!("/{filename}")
-        } else {
-            format!("{parent_path}/{filename}")
-        };
-
-        // Normalize path for database queries
-        let db_path = Self::normalize_path_for_db(&path);
+            Ok(None) => {}
+            Err(e) => {
+                error!("unlink: Database error checking for hard link {path}: {e}");
+                self.reply_error(reply, libc::EIO);
+                return;
+            }
+        }
 
         // Get the id
 
-        let id = match self.db.get_note_id_by_path(db_path) {
+        let (id, note_path) = match self.resolve_note(&parent_path, &filename) {
             Ok(maybe_id) => match maybe_id {
-                Some(id) => id,
+                Some(found) => found,
                 None => {
-                    eprintln!("74 [ERROR] (fn open) Could not find id for {path}");
-                    reply.error(ENOENT);
+                    error!("74 (fn open) Could not find id for {path}");
+                    self.reply_error(reply, ENOENT);
                     return;
                 }
             },
             Err(e) => {
-                eprintln!("75 [ERROR] (fn open) Could not find id for {path}: {e}");
-                reply.error(ENOENT);
+                error!("75 (fn open) Could not find id for {path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
         if self.is_dir(&path) {
             // This note has children, so it's a directory - cannot delete as a file
-            reply.error(libc::EISDIR);
+            self.reply_error(reply, libc::EISDIR);
+            return;
+        }
+        if let Err(errno) = self
+            .check_note_owner(&id)
+            .and_then(|()| self.check_note_unlocked(&id))
+        {
+            self.reply_error(reply, errno);
+            return;
+        }
+
+        // An index file is the folder's own content: clear it, rmdir removes the note
+        if parent_path != "/" && self.is_index_name(&filename) {
+            match self.db.clear_note_content(&id) {
+                Ok(_) => {
+                    if let Some(&inode) = self.inode_map.get(&note_path) {
+                        self.front_matter_buffers.remove(&inode);
+                    }
+                    reply.ok();
+                }
+                Err(e) => {
+                    error!("unlink: Unable to clear index {path}: {e}");
+                    self.reply_error(reply, libc::EIO);
+                }
+            }
             return;
         }
 
+        // Resolve the stored spelling before the row (and its path) disappears
+        let file_path = self.stored_note_path(&id, &note_path);
+
+        // While hard links remain, the note lives on under the oldest of them
+        match self.db.promote_note_alias(&id) {
+            Ok(Some(_)) => {
+                if let Ok(Some(new_path)) = self.db.get_note_path_by_id(&id)
+                    && let Some(new_path) = self.fuse_path(&new_path)
+                {
+                    self.update_inode_mappings(&file_path, &new_path);
+                }
+                reply.ok();
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("unlink: Unable to hand {path} over to a hard link: {e}");
+                self.reply_error(reply, libc::EIO);
+                return;
+            }
+        }
+
+        let meta = self.db.get_note_meta_by_id(&id).ok().flatten();
         match self.db.delete_note(&id) {
             Ok(_) => {
                 // Successfully deleted the note
-                let file_path = if parent_path == "/" {
-                    format!("/{filename}")
-                } else {
-                    format!("{parent_path}/{filename}")
-                };
+                if let Some(meta) = meta {
+                    self.keep_removed_companions(&file_path, &meta);
+                }
 
-                if let Some(inode) = self.inode_map.remove(&file_path) {
-                    self.reverse_inode_map.remove(&inode);
+                if let Some(inode) = self.remove_inode_mapping(&file_path) {
+                    self.release_inode(inode);
                 }
             }
             Err(_) => {
                 // If not successful
-                reply.error(libc::EIO);
+                self.reply_error(reply, libc::EIO);
                 return;
             }
         }
         reply.ok();
     }
 
+    /// Handle hard link creation
+    ///
+    /// Key behaviors:
+    /// - Adds another name for a note (a row in note_aliases), in any folder
+    /// - Every name shares the note's inode, content and metadata
+    /// - nlink counts the names; unlink removes one name at a time
+    /// - Folders, sidecars and virtual entries can't be linked (EPERM)
+    fn link(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        let _op = self.begin_op("link");
+        let link_name = match newname.to_str() {
+            Some(s) => self.incoming_name(s).into_owned(),
+            None => {
+                self.reply_error(reply, libc::EINVAL);
+                return;
+            }
+        };
+
+        debug!("link: ino={ino}, newparent={newparent}, newname={link_name}");
+
+        if let Err(errno) = self.validate_name(&link_name) {
+            error!("link: Rejecting invalid name {link_name:?}");
+            self.reply_error(reply, errno);
+            return;
+        }
+
+        let (Some(path), Some(parent_path)) = (
+            self.get_path_from_inode(ino),
+            self.get_path_from_inode(newparent),
+        ) else {
+            self.reply_error(reply, ENOENT);
+            return;
+        };
+
+        // Virtual directories are read-only
+        if self.is_virtual_dir(&parent_path) {
+            self.reply_error(reply, libc::EROFS);
+            return;
+        }
+
+        // Hard links can't cross into or out of another user's shared folder
+        if self.is_in_shared(&path) || self.is_in_shared(&format!("{parent_path}/{link_name}")) {
+            self.reply_error(reply, libc::EXDEV);
+            return;
+        }
+
+        // Only notes have names to add; folders and generated files don't
+        let note = match self.note_id_by_path(&self.db_path(&path)) {
+            Ok(Some(note_id)) if self.sidecar_note(&path).is_none() => {
+                match self.db.get_note_by_id(&note_id) {
+                    Ok(Some(note)) if note.user_id != self.user_id => {
+                        self.reply_error(reply, libc::EACCES);
+                        return;
+                    }
+                    Ok(Some(note)) => note,
+                    _ => {
+                        self.reply_error(reply, ENOENT);
+                        return;
+                    }
+                }
+            }
+            Ok(_) => {
+                error!("link: {path} is not a note");
+                self.reply_error(reply, libc::EPERM);
+                return;
+            }
+            Err(e) => {
+                error!("link: Database error looking up {path}: {e}");
+                self.reply_error(reply, libc::EIO);
+                return;
+            }
+        };
+
+        let link_name = self.canonical_note_name(&link_name);
+        let link_path = if parent_path == "/" {
+            format!("/{link_name}")
+        } else {
+            format!("{parent_path}/{link_name}")
+        };
+        if let Err(errno) = self.check_not_denied("link", &link_path, false) {
+            self.reply_error(reply, errno);
+            return;
+        }
+        let db_link_path = self.db_path(&link_path);
+        let taken = !matches!(self.db.get_note_id_by_path(&db_link_path), Ok(None))
+            || !matches!(
+                self.db
+                    .get_folder_id_by_path(&db_link_path, self.user_id.as_str()),
+                Ok(None)
+            )
+            || !matches!(
+                self.db
+                    .get_alias_by_path(&db_link_path, self.user_id.as_str()),
+                Ok(None)
+            );
+        if taken {
+            error!("link: {link_path} already exists");
+            self.reply_error(reply, libc::EEXIST);
+            return;
+        }
+
+        let parent_id = if parent_path == "/" {
+            self.root_folder_id()
+        } else {
+            match self
+                .db
+                .get_folder_id_by_path(&self.db_path(&parent_path), self.user_id.as_str())
+            {
+                Ok(Some(id)) => Some(id),
+                _ => {
+                    self.reply_error(reply, ENOENT);
+                    return;
+                }
+            }
+        };
+
+        let (title, syntax, extensionless) = self.parse_note_name(&link_name);
+        if let Err(e) = self.db.create_note_alias(
+            &note.id,
+            parent_id.as_deref(),
+            &title,
+            &syntax,
+            extensionless,
+            self.user_id.as_str(),
+        ) {
+            error!("link: Unable to create hard link {link_path}: {e}");
+            self.reply_error(reply, libc::EIO);
+            return;
+        }
+
+        let content_size = self.note_size(ino, &note);
+        let attr = FileAttr {
+            ino,
+            size: content_size as u64,
+            blocks: content_size.div_ceil(512) as u64,
+            atime: Self::datetime_to_systemtime(&note.accessed_at.unwrap_or(note.updated_at)),
+            mtime: Self::datetime_to_systemtime(&note.updated_at),
+            ctime: Self::datetime_to_systemtime(&note.updated_at),
+            crtime: Self::datetime_to_systemtime(&note.created_at),
+            kind: FileType::RegularFile,
+            perm: self.note_perm(&note.id, &note.user_id, note.mode, note.locked),
+            nlink: self.note_nlink(&note.id),
+            uid: note.uid.unwrap_or(self.options.uid),
+            gid: note.gid.unwrap_or(self.options.gid),
+            rdev: 0,
+            flags: note.flags.unwrap_or(0),
+            blksize: 512,
+        };
+        self.reply_entry(reply, attr);
+    }
+
     /// Only required in linux kernel before 2.6
     /// Otherwise the kernel will call open and create
     fn mknod(
@@ -1880,85 +5062,79 @@ impl Filesystem for ExampleFuseFs {
         _rdev: u32,
         reply: ReplyEntry,
     ) {
+        let _op = self.begin_op("mknod");
         let file_name = match name.to_str() {
-            Some(s) => s,
+            Some(s) => self.incoming_name(s),
             None => {
-                reply.error(libc::EINVAL);
+                self.reply_error(reply, libc::EINVAL);
                 return;
             }
         };
 
+        if let Err(errno) = self.validate_name(&file_name) {
+            error!("mknod: Rejecting invalid name {file_name:?}");
+            self.reply_error(reply, errno);
+            return;
+        }
+
         // Get parent path
         let parent_path = match self.get_path_from_inode(parent) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
+        // Virtual directories are read-only
+        if self.is_virtual_dir(&parent_path) {
+            self.reply_error(reply, libc::EROFS);
+            return;
+        }
+
         let parent_id = if parent_path == "/" {
-            None
+            self.root_folder_id()
         } else {
-            let db_parent_path = Self::normalize_path_for_db(&parent_path);
+            let db_parent_path = self.db_path(&parent_path);
             match self
                 .db
-                .get_folder_id_by_path(db_parent_path, self.user_id.as_str())
+                .get_folder_id_by_path(&db_parent_path, self.user_id.as_str())
             {
                 Ok(maybe_id) => maybe_id,
                 Err(e) => {
-                    eprintln!(
-                        "76 [ERROR] (fn open) Unable to query database for id for the directory {parent_path}: {e}"
+                    error!(
+                        "76 (fn open) Unable to query database for id for the directory {parent_path}: {e}"
                     );
-                    reply.error(ENOENT);
+                    self.reply_error(reply, ENOENT);
                     return;
                 }
             }
         };
         // Create the candidate full path
+        let note_name = self.canonical_note_name(&file_name);
         let full_path = if parent_path == "/" {
-            format!("/{file_name}")
+            format!("/{note_name}")
         } else {
-            format!("{parent_path}/{file_name}")
+            format!("{parent_path}/{note_name}")
         };
+        if let Err(errno) = self.check_not_denied("mknod", &full_path, false) {
+            self.reply_error(reply, errno);
+            return;
+        }
 
         // Get the title and extension
-
-        let file_name_path = Path::new(file_name);
-        let base = file_name_path
-            .file_stem()
-            .map(|s| s.to_string_lossy().into_owned());
-        let ext = file_name_path
-            .extension()
-            .map(|e| e.to_string_lossy().into_owned());
-
-        let title = match base {
-            Some(t) => t,
-            None => {
-                eprintln!("77 [ERROR] (fn mknod) Unable to get stem from {file_name}");
-                reply.error(ENOENT);
-                return;
-            }
-        };
-        let syntax = match ext {
-            Some(s) => s,
-            None => {
-                eprintln!(
-                    "78 [ERROR] All files in this filesystem must have an extension (e.g., {file_name}.txt, {file_name}.md)"
-                );
-                reply.error(libc::EINVAL);
-                return;
-            }
-        };
+        let (title, syntax, extensionless) = self.parse_note_name(&file_name);
 
         let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        let content = self.template_content(parent_id.as_deref(), &syntax);
         let _id = match self.db.create_note(
             &id,
             NoteContent {
                 title: &title,
                 abstract_text: Some(""),
-                content: "",
+                content: &content,
                 syntax: &syntax,
+                extensionless,
             },
             parent_id.as_deref(),
             self.user_id.as_str(),
@@ -1966,14 +5142,19 @@ impl Filesystem for ExampleFuseFs {
             // Get the returned id in case the API changes
             Ok(id) => id,
             Err(e) => {
-                eprintln!("79 [ERROR] Unable to create note for {full_path}: {e}");
-                reply.error(ENOENT);
+                error!("79 Unable to create note for {full_path}: {e}");
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
+        self.tag_new_note(&id);
 
         // Create inode for the new file
         let inode = self.get_or_create_inode(&full_path);
+        if self.options.front_matter {
+            self.front_matter_buffers
+                .insert(inode, content.clone().into_bytes());
+        }
 
         // Get current timestamp for attributes
         let now = SystemTime::now()
@@ -1981,9 +5162,13 @@ impl Filesystem for ExampleFuseFs {
             .unwrap()
             .as_secs();
 
+        let size = match self.db.get_note_by_id(&id) {
+            Ok(Some(note)) => self.note_size(inode, &note),
+            _ => content.len(),
+        };
         let attr = FileAttr {
             ino: inode,
-            size: 0, // Empty file initially
+            size: size as u64,
             blocks: 0,
             atime: UNIX_EPOCH + Duration::from_secs(now),
             mtime: UNIX_EPOCH + Duration::from_secs(now),
@@ -1992,34 +5177,51 @@ impl Filesystem for ExampleFuseFs {
             kind: FileType::RegularFile,
             perm: (mode & 0o777) as u16,
             nlink: 1,
-            uid: 501,
-            gid: 20,
+            uid: self.options.uid,
+            gid: self.options.gid,
             rdev: 0,
             flags: 0,
             blksize: 512,
         };
 
-        reply.entry(&TTL, &attr, 0);
+        self.reply_entry(reply, attr);
     }
 
-    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+        let _op = self.begin_op("rmdir");
+        let _in_flight = self.interrupts.begin(req.pid());
         let dirname = match name.to_str() {
-            Some(n) => n,
+            Some(n) => self.incoming_name(n),
             None => {
-                reply.error(libc::EINVAL);
+                self.reply_error(reply, libc::EINVAL);
                 return;
             }
         };
 
         // Get parent path
         let parent_path = match self.get_path_from_inode(parent) {
-            Some(path) => path.clone(),
+            Some(path) => path,
             None => {
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
+        // Removing a saved search leaves the notes it matched alone
+        if parent_path == SEARCHES_DIR {
+            match self.remove_saved_search_dir(&dirname) {
+                Ok(()) => reply.ok(),
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
+
+        // Virtual directories are read-only
+        if self.is_virtual_dir(&parent_path) {
+            self.reply_error(reply, libc::EROFS);
+            return;
+        }
+
         // Construct the path of the directory to delete
         let path = if parent_path == "/" {
             format!("/{dirname}")
@@ -2027,21 +5229,61 @@ impl Filesystem for ExampleFuseFs {
             format!("{parent_path}/{dirname}")
         };
 
+        // An assets directory lasts as long as its note
+        match self.asset_path(&path) {
+            Some(AssetPath::Dir(_)) => {
+                self.reply_error(reply, libc::EPERM);
+                return;
+            }
+            Some(AssetPath::File { .. }) => {
+                self.reply_error(reply, libc::ENOTDIR);
+                return;
+            }
+            None => {}
+        }
+        if self.remove_companion_left_behind(&path, true) {
+            reply.ok();
+            return;
+        }
+        if let Some(shared) = self.shared_path(&path) {
+            match self.remove_shared_folder(&shared) {
+                Ok(()) => {
+                    self.remove_inode_mapping(&path);
+                    reply.ok();
+                }
+                Err(errno) => self.reply_error(reply, errno),
+            }
+            return;
+        }
+
         // Get the folder ID of the directory being deleted
-        let db_path = Self::normalize_path_for_db(&path);
+        let db_path = self.db_path(&path);
         let folder_id = match self
             .db
-            .get_folder_id_by_path(db_path, self.user_id.as_str())
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
         {
             Ok(Some(id)) => id,
             Ok(None) => {
-                eprintln!("[ERROR] rmdir: Folder {path} not found");
-                reply.error(ENOENT);
+                error!("rmdir: Folder {path} not found");
+                self.reply_error(reply, ENOENT);
                 return;
             }
             Err(e) => {
-                eprintln!("[ERROR] rmdir: Database error looking up folder {path}: {e}");
-                reply.error(ENOENT);
+                error!("rmdir: Database error looking up folder {path}: {e}");
+                self.reply_error(reply, ENOENT);
+                return;
+            }
+        };
+
+        // The folder's index notes are its own content and go with it
+        let index_ids = match self
+            .db
+            .get_index_note_ids(&folder_id, self.user_id.as_str())
+        {
+            Ok(ids) => ids,
+            Err(e) => {
+                error!("rmdir: Unable to get index notes of {path}: {e}");
+                self.reply_error(reply, Self::errno_for(&e, libc::EIO));
                 return;
             }
         };
@@ -2049,45 +5291,246 @@ impl Filesystem for ExampleFuseFs {
         // NOTE CASCADE on a Foreign Key would be nice here
         let has_children = match self
             .db
-            .get_child_count(Some(&folder_id), self.user_id.as_str())
+            .has_children(Some(&folder_id), self.user_id.as_str())
         {
-            Ok((fc, nc)) => nc + fc > 0,
+            Ok(has_children) => has_children,
             Err(e) => {
-                eprintln!("82 [ERROR] (fn rmdir) Unable to get child counts from database");
+                error!("82 (fn rmdir) Unable to get child counts from database");
                 eprintln!("{e}");
-                reply.error(ENOENT);
+                self.reply_error(reply, ENOENT);
                 return;
             }
         };
 
-        if has_children {
-            reply.error(libc::EIO);
+        if has_children && !self.options.recursive_rmdir {
+            self.reply_error(reply, libc::EIO);
             return;
         }
 
-        // Directory is empty, proceed with deletion
-        match self.db.delete_folder(&folder_id, self.user_id.as_str()) {
+        for index_id in &index_ids {
+            if let Err(e) = self.db.delete_note(index_id) {
+                error!("rmdir: Unable to delete index note {index_id} of {path}: {e}");
+                self.reply_error(reply, libc::EIO);
+                return;
+            }
+        }
+
+        // Resolve the stored spelling before the row (and its path) disappears
+        let path = self.stored_folder_path(&folder_id, &path);
+
+        let deleted = if has_children {
+            self.db
+                .delete_folder_recursive(&folder_id, self.user_id.as_str())
+                .map(|(folders, notes)| {
+                    debug!(
+                        "rmdir: Deleted {path} with {notes} notes and {} folders inside",
+                        folders.saturating_sub(1)
+                    );
+                    folders > 0
+                })
+        } else {
+            self.db.delete_folder(&folder_id, self.user_id.as_str())
+        };
+        match deleted {
             Ok(success) => {
                 if success {
                     // Successfully deleted the directory
                     // Remove from inode mappings
-                    if let Some(inode) = self.inode_map.remove(&path) {
-                        self.reverse_inode_map.remove(&inode);
+                    self.remove_inode_mapping(&path);
+                    let prefix = format!("{path}/");
+                    let stale: Vec<String> = self
+                        .inode_map
+                        .keys()
+                        .filter(|p| p.starts_with(&prefix))
+                        .cloned()
+                        .collect();
+                    for child in stale {
+                        if let Some(inode) = self.inode_map.remove(&child) {
+                            self.reverse_inode_map.remove(&inode);
+                            self.release_inode(inode);
+                        }
                     }
                     reply.ok();
                 } else {
-                    eprintln!(
-                        "[ERROR] rmdir: Unable to delete directory {path} with id {folder_id}"
-                    );
-                    reply.error(ENOENT);
+                    error!("rmdir: Unable to delete directory {path} with id {folder_id}");
+                    self.reply_error(reply, ENOENT);
                 }
             }
             Err(e) => {
-                eprintln!(
-                    "[ERROR] rmdir: SQL error trying to delete directory {path} with id {folder_id}: {e}"
+                error!(
+                    "rmdir: SQL error trying to delete directory {path} with id {folder_id}: {e}"
                 );
-                reply.error(ENOENT);
+                self.reply_error(reply, Self::errno_for(&e, ENOENT));
             }
         }
     }
+
+    /// Check the caller's access to an entry against its stored (or default)
+    /// permission bits and owner
+    ///
+    /// Not called when mounted with `default_permissions`, where the kernel
+    /// makes the same check from getattr.
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: fuser::ReplyEmpty) {
+        let _op = self.begin_op("access");
+        debug!("access: ino={ino}, mask={mask:#o}, uid={}", req.uid());
+        match self.entry_permissions(ino) {
+            Ok(permissions) if permissions.allows(req.uid(), req.gid(), mask) => reply.ok(),
+            Ok(_) => self.reply_error(reply, libc::EACCES),
+            Err(errno) => self.reply_error(reply, errno),
+        }
+    }
+
+    /// Report the lock that would block the one described, or F_UNLCK
+    fn getlk(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        _pid: u32,
+        reply: fuser::ReplyLock,
+    ) {
+        let _op = self.begin_op("getlk");
+        match self.test_lock(ino, lock_owner, start, end, typ) {
+            Ok(Some((start, end, typ, pid))) => reply.locked(start, end, typ, pid),
+            Ok(None) => reply.locked(start, end, libc::F_UNLCK, 0),
+            Err(errno) => self.reply_error(reply, errno),
+        }
+    }
+
+    /// Take or release an advisory lock (fcntl, and flock when the kernel
+    /// forwards it)
+    ///
+    /// Requests run one at a time, so a blocking request (F_SETLKW) can't wait
+    /// for the holder and fails with EAGAIN like a non-blocking one.
+    fn setlk(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        _sleep: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let _op = self.begin_op("setlk");
+        debug!("setlk: ino={ino}, owner={lock_owner:#x}, {start}..={end}, typ={typ}");
+        match self.set_lock(ino, lock_owner, start, end, typ, pid) {
+            Ok(()) => reply.ok(),
+            Err(errno) => self.reply_error(reply, errno),
+        }
+    }
+
+    /// Read note metadata as an extended attribute
+    ///
+    /// Virtual files are readable once they changed since the handle last
+    /// read them, so a reader can wait for that; everything else always is
+    fn poll(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        ph: fuser::PollHandle,
+        events: u32,
+        flags: u32,
+        reply: fuser::ReplyPoll,
+    ) {
+        let _op = self.begin_op("poll");
+        debug!("poll: ino={ino}, fh={fh}, events={events:#x}");
+        let Some(path) = self.get_path_from_inode(ino) else {
+            self.reply_error(reply, ENOENT);
+            return;
+        };
+        let revents = match self.virtual_file(&path) {
+            Some(file) => self.poll_virtual_file(fh, file, ph, flags),
+            None => poll::ALWAYS_READY,
+        };
+        reply.poll(revents);
+    }
+
+    /// Commands for scripts, see the `ioctl` module
+    fn ioctl(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        _out_size: u32,
+        reply: fuser::ReplyIoctl,
+    ) {
+        let _op = self.begin_op("ioctl");
+        debug!("ioctl: ino={ino}, cmd={cmd:#x}");
+        match self.run_ioctl(ino, cmd, in_data) {
+            Ok(out) => reply.ioctl(0, &out),
+            Err(errno) => self.reply_error(reply, errno),
+        }
+    }
+
+    /// `user.abstract`, `user.syntax` and `user.tags` (comma separated) are
+    /// read from the database; folders have no attributes.
+    fn getxattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        let _op = self.begin_op("getxattr");
+        debug!("getxattr: ino={ino}, name={name:?}");
+        match self.get_note_xattr(ino, &name.to_string_lossy()) {
+            Ok(value) => self.reply_xattr(&value, size, reply),
+            Err(errno) => self.reply_error(reply, errno),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: fuser::ReplyXattr) {
+        let _op = self.begin_op("listxattr");
+        debug!("listxattr: ino={ino}");
+        match self.list_note_xattrs(ino) {
+            Ok(names) => self.reply_xattr(&names, size, reply),
+            Err(errno) => self.reply_error(reply, errno),
+        }
+    }
+
+    /// Write note metadata through an extended attribute
+    ///
+    /// Key behaviors:
+    /// - `user.abstract` and `user.tags` update their columns (and the FTS index)
+    /// - `user.syntax` renames the note to the matching extension
+    /// - Any other name fails with ENOTSUP; nothing else is stored
+    fn setxattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        _position: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let _op = self.begin_op("setxattr");
+        debug!("setxattr: ino={ino}, name={name:?}, flags={flags:#x}");
+        match self.set_note_xattr(ino, &name.to_string_lossy(), value, flags) {
+            Ok(()) => reply.ok(),
+            Err(errno) => self.reply_error(reply, errno),
+        }
+    }
+
+    fn removexattr(&mut self, _req: &Request, ino: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+        let _op = self.begin_op("removexattr");
+        debug!("removexattr: ino={ino}, name={name:?}");
+        match self.remove_note_xattr(ino, &name.to_string_lossy()) {
+            Ok(()) => reply.ok(),
+            Err(errno) => self.reply_error(reply, errno),
+        }
+    }
 }
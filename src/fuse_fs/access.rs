@@ -0,0 +1,212 @@
+use fuser::FileType;
+
+use super::ExampleFuseFs;
+use super::assets::AssetPath;
+use super::export::ExportPath;
+use super::virtual_dirs::VirtualPath;
+
+/// Owner and permission bits of an entry, as reported by getattr
+pub(super) struct Permissions {
+    pub kind: FileType,
+    pub perm: u16,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Permissions {
+    /// Whether a caller with `uid`/`gid` may access the entry as `mask`
+    /// (R_OK, W_OK and X_OK bits; F_OK is always allowed)
+    ///
+    /// Only the caller's primary group is known, so supplementary groups
+    /// fall under "other".
+    pub fn allows(&self, uid: u32, gid: u32, mask: i32) -> bool {
+        let mask = (mask & (libc::R_OK | libc::W_OK | libc::X_OK)) as u16;
+        if uid == 0 {
+            // root may do anything but execute a file nobody can execute
+            return mask & libc::X_OK as u16 == 0
+                || self.kind == FileType::Directory
+                || self.perm & 0o111 != 0;
+        }
+        let granted = if uid == self.uid {
+            self.perm >> 6
+        } else if gid == self.gid {
+            self.perm >> 3
+        } else {
+            self.perm
+        } & 0o7;
+        granted & mask == mask
+    }
+}
+
+impl ExampleFuseFs {
+    /// Permissions of the entry at `ino`, or the errno getattr would give
+    pub(super) fn entry_permissions(&self, ino: u64) -> Result<Permissions, i32> {
+        let dir = |perm| Permissions {
+            kind: FileType::Directory,
+            perm,
+            uid: self.options.uid,
+            gid: self.options.gid,
+        };
+        if ino == 1 {
+            return Ok(dir(self.options.dir_mode));
+        }
+        let path = &self.get_path_from_inode(ino).ok_or(libc::ENOENT)?;
+        match self.resolve_virtual(path) {
+            Some(VirtualPath::Dir) => return Ok(dir(self.virtual_dir_attr(ino).perm)),
+            Some(VirtualPath::Symlink(target)) => {
+                let attr = self.symlink_attr(ino, &target);
+                return Ok(Permissions {
+                    kind: attr.kind,
+                    perm: attr.perm,
+                    uid: attr.uid,
+                    gid: attr.gid,
+                });
+            }
+            _ => {}
+        }
+        if let Some(file) = self.virtual_file(path) {
+            let attr = self.virtual_file_attr(ino, file);
+            return Ok(Permissions {
+                kind: attr.kind,
+                perm: attr.perm,
+                uid: attr.uid,
+                gid: attr.gid,
+            });
+        }
+        match self.asset_path(path) {
+            Some(AssetPath::Dir(note)) => {
+                return Ok(Permissions {
+                    kind: FileType::Directory,
+                    perm: self.options.dir_mode,
+                    uid: note.uid.unwrap_or(self.options.uid),
+                    gid: note.gid.unwrap_or(self.options.gid),
+                });
+            }
+            Some(AssetPath::File {
+                note,
+                attachment: Some(_),
+                ..
+            }) => {
+                return Ok(Permissions {
+                    kind: FileType::RegularFile,
+                    perm: self.options.file_mode,
+                    uid: note.uid.unwrap_or(self.options.uid),
+                    gid: note.gid.unwrap_or(self.options.gid),
+                });
+            }
+            Some(AssetPath::File { .. }) => return Err(libc::ENOENT),
+            None => {}
+        }
+        if let Some(shared) = self.shared_path(path) {
+            let attr = self.shared_attr(ino, &shared)?;
+            return Ok(Permissions {
+                kind: attr.kind,
+                perm: attr.perm,
+                uid: attr.uid,
+                gid: attr.gid,
+            });
+        }
+        if let Some(ExportPath::File(note, format)) = self.export_path(path) {
+            let attr = self.export_attr(ino, &note, format);
+            return Ok(Permissions {
+                kind: attr.kind,
+                perm: attr.perm,
+                uid: attr.uid,
+                gid: attr.gid,
+            });
+        }
+        if let Some(note) = self.html_note(path) {
+            let attr = self.html_attr(ino, &note);
+            return Ok(Permissions {
+                kind: attr.kind,
+                perm: attr.perm,
+                uid: attr.uid,
+                gid: attr.gid,
+            });
+        }
+        if let Some(note) = self.sidecar_note(path) {
+            return Ok(Permissions {
+                kind: FileType::RegularFile,
+                perm: self.note_perm(&note.id, &note.user_id, note.mode, note.locked),
+                uid: note.uid.unwrap_or(self.options.uid),
+                gid: note.gid.unwrap_or(self.options.gid),
+            });
+        }
+
+        let db_path = self.db_path(path);
+        let to_errno = |e: rusqlite::Error| {
+            error!("access: Database error checking {path}: {e}");
+            libc::EIO
+        };
+        if let Some(folder_id) = self
+            .db
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
+            .map_err(to_errno)?
+        {
+            let folder = self
+                .db
+                .get_folder_by_id(&folder_id, self.user_id.as_str())
+                .map_err(to_errno)?
+                .ok_or(libc::ENOENT)?;
+            return Ok(Permissions {
+                kind: FileType::Directory,
+                perm: self.folder_perm(&folder),
+                uid: folder.uid.unwrap_or(self.options.uid),
+                gid: folder.gid.unwrap_or(self.options.gid),
+            });
+        }
+        let note_id = self
+            .note_id_by_path(&db_path)
+            .map_err(to_errno)?
+            .ok_or(libc::ENOENT)?;
+        let note = self
+            .db
+            .get_note_meta_by_id(&note_id)
+            .map_err(to_errno)?
+            .ok_or(libc::ENOENT)?;
+        Ok(Permissions {
+            kind: FileType::RegularFile,
+            perm: self.note_perm(&note.id, &note.user_id, note.mode, note.locked),
+            uid: note.uid.unwrap_or(self.options.uid),
+            gid: note.gid.unwrap_or(self.options.gid),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(perm: u16) -> Permissions {
+        Permissions {
+            kind: FileType::RegularFile,
+            perm,
+            uid: 1000,
+            gid: 100,
+        }
+    }
+
+    #[test]
+    fn test_permission_classes() {
+        let rw_r = file(0o640);
+        assert!(rw_r.allows(1000, 1, libc::R_OK | libc::W_OK));
+        assert!(rw_r.allows(2000, 100, libc::R_OK));
+        assert!(!rw_r.allows(2000, 100, libc::W_OK));
+        assert!(!rw_r.allows(2000, 1, libc::R_OK));
+        assert!(rw_r.allows(2000, 1, libc::F_OK));
+        // The owner class applies even when it grants less than "other"
+        assert!(!file(0o044).allows(1000, 1, libc::R_OK));
+    }
+
+    #[test]
+    fn test_root_access() {
+        assert!(file(0o000).allows(0, 0, libc::R_OK | libc::W_OK));
+        assert!(!file(0o644).allows(0, 0, libc::X_OK));
+        assert!(file(0o744).allows(0, 0, libc::X_OK));
+        let dir = Permissions {
+            kind: FileType::Directory,
+            ..file(0o000)
+        };
+        assert!(dir.allows(0, 0, libc::X_OK));
+    }
+}
@@ -0,0 +1,321 @@
+use fuser::{FileAttr, FileType};
+
+use super::ExampleFuseFs;
+use crate::database::{Attachment, Database, NoteMeta};
+
+/// Suffix of the virtual directory holding a note's attachments ("<title>_assets")
+pub(super) const ASSETS_SUFFIX: &str = "_assets";
+
+/// MIME type stored for an attachment, guessed from its file name
+fn mime_for(filename: &str) -> &'static str {
+    let ext = filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "zip" => "application/zip",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// What a path under `--assets` refers to
+pub(super) enum AssetPath {
+    /// The "<title>_assets" directory of a note
+    Dir(NoteMeta),
+    /// A name inside it; `attachment` is `None` until a file of that name is created
+    File {
+        note: NoteMeta,
+        name: String,
+        attachment: Option<Attachment>,
+    },
+}
+
+impl ExampleFuseFs {
+    /// Classify `path` if it is an assets directory or a file inside one
+    pub(super) fn asset_path(&self, path: &str) -> Option<AssetPath> {
+        if !self.options.assets {
+            return None;
+        }
+        if let Some(note) = self.companion_note(path, ASSETS_SUFFIX) {
+            return Some(AssetPath::Dir(note));
+        }
+        let (dir, name) = path.rsplit_once('/')?;
+        let note = self.companion_note(dir, ASSETS_SUFFIX)?;
+        let attachment = self.db.get_attachment_by_name(&note.id, name).ok()?;
+        Some(AssetPath::File {
+            note,
+            name: name.to_string(),
+            attachment,
+        })
+    }
+
+    /// Entry for the assets directory of `note`, listed next to it in `dir_path`
+    pub(super) fn asset_dir_entry(
+        &mut self,
+        dir_path: &str,
+        note: &NoteMeta,
+    ) -> Option<(u64, FileType, String)> {
+        if !self.options.assets {
+            return None;
+        }
+        let name = format!("{}{ASSETS_SUFFIX}", self.title_to_name(&note.title));
+        let path = if dir_path == "/" {
+            format!("/{name}")
+        } else {
+            format!("{dir_path}/{name}")
+        };
+        Some((self.get_or_create_inode(&path), FileType::Directory, name))
+    }
+
+    /// The attachments of `note`, as readdir entries of its assets directory at `path`
+    pub(super) fn asset_dir_entries(
+        &mut self,
+        path: &str,
+        note: &NoteMeta,
+    ) -> Result<Vec<(u64, FileType, String)>, i32> {
+        let attachments = self.db.list_attachments(&note.id).map_err(|e| {
+            error!("readdir: Unable to list attachments of {}: {e}", note.id);
+            Self::errno_for(&e, libc::EIO)
+        })?;
+        Ok(attachments
+            .into_iter()
+            .map(|attachment| {
+                let ino = self.get_or_create_inode(&format!("{path}/{}", attachment.filename));
+                (ino, FileType::RegularFile, attachment.filename)
+            })
+            .collect())
+    }
+
+    /// Attributes of the assets directory of `note`, which shares its owner and times
+    pub(super) fn asset_dir_attr(&self, ino: u64, note: &NoteMeta) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: Self::datetime_to_systemtime(&note.accessed_at.unwrap_or(note.updated_at)),
+            mtime: Self::datetime_to_systemtime(&note.updated_at),
+            ctime: Self::datetime_to_systemtime(&note.updated_at),
+            crtime: Self::datetime_to_systemtime(&note.created_at),
+            kind: FileType::Directory,
+            perm: self.options.dir_mode,
+            nlink: 2,
+            uid: note.uid.unwrap_or(self.options.uid),
+            gid: note.gid.unwrap_or(self.options.gid),
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+
+    /// Attributes of an attachment, sized by its pending writes if any
+    pub(super) fn attachment_attr(
+        &self,
+        ino: u64,
+        note: &NoteMeta,
+        attachment: &Attachment,
+    ) -> FileAttr {
+        let size = self
+            .asset_buffers
+            .get(&ino)
+            .map_or(attachment.size, |bytes| bytes.len() as u64);
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: Self::datetime_to_systemtime(&attachment.updated_at),
+            mtime: Self::datetime_to_systemtime(&attachment.updated_at),
+            ctime: Self::datetime_to_systemtime(&attachment.updated_at),
+            crtime: Self::datetime_to_systemtime(&attachment.created_at),
+            kind: FileType::RegularFile,
+            perm: self.options.file_mode,
+            nlink: 1,
+            uid: note.uid.unwrap_or(self.options.uid),
+            gid: note.gid.unwrap_or(self.options.gid),
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+
+    /// Contents of the attachment at `ino`: its pending writes, or the stored bytes
+    fn attachment_bytes(&self, ino: u64, attachment: &Attachment) -> Result<Vec<u8>, i32> {
+        if let Some(bytes) = self.asset_buffers.get(&ino) {
+            return Ok(bytes.clone());
+        }
+        match self.db.get_attachment_data(&attachment.id) {
+            Ok(Some(bytes)) => Ok(bytes),
+            Ok(None) => Err(libc::ENOENT),
+            Err(e) => {
+                error!("assets: Unable to read attachment {}: {e}", attachment.id);
+                Err(Self::errno_for(&e, libc::EIO))
+            }
+        }
+    }
+
+    /// At most `size` bytes of the attachment at `ino` from `offset`
+    pub(super) fn read_attachment(
+        &self,
+        ino: u64,
+        attachment: &Attachment,
+        offset: i64,
+        size: u32,
+    ) -> Result<Vec<u8>, i32> {
+        if let Some(bytes) = self.asset_buffers.get(&ino) {
+            return Ok(Self::read_range(bytes, offset, size).to_vec());
+        }
+        match self
+            .db
+            .get_attachment_range(&attachment.id, offset.max(0) as u64, size.into())
+        {
+            Ok(Some(bytes)) => Ok(bytes),
+            Ok(None) => Err(libc::ENOENT),
+            Err(e) => {
+                error!("read: Unable to read attachment {}: {e}", attachment.id);
+                Err(Self::errno_for(&e, libc::EIO))
+            }
+        }
+    }
+
+    /// Create an empty attachment `name` of `note`, returning its attributes
+    pub(super) fn create_attachment_file(
+        &mut self,
+        ino: u64,
+        note: &NoteMeta,
+        name: &str,
+    ) -> Result<FileAttr, i32> {
+        let to_errno = |e: rusqlite::Error| {
+            if Database::is_unique_violation(&e) {
+                libc::EEXIST
+            } else {
+                error!("create: Unable to attach {name} to {}: {e}", note.id);
+                libc::EIO
+            }
+        };
+        self.db
+            .create_attachment(&note.id, name, mime_for(name), &[])
+            .map_err(to_errno)?;
+        let attachment = self
+            .db
+            .get_attachment_by_name(&note.id, name)
+            .map_err(to_errno)?
+            .ok_or(libc::EIO)?;
+        Ok(self.attachment_attr(ino, note, &attachment))
+    }
+
+    /// Apply a write to the attachment at `ino`, collecting it until flush/release
+    ///
+    /// Returns `None` if `path` isn't an attachment.
+    pub(super) fn write_attachment(
+        &mut self,
+        ino: u64,
+        path: &str,
+        offset: i64,
+        data: &[u8],
+    ) -> Option<Result<(), i32>> {
+        let current = match self.asset_buffers.remove(&ino) {
+            Some(bytes) => bytes,
+            None => {
+                let attachment = match self.asset_path(path)? {
+                    AssetPath::Dir(_) => return Some(Err(libc::EISDIR)),
+                    AssetPath::File { attachment, .. } => attachment,
+                };
+                let Some(attachment) = attachment else {
+                    return Some(Err(libc::ENOENT));
+                };
+                match self.attachment_bytes(ino, &attachment) {
+                    Ok(bytes) => bytes,
+                    Err(errno) => return Some(Err(errno)),
+                }
+            }
+        };
+        self.asset_buffers
+            .insert(ino, Self::apply_write(current, offset, data));
+        Some(Ok(()))
+    }
+
+    /// Resize the attachment at `ino` to `size` bytes and store it
+    pub(super) fn truncate_attachment(
+        &mut self,
+        ino: u64,
+        attachment: &Attachment,
+        size: u64,
+    ) -> Result<(), i32> {
+        let mut bytes = self.attachment_bytes(ino, attachment)?;
+        bytes.resize(size as usize, 0);
+        self.asset_buffers.insert(ino, bytes);
+        self.store_attachment(ino)
+    }
+
+    /// Store the writes collected on the attachment at `ino`, if any
+    pub(super) fn store_attachment(&mut self, ino: u64) -> Result<(), i32> {
+        let Some(bytes) = self.asset_buffers.remove(&ino) else {
+            return Ok(());
+        };
+        let path = self.get_path_from_inode(ino).ok_or(libc::ENOENT)?;
+        let Some(AssetPath::File {
+            attachment: Some(attachment),
+            ..
+        }) = self.asset_path(&path)
+        else {
+            error!("assets: {path} was removed before its writes were stored");
+            return Err(libc::ENOENT);
+        };
+        debug!(
+            "assets: Storing {} bytes as attachment {}",
+            bytes.len(),
+            attachment.id
+        );
+        match self.db.update_attachment_data(&attachment.id, &bytes) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("assets: Unable to store attachment {}: {e}", attachment.id);
+                Err(Self::errno_for(&e, libc::EIO))
+            }
+        }
+    }
+
+    /// Rename an attachment within its note's assets directory, replacing
+    /// any attachment already called `new_name`
+    pub(super) fn rename_attachment_file(
+        &mut self,
+        attachment: &Attachment,
+        replaced: Option<&Attachment>,
+        new_name: &str,
+    ) -> Result<(), i32> {
+        let to_errno = |e: rusqlite::Error| {
+            error!("rename: Unable to rename attachment {}: {e}", attachment.id);
+            Self::errno_for(&e, libc::EIO)
+        };
+        if let Some(replaced) = replaced.filter(|r| r.id != attachment.id) {
+            self.db.delete_attachment(&replaced.id).map_err(to_errno)?;
+        }
+        self.db
+            .rename_attachment(&attachment.id, new_name, mime_for(new_name))
+            .map_err(to_errno)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mime_for() {
+        assert_eq!(mime_for("photo.JPG"), "image/jpeg");
+        assert_eq!(mime_for("diagram.svg"), "image/svg+xml");
+        assert_eq!(mime_for("archive.tar.gz"), "application/octet-stream");
+        assert_eq!(mime_for("README"), "application/octet-stream");
+    }
+}
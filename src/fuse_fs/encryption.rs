@@ -0,0 +1,190 @@
+//! Notes encrypted with age (`--identity`): their content is stored as
+//! ASCII-armored ciphertext, decrypted when read through the mount and
+//! encrypted again when written, so a copy of the database doesn't give
+//! them away
+//!
+//! `setfattr -n user.encrypted -v 1 note.md` encrypts a note (to the
+//! recipients of the identity), and `-v 0` or removing the attribute
+//! decrypts it again. Titles, tags and the other metadata stay readable.
+//! Without an identity, encrypted notes read as their ciphertext and can't
+//! be written.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use super::ExampleFuseFs;
+use crate::database::Note;
+
+/// Plain text of encrypted notes at a revision, by note id, so reading a
+/// note in pieces or listing its size doesn't run age each time
+#[derive(Default)]
+pub(super) struct Decrypted {
+    plain: RefCell<HashMap<String, (i64, String)>>,
+}
+
+impl Decrypted {
+    pub(super) fn clear(&self) {
+        self.plain.borrow_mut().clear();
+    }
+}
+
+/// Run `age` with `args`, feeding it `input`; its output, or what it
+/// printed on failure
+fn run_age(age: &Path, args: &[&str], identity: &Path, input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut child = Command::new(age)
+        .args(args)
+        .arg("--identity")
+        .arg(identity)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("unable to run {}: {e}", age.display()))?;
+    // age writes while it reads, so a large note would fill both pipes
+    let mut stdin = child.stdin.take().ok_or("no stdin")?;
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let mut output = Vec::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_end(&mut output).map_err(|e| e.to_string())?;
+    }
+    let finished = child.wait_with_output().map_err(|e| e.to_string())?;
+    // An age that exits early is reported by its status
+    let _ = writer.join();
+    if !finished.status.success() {
+        let stderr = String::from_utf8_lossy(&finished.stderr);
+        return Err(format!("age {}: {}", finished.status, stderr.trim()));
+    }
+    Ok(output)
+}
+
+impl ExampleFuseFs {
+    /// Encrypt `text` to the recipients of the identity
+    pub(super) fn encrypt(&self, text: &str) -> Result<String, String> {
+        let identity = self.options.identity.as_deref().ok_or("no identity")?;
+        let ciphertext = run_age(
+            &self.options.age,
+            &["--encrypt", "--armor"],
+            identity,
+            text.as_bytes(),
+        )?;
+        String::from_utf8(ciphertext).map_err(|_| "age output isn't armored".to_string())
+    }
+
+    /// The content of `note` in plain text
+    ///
+    /// Notes that aren't encrypted are returned as they are, and so are
+    /// encrypted ones without an identity to decrypt them with.
+    pub(super) fn plaintext<'a>(&self, note: &'a Note) -> Result<Cow<'a, str>, String> {
+        let Some(identity) = self.options.identity.as_deref() else {
+            return Ok(Cow::Borrowed(&note.content));
+        };
+        if !note.encrypted {
+            return Ok(Cow::Borrowed(&note.content));
+        }
+        if let Some((revision, plain)) = self.decrypted.plain.borrow().get(&note.id)
+            && *revision == note.revision
+        {
+            return Ok(Cow::Owned(plain.clone()));
+        }
+        let plain = run_age(
+            &self.options.age,
+            &["--decrypt"],
+            identity,
+            note.content.as_bytes(),
+        )?;
+        let plain = String::from_utf8(plain).map_err(|_| "plain text isn't UTF-8".to_string())?;
+        self.decrypted
+            .plain
+            .borrow_mut()
+            .insert(note.id.clone(), (note.revision, plain.clone()));
+        Ok(Cow::Owned(plain))
+    }
+
+    /// `plaintext`, or the ciphertext if it doesn't decrypt (after logging why)
+    pub(super) fn note_content<'a>(&self, note: &'a Note) -> Cow<'a, str> {
+        self.plaintext(note).unwrap_or_else(|e| {
+            error!("decrypt: Unable to decrypt note {}: {e}", note.id);
+            Cow::Borrowed(&note.content)
+        })
+    }
+
+    /// Whether a note stored `encrypted` is decrypted, so its size isn't the
+    /// stored one
+    pub(super) fn decrypts(&self, encrypted: bool) -> bool {
+        encrypted && self.options.identity.is_some()
+    }
+
+    /// Whether `note` can't be opened for writing: it is encrypted and the
+    /// mount has no identity to encrypt what is written
+    pub(super) fn encryption_read_only(&self, note: &Note) -> bool {
+        note.encrypted && self.options.identity.is_none()
+    }
+
+    /// `body`, written to `note`, in the form the note is stored in; failing
+    /// to encrypt it fails the write like a value the database can't store
+    pub(super) fn stored_form<'a>(
+        &self,
+        note: &Note,
+        body: &'a str,
+    ) -> rusqlite::Result<Cow<'a, str>> {
+        if !note.encrypted {
+            return Ok(Cow::Borrowed(body));
+        }
+        self.encrypt(body)
+            .map(Cow::Owned)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(format!("encrypt: {e}").into()))
+    }
+
+    /// Encrypt (or decrypt) the note at `path` in place
+    pub(super) fn set_note_encrypted(
+        &mut self,
+        ino: u64,
+        path: &str,
+        note_id: &str,
+        encrypted: bool,
+    ) -> Result<(), i32> {
+        if self.options.identity.is_none() {
+            return Err(libc::ENOKEY);
+        }
+        // Writes not stored yet are stored in the note's current form first
+        self.commit_pending(ino)?;
+        let note = match self.db.get_note_by_id(note_id) {
+            Ok(Some(note)) => note,
+            Ok(None) => return Err(libc::ENOENT),
+            Err(e) => {
+                error!("encryption: Database error retrieving note {note_id}: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        if note.encrypted == encrypted {
+            return Ok(());
+        }
+        let content = if encrypted {
+            self.encrypt(&note.content)
+        } else {
+            self.plaintext(&note).map(Cow::into_owned)
+        }
+        .map_err(|e| {
+            error!("encryption: Unable to change the encryption of {path}: {e}");
+            libc::EIO
+        })?;
+        match self
+            .db
+            .set_note_encrypted(&note.id, note.revision, &content, encrypted)
+        {
+            Ok(true) => {}
+            Ok(false) => return Err(libc::EAGAIN),
+            Err(e) => {
+                error!("encryption: Failed to store {path}: {e}");
+                return Err(libc::EIO);
+            }
+        }
+        self.refresh_handles(&note.id);
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! `/.export/<format>/<path>` (`--pandoc-export`): the folders of the mount
+//! again, with each note pandoc can read converted to the format, e.g.
+//! `cp /.export/pdf/Work/spec.pdf ~/`
+//!
+//! A note is converted when first read and kept until it changes; the mount
+//! waits for pandoc meanwhile. Until then the file is listed with size 0,
+//! so listings don't run pandoc on every note of the folder.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use fuser::{FileAttr, FileType};
+
+use super::ExampleFuseFs;
+use crate::database::{Note, Page};
+
+pub(super) const EXPORT_DIR: &str = "/.export";
+
+/// Subdirectories of `/.export`: the extension of the files in each, from
+/// which pandoc also picks the format it writes
+const FORMATS: [&str; 3] = ["pdf", "docx", "html"];
+
+/// pandoc's reader for notes with each file name extension; notes of other
+/// syntaxes aren't listed
+const READERS: [(&str, &str); 9] = [
+    ("md", "markdown"),
+    ("markdown", "markdown"),
+    ("org", "org"),
+    ("rst", "rst"),
+    ("tex", "latex"),
+    ("html", "html"),
+    ("textile", "textile"),
+    ("wiki", "mediawiki"),
+    ("typ", "typst"),
+];
+
+/// pandoc's reader for files with `extension`
+fn reader_for(extension: &str) -> Option<&'static str> {
+    READERS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map(|&(_, reader)| reader)
+}
+
+/// A note's revision converted, or the errno reading it fails with
+type Converted = (i64, Result<Vec<u8>, i32>);
+
+/// Conversions of notes at a revision, by (note id, format); failures are
+/// kept too, so pandoc isn't run again for every read of the file
+#[derive(Default)]
+pub(super) struct Exports {
+    converted: HashMap<(String, &'static str), Converted>,
+}
+
+impl Exports {
+    pub(super) fn clear(&mut self) {
+        self.converted.clear();
+    }
+}
+
+/// What a path below `/.export` refers to
+pub(super) enum ExportPath {
+    /// `/.export`, a format directory, or a folder below one
+    Dir,
+    /// A note, converted to the format
+    File(Box<Note>, &'static str),
+}
+
+impl ExampleFuseFs {
+    /// pandoc's reader for `note`, if there is one
+    fn pandoc_reader(&self, note: &Note) -> Option<&'static str> {
+        if note.extensionless {
+            return None;
+        }
+        reader_for(self.syntax_map.extension_for_syntax(&note.syntax))
+    }
+
+    /// The folder at the mount path `dir`, if there is one (`Some(None)` at
+    /// the top of the user's notes)
+    fn export_folder(&self, dir: &str) -> Option<Option<String>> {
+        if dir == "/" {
+            return Some(self.root_folder_id());
+        }
+        match self
+            .db
+            .get_folder_id_by_path(&self.db_path(dir), self.user_id.as_str())
+        {
+            Ok(folder) => folder.map(Some),
+            Err(e) => {
+                error!("export: Unable to look up folder {dir}: {e}");
+                None
+            }
+        }
+    }
+
+    /// The notes of the folder at `dir` that convert to `format`, by the
+    /// name they are listed under
+    fn export_notes(&self, dir: &str, format: &str) -> Vec<(String, Note)> {
+        let Some(folder) = self.export_folder(dir) else {
+            return Vec::new();
+        };
+        let notes =
+            match self
+                .db
+                .list_notes_by_parent(folder.as_deref(), self.user_id.as_str(), Page::ALL)
+            {
+                Ok(notes) => notes,
+                Err(e) => {
+                    error!("export: Unable to list notes of {dir}: {e}");
+                    return Vec::new();
+                }
+            };
+        let mut listed: Vec<(String, Note)> = Vec::new();
+        for note in notes {
+            if self.pandoc_reader(&note).is_none() {
+                continue;
+            }
+            // "spec.md" and "spec.org" both convert to "spec.pdf": the first is listed
+            let name = format!("{}.{format}", self.title_to_name(&note.title));
+            if !listed.iter().any(|(taken, _)| *taken == name) {
+                listed.push((name, note));
+            }
+        }
+        listed
+    }
+
+    /// Classify `path` if it lies in `/.export`
+    pub(super) fn export_path(&self, path: &str) -> Option<ExportPath> {
+        if !self.options.pandoc_export {
+            return None;
+        }
+        if path == EXPORT_DIR {
+            return Some(ExportPath::Dir);
+        }
+        let rest = path.strip_prefix(EXPORT_DIR)?.strip_prefix('/')?;
+        let (format, rest) = match rest.split_once('/') {
+            Some((format, rest)) => (format, Some(rest)),
+            None => (rest, None),
+        };
+        let format = *FORMATS.iter().find(|known| **known == format)?;
+        let Some(rest) = rest else {
+            return Some(ExportPath::Dir);
+        };
+        let mount_path = format!("/{rest}");
+        if self.is_dir(&mount_path) {
+            return Some(ExportPath::Dir);
+        }
+        let (dir, name) = mount_path.rsplit_once('/')?;
+        let dir = if dir.is_empty() { "/" } else { dir };
+        let (_, note) = self
+            .export_notes(dir, format)
+            .into_iter()
+            .find(|(listed, _)| listed == name)?;
+        Some(ExportPath::File(Box::new(note), format))
+    }
+
+    /// Subdirectories and file names of the directory `path` in `/.export`
+    pub(super) fn export_listing(&self, path: &str) -> Option<(Vec<String>, Vec<String>)> {
+        if path == EXPORT_DIR {
+            return Some((FORMATS.map(str::to_string).to_vec(), Vec::new()));
+        }
+        let rest = path.strip_prefix(EXPORT_DIR)?.strip_prefix('/')?;
+        let (format, dir) = match rest.split_once('/') {
+            Some((format, dir)) => (format, format!("/{dir}")),
+            None => (rest, "/".to_string()),
+        };
+        let folder = self.export_folder(&dir)?;
+        let folders = match self.db.list_folders_by_parent(
+            folder.as_deref(),
+            self.user_id.as_str(),
+            Page::ALL,
+        ) {
+            Ok(folders) => folders,
+            Err(e) => {
+                error!("export: Unable to list folders of {dir}: {e}");
+                Vec::new()
+            }
+        };
+        let subdirs = folders
+            .iter()
+            .map(|folder| self.title_to_name(&folder.title))
+            .collect();
+        let files = self
+            .export_notes(&dir, format)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        Some((subdirs, files))
+    }
+
+    /// Attributes of `note` converted to `format`: read-only, with the
+    /// note's owner and times, and its size once converted
+    pub(super) fn export_attr(&self, ino: u64, note: &Note, format: &'static str) -> FileAttr {
+        let size = match self.exports.converted.get(&(note.id.clone(), format)) {
+            Some((revision, Ok(bytes))) if *revision == note.revision => bytes.len() as u64,
+            _ => 0,
+        };
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: Self::datetime_to_systemtime(&note.accessed_at.unwrap_or(note.updated_at)),
+            mtime: Self::datetime_to_systemtime(&note.updated_at),
+            ctime: Self::datetime_to_systemtime(&note.updated_at),
+            crtime: Self::datetime_to_systemtime(&note.created_at),
+            kind: FileType::RegularFile,
+            perm: self.note_perm(&note.id, &note.user_id, note.mode, note.locked) & !0o222,
+            nlink: 1,
+            uid: note.uid.unwrap_or(self.options.uid),
+            gid: note.gid.unwrap_or(self.options.gid),
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+
+    /// `note` converted to `format`, converting it unless it was already
+    /// at its current revision
+    pub(super) fn export_bytes(&mut self, note: &Note, format: &'static str) -> Result<&[u8], i32> {
+        let key = (note.id.clone(), format);
+        let current = self
+            .exports
+            .converted
+            .get(&key)
+            .is_some_and(|(revision, _)| *revision == note.revision);
+        if !current {
+            let converted = self.convert(note, format).map_err(|e| {
+                error!("export: Unable to convert {} to {format}: {e}", note.title);
+                libc::EIO
+            });
+            self.exports
+                .converted
+                .insert(key.clone(), (note.revision, converted));
+        }
+        match &self.exports.converted[&key].1 {
+            Ok(bytes) => Ok(bytes),
+            Err(errno) => Err(*errno),
+        }
+    }
+
+    /// Run pandoc on `note`, writing `format`
+    fn convert(&self, note: &Note, format: &str) -> Result<Vec<u8>, String> {
+        let reader = self.pandoc_reader(note).ok_or("pandoc can't read it")?;
+        let content = self.plaintext(note)?;
+        // pandoc picks the writer (and for PDFs, a PDF engine) by the output
+        // file's extension, and won't write every format to a pipe
+        let output = std::env::temp_dir().join(format!(
+            "sqlite_fuse-export-{}.{format}",
+            uuid::Uuid::new_v4().as_simple()
+        ));
+        let pandoc = &self.options.pandoc;
+        let mut child = Command::new(pandoc)
+            .args(["--from", reader, "--standalone", "--metadata"])
+            .arg(format!("pagetitle={}", note.title))
+            .arg("--output")
+            .arg(&output)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("unable to run {}: {e}", pandoc.display()))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A pandoc that exits early is reported by its status
+            let _ = stdin.write_all(content.as_bytes());
+        }
+        let finished = child.wait_with_output().map_err(|e| e.to_string());
+        let bytes = std::fs::read(&output);
+        let _ = std::fs::remove_file(&output);
+        let finished = finished?;
+        if !finished.status.success() {
+            let stderr = String::from_utf8_lossy(&finished.stderr);
+            return Err(format!("pandoc {}: {}", finished.status, stderr.trim()));
+        }
+        bytes.map_err(|e| format!("unable to read pandoc's output: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_for() {
+        assert_eq!(reader_for("md"), Some("markdown"));
+        assert_eq!(reader_for("TEX"), Some("latex"));
+        assert_eq!(reader_for("wiki"), Some("mediawiki"));
+        assert_eq!(reader_for("json"), None);
+        assert_eq!(reader_for(""), None);
+    }
+}
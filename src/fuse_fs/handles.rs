@@ -0,0 +1,315 @@
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+use super::ExampleFuseFs;
+use super::stats::HandleState;
+use crate::database::{Note, NoteContent};
+
+/// How long writes through a handle are collected before they are stored,
+/// unless the handle is flushed, synced or closed first
+///
+/// flush, fsync and release store collected writes before replying and
+/// report a failure to store them as their error; the writes are dropped
+/// either way, so the closing process learns they were lost.
+const WRITE_WINDOW: Duration = Duration::from_secs(1);
+
+/// What a handle opened for writing last saw of its note
+///
+/// Writes go straight to the database, so another client (e.g. the web app)
+/// editing the note while it is open here would otherwise be overwritten.
+pub(super) struct OpenHandle {
+    /// Inode writes through this handle go to; moves to a conflict copy, or
+    /// to the note a rename replaced, after the file was opened
+    ino: u64,
+    note_id: String,
+    /// `revision` of the note when this handle last read or wrote it
+    revision: i64,
+    /// File contents as this handle last saw them, which a conflicting write applies to
+    view: Vec<u8>,
+    /// File contents with the writes not yet stored, and when the first of them arrived
+    pending: Option<(Vec<u8>, Instant)>,
+}
+
+impl ExampleFuseFs {
+    /// A new file handle for `ino`; handles that can write a note (`note`
+    /// given) remember its revision to detect concurrent edits
+    pub(super) fn open_handle(&mut self, ino: u64, note: Option<&Note>) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        if let Some(note) = note {
+            let view = self.note_bytes(ino, note);
+            self.handles.insert(
+                fh,
+                OpenHandle {
+                    ino,
+                    note_id: note.id.clone(),
+                    revision: note.revision,
+                    view,
+                    pending: None,
+                },
+            );
+        }
+        fh
+    }
+
+    /// FOPEN_* flags for opening `note`: `--direct-io` bypasses the page
+    /// cache, `--auto-cache` keeps it if the note is unchanged since the last open
+    pub(super) fn open_flags(&mut self, ino: u64, note: &Note) -> u32 {
+        if self.options.direct_io {
+            return fuser::consts::FOPEN_DIRECT_IO;
+        }
+        if !self.options.auto_cache {
+            return 0;
+        }
+        // updated_at also covers renames and metadata shown in front matter
+        let version = (note.revision, note.updated_at);
+        let kept = self.cached_versions.insert(ino, version) == Some(version);
+        self.stats.page_cache(kept);
+        if kept {
+            fuser::consts::FOPEN_KEEP_CACHE
+        } else {
+            0
+        }
+    }
+
+    /// Store what was written through `fh`, then forget the handle
+    pub(super) fn close_handle(&mut self, fh: u64) -> Result<(), i32> {
+        let stored = self.commit_handle(fh);
+        self.handles.remove(&fh);
+        stored
+    }
+
+    /// Contents of `ino` including writes that are not stored yet, if any
+    pub(super) fn pending_bytes(&self, ino: u64) -> Option<&Vec<u8>> {
+        self.handles
+            .values()
+            .filter(|handle| handle.ino == ino)
+            .find_map(|handle| handle.pending.as_ref().map(|(bytes, _)| bytes))
+    }
+
+    /// Add a write to the writes collected on `fh`, storing them all once the
+    /// window has passed. Returns `None` if `fh` has nothing collected, in
+    /// which case the write has to start a new batch with `begin_pending`.
+    pub(super) fn buffer_write(
+        &mut self,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+    ) -> Option<Result<(), i32>> {
+        let (bytes, since) = self.handles.get_mut(&fh)?.pending.take()?;
+        let bytes = Self::apply_write(bytes, offset, data);
+        self.handles.get_mut(&fh)?.pending = Some((bytes, since));
+        if since.elapsed() < WRITE_WINDOW {
+            return Some(Ok(()));
+        }
+        Some(self.commit_handle(fh))
+    }
+
+    /// Start collecting writes on `fh` with a write to `note`
+    ///
+    /// When someone else changed the note since the handle last saw it, the
+    /// write applies to what the handle saw, and storing it makes a conflict copy.
+    pub(super) fn begin_pending(&mut self, fh: u64, note: &Note, offset: i64, data: &[u8]) {
+        let Some(handle) = self.handles.get(&fh) else {
+            return;
+        };
+        let base = if handle.revision != note.revision {
+            handle.view.clone()
+        } else {
+            self.note_bytes(handle.ino, note)
+        };
+        let bytes = Self::apply_write(base, offset, data);
+        if let Some(handle) = self.handles.get_mut(&fh) {
+            handle.pending = Some((bytes, Instant::now()));
+        }
+    }
+
+    /// Store the writes collected on every handle on `ino`, e.g. before
+    /// the note is changed some other way
+    pub(super) fn commit_pending(&mut self, ino: u64) -> Result<(), i32> {
+        let fhs: Vec<u64> = self
+            .handles
+            .iter()
+            .filter(|(_, h)| h.ino == ino && h.pending.is_some())
+            .map(|(&fh, _)| fh)
+            .collect();
+        for fh in fhs {
+            self.commit_handle(fh)?;
+        }
+        Ok(())
+    }
+
+    /// Store the writes collected on `fh` as one update
+    ///
+    /// If someone else changed the note since the handle last saw it, the
+    /// writes go to a conflict copy instead.
+    pub(super) fn commit_handle(&mut self, fh: u64) -> Result<(), i32> {
+        let Some(handle) = self.handles.get_mut(&fh) else {
+            return Ok(());
+        };
+        let Some((bytes, _)) = handle.pending.take() else {
+            return Ok(());
+        };
+        let (ino, note_id, revision) = (handle.ino, handle.note_id.clone(), handle.revision);
+        debug!(
+            "write: Storing {} bytes written through fh={fh}",
+            bytes.len()
+        );
+
+        let note = match self.db.get_note_by_id(&note_id) {
+            Ok(Some(note)) => note,
+            Ok(None) => {
+                error!("write: Note {note_id} was deleted before its writes were stored");
+                return Err(libc::ENOENT);
+            }
+            Err(e) => {
+                error!("write: Database error retrieving note {note_id}: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        if note.revision != revision {
+            return self.write_conflict_copy(fh, &note, bytes);
+        }
+        let Some(path) = self.get_path_from_inode(ino) else {
+            return Err(libc::ENOENT);
+        };
+        match self.save_note_bytes(ino, &path, &note, bytes) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(libc::ESTALE),
+            Err(e) => {
+                error!("write: Failed to update note content: {e}");
+                Err(libc::EIO)
+            }
+        }
+    }
+
+    /// Handles open for writing, by fh, with their writes waiting to be stored
+    pub(super) fn handle_states(&self) -> Vec<HandleState> {
+        let mut states: Vec<HandleState> = self
+            .handles
+            .iter()
+            .map(|(&fh, handle)| HandleState {
+                fh,
+                path: self
+                    .get_path_from_inode(handle.ino)
+                    .unwrap_or_else(|| format!("<ino {}>", handle.ino)),
+                pending: handle
+                    .pending
+                    .as_ref()
+                    .map(|(bytes, since)| (bytes.len(), since.elapsed())),
+            })
+            .collect();
+        states.sort_by_key(|state| state.fh);
+        states
+    }
+
+    /// Inode writes through `fh` go to, if it is a handle on a note
+    pub(super) fn handle_target(&self, fh: u64) -> Option<u64> {
+        self.handles.get(&fh).map(|handle| handle.ino)
+    }
+
+    /// Inodes with a handle open on them
+    pub(super) fn open_inodes(&self) -> impl Iterator<Item = u64> + '_ {
+        self.handles.values().map(|handle| handle.ino)
+    }
+
+    /// Bring every handle on `note_id` up to date after the mount itself
+    /// changed the note, so its own writes don't count as conflicts
+    pub(super) fn refresh_handles(&mut self, note_id: &str) {
+        if !self.handles.values().any(|h| h.note_id == note_id) {
+            return;
+        }
+        let Ok(Some(note)) = self.db.get_note_by_id(note_id) else {
+            return;
+        };
+        let fhs: Vec<u64> = self
+            .handles
+            .iter()
+            .filter(|(_, h)| h.note_id == note_id)
+            .map(|(&fh, _)| fh)
+            .collect();
+        for fh in fhs {
+            let ino = self.handles[&fh].ino;
+            let view = self.note_bytes(ino, &note);
+            let handle = self.handles.get_mut(&fh).expect("handle listed above");
+            handle.revision = note.revision;
+            handle.view = view;
+        }
+    }
+
+    /// Point handles on `from_id` at `to_id` (inode `to_ino`) after a rename
+    /// moved the content of one note into the other
+    pub(super) fn retarget_handles(&mut self, from_id: &str, to_id: &str, to_ino: u64) {
+        for handle in self.handles.values_mut() {
+            if handle.note_id == from_id {
+                handle.ino = to_ino;
+                handle.note_id = to_id.to_string();
+            }
+        }
+        self.refresh_handles(to_id);
+    }
+
+    /// Store `bytes`, written through `fh`, as "<title> (conflict <time>)"
+    /// next to `note`, leaving `note` as the other writer left it. Later
+    /// writes through `fh` go to the copy.
+    fn write_conflict_copy(&mut self, fh: u64, note: &Note, bytes: Vec<u8>) -> Result<(), i32> {
+        let stamp = Utc::now()
+            .with_timezone(&self.db.timezone_for(self.user_id.as_str()))
+            .format("%Y-%m-%d %H%M%S");
+        let to_errno = |e: rusqlite::Error| {
+            error!("write: Unable to create conflict copy of {}: {e}", note.id);
+            libc::EIO
+        };
+        let title = self
+            .db
+            .unique_note_title(
+                note.parent_id.as_deref(),
+                &format!("{} (conflict {stamp})", note.title),
+                &note.syntax,
+                note.extensionless,
+                self.user_id.as_str(),
+            )
+            .map_err(to_errno)?;
+        let copy_id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        self.db
+            .create_note(
+                &copy_id,
+                NoteContent {
+                    title: &title,
+                    abstract_text: note.abstract_text.as_deref(),
+                    content: "",
+                    syntax: &note.syntax,
+                    extensionless: note.extensionless,
+                },
+                note.parent_id.as_deref(),
+                self.user_id.as_str(),
+            )
+            .map_err(to_errno)?;
+        self.tag_new_note(&copy_id);
+        let copy = self
+            .db
+            .get_note_by_id(&copy_id)
+            .map_err(to_errno)?
+            .ok_or(libc::EIO)?;
+        let copy_path = match self.db.get_note_path_by_id(&copy_id).map_err(to_errno)? {
+            Some(path) => self.fuse_path(&path).ok_or(libc::EIO)?,
+            None => return Err(libc::EIO),
+        };
+        let copy_ino = self.get_or_create_inode(&copy_path);
+        error!(
+            "write: Note {} changed underneath an open file; these writes went to {copy_path}",
+            note.id
+        );
+
+        if let Some(handle) = self.handles.get_mut(&fh) {
+            handle.ino = copy_ino;
+            handle.note_id = copy_id.clone();
+        }
+        match self.save_note_bytes(copy_ino, &copy_path, &copy, bytes) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(libc::EIO),
+            Err(e) => Err(to_errno(e)),
+        }
+    }
+}
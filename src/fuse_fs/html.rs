@@ -0,0 +1,89 @@
+use fuser::{FileAttr, FileType};
+
+use super::ExampleFuseFs;
+use crate::database::{Note, NoteMeta};
+use crate::markdown;
+
+/// Extension of the rendered companion of a Markdown note ("<title>.html")
+const HTML_EXTENSION: &str = "html";
+
+impl ExampleFuseFs {
+    /// Syntax whose notes get a rendered companion
+    fn markdown_syntax(&self) -> String {
+        self.syntax_map.syntax_for_extension("md")
+    }
+
+    /// The Markdown note rendered at `path`, if `path` is a companion
+    ///
+    /// A real note stored under the same name takes precedence.
+    pub(super) fn html_note(&self, path: &str) -> Option<Note> {
+        if !self.options.html_previews {
+            return None;
+        }
+        let stem = path.strip_suffix(HTML_EXTENSION)?.strip_suffix('.')?;
+        let syntax = self.markdown_syntax();
+        let note_path = format!("{stem}.{}", self.syntax_map.extension_for_syntax(&syntax));
+        if self
+            .db
+            .get_note_id_by_path(&self.db_path(path))
+            .ok()?
+            .is_some()
+        {
+            return None;
+        }
+        let note_id = self
+            .db
+            .get_visible_note_id_by_path(&self.db_path(&note_path), self.user_id.as_str(), false)
+            .ok()??;
+        let note = self.db.get_note_by_id(&note_id).ok()??;
+        (note.syntax == syntax && !note.extensionless).then_some(note)
+    }
+
+    /// The rendered page, regenerated from the note's current content
+    pub(super) fn html_bytes(&self, note: &Note) -> Vec<u8> {
+        markdown::to_html_page(&note.title, &self.note_content(note)).into_bytes()
+    }
+
+    /// Attributes of a companion: read-only, with the note's owner and times
+    pub(super) fn html_attr(&self, ino: u64, note: &Note) -> FileAttr {
+        let size = self.html_bytes(note).len() as u64;
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: Self::datetime_to_systemtime(&note.accessed_at.unwrap_or(note.updated_at)),
+            mtime: Self::datetime_to_systemtime(&note.updated_at),
+            ctime: Self::datetime_to_systemtime(&note.updated_at),
+            crtime: Self::datetime_to_systemtime(&note.created_at),
+            kind: FileType::RegularFile,
+            perm: self.note_perm(&note.id, &note.user_id, note.mode, note.locked) & !0o222,
+            nlink: 1,
+            uid: note.uid.unwrap_or(self.options.uid),
+            gid: note.gid.unwrap_or(self.options.gid),
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+
+    /// Entry for the companion of `note`, listed next to it in `dir_path`
+    pub(super) fn html_entry(
+        &mut self,
+        dir_path: &str,
+        note: &NoteMeta,
+    ) -> Option<(u64, FileType, String)> {
+        if !self.options.html_previews
+            || note.extensionless
+            || note.syntax != self.markdown_syntax()
+        {
+            return None;
+        }
+        let name = format!("{}.{HTML_EXTENSION}", self.title_to_name(&note.title));
+        let path = if dir_path == "/" {
+            format!("/{name}")
+        } else {
+            format!("{dir_path}/{name}")
+        };
+        Some((self.get_or_create_inode(&path), FileType::RegularFile, name))
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rusqlite::InterruptHandle;
+
+/// How often the watcher looks at the process behind a running operation
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Signals that end a process blocked on the mount (SIGHUP, SIGINT, SIGQUIT,
+/// SIGKILL, SIGTERM), which is when the kernel sends FUSE_INTERRUPT
+const FATAL_SIGNALS: [u32; 5] = [1, 2, 3, 9, 15];
+
+/// The operation being served: (sequence number, pid of the caller)
+type Current = Arc<Mutex<Option<(u64, u32)>>>;
+
+/// Cancels the database query of an operation whose caller was interrupted
+///
+/// fuser answers FUSE_INTERRUPT itself (with ENOSYS) and calls handlers one at
+/// a time, so the interrupt can't reach a running handler. Instead a thread
+/// watches the caller of the current operation and, once it has a fatal signal
+/// pending (e.g. Ctrl-C in a stuck `cat`), interrupts SQLite. The query fails
+/// and the handler replies EINTR.
+pub(super) struct InterruptWatch {
+    current: Current,
+    next_seq: u64,
+    /// Interrupts the connection in use, replaced when it is reopened
+    handle: Arc<Mutex<InterruptHandle>>,
+}
+
+/// Marks an operation as interruptible until dropped
+pub(super) struct InFlight {
+    current: Current,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        *self.current.lock().unwrap() = None;
+    }
+}
+
+impl InterruptWatch {
+    pub(super) fn spawn(handle: InterruptHandle) -> Self {
+        let current: Current = Arc::new(Mutex::new(None));
+        let watched = Arc::clone(&current);
+        let handle = Arc::new(Mutex::new(handle));
+        let interrupter = Arc::clone(&handle);
+        let spawned = thread::Builder::new()
+            .name("interrupt-watch".to_string())
+            .spawn(move || {
+                let mut interrupted = None;
+                loop {
+                    thread::sleep(POLL_INTERVAL);
+                    let op = *watched.lock().unwrap();
+                    let Some((seq, pid)) = op else {
+                        continue;
+                    };
+                    if interrupted == Some(seq) || !caller_interrupted(pid) {
+                        continue;
+                    }
+                    // Only if the same operation is still running
+                    let guard = watched.lock().unwrap();
+                    if *guard == Some((seq, pid)) {
+                        debug!("interrupt: Caller pid={pid} was interrupted, cancelling");
+                        interrupter.lock().unwrap().interrupt();
+                        interrupted = Some(seq);
+                    }
+                }
+            });
+        if let Err(e) = spawned {
+            error!("interrupt: Unable to start watcher, operations can't be cancelled: {e}");
+        }
+        Self {
+            current,
+            next_seq: 0,
+            handle,
+        }
+    }
+
+    /// Cancel work on the connection of `handle` from now on
+    pub(super) fn watch(&self, handle: InterruptHandle) {
+        *self.handle.lock().unwrap() = handle;
+    }
+
+    /// Let an interrupt of `pid` cancel the database work done until the
+    /// returned guard is dropped
+    pub(super) fn begin(&mut self, pid: u32) -> InFlight {
+        self.next_seq += 1;
+        *self.current.lock().unwrap() = Some((self.next_seq, pid));
+        InFlight {
+            current: Arc::clone(&self.current),
+        }
+    }
+}
+
+/// Whether `pid` has a fatal signal pending (always false off Linux)
+fn caller_interrupted(pid: u32) -> bool {
+    if !cfg!(target_os = "linux") {
+        return false;
+    }
+    match std::fs::read_to_string(format!("/proc/{pid}/status")) {
+        Ok(status) => fatal_signal_pending(&status),
+        // The caller is gone
+        Err(_) => true,
+    }
+}
+
+/// Whether a `/proc/<pid>/status` lists a fatal signal as pending, for the
+/// thread (SigPnd) or its process (ShdPnd)
+fn fatal_signal_pending(status: &str) -> bool {
+    status
+        .lines()
+        .filter_map(|line| {
+            let mask = line
+                .strip_prefix("SigPnd:")
+                .or_else(|| line.strip_prefix("ShdPnd:"))?;
+            u64::from_str_radix(mask.trim(), 16).ok()
+        })
+        .any(|mask| FATAL_SIGNALS.iter().any(|sig| mask & (1 << (sig - 1)) != 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fatal_signal_pending() {
+        let status = |sig_pnd: &str, shd_pnd: &str| {
+            format!(
+                "Name:\tcat\nSigQ:\t0/1\nSigPnd:\t{sig_pnd}\nShdPnd:\t{shd_pnd}\nSigBlk:\t0000000000000002\n"
+            )
+        };
+        assert!(!fatal_signal_pending(&status(
+            "0000000000000000",
+            "0000000000000000"
+        )));
+        // SIGINT pending on the process
+        assert!(fatal_signal_pending(&status(
+            "0000000000000000",
+            "0000000000000002"
+        )));
+        // SIGKILL pending on the thread
+        assert!(fatal_signal_pending(&status(
+            "0000000000000100",
+            "0000000000000000"
+        )));
+        // SIGCHLD isn't fatal
+        assert!(!fatal_signal_pending(&status(
+            "0000000000010000",
+            "0000000000000000"
+        )));
+    }
+}
@@ -0,0 +1,139 @@
+//! ioctl(2) commands on files of the mount, for scripts that need more than
+//! the filesystem offers without remounting:
+//!
+//! | command                | number       | argument  | does                                  |
+//! |------------------------|--------------|-----------|---------------------------------------|
+//! | `NOTES_IOC_GET_ID`     | `0x80404e01` | out, 64 B | the note's id, NUL-padded             |
+//! | `NOTES_IOC_DROP_CACHE` | `0x00004e02` | none      | drop what the mount and kernel cached |
+//! | `NOTES_IOC_CHECKPOINT` | `0x00004e03` | none      | checkpoint the WAL into the database  |
+//! | `NOTES_IOC_SET_DEBUG`  | `0x40044e04` | in, `u32` | "[DEBUG]" lines off (0) or on         |
+//! | `NOTES_IOC_LOCK`       | `0x42004e05` | in, 512 B | lock a note of the folder             |
+//! | `NOTES_IOC_UNLOCK`     | `0x42004e06` | in, 512 B | unlock a note of the folder           |
+//!
+//! e.g. `fcntl.ioctl(fd, 0x80404e01, bytes(64))` in Python. The lock
+//! commands are given a folder, since a locked note can't be opened, and
+//! take "name\0passphrase" padded with NULs (see `note_locks`).
+
+use super::ExampleFuseFs;
+use crate::logging;
+
+/// ioctl "type" of the commands: 'N', for notes
+const MAGIC: u32 = b'N' as u32;
+
+/// Direction bits of a command number (from <asm-generic/ioctl.h>)
+const IOC_NONE: u32 = 0;
+const IOC_WRITE: u32 = 1;
+const IOC_READ: u32 = 2;
+
+/// Size of the buffer `NOTES_IOC_GET_ID` fills
+const ID_SIZE: u32 = 64;
+
+/// Size of the argument of `NOTES_IOC_LOCK` and `NOTES_IOC_UNLOCK`
+const LOCK_REQUEST_SIZE: u32 = 512;
+
+/// The command number `_IOC(dir, MAGIC, nr, size)`, as the kernel sees it
+const fn ioc(dir: u32, nr: u32, size: u32) -> u32 {
+    (dir << 30) | (size << 16) | (MAGIC << 8) | nr
+}
+
+const NOTES_IOC_GET_ID: u32 = ioc(IOC_READ, 1, ID_SIZE);
+const NOTES_IOC_DROP_CACHE: u32 = ioc(IOC_NONE, 2, 0);
+const NOTES_IOC_CHECKPOINT: u32 = ioc(IOC_NONE, 3, 0);
+const NOTES_IOC_SET_DEBUG: u32 = ioc(IOC_WRITE, 4, 4);
+const NOTES_IOC_LOCK: u32 = ioc(IOC_WRITE, 5, LOCK_REQUEST_SIZE);
+const NOTES_IOC_UNLOCK: u32 = ioc(IOC_WRITE, 6, LOCK_REQUEST_SIZE);
+
+impl ExampleFuseFs {
+    /// Run the ioctl command `cmd` on `ino`, returning what is copied back
+    /// to the caller
+    pub(super) fn run_ioctl(&mut self, ino: u64, cmd: u32, in_data: &[u8]) -> Result<Vec<u8>, i32> {
+        match cmd {
+            NOTES_IOC_GET_ID => {
+                let path = self.get_path_from_inode(ino).ok_or(libc::ENOENT)?;
+                let db_path = self.db_path(&path);
+                let id = match self.note_id_by_path(&db_path) {
+                    Ok(Some(id)) => id,
+                    // Folders and virtual files have no note id
+                    Ok(None) => return Err(libc::EINVAL),
+                    Err(e) => {
+                        error!("ioctl: Unable to look up note {path}: {e}");
+                        return Err(libc::EIO);
+                    }
+                };
+                let mut out = id.into_bytes();
+                if out.len() >= ID_SIZE as usize {
+                    return Err(libc::ERANGE);
+                }
+                out.resize(ID_SIZE as usize, 0);
+                Ok(out)
+            }
+            NOTES_IOC_DROP_CACHE => {
+                debug!(
+                    "ioctl: Dropping caches, invalidating {} inodes",
+                    self.inode_map.len()
+                );
+                self.drop_cached();
+                Ok(Vec::new())
+            }
+            NOTES_IOC_CHECKPOINT => {
+                if self.options.read_only {
+                    return Err(libc::EROFS);
+                }
+                let checkpoint =
+                    self.db
+                        .connection
+                        .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+                            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(2)?))
+                        });
+                match checkpoint {
+                    // Another connection was reading or writing
+                    Ok((1, _)) => Err(libc::EBUSY),
+                    Ok((_, pages)) => {
+                        debug!("ioctl: Checkpointed {pages} pages");
+                        Ok(Vec::new())
+                    }
+                    Err(e) => {
+                        error!("ioctl: Unable to checkpoint: {e}");
+                        Err(libc::EIO)
+                    }
+                }
+            }
+            NOTES_IOC_SET_DEBUG => {
+                let on: [u8; 4] = in_data.try_into().map_err(|_| libc::EINVAL)?;
+                let on = u32::from_ne_bytes(on) != 0;
+                // Written either way, so turning them off is seen to work
+                eprintln!(
+                    "[DEBUG] ioctl: Debug lines turned {}",
+                    if on { "on" } else { "off" }
+                );
+                logging::set_debug(on);
+                Ok(Vec::new())
+            }
+            NOTES_IOC_LOCK | NOTES_IOC_UNLOCK => {
+                if self.options.read_only && cmd == NOTES_IOC_LOCK {
+                    return Err(libc::EROFS);
+                }
+                self.lock_in_folder(ino, in_data, cmd == NOTES_IOC_LOCK)?;
+                Ok(Vec::new())
+            }
+            _ => Err(libc::ENOTTY),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_numbers() {
+        // As _IOR('N', 1, char[64]), _IO('N', 2), _IO('N', 3),
+        // _IOW('N', 4, uint32_t) and _IOW('N', 5 or 6, char[512]) expand to in C
+        assert_eq!(NOTES_IOC_GET_ID, 0x8040_4e01);
+        assert_eq!(NOTES_IOC_DROP_CACHE, 0x0000_4e02);
+        assert_eq!(NOTES_IOC_CHECKPOINT, 0x0000_4e03);
+        assert_eq!(NOTES_IOC_SET_DEBUG, 0x4004_4e04);
+        assert_eq!(NOTES_IOC_LOCK, 0x4200_4e05);
+        assert_eq!(NOTES_IOC_UNLOCK, 0x4200_4e06);
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use super::ExampleFuseFs;
+
+/// An advisory lock on the byte range `start..=end` of an entry
+#[derive(Debug, Clone, PartialEq)]
+struct Lock {
+    owner: u64,
+    start: u64,
+    end: u64,
+    /// F_RDLCK or F_WRLCK
+    typ: i32,
+    pid: u32,
+}
+
+impl Lock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start <= end && start <= self.end
+    }
+}
+
+/// POSIX (fcntl) and BSD (flock) locks held on the mount, by note or folder id
+///
+/// Locks only exist while mounted; they coordinate processes using this
+/// mount, not writers going to the database directly. The kernel's request
+/// doesn't say which kind a lock is, so unlike on a local disk a flock and
+/// an fcntl lock on the same file conflict.
+#[derive(Default)]
+pub(super) struct LockTable {
+    locks: HashMap<String, Vec<Lock>>,
+}
+
+impl LockTable {
+    /// A lock held by another owner that stops `owner` taking `typ` on the range
+    fn conflict(&self, key: &str, owner: u64, start: u64, end: u64, typ: i32) -> Option<&Lock> {
+        self.locks.get(key)?.iter().find(|lock| {
+            lock.owner != owner
+                && lock.overlaps(start, end)
+                && (typ == libc::F_WRLCK || lock.typ == libc::F_WRLCK)
+        })
+    }
+
+    /// Take, change or (with F_UNLCK) release `owner`'s lock on the range
+    fn set(
+        &mut self,
+        key: &str,
+        owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+    ) -> Result<(), i32> {
+        if typ != libc::F_UNLCK && self.conflict(key, owner, start, end, typ).is_some() {
+            return Err(libc::EAGAIN);
+        }
+
+        // The new range replaces whatever the owner held on it, splitting
+        // locks that extend past either side
+        let locks = self.locks.entry(key.to_string()).or_default();
+        let mut kept = Vec::with_capacity(locks.len() + 1);
+        for lock in locks.drain(..) {
+            if lock.owner != owner || !lock.overlaps(start, end) {
+                kept.push(lock);
+                continue;
+            }
+            if lock.start < start {
+                kept.push(Lock {
+                    end: start - 1,
+                    ..lock.clone()
+                });
+            }
+            if lock.end > end {
+                kept.push(Lock {
+                    start: end + 1,
+                    ..lock
+                });
+            }
+        }
+        if typ != libc::F_UNLCK {
+            kept.push(Lock {
+                owner,
+                start,
+                end,
+                typ,
+                pid,
+            });
+        }
+
+        if kept.is_empty() {
+            self.locks.remove(key);
+        } else {
+            *locks = kept;
+        }
+        Ok(())
+    }
+
+    /// Drop every lock `owner` holds (the file was closed)
+    fn release_owner(&mut self, owner: u64) {
+        self.locks.retain(|_, locks| {
+            locks.retain(|lock| lock.owner != owner);
+            !locks.is_empty()
+        });
+    }
+}
+
+impl ExampleFuseFs {
+    /// Id locks on `ino` are kept under, so they follow the entry across renames
+    fn lock_key(&self, ino: u64) -> Result<String, i32> {
+        let path = &self.get_path_from_inode(ino).ok_or(libc::ENOENT)?;
+        let db_path = self.db_path(path);
+        if let Ok(Some(id)) = self.note_id_by_path(&db_path) {
+            return Ok(id);
+        }
+        if let Ok(Some(id)) = self
+            .db
+            .get_folder_id_by_path(&db_path, self.user_id.as_str())
+        {
+            return Ok(id);
+        }
+        // The root, virtual entries and editor temp files
+        Ok(path.clone())
+    }
+
+    /// getlk: the lock that would block the requested one, if any
+    pub(super) fn test_lock(
+        &self,
+        ino: u64,
+        owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+    ) -> Result<Option<(u64, u64, i32, u32)>, i32> {
+        let key = self.lock_key(ino)?;
+        Ok(self
+            .locks
+            .conflict(&key, owner, start, end, typ)
+            .map(|lock| (lock.start, lock.end, lock.typ, lock.pid)))
+    }
+
+    /// setlk: take or release a lock, failing with EAGAIN on a conflict
+    pub(super) fn set_lock(
+        &mut self,
+        ino: u64,
+        owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+    ) -> Result<(), i32> {
+        let key = self.lock_key(ino)?;
+        self.locks.set(&key, owner, start, end, typ, pid)
+    }
+
+    /// Release the locks of a closing lock owner
+    pub(super) fn release_locks(&mut self, owner: u64) {
+        self.locks.release_owner(owner);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflicting_locks() {
+        let mut table = LockTable::default();
+        table.set("n", 1, 0, 99, libc::F_RDLCK, 10).unwrap();
+        // Shared locks coexist, exclusive ones don't
+        table.set("n", 2, 50, 149, libc::F_RDLCK, 20).unwrap();
+        assert_eq!(
+            table.set("n", 3, 0, 0, libc::F_WRLCK, 30),
+            Err(libc::EAGAIN)
+        );
+        assert!(table.set("n", 3, 150, 200, libc::F_WRLCK, 30).is_ok());
+        assert!(table.set("other", 3, 0, 0, libc::F_WRLCK, 30).is_ok());
+
+        let blocker = table.conflict("n", 3, 0, 10, libc::F_WRLCK).unwrap();
+        assert_eq!((blocker.owner, blocker.pid), (1, 10));
+
+        table.release_owner(1);
+        table.release_owner(2);
+        assert!(table.conflict("n", 3, 0, 10, libc::F_WRLCK).is_none());
+    }
+
+    #[test]
+    fn test_unlock_splits_range() {
+        let mut table = LockTable::default();
+        table.set("n", 1, 0, 99, libc::F_WRLCK, 10).unwrap();
+        table.set("n", 1, 40, 59, libc::F_UNLCK, 10).unwrap();
+
+        assert!(table.conflict("n", 2, 40, 59, libc::F_RDLCK).is_none());
+        assert!(table.conflict("n", 2, 0, 0, libc::F_RDLCK).is_some());
+        assert!(table.conflict("n", 2, 99, 99, libc::F_RDLCK).is_some());
+
+        table.set("n", 1, 0, u64::MAX, libc::F_UNLCK, 10).unwrap();
+        assert!(table.locks.is_empty());
+    }
+}
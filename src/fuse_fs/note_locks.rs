@@ -0,0 +1,267 @@
+//! Locked notes: listed with no permissions and refused (EACCES) to every
+//! open, write, rename and unlink until unlocked, so a note can't be
+//! edited, deleted or shown by accident
+//!
+//! `setfattr -n user.lock -v <passphrase> note.md` locks a note, and
+//! `setfattr -n user.unlock -v <passphrase> note.md` unlocks it until the
+//! mount ends (either value may be empty). `getfattr -n user.lock` reads
+//! "locked" or "unlocked"; removing `user.lock` while unlocked removes the
+//! lock for good. The `NOTES_IOC_LOCK` and `NOTES_IOC_UNLOCK` ioctls do the
+//! same on the note's folder.
+//!
+//! The passphrase is stored as a salted PBKDF2 hash, slow to guess from.
+//! It keeps the mount from giving a note away, not someone with the
+//! database; encrypt it for that.
+
+use std::fmt::Write;
+
+use sha2::Sha256;
+
+use super::ExampleFuseFs;
+
+/// PBKDF2 rounds of new lock hashes; a hash keeps the rounds it was made with
+const PBKDF2_ROUNDS: u32 = if cfg!(test) { 1_000 } else { 600_000 };
+
+/// Prefix of lock hashes made with PBKDF2-HMAC-SHA256
+const PBKDF2_SCHEME: &str = "pbkdf2-sha256";
+
+/// The lock hash of `passphrase` with `salt`:
+/// "pbkdf2-sha256$rounds$salt$hash"
+fn passphrase_hash(salt: &str, passphrase: &str) -> String {
+    pbkdf2_hash(PBKDF2_ROUNDS, salt, passphrase)
+}
+
+fn pbkdf2_hash(rounds: u32, salt: &str, passphrase: &str) -> String {
+    let key: [u8; 32] =
+        pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(passphrase.as_bytes(), salt.as_bytes(), rounds);
+    let mut out = format!("{PBKDF2_SCHEME}${rounds}${salt}$");
+    for byte in key {
+        let _ = write!(out, "{byte:02x}");
+    }
+    out
+}
+
+/// Whether `passphrase` unlocks a note locked with `lock_hash`
+fn passphrase_matches(lock_hash: &str, passphrase: &str) -> bool {
+    let mut parts = lock_hash.split('$');
+    let (Some(PBKDF2_SCHEME), Some(rounds), Some(salt), Some(_), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return legacy_passphrase_matches(lock_hash, passphrase);
+    };
+    rounds
+        .parse()
+        .is_ok_and(|rounds| pbkdf2_hash(rounds, salt, passphrase) == lock_hash)
+}
+
+/// Whether `passphrase` unlocks a note locked before lock hashes used
+/// PBKDF2, as "salt:hash" with a 64-bit FNV-1a hash; `unlock_note` hashes
+/// it again
+fn legacy_passphrase_matches(lock_hash: &str, passphrase: &str) -> bool {
+    let fnv1a = |hash: u64, bytes: &[u8]| {
+        bytes.iter().fold(hash, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    };
+    lock_hash.split_once(':').is_some_and(|(salt, hash)| {
+        let expected = fnv1a(
+            fnv1a(0xcbf2_9ce4_8422_2325, salt.as_bytes()),
+            passphrase.as_bytes(),
+        );
+        format!("{expected:016x}") == hash
+    })
+}
+
+/// A new lock hash of `passphrase`, with a random salt
+fn new_passphrase_hash(passphrase: &str) -> String {
+    let salt = uuid::Uuid::new_v4().simple().to_string();
+    passphrase_hash(&salt[..16], passphrase)
+}
+
+/// The note name and passphrase in the argument of the lock ioctls,
+/// "name\0passphrase" padded with NULs
+fn parse_lock_request(data: &[u8]) -> Result<(&str, &str), i32> {
+    let end = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    let data = std::str::from_utf8(&data[..end]).map_err(|_| libc::EINVAL)?;
+    let (name, passphrase) = data.split_once('\0').unwrap_or((data, ""));
+    if name.is_empty() || name.contains('/') {
+        return Err(libc::EINVAL);
+    }
+    Ok((name, passphrase))
+}
+
+impl ExampleFuseFs {
+    /// Whether note `id`, stored as `locked`, is locked in this mount
+    pub(super) fn is_locked(&self, id: &str, locked: bool) -> bool {
+        locked && !self.unlocked.contains(id)
+    }
+
+    /// EACCES if note `id` is locked in this mount
+    pub(super) fn check_note_unlocked(&self, id: &str) -> Result<(), i32> {
+        match self.db.get_note_meta_by_id(id) {
+            Ok(Some(note)) if self.is_locked(&note.id, note.locked) => Err(libc::EACCES),
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err(libc::ENOENT),
+            Err(e) => {
+                error!("lock: Unable to check the lock of note {id}: {e}");
+                Err(libc::EIO)
+            }
+        }
+    }
+
+    /// The stored lock hash of note `id`, if it is locked
+    fn note_lock(&self, id: &str) -> Result<Option<String>, i32> {
+        self.db.get_note_lock(id).map_err(|e| {
+            error!("lock: Unable to read the lock of note {id}: {e}");
+            libc::EIO
+        })
+    }
+
+    /// Have the kernel ask again for the attributes of the note at `path`,
+    /// whose permissions came with being locked
+    fn lock_changed(&self, path: &str) {
+        if let Some(&ino) = self.inode_map.get(path) {
+            self.notify.inval_inode(ino);
+        }
+    }
+
+    /// Lock note `id` (at `path`) behind `passphrase`; a note that is locked
+    /// here already only takes its own passphrase
+    pub(super) fn lock_note(&mut self, path: &str, id: &str, passphrase: &str) -> Result<(), i32> {
+        if let Some(lock_hash) = self.note_lock(id)?
+            && self.is_locked(id, true)
+            && !passphrase_matches(&lock_hash, passphrase)
+        {
+            return Err(libc::EACCES);
+        }
+        match self
+            .db
+            .set_note_lock(id, Some(&new_passphrase_hash(passphrase)))
+        {
+            Ok(true) => {}
+            Ok(false) => return Err(libc::ENOENT),
+            Err(e) => {
+                error!("lock: Failed to lock {path}: {e}");
+                return Err(libc::EIO);
+            }
+        }
+        debug!("lock: Locked {path}");
+        self.unlocked.remove(id);
+        self.lock_changed(path);
+        Ok(())
+    }
+
+    /// Unlock note `id` (at `path`) until the mount ends
+    pub(super) fn unlock_note(
+        &mut self,
+        path: &str,
+        id: &str,
+        passphrase: &str,
+    ) -> Result<(), i32> {
+        let Some(lock_hash) = self.note_lock(id)? else {
+            return Ok(());
+        };
+        if !passphrase_matches(&lock_hash, passphrase) {
+            debug!("lock: Wrong passphrase for {path}");
+            return Err(libc::EACCES);
+        }
+        if !lock_hash.starts_with(PBKDF2_SCHEME)
+            && let Err(e) = self
+                .db
+                .set_note_lock(id, Some(&new_passphrase_hash(passphrase)))
+        {
+            error!("lock: Unable to hash the passphrase of {path} again: {e}");
+        }
+        debug!("lock: Unlocked {path}");
+        self.unlocked.insert(id.to_string());
+        self.lock_changed(path);
+        Ok(())
+    }
+
+    /// Remove the lock of note `id` (at `path`), which has to be unlocked
+    pub(super) fn remove_note_lock(&mut self, path: &str, id: &str) -> Result<(), i32> {
+        if self.is_locked(id, true) {
+            return Err(libc::EACCES);
+        }
+        if let Err(e) = self.db.set_note_lock(id, None) {
+            error!("lock: Failed to remove the lock of {path}: {e}");
+            return Err(libc::EIO);
+        }
+        self.unlocked.remove(id);
+        self.lock_changed(path);
+        Ok(())
+    }
+
+    /// `NOTES_IOC_LOCK` or `NOTES_IOC_UNLOCK` on the folder `ino`: lock or
+    /// unlock the note named in `data` (see `parse_lock_request`)
+    pub(super) fn lock_in_folder(&mut self, ino: u64, data: &[u8], lock: bool) -> Result<(), i32> {
+        let dir = self.get_path_from_inode(ino).ok_or(libc::ENOENT)?;
+        if !self.is_dir(&dir) {
+            return Err(libc::ENOTDIR);
+        }
+        let (name, passphrase) = parse_lock_request(data)?;
+        let path = if dir == "/" {
+            format!("/{name}")
+        } else {
+            format!("{dir}/{name}")
+        };
+        let id = match self.note_id_by_path(&self.db_path(&path)) {
+            Ok(Some(id)) => id,
+            Ok(None) => return Err(libc::ENOENT),
+            Err(e) => {
+                error!("ioctl: Unable to look up note {path}: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        self.check_note_owner(&id)?;
+        if lock {
+            self.lock_note(&path, &id, passphrase)
+        } else {
+            self.unlock_note(&path, &id, passphrase)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passphrase_matches() {
+        let lock_hash = passphrase_hash("0123456789abcdef", "hunter2");
+        assert!(lock_hash.starts_with("pbkdf2-sha256$1000$0123456789abcdef$"));
+        assert!(passphrase_matches(&lock_hash, "hunter2"));
+        assert!(!passphrase_matches(&lock_hash, "hunter3"));
+        assert!(!passphrase_matches(&lock_hash, ""));
+        // The salt changes the hash of the same passphrase
+        assert_ne!(
+            passphrase_hash("salt", "hunter2"),
+            passphrase_hash("pepper", "hunter2")
+        );
+        assert!(passphrase_matches(&passphrase_hash("salt", ""), ""));
+        assert!(!passphrase_matches("no separator", ""));
+        assert!(!passphrase_matches("pbkdf2-sha256$many$salt$00", ""));
+        // Hashes keep the rounds they were made with
+        let stronger = pbkdf2_hash(2_000, "salt", "hunter2");
+        assert!(passphrase_matches(&stronger, "hunter2"));
+        assert!(!passphrase_matches(&stronger, "hunter3"));
+
+        // Locks from before PBKDF2 still open
+        assert!(passphrase_matches("salt:4eeb24a29e1e93c7", "hunter2"));
+        assert!(!passphrase_matches("salt:4eeb24a29e1e93c7", "hunter3"));
+    }
+
+    #[test]
+    fn test_parse_lock_request() {
+        let mut data = b"diary.md\0hunter2".to_vec();
+        data.resize(512, 0);
+        assert_eq!(parse_lock_request(&data), Ok(("diary.md", "hunter2")));
+        assert_eq!(parse_lock_request(b"diary.md\0\0\0"), Ok(("diary.md", "")));
+        assert_eq!(parse_lock_request(b"\0hunter2"), Err(libc::EINVAL));
+        assert_eq!(parse_lock_request(b"a/b.md\0"), Err(libc::EINVAL));
+    }
+}
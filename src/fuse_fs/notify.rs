@@ -0,0 +1,100 @@
+use std::ffi::OsString;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use fuser::Notifier;
+
+/// Where the session's notifier goes once the filesystem is mounted
+pub type NotifierSlot = Arc<OnceLock<Notifier>>;
+
+/// What the kernel should forget
+enum Notice {
+    /// A directory entry that was removed: (parent inode, inode, name)
+    Delete(u64, u64, OsString),
+    /// A directory entry that may now be something else: (parent inode, name)
+    InvalEntry(u64, OsString),
+    /// The attributes and cached contents of an inode
+    InvalInode(u64),
+}
+
+/// Tells the kernel about entries removed or changed underneath the mount
+///
+/// The kernel locks the parent directory to drop an entry, and the caller
+/// of the operation being served may hold that lock, so notifications are
+/// sent from a thread of their own rather than from a handler. Nothing is
+/// sent until a notifier is put in the slot (see
+/// `ExampleFuseFs::notifier_slot`).
+pub(super) struct KernelNotify {
+    slot: NotifierSlot,
+    sender: Option<Sender<Notice>>,
+}
+
+impl KernelNotify {
+    pub(super) fn spawn() -> Self {
+        let slot: NotifierSlot = Arc::default();
+        let notifier = Arc::clone(&slot);
+        let (sender, receiver) = mpsc::channel::<Notice>();
+        let spawned = thread::Builder::new()
+            .name("kernel-notify".to_string())
+            .spawn(move || {
+                for notice in receiver {
+                    let Some(notifier) = notifier.get() else {
+                        continue;
+                    };
+                    let (sent, what) = match notice {
+                        Notice::Delete(parent, child, name) => (
+                            notifier.delete(parent, child, &name),
+                            format!("{name:?} in {parent}"),
+                        ),
+                        Notice::InvalEntry(parent, name) => (
+                            notifier.inval_entry(parent, &name),
+                            format!("{name:?} in {parent}"),
+                        ),
+                        Notice::InvalInode(ino) => {
+                            (notifier.inval_inode(ino, 0, 0), format!("inode {ino}"))
+                        }
+                    };
+                    // ENOENT: the kernel had nothing cached
+                    if let Err(e) = sent
+                        && e.raw_os_error() != Some(libc::ENOENT)
+                    {
+                        error!("notify: Unable to drop {what} from the kernel cache: {e}");
+                    }
+                }
+            });
+        let sender = match spawned {
+            Ok(_) => Some(sender),
+            Err(e) => {
+                error!("notify: Unable to start thread, the kernel won't hear of removals: {e}");
+                None
+            }
+        };
+        Self { slot, sender }
+    }
+
+    pub(super) fn slot(&self) -> NotifierSlot {
+        Arc::clone(&self.slot)
+    }
+
+    /// Have the kernel drop the entry `name` of inode `child` in `parent`
+    pub(super) fn delete(&self, parent: u64, child: u64, name: &str) {
+        self.send(Notice::Delete(parent, child, OsString::from(name)));
+    }
+
+    /// Have the kernel look up the entry `name` in `parent` again
+    pub(super) fn inval_entry(&self, parent: u64, name: &str) {
+        self.send(Notice::InvalEntry(parent, OsString::from(name)));
+    }
+
+    /// Have the kernel read the attributes and contents of `ino` again
+    pub(super) fn inval_inode(&self, ino: u64) {
+        self.send(Notice::InvalInode(ino));
+    }
+
+    fn send(&self, notice: Notice) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(notice);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! `--persist-to`: an in-memory database saved to a file on unmount, and
+//! loaded from it on the next mount, for a fast scratch workspace whose
+//! notes outlive the process
+//!
+//! Nothing is saved if the process ends without unmounting (e.g. killed).
+
+use std::path::PathBuf;
+
+use super::ExampleFuseFs;
+
+/// Where the database is saved, and the SQLCipher passphrase it is
+/// encrypted with there
+pub(super) struct PersistTo {
+    path: PathBuf,
+    key: Option<String>,
+}
+
+impl ExampleFuseFs {
+    /// Save the database to `path` on unmount, encrypted with `key` if given
+    pub fn persist_to(&mut self, path: impl Into<PathBuf>, key: Option<String>) {
+        self.persist_to = Some(PersistTo {
+            path: path.into(),
+            key,
+        });
+    }
+
+    /// Save the database where `--persist-to` says; the copy saved last
+    /// time is only replaced once this one is complete
+    pub(super) fn save_persisted(&self) {
+        let Some(persist) = &self.persist_to else {
+            return;
+        };
+        let mut partial = persist.path.clone().into_os_string();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        let _ = std::fs::remove_file(&partial);
+        let saved = self
+            .db
+            .snapshot(&partial, persist.key.as_deref(), None)
+            .map_err(|e| e.to_string())
+            .and_then(|()| std::fs::rename(&partial, &persist.path).map_err(|e| e.to_string()));
+        match saved {
+            Ok(()) => debug!("persist: Saved the database to {}", persist.path.display()),
+            Err(e) => {
+                let _ = std::fs::remove_file(&partial);
+                error!(
+                    "persist: Unable to save the database to {}: {e}",
+                    persist.path.display()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use fuser::PollHandle;
+
+use super::ExampleFuseFs;
+use super::virtual_files::VirtualFile;
+use crate::logging;
+
+/// Readiness of files that don't change underneath a reader (what the
+/// kernel assumes of files whose filesystem has no poll)
+pub(super) const ALWAYS_READY: u32 =
+    (libc::POLLIN | libc::POLLOUT | libc::POLLRDNORM | libc::POLLWRNORM) as u32;
+
+/// The kernel wants to be told when the handle becomes ready
+/// (FUSE_POLL_SCHEDULE_NOTIFY)
+const SCHEDULE_NOTIFY: u32 = 1;
+
+/// Pollers waiting for a version of something to go past the one they saw
+#[derive(Default)]
+pub(super) struct PollWaiters {
+    waiting: Vec<(u64, PollHandle)>,
+}
+
+impl PollWaiters {
+    /// Notify `handle` once the version is past `seen`
+    pub(super) fn add(&mut self, seen: u64, handle: PollHandle) {
+        self.waiting.push((seen, handle));
+    }
+
+    /// Notify the pollers that have not seen `version`
+    pub(super) fn wake(&mut self, version: u64) {
+        let (ready, waiting) = std::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition(|(seen, _)| version > *seen);
+        self.waiting = waiting;
+        for (_, handle) in ready {
+            Self::notify(handle);
+        }
+    }
+
+    /// Tell the kernel the poll of `handle` is ready
+    fn notify(handle: PollHandle) {
+        // ENOENT: the poll was already over (the poller timed out or closed)
+        if let Err(e) = handle.notify()
+            && e.raw_os_error() != Some(libc::ENOENT)
+        {
+            error!("poll: Unable to notify a poller: {e}");
+        }
+    }
+}
+
+impl ExampleFuseFs {
+    /// The version of `file` a reader has seen once the operation being
+    /// served is over; it changes whenever the contents may have
+    fn virtual_file_version(&self, file: VirtualFile) -> u64 {
+        match file {
+            // Every operation shows in the counters, this one included, and
+            // may change the health of the mount
+            VirtualFile::Stats | VirtualFile::Health => self.stats.finished() + 1,
+            VirtualFile::Log => logging::errors_logged(),
+        }
+    }
+
+    /// Whether `file` changed since the version `seen`
+    fn virtual_file_changed(&self, file: VirtualFile, seen: u64) -> bool {
+        match file {
+            VirtualFile::Stats | VirtualFile::Health => self.stats.finished() > seen,
+            VirtualFile::Log => logging::errors_logged() > seen,
+        }
+    }
+
+    /// Note that handle `fh` read `file`, so polls wait for it to change again
+    pub(super) fn virtual_file_read(&mut self, fh: u64, file: VirtualFile) {
+        let version = self.virtual_file_version(file);
+        self.virtual_seen.insert(fh, version);
+    }
+
+    /// Readiness of handle `fh` on `file`: readable once it changed since
+    /// the handle last read it (or if it never did). If not, `handle` is
+    /// notified when it changes, if the kernel asked for that.
+    pub(super) fn poll_virtual_file(
+        &mut self,
+        fh: u64,
+        file: VirtualFile,
+        handle: PollHandle,
+        flags: u32,
+    ) -> u32 {
+        let ready = (libc::POLLIN | libc::POLLRDNORM) as u32;
+        let Some(&seen) = self.virtual_seen.get(&fh) else {
+            return ready;
+        };
+        if self.virtual_file_changed(file, seen) {
+            return ready;
+        }
+        // Polling isn't a change the poller should wake up for
+        let seen = self.virtual_file_version(file);
+        self.virtual_seen.insert(fh, seen);
+        if flags & SCHEDULE_NOTIFY != 0 {
+            match file {
+                VirtualFile::Stats | VirtualFile::Health => self.stats.wake_after(seen, handle),
+                // Errors are logged by other threads too
+                VirtualFile::Log => logging::on_error_after(seen, move || {
+                    PollWaiters::notify(handle);
+                }),
+            }
+        }
+        0
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+
+use super::{ExampleFuseFs, stats};
+use crate::database::{ChangedItem, Database};
+
+/// How often the database file is looked at, at most
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Opens the database again, as it was opened for the mount
+type Reopen = Box<dyn Fn() -> rusqlite::Result<Connection> + Send>;
+
+/// The file the mounted database was opened from, watched for being
+/// replaced (restored from a backup, synced from another machine), since
+/// the open connection keeps reading the file that was there
+pub(super) struct DatabaseFile {
+    path: PathBuf,
+    /// (device, inode) of the file the connection has open
+    identity: (u64, u64),
+    checked: Instant,
+    reopen: Reopen,
+    /// The old database was closed for a replacement that isn't served yet
+    closed: bool,
+}
+
+/// The last look for other processes' commits
+pub(super) struct LastSeen {
+    /// `PRAGMA data_version`
+    data_version: i64,
+    /// Anything changed since has an `updated_at` or `deleted_at` this late
+    looked_at: DateTime<Utc>,
+}
+
+impl ExampleFuseFs {
+    /// Reopen the database with `reopen` whenever the file at `path` is
+    /// replaced by another one
+    pub fn reopen_when_replaced(
+        &mut self,
+        path: impl Into<PathBuf>,
+        reopen: impl Fn() -> rusqlite::Result<Connection> + Send + 'static,
+    ) {
+        let path = path.into();
+        match std::fs::metadata(&path) {
+            Ok(metadata) => {
+                self.database_file = Some(DatabaseFile {
+                    path,
+                    identity: (metadata.dev(), metadata.ino()),
+                    checked: Instant::now(),
+                    reopen: Box::new(reopen),
+                    closed: false,
+                });
+            }
+            Err(e) => error!(
+                "reload: Unable to stat {}, it won't be reopened if replaced: {e}",
+                path.display()
+            ),
+        }
+    }
+
+    /// Reopen the database if its file was replaced since the last look
+    pub(super) fn check_database_file(&mut self) {
+        let Some(file) = &mut self.database_file else {
+            return;
+        };
+        if file.checked.elapsed() < CHECK_INTERVAL {
+            return;
+        }
+        file.checked = Instant::now();
+        // Missing for a moment while a new copy is moved into place
+        let Ok(metadata) = std::fs::metadata(&file.path) else {
+            return;
+        };
+        let identity = (metadata.dev(), metadata.ino());
+        if identity == file.identity {
+            return;
+        }
+        let path = file.path.clone();
+        if !file.closed {
+            debug!("reload: {} was replaced, reopening", path.display());
+            file.closed = true;
+            self.close_database();
+        }
+        let Some(file) = &mut self.database_file else {
+            return;
+        };
+        let connection = match (file.reopen)() {
+            Ok(connection) => connection,
+            Err(e) => {
+                // Tried again on the next look; operations fail until then
+                error!("reload: Unable to reopen {}: {e}", path.display());
+                return;
+            }
+        };
+        file.identity = identity;
+        // Looked at again only once the file is replaced again
+        let schema = Database::new(connection, None);
+        match schema.schema_state() {
+            Ok(state) => {
+                if let Some(problem) = state.problem() {
+                    error!(
+                        "reload: {} can't be served until it is replaced again: {problem}",
+                        path.display()
+                    );
+                    return;
+                }
+            }
+            Err(e) => {
+                error!(
+                    "reload: Unable to read the schema of {}: {e}",
+                    path.display()
+                );
+                return;
+            }
+        }
+        file.closed = false;
+        self.reload_database(schema.connection);
+    }
+
+    /// Checkpoint and close the database of the replaced file, leaving an
+    /// empty in-memory one (on which every operation fails) until the
+    /// replacement is opened
+    ///
+    /// Closed before the replacement is opened: closing removes the -wal and
+    /// -shm files, which are found by name and so would be the replacement's
+    /// too, under its open connection.
+    fn close_database(&mut self) {
+        if !self.options.read_only
+            && let Err(e) = self
+                .db
+                .connection
+                .execute_batch("PRAGMA wal_checkpoint(TRUNCATE)")
+        {
+            error!("reload: Unable to checkpoint the replaced database: {e}");
+        }
+        let placeholder = match Connection::open_in_memory() {
+            Ok(connection) => Database::new(connection, Some(self.db.timezone)),
+            Err(e) => {
+                error!(
+                    "reload: Unable to open a placeholder, keeping the replaced database open: {e}"
+                );
+                return;
+            }
+        };
+        let old = std::mem::replace(&mut self.db, placeholder);
+        if let Err((_, e)) = old.connection.close() {
+            error!("reload: Unable to close the replaced database: {e}");
+        }
+        self.interrupts.watch(self.db.interrupt_handle());
+    }
+
+    /// Serve the database of `connection` in place of the current one,
+    /// dropping everything cached from the old one
+    fn reload_database(&mut self, connection: Connection) {
+        let mut db = Database::new(connection, Some(self.db.timezone));
+        db.case_insensitive = self.options.case_insensitive;
+        if self.options.normalize_unicode
+            && let Err(e) = db.normalize_names()
+        {
+            error!("reload: Unable to match names by their NFC forms: {e}");
+        }
+        db.profile_queries(stats::record_query);
+        self.db = db;
+        self.interrupts.watch(self.db.interrupt_handle());
+
+        match Self::prepare_database(&self.db, &self.user_id, &self.options) {
+            Ok((generation, subdir, syntax_map)) => {
+                self.generation = generation;
+                self.subdir = subdir;
+                self.syntax_map = syntax_map;
+            }
+            Err(e) => error!("reload: Unable to read the new database: {e}"),
+        }
+        self.last_seen = None;
+        self.drop_cached();
+        debug!(
+            "reload: Reopened the database, {} inodes invalidated",
+            self.inode_map.len()
+        );
+    }
+
+    /// Have the kernel look up and read again the notes and folders another
+    /// connection changed since the last look, rather than leaving it to show
+    /// them until the attribute and entry timeouts run out
+    ///
+    /// Only the notes and folders stamped since the last look are read, so
+    /// commits of the mount's own backup and maintenance threads, which
+    /// leave every note and folder as it was, cost one query that finds
+    /// nothing. Cached conversions and decryptions are by revision, so they
+    /// stay.
+    pub(super) fn check_external_commits(&mut self) {
+        // Nothing to look at until a replaced database is opened again
+        if self.database_file.as_ref().is_some_and(|file| file.closed) {
+            return;
+        }
+        // Taken first: a commit after the version is read is stamped this
+        // late or later, so the next look finds it
+        let looked_at = Utc::now();
+        let version = match self.db.data_version() {
+            Ok(version) => version,
+            Err(e) => {
+                error!("refresh: Unable to read the data version: {e}");
+                return;
+            }
+        };
+        if self
+            .last_seen
+            .as_ref()
+            .is_some_and(|seen| seen.data_version == version)
+        {
+            return;
+        }
+        if let Some(previous) = &self.last_seen {
+            let changed = match self.db.changed_since(&self.user_id, previous.looked_at) {
+                Ok(changed) => changed,
+                Err(e) => {
+                    // Looked at again on the next operation
+                    error!("refresh: Unable to read the changed notes and folders: {e}");
+                    return;
+                }
+            };
+            self.invalidate_changed(&changed);
+            if !changed.is_empty() {
+                debug!(
+                    "refresh: Another connection changed {} notes and folders",
+                    changed.len()
+                );
+            }
+        }
+        self.last_seen = Some(LastSeen {
+            data_version: version,
+            looked_at,
+        });
+    }
+
+    /// Invalidate the `changed` notes and folders, and the entries of the
+    /// directories they are in or were added to, removed from or renamed in
+    fn invalidate_changed(&self, changed: &[ChangedItem]) {
+        if changed.is_empty() {
+            return;
+        }
+        // The top of the notes has no row to be touched when something
+        // leaves it, so its entries are looked up again on any change
+        let mut dirs: HashSet<Option<&str>> = HashSet::from([None]);
+        for item in changed {
+            dirs.insert(item.parent_id.as_deref());
+            if item.is_folder {
+                // Touched when something in it changed
+                dirs.insert(Some(item.id.as_str()));
+            }
+            if item.deleted {
+                // Its name goes with the entries of its folder
+                continue;
+            }
+            if let Some(path) = self.item_mount_path(item)
+                && let Some((parent, name)) = path.rsplit_once('/')
+            {
+                if let Some(&ino) = self.inode_map.get(&path) {
+                    self.notify.inval_inode(ino);
+                }
+                // The kernel may hold that the name doesn't exist
+                let parent = if parent.is_empty() { "/" } else { parent };
+                if !name.is_empty()
+                    && let Some(&parent) = self.inode_map.get(parent)
+                {
+                    self.notify.inval_entry(parent, name);
+                }
+            }
+        }
+        let mut dir_inos = HashMap::new();
+        for folder_id in dirs {
+            if let Some(dir) = self.folder_mount_path(folder_id)
+                && let Some(&dir_ino) = self.inode_map.get(&dir)
+            {
+                self.notify.inval_inode(dir_ino);
+                dir_inos.insert(dir, dir_ino);
+            }
+        }
+        // Names the kernel may hold that are gone or lead elsewhere now
+        for path in self.inode_map.keys() {
+            if let Some((parent, name)) = path.rsplit_once('/')
+                && !name.is_empty()
+                && let Some(&dir_ino) = dir_inos.get(if parent.is_empty() { "/" } else { parent })
+            {
+                self.notify.inval_entry(dir_ino, name);
+            }
+        }
+    }
+
+    /// Where the mount shows the note or folder `item`, if it does
+    fn item_mount_path(&self, item: &ChangedItem) -> Option<String> {
+        if item.is_folder {
+            return self.folder_mount_path(Some(&item.id));
+        }
+        self.fuse_path(&self.db.get_note_path_by_id(&item.id).ok()??)
+    }
+
+    /// Where the mount shows the folder `folder_id` (`None` for the top of
+    /// the user's notes), if it does
+    fn folder_mount_path(&self, folder_id: Option<&str>) -> Option<String> {
+        match folder_id {
+            Some(id) if self.root_folder_id().as_deref() == Some(id) => Some("/".to_string()),
+            Some(id) => self.fuse_path(&self.db.get_folder_path_by_id(id, &self.user_id).ok()??),
+            None if self.subdir.is_none() => Some("/".to_string()),
+            None => None,
+        }
+    }
+
+    /// Forget the note versions kept for `--auto-cache` and the notes
+    /// converted for `/.export` or decrypted, and have the kernel look up and read again
+    /// everything it was given
+    pub(super) fn drop_cached(&mut self) {
+        self.cached_versions.clear();
+        // A replaced database may reuse note ids and revisions
+        self.exports.clear();
+        self.decrypted.clear();
+        // Inode numbers the kernel holds keep naming the same paths, but what
+        // is at those paths (if anything) is read again
+        for (path, &inode) in &self.inode_map {
+            self.notify.inval_inode(inode);
+            if let Some((parent, name)) = path.rsplit_once('/')
+                && !name.is_empty()
+            {
+                let parent = if parent.is_empty() { "/" } else { parent };
+                if let Some(&parent) = self.inode_map.get(parent) {
+                    self.notify.inval_entry(parent, name);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Scratch files: the metadata macOS writes next to files it touches
+//! ("._*" AppleDouble files and .DS_Store), kept in memory for as long as
+//! the mount lasts so Finder can read back what it wrote without any of it
+//! being stored as notes.
+
+use std::time::SystemTime;
+
+use fuser::{FileAttr, FileType};
+
+use super::ExampleFuseFs;
+
+/// Contents and times of a scratch file
+pub(super) struct ScratchFile {
+    bytes: Vec<u8>,
+    accessed: SystemTime,
+    modified: SystemTime,
+    created: SystemTime,
+}
+
+/// Whether files named `name` are kept as scratch files
+pub(super) fn is_scratch_name(name: &str) -> bool {
+    name == ".DS_Store" || name.starts_with("._")
+}
+
+impl ExampleFuseFs {
+    /// The scratch file at `path` and its inode, if there is one
+    pub(super) fn scratch_file(&self, path: &str) -> Option<(u64, &ScratchFile)> {
+        let ino = *self.inode_map.get(path)?;
+        Some((ino, self.scratch_files.get(&ino)?))
+    }
+
+    /// Creates an empty scratch file at `path`, replacing any there; returns its inode
+    pub(super) fn create_scratch(&mut self, path: &str) -> u64 {
+        let ino = self.get_or_create_inode(path);
+        let now = SystemTime::now();
+        self.scratch_files.insert(
+            ino,
+            ScratchFile {
+                bytes: Vec::new(),
+                accessed: now,
+                modified: now,
+                created: now,
+            },
+        );
+        ino
+    }
+
+    /// Contents of the scratch file `ino`, if it is one
+    pub(super) fn scratch_bytes(&self, ino: u64) -> Option<&[u8]> {
+        self.scratch_files
+            .get(&ino)
+            .map(|file| file.bytes.as_slice())
+    }
+
+    /// Applies a write to the scratch file `ino`; false if it isn't one
+    pub(super) fn write_scratch(&mut self, ino: u64, offset: i64, data: &[u8]) -> bool {
+        let Some(file) = self.scratch_files.get_mut(&ino) else {
+            return false;
+        };
+        file.bytes = Self::apply_write(std::mem::take(&mut file.bytes), offset, data);
+        file.modified = SystemTime::now();
+        true
+    }
+
+    /// Truncates and/or sets the times of the scratch file `ino`; false if
+    /// it isn't one
+    pub(super) fn setattr_scratch(
+        &mut self,
+        ino: u64,
+        size: Option<u64>,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+        created: Option<SystemTime>,
+    ) -> bool {
+        let Some(file) = self.scratch_files.get_mut(&ino) else {
+            return false;
+        };
+        if let Some(size) = size {
+            file.bytes.resize(size as usize, 0);
+            file.modified = SystemTime::now();
+        }
+        file.accessed = accessed.unwrap_or(file.accessed);
+        file.modified = modified.unwrap_or(file.modified);
+        file.created = created.unwrap_or(file.created);
+        true
+    }
+
+    /// Drops the scratch file at `path`; false if there isn't one
+    pub(super) fn remove_scratch(&mut self, path: &str) -> bool {
+        let Some(&ino) = self.inode_map.get(path) else {
+            return false;
+        };
+        if self.scratch_files.remove(&ino).is_none() {
+            return false;
+        }
+        self.remove_inode_mapping(path);
+        true
+    }
+
+    /// Attributes of a scratch file, as for a note of the mount's user
+    pub(super) fn scratch_attr(&self, ino: u64, file: &ScratchFile) -> FileAttr {
+        let size = file.bytes.len() as u64;
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: file.accessed,
+            mtime: file.modified,
+            ctime: file.modified,
+            crtime: file.created,
+            kind: FileType::RegularFile,
+            perm: self.options.file_mode,
+            nlink: 1,
+            uid: self.options.uid,
+            gid: self.options.gid,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+}
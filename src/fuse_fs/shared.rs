@@ -0,0 +1,429 @@
+use fuser::{FileAttr, FileType};
+
+use super::ExampleFuseFs;
+use crate::database::{Access, Folder, Note, NoteContent, Page};
+
+/// `/shared/<owner>/<folder>/` holds the folders other users shared with the
+/// mount's user (see the `share` command)
+pub(super) const SHARED_DIR: &str = "/shared";
+
+/// What a path below `/shared/<owner>/` refers to
+pub(super) enum SharedPath {
+    /// A folder of the owner at or below one they shared
+    Folder(Folder, Access),
+    /// A name inside such a folder; `note` is `None` until a file of that name is created
+    Note {
+        parent: Folder,
+        name: String,
+        note: Option<Box<Note>>,
+        access: Access,
+    },
+}
+
+impl SharedPath {
+    pub fn access(&self) -> Access {
+        match self {
+            SharedPath::Folder(_, access) | SharedPath::Note { access, .. } => *access,
+        }
+    }
+
+    /// EACCES unless the owner granted write access
+    pub fn check_writable(&self) -> Result<(), i32> {
+        match self.access() {
+            Access::Write => Ok(()),
+            Access::Read => Err(libc::EACCES),
+        }
+    }
+}
+
+/// `perm` without its write bits where only reading was granted
+fn granted_perm(perm: u16, access: Access) -> u16 {
+    match access {
+        Access::Write => perm,
+        Access::Read => perm & !0o222,
+    }
+}
+
+impl ExampleFuseFs {
+    /// Folders shared with the mount's user, with the access granted
+    fn shared_roots(&self) -> Vec<(Folder, Access)> {
+        if !self.options.shared {
+            return Vec::new();
+        }
+        match self.db.list_shared_folders(self.user_id.as_str()) {
+            Ok(folders) => folders,
+            Err(e) => {
+                error!("shared: Unable to list shared folders: {e}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Whether `path` is `/shared` or `/shared/<owner>` of someone sharing a folder
+    pub(super) fn is_shared_dir(&self, path: &str) -> bool {
+        if !self.options.shared {
+            return false;
+        }
+        if path == SHARED_DIR {
+            return true;
+        }
+        let Some(owner) = path
+            .strip_prefix(SHARED_DIR)
+            .and_then(|rest| rest.strip_prefix('/'))
+        else {
+            return false;
+        };
+        !owner.contains('/')
+            && self
+                .shared_roots()
+                .iter()
+                .any(|(folder, _)| folder.user_id == owner)
+    }
+
+    /// Whether `path` lies below `/shared/<owner>/`, where names can't be
+    /// renamed or linked in from elsewhere
+    pub(super) fn is_in_shared(&self, path: &str) -> bool {
+        self.options.shared
+            && path
+                .strip_prefix(SHARED_DIR)
+                .and_then(|rest| rest.strip_prefix('/'))
+                .is_some_and(|rest| rest.contains('/'))
+    }
+
+    /// Subdirectories of `/shared` (the owners) or of `/shared/<owner>` (their folders)
+    pub(super) fn shared_dir_names(&self, path: &str) -> Vec<String> {
+        let roots = self.shared_roots();
+        let mut names: Vec<String> = if path == SHARED_DIR {
+            roots
+                .into_iter()
+                .map(|(folder, _)| folder.user_id)
+                .collect()
+        } else {
+            let owner = &path[SHARED_DIR.len() + 1..];
+            roots
+                .into_iter()
+                .filter(|(folder, _)| folder.user_id == owner)
+                .map(|(folder, _)| self.title_to_name(&folder.title))
+                .collect()
+        };
+        names.dedup();
+        names
+    }
+
+    /// Classify `path` if it lies in a folder shared with the mount's user
+    ///
+    /// Access is checked on every call, so a revoked share disappears at once.
+    pub(super) fn shared_path(&self, path: &str) -> Option<SharedPath> {
+        let rest = path.strip_prefix(SHARED_DIR)?.strip_prefix('/')?;
+        let mut names = rest.split('/');
+        let owner = names.next()?;
+        let root_name = names.next()?;
+        let mut folder = self
+            .shared_roots()
+            .into_iter()
+            .find(|(folder, _)| {
+                folder.user_id == owner && self.title_to_name(&folder.title) == root_name
+            })?
+            .0;
+
+        let mut names = names.peekable();
+        while let Some(name) = names.next() {
+            let title = Self::name_to_title(name);
+            match self
+                .db
+                .find_folder_by_title(Some(&folder.id), &title, owner)
+                .ok()?
+            {
+                Some(id) => folder = self.db.get_folder_by_id(&id, owner).ok()??,
+                None if names.peek().is_none() => {
+                    let access = self
+                        .db
+                        .get_folder_access(&folder.id, self.user_id.as_str())
+                        .ok()??;
+                    let (title, syntax, extensionless) = self.parse_note_name(name);
+                    let note = match self
+                        .db
+                        .find_note_by_title(Some(&folder.id), &title, &syntax, extensionless, owner)
+                        .ok()?
+                    {
+                        Some(id) => self
+                            .db
+                            .get_note_by_id(&id)
+                            .ok()?
+                            .filter(|note| note.user_id == owner)
+                            .map(Box::new),
+                        None => None,
+                    };
+                    return Some(SharedPath::Note {
+                        parent: folder,
+                        name: name.to_string(),
+                        note,
+                        access,
+                    });
+                }
+                None => return None,
+            }
+        }
+        let access = self
+            .db
+            .get_folder_access(&folder.id, self.user_id.as_str())
+            .ok()??;
+        Some(SharedPath::Folder(folder, access))
+    }
+
+    /// Attributes of a shared folder or note, without write bits where only
+    /// reading was granted; ENOENT for a name with no note yet
+    pub(super) fn shared_attr(&self, ino: u64, shared: &SharedPath) -> Result<FileAttr, i32> {
+        match shared {
+            SharedPath::Folder(folder, access) => Ok(FileAttr {
+                ino,
+                size: 0,
+                blocks: 0,
+                atime: Self::datetime_to_systemtime(
+                    &folder.accessed_at.unwrap_or(folder.updated_at),
+                ),
+                mtime: Self::datetime_to_systemtime(&folder.updated_at),
+                ctime: Self::datetime_to_systemtime(&folder.updated_at),
+                crtime: Self::datetime_to_systemtime(&folder.created_at),
+                kind: FileType::Directory,
+                perm: granted_perm(self.folder_perm(folder), *access),
+                nlink: 2,
+                uid: self.options.uid,
+                gid: self.options.gid,
+                rdev: 0,
+                flags: folder.flags.unwrap_or(0),
+                blksize: 512,
+            }),
+            SharedPath::Note {
+                note: Some(note),
+                access,
+                ..
+            } => Ok(self.shared_note_attr(ino, note, *access)),
+            SharedPath::Note { note: None, .. } => Err(libc::ENOENT),
+        }
+    }
+
+    /// Attributes of a note in a shared folder
+    pub(super) fn shared_note_attr(&self, ino: u64, note: &Note, access: Access) -> FileAttr {
+        let size = self.note_size(ino, note) as u64;
+        let perm = note
+            .mode
+            .map_or(self.options.file_mode, |mode| (mode & 0o7777) as u16);
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: Self::datetime_to_systemtime(&note.accessed_at.unwrap_or(note.updated_at)),
+            mtime: Self::datetime_to_systemtime(&note.updated_at),
+            ctime: Self::datetime_to_systemtime(&note.updated_at),
+            crtime: Self::datetime_to_systemtime(&note.created_at),
+            kind: FileType::RegularFile,
+            perm: granted_perm(perm, access),
+            nlink: 1,
+            uid: self.options.uid,
+            gid: self.options.gid,
+            rdev: 0,
+            flags: note.flags.unwrap_or(0),
+            blksize: 512,
+        }
+    }
+
+    /// Folders and notes in the shared `folder` at `path`, as readdir entries
+    pub(super) fn shared_dir_entries(
+        &mut self,
+        path: &str,
+        folder: &Folder,
+    ) -> Result<Vec<(u64, FileType, String)>, i32> {
+        let children = self
+            .db
+            .list_children(Some(&folder.id), &folder.user_id, false, Page::ALL)
+            .map_err(|e| {
+                error!("readdir: Unable to get children of {path}: {e}");
+                Self::errno_for(&e, libc::EIO)
+            })?;
+        Ok(children
+            .iter()
+            .map(|child| {
+                let (name, kind) = self.child_entry_name(child);
+                (
+                    self.get_or_create_inode(&format!("{path}/{name}")),
+                    kind,
+                    name,
+                )
+            })
+            .collect())
+    }
+
+    /// create in a shared folder: a new empty note of the folder's owner
+    pub(super) fn create_shared_note(&mut self, shared: &SharedPath) -> Result<Note, i32> {
+        let SharedPath::Note {
+            parent,
+            name,
+            note: None,
+            ..
+        } = shared
+        else {
+            return Err(libc::EEXIST);
+        };
+        shared.check_writable()?;
+        let (title, syntax, extensionless) = self.parse_note_name(name);
+        let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+        let content = NoteContent {
+            title: &title,
+            abstract_text: None,
+            content: "",
+            syntax: &syntax,
+            extensionless,
+        };
+        let created = self
+            .db
+            .create_note(&id, content, Some(&parent.id), &parent.user_id)
+            .and_then(|_| self.db.get_note_by_id(&id));
+        match created {
+            Ok(Some(note)) => Ok(note),
+            Ok(None) => Err(libc::EIO),
+            Err(e) => {
+                error!("create: Unable to create {name} in shared folder: {e}");
+                Err(Self::errno_for(&e, libc::EIO))
+            }
+        }
+    }
+
+    /// mkdir in a shared folder: a new folder of the folder's owner
+    pub(super) fn create_shared_folder(&mut self, shared: &SharedPath) -> Result<Folder, i32> {
+        let SharedPath::Note {
+            parent,
+            name,
+            note: None,
+            ..
+        } = shared
+        else {
+            return Err(libc::EEXIST);
+        };
+        shared.check_writable()?;
+        let title = Self::name_to_title(name);
+        let created = self
+            .db
+            .create_folder(&title, Some(&parent.id), &parent.user_id)
+            .and_then(|id| self.db.get_folder_by_id(&id, &parent.user_id));
+        match created {
+            Ok(Some(folder)) => Ok(folder),
+            Ok(None) => Err(libc::EIO),
+            Err(e) => {
+                error!("mkdir: Unable to create {name} in shared folder: {e}");
+                Err(Self::errno_for(&e, libc::EIO))
+            }
+        }
+    }
+
+    /// setattr on a shared folder or note: notes can be truncated where
+    /// write access was granted; ownership and modes stay the owner's
+    pub(super) fn setattr_shared(
+        &mut self,
+        ino: u64,
+        path: &str,
+        shared: SharedPath,
+        size: Option<u64>,
+        ownership: (Option<u32>, Option<u32>, Option<u32>),
+    ) -> Result<FileAttr, i32> {
+        // Setting what is already there (as cp -p and mv do) is allowed
+        let attr = self.shared_attr(ino, &shared)?;
+        let (mode, uid, gid) = ownership;
+        if mode.is_some_and(|mode| mode & 0o7777 != u32::from(attr.perm))
+            || uid.is_some_and(|uid| uid != attr.uid)
+            || gid.is_some_and(|gid| gid != attr.gid)
+        {
+            return Err(libc::EPERM);
+        }
+        let (note, access) = match shared {
+            SharedPath::Note {
+                note: Some(note),
+                access,
+                ..
+            } => (note, access),
+            _ => return Ok(attr),
+        };
+        let Some(new_size) = size else {
+            return Ok(attr);
+        };
+        if matches!(access, Access::Read) {
+            return Err(libc::EACCES);
+        }
+        self.commit_pending(ino)?;
+        let note = match self.db.get_note_by_id(&note.id) {
+            Ok(Some(note)) => note,
+            Ok(None) => return Err(libc::ENOENT),
+            Err(e) => {
+                error!("setattr: Database error retrieving note {}: {e}", note.id);
+                return Err(libc::EIO);
+            }
+        };
+        let mut bytes = self.note_bytes(ino, &note);
+        bytes.resize(new_size as usize, 0);
+        match self.save_note_bytes(ino, path, &note, bytes) {
+            Ok(true) => {}
+            Ok(false) => return Err(libc::ESTALE),
+            Err(e) => {
+                error!("setattr: Unable to truncate shared note {}: {e}", note.id);
+                return Err(libc::EIO);
+            }
+        }
+        match self.db.get_note_by_id(&note.id) {
+            Ok(Some(note)) => Ok(self.shared_note_attr(ino, &note, access)),
+            Ok(None) => Err(libc::ENOENT),
+            Err(e) => {
+                error!("setattr: Database error retrieving note {}: {e}", note.id);
+                Err(libc::EIO)
+            }
+        }
+    }
+
+    /// unlink in a shared folder
+    pub(super) fn remove_shared_note(&mut self, shared: &SharedPath) -> Result<(), i32> {
+        let SharedPath::Note {
+            note: Some(note), ..
+        } = shared
+        else {
+            return Err(match shared {
+                SharedPath::Folder(..) => libc::EISDIR,
+                SharedPath::Note { .. } => libc::ENOENT,
+            });
+        };
+        shared.check_writable()?;
+        match self.db.delete_note(&note.id) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("unlink: Unable to delete shared note {}: {e}", note.id);
+                Err(Self::errno_for(&e, libc::EIO))
+            }
+        }
+    }
+
+    /// rmdir in a shared folder; the shared folders themselves stay, and
+    /// only empty folders can be removed
+    pub(super) fn remove_shared_folder(&mut self, shared: &SharedPath) -> Result<(), i32> {
+        let SharedPath::Folder(folder, _) = shared else {
+            return Err(libc::ENOTDIR);
+        };
+        shared.check_writable()?;
+        let to_errno = |e: rusqlite::Error| {
+            error!("rmdir: Unable to delete shared folder {}: {e}", folder.id);
+            Self::errno_for(&e, libc::EIO)
+        };
+        let roots = self.shared_roots();
+        if roots.iter().any(|(root, _)| root.id == folder.id) {
+            return Err(libc::EACCES);
+        }
+        if self
+            .db
+            .has_children(Some(&folder.id), &folder.user_id)
+            .map_err(to_errno)?
+        {
+            return Err(libc::ENOTEMPTY);
+        }
+        self.db
+            .delete_folder(&folder.id, &folder.user_id)
+            .map_err(to_errno)?;
+        Ok(())
+    }
+}
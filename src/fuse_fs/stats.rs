@@ -0,0 +1,459 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fuser::{
+    PollHandle, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
+    ReplyIoctl, ReplyLock, ReplyOpen, ReplyPoll, ReplyWrite, ReplyXattr,
+};
+use rusqlite::trace::TraceEvent;
+
+use super::poll::PollWaiters;
+
+use super::ExampleFuseFs;
+
+/// Statements the database connection has run, and their total time in
+/// nanoseconds; SQLite's profile hook takes a plain function, hence statics
+static QUERIES: AtomicU64 = AtomicU64::new(0);
+static QUERY_NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// Profile hook for `Connection::trace_v2`, counting every finished statement
+pub(super) fn record_query(event: TraceEvent<'_>) {
+    if let TraceEvent::Profile(_, elapsed) = event {
+        QUERIES.fetch_add(1, Ordering::Relaxed);
+        QUERY_NANOS.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Upper bounds of the latency histogram buckets; the last bucket has none
+const LATENCY_BOUNDS: [(Duration, &str); 5] = [
+    (Duration::from_micros(100), "<0.1ms"),
+    (Duration::from_millis(1), "<1ms"),
+    (Duration::from_millis(10), "<10ms"),
+    (Duration::from_millis(100), "<100ms"),
+    (Duration::from_secs(1), "<1s"),
+];
+
+#[derive(Default)]
+struct OpCounts {
+    calls: u64,
+    errors: u64,
+    /// Time spent in the handler, in total, at most, and by histogram bucket
+    total: Duration,
+    max: Duration,
+    buckets: [u64; LATENCY_BOUNDS.len() + 1],
+}
+
+impl OpCounts {
+    fn record(&mut self, elapsed: Duration) {
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+        let bucket = LATENCY_BOUNDS
+            .iter()
+            .position(|&(bound, _)| elapsed < bound)
+            .unwrap_or(LATENCY_BOUNDS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    /// Mean time spent in the handler by the calls that have finished
+    fn mean(&self) -> Duration {
+        match u32::try_from(self.buckets.iter().sum::<u64>()) {
+            Ok(0) | Err(_) => Duration::ZERO,
+            Ok(calls) => self.total / calls,
+        }
+    }
+}
+
+/// `duration` in milliseconds
+fn ms(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// A file handle as of the start of the current operation
+pub(super) struct HandleState {
+    pub fh: u64,
+    pub path: String,
+    /// Size of the writes collected on the handle and how long ago the first
+    /// arrived, if any are waiting to be stored
+    pub pending: Option<(usize, Duration)>,
+}
+
+/// Filesystem state as of the start of the current operation, for the SIGUSR1 dump
+#[derive(Default)]
+pub(super) struct State {
+    pub inodes: usize,
+    pub handles: Vec<HandleState>,
+    /// Front matter, sidecar and attachment buffers not yet stored
+    pub buffers: usize,
+}
+
+struct Counters {
+    started: Instant,
+    ops: BTreeMap<&'static str, OpCounts>,
+    /// Operation being served, which errors are counted against, when it
+    /// began, and the errno it replied with
+    current: &'static str,
+    current_since: Instant,
+    current_errno: Option<i32>,
+    state: State,
+    inode_hits: u64,
+    inode_misses: u64,
+    /// Mappings dropped to stay within --max-inodes
+    inode_evictions: u64,
+    cache_kept: u64,
+    cache_dropped: u64,
+    /// Operations finished since mount
+    finished: u64,
+    /// Polls of `/.stats` waiting for an operation to finish
+    pollers: PollWaiters,
+}
+
+/// Counters since mount, reported by `/.stats` and dumped on SIGUSR1
+///
+/// Shared with the thread answering SIGUSR1, so a dump works while an
+/// operation is stuck.
+#[derive(Clone)]
+pub(super) struct Stats {
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            counters: Arc::new(Mutex::new(Counters {
+                started: now,
+                ops: BTreeMap::new(),
+                current: "",
+                current_since: now,
+                current_errno: None,
+                state: State::default(),
+                inode_hits: 0,
+                inode_misses: 0,
+                inode_evictions: 0,
+                cache_kept: 0,
+                cache_dropped: 0,
+                finished: 0,
+                pollers: PollWaiters::default(),
+            })),
+        }
+    }
+}
+
+/// `part` as a percentage of `part + rest`
+fn rate(part: u64, rest: u64) -> f64 {
+    match part + rest {
+        0 => 0.0,
+        total => part as f64 * 100.0 / total as f64,
+    }
+}
+
+impl Stats {
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.counters.lock().unwrap()
+    }
+
+    /// Count a call of the operation `op`, which the handler is now serving,
+    /// with the filesystem in `state`; it is timed until the returned guard is dropped
+    pub(super) fn begin(&self, op: &'static str, state: State) -> OpTimer {
+        let mut counters = self.counters();
+        counters.current = op;
+        counters.current_since = Instant::now();
+        counters.current_errno = None;
+        counters.state = state;
+        counters.ops.entry(op).or_default().calls += 1;
+        OpTimer {
+            stats: self.clone(),
+        }
+    }
+
+    /// Count an error reply of the current operation
+    pub(super) fn error(&self, errno: i32) {
+        let mut counters = self.counters();
+        let op = counters.current;
+        counters.current_errno = Some(errno);
+        counters.ops.entry(op).or_default().errors += 1;
+    }
+
+    /// Record how long the current operation took, and log it with its outcome
+    fn finish(&self) {
+        let mut counters = self.counters();
+        let (op, elapsed) = (counters.current, counters.current_since.elapsed());
+        let outcome = match counters.current_errno {
+            Some(errno) => std::io::Error::from_raw_os_error(errno).to_string(),
+            None => "ok".to_string(),
+        };
+        counters.ops.entry(op).or_default().record(elapsed);
+        counters.finished += 1;
+        let finished = counters.finished;
+        counters.pollers.wake(finished);
+        drop(counters);
+        debug!("{op}: Finished in {} ({outcome})", ms(elapsed));
+    }
+
+    /// Operations finished since mount
+    pub(super) fn finished(&self) -> u64 {
+        self.counters().finished
+    }
+
+    /// Notify `handle` once more than `seen` operations have finished
+    pub(super) fn wake_after(&self, seen: u64, handle: PollHandle) {
+        self.counters().pollers.add(seen, handle);
+    }
+
+    /// Count a path looked up in the inode map (`hit` if it already had an inode)
+    pub(super) fn inode_lookup(&self, hit: bool) {
+        let mut counters = self.counters();
+        if hit {
+            counters.inode_hits += 1;
+        } else {
+            counters.inode_misses += 1;
+        }
+    }
+
+    /// Count inode mappings evicted to stay within `--max-inodes`
+    pub(super) fn inodes_evicted(&self, count: usize) {
+        self.counters().inode_evictions += count as u64;
+    }
+
+    /// Count an `--auto-cache` open that kept (or dropped) the kernel's cached pages
+    pub(super) fn page_cache(&self, kept: bool) {
+        let mut counters = self.counters();
+        if kept {
+            counters.cache_kept += 1;
+        } else {
+            counters.cache_dropped += 1;
+        }
+    }
+
+    /// The text of `/.stats`
+    pub(super) fn render(&self) -> String {
+        self.counters().render()
+    }
+
+    /// Histogram of the time each operation took, for `--latency-summary`
+    pub(super) fn latency_summary(&self) -> String {
+        let counters = self.counters();
+        let mut out = format!("{:<14}", "operation");
+        for (_, label) in LATENCY_BOUNDS {
+            let _ = write!(out, "{label:>9}");
+        }
+        let _ = writeln!(out, "{:>9}{:>12}", ">=1s", "max");
+        for (op, counts) in &counters.ops {
+            let _ = write!(out, "{op:<14}");
+            for count in counts.buckets {
+                let _ = write!(out, "{count:>9}");
+            }
+            let _ = writeln!(out, "{:>12}", ms(counts.max));
+        }
+        out
+    }
+
+    /// Print the counters and the state of the filesystem to stderr whenever
+    /// the process gets SIGUSR1 (`kill -USR1 <pid>`), e.g. to see what a hung
+    /// mount is doing, whether or not debug logging is on
+    ///
+    /// Must run before any other thread is started: SIGUSR1 is blocked here and
+    /// in every thread started later, and a thread of its own waits for it.
+    pub(super) fn dump_on_sigusr1(&self) {
+        // SAFETY: sigset_t is plain data, initialized by sigemptyset
+        let signals = unsafe {
+            let mut signals: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut signals);
+            libc::sigaddset(&mut signals, libc::SIGUSR1);
+            libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+            signals
+        };
+        let stats = self.clone();
+        let spawned = thread::Builder::new()
+            .name("sigusr1-dump".to_string())
+            .spawn(move || {
+                loop {
+                    let mut signal = 0;
+                    // SAFETY: both pointers are to live locals
+                    if unsafe { libc::sigwait(&signals, &mut signal) } != 0 {
+                        error!("sigusr1: Unable to wait for SIGUSR1, dumps disabled");
+                        return;
+                    }
+                    eprintln!("sigusr1: State dump\n{}", stats.counters().dump());
+                }
+            });
+        if let Err(e) = spawned {
+            error!("sigusr1: Unable to start dump thread: {e}");
+        }
+    }
+}
+
+/// Times an operation from `Stats::begin` until dropped
+pub(super) struct OpTimer {
+    stats: Stats,
+}
+
+impl Drop for OpTimer {
+    fn drop(&mut self) {
+        self.stats.finish();
+    }
+}
+
+impl Counters {
+    fn render(&self) -> String {
+        let queries = QUERIES.load(Ordering::Relaxed);
+        let query_time = Duration::from_nanos(QUERY_NANOS.load(Ordering::Relaxed));
+        let mut out = String::new();
+        let _ = writeln!(out, "uptime        {}s", self.started.elapsed().as_secs());
+        let _ = writeln!(
+            out,
+            "db queries    {queries} ({:.3}s)",
+            query_time.as_secs_f64()
+        );
+        let _ = writeln!(
+            out,
+            "inode cache   {} hits, {} misses ({:.1}% hits), {} evicted",
+            self.inode_hits,
+            self.inode_misses,
+            rate(self.inode_hits, self.inode_misses),
+            self.inode_evictions
+        );
+        let _ = writeln!(
+            out,
+            "page cache    {} kept, {} dropped ({:.1}% kept)",
+            self.cache_kept,
+            self.cache_dropped,
+            rate(self.cache_kept, self.cache_dropped)
+        );
+        let _ = writeln!(out);
+        let _ = writeln!(
+            out,
+            "{:<14}{:>10}{:>10}{:>12}{:>12}",
+            "operation", "calls", "errors", "mean", "max"
+        );
+        for (op, counts) in &self.ops {
+            let _ = writeln!(
+                out,
+                "{op:<14}{:>10}{:>10}{:>12}{:>12}",
+                counts.calls,
+                counts.errors,
+                ms(counts.mean()),
+                ms(counts.max)
+            );
+        }
+        out
+    }
+
+    /// `render` followed by what the filesystem is doing
+    fn dump(&self) -> String {
+        let mut out = self.render();
+        let _ = writeln!(out);
+        if !self.current.is_empty() {
+            let _ = writeln!(
+                out,
+                "last operation {} (started {:.3}s ago)",
+                self.current,
+                self.current_since.elapsed().as_secs_f64()
+            );
+        }
+        let state = &self.state;
+        let _ = writeln!(out, "inode map     {} entries", state.inodes);
+        let _ = writeln!(out, "buffers       {} unparsed", state.buffers);
+        let _ = writeln!(out, "open handles  {}", state.handles.len());
+        for handle in &state.handles {
+            let _ = match handle.pending {
+                Some((len, age)) => writeln!(
+                    out,
+                    "  fh={} {} ({len} bytes pending for {:.3}s)",
+                    handle.fh,
+                    handle.path,
+                    age.as_secs_f64()
+                ),
+                None => writeln!(out, "  fh={} {}", handle.fh, handle.path),
+            };
+        }
+        out
+    }
+}
+
+impl ExampleFuseFs {
+    /// Count the start of the operation `op`, noting the state it starts in;
+    /// it is timed until the returned guard is dropped. Every operation
+    /// starts here, so this is also where a replaced database is reopened
+    /// and changes made by other processes are noticed.
+    pub(super) fn begin_op(&mut self, op: &'static str) -> OpTimer {
+        self.check_database_file();
+        self.check_external_commits();
+        let state = State {
+            inodes: self.inode_map.len(),
+            handles: self.handle_states(),
+            buffers: self.front_matter_buffers.len()
+                + self.sidecar_buffers.len()
+                + self.asset_buffers.len(),
+        };
+        self.stats.begin(op, state)
+    }
+}
+
+/// fuser replies that can fail with an errno, so errors can be counted in one place
+pub(super) trait ErrorReply {
+    fn error(self, errno: i32);
+}
+
+macro_rules! error_reply {
+    ($($reply:ty),*) => {
+        $(impl ErrorReply for $reply {
+            fn error(self, errno: i32) {
+                <$reply>::error(self, errno)
+            }
+        })*
+    };
+}
+
+error_reply!(
+    ReplyAttr,
+    ReplyCreate,
+    ReplyData,
+    ReplyDirectory,
+    ReplyEmpty,
+    ReplyEntry,
+    ReplyIoctl,
+    ReplyLock,
+    ReplyOpen,
+    ReplyPoll,
+    ReplyWrite,
+    ReplyXattr
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counts_errors_against_current_op() {
+        let stats = Stats::default();
+        stats.begin("lookup", State::default());
+        stats.error(libc::ENOENT);
+        stats.begin("lookup", State::default());
+        stats.begin("read", State::default());
+        stats.inode_lookup(true);
+        stats.inode_lookup(true);
+        stats.inode_lookup(true);
+        stats.inode_lookup(false);
+
+        let text = stats.render();
+        assert!(text.contains("inode cache   3 hits, 1 misses (75.0% hits)"));
+        assert!(text.contains(&format!("{:<14}{:>10}{:>10}", "lookup", 2, 1)));
+        assert!(text.contains(&format!("{:<14}{:>10}{:>10}", "read", 1, 0)));
+    }
+
+    #[test]
+    fn test_latency_buckets() {
+        let mut counts = OpCounts::default();
+        counts.record(Duration::from_micros(50));
+        counts.record(Duration::from_millis(1));
+        counts.record(Duration::from_secs(2));
+        assert_eq!(counts.buckets, [1, 0, 1, 0, 0, 1]);
+        assert_eq!(counts.max, Duration::from_secs(2));
+        assert_eq!(counts.mean(), Duration::from_micros(2_001_050) / 3);
+    }
+}
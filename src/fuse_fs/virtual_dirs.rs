@@ -0,0 +1,503 @@
+use std::collections::{BTreeSet, HashSet};
+use std::time::UNIX_EPOCH;
+
+use fuser::{FileAttr, FileType};
+
+use super::ExampleFuseFs;
+use super::export::ExportPath;
+use super::shared::SHARED_DIR;
+use crate::database::{Database, Note};
+
+mod journal;
+mod note_links;
+
+use journal::JOURNAL_DIR;
+
+/// `/.backlinks/<title>/` lists the notes with a `[[wikilink]]` to that title
+pub(super) const BACKLINKS_DIR: &str = "/.backlinks";
+
+/// `/.by-id/<id>` resolves any note or folder by its database id
+pub(super) const BY_ID_DIR: &str = "/.by-id";
+
+/// `/lost+found/` lists the notes whose folder is gone, to be moved out
+pub(super) const LOST_FOUND_DIR: &str = "/lost+found";
+
+/// `/.recent/` lists the most recently updated notes
+pub(super) const RECENT_DIR: &str = "/.recent";
+
+/// `/.searches/<name>/` lists the current matches of each saved search
+pub(super) const SEARCHES_DIR: &str = "/.searches";
+
+/// A note listed in a virtual directory under a name of its own
+struct AliasEntry {
+    /// Subdirectory it is listed in ("" when listed directly)
+    group: String,
+    /// Name shown in the listing, suffixed with " (n)" if taken in the same directory
+    name: String,
+    /// Real path of the note
+    path: String,
+}
+
+/// What a path inside one of the virtual directories refers to
+pub(super) enum VirtualPath {
+    /// A directory that exists only in the mount
+    Dir,
+    /// Another name for the real note or folder at this path
+    Alias(String),
+    /// A symlink to this target, relative to the symlink's directory
+    Symlink(String),
+}
+
+/// The part of `path` below the virtual directory `dir`, if it is inside it
+fn below<'a>(path: &'a str, dir: &str) -> Option<&'a str> {
+    path.strip_prefix(dir)?.strip_prefix('/')
+}
+
+/// The name of a note listed in `/lost+found` at `path`, if it is one
+pub(super) fn lost_found_name(path: &str) -> Option<&str> {
+    below(path, LOST_FOUND_DIR).filter(|name| !name.contains('/'))
+}
+
+/// Resolve `rest` (a name, or "group/name" when `grouped`) against listed entries
+fn resolve_entry(entries: Vec<AliasEntry>, rest: &str, grouped: bool) -> Option<VirtualPath> {
+    let (group, name) = match rest.split_once('/') {
+        Some((group, name)) if grouped => (group, name),
+        None if grouped => {
+            return entries
+                .iter()
+                .any(|e| e.group == rest)
+                .then_some(VirtualPath::Dir);
+        }
+        Some(_) => return None,
+        None => ("", rest),
+    };
+    entries
+        .into_iter()
+        .find(|e| e.group == group && e.name == name)
+        .map(|e| VirtualPath::Alias(e.path))
+}
+
+/// Decode %XX escapes, so a saved search directory name can hold any query
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+impl ExampleFuseFs {
+    /// Classify `path` if it lies in a virtual directory
+    ///
+    /// Aliases are looked up as their real path, so they share the real inode
+    /// and every other operation works on them unchanged.
+    pub(super) fn resolve_virtual(&self, path: &str) -> Option<VirtualPath> {
+        if path == BACKLINKS_DIR
+            || path == BY_ID_DIR
+            || path == LOST_FOUND_DIR
+            || path == RECENT_DIR
+            || path == SEARCHES_DIR
+        {
+            return Some(VirtualPath::Dir);
+        }
+        if self.is_shared_dir(path) {
+            return Some(VirtualPath::Dir);
+        }
+        if let Some(ExportPath::Dir) = self.export_path(path) {
+            return Some(VirtualPath::Dir);
+        }
+        if let Some(id) = below(path, BY_ID_DIR) {
+            return self.path_for_id(id).map(VirtualPath::Alias);
+        }
+        if let Some(rest) = below(path, RECENT_DIR) {
+            return resolve_entry(self.recent_entries(), rest, self.options.recent_by_day);
+        }
+        if let Some(rest) = below(path, BACKLINKS_DIR) {
+            let (target, rest) = match rest.split_once('/') {
+                Some((target, rest)) => (target, Some(rest)),
+                None => (rest, None),
+            };
+            let entries = self.backlink_entries(target)?;
+            return match rest {
+                None => Some(VirtualPath::Dir),
+                Some(rest) => resolve_entry(entries, rest, false),
+            };
+        }
+        if self.options.journal {
+            if path == JOURNAL_DIR {
+                return Some(VirtualPath::Dir);
+            }
+            if let Some(rest) = below(path, JOURNAL_DIR) {
+                return self.resolve_journal(rest);
+            }
+        }
+        if let Some(resolved) = self.resolve_note_links(path) {
+            return Some(resolved);
+        }
+        let rest = below(path, SEARCHES_DIR)?;
+        let (search, rest) = match rest.split_once('/') {
+            Some((search, rest)) => (search, Some(rest)),
+            None => (rest, None),
+        };
+        let entries = self.search_entries(search)?;
+        match rest {
+            None => Some(VirtualPath::Dir),
+            Some(rest) => resolve_entry(entries, rest, false),
+        }
+    }
+
+    /// True for the virtual directories themselves, whose contents can't be changed
+    pub(super) fn is_virtual_dir(&self, path: &str) -> bool {
+        matches!(self.resolve_virtual(path), Some(VirtualPath::Dir))
+    }
+
+    /// The file name of `note`, suffixed with " (n)" if already `taken` in `group`
+    fn free_name(&self, taken: &mut HashSet<(String, String)>, group: &str, note: &Note) -> String {
+        // Notes from different folders may share a name
+        let mut name = self.note_file_name(&note.title, &note.syntax, note.extensionless);
+        let mut n = 1;
+        while !taken.insert((group.to_string(), name.clone())) {
+            let title = format!("{} ({n})", note.title);
+            name = self.note_file_name(&title, &note.syntax, note.extensionless);
+            n += 1;
+        }
+        name
+    }
+
+    /// Give each (group, note) a name that is unique within its group
+    fn alias_entries(&self, notes: Vec<(String, Note)>) -> Vec<AliasEntry> {
+        let mut taken = HashSet::new();
+        let mut entries = Vec::new();
+        for (group, note) in notes {
+            if !self.note_shown(&note.id, &note.syntax) {
+                continue;
+            }
+            // Notes outside --subdir aren't listed
+            let Some(path) = self
+                .db
+                .get_note_path_by_id(&note.id)
+                .ok()
+                .flatten()
+                .and_then(|path| self.fuse_path(&path))
+            else {
+                continue;
+            };
+
+            let name = self.free_name(&mut taken, &group, &note);
+            entries.push(AliasEntry { group, name, path });
+        }
+        entries
+    }
+
+    /// (name, id) of the notes shown in `/lost+found`. No real path leads to
+    /// them, so unlike aliases their inodes are those of these names, and
+    /// `note_id_by_path` resolves them through here.
+    fn orphans(&self) -> Vec<(String, String)> {
+        let notes = match self.db.list_orphaned_notes(self.user_id.as_str()) {
+            Ok(notes) => notes,
+            Err(e) => {
+                error!("lost+found: Unable to list orphaned notes: {e}");
+                return Vec::new();
+            }
+        };
+        let mut taken = HashSet::new();
+        notes
+            .into_iter()
+            .filter(|note| self.note_shown(&note.id, &note.syntax))
+            .map(|note| (self.free_name(&mut taken, "", &note), note.id))
+            .collect()
+    }
+
+    /// Id of the note listed as `name` in `/lost+found`
+    pub(super) fn orphan_id(&self, name: &str) -> Option<String> {
+        self.orphans()
+            .into_iter()
+            .find(|(orphan, _)| orphan == name)
+            .map(|(_, id)| id)
+    }
+
+    /// Whether `/lost+found` is listed in the root: only while it holds notes
+    fn has_orphans(&self) -> bool {
+        match self.db.has_orphaned_notes(self.user_id.as_str()) {
+            Ok(found) => found,
+            Err(e) => {
+                error!("lost+found: Unable to look for orphaned notes: {e}");
+                false
+            }
+        }
+    }
+
+    /// The notes shown in `/.recent`, newest first, grouped by day if enabled
+    fn recent_entries(&self) -> Vec<AliasEntry> {
+        let notes = match self
+            .db
+            .list_recent_notes(self.user_id.as_str(), self.options.recent_limit)
+        {
+            Ok(notes) => notes,
+            Err(e) => {
+                error!("recent: Unable to list recent notes: {e}");
+                return Vec::new();
+            }
+        };
+        let by_day = self.options.recent_by_day;
+        let timezone = self.db.timezone_for(self.user_id.as_str());
+        self.alias_entries(
+            notes
+                .into_iter()
+                .map(|note| {
+                    let day = if by_day {
+                        let updated_at = note.updated_at.with_timezone(&timezone);
+                        updated_at.format("%Y-%m-%d").to_string()
+                    } else {
+                        String::new()
+                    };
+                    (day, note)
+                })
+                .collect(),
+        )
+    }
+
+    /// Current matches of the saved search `name`, or `None` if there is no such search
+    fn search_entries(&self, name: &str) -> Option<Vec<AliasEntry>> {
+        let search = self
+            .db
+            .get_saved_search(name, self.user_id.as_str())
+            .ok()??;
+        let mut notes = match self.db.search_notes(&search.query, self.user_id.as_str()) {
+            Ok(notes) => notes,
+            Err(e) => {
+                error!("searches: Query {:?} failed: {e}", search.query);
+                Vec::new()
+            }
+        };
+        if self.options.ranked_searches {
+            // Numbered after hiding, so the numbers have no gaps
+            notes.retain(|note| self.note_shown(&note.id, &note.syntax));
+            let width = notes.len().to_string().len().max(2);
+            for (rank, note) in notes.iter_mut().enumerate() {
+                note.title = format!("{:0width$} {}", rank + 1, note.title);
+            }
+        }
+        Some(self.alias_entries(notes.into_iter().map(|n| (String::new(), n)).collect()))
+    }
+
+    /// Notes linking to the title listed as `name` in `/.backlinks`, or
+    /// `None` if no note has that title
+    fn backlink_entries(&self, name: &str) -> Option<Vec<AliasEntry>> {
+        let title = Self::name_to_title(name);
+        if !self
+            .db
+            .note_title_exists(&title, self.user_id.as_str())
+            .ok()?
+        {
+            return None;
+        }
+        let notes = match self.db.list_backlinks(&title, self.user_id.as_str()) {
+            Ok(notes) => notes,
+            Err(e) => {
+                error!("backlinks: Unable to list links to {title:?}: {e}");
+                Vec::new()
+            }
+        };
+        Some(self.alias_entries(notes.into_iter().map(|n| (String::new(), n)).collect()))
+    }
+
+    /// Current path of the note or folder with database id `id`
+    fn path_for_id(&self, id: &str) -> Option<String> {
+        if let Ok(Some(path)) = self.db.get_folder_path_by_id(id, self.user_id.as_str()) {
+            return self.fuse_path(&path);
+        }
+        match self.db.get_note_by_id(id) {
+            Ok(Some(note)) if note.user_id == self.user_id => {
+                let path = self.db.get_note_path_by_id(id).ok()??;
+                self.fuse_path(&path)
+            }
+            _ => None,
+        }
+    }
+
+    /// Entries of the virtual directory at `path`, excluding "." and ".."
+    pub(super) fn virtual_dir_entries(&mut self, path: &str) -> Vec<(u64, FileType, String)> {
+        if let Some(listing) = self.note_links_listing(path) {
+            return listing;
+        }
+        let (subdirs, entries) = if path == RECENT_DIR && self.options.recent_by_day {
+            let days: BTreeSet<String> =
+                self.recent_entries().into_iter().map(|e| e.group).collect();
+            (days.into_iter().rev().collect(), Vec::new())
+        } else if path == RECENT_DIR {
+            (Vec::new(), self.recent_entries())
+        } else if let Some(day) = below(path, RECENT_DIR) {
+            let mut entries = self.recent_entries();
+            entries.retain(|e| e.group == day);
+            (Vec::new(), entries)
+        } else if path == SEARCHES_DIR {
+            match self.db.list_saved_searches(self.user_id.as_str()) {
+                Ok(searches) => (searches.into_iter().map(|s| s.name).collect(), Vec::new()),
+                Err(e) => {
+                    error!("searches: Unable to list saved searches: {e}");
+                    (Vec::new(), Vec::new())
+                }
+            }
+        } else if let Some(search) = below(path, SEARCHES_DIR) {
+            (Vec::new(), self.search_entries(search).unwrap_or_default())
+        } else if path == BACKLINKS_DIR {
+            match self.db.list_linked_titles(self.user_id.as_str()) {
+                Ok(titles) => (
+                    titles.iter().map(|t| self.title_to_name(t)).collect(),
+                    Vec::new(),
+                ),
+                Err(e) => {
+                    error!("backlinks: Unable to list linked notes: {e}");
+                    (Vec::new(), Vec::new())
+                }
+            }
+        } else if let Some(target) = below(path, BACKLINKS_DIR) {
+            (
+                Vec::new(),
+                self.backlink_entries(target).unwrap_or_default(),
+            )
+        } else if path == LOST_FOUND_DIR {
+            let entries = self
+                .orphans()
+                .into_iter()
+                .map(|(name, _)| AliasEntry {
+                    group: String::new(),
+                    path: format!("{LOST_FOUND_DIR}/{name}"),
+                    name,
+                })
+                .collect();
+            (Vec::new(), entries)
+        } else if path == JOURNAL_DIR {
+            self.journal_listing("")
+        } else if let Some(rest) = below(path, JOURNAL_DIR) {
+            self.journal_listing(rest)
+        } else if self.is_shared_dir(path) {
+            (self.shared_dir_names(path), Vec::new())
+        } else if let Some((subdirs, files)) = self.export_listing(path) {
+            let entries = files
+                .into_iter()
+                .map(|name| AliasEntry {
+                    group: String::new(),
+                    path: format!("{path}/{name}"),
+                    name,
+                })
+                .collect();
+            (subdirs, entries)
+        } else {
+            // Every id would be an unbounded listing; ids resolve on lookup instead
+            (Vec::new(), Vec::new())
+        };
+
+        let mut listing = Vec::new();
+        for name in subdirs {
+            let ino = self.get_or_create_inode(&format!("{path}/{name}"));
+            listing.push((ino, FileType::Directory, name));
+        }
+        for entry in entries {
+            let ino = self.get_or_create_inode(&entry.path);
+            listing.push((ino, FileType::RegularFile, entry.name));
+        }
+        listing
+    }
+
+    /// Number of directories `root_virtual_entries` lists
+    pub(super) fn root_virtual_dir_count(&self) -> usize {
+        usize::from(self.options.journal)
+            + usize::from(self.options.shared)
+            + usize::from(self.has_orphans())
+    }
+
+    /// Virtual directories listed in the root alongside the real entries
+    pub(super) fn root_virtual_entries(&mut self) -> Vec<(u64, FileType, String)> {
+        let mut entries = Vec::new();
+        if self.options.journal {
+            let ino = self.get_or_create_inode(JOURNAL_DIR);
+            entries.push((ino, FileType::Directory, JOURNAL_DIR[1..].to_string()));
+        }
+        if self.options.shared {
+            let ino = self.get_or_create_inode(SHARED_DIR);
+            entries.push((ino, FileType::Directory, SHARED_DIR[1..].to_string()));
+        }
+        if self.has_orphans() {
+            let ino = self.get_or_create_inode(LOST_FOUND_DIR);
+            entries.push((ino, FileType::Directory, LOST_FOUND_DIR[1..].to_string()));
+        }
+        entries
+    }
+
+    /// mkdir in `/.searches`: save a search named `name` whose query is the
+    /// name with %XX escapes decoded (so "%2F" can stand for '/')
+    pub(super) fn create_saved_search_dir(&mut self, name: &str) -> Result<FileAttr, i32> {
+        let query = percent_decode(name);
+        if let Err(e) = self.db.search_notes(&query, self.user_id.as_str()) {
+            error!("mkdir: Invalid search query {query:?}: {e}");
+            return Err(libc::EINVAL);
+        }
+        match self
+            .db
+            .create_saved_search(name, &query, self.user_id.as_str())
+        {
+            Ok(_) => {
+                let ino = self.get_or_create_inode(&format!("{SEARCHES_DIR}/{name}"));
+                Ok(self.virtual_dir_attr(ino))
+            }
+            Err(e) if Database::is_unique_violation(&e) => Err(libc::EEXIST),
+            Err(e) => {
+                error!("mkdir: Unable to save search {name:?}: {e}");
+                Err(libc::EIO)
+            }
+        }
+    }
+
+    /// rmdir in `/.searches`: forget the saved search (its matches are untouched)
+    pub(super) fn remove_saved_search_dir(&mut self, name: &str) -> Result<(), i32> {
+        match self.db.delete_saved_search(name, self.user_id.as_str()) {
+            Ok(true) => {
+                self.remove_inode_mapping(&format!("{SEARCHES_DIR}/{name}"));
+                Ok(())
+            }
+            Ok(false) => Err(libc::ENOENT),
+            Err(e) => {
+                error!("rmdir: Unable to delete saved search {name:?}: {e}");
+                Err(libc::EIO)
+            }
+        }
+    }
+
+    /// Attributes of a virtual directory: the default directory mode, read-only
+    pub(super) fn virtual_dir_attr(&self, ino: u64) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::Directory,
+            perm: self.options.dir_mode & !0o222,
+            nlink: 2,
+            uid: self.options.uid,
+            gid: self.options.gid,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+}
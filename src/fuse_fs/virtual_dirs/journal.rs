@@ -0,0 +1,184 @@
+use std::collections::BTreeSet;
+
+use chrono::{NaiveDate, Utc};
+
+use super::{AliasEntry, VirtualPath};
+use crate::database::NoteContent;
+use crate::fuse_fs::ExampleFuseFs;
+
+/// `/journal/YYYY/MM/DD.md` lists the daily notes kept in the journal folder
+pub(super) const JOURNAL_DIR: &str = "/journal";
+
+/// Syntax of daily notes, whose titles are their date as YYYY-MM-DD
+const JOURNAL_SYNTAX: &str = "md";
+
+impl ExampleFuseFs {
+    /// Today's date in the database timezone
+    fn journal_today(&self) -> NaiveDate {
+        Utc::now()
+            .with_timezone(&self.db.timezone_for(self.user_id.as_str()))
+            .date_naive()
+    }
+
+    /// Id of the folder the daily notes are stored in, creating each missing
+    /// component of `--journal-folder` when `create` is set
+    fn journal_folder_id(&self, create: bool) -> rusqlite::Result<Option<String>> {
+        let mut folder_id = self.root_folder_id();
+        for title in self.options.journal_folder.split('/') {
+            if title.is_empty() {
+                continue;
+            }
+            let found =
+                self.db
+                    .find_folder_by_title(folder_id.as_deref(), title, self.user_id.as_str())?;
+            folder_id = match found {
+                Some(id) => Some(id),
+                None if create => Some(self.db.create_folder(
+                    title,
+                    folder_id.as_deref(),
+                    self.user_id.as_str(),
+                )?),
+                None => return Ok(None),
+            };
+        }
+        Ok(folder_id)
+    }
+
+    /// Daily notes grouped by "YYYY/MM" and named "DD.md", oldest first
+    fn journal_entries(&self) -> Vec<AliasEntry> {
+        let notes = match self
+            .journal_folder_id(false)
+            .and_then(|folder_id| match folder_id {
+                Some(id) => self
+                    .db
+                    .list_note_meta_by_parent(Some(&id), self.user_id.as_str()),
+                None => Ok(Vec::new()),
+            }) {
+            Ok(notes) => notes,
+            Err(e) => {
+                error!("journal: Unable to list daily notes: {e}");
+                return Vec::new();
+            }
+        };
+
+        let extension = self.syntax_map.extension_for_syntax(JOURNAL_SYNTAX);
+        notes
+            .into_iter()
+            .filter(|note| note.syntax == JOURNAL_SYNTAX && !note.extensionless)
+            .filter_map(|note| {
+                let date = NaiveDate::parse_from_str(&note.title, "%Y-%m-%d").ok()?;
+                // Only the zero-padded spelling, so no day has two entries
+                if date.format("%Y-%m-%d").to_string() != note.title {
+                    return None;
+                }
+                let path = self.db.get_note_path_by_id(&note.id).ok()??;
+                Some(AliasEntry {
+                    group: date.format("%Y/%m").to_string(),
+                    name: format!("{}.{extension}", date.format("%d")),
+                    path: self.fuse_path(&path)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Every "YYYY" and "YYYY/MM" below `/journal`, including today's so its
+    /// note can be opened by path before it exists
+    fn journal_dirs(&self, entries: &[AliasEntry]) -> BTreeSet<String> {
+        let mut dirs = BTreeSet::new();
+        let today = self.journal_today().format("%Y/%m").to_string();
+        for group in entries
+            .iter()
+            .map(|e| e.group.as_str())
+            .chain([today.as_str()])
+        {
+            if let Some((year, _)) = group.split_once('/') {
+                dirs.insert(year.to_string());
+            }
+            dirs.insert(group.to_string());
+        }
+        dirs
+    }
+
+    /// Resolve `rest`, a path below `/journal`
+    ///
+    /// Looking up today's "DD.md" creates the daily note when it is missing,
+    /// so `$EDITOR /journal/YYYY/MM/DD.md` works without any setup.
+    pub(super) fn resolve_journal(&self, rest: &str) -> Option<VirtualPath> {
+        let entries = self.journal_entries();
+        if self.journal_dirs(&entries).contains(rest) {
+            return Some(VirtualPath::Dir);
+        }
+
+        let (group, name) = rest.rsplit_once('/')?;
+        if let Some(entry) = entries.iter().find(|e| e.group == group && e.name == name) {
+            return Some(VirtualPath::Alias(entry.path.clone()));
+        }
+
+        let today = self.journal_today();
+        let today_name = format!(
+            "{}.{}",
+            today.format("%d"),
+            self.syntax_map.extension_for_syntax(JOURNAL_SYNTAX)
+        );
+        if group != today.format("%Y/%m").to_string() || name != today_name {
+            return None;
+        }
+        self.create_journal_note(today).map(VirtualPath::Alias)
+    }
+
+    /// Create the daily note for `date`, returning its path
+    fn create_journal_note(&self, date: NaiveDate) -> Option<String> {
+        let title = date.format("%Y-%m-%d").to_string();
+        let result = self.journal_folder_id(true).and_then(|folder_id| {
+            let Some(folder_id) = folder_id else {
+                return Ok(None);
+            };
+            let id = format!("{:x}", uuid::Uuid::new_v4().as_simple());
+            let content = self.template_content(Some(&folder_id), JOURNAL_SYNTAX);
+            let note_content = NoteContent {
+                title: &title,
+                abstract_text: None,
+                content: &content,
+                syntax: JOURNAL_SYNTAX,
+                extensionless: false,
+            };
+            self.db
+                .create_note(&id, note_content, Some(&folder_id), self.user_id.as_str())?;
+            self.tag_new_note(&id);
+            self.db.get_note_path_by_id(&id)
+        });
+
+        match result {
+            Ok(Some(path)) => {
+                debug!("journal: Created daily note {path}");
+                self.fuse_path(&path)
+            }
+            Ok(None) => {
+                error!("journal: No folder to store daily note {title} in");
+                None
+            }
+            Err(e) => {
+                error!("journal: Unable to create daily note {title}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Subdirectories and notes of `/journal/<rest>` ("" for `/journal` itself),
+    /// newest year and month first
+    pub(super) fn journal_listing(&self, rest: &str) -> (Vec<String>, Vec<AliasEntry>) {
+        let mut entries = self.journal_entries();
+        let subdirs = self
+            .journal_dirs(&entries)
+            .into_iter()
+            .rev()
+            .filter_map(|dir| match dir.rsplit_once('/') {
+                Some((parent, name)) if parent == rest => Some(name.to_string()),
+                None if rest.is_empty() => Some(dir),
+                _ => None,
+            })
+            .collect();
+        entries.retain(|e| e.group == rest);
+        (subdirs, entries)
+    }
+}
@@ -0,0 +1,95 @@
+use fuser::{FileAttr, FileType};
+
+use super::{AliasEntry, VirtualPath};
+use crate::database::NoteMeta;
+use crate::fuse_fs::ExampleFuseFs;
+
+/// Suffix of the directory of a note's outgoing links ("<title>.links")
+const LINKS_SUFFIX: &str = ".links";
+
+impl ExampleFuseFs {
+    /// Classify `path` if it is a links directory or a link inside one
+    pub(super) fn resolve_note_links(&self, path: &str) -> Option<VirtualPath> {
+        if !self.options.link_dirs {
+            return None;
+        }
+        if self.companion_note(path, LINKS_SUFFIX).is_some() {
+            return Some(VirtualPath::Dir);
+        }
+        let (dir, name) = path.rsplit_once('/')?;
+        let note = self.companion_note(dir, LINKS_SUFFIX)?;
+        self.note_link_entries(dir, &note)
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| VirtualPath::Symlink(entry.path))
+    }
+
+    /// The notes `note` links to, named uniquely, with `path` set to the
+    /// symlink target: the note's real path relative to `dir`
+    fn note_link_entries(&self, dir: &str, note: &NoteMeta) -> Vec<AliasEntry> {
+        let notes = match self.db.list_linked_notes(&note.id, self.user_id.as_str()) {
+            Ok(notes) => notes,
+            Err(e) => {
+                error!("links: Unable to list links of {}: {e}", note.id);
+                return Vec::new();
+            }
+        };
+        // "/a/b.links" is two levels below the root
+        let up = "../".repeat(dir.matches('/').count());
+        let mut entries =
+            self.alias_entries(notes.into_iter().map(|n| (String::new(), n)).collect());
+        for entry in &mut entries {
+            entry.path = format!("{up}{}", &entry.path[1..]);
+        }
+        entries
+    }
+
+    /// Symlinks in the links directory at `path`, if it is one
+    pub(super) fn note_links_listing(
+        &mut self,
+        path: &str,
+    ) -> Option<Vec<(u64, FileType, String)>> {
+        if !self.options.link_dirs {
+            return None;
+        }
+        let note = self.companion_note(path, LINKS_SUFFIX)?;
+        let listing = self
+            .note_link_entries(path, &note)
+            .into_iter()
+            .map(|entry| {
+                let ino = self.get_or_create_inode(&format!("{path}/{}", entry.name));
+                (ino, FileType::Symlink, entry.name)
+            })
+            .collect();
+        Some(listing)
+    }
+
+    /// Entry for the links directory of `note`, listed next to it in `dir_path`
+    pub(in crate::fuse_fs) fn note_links_entry(
+        &mut self,
+        dir_path: &str,
+        note: &NoteMeta,
+    ) -> Option<(u64, FileType, String)> {
+        if !self.options.link_dirs {
+            return None;
+        }
+        let name = format!("{}{LINKS_SUFFIX}", self.title_to_name(&note.title));
+        let path = if dir_path == "/" {
+            format!("/{name}")
+        } else {
+            format!("{dir_path}/{name}")
+        };
+        Some((self.get_or_create_inode(&path), FileType::Directory, name))
+    }
+
+    /// Attributes of a symlink pointing at `target`
+    pub(in crate::fuse_fs) fn symlink_attr(&self, ino: u64, target: &str) -> FileAttr {
+        FileAttr {
+            kind: FileType::Symlink,
+            size: target.len() as u64,
+            perm: 0o777,
+            nlink: 1,
+            ..self.virtual_dir_attr(ino)
+        }
+    }
+}
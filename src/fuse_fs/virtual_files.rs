@@ -0,0 +1,216 @@
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local};
+use fuser::{FileAttr, FileType};
+
+use super::ExampleFuseFs;
+use crate::json::Value;
+use crate::logging;
+
+/// `/.stats` reports operation counters since mount
+const STATS_FILE: &str = "/.stats";
+/// `/.log` lists the most recent errors, oldest first, for finding out why
+/// an operation failed without the terminal the mount was started from
+const LOG_FILE: &str = "/.log";
+/// `/.health` is a JSON status document for monitoring scripts
+const HEALTH_FILE: &str = "/.health";
+
+/// Writes still not stored after this long are stuck (they are normally
+/// stored within a second)
+const STUCK_WRITES: Duration = Duration::from_secs(10);
+/// The mount counts as degraded for this long after an error
+const RECENT_ERROR: Duration = Duration::from_secs(60);
+
+/// A read-only file generated by the mount rather than stored as a note
+#[derive(Clone, Copy)]
+pub(super) enum VirtualFile {
+    Stats,
+    Log,
+    Health,
+}
+
+/// What `/.health` reports
+struct Health {
+    /// Why the database can't be read, if it can't
+    database_error: Option<String>,
+    wal_bytes: u64,
+    /// How long each write not yet stored has been waiting
+    pending: Vec<Duration>,
+    last_error: Option<(DateTime<Local>, String)>,
+    now: DateTime<Local>,
+}
+
+impl Health {
+    /// The document: "status" is "degraded" when "errors" says why
+    fn to_json(&self) -> Value {
+        let mut errors = Vec::new();
+        if let Some(e) = &self.database_error {
+            errors.push(format!("database unreachable: {e}"));
+        }
+        let stuck = self
+            .pending
+            .iter()
+            .filter(|&&age| age >= STUCK_WRITES)
+            .count();
+        if stuck > 0 {
+            errors.push(format!(
+                "{stuck} writes not stored after {}s",
+                STUCK_WRITES.as_secs()
+            ));
+        }
+        if let Some((at, message)) = &self.last_error {
+            let age = (self.now - *at).to_std().unwrap_or_default();
+            if age < RECENT_ERROR {
+                errors.push(format!("error {}s ago: {message}", age.as_secs()));
+            }
+        }
+        let status = if errors.is_empty() { "ok" } else { "degraded" };
+        Value::Object(vec![
+            ("status".to_string(), status.into()),
+            (
+                "database".to_string(),
+                Value::Object(vec![
+                    (
+                        "reachable".to_string(),
+                        Value::Bool(self.database_error.is_none()),
+                    ),
+                    (
+                        "wal_bytes".to_string(),
+                        Value::Number(self.wal_bytes as f64),
+                    ),
+                ]),
+            ),
+            (
+                "pending_writes".to_string(),
+                Value::Number(self.pending.len() as f64),
+            ),
+            (
+                "last_error".to_string(),
+                match &self.last_error {
+                    Some((at, message)) => Value::Object(vec![
+                        ("at".to_string(), at.to_rfc3339().into()),
+                        ("message".to_string(), message.as_str().into()),
+                    ]),
+                    None => Value::Null,
+                },
+            ),
+            (
+                "errors".to_string(),
+                Value::Array(errors.into_iter().map(Value::from).collect()),
+            ),
+        ])
+    }
+}
+
+impl ExampleFuseFs {
+    /// The virtual file at `path`, if it is one
+    pub(super) fn virtual_file(&self, path: &str) -> Option<VirtualFile> {
+        match path {
+            STATS_FILE => Some(VirtualFile::Stats),
+            LOG_FILE => Some(VirtualFile::Log),
+            HEALTH_FILE => Some(VirtualFile::Health),
+            _ => None,
+        }
+    }
+
+    /// Current contents of `file`, generated on every read
+    pub(super) fn virtual_file_bytes(&self, file: VirtualFile) -> Vec<u8> {
+        match file {
+            VirtualFile::Stats => self.stats.render().into_bytes(),
+            VirtualFile::Log => logging::recent_errors().into_bytes(),
+            VirtualFile::Health => self.health().to_json().to_string_pretty().into_bytes(),
+        }
+    }
+
+    fn health(&self) -> Health {
+        // Empty for an in-memory database, which has no WAL
+        let wal_bytes = match self.db.connection.path() {
+            Some(path) if !path.is_empty() => std::fs::metadata(format!("{path}-wal"))
+                .map(|metadata| metadata.len())
+                .unwrap_or(0),
+            _ => 0,
+        };
+        Health {
+            database_error: self.db.ping().err().map(|e| e.to_string()),
+            wal_bytes,
+            pending: self
+                .handle_states()
+                .into_iter()
+                .filter_map(|handle| handle.pending.map(|(_, age)| age))
+                .collect(),
+            last_error: logging::last_error(),
+            now: Local::now(),
+        }
+    }
+
+    /// Attributes of `file`: read-only, and modified now since it changes on
+    /// every read
+    pub(super) fn virtual_file_attr(&self, ino: u64, file: VirtualFile) -> FileAttr {
+        let size = self.virtual_file_bytes(file).len() as u64;
+        let now = SystemTime::now();
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind: FileType::RegularFile,
+            perm: self.options.file_mode & !0o222,
+            nlink: 1,
+            uid: self.options.uid,
+            gid: self.options.gid,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health() {
+        let now = Local::now();
+        let healthy = Health {
+            database_error: None,
+            wal_bytes: 4152,
+            pending: vec![Duration::from_millis(200)],
+            // Long enough ago not to count
+            last_error: Some((now - chrono::Duration::minutes(5), "old".to_string())),
+            now,
+        }
+        .to_json();
+        assert_eq!(healthy.get("status"), Some(&Value::from("ok")));
+        assert_eq!(healthy.get("errors"), Some(&Value::Array(Vec::new())));
+        assert_eq!(healthy.get("pending_writes"), Some(&Value::Number(1.0)));
+        assert_eq!(
+            healthy.get("last_error").and_then(|e| e.get("message")),
+            Some(&Value::from("old"))
+        );
+
+        let degraded = Health {
+            database_error: Some("database is locked".to_string()),
+            wal_bytes: 0,
+            pending: vec![Duration::from_secs(30)],
+            last_error: Some((
+                now - chrono::Duration::seconds(3),
+                "write: Failed".to_string(),
+            )),
+            now,
+        }
+        .to_json();
+        assert_eq!(degraded.get("status"), Some(&Value::from("degraded")));
+        assert_eq!(
+            degraded.get("errors"),
+            Some(&Value::Array(vec![
+                Value::from("database unreachable: database is locked"),
+                Value::from("1 writes not stored after 10s"),
+                Value::from("error 3s ago: write: Failed"),
+            ]))
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use super::ExampleFuseFs;
+use crate::database::{Database, NoteMeta};
+
+/// The note's abstract, as UTF-8 text
+const ABSTRACT_XATTR: &str = "user.abstract";
+/// The note's syntax; changing it changes the file's extension
+const SYNTAX_XATTR: &str = "user.syntax";
+/// The note's tags, separated by commas
+const TAGS_XATTR: &str = "user.tags";
+/// Hash of the note's stored content, changing whenever it does (read-only)
+const ETAG_XATTR: &str = "user.etag";
+/// "1" while the note is stored encrypted; setting it to "0" or removing it
+/// decrypts the note
+const ENCRYPTED_XATTR: &str = "user.encrypted";
+/// "locked" or "unlocked" while the note has a lock; setting it locks the
+/// note behind the value as passphrase, and removing it (once unlocked)
+/// removes the lock
+const LOCK_XATTR: &str = "user.lock";
+/// Setting it to the passphrase of a locked note unlocks it until the
+/// mount ends (write-only)
+const UNLOCK_XATTR: &str = "user.unlock";
+
+/// setxattr flags (from <sys/xattr.h>)
+const XATTR_CREATE: i32 = 1;
+const XATTR_REPLACE: i32 = 2;
+
+/// Error for an attribute that isn't set
+#[cfg(target_os = "macos")]
+const NO_XATTR: i32 = libc::ENOATTR;
+#[cfg(not(target_os = "macos"))]
+const NO_XATTR: i32 = libc::ENODATA;
+
+/// Split a `user.tags` value into tags, dropping blanks and repeats
+fn parse_tags(value: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in value.split(',').map(str::trim) {
+        if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+impl ExampleFuseFs {
+    /// The note at `ino`; folders, virtual entries and other users' notes
+    /// have no note attributes
+    fn xattr_note(&self, ino: u64) -> Result<(String, NoteMeta), i32> {
+        let path = self.get_path_from_inode(ino).ok_or(libc::ENOENT)?;
+        let db_path = self.db_path(&path);
+        let note_id = match self.note_id_by_path(&db_path) {
+            Ok(Some(id)) => id,
+            Ok(None) => return Err(NO_XATTR),
+            Err(e) => {
+                error!("xattr: Database error resolving {path}: {e}");
+                return Err(libc::EIO);
+            }
+        };
+        match self.db.get_note_meta_by_id(&note_id) {
+            Ok(Some(note)) if note.user_id != self.user_id => Err(NO_XATTR),
+            Ok(Some(note)) => Ok((path, note)),
+            Ok(None) => Err(libc::ENOENT),
+            Err(e) => {
+                error!("xattr: Database error retrieving note {note_id}: {e}");
+                Err(libc::EIO)
+            }
+        }
+    }
+
+    /// Current value of the attribute `name`, or `None` if it isn't set
+    fn note_xattr_value(&self, note: &NoteMeta, name: &str) -> Result<Option<String>, i32> {
+        match name {
+            ABSTRACT_XATTR => Ok(note.abstract_text.clone().filter(|a| !a.is_empty())),
+            SYNTAX_XATTR => Ok(Some(note.syntax.clone())),
+            TAGS_XATTR => match self.db.get_note_tags(&note.id) {
+                Ok(tags) if tags.is_empty() => Ok(None),
+                Ok(tags) => Ok(Some(tags.join(","))),
+                Err(e) => {
+                    error!("xattr: Unable to get tags for {}: {e}", note.id);
+                    Err(libc::EIO)
+                }
+            },
+            ETAG_XATTR => self.db.get_note_etag(&note.id).map_err(|e| {
+                error!("xattr: Unable to get etag for {}: {e}", note.id);
+                libc::EIO
+            }),
+            ENCRYPTED_XATTR => Ok(note.encrypted.then(|| "1".to_string())),
+            LOCK_XATTR => Ok(note.locked.then(|| {
+                if self.is_locked(&note.id, note.locked) {
+                    "locked".to_string()
+                } else {
+                    "unlocked".to_string()
+                }
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// getxattr: the value of `name` on the note at `ino`
+    pub(super) fn get_note_xattr(&self, ino: u64, name: &str) -> Result<Vec<u8>, i32> {
+        let (_, note) = self.xattr_note(ino)?;
+        self.note_xattr_value(&note, name)?
+            .map(String::into_bytes)
+            .ok_or(NO_XATTR)
+    }
+
+    /// listxattr: the NUL-terminated names of the attributes set on `ino`
+    pub(super) fn list_note_xattrs(&self, ino: u64) -> Result<Vec<u8>, i32> {
+        let note = match self.xattr_note(ino) {
+            Ok((_, note)) => note,
+            Err(errno) if errno == NO_XATTR => return Ok(Vec::new()),
+            Err(errno) => return Err(errno),
+        };
+        let mut names = Vec::new();
+        for name in [
+            ABSTRACT_XATTR,
+            SYNTAX_XATTR,
+            TAGS_XATTR,
+            ETAG_XATTR,
+            ENCRYPTED_XATTR,
+            LOCK_XATTR,
+        ] {
+            if self.note_xattr_value(&note, name)?.is_some() {
+                names.extend_from_slice(name.as_bytes());
+                names.push(0);
+            }
+        }
+        Ok(names)
+    }
+
+    /// setxattr: store `value` in the database column behind `name`
+    pub(super) fn set_note_xattr(
+        &mut self,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: i32,
+    ) -> Result<(), i32> {
+        if name == ETAG_XATTR {
+            return Err(libc::EPERM);
+        }
+        if ![
+            ABSTRACT_XATTR,
+            SYNTAX_XATTR,
+            TAGS_XATTR,
+            ENCRYPTED_XATTR,
+            LOCK_XATTR,
+            UNLOCK_XATTR,
+        ]
+        .contains(&name)
+        {
+            return Err(libc::ENOTSUP);
+        }
+        let (path, note) = self.xattr_note(ino).map_err(|errno| {
+            // Only the user's notes carry these attributes
+            if errno == NO_XATTR {
+                libc::ENOTSUP
+            } else {
+                errno
+            }
+        })?;
+        let value = std::str::from_utf8(value).map_err(|_| libc::EINVAL)?;
+        match name {
+            LOCK_XATTR if flags & XATTR_REPLACE != 0 && !note.locked => return Err(NO_XATTR),
+            LOCK_XATTR if flags & XATTR_CREATE != 0 && note.locked => return Err(libc::EEXIST),
+            LOCK_XATTR => return self.lock_note(&path, &note.id, value),
+            UNLOCK_XATTR => return self.unlock_note(&path, &note.id, value),
+            _ if self.is_locked(&note.id, note.locked) => return Err(libc::EACCES),
+            _ => {}
+        }
+        let exists = self.note_xattr_value(&note, name)?.is_some();
+        if flags & XATTR_CREATE != 0 && exists {
+            return Err(libc::EEXIST);
+        }
+        if flags & XATTR_REPLACE != 0 && !exists {
+            return Err(NO_XATTR);
+        }
+
+        match name {
+            ABSTRACT_XATTR => self.db.set_note_abstract(&note.id, Some(value)).map(|_| ()),
+            TAGS_XATTR => {
+                self.db
+                    .set_note_tags(&note.id, &parse_tags(value), self.user_id.as_str())
+            }
+            ENCRYPTED_XATTR => {
+                let encrypted = match value.trim() {
+                    "1" => true,
+                    "0" => false,
+                    _ => return Err(libc::EINVAL),
+                };
+                return self.set_note_encrypted(ino, &path, &note.id, encrypted);
+            }
+            _ => return self.set_note_syntax(&path, &note, value.trim()),
+        }
+        .map_err(|e| {
+            error!("setxattr: Failed to set {name} on {path}: {e}");
+            libc::EIO
+        })
+    }
+
+    /// removexattr: clear the column behind `name` (the syntax can't be removed)
+    pub(super) fn remove_note_xattr(&mut self, ino: u64, name: &str) -> Result<(), i32> {
+        let (path, note) = self.xattr_note(ino)?;
+        if self.note_xattr_value(&note, name)?.is_none() {
+            return Err(NO_XATTR);
+        }
+        if self.is_locked(&note.id, note.locked) {
+            return Err(libc::EACCES);
+        }
+        match name {
+            ABSTRACT_XATTR => self.db.set_note_abstract(&note.id, None).map(|_| ()),
+            TAGS_XATTR => self.db.set_note_tags(&note.id, &[], self.user_id.as_str()),
+            ENCRYPTED_XATTR => return self.set_note_encrypted(ino, &path, &note.id, false),
+            LOCK_XATTR => return self.remove_note_lock(&path, &note.id),
+            _ => return Err(libc::EPERM),
+        }
+        .map_err(|e| {
+            error!("removexattr: Failed to remove {name} from {path}: {e}");
+            libc::EIO
+        })
+    }
+
+    /// Change a note's syntax, which renames it to the matching extension
+    fn set_note_syntax(&mut self, path: &str, note: &NoteMeta, syntax: &str) -> Result<(), i32> {
+        if syntax.is_empty() || syntax.contains('/') {
+            return Err(libc::EINVAL);
+        }
+        if let Err(e) = self
+            .db
+            .update_note_name(&note.id, &note.title, syntax, note.extensionless)
+        {
+            if Database::is_unique_violation(&e) {
+                return Err(libc::EEXIST);
+            }
+            error!("setxattr: Failed to set syntax of {path}: {e}");
+            return Err(libc::EIO);
+        }
+        if let Ok(Some(new_path)) = self.db.get_note_path_by_id(&note.id)
+            && let Some(new_path) = self.fuse_path(&new_path)
+            && new_path != path
+        {
+            self.update_inode_mappings(path, &new_path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tags() {
+        assert_eq!(parse_tags("rust, fuse ,,rust"), vec!["rust", "fuse"]);
+        assert!(parse_tags(" , ").is_empty());
+    }
+}
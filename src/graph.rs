@@ -0,0 +1,196 @@
+//! The notes network of the `graph` subcommand as DOT (for Graphviz) or
+//! GraphML (for Gephi, yEd and the like)
+//!
+//! Folders and notes are nodes labelled with their names, with their paths
+//! kept alongside. "contains" edges lead from folders to what is in them
+//! (unless `folders` is false, leaving folders out), and "links" edges from
+//! notes to the notes their wikilinks lead to.
+
+use std::collections::HashSet;
+
+use crate::database::LinkGraph;
+use crate::markdown::escape;
+
+/// Last component of `path`
+fn name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Node ids: folders and notes have ids of their own tables, which could
+/// repeat across the two
+fn folder_node(id: &str) -> String {
+    format!("f:{id}")
+}
+
+fn note_node(id: &str) -> String {
+    format!("n:{id}")
+}
+
+/// (node id, kind, path)
+type Node<'a> = (String, &'static str, &'a str);
+
+/// (from, to, relation)
+type Edge = (String, String, &'static str);
+
+/// The nodes and edges of the parts of `graph` shown
+fn parts(graph: &LinkGraph, folders: bool) -> (Vec<Node<'_>>, Vec<Edge>) {
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    let mut shown = HashSet::new();
+    if folders {
+        for (id, _, path) in &graph.folders {
+            shown.insert(folder_node(id));
+            nodes.push((folder_node(id), "folder", path.as_str()));
+        }
+    }
+    for (id, _, path) in &graph.notes {
+        shown.insert(note_node(id));
+        nodes.push((note_node(id), "note", path.as_str()));
+    }
+    if folders {
+        let contained = graph
+            .folders
+            .iter()
+            .map(|(id, parent, _)| (parent, folder_node(id)))
+            .chain(
+                graph
+                    .notes
+                    .iter()
+                    .map(|(id, parent, _)| (parent, note_node(id))),
+            );
+        for (parent, child) in contained {
+            if let Some(parent) = parent {
+                edges.push((folder_node(parent), child, "contains"));
+            }
+        }
+    }
+    for (source, target) in &graph.links {
+        edges.push((note_node(source), note_node(target), "links"));
+    }
+    // Orphaned notes and folders aren't listed, nor edges to or from them
+    edges.retain(|(from, to, _)| shown.contains(from) && shown.contains(to));
+    (nodes, edges)
+}
+
+/// `text` as a quoted DOT string
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn to_dot(graph: &LinkGraph, folders: bool) -> String {
+    let (nodes, edges) = parts(graph, folders);
+    let mut out = String::from("digraph notes {\n    node [shape=box];\n");
+    for (node, kind, path) in nodes {
+        let shape = if kind == "folder" {
+            ", shape=folder"
+        } else {
+            ""
+        };
+        out.push_str(&format!(
+            "    {} [label={}, tooltip={}{shape}];\n",
+            dot_string(&node),
+            dot_string(name(path)),
+            dot_string(path)
+        ));
+    }
+    for (from, to, relation) in edges {
+        let style = if relation == "contains" {
+            " [style=dashed, arrowhead=none]"
+        } else {
+            ""
+        };
+        out.push_str(&format!(
+            "    {} -> {}{style};\n",
+            dot_string(&from),
+            dot_string(&to)
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+pub fn to_graphml(graph: &LinkGraph, folders: bool) -> String {
+    let (nodes, edges) = parts(graph, folders);
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n\
+         \x20 <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n\
+         \x20 <key id=\"path\" for=\"node\" attr.name=\"path\" attr.type=\"string\"/>\n\
+         \x20 <key id=\"kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>\n\
+         \x20 <key id=\"relation\" for=\"edge\" attr.name=\"relation\" attr.type=\"string\"/>\n\
+         \x20 <graph id=\"notes\" edgedefault=\"directed\">\n",
+    );
+    for (node, kind, path) in nodes {
+        out.push_str(&format!(
+            "    <node id=\"{}\"><data key=\"label\">{}</data><data key=\"path\">{}</data><data key=\"kind\">{kind}</data></node>\n",
+            escape(&node),
+            escape(name(path)),
+            escape(path)
+        ));
+    }
+    for (from, to, relation) in edges {
+        out.push_str(&format!(
+            "    <edge source=\"{}\" target=\"{}\"><data key=\"relation\">{relation}</data></edge>\n",
+            escape(&from),
+            escape(&to)
+        ));
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> LinkGraph {
+        let entry = |id: &str, parent: Option<&str>, path: &str| {
+            (id.to_string(), parent.map(str::to_string), path.to_string())
+        };
+        LinkGraph {
+            folders: vec![entry("p", None, "Projects")],
+            notes: vec![
+                entry("a", Some("p"), "Projects/a \"quoted\".md"),
+                entry("b", None, "b<c>.md"),
+            ],
+            links: vec![
+                ("a".to_string(), "b".to_string()),
+                // To a note that isn't listed
+                ("b".to_string(), "orphan".to_string()),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_dot() {
+        let dot = to_dot(&graph(), true);
+        assert!(dot.starts_with("digraph notes {\n"));
+        assert!(dot.contains("\"f:p\" [label=\"Projects\", tooltip=\"Projects\", shape=folder];"));
+        assert!(dot.contains(
+            "[label=\"a \\\"quoted\\\".md\", tooltip=\"Projects/a \\\"quoted\\\".md\"];"
+        ));
+        assert!(dot.contains("\"f:p\" -> \"n:a\" [style=dashed, arrowhead=none];"));
+        assert!(dot.contains("\"n:a\" -> \"n:b\";"));
+        assert!(!dot.contains("orphan"));
+
+        let links_only = to_dot(&graph(), false);
+        assert!(!links_only.contains("f:p"));
+        assert!(links_only.contains("\"n:a\" -> \"n:b\";"));
+    }
+
+    #[test]
+    fn test_graphml() {
+        let graphml = to_graphml(&graph(), true);
+        assert!(graphml.contains(
+            "<node id=\"n:b\"><data key=\"label\">b&lt;c&gt;.md</data><data key=\"path\">b&lt;c&gt;.md</data><data key=\"kind\">note</data></node>"
+        ));
+        assert!(graphml.contains(
+            "<edge source=\"f:p\" target=\"n:a\"><data key=\"relation\">contains</data></edge>"
+        ));
+        assert!(graphml.contains(
+            "<edge source=\"n:a\" target=\"n:b\"><data key=\"relation\">links</data></edge>"
+        ));
+        assert_eq!(graphml.matches("<edge ").count(), 2);
+        assert!(graphml.ends_with("</graph>\n</graphml>\n"));
+    }
+}
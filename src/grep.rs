@@ -0,0 +1,558 @@
+//! Patterns for the `grep` subcommand: the usual regular expression syntax,
+//! matched by the regex crate (in time linear in the line), and the words
+//! of a pattern the search index can narrow the notes to before their
+//! lines are scanned.
+//!
+//! Supported: literals, `.`, `[...]` and `[^...]` (with ranges and `\d`
+//! `\w` `\s`), `\d` `\w` `\s` and their negations `\D` `\W` `\S`, `\b`,
+//! `^` and `$` (start and end of the line), groups `(...)` and `(?:...)`
+//! with `|`, and the greedy quantifiers `*` `+` `?` `{m}` `{m,}` `{m,n}`.
+//! No backreferences or lookaround.
+
+use std::ops::Range;
+
+/// Lines longer than this many characters are cut around the first match
+const SNIPPET_LENGTH: usize = 160;
+
+/// Characters of a cut line shown before its first match
+const SNIPPET_CONTEXT: usize = 40;
+
+/// How matches are shown in a terminal: bold red
+const HIGHLIGHT_START: &str = "\x1b[1;31m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+/// What one `[...]` item, or an escape like `\d`, matches
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    Digit,
+    Word,
+    Space,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        items: Vec<ClassItem>,
+        negated: bool,
+    },
+    Start,
+    End,
+    WordBoundary,
+    /// Alternatives, each a sequence
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+/// A compiled pattern
+#[derive(Debug, Clone)]
+pub struct Regex {
+    /// The pattern as parsed, for `fts_query`
+    root: Node,
+    compiled: regex::Regex,
+}
+
+impl Regex {
+    /// Compile `pattern`, or say what is wrong with it
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let alternatives = parser.alternatives()?;
+        if parser.pos < parser.chars.len() {
+            return Err("unmatched ')'".to_string());
+        }
+        let root = Node::Group(alternatives);
+        let compiled = compile(&root, ignore_case)?;
+        Ok(Regex { root, compiled })
+    }
+
+    /// A pattern matching `text` as it is (`grep -F`)
+    pub fn literal(text: &str, ignore_case: bool) -> Regex {
+        let root = Node::Group(vec![text.chars().map(Node::Char).collect()]);
+        let compiled = compile(&root, ignore_case).expect("literals always compile");
+        Regex { root, compiled }
+    }
+
+    /// Character ranges of the non-empty matches in `line`, left to right
+    pub fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        let mut found = Vec::new();
+        // Byte offsets counted in characters, in one pass as matches go right
+        let (mut byte, mut chars) = (0, 0);
+        let mut to_chars = |offset: usize| {
+            chars += line[byte..offset].chars().count();
+            byte = offset;
+            chars
+        };
+        for matched in self.compiled.find_iter(line) {
+            if matched.is_empty() {
+                continue;
+            }
+            let start = to_chars(matched.start());
+            found.push(start..to_chars(matched.end()));
+        }
+        found
+    }
+
+    /// An FTS5 query every note with a match also matches, if the pattern
+    /// has words it can't match without: words delimited on both sides in
+    /// the pattern are searched for as they are, one only delimited on its
+    /// left as a prefix. Words the pattern could match as part of a longer
+    /// one are of no use, as the index only holds whole words.
+    pub fn fts_query(&self) -> Option<String> {
+        let Node::Group(alternatives) = &self.root else {
+            return None;
+        };
+        let [sequence] = alternatives.as_slice() else {
+            return None;
+        };
+        let mut terms = Vec::new();
+        let mut run = String::new();
+        let mut left_bounded = false;
+        for node in sequence.iter().chain([&Node::Any]) {
+            if let Node::Char(c) = node {
+                run.push(*c);
+                continue;
+            }
+            let right_bounded = matches!(node, Node::End | Node::WordBoundary);
+            terms.extend(run_terms(&run, left_bounded, right_bounded));
+            run.clear();
+            left_bounded = matches!(node, Node::Start | Node::WordBoundary);
+        }
+        if terms.is_empty() {
+            None
+        } else {
+            Some(terms.join(" "))
+        }
+    }
+}
+
+/// `root` in the regex crate's syntax, compiled
+fn compile(root: &Node, ignore_case: bool) -> Result<regex::Regex, String> {
+    let mut pattern = String::new();
+    write_node(&mut pattern, root);
+    regex::RegexBuilder::new(&pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| match e {
+            regex::Error::CompiledTooBig(_) => "pattern too large".to_string(),
+            e => e.to_string(),
+        })
+}
+
+/// Append `node` to `out` in the regex crate's syntax, which has every
+/// construct this one does
+fn write_node(out: &mut String, node: &Node) {
+    match node {
+        Node::Char(c) => out.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        // Lines have no newlines, but '.' would match any other character
+        Node::Any => out.push_str("(?s:.)"),
+        Node::Class { items, negated } => {
+            out.push_str(if *negated { "[^" } else { "[" });
+            for item in items {
+                match *item {
+                    ClassItem::Range(low, high) if low == high => {
+                        out.push_str(&format!("\\x{{{:x}}}", low as u32));
+                    }
+                    ClassItem::Range(low, high) => {
+                        out.push_str(&format!("\\x{{{:x}}}-\\x{{{:x}}}", low as u32, high as u32))
+                    }
+                    ClassItem::Digit => out.push_str("0-9"),
+                    ClassItem::Word => out.push_str("\\w"),
+                    ClassItem::Space => out.push_str("\\s"),
+                }
+            }
+            out.push(']');
+        }
+        Node::Start => out.push('^'),
+        Node::End => out.push('$'),
+        Node::WordBoundary => out.push_str("\\b"),
+        Node::Group(alternatives) => {
+            out.push_str("(?:");
+            for (i, sequence) in alternatives.iter().enumerate() {
+                if i > 0 {
+                    out.push('|');
+                }
+                for node in sequence {
+                    write_node(out, node);
+                }
+            }
+            out.push(')');
+        }
+        Node::Repeat { node, min, max } => {
+            // Grouped, so a repeat of a repeat is one too
+            out.push_str("(?:");
+            write_node(out, node);
+            out.push(')');
+            match max {
+                Some(max) => out.push_str(&format!("{{{min},{max}}}")),
+                None => out.push_str(&format!("{{{min},}}")),
+            }
+        }
+    }
+}
+
+/// FTS5 terms for the words of `run`, a stretch of literal characters of a
+/// pattern; the start of the pattern or of the line bounds a word as well
+/// as a space does
+fn run_terms(run: &str, left_bounded: bool, right_bounded: bool) -> Vec<String> {
+    let delimiter = |c: char| c.is_whitespace() || c.is_ascii_punctuation();
+    let chars: Vec<char> = run.chars().collect();
+    let mut terms = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let end = (start..chars.len())
+            .find(|&i| delimiter(chars[i]))
+            .unwrap_or(chars.len());
+        let word: String = chars[start..end].iter().collect();
+        // Symbols and combining marks are split off by the index in ways
+        // not worth guessing at
+        if !word.is_empty() && word.chars().all(char::is_alphanumeric) {
+            let left = start > 0 || left_bounded;
+            let right = end < chars.len() || right_bounded;
+            if left && right {
+                terms.push(format!("\"{word}\""));
+            } else if left {
+                terms.push(format!("\"{word}\"*"));
+            }
+        }
+        start = end + 1;
+    }
+    terms
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    /// Sequences separated by '|', up to a ')' or the end
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut sequence = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let mut node = self.atom()?;
+            while let Some((min, max)) = self.quantifier()? {
+                node = Node::Repeat {
+                    node: Box::new(node),
+                    min,
+                    max,
+                };
+            }
+            sequence.push(node);
+        }
+        Ok(sequence)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some('(') => {
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                let alternatives = self.alternatives()?;
+                if self.next() != Some(')') {
+                    return Err("missing ')'".to_string());
+                }
+                Ok(Node::Group(alternatives))
+            }
+            Some('[') => self.class(),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('\\') => self.escape(),
+            Some(c @ ('*' | '+' | '?')) => Err(format!("nothing to repeat before '{c}'")),
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("unexpected end of pattern".to_string()),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let class = |item, negated| Node::Class {
+            items: vec![item],
+            negated,
+        };
+        Ok(match self.next() {
+            Some('d') => class(ClassItem::Digit, false),
+            Some('D') => class(ClassItem::Digit, true),
+            Some('w') => class(ClassItem::Word, false),
+            Some('W') => class(ClassItem::Word, true),
+            Some('s') => class(ClassItem::Space, false),
+            Some('S') => class(ClassItem::Space, true),
+            Some('b') => Node::WordBoundary,
+            Some('t') => Node::Char('\t'),
+            Some(c) if c.is_alphanumeric() => return Err(format!("unknown escape '\\{c}'")),
+            Some(c) => Node::Char(c),
+            None => return Err("trailing '\\'".to_string()),
+        })
+    }
+
+    /// The rest of a `[...]`, after its '['
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.next() {
+                None => return Err("missing ']'".to_string()),
+                // A ']' right after the '[' is one to match
+                Some(']') if !first => break,
+                Some('\\') => match self.next() {
+                    Some('d') => {
+                        items.push(ClassItem::Digit);
+                        continue;
+                    }
+                    Some('w') => {
+                        items.push(ClassItem::Word);
+                        continue;
+                    }
+                    Some('s') => {
+                        items.push(ClassItem::Space);
+                        continue;
+                    }
+                    Some('t') => '\t',
+                    Some(c) if c.is_alphanumeric() => {
+                        return Err(format!("unknown escape '\\{c}' in '[...]'"));
+                    }
+                    Some(c) => c,
+                    None => return Err("missing ']'".to_string()),
+                },
+                Some(c) => c,
+            };
+            first = false;
+            if self.peek() == Some('-') && !matches!(self.chars.get(self.pos + 1), None | Some(']'))
+            {
+                self.pos += 1;
+                let high = match self.next() {
+                    Some('\\') => self.next().ok_or("missing ']'")?,
+                    Some(high) => high,
+                    None => return Err("missing ']'".to_string()),
+                };
+                if high < c {
+                    return Err(format!("bad range '{c}-{high}'"));
+                }
+                items.push(ClassItem::Range(c, high));
+            } else {
+                items.push(ClassItem::Range(c, c));
+            }
+        }
+        Ok(Node::Class { items, negated })
+    }
+
+    /// A quantifier after an atom, as (min, max)
+    fn quantifier(&mut self) -> Result<Option<(usize, Option<usize>)>, String> {
+        let quantifier = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            // Taken as a '{' to match unless it is a whole "{m}", "{m,}" or
+            // "{m,n}"
+            Some('{') => {
+                let Some(length) = self.chars[self.pos..].iter().position(|&c| c == '}') else {
+                    return Ok(None);
+                };
+                let inside: String = self.chars[self.pos + 1..self.pos + length].iter().collect();
+                let bound = |text: &str| text.parse::<usize>().ok();
+                let quantifier = match inside.split_once(',') {
+                    None => bound(&inside).map(|m| (m, Some(m))),
+                    Some((min, "")) => bound(min).map(|m| (m, None)),
+                    Some((min, max)) => bound(min).zip(bound(max)).map(|(m, n)| (m, Some(n))),
+                };
+                let Some(quantifier) = quantifier else {
+                    return Ok(None);
+                };
+                if quantifier.1.is_some_and(|max| max < quantifier.0) {
+                    return Err(format!("bad repetition '{{{inside}}}'"));
+                }
+                self.pos += length;
+                quantifier
+            }
+            _ => return Ok(None),
+        };
+        self.pos += 1;
+        Ok(Some(quantifier))
+    }
+}
+
+/// `line` with the character `ranges` matched highlighted when `color`,
+/// cut to `SNIPPET_LENGTH` characters around the first match if longer
+pub fn snippet(line: &str, ranges: &[Range<usize>], color: bool) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let (mut from, mut to) = (0, chars.len());
+    if chars.len() > SNIPPET_LENGTH {
+        let first = ranges.first().map_or(0, |range| range.start);
+        from = first
+            .saturating_sub(SNIPPET_CONTEXT)
+            .min(chars.len() - SNIPPET_LENGTH);
+        to = from + SNIPPET_LENGTH;
+    }
+    let mut out = String::new();
+    if from > 0 {
+        out.push('…');
+    }
+    let mut pos = from;
+    for range in ranges {
+        let (start, end) = (range.start.max(from), range.end.min(to));
+        if start >= end {
+            continue;
+        }
+        out.extend(&chars[pos..start]);
+        if color {
+            out.push_str(HIGHLIGHT_START);
+        }
+        out.extend(&chars[start..end]);
+        if color {
+            out.push_str(HIGHLIGHT_END);
+        }
+        pos = end;
+    }
+    out.extend(&chars[pos..to]);
+    if to < chars.len() {
+        out.push('…');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(pattern: &str, line: &str) -> Vec<String> {
+        let chars: Vec<char> = line.chars().collect();
+        Regex::new(pattern, false)
+            .unwrap()
+            .find_all(line)
+            .into_iter()
+            .map(|range| chars[range].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_matching() {
+        assert_eq!(found("ab", "xabyab"), ["ab", "ab"]);
+        assert_eq!(found("a.c", "abc a-c ac"), ["abc", "a-c"]);
+        assert_eq!(found("colou?r", "color colour"), ["color", "colour"]);
+        assert_eq!(found("\\d+", "in 2024, 12 notes"), ["2024", "12"]);
+        assert_eq!(found("[A-Z]\\w*", "see Rust and Go"), ["Rust", "Go"]);
+        assert_eq!(found("[^a-z ]+", "abc DEF ghi"), ["DEF"]);
+        assert_eq!(found("^to", "todo: to"), ["to"]);
+        assert_eq!(found("do$", "do todo"), ["do"]);
+        assert_eq!(found("\\bcat\\b", "cat concat cats cat."), ["cat", "cat"]);
+        assert_eq!(found("(foo|ba[rz])+", "foobar baz qux"), ["foobar", "baz"]);
+        assert_eq!(found("x{2,3}", "x xx xxxx"), ["xx", "xxx"]);
+        assert_eq!(found("a{,}", "a{,}"), ["a{,}"]);
+        assert_eq!(found("(a*)*b", "aab"), ["aab"]);
+        assert!(found("x*", "abc").is_empty());
+
+        let regex = Regex::new("rust", true).unwrap();
+        assert_eq!(regex.find_all("Rust RUST rust").len(), 3);
+        let regex = Regex::new("[^a-z]", true).unwrap();
+        assert!(regex.find_all("aB").is_empty());
+        assert_eq!(
+            Regex::literal("a.b", false).find_all("axb a.b"),
+            [Range { start: 4, end: 7 }]
+        );
+
+        for bad in ["(a", "a)", "[a", "*a", "a\\", "\\q", "[z-a]", "a{3,1}"] {
+            assert!(Regex::new(bad, false).is_err(), "{bad}");
+        }
+        assert!(Regex::new("a{100000}{100000}", false).is_err());
+    }
+
+    #[test]
+    fn test_matching_time() {
+        // Neither deep nor slow, whatever the line
+        let line = format!("x{}", "a".repeat(200_000));
+        assert!(
+            Regex::new("x.*y", false)
+                .unwrap()
+                .find_all(&line)
+                .is_empty()
+        );
+        assert_eq!(found("x.*y", &format!("{line}y")).len(), 1);
+        let start = std::time::Instant::now();
+        assert!(found("(x|x)*y", &"x".repeat(25)).is_empty());
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(found("é+", "aéé b"), ["éé"]);
+    }
+
+    #[test]
+    fn test_fts_query() {
+        let query = |pattern: &str| Regex::new(pattern, false).unwrap().fts_query();
+        // The first word may be the end of a longer one
+        assert_eq!(query("error handling"), Some("\"handling\"*".to_string()));
+        assert_eq!(
+            query("^error handling in"),
+            Some("\"error\" \"handling\" \"in\"*".to_string())
+        );
+        assert_eq!(query("\\bfoo\\b"), Some("\"foo\"".to_string()));
+        assert_eq!(
+            query("a (very)? big deal"),
+            Some("\"big\" \"deal\"*".to_string())
+        );
+        assert_eq!(query("todo|fixme"), None);
+        assert_eq!(query("\\d+ notes"), Some("\"notes\"*".to_string()));
+        assert_eq!(query("word"), None);
+        assert_eq!(
+            Regex::literal("x \"quoted\" y", false).fts_query(),
+            Some("\"quoted\" \"y\"*".to_string())
+        );
+    }
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(
+            snippet("a cat", &[Range { start: 2, end: 5 }], true),
+            "a \x1b[1;31mcat\x1b[0m"
+        );
+        assert_eq!(
+            snippet("a cat", &[Range { start: 2, end: 5 }], false),
+            "a cat"
+        );
+
+        let line = format!("{}needle{}", "x".repeat(100), "y".repeat(200));
+        let cut = snippet(
+            &line,
+            &[Range {
+                start: 100,
+                end: 106,
+            }],
+            false,
+        );
+        assert!(cut.starts_with('…') && cut.ends_with('…'));
+        assert_eq!(cut.chars().count(), SNIPPET_LENGTH + 2);
+        assert_eq!(cut.find("needle"), Some('…'.len_utf8() + SNIPPET_CONTEXT));
+    }
+}
@@ -0,0 +1,261 @@
+//! gitignore-style patterns naming entries that are kept out of the mount
+//! (`--ignore`, `--ignore-file`)
+//!
+//! A pattern without a '/' matches a name at any depth; one with a '/'
+//! (other than a trailing one) is matched against the whole path from the
+//! root of the mount. A trailing '/' limits the pattern to directories, and
+//! a leading '!' lets back in what an earlier pattern left out. The last
+//! pattern that matches decides, and everything below an ignored directory
+//! is ignored too.
+
+/// One pattern of the list
+#[derive(Debug, Clone)]
+struct Rule {
+    glob: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Rule {
+    /// Whether the rule matches the entry at `relative` (no leading '/')
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            glob_match(&self.glob, relative)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            glob_match(&self.glob, name)
+        }
+    }
+}
+
+/// An ordered list of ignore patterns
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreList {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreList {
+    /// Adds one pattern; blank lines and "#" comments are skipped
+    pub fn add(&mut self, line: &str) {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return;
+        }
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, pattern) = match pattern.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let anchored = pattern.contains('/');
+        let glob = pattern.strip_prefix('/').unwrap_or(pattern);
+        if glob.is_empty() {
+            return;
+        }
+        self.rules.push(Rule {
+            glob: glob.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        });
+    }
+
+    /// Adds every line of a pattern file
+    pub fn add_lines(&mut self, text: &str) {
+        for line in text.lines() {
+            self.add(line);
+        }
+    }
+
+    /// Whether the entry at mount path `path` is ignored, itself or through
+    /// one of the directories above it
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let relative = path.trim_start_matches('/');
+        if relative.is_empty() {
+            return false;
+        }
+        let mut ancestor_end = 0;
+        while let Some(pos) = relative[ancestor_end..].find('/') {
+            ancestor_end += pos;
+            if self.decide(&relative[..ancestor_end], true) {
+                return true;
+            }
+            ancestor_end += 1;
+        }
+        self.decide(relative, is_dir)
+    }
+
+    /// The verdict of the last rule matching `relative`, not ignored if none does
+    fn decide(&self, relative: &str, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(relative, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+/// Match `text` against a shell glob: `*` and `?` within one path
+/// component, `**` across components, `[...]` classes (`[!...]` to negate)
+/// and `\` to take the next character literally
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    let Some(&first) = pattern.first() else {
+        return text.is_empty();
+    };
+    match first {
+        '*' if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            if let Some(after_slash) = rest.strip_prefix(&['/']) {
+                // "**/" also matches no directory at all
+                if match_from(after_slash, text) {
+                    return true;
+                }
+                return (0..text.len())
+                    .filter(|&i| text[i] == '/')
+                    .any(|i| match_from(after_slash, &text[i + 1..]));
+            }
+            (0..=text.len()).any(|i| match_from(rest, &text[i..]))
+        }
+        '*' => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if match_from(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        '?' => match text.first() {
+            Some(&c) if c != '/' => match_from(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        '[' => match class_end(pattern) {
+            Some(end) => match text.first() {
+                Some(&c) if c != '/' && class_matches(&pattern[1..end], c) => {
+                    match_from(&pattern[end + 1..], &text[1..])
+                }
+                _ => false,
+            },
+            None => text.first() == Some(&'[') && match_from(&pattern[1..], &text[1..]),
+        },
+        '\\' if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && match_from(&pattern[2..], &text[1..])
+        }
+        c => text.first() == Some(&c) && match_from(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Index of the ']' closing the class that opens `pattern`, if it is closed
+fn class_end(pattern: &[char]) -> Option<usize> {
+    let mut i = 1;
+    if matches!(pattern.get(i), Some('!' | '^')) {
+        i += 1;
+    }
+    // A ']' right after the opening is part of the class
+    if pattern.get(i) == Some(&']') {
+        i += 1;
+    }
+    (i..pattern.len()).find(|&j| pattern[j] == ']')
+}
+
+/// Whether `c` is in the class `body` (what is between the brackets)
+fn class_matches(body: &[char], c: char) -> bool {
+    let (negated, body) = match body.first() {
+        Some('!' | '^') => (true, &body[1..]),
+        _ => (false, body),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < body.len() {
+        if i + 2 < body.len() && body[i + 1] == '-' {
+            found |= body[i] <= c && c <= body[i + 2];
+            i += 3;
+        } else {
+            found |= body[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(lines: &str) -> IgnoreList {
+        let mut list = IgnoreList::default();
+        list.add_lines(lines);
+        list
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.o", "main.o"));
+        assert!(!glob_match("*.o", "main.org"));
+        assert!(!glob_match("*.o", "src/main.o"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "a/c"));
+        assert!(glob_match("[a-c]x", "bx"));
+        assert!(!glob_match("[!a-c]x", "bx"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("\\*", "*"));
+        assert!(!glob_match("\\*", "a"));
+        assert!(glob_match("[", "["));
+        assert!(glob_match("**/build", "build"));
+        assert!(glob_match("**/build", "a/b/build"));
+        assert!(glob_match("docs/**", "docs/a/b.md"));
+        assert!(glob_match("a/**/z", "a/z"));
+        assert!(glob_match("a/**/z", "a/b/c/z"));
+    }
+
+    #[test]
+    fn test_names_match_at_any_depth() {
+        let ignore = list("node_modules\n*.o\n# a comment\n\n");
+        assert!(ignore.is_ignored("/node_modules", true));
+        assert!(ignore.is_ignored("/code/node_modules", true));
+        assert!(ignore.is_ignored("/code/node_modules/react/index.js", false));
+        assert!(ignore.is_ignored("/code/main.o", false));
+        assert!(!ignore.is_ignored("/code/main.org", false));
+        assert!(!ignore.is_ignored("/", true));
+    }
+
+    #[test]
+    fn test_anchored_and_dir_only() {
+        let ignore = list("/build\ntarget/\nnotes/*.log\n");
+        assert!(ignore.is_ignored("/build", false));
+        assert!(!ignore.is_ignored("/src/build", false));
+        assert!(ignore.is_ignored("/target", true));
+        assert!(!ignore.is_ignored("/target", false));
+        assert!(ignore.is_ignored("/a/target/x.md", false));
+        assert!(ignore.is_ignored("/notes/today.log", false));
+        assert!(!ignore.is_ignored("/other/notes/today.log", false));
+    }
+
+    #[test]
+    fn test_negation() {
+        let ignore = list("*.tmp\n!keep.tmp\n");
+        assert!(ignore.is_ignored("/a.tmp", false));
+        assert!(!ignore.is_ignored("/keep.tmp", false));
+        // Nothing below an ignored directory comes back
+        let ignore = list("cache/\n!cache/keep.md\n");
+        assert!(ignore.is_ignored("/cache/keep.md", false));
+    }
+}
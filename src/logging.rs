@@ -0,0 +1,136 @@
+//! "[DEBUG]" and "[ERROR]" lines on stderr. Debug lines can be turned off
+//! while mounted (see `fuse_fs::ioctl`); error lines always are written, and
+//! the most recent are kept for `/.log`.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{DateTime, Local};
+
+/// How many error lines `/.log` keeps
+const RECENT_ERRORS: usize = 256;
+
+static DEBUG: AtomicBool = AtomicBool::new(true);
+
+/// Called once more errors than a given number were logged
+type Waiter = (u64, Box<dyn FnOnce() + Send>);
+
+/// The last `RECENT_ERRORS` error lines, with when they were logged
+struct Recent {
+    lines: VecDeque<(DateTime<Local>, String)>,
+    /// Errors logged since start, including those no longer kept
+    total: u64,
+    waiters: Vec<Waiter>,
+}
+
+static RECENT: Mutex<Recent> = Mutex::new(Recent {
+    lines: VecDeque::new(),
+    total: 0,
+    waiters: Vec::new(),
+});
+
+fn recent() -> MutexGuard<'static, Recent> {
+    // A panic while holding it leaves nothing half-done worth refusing
+    RECENT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Turn "[DEBUG]" lines on or off
+pub fn set_debug(on: bool) {
+    DEBUG.store(on, Ordering::Relaxed);
+}
+
+pub fn debug_enabled() -> bool {
+    DEBUG.load(Ordering::Relaxed)
+}
+
+/// Keep `message` (an error line without its "[ERROR] ") for `/.log`
+pub fn record_error(message: String) {
+    let mut recent = recent();
+    if recent.lines.len() == RECENT_ERRORS {
+        recent.lines.pop_front();
+    }
+    recent.lines.push_back((Local::now(), message));
+    recent.total += 1;
+    let total = recent.total;
+    let (ready, waiting) = std::mem::take(&mut recent.waiters)
+        .into_iter()
+        .partition(|(seen, _)| total > *seen);
+    recent.waiters = waiting;
+    drop(recent);
+    for (_, wake) in ready {
+        wake();
+    }
+}
+
+/// The error lines kept, oldest first
+pub fn recent_errors() -> String {
+    recent()
+        .lines
+        .iter()
+        .map(|(at, message)| format!("{} {message}\n", at.format("%Y-%m-%d %H:%M:%S")))
+        .collect()
+}
+
+/// The last error logged, and when
+pub fn last_error() -> Option<(DateTime<Local>, String)> {
+    recent().lines.back().cloned()
+}
+
+/// How many errors were logged since start
+pub fn errors_logged() -> u64 {
+    recent().total
+}
+
+/// Call `wake` once more than `seen` errors were logged
+pub fn on_error_after(seen: u64, wake: impl FnOnce() + Send + 'static) {
+    let mut recent = recent();
+    if recent.total > seen {
+        drop(recent);
+        wake();
+    } else {
+        recent.waiters.push((seen, Box::new(wake)));
+    }
+}
+
+/// `eprintln!` a "[DEBUG]" line, unless they are turned off
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::logging::debug_enabled() {
+            eprintln!("[DEBUG] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+/// `eprintln!` an "[ERROR]" line, and keep it for `/.log`
+macro_rules! error {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        eprintln!("[ERROR] {message}");
+        $crate::logging::record_error(message);
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_errors() {
+        // Other tests may log errors of their own meanwhile
+        let before = errors_logged();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        on_error_after(before, move || sender.send(()).unwrap());
+        error!("test: Something failed for {}", "note.md");
+        assert!(errors_logged() > before);
+        receiver.try_recv().unwrap();
+        assert!(recent_errors().contains(" test: Something failed for note.md\n"));
+
+        for i in 0..RECENT_ERRORS {
+            record_error(format!("test: Filler {i}"));
+        }
+        let kept = recent_errors();
+        assert_eq!(kept.lines().count(), RECENT_ERRORS);
+        assert!(!kept.contains("Something failed"));
+    }
+}
@@ -1,30 +1,342 @@
+#[macro_use]
+mod logging;
+mod backups;
 mod database;
+mod front_matter;
 mod fuse_fs;
-use crate::fuse_fs::ExampleFuseFs;
+mod graph;
+mod grep;
+mod ignore;
+mod json;
+mod maintenance;
+mod markdown;
+mod selftest;
+mod site;
+mod syntax;
+mod wikilinks;
+mod writer_lock;
+mod zip;
+use crate::database::{Access, UserProfile};
+use crate::fuse_fs::{
+    DEFAULT_EDITOR_TEMP_PATTERNS, DEFAULT_GID, DEFAULT_JOURNAL_FOLDER, DEFAULT_MAX_INODES,
+    DEFAULT_MAX_NAME_LENGTH, DEFAULT_RECENT_LIMIT, DEFAULT_SYNTAX, DEFAULT_SYSTEM_FILE_PATTERNS,
+    DEFAULT_TEMPLATES_FOLDER, DEFAULT_UID, DOTFILE_PATTERN, ExampleFuseFs, FsOptions,
+};
+use crate::ignore::IgnoreList;
+use crate::writer_lock::WriterLock;
 
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 use chrono_tz::Tz;
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand};
 use fuser::MountOption;
+use rusqlite::{OpenFlags, OptionalExtension};
+use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Cli {
-    mountpoint: String,
+    #[arg(required = true)]
+    mountpoint: Option<String>,
     // Optional Database (in memory otherwise)
     database: Option<String>,
 
-    /// Initialize the database with schema (required for first-time setup)
-    #[arg(long)]
-    init_db: bool,
+    /// Without DATABASE: save the in-memory database to PATH when
+    /// unmounting, and load it from there when mounting (a new one is set
+    /// up while there is nothing to load)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["database", "as_of"])]
+    persist_to: Option<std::path::PathBuf>,
 
-    /// Timezone for database timestamps (default: Australia/Sydney)
+    /// Timezone times are shown in, for users without their own (default: Australia/Sydney)
     #[arg(long, default_value = "Australia/Sydney")]
     timezone: String,
 
     /// User ID for database operations (required)
+    #[arg(long, required = true)]
+    user_id: Option<String>,
+
+    /// Register --user-id before mounting, for a user's first mount
+    #[arg(long)]
+    create_user: bool,
+
+    /// Display name stored for the user registered by --create-user
+    #[arg(long, value_name = "NAME", requires = "create_user")]
+    display_name: Option<String>,
+
+    /// Timezone stored for the user registered by --create-user (see set-timezone)
+    #[arg(long, value_name = "ZONE", value_parser = parse_timezone, requires = "create_user")]
+    user_timezone: Option<Tz>,
+
+    /// Store colliding names as "name (1).md" instead of failing with EEXIST
+    #[arg(long)]
+    auto_rename: bool,
+
+    /// NFC-normalize file names (for macOS clients that send decomposed names)
+    #[arg(long)]
+    normalize_unicode: bool,
+
+    /// Match file names case-insensitively (stored case is preserved)
+    #[arg(long)]
+    case_insensitive: bool,
+
+    /// Longest file or directory name accepted, in bytes
+    #[arg(long, default_value_t = DEFAULT_MAX_NAME_LENGTH)]
+    max_name_length: usize,
+
+    /// Syntax stored for files created without an extension (e.g. LICENSE)
+    #[arg(long, default_value = DEFAULT_SYNTAX)]
+    default_syntax: String,
+
+    /// Store files ending in EXT with SYNTAX (e.g. qmd=quarto); saved in the database, may be repeated
+    #[arg(long = "syntax-map", value_name = "EXT=SYNTAX", value_parser = syntax::parse_mapping)]
+    syntax_map: Vec<(String, String)>,
+
+    /// Show only notes of these syntaxes or extensions (e.g. md,org), hiding
+    /// the rest from listings and lookups
+    #[arg(long, value_name = "SYNTAXES", value_delimiter = ',')]
+    only_syntax: Vec<String>,
+
+    /// Show only notes tagged TAG, and the folders holding them; notes
+    /// created through the mount are given the tag
+    #[arg(long, value_name = "TAG")]
+    filter_tag: Option<String>,
+
+    /// Keep entries matching this gitignore-style pattern (e.g. node_modules/
+    /// or *.o) out of the mount; may be repeated
+    #[arg(long, value_name = "PATTERN")]
+    ignore: Vec<String>,
+
+    /// Read --ignore patterns from FILE, one per line as in .gitignore
+    #[arg(long, value_name = "FILE")]
+    ignore_file: Option<String>,
+
+    /// Also treat names matching GLOB as editor temporary files, which are
+    /// never stored; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    editor_temp: Vec<String>,
+
+    /// Store names matching this built-in editor temporary file pattern
+    /// (e.g. "*~") as notes after all; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    no_editor_temp: Vec<String>,
+
+    /// Store dotfiles (.gitignore, .env) as notes instead of treating every
+    /// name starting with "." as an editor temporary file
+    #[arg(long)]
+    dotfile_notes: bool,
+
+    /// Also answer lookups of names matching GLOB with "not found" at once,
+    /// and refuse to create them; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    system_file: Vec<String>,
+
+    /// Let names matching this built-in system file pattern (e.g. ".hidden")
+    /// reach the database after all; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    no_system_file: Vec<String>,
+
+    /// Prepend a YAML front matter block (title, abstract, tags, id, timestamps)
+    /// to note contents; edits to the block update the database
+    #[arg(long)]
+    front_matter: bool,
+
+    /// Answer "<file>.meta.json" with an editable JSON view of each note's metadata
     #[arg(long)]
-    user_id: String,
+    meta_sidecars: bool,
+
+    /// Number of recently updated notes listed in /.recent
+    #[arg(long, default_value_t = DEFAULT_RECENT_LIMIT)]
+    recent_limit: usize,
+
+    /// Group /.recent into one subdirectory per day
+    #[arg(long)]
+    recent_by_day: bool,
+
+    /// Number the matches listed in /.searches/<name>/ by rank ("01 title.md"),
+    /// so they sort best first; title matches rank above content matches
+    #[arg(long)]
+    ranked_searches: bool,
+
+    /// Show daily notes as /journal/YYYY/MM/DD.md; opening today's creates it
+    #[arg(long)]
+    journal: bool,
+
+    /// Folder path the daily notes are stored in
+    #[arg(long, default_value = DEFAULT_JOURNAL_FOLDER)]
+    journal_folder: String,
+
+    /// Folder whose notes seed new notes of the same syntax ("default.<ext>" wins)
+    #[arg(long, default_value = DEFAULT_TEMPLATES_FOLDER)]
+    templates_folder: String,
+
+    /// Rename Markdown notes to match a "# Heading" written on their first line
+    #[arg(long)]
+    title_from_heading: bool,
+
+    /// Octal permissions of files that were never chmod'ed
+    #[arg(long, default_value = "644", value_parser = fuse_fs::parse_mode)]
+    file_mode: u16,
+
+    /// Octal permissions of directories that were never chmod'ed
+    #[arg(long, default_value = "755", value_parser = fuse_fs::parse_mode)]
+    dir_mode: u16,
+
+    /// Owner reported for entries that were never chown'ed
+    #[arg(long, default_value_t = DEFAULT_UID)]
+    uid: u32,
+
+    /// Group reported for entries that were never chown'ed
+    #[arg(long, default_value_t = DEFAULT_GID)]
+    gid: u32,
+
+    /// Bypass the kernel page cache, so reads always see the database's current content
+    #[arg(long)]
+    direct_io: bool,
+
+    /// Keep cached file contents across opens while the note is unchanged
+    #[arg(long, conflicts_with = "direct_io")]
+    auto_cache: bool,
+
+    /// Passphrase of a SQLCipher-encrypted database (needs the `sqlcipher` feature)
+    #[arg(long, conflicts_with = "key_file")]
+    key: Option<String>,
+
+    /// Read the SQLCipher passphrase from this file instead (a trailing newline is ignored)
+    #[arg(long)]
+    key_file: Option<String>,
+
+    /// Print a histogram of how long each operation took when unmounting
+    #[arg(long)]
+    latency_summary: bool,
+
+    /// When unmounting, update the query planner's statistics (PRAGMA
+    /// optimize) and checkpoint the whole WAL into the database file
+    #[arg(long)]
+    optimize_on_unmount: bool,
+
+    /// When unmounting, also VACUUM the database, returning the space of
+    /// deleted notes to the disk (implies --optimize-on-unmount; slow for
+    /// large databases)
+    #[arg(long)]
+    vacuum_on_unmount: bool,
+
+    /// List a "<title>_assets" folder next to each note; files copied into it
+    /// are stored as the note's attachments
+    #[arg(long)]
+    assets: bool,
+
+    /// List a "<title>.links" folder next to each note, with a symlink to
+    /// each note its [[wikilinks]] point at
+    #[arg(long)]
+    link_dirs: bool,
+
+    /// List a read-only "<title>.html" rendering next to each Markdown note
+    #[arg(long)]
+    html_previews: bool,
+
+    /// List /.export/pdf, /.export/docx and /.export/html, holding each note
+    /// pandoc can read converted to that format when read
+    #[arg(long)]
+    pandoc_export: bool,
+
+    /// pandoc program run for --pandoc-export
+    #[arg(long, value_name = "PROGRAM", default_value = "pandoc")]
+    pandoc: std::path::PathBuf,
+
+    /// age identity file: notes marked encrypted (user.encrypted=1) are
+    /// decrypted with it when read and encrypted to its recipients when written
+    #[arg(long, value_name = "FILE")]
+    identity: Option<std::path::PathBuf>,
+
+    /// age program run to encrypt and decrypt notes
+    #[arg(long, value_name = "PROGRAM", default_value = "age")]
+    age: std::path::PathBuf,
+
+    /// Let rmdir (and so `rm -r`) delete a folder that isn't empty, with
+    /// everything inside it
+    #[arg(long)]
+    recursive_rmdir: bool,
+
+    /// Other users' notes in the listed folders: "hide" them, or "deny" to
+    /// list them without permissions and refuse to open them
+    #[arg(long, value_name = "POLICY", default_value = "hide", value_parser = fuse_fs::parse_other_users)]
+    other_users: fuse_fs::OtherUsers,
+
+    /// Show the folders other users shared with --user-id as
+    /// /shared/<owner>/<folder>, read-only unless they granted write access
+    #[arg(long)]
+    shared: bool,
+
+    /// Name Finder shows for the mounted volume (macFUSE only)
+    #[arg(long, value_name = "NAME")]
+    volname: Option<String>,
+
+    /// Stop macOS writing "._*" AppleDouble files to hold extended
+    /// attributes and resource forks (macFUSE only)
+    #[arg(long)]
+    noappledouble: bool,
+
+    /// Paths whose inode numbers are kept in memory; the least recently
+    /// used are dropped past this (and read back from the database)
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_INODES)]
+    max_inodes: usize,
+
+    /// Seconds the kernel may cache a file's attributes; lower it when
+    /// notes are also edited elsewhere (the web app), raise it for fewer calls
+    #[arg(long, value_name = "SECONDS", default_value = "1", value_parser = fuse_fs::parse_ttl)]
+    attr_ttl: Duration,
+
+    /// Seconds the kernel may cache a name lookup before asking again
+    #[arg(long, value_name = "SECONDS", default_value = "1", value_parser = fuse_fs::parse_ttl)]
+    entry_ttl: Duration,
+
+    /// Mount only this folder (e.g. "Work/Projects") and what is below it,
+    /// leaving the rest of the notes out of reach
+    #[arg(long, value_name = "FOLDER")]
+    subdir: Option<String>,
+
+    /// Mount a read-only view of the notes as they were at TIMESTAMP
+    /// ("YYYY-MM-DD[ HH:MM[:SS]]" in --timezone), rebuilt from their history
+    #[arg(long, value_name = "TIMESTAMP", value_parser = parse_as_of)]
+    as_of: Option<NaiveDateTime>,
+
+    /// Mount read-only, next to the one writable mount of the database (in
+    /// WAL mode, as init sets up), e.g. from another machine sharing it
+    #[arg(long, conflicts_with_all = ["create_user", "as_of"])]
+    read_only: bool,
+
+    /// With --read-only, open the database as immutable: without any locking,
+    /// for shared filesystems where locks don't work. Only safe while nothing
+    /// writes to it: changes still in the writer's WAL aren't seen, and later
+    /// ones may be read half done.
+    #[arg(long, requires = "read_only")]
+    immutable: bool,
+
+    /// While mounted, purge all but the newest N versions of each note from
+    /// the history (hourly)
+    #[arg(long, value_name = "N")]
+    history_keep_versions: Option<usize>,
+
+    /// While mounted, purge versions replaced more than DAYS ago from the
+    /// history (hourly)
+    #[arg(long, value_name = "DAYS")]
+    history_keep_days: Option<u32>,
+
+    /// While mounted, back the database up into DIR as
+    /// "<name>-<UTC time>.sqlite", on mounting and every --backup-interval
+    #[arg(long, value_name = "DIR")]
+    backup_dir: Option<std::path::PathBuf>,
+
+    /// Minutes between backups
+    #[arg(long, value_name = "MINUTES", default_value_t = 60,
+          value_parser = clap::value_parser!(u64).range(1..), requires = "backup_dir")]
+    backup_interval: u64,
+
+    /// Backups kept in --backup-dir; older ones are removed
+    #[arg(long, value_name = "N", default_value_t = 24,
+          value_parser = clap::value_parser!(u32).range(1..), requires = "backup_dir")]
+    backup_keep: u32,
 
     #[command(subcommand)]
     command: Option<Commands>,
@@ -38,12 +350,575 @@ enum Commands {
         #[arg(short, long)]
         list: bool,
     },
+    /// Set up a new database file for mounting, registering --user-id if
+    /// given. An existing file is only touched with --force.
+    Init {
+        /// Path of the database, e.g. notes.sqlite
+        db: std::path::PathBuf,
+        /// Set up the schema in DB even though it exists and isn't empty
+        #[arg(long)]
+        force: bool,
+    },
     /// List all user IDs in the database
     ListUsers,
+    /// Mount a scratch database and check that basic file operations work
+    Selftest,
+    /// Store identical note contents once (e.g. after a bulk import)
+    Dedup,
+    /// Check the database file, the search index and note paths; exits
+    /// non-zero if anything is wrong, for backup scripts
+    IntegrityCheck {
+        /// Run SQLite's faster quick_check, which skips matching indexes to tables
+        #[arg(long)]
+        quick: bool,
+    },
+    /// Bring the schema of a database created by an older version up to
+    /// date, which mounting it requires; back it up first
+    Migrate,
+    /// Copy the database, as it is at this moment, to a new file (e.g. before a
+    /// bulk change on the mount); safe while it is mounted
+    Snapshot {
+        /// Path of the copy, which must not exist yet
+        dest: String,
+        /// Label recorded in the copy (table snapshot_info), with the time
+        #[arg(long, value_name = "TAG")]
+        tag: Option<String>,
+    },
+    /// Search the notes of --user-id, best match first. QUERY is FTS5 syntax
+    /// (`rust AND "error handling"`, `NEAR(borrow checker, 5)`, `title:rust`,
+    /// `async*`) unless --phrase or --near is given.
+    Search {
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Match the words as one phrase, in order
+        #[arg(long, conflicts_with = "near")]
+        phrase: bool,
+        /// Match the words within N words of each other, in any order
+        #[arg(long, value_name = "N")]
+        near: Option<u32>,
+        /// Only match words in this column
+        #[arg(long, value_name = "COLUMN",
+              value_parser = clap::builder::PossibleValuesParser::new(database::SEARCH_COLUMNS))]
+        column: Option<String>,
+        /// Show at most N matches
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+    },
+    /// Write --user-id's notes to a directory as a static HTML site
+    ExportHtml {
+        /// Directory to write to (created if missing)
+        dir: std::path::PathBuf,
+    },
+    /// Write --user-id's notes to a zip archive, with their folders, times
+    /// and permissions
+    ExportZip {
+        /// Archive to write (replaced if it exists)
+        file: std::path::PathBuf,
+        /// Only this folder (e.g. "Work/Projects") and what is in it
+        #[arg(long, value_name = "PATH")]
+        folder: Option<String>,
+    },
+    /// Print --user-id's folders and notes, and the wikilinks between them,
+    /// as a graph for Graphviz or Gephi
+    Graph {
+        /// "dot" for Graphviz, "graphml" for Gephi, yEd and the like
+        #[arg(long, default_value = "dot",
+              value_parser = clap::builder::PossibleValuesParser::new(["dot", "graphml"]))]
+        format: String,
+        /// Only the notes and their links, without the folders
+        #[arg(long)]
+        links_only: bool,
+    },
+    /// Print the lines of --user-id's notes matching a regular expression,
+    /// as "path:line: text"
+    Grep {
+        pattern: String,
+        /// Match regardless of case
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// Match PATTERN as it is, not as a regular expression
+        #[arg(short = 'F', long)]
+        fixed_strings: bool,
+        /// Highlight matches: "auto" when printing to a terminal
+        #[arg(long, value_name = "WHEN", default_value = "auto",
+              value_parser = clap::builder::PossibleValuesParser::new(["auto", "always", "never"]))]
+        color: String,
+    },
+    /// Remove deleted notes and folders for good (rm and rmdir only hide them)
+    PurgeDeleted {
+        /// Only those deleted more than DAYS days ago
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u32>,
+    },
+    /// Delete old note versions from the history
+    #[command(group(ArgGroup::new("retention").required(true).multiple(true)))]
+    PurgeHistory {
+        /// Keep only the newest N versions of each note
+        #[arg(long, value_name = "N", group = "retention")]
+        keep_versions: Option<usize>,
+        /// Keep only versions replaced in the last DAYS days
+        #[arg(long, value_name = "DAYS", group = "retention")]
+        keep_days: Option<u32>,
+    },
+    /// Let another user into a folder of --user-id and everything below it
+    Share {
+        /// Path of the folder, e.g. "Projects/Website"
+        folder: String,
+        /// User ID to let in
+        user: String,
+        /// Allow changes too, not just reading
+        #[arg(long)]
+        write: bool,
+    },
+    /// Withdraw a user's access to a folder of --user-id
+    Unshare {
+        /// Path of the folder
+        folder: String,
+        /// User ID to shut out
+        user: String,
+    },
+    /// Manage the users of the database
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Manage --user-id's saved searches, the directories of /.searches
+    SavedSearch {
+        #[command(subcommand)]
+        command: SavedSearchCommand,
+    },
+    /// Tag --user-id's notes, or see their tags
+    Tag {
+        #[command(subcommand)]
+        command: TagCommand,
+    },
+    /// Store the timezone --user-id's times are shown in (front matter, journal dates)
+    SetTimezone {
+        /// Zone name, e.g. "Europe/Berlin"; without one --timezone applies again
+        #[arg(value_parser = parse_timezone)]
+        zone: Option<Tz>,
+    },
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Register a new user ID, which can then be mounted with --user-id
+    Add {
+        /// User ID to register
+        user: String,
+        /// Human-readable name for the user
+        #[arg(long, value_name = "NAME")]
+        display_name: Option<String>,
+        /// Timezone their times are shown in (default: the mount's --timezone)
+        #[arg(long, value_name = "ZONE", value_parser = parse_timezone)]
+        timezone: Option<Tz>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SavedSearchCommand {
+    /// Save a search, listed as /.searches/NAME
+    Add {
+        /// Name of its directory
+        name: String,
+        /// FTS5 query, e.g. "rust NOT python"
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    /// List the saved searches with their queries
+    List,
+    /// Forget a saved search (the notes it matches stay)
+    Rm {
+        /// Name of its directory
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TagCommand {
+    /// Add tags to a note
+    Add {
+        /// Path of the note, e.g. "Projects/todo.md", or its id
+        note: String,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Take tags off a note
+    Rm {
+        /// Path of the note, or its id
+        note: String,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// List the tags of a note
+    List {
+        /// Path of the note, or its id
+        note: String,
+    },
+}
+
+/// Run the tag `command` on a note of --user-id, then exit
+fn tag(cli: &Cli, key: Option<&str>, command: &TagCommand) {
+    let (Some(path), Some(user_id)) = (&cli.database, &cli.user_id) else {
+        eprintln!("Error: Database path and --user-id are required to tag notes");
+        std::process::exit(1);
+    };
+    let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+    if let Some(key) = key {
+        unlock_database(&con, key);
+    }
+    let db = database::Database::new(con, None);
+    let (TagCommand::Add { note, .. } | TagCommand::Rm { note, .. } | TagCommand::List { note }) =
+        command;
+    // A path first, as ids are rarely typed but names could look like one
+    let by_path = db.get_visible_note_id_by_path(note.trim_start_matches('/'), user_id, false);
+    let id = match by_path {
+        Ok(Some(id)) => id,
+        Ok(None) => match db.get_note_by_id(note) {
+            Ok(Some(found)) if found.user_id == *user_id => found.id,
+            Ok(_) => {
+                eprintln!("Error: {user_id} has no note {note}");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error: Unable to look up {note}: {e}");
+                std::process::exit(1);
+            }
+        },
+        Err(e) => {
+            eprintln!("Error: Unable to look up {note}: {e}");
+            std::process::exit(1);
+        }
+    };
+    let result = match command {
+        TagCommand::Add { tags, .. } | TagCommand::Rm { tags, .. } => {
+            // Commas separate the tags of the user.tags attribute
+            if let Some(bad) = tags
+                .iter()
+                .find(|tag| tag.trim().is_empty() || tag.contains(','))
+            {
+                eprintln!("Error: {bad:?} can't be a tag");
+                std::process::exit(1);
+            }
+            tags.iter().try_for_each(|tag| {
+                if matches!(command, TagCommand::Add { .. }) {
+                    db.add_note_tag(&id, tag.trim(), user_id)?;
+                } else if !db.remove_note_tag(&id, tag.trim(), user_id)? {
+                    eprintln!("{note} wasn't tagged {tag}");
+                }
+                Ok(())
+            })
+        }
+        TagCommand::List { .. } => Ok(()),
+    };
+    // What the note ends up with, so scripts see the outcome either way
+    match result.and_then(|()| db.get_note_tags(&id)) {
+        Ok(tags) => {
+            for tag in tags {
+                println!("{tag}");
+            }
+        }
+        Err(e) => {
+            eprintln!("Error: Unable to update the tags of {note}: {e}");
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+/// Run the saved search `command` for --user-id, then exit
+fn saved_search(cli: &Cli, key: Option<&str>, command: &SavedSearchCommand) {
+    let (Some(path), Some(user_id)) = (&cli.database, &cli.user_id) else {
+        eprintln!("Error: Database path and --user-id are required to manage saved searches");
+        std::process::exit(1);
+    };
+    let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+    if let Some(key) = key {
+        unlock_database(&con, key);
+    }
+    let db = database::Database::new(con, None);
+    match command {
+        SavedSearchCommand::Add { name, query } => {
+            if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+                eprintln!("Error: {name:?} can't name a directory");
+                std::process::exit(1);
+            }
+            let query = query.join(" ");
+            // As mkdir in /.searches does, so the directory can be listed
+            if let Err(e) = db.search_notes(&query, user_id) {
+                eprintln!("Error: Invalid search query {query:?}: {e}");
+                std::process::exit(1);
+            }
+            match db.create_saved_search(name, &query, user_id) {
+                Ok(_) => println!("Saved search {name}: {query}"),
+                Err(e) if database::Database::is_unique_violation(&e) => {
+                    eprintln!("Error: {user_id} already has a saved search {name}");
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to save search {name}: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        SavedSearchCommand::List => match db.list_saved_searches(user_id) {
+            Ok(searches) => {
+                for search in searches {
+                    println!("{}\t{}", search.name, search.query);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: Unable to list the saved searches of {user_id}: {e}");
+                std::process::exit(1);
+            }
+        },
+        SavedSearchCommand::Rm { name } => match db.delete_saved_search(name, user_id) {
+            Ok(true) => println!("Removed saved search {name}"),
+            Ok(false) => {
+                eprintln!("Error: {user_id} has no saved search {name}");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error: Unable to remove saved search {name}: {e}");
+                std::process::exit(1);
+            }
+        },
+    }
+    std::process::exit(0);
+}
+
+/// Grant `user` `access` to the folder at `folder` of --user-id, or with
+/// `None` withdraw their grant, then exit
+fn share_folder(cli: &Cli, key: Option<&str>, folder: &str, user: &str, access: Option<Access>) {
+    let (Some(path), Some(owner)) = (&cli.database, &cli.user_id) else {
+        eprintln!("Error: Database path and --user-id are required to share folders");
+        std::process::exit(1);
+    };
+    let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+    if let Some(key) = key {
+        unlock_database(&con, key);
+    }
+    let db = database::Database::new(con, None);
+    let folder_id = match db.get_folder_id_by_path(folder.trim_matches('/'), owner) {
+        Ok(Some(id)) => id,
+        Ok(None) => {
+            eprintln!("Error: {owner} has no folder {folder}");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: Unable to look up {folder}: {e}");
+            std::process::exit(1);
+        }
+    };
+    let result = match access {
+        Some(access) => db.grant_folder_access(&folder_id, owner, user, access),
+        None => db.revoke_folder_access(&folder_id, owner, user),
+    };
+    match (result, access) {
+        (Ok(_), Some(access)) => println!("Shared {folder} with {user} ({})", access.as_str()),
+        (Ok(true), None) => println!("Stopped sharing {folder} with {user}"),
+        (Ok(false), None) => println!("{folder} wasn't shared with {user}"),
+        (Err(e), _) => {
+            eprintln!("Error: Unable to update the sharing of {folder}: {e}");
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+/// Exit, saying what to do, unless the database has the schema this build
+/// expects
+fn check_schema(con: rusqlite::Connection) -> rusqlite::Connection {
+    let db = database::Database::new(con, None);
+    match db.schema_state() {
+        Ok(state) => {
+            if let Some(problem) = state.problem() {
+                eprintln!("Error: Unable to mount: {problem}");
+                std::process::exit(1);
+            }
+            db.connection
+        }
+        Err(e) => {
+            eprintln!("Error: Unable to read the database schema: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Exit, listing the known users, unless `user_id` is one of them; with a
+/// `register` profile (--create-user, or a new in-memory database) they are
+/// added first
+fn check_user(
+    con: rusqlite::Connection,
+    user_id: &str,
+    register: Option<UserProfile>,
+) -> rusqlite::Connection {
+    let db = database::Database::new(con, None);
+    if let Some(profile) = register
+        && let Err(e) = db.add_user(user_id, profile)
+    {
+        eprintln!("Error: Unable to register user {user_id}: {e}");
+        std::process::exit(1);
+    }
+    match db.list_users() {
+        Ok(users) if users.iter().any(|user| user == user_id) => db.connection,
+        Ok(users) if users.is_empty() => {
+            eprintln!("Error: Unknown user {user_id:?}; the database has no users yet");
+            eprintln!("Mount with --create-user to add {user_id:?} as its first user");
+            std::process::exit(1);
+        }
+        Ok(users) => {
+            eprintln!("Error: Unknown user {user_id:?}");
+            eprintln!("Known users: {}", users.join(", "));
+            eprintln!("Mount with --create-user to add {user_id:?} as a new user");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: Unable to look up users: {e}");
+            eprintln!("Was the database set up with the init subcommand?");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parse a timezone name such as "Europe/Berlin"
+fn parse_timezone(value: &str) -> Result<Tz, String> {
+    Tz::from_str(value).map_err(|e| e.to_string())
+}
+
+/// Parse an `--as-of` time; a bare date means midnight
+fn parse_as_of(value: &str) -> Result<NaiveDateTime, String> {
+    let value = value.trim().replacen('T', " ", 1);
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(&value, format) {
+            return Ok(time);
+        }
+    }
+    NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+        .map(|date| date.and_time(NaiveTime::MIN))
+        .map_err(|_| format!("expected YYYY-MM-DD[ HH:MM[:SS]], got {value:?}"))
+}
+
+/// The patterns of `--ignore-file`, then those of each `--ignore`
+fn ignore_patterns(ignore_file: Option<&str>, patterns: &[String]) -> IgnoreList {
+    let mut list = IgnoreList::default();
+    if let Some(path) = ignore_file {
+        match std::fs::read_to_string(path) {
+            Ok(text) => list.add_lines(&text),
+            Err(e) => {
+                eprintln!("Error: Unable to read ignore file {path}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    for pattern in patterns {
+        list.add(pattern);
+    }
+    list
+}
+
+/// The built-in `defaults` less those `removed` (`--no-editor-temp`,
+/// `--no-system-file`), then those `added`
+fn pattern_list(defaults: &[&str], added: &[String], removed: &[String]) -> Vec<String> {
+    defaults
+        .iter()
+        .filter(|pattern| !removed.iter().any(|removed| removed == *pattern))
+        .map(|pattern| pattern.to_string())
+        .chain(added.iter().cloned())
+        .collect()
+}
+
+/// URI opening the database at `path` read-only (`--read-only`), and with
+/// `immutable` without locking
+fn read_only_uri(path: &str, immutable: bool) -> String {
+    // Characters that would end the path or start an escape in a URI
+    let path = path
+        .replace('%', "%25")
+        .replace('?', "%3f")
+        .replace('#', "%23");
+    let immutable = if immutable { "&immutable=1" } else { "" };
+    format!("file:{path}?mode=ro{immutable}")
+}
+
+/// Opens the database at `path` read-only (`--read-only`), and with
+/// `immutable` without locking
+fn open_read_only(path: &str, immutable: bool) -> rusqlite::Result<rusqlite::Connection> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+        | OpenFlags::SQLITE_OPEN_URI
+        | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    rusqlite::Connection::open_with_flags(read_only_uri(path, immutable), flags)
+}
+
+/// Opens the mounted database again the way the mount opened it, for the
+/// maintenance thread and after the file is replaced. The key was checked
+/// when mounting.
+fn database_opener(
+    path: &str,
+    read_only: bool,
+    immutable: bool,
+    key: Option<&str>,
+) -> impl Fn() -> rusqlite::Result<rusqlite::Connection> + Send + Clone + 'static {
+    let path = path.to_string();
+    let key = key.map(str::to_string);
+    move || {
+        let con = if read_only {
+            open_read_only(&path, immutable)?
+        } else {
+            rusqlite::Connection::open(&path)?
+        };
+        if let Some(key) = &key {
+            con.pragma_update(None, "key", key)?;
+        }
+        Ok(con)
+    }
+}
+
+/// The passphrase from `--key` or `--key-file`, if either was given
+fn database_key(cli: &Cli) -> Option<String> {
+    if let Some(key) = &cli.key {
+        return Some(key.clone());
+    }
+    let path = cli.key_file.as_ref()?;
+    match std::fs::read_to_string(path) {
+        Ok(key) => Some(key.trim_end_matches(['\n', '\r']).to_string()),
+        Err(e) => {
+            eprintln!("Error: Unable to read key file {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Decrypt `con` with `key`, exiting with a clear error if this build has no
+/// SQLCipher or the key doesn't open the database
+fn unlock_database(con: &rusqlite::Connection, key: &str) {
+    // Plain SQLite ignores PRAGMA key, which would leave the database unencrypted
+    let cipher = con
+        .query_row("PRAGMA cipher_version", [], |row| row.get::<_, String>(0))
+        .optional();
+    if !matches!(cipher, Ok(Some(_))) {
+        eprintln!("Error: --key needs SQLCipher; rebuild with `cargo build --features sqlcipher`");
+        std::process::exit(1);
+    }
+    if let Err(e) = con.pragma_update(None, "key", key) {
+        eprintln!("Error: Unable to set database key: {e}");
+        std::process::exit(1);
+    }
+    // The key is only checked once the first page is read
+    if let Err(e) = con.query_row("SELECT count(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    }) {
+        if e.sqlite_error_code() == Some(rusqlite::ErrorCode::NotADatabase) {
+            eprintln!("Error: Wrong key, or the database is not encrypted");
+        } else {
+            eprintln!("Error: Unable to open encrypted database: {e}");
+        }
+        std::process::exit(1);
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
+    let key = database_key(&cli);
 
     // You can check for the existence of subcommands, and if found use their
     // matches just as you would the top level cmd
@@ -55,6 +930,46 @@ fn main() {
                 println!("Not printing testing lists...");
             }
         }
+        Some(Commands::Init { db, force }) => {
+            // Applying the schema to the wrong file would add a dozen tables
+            // to it, or fail halfway through
+            let in_use = std::fs::metadata(db).is_ok_and(|metadata| metadata.len() > 0);
+            if in_use && !force {
+                eprintln!(
+                    "Error: {} exists and isn't empty; pass --force to set up the schema in it anyway",
+                    db.display()
+                );
+                std::process::exit(1);
+            }
+            let con = rusqlite::Connection::open(db).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            let database = database::Database::new(con, None);
+            match database.init_schema() {
+                Ok(true) => println!(
+                    "Initialized {} (schema version {})",
+                    db.display(),
+                    database::SCHEMA_VERSION
+                ),
+                Ok(false) => println!("{} has a schema already, left as it is", db.display()),
+                Err(e) => {
+                    eprintln!("Error: Failed to initialize {}: {e}", db.display());
+                    std::process::exit(1);
+                }
+            }
+            if let Some(user_id) = &cli.user_id {
+                match database.add_user(user_id, UserProfile::default()) {
+                    Ok(true) => println!("Added user {user_id}"),
+                    Ok(false) => {}
+                    Err(e) => {
+                        eprintln!("Error: Unable to add user {user_id}: {e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            std::process::exit(0);
+        }
         Some(Commands::ListUsers) => {
             // Open database connection for list-users command
             let con = match cli.database {
@@ -66,11 +981,14 @@ fn main() {
                     std::process::exit(1);
                 }
             };
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
 
             // Query to get all user IDs with their folder counts
             let mut stmt = con
                 .prepare(
-                    "SELECT user_id, COUNT(*) as folder_count FROM folders GROUP BY user_id ORDER BY folder_count DESC"
+                    "SELECT user_id, COUNT(*) as folder_count FROM folders WHERE deleted_at IS NULL GROUP BY user_id ORDER BY folder_count DESC"
                 )
                 .expect("Failed to prepare statement");
 
@@ -90,35 +1008,461 @@ fn main() {
 
             std::process::exit(0);
         }
-        None => {}
-    }
-
-    let con = match cli.database {
-        Some(path) => {
+        Some(Commands::Dedup) => {
+            let Some(path) = cli.database else {
+                eprintln!("Error: Database path is required for dedup command");
+                std::process::exit(1);
+            };
             let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
-
-            // Initialize database only if flag is set
-            if cli.init_db {
-                let init_sql = include_str!("../sql/init.sql");
-                con.execute_batch(init_sql)
-                    .expect("Failed to initialize database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            match database::Database::new(con, None).deduplicate_notes() {
+                Ok(summary) => {
+                    println!(
+                        "Shared {} notes through {} new bodies, saving {} bytes",
+                        summary.notes, summary.bodies, summary.bytes_saved
+                    );
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to deduplicate notes: {e}");
+                    std::process::exit(1);
+                }
             }
-
-            con
         }
-        None => {
-            let con =
-                rusqlite::Connection::open_in_memory().expect("Unable to Connect to Database");
-
-            // Initialize database only if flag is set
-            if cli.init_db {
-                let init_sql = include_str!("../sql/init.sql");
-                con.execute_batch(init_sql)
-                    .expect("Failed to initialize database");
+        Some(Commands::IntegrityCheck { quick }) => {
+            let Some(path) = &cli.database else {
+                eprintln!("Error: Database path is required for integrity-check command");
+                std::process::exit(1);
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            match database::Database::new(con, None).integrity_check(*quick) {
+                Ok(problems) if problems.is_empty() => {
+                    println!("ok");
+                    std::process::exit(0);
+                }
+                Ok(problems) => {
+                    for problem in &problems {
+                        println!("{problem}");
+                    }
+                    eprintln!("Error: {} problems found in {path}", problems.len());
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to check the database: {e}");
+                    std::process::exit(1);
+                }
             }
-
-            con
         }
+        Some(Commands::Migrate) => {
+            let Some(path) = &cli.database else {
+                eprintln!("Error: Database path is required for migrate command");
+                std::process::exit(1);
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            // Timestamps from before UTC are read in users' zones, else this one
+            let timezone = Tz::from_str(&cli.timezone).unwrap_or(chrono_tz::Australia::Sydney);
+            let db = database::Database::new(con, Some(timezone));
+            let state = match db.schema_state() {
+                Ok(state) => state,
+                Err(e) => {
+                    eprintln!("Error: Unable to read the database schema: {e}");
+                    std::process::exit(1);
+                }
+            };
+            match state {
+                database::SchemaState::Current => {
+                    println!(
+                        "{path} is up to date (schema version {})",
+                        database::SCHEMA_VERSION
+                    );
+                    std::process::exit(0);
+                }
+                database::SchemaState::Older(_) => {}
+                database::SchemaState::Empty | database::SchemaState::Newer(_) => {
+                    let problem = state.problem().unwrap_or_default();
+                    eprintln!("Error: Unable to migrate: {problem}");
+                    std::process::exit(1);
+                }
+            }
+            match db.migrate() {
+                Ok(done) => {
+                    for step in done {
+                        println!("{step}");
+                    }
+                    println!(
+                        "Migrated {path} to schema version {}",
+                        database::SCHEMA_VERSION
+                    );
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to migrate the database: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Snapshot { dest, tag }) => {
+            let Some(path) = &cli.database else {
+                eprintln!("Error: Database path is required for snapshot command");
+                std::process::exit(1);
+            };
+            if Path::new(dest).exists() {
+                eprintln!("Error: {dest} already exists");
+                std::process::exit(1);
+            }
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            let db = database::Database::new(con, None);
+            match db.snapshot(Path::new(dest), key.as_deref(), tag.as_deref()) {
+                Ok(()) => {
+                    match tag {
+                        Some(tag) => println!("Copied {path} to {dest} (tagged {tag:?})"),
+                        None => println!("Copied {path} to {dest}"),
+                    }
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to copy {path} to {dest}: {e}");
+                    // Don't leave a partial copy that looks like a snapshot
+                    let _ = std::fs::remove_file(dest);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::PurgeDeleted { older_than }) => {
+            let Some(path) = &cli.database else {
+                eprintln!("Error: Database path is required for purge-deleted command");
+                std::process::exit(1);
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            let timezone = Tz::from_str(&cli.timezone).unwrap_or(chrono_tz::Australia::Sydney);
+            match database::Database::new(con, Some(timezone)).purge_deleted(*older_than) {
+                Ok((folders, notes)) => {
+                    println!("Purged {notes} notes and {folders} folders");
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to purge deleted notes: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::PurgeHistory {
+            keep_versions,
+            keep_days,
+        }) => {
+            let Some(path) = &cli.database else {
+                eprintln!("Error: Database path is required for purge-history command");
+                std::process::exit(1);
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            let retention = database::HistoryRetention {
+                keep_versions: *keep_versions,
+                keep_days: *keep_days,
+            };
+            match database::Database::new(con, None).purge_history(&retention) {
+                Ok(purged) => {
+                    println!("Purged {purged} versions from history");
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to purge history: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Share {
+            folder,
+            user,
+            write,
+        }) => {
+            let access = if *write { Access::Write } else { Access::Read };
+            share_folder(&cli, key.as_deref(), folder, user, Some(access));
+        }
+        Some(Commands::Unshare { folder, user }) => {
+            share_folder(&cli, key.as_deref(), folder, user, None);
+        }
+        Some(Commands::User {
+            command:
+                UserCommand::Add {
+                    user,
+                    display_name,
+                    timezone,
+                },
+        }) => {
+            let Some(path) = &cli.database else {
+                eprintln!("Error: Database path is required to add a user");
+                std::process::exit(1);
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            let profile = UserProfile {
+                display_name: display_name.as_deref(),
+                timezone: *timezone,
+            };
+            match database::Database::new(con, None).add_user(user, profile) {
+                Ok(true) => {
+                    println!("Added user {user}");
+                    std::process::exit(0);
+                }
+                Ok(false) => {
+                    eprintln!("Error: User {user} already exists");
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to add user {user}: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Tag { command }) => {
+            tag(&cli, key.as_deref(), command);
+        }
+        Some(Commands::SavedSearch { command }) => {
+            saved_search(&cli, key.as_deref(), command);
+        }
+        Some(Commands::Search {
+            query,
+            phrase,
+            near,
+            column,
+            limit,
+        }) => {
+            let (Some(path), Some(user_id)) = (&cli.database, &cli.user_id) else {
+                eprintln!("Error: Database path and --user-id are required to search");
+                std::process::exit(1);
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            let db = database::Database::new(con, None);
+            let syntax = database::QuerySyntax {
+                phrase: *phrase,
+                near: *near,
+                column: column.clone(),
+            };
+            let query = database::fts_query(query, &syntax);
+            match db.search_notes_ranked(&query, user_id, *limit) {
+                Ok(matches) => {
+                    // bm25 scores are negative, lower is better
+                    for (note, score) in matches {
+                        let path = db
+                            .get_note_path_by_id(&note.id)
+                            .ok()
+                            .flatten()
+                            .unwrap_or(note.title);
+                        println!("{:>8.2}  {path}", -score);
+                    }
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: Search {query:?} failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::ExportHtml { dir }) => {
+            let (Some(path), Some(user_id)) = (&cli.database, &cli.user_id) else {
+                eprintln!("Error: Database path and --user-id are required to export notes");
+                std::process::exit(1);
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            let db = database::Database::new(con, None);
+            match site::export(&db, user_id, dir) {
+                Ok(summary) => {
+                    println!(
+                        "Exported {} pages, {} other files and {} folder indexes to {}",
+                        summary.pages,
+                        summary.files,
+                        summary.indexes,
+                        dir.display()
+                    );
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to export to {}: {e}", dir.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::ExportZip { file, folder }) => {
+            let (Some(path), Some(user_id)) = (&cli.database, &cli.user_id) else {
+                eprintln!("Error: Database path and --user-id are required to export notes");
+                std::process::exit(1);
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            let db = database::Database::new(con, None);
+            match zip::export(&db, user_id, folder.as_deref(), file) {
+                Ok(summary) => {
+                    println!(
+                        "Exported {} notes and {} folders to {}",
+                        summary.notes,
+                        summary.folders,
+                        file.display()
+                    );
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to export to {}: {e}", file.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Graph { format, links_only }) => {
+            let (Some(path), Some(user_id)) = (&cli.database, &cli.user_id) else {
+                eprintln!("Error: Database path and --user-id are required to export a graph");
+                std::process::exit(1);
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            match database::Database::new(con, None).link_graph(user_id) {
+                Ok(link_graph) => {
+                    if format == "graphml" {
+                        print!("{}", graph::to_graphml(&link_graph, !links_only));
+                    } else {
+                        print!("{}", graph::to_dot(&link_graph, !links_only));
+                    }
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to read the notes of {user_id}: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Grep {
+            pattern,
+            ignore_case,
+            fixed_strings,
+            color,
+        }) => {
+            // Exit codes as grep's: 0 if a line matched, 1 if none, 2 on errors
+            let (Some(path), Some(user_id)) = (&cli.database, &cli.user_id) else {
+                eprintln!("Error: Database path and --user-id are required to grep");
+                std::process::exit(2);
+            };
+            let regex = if *fixed_strings {
+                grep::Regex::literal(pattern, *ignore_case)
+            } else {
+                match grep::Regex::new(pattern, *ignore_case) {
+                    Ok(regex) => regex,
+                    Err(e) => {
+                        eprintln!("Error: Invalid pattern {pattern:?}: {e}");
+                        std::process::exit(2);
+                    }
+                }
+            };
+            let color = match color.as_str() {
+                "always" => true,
+                "never" => false,
+                _ => std::io::IsTerminal::is_terminal(&std::io::stdout()),
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            let db = database::Database::new(con, None);
+            // Only notes with the pattern's words can match, but patterns
+            // without any need every note scanned
+            let notes = match regex.fts_query() {
+                Some(query) => db
+                    .search_notes(&query, user_id)
+                    .or_else(|_| db.list_notes(user_id)),
+                None => db.list_notes(user_id),
+            };
+            let notes = match notes {
+                Ok(notes) => notes,
+                Err(e) => {
+                    eprintln!("Error: Unable to read the notes of {user_id}: {e}");
+                    std::process::exit(2);
+                }
+            };
+            let mut notes: Vec<_> = notes
+                .into_iter()
+                .map(|note| {
+                    let path = db
+                        .get_note_path_by_id(&note.id)
+                        .ok()
+                        .flatten()
+                        .unwrap_or_else(|| note.title.clone());
+                    (path, note)
+                })
+                .collect();
+            notes.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let mut matched = false;
+            for (path, note) in &notes {
+                for (number, line) in note.content.lines().enumerate() {
+                    let ranges = regex.find_all(line);
+                    if ranges.is_empty() {
+                        continue;
+                    }
+                    matched = true;
+                    let snippet = grep::snippet(line, &ranges, color);
+                    println!("{path}:{}: {snippet}", number + 1);
+                }
+            }
+            std::process::exit(if matched { 0 } else { 1 });
+        }
+        Some(Commands::SetTimezone { zone }) => {
+            let (Some(path), Some(user_id)) = (&cli.database, &cli.user_id) else {
+                eprintln!("Error: Database path and --user-id are required to set a timezone");
+                std::process::exit(1);
+            };
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            match database::Database::new(con, None).set_user_timezone(user_id, *zone) {
+                Ok(()) => {
+                    match zone {
+                        Some(zone) => println!("Times of {user_id} are now shown in {zone}"),
+                        None => println!("Times of {user_id} follow --timezone again"),
+                    }
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: Unable to set the timezone of {user_id}: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Selftest) => {
+            let passed = selftest::run();
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        None => {}
+    }
+
+    // Without a subcommand clap has already required both
+    let (Some(mountpoint), Some(user_id)) = (cli.mountpoint, cli.user_id) else {
+        unreachable!("mountpoint and --user-id are required");
     };
 
     // Parse the timezone
@@ -131,18 +1475,243 @@ fn main() {
         }
     };
 
-    let fs = match ExampleFuseFs::new(con, timezone, cli.user_id) {
+    // Two writable mounts of a database would overwrite each other's
+    // changes; --as-of only reads it
+    let _writer_lock = match (&cli.database, cli.as_of) {
+        (Some(path), None) if !cli.read_only => match WriterLock::acquire(Path::new(path)) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+
+    // Reopened by the maintenance thread, and when the file is replaced
+    let database_path = cli.database.clone();
+    // Registers the user in a new in-memory database; one reloaded from
+    // --persist-to has them already
+    let mut created = false;
+    let con = match (cli.database, cli.as_of) {
+        (Some(path), Some(as_of)) => {
+            // Rebuilt in memory, so nothing can reach the database on disk
+            let con =
+                rusqlite::Connection::open_in_memory().expect("Unable to Connect to Database");
+            con.execute_batch(include_str!("../sql/init.sql"))
+                .expect("Failed to initialize database");
+            let db = database::Database::new(con, timezone);
+            match db.load_as_of(&path, key.as_deref(), as_of) {
+                Ok(notes) => debug!("as-of: Restored {notes} notes as of {as_of}"),
+                Err(e) => {
+                    eprintln!("Error: Unable to read the notes as of {as_of}: {e}");
+                    std::process::exit(1);
+                }
+            }
+            db.connection
+        }
+        (None, Some(_)) => {
+            eprintln!("Error: --as-of needs a database path");
+            std::process::exit(1);
+        }
+        (Some(path), None) if cli.read_only => {
+            let con = open_read_only(&path, cli.immutable).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            con
+        }
+        (Some(path), None) => {
+            let con = rusqlite::Connection::open(path).expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            con
+        }
+        (None, None) => {
+            let con =
+                rusqlite::Connection::open_in_memory().expect("Unable to Connect to Database");
+            if let Some(key) = &key {
+                unlock_database(&con, key);
+            }
+            match &cli.persist_to {
+                Some(path) if path.exists() => {
+                    let mut db = database::Database::new(con, None);
+                    if let Err(e) = db.restore_from(path, key.as_deref()) {
+                        eprintln!("Error: Unable to load {}: {e}", path.display());
+                        std::process::exit(1);
+                    }
+                    debug!("persist: Loaded the database from {}", path.display());
+                    db.connection
+                }
+                // Nothing to misapply the schema to
+                _ => {
+                    let db = database::Database::new(con, None);
+                    if let Err(e) = db.init_schema() {
+                        eprintln!("Error: Failed to initialize database: {e}");
+                        std::process::exit(1);
+                    }
+                    created = true;
+                    db.connection
+                }
+            }
+        }
+    };
+    // Mounting an older or newer schema would fail in odd places later on
+    let con = check_schema(con);
+
+    // A typo in --user-id would otherwise mount an empty filesystem, and
+    // files created there would belong to a user no one else sees
+    let profile = UserProfile {
+        display_name: cli.display_name.as_deref(),
+        timezone: cli.user_timezone,
+    };
+    let con = check_user(
+        con,
+        &user_id,
+        (created || cli.create_user).then_some(profile),
+    );
+
+    let ignore = ignore_patterns(cli.ignore_file.as_deref(), &cli.ignore);
+    let mut no_editor_temp = cli.no_editor_temp;
+    if cli.dotfile_notes {
+        no_editor_temp.push(DOTFILE_PATTERN.to_string());
+    }
+    let editor_temp_patterns = pattern_list(
+        DEFAULT_EDITOR_TEMP_PATTERNS,
+        &cli.editor_temp,
+        &no_editor_temp,
+    );
+    let system_file_patterns = pattern_list(
+        DEFAULT_SYSTEM_FILE_PATTERNS,
+        &cli.system_file,
+        &cli.no_system_file,
+    );
+    let fs_options = FsOptions {
+        auto_rename: cli.auto_rename,
+        normalize_unicode: cli.normalize_unicode,
+        case_insensitive: cli.case_insensitive,
+        max_name_length: cli.max_name_length,
+        default_syntax: cli.default_syntax,
+        syntax_overrides: cli.syntax_map,
+        only_syntax: cli.only_syntax,
+        filter_tag: cli.filter_tag,
+        ignore,
+        editor_temp_patterns,
+        system_file_patterns,
+        front_matter: cli.front_matter,
+        meta_sidecars: cli.meta_sidecars,
+        recent_limit: cli.recent_limit,
+        recent_by_day: cli.recent_by_day,
+        ranked_searches: cli.ranked_searches,
+        journal: cli.journal,
+        journal_folder: cli.journal_folder,
+        templates_folder: cli.templates_folder,
+        title_from_heading: cli.title_from_heading,
+        file_mode: cli.file_mode,
+        dir_mode: cli.dir_mode,
+        uid: cli.uid,
+        gid: cli.gid,
+        direct_io: cli.direct_io,
+        auto_cache: cli.auto_cache,
+        latency_summary: cli.latency_summary,
+        optimize_on_unmount: cli.optimize_on_unmount,
+        vacuum_on_unmount: cli.vacuum_on_unmount,
+        assets: cli.assets,
+        link_dirs: cli.link_dirs,
+        html_previews: cli.html_previews,
+        pandoc_export: cli.pandoc_export,
+        pandoc: cli.pandoc.clone(),
+        identity: cli.identity.clone(),
+        age: cli.age.clone(),
+        recursive_rmdir: cli.recursive_rmdir,
+        other_users: cli.other_users,
+        shared: cli.shared,
+        max_inodes: cli.max_inodes,
+        attr_ttl: cli.attr_ttl,
+        entry_ttl: cli.entry_ttl,
+        subdir: cli.subdir,
+        read_only: cli.read_only,
+    };
+
+    let mut fs = match ExampleFuseFs::new(con, timezone, user_id, fs_options) {
         Ok(fs) => fs,
         Err(e) => {
             eprintln!("Failed to open database: {e}");
             std::process::exit(1);
         }
     };
+    if let Some(path) = &cli.persist_to {
+        fs.persist_to(path, key.clone());
+    }
+    // A mount --as-of has its own copy, which nothing replaces
+    if let (Some(path), None) = (&database_path, cli.as_of) {
+        fs.reopen_when_replaced(
+            path,
+            database_opener(path, cli.read_only, cli.immutable, key.as_deref()),
+        );
+    }
+
+    // After the filesystem has set up its signal handling, which later threads inherit
+    let retention = database::HistoryRetention {
+        keep_versions: cli.history_keep_versions,
+        keep_days: cli.history_keep_days,
+    };
+    if retention.keep_versions.is_some() || retention.keep_days.is_some() {
+        match (&database_path, cli.as_of) {
+            (Some(_), None) if cli.read_only => {
+                debug!("maintenance: Read-only mount, leaving the history to the writer")
+            }
+            (Some(path), None) => {
+                maintenance::spawn(
+                    database_opener(path, false, false, key.as_deref()),
+                    retention,
+                );
+            }
+            // Nothing that outlives the mount to purge
+            _ => debug!("maintenance: History is not kept, not purging"),
+        }
+    }
+    if let Some(dir) = &cli.backup_dir {
+        match (&database_path, cli.as_of) {
+            (Some(path), None) => {
+                backups::spawn(
+                    database_opener(path, cli.read_only, cli.immutable, key.as_deref()),
+                    backups::BackupPolicy {
+                        dir: dir.clone(),
+                        stem: backups::backup_stem(Path::new(path)),
+                        interval: Duration::from_secs(cli.backup_interval * 60),
+                        keep: cli.backup_keep as usize,
+                        key: key.clone(),
+                    },
+                );
+            }
+            // An in-memory database or a copy --as-of: nothing that outlives the mount
+            _ => debug!("backups: Nothing to back up, ignoring --backup-dir"),
+        }
+    }
 
-    let options = vec![MountOption::FSName("fuse_ecample".to_string())];
+    let mut options = vec![MountOption::FSName("fuse_ecample".to_string())];
+    if cli.as_of.is_some() || cli.read_only {
+        options.push(MountOption::RO);
+    }
+    // Other FUSE implementations refuse options they don't know
+    if cfg!(target_os = "macos") {
+        if let Some(name) = &cli.volname {
+            options.push(MountOption::CUSTOM(format!("volname={name}")));
+        }
+        if cli.noappledouble {
+            options.push(MountOption::CUSTOM("noappledouble".to_string()));
+        }
+    } else if cli.volname.is_some() || cli.noappledouble {
+        debug!("mount: Ignoring --volname and --noappledouble, which need macFUSE");
+    }
     // These require specific behaviour in  /etc/fuse.conf because umount requires root
     // root is not the user so it gets tricky
     // options.push(MountOption::AutoUnmount);
     // options.push(MountOption::AllowRoot);
-    fuser::mount2(fs, cli.mountpoint, &options).unwrap();
+    let notifier = fs.notifier_slot();
+    let mut session = fuser::Session::new(fs, mountpoint, &options).unwrap();
+    let _ = notifier.set(session.notifier());
+    session.run().unwrap();
 }
@@ -0,0 +1,49 @@
+use std::thread;
+use std::time::Duration;
+
+use rusqlite::Connection;
+
+use crate::database::{Database, HistoryRetention};
+
+/// How often the history is purged while mounted
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long a purge waits for the mount's own writes to finish
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Purge the history to `retention` now and then hourly, on a thread of its
+/// own. Each purge has a connection of its own from `open`, so it reaches
+/// whatever file is at the database path then (see
+/// `ExampleFuseFs::reopen_when_replaced`), and none is held in between.
+pub fn spawn(
+    open: impl Fn() -> rusqlite::Result<Connection> + Send + 'static,
+    retention: HistoryRetention,
+) {
+    let spawned = thread::Builder::new()
+        .name("maintenance".to_string())
+        .spawn(move || {
+            loop {
+                match purge(&open, &retention) {
+                    Ok(0) => {}
+                    Ok(purged) => {
+                        debug!("maintenance: Purged {purged} versions from history")
+                    }
+                    Err(e) => error!("maintenance: Unable to purge history: {e}"),
+                }
+                thread::sleep(PURGE_INTERVAL);
+            }
+        });
+    if let Err(e) = spawned {
+        error!("maintenance: Unable to start thread, history won't be purged: {e}");
+    }
+}
+
+/// Purge the history once, on a new connection
+fn purge(
+    open: &impl Fn() -> rusqlite::Result<Connection>,
+    retention: &HistoryRetention,
+) -> rusqlite::Result<usize> {
+    let connection = open()?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    Database::new(connection, None).purge_history(retention)
+}
@@ -0,0 +1,403 @@
+/// Minimal Markdown to HTML renderer for the `.html` companion files
+///
+/// Covers what notes mostly use: ATX headings, paragraphs, fenced code,
+/// block quotes, flat lists, rules, and inline code, emphasis, links and
+/// images. Anything else is shown as (escaped) text.
+pub fn to_html(markdown: &str) -> String {
+    render(markdown, None)
+}
+
+/// A full page around the rendered `markdown`
+pub fn to_html_page(title: &str, markdown: &str) -> String {
+    page(title, &to_html(markdown))
+}
+
+/// Where links between notes lead, for pages rendered to be read away from
+/// the mount (`export-html`)
+pub trait Links {
+    /// href of the note titled `title`, for a `[[title]]` wikilink
+    fn wikilink(&self, title: &str) -> Option<String>;
+    /// href to use for a link to `target`, if it names another note
+    fn link(&self, target: &str) -> Option<String>;
+}
+
+/// Like `to_html_page`, with `[[wikilinks]]` rendered as links (or marked
+/// missing) and links to notes led to where `links` says
+pub fn to_html_page_with_links(title: &str, markdown: &str, links: &dyn Links) -> String {
+    page(title, &render(markdown, Some(links)))
+}
+
+/// A full page with `body` (HTML)
+pub fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n{body}</body>\n</html>\n",
+        escape(title)
+    )
+}
+
+fn render(markdown: &str, links: Option<&dyn Links>) -> String {
+    let mut out = String::new();
+    render_blocks(&mut out, &markdown.lines().collect::<Vec<_>>(), links, 0);
+    out
+}
+
+/// Block quotes nested deeper than this are shown as text: each level is a
+/// stack frame
+const MAX_QUOTE_DEPTH: usize = 32;
+
+/// The kind of list being rendered, if any
+#[derive(PartialEq)]
+enum List {
+    Unordered,
+    Ordered,
+}
+
+/// Render `lines`, inside `depth` block quotes
+fn render_blocks(out: &mut String, lines: &[&str], links: Option<&dyn Links>, depth: usize) {
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut list: Option<List> = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_start();
+
+        // A fence runs to its closing fence (or the end of the note)
+        if let Some(fence) = ["```", "~~~"].into_iter().find(|f| trimmed.starts_with(f)) {
+            flush_paragraph(out, &mut paragraph, links);
+            close_list(out, &mut list);
+            let lang = trimmed[fence.len()..].trim();
+            if lang.is_empty() {
+                out.push_str("<pre><code>");
+            } else {
+                out.push_str(&format!("<pre><code class=\"language-{}\">", escape(lang)));
+            }
+            i += 1;
+            while i < lines.len() && !lines[i].trim_start().starts_with(fence) {
+                out.push_str(&escape(lines[i]));
+                out.push('\n');
+                i += 1;
+            }
+            out.push_str("</code></pre>\n");
+            i += 1;
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            flush_paragraph(out, &mut paragraph, links);
+            close_list(out, &mut list);
+            i += 1;
+            continue;
+        }
+
+        if let Some((level, text)) = heading(trimmed) {
+            flush_paragraph(out, &mut paragraph, links);
+            close_list(out, &mut list);
+            out.push_str(&format!("<h{level}>{}</h{level}>\n", inline(text, links)));
+            i += 1;
+            continue;
+        }
+
+        if is_rule(trimmed) {
+            flush_paragraph(out, &mut paragraph, links);
+            close_list(out, &mut list);
+            out.push_str("<hr>\n");
+            i += 1;
+            continue;
+        }
+
+        // Consecutive quoted lines are rendered as blocks of their own
+        if trimmed.starts_with('>') && depth < MAX_QUOTE_DEPTH {
+            flush_paragraph(out, &mut paragraph, links);
+            close_list(out, &mut list);
+            let mut quoted = Vec::new();
+            while i < lines.len() {
+                let Some(rest) = lines[i].trim_start().strip_prefix('>') else {
+                    break;
+                };
+                quoted.push(rest.strip_prefix(' ').unwrap_or(rest));
+                i += 1;
+            }
+            out.push_str("<blockquote>\n");
+            render_blocks(out, &quoted, links, depth + 1);
+            out.push_str("</blockquote>\n");
+            continue;
+        }
+
+        if let Some((kind, text)) = list_item(trimmed) {
+            flush_paragraph(out, &mut paragraph, links);
+            if list.as_ref() != Some(&kind) {
+                close_list(out, &mut list);
+                out.push_str(if kind == List::Ordered {
+                    "<ol>\n"
+                } else {
+                    "<ul>\n"
+                });
+                list = Some(kind);
+            }
+            // Indented lines that follow continue the item
+            let mut item = vec![text];
+            while i + 1 < lines.len()
+                && lines[i + 1].starts_with([' ', '\t'])
+                && list_item(lines[i + 1].trim_start()).is_none()
+                && !lines[i + 1].trim().is_empty()
+            {
+                i += 1;
+                item.push(lines[i].trim());
+            }
+            out.push_str(&format!("<li>{}</li>\n", inline(&item.join(" "), links)));
+            i += 1;
+            continue;
+        }
+
+        close_list(out, &mut list);
+        paragraph.push(trimmed);
+        i += 1;
+    }
+    flush_paragraph(out, &mut paragraph, links);
+    close_list(out, &mut list);
+}
+
+fn flush_paragraph(out: &mut String, paragraph: &mut Vec<&str>, links: Option<&dyn Links>) {
+    if !paragraph.is_empty() {
+        out.push_str(&format!(
+            "<p>{}</p>\n",
+            inline(&paragraph.join("\n"), links)
+        ));
+        paragraph.clear();
+    }
+}
+
+fn close_list(out: &mut String, list: &mut Option<List>) {
+    match list.take() {
+        Some(List::Ordered) => out.push_str("</ol>\n"),
+        Some(List::Unordered) => out.push_str("</ul>\n"),
+        None => {}
+    }
+}
+
+/// "## Title" as (2, "Title")
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+/// "---", "***" or "___", possibly spaced out
+fn is_rule(line: &str) -> bool {
+    let marks: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3 && ['-', '*', '_'].iter().any(|m| marks.iter().all(|c| c == m))
+}
+
+/// "- item", "* item", "+ item" or "1. item"
+fn list_item(line: &str) -> Option<(List, &str)> {
+    if let Some(text) = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|marker| line.strip_prefix(marker))
+    {
+        return Some((List::Unordered, text));
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let text = line[digits..].strip_prefix(". ")?;
+    (digits > 0).then_some((List::Ordered, text))
+}
+
+/// Render code spans, images, links and emphasis in `text`, and wikilinks
+/// if there are `links` to resolve them
+fn inline(text: &str, links: Option<&dyn Links>) -> String {
+    let mut out = String::new();
+    let mut strong = false;
+    let mut em = false;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '`'
+            && let Some(end) = rest[1..].find('`')
+        {
+            out.push_str(&format!("<code>{}</code>", escape(&rest[1..1 + end])));
+            rest = &rest[end + 2..];
+            continue;
+        }
+        if c == '!'
+            && let Some((alt, src, len)) = link(&rest[1..])
+        {
+            out.push_str(&format!(
+                "<img src=\"{}\" alt=\"{}\">",
+                escape(src),
+                escape(alt)
+            ));
+            rest = &rest[1 + len..];
+            continue;
+        }
+        if let Some(links) = links
+            && let Some((title, shown, len)) = wikilink(rest)
+        {
+            match links.wikilink(title) {
+                Some(href) => out.push_str(&format!(
+                    "<a class=\"wikilink\" href=\"{}\">{}</a>",
+                    escape(&href),
+                    escape(shown)
+                )),
+                None => out.push_str(&format!(
+                    "<span class=\"wikilink missing\">{}</span>",
+                    escape(shown)
+                )),
+            }
+            rest = &rest[len..];
+            continue;
+        }
+        if c == '['
+            && let Some((label, href, len)) = link(rest)
+        {
+            let href = links
+                .and_then(|links| links.link(href))
+                .unwrap_or_else(|| href.to_string());
+            out.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                escape(&href),
+                inline(label, links)
+            ));
+            rest = &rest[len..];
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("**")
+            && (strong || after.contains("**"))
+        {
+            out.push_str(if strong { "</strong>" } else { "<strong>" });
+            strong = !strong;
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix('*')
+            && (em || after.contains('*'))
+        {
+            out.push_str(if em { "</em>" } else { "<em>" });
+            em = !em;
+            rest = after;
+            continue;
+        }
+        if c == '\n' {
+            out.push('\n');
+        } else {
+            out.push_str(&escape(&c.to_string()));
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    if em {
+        out.push_str("</em>");
+    }
+    if strong {
+        out.push_str("</strong>");
+    }
+    out
+}
+
+/// "[[Title|shown]]" (or "[[Title#Heading]]") at the start of `text` as
+/// (title, shown text, length), as `wikilinks::parse` reads them
+fn wikilink(text: &str) -> Option<(&str, &str, usize)> {
+    let after = text.strip_prefix("[[")?;
+    let inner = &after[..after.find("]]")?];
+    if inner.contains('\n') || inner.contains("[[") {
+        return None;
+    }
+    let (target, shown) = inner.split_once('|').unwrap_or((inner, inner));
+    let title = target.split('#').next().unwrap_or("").trim();
+    (!title.is_empty()).then_some((title, shown.trim(), 2 + inner.len() + 2))
+}
+
+/// "[label](target)" at the start of `text` as (label, target, length)
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let after = text.strip_prefix('[')?;
+    let close = after.find("](")?;
+    let label = &after[..close];
+    let target_start = 1 + close + 2;
+    let target_len = text[target_start..].find(')')?;
+    let target = &text[target_start..target_start + target_len];
+    Some((label, target.trim(), target_start + target_len + 1))
+}
+
+/// `text` with the characters HTML (and XML) give a meaning escaped
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks() {
+        let markdown = "# Title\n\nSome *text* with **bold** and `a<b`.\nSecond line.\n\n\
+                        - one\n- [two](b.html)\n  continued\n\n1. first\n\n\
+                        > quoted\n\n```rust\nfn main() {}\n```\n---\n";
+        assert_eq!(
+            to_html(markdown),
+            "<h1>Title</h1>\n\
+             <p>Some <em>text</em> with <strong>bold</strong> and <code>a&lt;b</code>.\nSecond line.</p>\n\
+             <ul>\n<li>one</li>\n<li><a href=\"b.html\">two</a> continued</li>\n</ul>\n\
+             <ol>\n<li>first</li>\n</ol>\n\
+             <blockquote>\n<p>quoted</p>\n</blockquote>\n\
+             <pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n\
+             <hr>\n"
+        );
+    }
+
+    #[test]
+    fn test_deep_quotes() {
+        let html = to_html(&format!("{} hi", ">".repeat(100_000)));
+        assert_eq!(html.matches("<blockquote>").count(), MAX_QUOTE_DEPTH);
+        assert!(html.contains("&gt; hi"));
+    }
+
+    struct TestLinks;
+
+    impl Links for TestLinks {
+        fn wikilink(&self, title: &str) -> Option<String> {
+            (title == "Known").then(|| "known.html".to_string())
+        }
+
+        fn link(&self, target: &str) -> Option<String> {
+            target
+                .strip_suffix(".md")
+                .map(|stem| format!("{stem}.html"))
+        }
+    }
+
+    #[test]
+    fn test_links() {
+        assert_eq!(
+            inline(
+                "[[Known|this]], [[Known#Part]], [[Gone]] and [x](x.md), [y](y.png)",
+                Some(&TestLinks)
+            ),
+            "<a class=\"wikilink\" href=\"known.html\">this</a>, \
+             <a class=\"wikilink\" href=\"known.html\">Known#Part</a>, \
+             <span class=\"wikilink missing\">Gone</span> and \
+             <a href=\"x.html\">x</a>, <a href=\"y.png\">y</a>"
+        );
+        // Left alone without links to resolve them
+        assert_eq!(inline("[[Known]]", None), "[[Known]]");
+    }
+
+    #[test]
+    fn test_inline_leaves_unmatched_marks() {
+        assert_eq!(
+            inline("2 * 3 <tag> ![img](a.png) [x]", None),
+            "2 * 3 &lt;tag&gt; <img src=\"a.png\" alt=\"img\"> [x]"
+        );
+    }
+}
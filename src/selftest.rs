@@ -0,0 +1,214 @@
+//! `selftest`: mount a scratch database and check that everyday file
+//! operations work on this kernel
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use fuser::MountOption;
+
+use crate::fuse_fs::{ExampleFuseFs, FsOptions};
+
+/// A check run against the mountpoint, in order; each builds on the ones before
+type Check = fn(&Path) -> Result<(), String>;
+
+const CHECKS: [(&str, Check); 10] = [
+    ("mkdir", mkdir),
+    ("create and write", create_and_write),
+    ("read", read),
+    ("append", append),
+    ("truncate", truncate),
+    ("list directory", list_directory),
+    ("rename file", rename_file),
+    ("rename directory", rename_directory),
+    ("remove file", remove_file),
+    ("remove directory", remove_directory),
+];
+
+/// Checks run against a mount listing companions next to each note
+/// (`--assets`, `--link-dirs` and `--html-previews`)
+const COMPANION_CHECKS: [(&str, Check); 1] = [("remove directory recursively", remove_recursively)];
+
+/// Run every check, printing PASS/FAIL for each; true if all passed
+pub fn run() -> bool {
+    let scratch =
+        std::env::temp_dir().join(format!("sqlite_fuse-selftest-{}", uuid::Uuid::new_v4()));
+    let companions = FsOptions {
+        assets: true,
+        link_dirs: true,
+        html_previews: true,
+        ..FsOptions::default()
+    };
+    let passed = run_checks(&scratch.join("plain"), FsOptions::default(), &CHECKS)
+        & run_checks(&scratch.join("companions"), companions, &COMPANION_CHECKS);
+    if let Err(e) = fs::remove_dir_all(&scratch) {
+        error!("selftest: Unable to remove {}: {e}", scratch.display());
+    }
+    passed
+}
+
+/// Run `checks` on a new mount in `scratch`; true if all passed
+fn run_checks(scratch: &Path, options: FsOptions, checks: &[(&str, Check)]) -> bool {
+    let mountpoint = scratch.join("mnt");
+    match mount(scratch, &mountpoint, options) {
+        Ok(session) => {
+            let mut passed = true;
+            for (name, check) in checks {
+                match check(&mountpoint) {
+                    Ok(()) => println!("PASS {name}"),
+                    Err(e) => {
+                        println!("FAIL {name}: {e}");
+                        passed = false;
+                    }
+                }
+            }
+            session.join();
+            passed
+        }
+        Err(e) => {
+            println!("FAIL mount: {e}");
+            false
+        }
+    }
+}
+
+/// Mount a new database in `scratch` at `mountpoint`
+fn mount(
+    scratch: &Path,
+    mountpoint: &Path,
+    options: FsOptions,
+) -> Result<fuser::BackgroundSession, String> {
+    fs::create_dir_all(mountpoint).map_err(|e| format!("creating {mountpoint:?}: {e}"))?;
+    let con = rusqlite::Connection::open(scratch.join("selftest.sqlite"))
+        .map_err(|e| format!("creating database: {e}"))?;
+    con.execute_batch(include_str!("../sql/init.sql"))
+        .map_err(|e| format!("initializing database: {e}"))?;
+    let fs = ExampleFuseFs::new(con, None, "selftest".to_string(), options)
+        .map_err(|e| format!("opening database: {e}"))?;
+    let notifier = fs.notifier_slot();
+    let options = [MountOption::FSName("sqlite_fuse-selftest".to_string())];
+    let session = fuser::spawn_mount2(fs, mountpoint, &options).map_err(|e| format!("{e}"))?;
+    let _ = notifier.set(session.notifier());
+    Ok(session)
+}
+
+fn dir(mnt: &Path) -> PathBuf {
+    mnt.join("selftest")
+}
+
+/// `expected` if `path` holds it, else what it holds instead
+fn expect_content(path: &Path, expected: &str) -> Result<(), String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("reading {path:?}: {e}"))?;
+    if content != expected {
+        return Err(format!("{path:?} holds {content:?}, expected {expected:?}"));
+    }
+    Ok(())
+}
+
+fn expect_missing(path: &Path) -> Result<(), String> {
+    match fs::symlink_metadata(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("checking {path:?}: {e}")),
+        Ok(_) => Err(format!("{path:?} still exists")),
+    }
+}
+
+fn mkdir(mnt: &Path) -> Result<(), String> {
+    fs::create_dir(dir(mnt)).map_err(|e| e.to_string())?;
+    if !fs::metadata(dir(mnt)).map_err(|e| e.to_string())?.is_dir() {
+        return Err("not a directory after mkdir".to_string());
+    }
+    Ok(())
+}
+
+fn create_and_write(mnt: &Path) -> Result<(), String> {
+    fs::write(dir(mnt).join("note.md"), "hello\n").map_err(|e| e.to_string())
+}
+
+fn read(mnt: &Path) -> Result<(), String> {
+    expect_content(&dir(mnt).join("note.md"), "hello\n")
+}
+
+fn append(mnt: &Path) -> Result<(), String> {
+    let path = dir(mnt).join("note.md");
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .map_err(|e| e.to_string())?;
+    file.write_all(b"world\n").map_err(|e| e.to_string())?;
+    drop(file);
+    expect_content(&path, "hello\nworld\n")
+}
+
+fn truncate(mnt: &Path) -> Result<(), String> {
+    let path = dir(mnt).join("note.md");
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .map_err(|e| e.to_string())?;
+    file.set_len(5).map_err(|e| e.to_string())?;
+    drop(file);
+    expect_content(&path, "hello")
+}
+
+fn list_directory(mnt: &Path) -> Result<(), String> {
+    let mut names = fs::read_dir(dir(mnt))
+        .map_err(|e| e.to_string())?
+        .map(|entry| entry.map(|e| e.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    names.sort();
+    if names != ["note.md"] {
+        return Err(format!("listed {names:?}, expected [\"note.md\"]"));
+    }
+    Ok(())
+}
+
+fn rename_file(mnt: &Path) -> Result<(), String> {
+    let (from, to) = (dir(mnt).join("note.md"), dir(mnt).join("renamed.md"));
+    fs::rename(&from, &to).map_err(|e| e.to_string())?;
+    expect_missing(&from)?;
+    expect_content(&to, "hello")
+}
+
+fn rename_directory(mnt: &Path) -> Result<(), String> {
+    let to = mnt.join("selftest-renamed");
+    fs::rename(dir(mnt), &to).map_err(|e| e.to_string())?;
+    expect_missing(&dir(mnt))?;
+    expect_content(&to.join("renamed.md"), "hello")?;
+    // Back, so the remaining checks find it
+    fs::rename(&to, dir(mnt)).map_err(|e| e.to_string())
+}
+
+fn remove_file(mnt: &Path) -> Result<(), String> {
+    let path = dir(mnt).join("renamed.md");
+    fs::remove_file(&path).map_err(|e| e.to_string())?;
+    expect_missing(&path)
+}
+
+fn remove_directory(mnt: &Path) -> Result<(), String> {
+    fs::remove_dir(dir(mnt)).map_err(|e| e.to_string())?;
+    expect_missing(&dir(mnt))
+}
+
+/// `rm -r` of a folder whose notes have companions, which are listed before
+/// the notes are removed and removed after them
+fn remove_recursively(mnt: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir(mnt).join("sub")).map_err(|e| e.to_string())?;
+    fs::write(dir(mnt).join("note.md"), "see [[other]]\n").map_err(|e| e.to_string())?;
+    fs::write(dir(mnt).join("sub/other.md"), "hello\n").map_err(|e| e.to_string())?;
+    fs::write(dir(mnt).join("note_assets/image.png"), b"png").map_err(|e| e.to_string())?;
+    for companion in ["note_assets", "note.links", "note.html"] {
+        fs::symlink_metadata(dir(mnt).join(companion))
+            .map_err(|e| format!("{companion} isn't listed: {e}"))?;
+    }
+    let status = std::process::Command::new("rm")
+        .arg("-r")
+        .arg(dir(mnt))
+        .status()
+        .map_err(|e| format!("running rm: {e}"))?;
+    if !status.success() {
+        return Err(format!("rm -r exited with {status}"));
+    }
+    expect_missing(&dir(mnt))
+}
@@ -0,0 +1,297 @@
+//! `export-html`: a user's notes as a static site, browsable without the
+//! mount or a server
+//!
+//! Markdown notes become pages ("Projects/todo.md" → "Projects/todo.html")
+//! whose `[[wikilinks]]` and links to other notes lead to those notes'
+//! pages; other notes are written as they are. Each folder gets an
+//! "index.html" listing what is in it.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+
+use crate::database::{Database, LinkGraph};
+use crate::markdown::{self, Links, escape};
+use crate::syntax::SyntaxMap;
+
+/// Name of the listing page of each folder
+const INDEX_PAGE: &str = "index.html";
+
+/// What `export` wrote
+#[derive(Debug, Default, PartialEq)]
+pub struct ExportSummary {
+    /// Markdown notes rendered to pages
+    pub pages: usize,
+    /// Other notes, written as they are
+    pub files: usize,
+    /// Folder listings, the top one included
+    pub indexes: usize,
+}
+
+/// Folder part of `path` ("" at the top)
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// Percent-encode a path component for an href
+fn encode(component: &str) -> String {
+    let mut out = String::new();
+    for byte in component.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+/// href leading from the page at `from` to the file at `to`, both relative
+/// to the top of the site
+fn relative_href(from: &str, to: &str) -> String {
+    let from_dirs: Vec<&str> = parent(from).split('/').filter(|c| !c.is_empty()).collect();
+    let to_parts: Vec<&str> = to.split('/').collect();
+    let (to_file, to_dirs) = to_parts.split_last().expect("split yields one part");
+    let common = from_dirs
+        .iter()
+        .zip(to_dirs)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut parts: Vec<String> = vec!["..".to_string(); from_dirs.len() - common];
+    parts.extend(to_dirs[common..].iter().map(|c| encode(c)));
+    parts.push(encode(to_file));
+    parts.join("/")
+}
+
+/// `target` (relative to the folder `dir`) as a path from the top, unless
+/// it leads above it
+fn resolve_relative(dir: &str, target: &str) -> Option<String> {
+    let mut parts: Vec<&str> = dir.split('/').filter(|c| !c.is_empty()).collect();
+    for component in target.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            component => parts.push(component),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Where the files of the site are, for the links of one page
+struct SiteLinks<'a> {
+    /// Path of the page rendered
+    page: &'a str,
+    /// Page of each note path
+    pages: &'a HashMap<String, String>,
+    /// Pages of the notes with each title
+    titled: &'a HashMap<String, Vec<String>>,
+}
+
+impl Links for SiteLinks<'_> {
+    fn wikilink(&self, title: &str) -> Option<String> {
+        let candidates = self.titled.get(title)?;
+        // Titles repeat across folders: the note next to this one wins
+        let page = candidates
+            .iter()
+            .find(|page| parent(page) == parent(self.page))
+            .unwrap_or(&candidates[0]);
+        Some(relative_href(self.page, page))
+    }
+
+    fn link(&self, target: &str) -> Option<String> {
+        if target.contains("://") || target.starts_with(['#', '/']) || target.starts_with("mailto:")
+        {
+            return None;
+        }
+        let (path, fragment) = match target.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment)),
+            None => (target, None),
+        };
+        let path = resolve_relative(parent(self.page), &path.replace("%20", " "))?;
+        let page = self.pages.get(&path)?;
+        let href = relative_href(self.page, page);
+        Some(match fragment {
+            Some(fragment) => format!("{href}#{fragment}"),
+            None => href,
+        })
+    }
+}
+
+/// Page of the Markdown note at `path`: its extension replaced by ".html",
+/// unless another file of the site or a folder listing has that name
+fn page_path(path: &str, taken: &HashSet<String>) -> String {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let page = if dir.is_empty() {
+        format!("{stem}.html")
+    } else {
+        format!("{dir}/{stem}.html")
+    };
+    if format!("{stem}.html") == INDEX_PAGE || taken.contains(&page) {
+        format!("{path}.html")
+    } else {
+        page
+    }
+}
+
+/// The listing of the folder at `dir` ("" for the top) in `graph`
+fn index_page(dir: &str, graph: &LinkGraph, pages: &HashMap<String, String>) -> String {
+    let index = if dir.is_empty() {
+        INDEX_PAGE.to_string()
+    } else {
+        format!("{dir}/{INDEX_PAGE}")
+    };
+    let title = if dir.is_empty() { "Notes" } else { dir };
+    let mut body = format!("<h1>{}</h1>\n<ul>\n", escape(title));
+    if !dir.is_empty() {
+        let up = if parent(dir).is_empty() {
+            INDEX_PAGE.to_string()
+        } else {
+            format!("{}/{INDEX_PAGE}", parent(dir))
+        };
+        body.push_str(&format!(
+            "<li><a href=\"{}\">..</a></li>\n",
+            escape(&relative_href(&index, &up))
+        ));
+    }
+    for (_, _, path) in graph
+        .folders
+        .iter()
+        .filter(|(_, _, path)| parent(path) == dir)
+    {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        body.push_str(&format!(
+            "<li><a href=\"{}\">{}/</a></li>\n",
+            escape(&relative_href(&index, &format!("{path}/{INDEX_PAGE}"))),
+            escape(name)
+        ));
+    }
+    for (_, _, path) in graph
+        .notes
+        .iter()
+        .filter(|(_, _, path)| parent(path) == dir)
+    {
+        let Some(page) = pages.get(path) else {
+            continue;
+        };
+        let name = path.rsplit('/').next().unwrap_or(path);
+        body.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            escape(&relative_href(&index, page)),
+            escape(name)
+        ));
+    }
+    body.push_str("</ul>\n");
+    markdown::page(title, &body)
+}
+
+/// Write `data` to `path` in `dir`, creating the folders it is in
+fn write(dir: &Path, path: &str, data: &[u8]) -> io::Result<()> {
+    let file = dir.join(path);
+    if let Some(folder) = file.parent() {
+        std::fs::create_dir_all(folder)?;
+    }
+    std::fs::write(file, data)
+}
+
+/// Write the site of `user_id`'s notes into `dir`, replacing files already
+/// there under the same names
+pub fn export(db: &Database, user_id: &str, dir: &Path) -> io::Result<ExportSummary> {
+    let graph = db.link_graph(user_id).map_err(io::Error::other)?;
+    let syntax_map = SyntaxMap::from_rows(db.list_syntax_extensions().map_err(io::Error::other)?);
+    let markdown_syntax = syntax_map.syntax_for_extension("md");
+
+    let mut notes = Vec::new();
+    for (id, _, path) in &graph.notes {
+        // Deleted since the graph was read
+        if let Some(note) = db.get_note_by_id(id).map_err(io::Error::other)? {
+            // Encrypted notes are written as the ciphertext they are stored as
+            let rendered = note.syntax == markdown_syntax && !note.extensionless && !note.encrypted;
+            notes.push((path.as_str(), note, rendered));
+        }
+    }
+
+    // Files written as they are keep their names; pages take what is left
+    let mut taken: HashSet<String> = notes
+        .iter()
+        .filter(|(_, _, rendered)| !rendered)
+        .map(|(path, _, _)| path.to_string())
+        .collect();
+    let mut pages = HashMap::new();
+    let mut titled: HashMap<String, Vec<String>> = HashMap::new();
+    for (path, note, rendered) in &notes {
+        let page = if *rendered {
+            let page = page_path(path, &taken);
+            taken.insert(page.clone());
+            page
+        } else {
+            path.to_string()
+        };
+        titled
+            .entry(note.title.clone())
+            .or_default()
+            .push(page.clone());
+        pages.insert(path.to_string(), page);
+    }
+
+    let mut summary = ExportSummary::default();
+    // A note of the user's own named "index.html" replaces a listing
+    let folders = std::iter::once("").chain(graph.folders.iter().map(|(_, _, path)| path.as_str()));
+    for folder in folders {
+        let index = if folder.is_empty() {
+            INDEX_PAGE.to_string()
+        } else {
+            format!("{folder}/{INDEX_PAGE}")
+        };
+        write(dir, &index, index_page(folder, &graph, &pages).as_bytes())?;
+        summary.indexes += 1;
+    }
+    for (path, note, rendered) in &notes {
+        let page = &pages[*path];
+        if *rendered {
+            let links = SiteLinks {
+                page,
+                pages: &pages,
+                titled: &titled,
+            };
+            let html = markdown::to_html_page_with_links(&note.title, &note.content, &links);
+            write(dir, page, html.as_bytes())?;
+            summary.pages += 1;
+        } else {
+            write(dir, page, note.content.as_bytes())?;
+            summary.files += 1;
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_href() {
+        assert_eq!(relative_href("a.html", "b.html"), "b.html");
+        assert_eq!(relative_href("P/a.html", "b.html"), "../b.html");
+        assert_eq!(
+            relative_href("P/a.html", "P/Q/my note.html"),
+            "Q/my%20note.html"
+        );
+        assert_eq!(relative_href("P/Q/a.html", "P/R/b.html"), "../R/b.html");
+        assert_eq!(
+            resolve_relative("P/Q", "../R/./b.md").as_deref(),
+            Some("P/R/b.md")
+        );
+        assert_eq!(resolve_relative("P", "../../b.md"), None);
+    }
+
+    #[test]
+    fn test_page_path() {
+        let taken = HashSet::from(["P/a.html".to_string()]);
+        assert_eq!(page_path("P/todo.md", &taken), "P/todo.html");
+        assert_eq!(page_path("P/a.md", &taken), "P/a.md.html");
+        assert_eq!(page_path("index.md", &taken), "index.md.html");
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+/// In-memory copy of the `syntax_extensions` table
+///
+/// Maps file name extensions to the `syntax` stored for a note and back.
+/// Extensions that are not in the table are stored verbatim as the syntax,
+/// and syntaxes without a preferred extension are shown verbatim, which is
+/// exactly what the path views in sql/init.sql do.
+#[derive(Debug, Clone, Default)]
+pub struct SyntaxMap {
+    by_extension: HashMap<String, String>,
+    by_syntax: HashMap<String, String>,
+}
+
+impl SyntaxMap {
+    /// Build the map from (extension, syntax, preferred) rows
+    pub fn from_rows(rows: Vec<(String, String, bool)>) -> Self {
+        let mut map = SyntaxMap::default();
+        for (extension, syntax, preferred) in rows {
+            let extension = extension.to_ascii_lowercase();
+            if preferred {
+                map.by_syntax.insert(syntax.clone(), extension.clone());
+            }
+            map.by_extension.insert(extension, syntax);
+        }
+        map
+    }
+
+    /// Syntax to store for a file name extension (matched case-insensitively)
+    pub fn syntax_for_extension(&self, extension: &str) -> String {
+        match self.by_extension.get(&extension.to_ascii_lowercase()) {
+            Some(syntax) => syntax.clone(),
+            None => extension.to_string(),
+        }
+    }
+
+    /// Extension to show in file names for a stored syntax
+    pub fn extension_for_syntax<'a>(&'a self, syntax: &'a str) -> &'a str {
+        match self.by_syntax.get(syntax) {
+            Some(extension) => extension,
+            None => syntax,
+        }
+    }
+}
+
+/// Parse an `EXT=SYNTAX` command line mapping
+pub fn parse_mapping(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((extension, syntax)) if !extension.is_empty() && !syntax.is_empty() => Ok((
+            extension.trim_start_matches('.').to_string(),
+            syntax.to_string(),
+        )),
+        _ => Err(format!("expected EXT=SYNTAX, got '{s}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syntax_map_lookups() {
+        let map = SyntaxMap::from_rows(vec![
+            ("md".to_string(), "md".to_string(), true),
+            ("markdown".to_string(), "md".to_string(), false),
+            ("qmd".to_string(), "quarto".to_string(), true),
+        ]);
+        assert_eq!(map.syntax_for_extension("markdown"), "md");
+        assert_eq!(map.syntax_for_extension("MD"), "md");
+        assert_eq!(map.extension_for_syntax("md"), "md");
+        assert_eq!(map.extension_for_syntax("quarto"), "qmd");
+
+        // Unknown extensions and syntaxes pass through unchanged
+        assert_eq!(map.syntax_for_extension("py"), "py");
+        assert_eq!(map.extension_for_syntax("py"), "py");
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        assert_eq!(
+            parse_mapping(".qmd=quarto").unwrap(),
+            ("qmd".to_string(), "quarto".to_string())
+        );
+        assert!(parse_mapping("md").is_err());
+        assert!(parse_mapping("=md").is_err());
+        assert!(parse_mapping("md=").is_err());
+    }
+}
@@ -0,0 +1,47 @@
+/// Titles named by the `[[wikilinks]]` in `content`, once each, in order of
+/// first appearance
+///
+/// `[[Title|shown text]]` and `[[Title#Heading]]` both link to "Title". Links
+/// don't span lines, and empty ones (`[[]]`, `[[#Heading]]`) are skipped.
+pub fn parse(content: &str) -> Vec<String> {
+    let mut titles: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let mut rest = line;
+        while let Some(start) = rest.find("[[") {
+            let after = &rest[start + 2..];
+            let Some(end) = after.find("]]") else {
+                break;
+            };
+            let inner = &after[..end];
+            // "[[[[a]]" links to "a": restart at the innermost opening
+            if let Some(nested) = inner.rfind("[[") {
+                rest = &after[nested..];
+                continue;
+            }
+            let target = inner.split(['|', '#']).next().unwrap_or("").trim();
+            if !target.is_empty() && !titles.iter().any(|t| t == target) {
+                titles.push(target.to_string());
+            }
+            rest = &after[end + 2..];
+        }
+    }
+    titles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let content = "See [[Alpha]] and [[Beta|the second]].\n\
+                       Also [[Alpha#Intro]], [[ Gamma ]] and [[]].\n\
+                       Unclosed [[Delta\n]] and [[[[Epsilon]]";
+        assert_eq!(parse(content), ["Alpha", "Beta", "Gamma", "Epsilon"]);
+    }
+
+    #[test]
+    fn test_parse_without_links() {
+        assert!(parse("[not a link] [[#Heading]] ]] [[").is_empty());
+    }
+}
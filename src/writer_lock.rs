@@ -0,0 +1,85 @@
+//! The lock a writable mount holds on its database, so that a second one is
+//! refused instead of both caching notes and overwriting each other's changes
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// An exclusive flock(2) on "<database>.lock", held until dropped (or the process
+/// ends, however it ends). The file is left behind; only the lock counts.
+pub struct WriterLock {
+    _file: File,
+}
+
+/// The lock file of the database at `database`
+pub fn lock_path(database: &Path) -> PathBuf {
+    let mut path = database.as_os_str().to_owned();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+impl WriterLock {
+    /// Take the lock of `database`, or say who holds it
+    pub fn acquire(database: &Path) -> Result<Self, String> {
+        let path = lock_path(database);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| format!("Unable to open lock file {}: {e}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::Error(e)) => {
+                return Err(format!("Unable to lock {}: {e}", path.display()));
+            }
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                let holder = match holder.trim() {
+                    "" => String::new(),
+                    pid => format!(" by pid {pid}"),
+                };
+                return Err(format!(
+                    "{} is already mounted writable{holder}; a second mount can only read it (--read-only)",
+                    database.display()
+                ));
+            }
+        }
+        // Read by a second mount, to name the holder in its error
+        let written = file
+            .set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| writeln!(file, "{}", std::process::id()));
+        if let Err(e) = written {
+            error!("lock: Unable to record pid in {}: {e}", path.display());
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_writer_is_refused() {
+        let dir = std::env::temp_dir().join(format!("writer_lock-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let database = dir.join("notes.sqlite");
+        assert_eq!(lock_path(&database), dir.join("notes.sqlite.lock"));
+
+        let first = WriterLock::acquire(&database).unwrap();
+        let refused = WriterLock::acquire(&database).err().unwrap();
+        assert!(
+            refused.contains(&format!("by pid {}", std::process::id())),
+            "{refused}"
+        );
+
+        // Released with the first mount
+        drop(first);
+        assert!(WriterLock::acquire(&database).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
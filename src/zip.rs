@@ -0,0 +1,313 @@
+//! `export-zip`: a user's notes, or one folder of them, as a zip archive,
+//! e.g. to share a snapshot of the notes without exporting a directory
+//!
+//! Notes are stored as they are (uncompressed), under the paths the mount
+//! shows, with their modification times and chmod permissions. Folders get
+//! entries of their own, so empty ones are kept.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+
+use crate::database::Database;
+
+/// Unix file types, as zip archives record them in the mode of an entry
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+/// Permissions of entries that were never chmodded
+const DEFAULT_FILE_MODE: u32 = 0o644;
+const DEFAULT_DIR_MODE: u32 = 0o755;
+
+/// Version of the zip format needed to read the archive (2.0), and made by
+/// a Unix system, so the modes in the external attributes are used
+const VERSION_NEEDED: u16 = 20;
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_NEEDED;
+/// General purpose flag: names are UTF-8
+const UTF8_NAMES: u16 = 1 << 11;
+/// Extra field holding the modification time as a Unix timestamp, which
+/// the DOS time of an entry (local, in 2 s steps) can't tell exactly
+const EXTENDED_TIMESTAMP: u16 = 0x5455;
+
+/// CRC-32 (IEEE) of each byte value
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[n] = crc;
+        n += 1;
+    }
+    table
+};
+
+/// CRC-32 of `data`, as zip records it
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// (time, date) of `time` in MS-DOS form, in local time; zip can't record
+/// times before 1980
+fn dos_time(time: &DateTime<Utc>) -> (u16, u16) {
+    let local = time.with_timezone(&Local);
+    if local.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let dos_time = (local.hour() << 11) | (local.minute() << 5) | (local.second() / 2);
+    let dos_date = (((local.year() - 1980) as u32) << 9) | (local.month() << 5) | local.day();
+    (dos_time as u16, dos_date as u16)
+}
+
+/// Writes a zip archive of stored entries to `W`
+pub struct ZipWriter<W: Write> {
+    out: W,
+    /// Bytes written so far, where the next entry starts
+    offset: u64,
+    /// Central directory headers of the entries written
+    central: Vec<u8>,
+    entries: usize,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(out: W) -> Self {
+        ZipWriter {
+            out,
+            offset: 0,
+            central: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    /// Add the folder `path` (without a trailing '/')
+    pub fn add_dir(&mut self, path: &str, modified: &DateTime<Utc>, mode: u32) -> io::Result<()> {
+        self.add(&format!("{path}/"), &[], modified, S_IFDIR | mode)
+    }
+
+    /// Add the file `path` holding `data`
+    pub fn add_file(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        modified: &DateTime<Utc>,
+        mode: u32,
+    ) -> io::Result<()> {
+        self.add(path, data, modified, S_IFREG | mode)
+    }
+
+    fn add(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        modified: &DateTime<Utc>,
+        mode: u32,
+    ) -> io::Result<()> {
+        let too_large = || io::Error::other(format!("{name} is too large for a zip archive"));
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let offset = u32::try_from(self.offset).map_err(|_| too_large())?;
+        let name_len = u16::try_from(name.len()).map_err(|_| too_large())?;
+        if self.entries == usize::from(u16::MAX) {
+            return Err(io::Error::other("too many notes for a zip archive"));
+        }
+        let (time, date) = dos_time(modified);
+        let crc = crc32(data);
+        let mut extra = Vec::with_capacity(9);
+        extra.extend_from_slice(&EXTENDED_TIMESTAMP.to_le_bytes());
+        extra.extend_from_slice(&5u16.to_le_bytes());
+        // Only the modification time is given
+        extra.push(1);
+        extra.extend_from_slice(
+            &(modified.timestamp().clamp(0, u32::MAX.into()) as u32).to_le_bytes(),
+        );
+
+        // Fields the local and central headers share, from "version needed"
+        let mut common = Vec::with_capacity(26);
+        common.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+        common.extend_from_slice(&UTF8_NAMES.to_le_bytes());
+        // Method: stored
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&time.to_le_bytes());
+        common.extend_from_slice(&date.to_le_bytes());
+        common.extend_from_slice(&crc.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&name_len.to_le_bytes());
+        common.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+
+        let mut local = Vec::with_capacity(30 + name.len() + extra.len());
+        local.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        local.extend_from_slice(&common);
+        local.extend_from_slice(name.as_bytes());
+        local.extend_from_slice(&extra);
+        self.out.write_all(&local)?;
+        self.out.write_all(data)?;
+
+        self.central
+            .extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        self.central
+            .extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+        self.central.extend_from_slice(&common);
+        // Comment length, disk number, internal attributes
+        self.central.extend_from_slice(&[0; 6]);
+        // Unix mode in the high half; MS-DOS's directory bit in the low one
+        let dos_attributes = if name.ends_with('/') { 0x10 } else { 0 };
+        self.central
+            .extend_from_slice(&((mode << 16) | dos_attributes).to_le_bytes());
+        self.central.extend_from_slice(&offset.to_le_bytes());
+        self.central.extend_from_slice(name.as_bytes());
+        self.central.extend_from_slice(&extra);
+
+        self.offset += (local.len() + data.len()) as u64;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Write the central directory, ending the archive
+    pub fn finish(mut self) -> io::Result<W> {
+        let too_large = || io::Error::other("the notes are too large for a zip archive");
+        let offset = u32::try_from(self.offset).map_err(|_| too_large())?;
+        let size = u32::try_from(self.central.len()).map_err(|_| too_large())?;
+        let entries = self.entries as u16;
+        self.out.write_all(&self.central)?;
+        let mut end = Vec::with_capacity(22);
+        end.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        // This disk and the one the central directory starts on
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&size.to_le_bytes());
+        end.extend_from_slice(&offset.to_le_bytes());
+        // Comment length
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// What `export` wrote
+#[derive(Debug, Default, PartialEq)]
+pub struct ZipSummary {
+    pub notes: usize,
+    pub folders: usize,
+}
+
+/// Write `user_id`'s notes to the archive `file`, replacing it, or only
+/// those in `folder` (a path such as "Work/Projects"), which is then the
+/// top entry of the archive
+pub fn export(
+    db: &Database,
+    user_id: &str,
+    folder: Option<&str>,
+    file: &Path,
+) -> io::Result<ZipSummary> {
+    let graph = db.link_graph(user_id).map_err(io::Error::other)?;
+    let folder = folder.map(|folder| folder.trim_matches('/'));
+    if let Some(folder) = folder
+        && !graph.folders.iter().any(|(_, _, path)| path == folder)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no folder {folder}"),
+        ));
+    }
+    // Paths in the archive start at the folder exported
+    let strip = folder.map_or(0, |folder| {
+        folder
+            .rsplit_once('/')
+            .map_or(0, |(parent, _)| parent.len() + 1)
+    });
+    let included = |path: &str| {
+        folder.is_none_or(|folder| {
+            path == folder
+                || path
+                    .strip_prefix(folder)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    };
+
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(file)?));
+    let mut summary = ZipSummary::default();
+    let written = (|| {
+        for (id, _, path) in graph.folders.iter().filter(|(_, _, path)| included(path)) {
+            // Deleted since the graph was read
+            let Some(folder) = db.get_folder_by_id(id, user_id).map_err(io::Error::other)? else {
+                continue;
+            };
+            let mode = folder.mode.map_or(DEFAULT_DIR_MODE, |mode| mode & 0o7777);
+            zip.add_dir(&path[strip..], &folder.updated_at, mode)?;
+            summary.folders += 1;
+        }
+        for (id, _, path) in graph.notes.iter().filter(|(_, _, path)| included(path)) {
+            let Some(note) = db.get_note_by_id(id).map_err(io::Error::other)? else {
+                continue;
+            };
+            // Encrypted notes are written as the ciphertext they are stored as
+            let mode = note.mode.map_or(DEFAULT_FILE_MODE, |mode| mode & 0o7777);
+            zip.add_file(
+                &path[strip..],
+                note.content.as_bytes(),
+                &note.updated_at,
+                mode,
+            )?;
+            summary.notes += 1;
+        }
+        zip.finish()
+    })();
+    if let Err(e) = written {
+        // Rather than leave half an archive behind
+        let _ = std::fs::remove_file(file);
+        return Err(e);
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_zip_writer() {
+        let modified = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.add_dir("Work", &modified, 0o755).unwrap();
+        zip.add_file("Work/todo.md", b"- [ ] ship", &modified, 0o600)
+            .unwrap();
+        let archive = zip.finish().unwrap();
+
+        // Local header of the folder, then its central directory entry
+        assert!(archive.starts_with(&0x0403_4b50u32.to_le_bytes()));
+        let end = &archive[archive.len() - 22..];
+        assert_eq!(end[..4], 0x0605_4b50u32.to_le_bytes());
+        // Two entries, with the central directory where the end says
+        assert_eq!(end[10..12], 2u16.to_le_bytes());
+        let size = u32::from_le_bytes(end[12..16].try_into().unwrap()) as usize;
+        let offset = u32::from_le_bytes(end[16..20].try_into().unwrap()) as usize;
+        assert_eq!(offset + size, archive.len() - 22);
+        let central = &archive[offset..offset + size];
+        assert_eq!(central[..4], 0x0201_4b50u32.to_le_bytes());
+        // The file's mode, CRC and name
+        let file = &central[46 + "Work/".len() + 9..];
+        assert_eq!(file[38..42], ((S_IFREG | 0o600) << 16).to_le_bytes());
+        assert_eq!(file[16..20], crc32(b"- [ ] ship").to_le_bytes());
+        assert_eq!(&file[46..46 + "Work/todo.md".len()], b"Work/todo.md");
+    }
+}